@@ -207,6 +207,18 @@ public static class Pos
         EnsureSuccess(result, nameof(AddCashTender));
     }
 
+    /// <summary>
+    /// Commits a fully tendered transaction. Tendering in full leaves the transaction open
+    /// until this is called.
+    /// </summary>
+    /// <param name="handle">Transaction handle</param>
+    /// <exception cref="PosException">Thrown when the transaction is not open or not fully tendered</exception>
+    public static void FinalizeTransaction(ulong handle)
+    {
+        var result = RustNative.pk_finalize_transaction(handle);
+        EnsureSuccess(result, nameof(FinalizeTransaction));
+    }
+
     /// <summary>
     /// Gets the current totals for a transaction.
     /// </summary>
@@ -283,7 +295,8 @@ public static class Pos
     {
         if (!RustNative.pk_result_is_ok(result))
         {
-            throw new PosException($"POS Kernel operation '{operationName}' failed with code {result.code}");
+            var code = (PosResultCode)result.code;
+            throw new PosException($"POS Kernel operation '{operationName}' failed with code {result.code} ({code})", code);
         }
     }
 }
@@ -363,6 +376,15 @@ public class Transaction : IDisposable
         Pos.AddCashTender(Handle, amount);
     }
 
+    /// <summary>
+    /// Commits this transaction once it is fully tendered.
+    /// </summary>
+    public void Commit()
+    {
+        ThrowIfDisposed();
+        Pos.FinalizeTransaction(Handle);
+    }
+
     /// <summary>
     /// Gets the current totals for this transaction.
     /// </summary>
@@ -376,12 +398,12 @@ public class Transaction : IDisposable
     }
 
     /// <summary>
-    /// Gets whether this transaction is completed (sufficient tender provided).
+    /// Gets whether this transaction is completed (fully tendered and committed).
     /// </summary>
     public bool IsCompleted => Totals.State == TransactionState.Completed;
 
     /// <summary>
-    /// Gets whether this transaction is still building (insufficient tender).
+    /// Gets whether this transaction is still building (not yet committed).
     /// </summary>
     public bool IsBuilding => Totals.State == TransactionState.Building;
 
@@ -478,15 +500,86 @@ public enum TransactionState
     Completed = 1
 }
 
+/// <summary>
+/// Result codes returned by the POS Kernel; values match the native <c>ResultCode</c> enum.
+/// </summary>
+public enum PosResultCode
+{
+    /// <summary>
+    /// Operation succeeded.
+    /// </summary>
+    Ok = 0,
+
+    /// <summary>
+    /// Handle or referenced item not found.
+    /// </summary>
+    NotFound = 1,
+
+    /// <summary>
+    /// Operation not valid in the current state.
+    /// </summary>
+    InvalidState = 2,
+
+    /// <summary>
+    /// Input validation failed.
+    /// </summary>
+    ValidationFailed = 3,
+
+    /// <summary>
+    /// Output buffer too small.
+    /// </summary>
+    InsufficientBuffer = 4,
+
+    /// <summary>
+    /// Timed out waiting for the kernel.
+    /// </summary>
+    TimedOut = 5,
+
+    /// <summary>
+    /// The operator lacks the required permission.
+    /// </summary>
+    PermissionDenied = 6,
+
+    /// <summary>
+    /// An active-transaction cap was reached; retry after open sales settle.
+    /// </summary>
+    CapacityExceeded = 7,
+
+    /// <summary>
+    /// The parent line was voided; modifiers and deposits cannot attach to it.
+    /// </summary>
+    ParentLineVoided = 8,
+
+    /// <summary>
+    /// The tender was stated in a currency other than the transaction's.
+    /// </summary>
+    CurrencyMismatch = 9,
+
+    /// <summary>
+    /// Internal system error.
+    /// </summary>
+    InternalError = 255
+}
+
 /// <summary>
 /// Exception thrown when a POS Kernel operation fails.
 /// </summary>
 public class PosException : Exception
 {
+    /// <summary>
+    /// The result code reported by the kernel, when the failure came from a kernel call.
+    /// </summary>
+    public PosResultCode? Code { get; }
+
     public PosException(string message) : base(message)
     {
     }
 
+    public PosException(string message, PosResultCode code) : base(message)
+    {
+        Code = code;
+    }
+
     public PosException(string message, Exception innerException) : base(message, innerException)
     {
     }
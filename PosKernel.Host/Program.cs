@@ -89,6 +89,7 @@ try
     using var usdTx = Pos.CreateTransaction($"Store-{terminalId}", "USD");
     usdTx.AddItem("COFFEE", 3.99m);
     usdTx.AddCashTender(5.00m);
+    usdTx.Commit();
     var usdTotals = usdTx.Totals;
     Console.WriteLine($"USD Transaction: Total={usdTotals.Total:C} Change={usdTotals.Change:C}");
 
@@ -96,6 +97,7 @@ try
     using var jpyTx = Pos.CreateTransaction($"Store-Japan-{terminalId}", "JPY");
     jpyTx.AddItem("RAMEN", 800m); // ¥800
     jpyTx.AddCashTender(1000m);   // ¥1000
+    jpyTx.Commit();
     var jpyTotals = jpyTx.Totals;
     Console.WriteLine($"JPY Transaction: Total=¥{jpyTotals.Total:F0} Change=¥{jpyTotals.Change:F0}");
 } 
@@ -76,6 +76,9 @@ namespace PosKernel.Host
         [DllImport(LIB, EntryPoint = "pk_add_cash_tender", CallingConvention = CallingConvention.Cdecl)]
         internal static extern PkResult pk_add_cash_tender(ulong handle, long amountMinor);
 
+        [DllImport(LIB, EntryPoint = "pk_finalize_transaction", CallingConvention = CallingConvention.Cdecl)]
+        internal static extern PkResult pk_finalize_transaction(ulong handle);
+
         // === QUERY/INSPECTION OPERATIONS ===
 
         [DllImport(LIB, EntryPoint = "pk_get_totals", CallingConvention = CallingConvention.Cdecl)]
@@ -153,8 +156,8 @@ namespace PosKernel.Host
                 var errorCode = pk_result_get_code(result);
                 var errorMessage = errorCode switch
                 {
-                    2 => $"Terminal '{terminalId}' is already in use by another process",
-                    3 => $"Invalid terminal ID format: '{terminalId}'",
+                    (int)PosResultCode.InvalidState => $"Terminal '{terminalId}' is already in use by another process",
+                    (int)PosResultCode.ValidationFailed => $"Invalid terminal ID format: '{terminalId}'",
                     _ => $"Failed to initialize terminal '{terminalId}': error code {errorCode}"
                 };
                 
@@ -170,7 +173,7 @@ namespace PosKernel.Host
             // First call to get required size
             var result = pk_get_terminal_info(null, UIntPtr.Zero, out var requiredSize);
             
-            if (result.code != 0 && result.code != 4) // 4 = InsufficientBuffer
+            if (result.code != (int)PosResultCode.Ok && result.code != (int)PosResultCode.InsufficientBuffer)
             {
                 throw new InvalidOperationException($"Failed to get terminal info length: {result.code}");
             }
@@ -214,7 +217,7 @@ namespace PosKernel.Host
             // First call to get required size
             var result = pk_get_store_name(handle, null, UIntPtr.Zero, out var requiredSize);
             
-            if (result.code != 0 && result.code != 4) // 4 = InsufficientBuffer
+            if (result.code != (int)PosResultCode.Ok && result.code != (int)PosResultCode.InsufficientBuffer)
             {
                 throw new InvalidOperationException($"Failed to get store name length: {result.code}");
             }
@@ -244,7 +247,7 @@ namespace PosKernel.Host
             // First call to get required size
             var result = pk_get_currency(handle, null, UIntPtr.Zero, out var requiredSize);
             
-            if (result.code != 0 && result.code != 4) // 4 = InsufficientBuffer
+            if (result.code != (int)PosResultCode.Ok && result.code != (int)PosResultCode.InsufficientBuffer)
             {
                 throw new InvalidOperationException($"Failed to get currency length: {result.code}");
             }
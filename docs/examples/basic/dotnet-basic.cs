@@ -33,6 +33,7 @@ try
     
     // Add payment
     tx.AddCashTender(10.00m);
+    tx.Commit();
     
     var totals = tx.Totals;
     Console.WriteLine($"Payment: {totals.Tendered:C}, Change: {totals.Change:C}");
@@ -64,6 +65,7 @@ foreach (var (store, currency, items, payment) in transactions)
     }
     
     tx.AddCashTender(payment);
+    tx.Commit();
     
     var totals = tx.Totals;
     var culture = currency switch
@@ -130,6 +132,9 @@ using (var tx = Pos.CreateTransaction("Demo-Store", "USD"))
     tx.AddCashTender(60.00m); // Complete payment  
     totals = tx.Totals;
     Console.WriteLine($"After full payment - Change: {totals.Change:C}, State: {totals.State}");
+    
+    tx.Commit(); // Fully tendered sales stay open until committed
+    Console.WriteLine($"After commit - State: {tx.Totals.State}");
 }
 
 Console.WriteLine("\n=== All Examples Complete ===");
@@ -0,0 +1,199 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Kernel benchmarks
+//! Measures the paths the locking design is meant to keep fast: scanning lines into a basket,
+//! lanes checking out concurrently, cascading voids through deep line hierarchies, polling
+//! totals, and bulk line reads (copied per line versus pinned). Run with `cargo bench`; criterion keeps the previous run as the baseline, so a
+//! regression shows up as a change against it.
+//!
+//! The kernel store is process-wide and the journal is kept in memory, so measurement times are
+//! kept short to bound memory growth over a run.
+
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use pos_kernel::*;
+
+const BASKET_LINES: u32 = 50;
+
+fn begin() -> u64 {
+    let (store, currency) = ("BENCH", "USD");
+    let mut handle = 0;
+    let result = unsafe {
+        pk_begin_transaction(store.as_ptr(), store.len(), currency.as_ptr(), currency.len(), 2, &mut handle)
+    };
+    assert!(pk_result_is_ok(result));
+    handle
+}
+
+fn add_line(handle: u64, sku: &str) {
+    let result = unsafe { pk_add_line(handle, sku.as_ptr(), sku.len(), 1, 199) };
+    assert!(pk_result_is_ok(result));
+}
+
+// One basket: lines scanned, then settled in cash
+fn checkout(lines: u32) {
+    let handle = begin();
+    for i in 0..lines {
+        add_line(handle, if i % 2 == 0 { "SKU-0001" } else { "SKU-0002" });
+    }
+    assert!(pk_result_is_ok(pk_add_cash_tender(handle, 199 * lines as i64)));
+    assert!(pk_result_is_ok(pk_finalize_transaction(handle)));
+}
+
+fn scan_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    group.throughput(Throughput::Elements(BASKET_LINES as u64));
+    group.bench_function("basket", |b| {
+        b.iter_batched(begin, |handle| {
+            for _ in 0..BASKET_LINES {
+                add_line(handle, "SKU-0001");
+            }
+        }, BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+fn concurrent_lanes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lanes");
+    for lanes in [1u64, 2, 4, 8] {
+        group.throughput(Throughput::Elements(lanes * BASKET_LINES as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(lanes), &lanes, |b, &lanes| {
+            // One iteration is one checkout on every lane at once
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                thread::scope(|scope| {
+                    for _ in 0..lanes {
+                        scope.spawn(move || {
+                            for _ in 0..iters {
+                                checkout(BASKET_LINES);
+                            }
+                        });
+                    }
+                });
+                start.elapsed()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn void_cascade(c: &mut Criterion) {
+    let mut group = c.benchmark_group("void_cascade");
+    let reason = "bench";
+    for depth in [1u32, 4, 16, 64] {
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |b, &depth| {
+            // Line IDs start at 1 in a new transaction; each line is the child of the previous
+            let setup = || {
+                let handle = begin();
+                add_line(handle, "ROOT");
+                for parent in 1..depth {
+                    let sku = "CHILD";
+                    let result = unsafe { pk_add_child_line(handle, sku.as_ptr(), sku.len(), 1, 50, parent) };
+                    assert!(pk_result_is_ok(result));
+                }
+                handle
+            };
+            b.iter_batched(setup, |handle| {
+                let result = unsafe { pk_void_line_item_with_cascade(handle, 1, reason.as_ptr(), reason.len()) };
+                assert!(pk_result_is_ok(result));
+            }, BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+fn totals_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("totals");
+    let handle = begin();
+    for _ in 0..200 {
+        add_line(handle, "SKU-0001");
+    }
+
+    let read_totals = || {
+        let (mut total, mut tendered, mut change, mut state) = (0, 0, 0, 0);
+        let result = unsafe { pk_get_totals(handle, &mut total, &mut tendered, &mut change, &mut state) };
+        black_box((result, total, tendered, change, state))
+    };
+
+    group.bench_function("idle", |b| b.iter(read_totals));
+
+    // A customer display polling while another lane keeps scanning
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                checkout(10);
+            }
+        })
+    };
+    group.bench_function("under_scan_load", |b| b.iter(read_totals));
+    stop.store(true, Ordering::Relaxed);
+    writer.join().expect("writer lane panicked");
+    group.finish();
+}
+
+fn line_reads(c: &mut Criterion) {
+    const LINES: u32 = 500;
+    let mut group = c.benchmark_group("line_reads");
+    group.throughput(Throughput::Elements(LINES as u64));
+    let handle = begin();
+    for _ in 0..LINES {
+        add_line(handle, "SKU-0001");
+    }
+
+    group.bench_function("copied", |b| {
+        let mut sku = [0u8; 64];
+        b.iter(|| {
+            let mut total = 0i64;
+            for index in 0..LINES {
+                let (mut sku_len, mut qty, mut unit, mut parent, mut has_parent) = (sku.len(), 0, 0, 0, false);
+                let result = unsafe {
+                    pk_get_line_item_with_parent(handle, index, sku.as_mut_ptr(), &mut sku_len, &mut qty, &mut unit, &mut parent, &mut has_parent)
+                };
+                assert!(pk_result_is_ok(result));
+                total += unit * qty as i64;
+            }
+            black_box(total)
+        })
+    });
+
+    group.bench_function("pinned", |b| {
+        b.iter(|| {
+            let (mut lines, mut count) = (std::ptr::null(), 0);
+            assert!(pk_result_is_ok(unsafe { pk_pin_lines(handle, &mut lines, &mut count) }));
+            let lines = unsafe { std::slice::from_raw_parts(lines, count) };
+            black_box(lines.iter().map(|line| line.total_minor).sum::<i64>())
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .sample_size(20)
+        .warm_up_time(Duration::from_millis(500))
+        .measurement_time(Duration::from_secs(2));
+    targets = scan_throughput, concurrent_lanes, void_cascade, totals_latency, line_reads
+}
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Fuzz target for FFI call sequences
+//! Decodes the input into a sequence of FFI calls and runs it through the model shared with
+//! the property tests, which checks the kernel invariants after every call. Requires nightly
+//! and cargo-fuzz; from pos-kernel-rs:
+//!
+//!     cargo +nightly fuzz run ffi_sequence
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/ffi_model/mod.rs"]
+mod ffi_model;
+
+fuzz_target!(|data: &[u8]| {
+    ffi_model::Harness::run(&ffi_model::Op::decode_all(data));
+});
@@ -0,0 +1,190 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Accounting journal export
+//! Committed transactions are summarized into one balanced voucher per business day (UTC commit
+//! date) and currency, posted to the accounts of a configurable `AccountMapping`:
+//! tender clearing accounts are debited with what was collected (cash net of change, card
+//! captures including tips); sales, deposit liability, tax payable and tips payable are
+//! credited. Manual adjustments are debited to the discount account when one is mapped and
+//! reduce sales otherwise. Amounts stay in minor units.
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::reports::{csv_field, ReportPeriod};
+use crate::{LineKind, TenderKind, Transaction};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountMapping {
+    pub sales: String,
+    pub tax_payable: String,
+    // Overrides of the tax payable account per tax jurisdiction
+    #[serde(default)]
+    pub tax_payable_by_jurisdiction: BTreeMap<String, String>,
+    pub cash_clearing: String,
+    pub card_clearing: String,
+    pub points_clearing: String,
+    // Container deposits collected and refunded; defaults to the sales account
+    pub deposit_liability: Option<String>,
+    pub discounts: Option<String>,
+    // Card tips; when unmapped, tips are left out of the export
+    pub tips_payable: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoucherLine {
+    pub account: String,
+    pub debit_minor: i64,
+    pub credit_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Voucher {
+    pub date: NaiveDate,
+    pub currency: String,
+    pub transaction_count: u64,
+    pub lines: Vec<VoucherLine>,
+    pub total_debit_minor: i64,
+    pub total_credit_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountingExport {
+    pub period: ReportPeriod,
+    pub vouchers: Vec<Voucher>,
+}
+
+#[derive(Default)]
+struct VoucherBuilder {
+    transaction_count: u64,
+    // Signed balances per account: positive is a debit, negative a credit
+    balances: BTreeMap<String, i64>,
+}
+
+impl VoucherBuilder {
+    fn post(&mut self, account: &str, debit_minor: i64) {
+        if debit_minor != 0 {
+            *self.balances.entry(account.to_string()).or_insert(0) += debit_minor;
+        }
+    }
+}
+
+pub(crate) fn export(transactions: impl Iterator<Item = impl Deref<Target = Transaction>>, mapping: &AccountMapping, period: ReportPeriod) -> Result<AccountingExport, String> {
+    let mut builders: BTreeMap<(NaiveDate, String), VoucherBuilder> = BTreeMap::new();
+    for tx in transactions.filter(|tx| period.includes(tx)) {
+        let date = match tx.committed_at {
+            Some(t) => t.date_naive(),
+            None => continue,
+        };
+        let voucher = builders.entry((date, tx.currency.code.clone())).or_default();
+        voucher.transaction_count += 1;
+        post_transaction(voucher, &tx, mapping);
+    }
+
+    let mut vouchers = Vec::new();
+    for ((date, currency), builder) in builders {
+        let lines: Vec<VoucherLine> = builder.balances.into_iter()
+            .filter(|(_, balance)| *balance != 0)
+            .map(|(account, balance)| VoucherLine {
+                account,
+                debit_minor: balance.max(0),
+                credit_minor: (-balance).max(0),
+            })
+            .collect();
+        let total_debit_minor: i64 = lines.iter().map(|l| l.debit_minor).sum();
+        let total_credit_minor: i64 = lines.iter().map(|l| l.credit_minor).sum();
+        if total_debit_minor != total_credit_minor {
+            return Err(format!("Accounting voucher for {} {} does not balance", date, currency));
+        }
+        vouchers.push(Voucher {
+            date,
+            currency,
+            transaction_count: builder.transaction_count,
+            lines,
+            total_debit_minor,
+            total_credit_minor,
+        });
+    }
+
+    Ok(AccountingExport { period, vouchers })
+}
+
+fn post_transaction(voucher: &mut VoucherBuilder, tx: &Transaction, mapping: &AccountMapping) {
+    let deposits = mapping.deposit_liability.as_deref().unwrap_or(&mapping.sales);
+    for line in &tx.lines {
+        let account = match (line.kind, mapping.discounts.as_deref()) {
+            (LineKind::Sale | LineKind::Return, _) => &mapping.sales,
+            (LineKind::Deposit | LineKind::DepositReturn, _) => deposits,
+            (LineKind::Adjustment, Some(discounts)) => discounts,
+            (LineKind::Adjustment, None) => &mapping.sales,
+        };
+        voucher.post(account, -line.total_minor());
+    }
+
+    for tax in &tx.taxes {
+        let account = mapping.tax_payable_by_jurisdiction.get(&tax.jurisdiction)
+            .unwrap_or(&mapping.tax_payable);
+        voucher.post(account, -tax.tax_minor);
+        // Inclusive tax is part of the recorded line amounts; move it out of sales
+        if tax.inclusive {
+            voucher.post(&mapping.sales, tax.tax_minor);
+        }
+    }
+
+    let mut change_minor = tx.change_minor();
+    for tender in tx.tenders.iter().rev() {
+        let (account, mut amount_minor) = match tender.kind {
+            TenderKind::Cash => (&mapping.cash_clearing, tender.amount_minor),
+            TenderKind::Card => (&mapping.card_clearing, tender.amount_minor),
+            TenderKind::Points => (&mapping.points_clearing, tender.amount_minor),
+        };
+        // Change is paid out of cash
+        if tender.kind == TenderKind::Cash && change_minor > 0 {
+            let applied = change_minor.min(amount_minor);
+            amount_minor -= applied;
+            change_minor -= applied;
+        }
+        voucher.post(account, amount_minor);
+
+        if let (Some(card), Some(tips)) = (tender.card.as_ref(), mapping.tips_payable.as_deref()) {
+            voucher.post(account, card.tip_minor);
+            voucher.post(tips, -card.tip_minor);
+        }
+    }
+}
+
+impl AccountingExport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("date,currency,account,debit_minor,credit_minor\n");
+        for voucher in &self.vouchers {
+            for line in &voucher.lines {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    voucher.date,
+                    csv_field(&voucher.currency),
+                    csv_field(&line.account),
+                    line.debit_minor,
+                    line.credit_minor
+                ));
+            }
+        }
+        csv
+    }
+}
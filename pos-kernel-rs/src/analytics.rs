@@ -0,0 +1,152 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Basket analytics
+//! REGULATORY COMPLIANCE: Demand forecasting needs what sold together, not who bought it.
+//! With `basket_analytics_enabled` on, each committed sale is summarized under the store lock
+//! and the summary is handed to the registered `AnalyticsSink`s (a Rust integration or an
+//! `analytics` plugin) by a persistence worker. A summary has the store, the currency, the
+//! hour of the commit, item counts, the mix by catalog department and the totals. It carries
+//! no transaction handle, receipt number, terminal, operator, customer, tender or free text,
+//! and no SKU, so it cannot be matched back to a receipt. Summaries are not queued or retried:
+//! a sink that fails misses that basket, with a warning.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+
+use crate::{write_store_for_api, KernelError, LineKind, Transaction, TxState};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryMix {
+    // The catalog's department; None for SKUs it assigns no department
+    pub category: Option<String>,
+    pub units_sold: i64,
+    pub units_returned: i64,
+    // Sales less returns, before adjustments and tax
+    pub amount_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BasketSummary {
+    pub store: String,
+    pub currency: String,
+    pub decimal_places: u8,
+    // The commit time, truncated to the hour
+    pub hour_start: DateTime<Utc>,
+    pub line_count: usize,
+    pub distinct_items: usize,
+    pub units_sold: i64,
+    pub units_returned: i64,
+    // By category name, uncategorized first
+    pub categories: Vec<CategoryMix>,
+    // Sale lines before adjustments, returns and exclusive tax
+    pub gross_minor: i64,
+    pub returns_minor: i64,
+    // Price reductions given, as a positive amount
+    pub savings_minor: i64,
+    pub tax_minor: i64,
+    pub total_minor: i64,
+}
+
+impl BasketSummary {
+    // None unless the transaction is committed; `department` resolves a SKU in the catalog
+    pub(crate) fn build(tx: &Transaction, department: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let committed_at = match tx.committed_at {
+            Some(at) if tx.state == TxState::Committed => at,
+            _ => return None,
+        };
+
+        let mut categories: BTreeMap<Option<String>, CategoryMix> = BTreeMap::new();
+        let mut items = HashSet::new();
+        let (mut gross_minor, mut returns_minor) = (0, 0);
+        for line in tx.lines.iter() {
+            if !matches!(line.kind, LineKind::Sale | LineKind::Return) {
+                continue;
+            }
+            items.insert(&line.sku);
+            let category = department(&line.sku);
+            let mix = categories.entry(category.clone()).or_insert_with(|| CategoryMix {
+                category,
+                units_sold: 0,
+                units_returned: 0,
+                amount_minor: 0,
+            });
+            match line.kind {
+                LineKind::Return => {
+                    mix.units_returned += i64::from(line.qty);
+                    returns_minor -= line.total_minor();
+                },
+                _ => {
+                    mix.units_sold += i64::from(line.qty);
+                    gross_minor += line.total_minor();
+                },
+            }
+            mix.amount_minor += line.total_minor();
+        }
+        let categories: Vec<CategoryMix> = categories.into_values().collect();
+
+        Some(Self {
+            store: tx.store.clone(),
+            currency: tx.currency.code.clone(),
+            decimal_places: tx.currency.decimal_places,
+            hour_start: committed_at.duration_trunc(Duration::hours(1)).unwrap_or(committed_at),
+            line_count: tx.lines.len(),
+            distinct_items: items.len(),
+            units_sold: categories.iter().map(|c| c.units_sold).sum(),
+            units_returned: categories.iter().map(|c| c.units_returned).sum(),
+            categories,
+            gross_minor,
+            returns_minor,
+            savings_minor: tx.savings_minor(),
+            tax_minor: tx.taxes.iter().map(|t| t.tax_minor).sum(),
+            total_minor: tx.total_minor(),
+        })
+    }
+}
+
+// Forecasting feeds (message queue, data lake upload) receive summaries off the request path
+pub trait AnalyticsSink: Send + Sync {
+    // Identifies the sink in warnings
+    fn name(&self) -> &str;
+
+    fn publish(&self, basket: &BasketSummary) -> Result<(), String>;
+}
+
+// A summary built under the store lock, published without it
+pub(crate) struct BasketDelivery {
+    pub basket: BasketSummary,
+    pub sinks: Vec<Arc<dyn AnalyticsSink>>,
+}
+
+impl BasketDelivery {
+    pub fn publish(&self) {
+        for sink in &self.sinks {
+            if let Err(e) = sink.publish(&self.basket) {
+                eprintln!("WARNING: Basket summary not published to {}: {}", sink.name(), e);
+            }
+        }
+    }
+}
+
+// Registers a sink for basket summaries; none are built until `basket_analytics_enabled` is on
+pub fn add_analytics_sink(sink: Box<dyn AnalyticsSink>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.analytics_sinks.push(Arc::from(sink));
+    Ok(())
+}
@@ -0,0 +1,182 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Void and override anomaly detection
+//! SECURITY: Voids and manual price adjustments are counted per operator and per terminal in a
+//! sliding window. When a count exceeds its configured threshold, an `AnomalyDetected` event
+//! is published once per breach (event sinks forward it to webhooks or loss-prevention
+//! systems); counters stay available for reporting.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::reports::csv_field;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum AnomalyKind {
+    Void,
+    PriceOverride,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum AnomalyScope {
+    Operator,
+    Terminal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AnomalyThresholds {
+    pub window_secs: i64,
+    // 0 disables the check
+    pub max_voids: usize,
+    pub max_overrides: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyAlert {
+    pub kind: AnomalyKind,
+    pub scope: AnomalyScope,
+    pub subject: String,
+    pub count: usize,
+    pub threshold: usize,
+    pub window_secs: i64,
+}
+
+#[derive(Debug, Default)]
+struct SubjectCounters {
+    recent: BTreeMap<AnomalyKind, VecDeque<DateTime<Utc>>>,
+    totals: BTreeMap<AnomalyKind, u64>,
+    alerts: u64,
+    // Set while the subject is over a threshold, so each breach alerts once
+    breached: BTreeMap<AnomalyKind, bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyCounterRow {
+    pub scope: AnomalyScope,
+    pub subject: String,
+    pub voids_in_window: usize,
+    pub overrides_in_window: usize,
+    pub voids_total: u64,
+    pub overrides_total: u64,
+    pub alerts_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyCounters {
+    pub window_secs: i64,
+    pub rows: Vec<AnomalyCounterRow>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AnomalyTracker {
+    subjects: BTreeMap<(AnomalyScope, String), SubjectCounters>,
+}
+
+impl AnomalyTracker {
+    // Records one occurrence for the operator and terminal; returns alerts for new breaches
+    pub fn record(&mut self, kind: AnomalyKind, operator: Option<&str>, terminal: Option<&str>, thresholds: AnomalyThresholds, now: DateTime<Utc>) -> Vec<AnomalyAlert> {
+        let threshold = match kind {
+            AnomalyKind::Void => thresholds.max_voids,
+            AnomalyKind::PriceOverride => thresholds.max_overrides,
+        };
+
+        let mut alerts = Vec::new();
+        let subjects = [(AnomalyScope::Operator, operator), (AnomalyScope::Terminal, terminal)];
+        for (scope, subject) in subjects {
+            let subject = match subject {
+                Some(subject) => subject,
+                None => continue,
+            };
+            let counters = self.subjects.entry((scope, subject.to_string())).or_default();
+            *counters.totals.entry(kind).or_insert(0) += 1;
+
+            let recent = counters.recent.entry(kind).or_default();
+            recent.push_back(now);
+            Self::prune(recent, thresholds.window_secs, now);
+
+            let breached = counters.breached.entry(kind).or_insert(false);
+            if threshold == 0 || recent.len() <= threshold {
+                *breached = false;
+                continue;
+            }
+            if !*breached {
+                *breached = true;
+                counters.alerts += 1;
+                alerts.push(AnomalyAlert {
+                    kind,
+                    scope,
+                    subject: subject.to_string(),
+                    count: recent.len(),
+                    threshold,
+                    window_secs: thresholds.window_secs,
+                });
+            }
+        }
+        alerts
+    }
+
+    fn prune(recent: &mut VecDeque<DateTime<Utc>>, window_secs: i64, now: DateTime<Utc>) {
+        let cutoff = now - Duration::seconds(window_secs);
+        while recent.front().is_some_and(|t| *t <= cutoff) {
+            recent.pop_front();
+        }
+    }
+
+    pub fn counters(&self, window_secs: i64, now: DateTime<Utc>) -> AnomalyCounters {
+        let cutoff = now - Duration::seconds(window_secs);
+        let in_window = |counters: &SubjectCounters, kind| {
+            counters.recent.get(&kind).map_or(0, |r| r.iter().filter(|t| **t > cutoff).count())
+        };
+        let rows = self.subjects.iter()
+            .map(|((scope, subject), counters)| AnomalyCounterRow {
+                scope: *scope,
+                subject: subject.clone(),
+                voids_in_window: in_window(counters, AnomalyKind::Void),
+                overrides_in_window: in_window(counters, AnomalyKind::PriceOverride),
+                voids_total: counters.totals.get(&AnomalyKind::Void).copied().unwrap_or(0),
+                overrides_total: counters.totals.get(&AnomalyKind::PriceOverride).copied().unwrap_or(0),
+                alerts_total: counters.alerts,
+            })
+            .collect();
+        AnomalyCounters { window_secs, rows }
+    }
+}
+
+impl AnomalyCounters {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("scope,subject,voids_in_window,overrides_in_window,voids_total,overrides_total,alerts_total\n");
+        for row in &self.rows {
+            let scope = match row.scope {
+                AnomalyScope::Operator => "Operator",
+                AnomalyScope::Terminal => "Terminal",
+            };
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                scope,
+                csv_field(&row.subject),
+                row.voids_in_window,
+                row.overrides_in_window,
+                row.voids_total,
+                row.overrides_total,
+                row.alerts_total
+            ));
+        }
+        csv
+    }
+}
@@ -0,0 +1,487 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Settled-transaction archive
+//! AUDIT COMPLIANCE: Once committed or cancelled (abandoned sales, cancelled layaways),
+//! transactions leave the mutable active store and are sealed into an append-only archive. Each record carries a SHA-256 hash over the previous
+//! record's hash and the transaction's export, so any alteration or removal breaks the chain.
+//! The customer token is left out of the hash, so GDPR erasure replaces it without touching
+//! the chain. Loyalty activity recorded after a transaction was sealed is kept beside its
+//! record as an addendum, outside the hash, and journaled; the sealed transaction is never
+//! edited.
+//! Records can be queried by receipt number, commit date range, operator and SKU.
+//! ARCHITECTURAL PRINCIPLE: Reporting reads immutable snapshots. Records are shared
+//! copy-on-write, so a snapshot costs one reference count, and queries and reports run on it
+//! without holding the store lock while checkout keeps committing.
+//! Under a memory budget, the least recently used records are evicted to the persistence
+//! backend; their hash chain entries stay in memory and the transaction is reloaded on access.
+//! The budget covers archived records only: open transactions and settled ones a worker has
+//! not archived yet are in the active shards and are not counted.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::export::TransactionExport;
+use crate::loyalty::LoyaltyActivity;
+use crate::persistence::TransactionBackend;
+use crate::{KernelError, Transaction};
+
+// Hash the first record is chained to
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone)]
+pub(crate) struct ArchivedTransaction {
+    pub sequence_number: u64,
+    pub archived_at: DateTime<Utc>,
+    pub previous_hash: String,
+    pub hash: String,
+    handle: u64,
+    // Estimated from the export the record is hashed over
+    size_bytes: usize,
+    // None once evicted to the persistence backend
+    transaction: Option<Arc<Transaction>>,
+    // LOYALTY: Activity recorded after the transaction was sealed
+    pub loyalty_addendum: Option<LoyaltyActivity>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveRecord {
+    pub sequence_number: u64,
+    pub archived_at: DateTime<Utc>,
+    pub previous_hash: String,
+    pub hash: String,
+    pub transaction: TransactionExport,
+}
+
+impl ArchiveRecord {
+    fn new(record: &ArchivedTransaction, tx: &Transaction) -> Self {
+        Self {
+            sequence_number: record.sequence_number,
+            archived_at: record.archived_at,
+            previous_hash: record.previous_hash.clone(),
+            hash: record.hash.clone(),
+            transaction: TransactionExport::from(tx),
+        }
+    }
+}
+
+// All criteria are optional and combined; the date range is half-open [from, to) on commit time
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveQuery {
+    // Transaction number, or the document number assigned by a fiscal device
+    pub receipt_number: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub operator_id: Option<String>,
+    pub sku: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl ArchiveQuery {
+    fn matches(&self, tx: &Transaction) -> bool {
+        if let Some(receipt_number) = &self.receipt_number {
+            let fiscal_number = tx.fiscal_registration.as_ref().map(|r| r.document_number.as_str());
+            if tx.id.to_string() != *receipt_number && fiscal_number != Some(receipt_number.as_str()) {
+                return false;
+            }
+        }
+        // Cancelled transactions have no commit time and fall outside any date range
+        if self.from.is_some() || self.to.is_some() {
+            let in_range = |at: DateTime<Utc>| self.from.is_none_or(|from| at >= from) && self.to.is_none_or(|to| at < to);
+            if !tx.committed_at.is_some_and(in_range) {
+                return false;
+            }
+        }
+        if self.operator_id.as_ref().is_some_and(|op| tx.operator_id.as_ref() != Some(op)) {
+            return false;
+        }
+        if let Some(sku) = &self.sku {
+            if !tx.lines.iter().any(|l| *l.sku == **sku) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveVerification {
+    pub records: u64,
+    pub head_hash: String,
+    // Sequence number of the first record whose hash does not verify
+    pub first_invalid: Option<u64>,
+}
+
+type Records = Arc<Vec<Arc<ArchivedTransaction>>>;
+type Backend = Option<Arc<dyn TransactionBackend>>;
+
+fn head_hash(records: &[Arc<ArchivedTransaction>]) -> &str {
+    records.last().map_or(GENESIS_HASH, |r| r.hash.as_str())
+}
+
+// Returns the record hash and the size of the hashed content. The customer token is blanked
+// before hashing, so that erasing it leaves the chain intact.
+fn record_hash(previous_hash: &str, tx: &Transaction) -> Result<(String, usize), String> {
+    let mut export = TransactionExport::from(tx);
+    if let Some(customer) = export.customer.as_mut() {
+        customer.token.clear();
+    }
+    let content = serde_json::to_string(&export)
+        .map_err(|e| format!("Failed to serialize transaction: {}", e))?;
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(content.as_bytes());
+    Ok((hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect(), content.len()))
+}
+
+fn load(backend: &Backend, record: &ArchivedTransaction) -> Result<Arc<Transaction>, String> {
+    if let Some(tx) = &record.transaction {
+        return Ok(Arc::clone(tx));
+    }
+    match backend {
+        Some(backend) => {
+            let tx = backend.load(record.handle)?;
+            tx.check_hierarchy()?;
+            Ok(Arc::new(tx))
+        },
+        None => Err(format!("Transaction {} was evicted and no backend is configured", record.handle)),
+    }
+}
+
+// The sealed transaction with any loyalty addendum applied, as callers and reports see it
+fn view(backend: &Backend, record: &ArchivedTransaction) -> Result<Arc<Transaction>, String> {
+    let tx = load(backend, record)?;
+    let addendum = match &record.loyalty_addendum {
+        Some(addendum) => addendum,
+        None => return Ok(tx),
+    };
+    let mut tx = Arc::unwrap_or_clone(tx);
+    tx.loyalty = Some(with_addendum(tx.loyalty, addendum));
+    Ok(Arc::new(tx))
+}
+
+fn with_addendum(sealed: Option<LoyaltyActivity>, addendum: &LoyaltyActivity) -> LoyaltyActivity {
+    let mut activity = sealed.unwrap_or_else(|| LoyaltyActivity {
+        account_id: addendum.account_id.clone(),
+        ..LoyaltyActivity::default()
+    });
+    activity.points_accrued += addendum.points_accrued;
+    activity.points_redeemed += addendum.points_redeemed;
+    activity
+}
+
+#[derive(Default)]
+pub(crate) struct TransactionArchive {
+    records: Records,
+    by_handle: HashMap<u64, usize>,
+    backend: Backend,
+    resident_bytes: usize,
+    evicted: usize,
+    // LRU order of resident records: handle -> tick of last access
+    clock: AtomicU64,
+    last_access: Mutex<HashMap<u64, u64>>,
+}
+
+// Immutable view of the archive as of `taken_at`; later commits and amendments are not visible
+#[derive(Clone)]
+pub(crate) struct ArchiveSnapshot {
+    records: Records,
+    backend: Backend,
+    pub taken_at: DateTime<Utc>,
+}
+
+impl ArchiveSnapshot {
+    // Runs `scan` over every transaction in archive order. Evicted transactions are reloaded
+    // one at a time as the scan reaches them; fails if one could not be reloaded.
+    pub fn scan<R>(&self, scan: impl FnOnce(&mut dyn Iterator<Item = Arc<Transaction>>) -> R) -> Result<R, String> {
+        let mut error = None;
+        let mut transactions = self.records.iter()
+            .map_while(|r| view(&self.backend, r).map_err(|e| error = Some(e)).ok());
+        let result = scan(&mut transactions);
+        drop(transactions);
+        match error {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+
+    // BACKUP: Visits every record with its sealed transaction in archive order, reloading
+    // evicted ones
+    pub fn for_each_record(&self, mut visit: impl FnMut(&ArchivedTransaction, &Transaction) -> Result<(), String>) -> Result<(), String> {
+        for record in self.records.iter() {
+            visit(record, &*load(&self.backend, record)?)?;
+        }
+        Ok(())
+    }
+
+    // Visits the records after archive sequence number `sequence` in order until `visit`
+    // returns false, reloading evicted transactions as it reaches them
+    pub fn scan_after(&self, sequence: u64, mut visit: impl FnMut(u64, &Transaction) -> bool) -> Result<(), String> {
+        let start = self.records.partition_point(|r| r.sequence_number <= sequence);
+        for record in &self.records[start..] {
+            if !visit(record.sequence_number, &*view(&self.backend, record)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn head_hash(&self) -> &str {
+        head_hash(&self.records)
+    }
+
+    pub fn query(&self, query: &ArchiveQuery) -> Result<Vec<ArchiveRecord>, String> {
+        let mut matches = Vec::new();
+        for record in self.records.iter() {
+            if matches.len() >= query.limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            let tx = view(&self.backend, record)?;
+            if query.matches(&tx) {
+                matches.push(ArchiveRecord::new(record, &tx));
+            }
+        }
+        Ok(matches)
+    }
+
+    // Recomputes the hash chain from the first record; a record that cannot be reloaded or is
+    // out of sequence does not verify
+    pub fn verify(&self) -> ArchiveVerification {
+        let mut previous_hash = GENESIS_HASH.to_string();
+        let mut first_invalid = None;
+        for (index, record) in self.records.iter().enumerate() {
+            let valid = record.sequence_number == index as u64 + 1
+                && record.previous_hash == previous_hash
+                && load(&self.backend, record)
+                    .and_then(|tx| record_hash(&previous_hash, &tx))
+                    .is_ok_and(|(h, _)| h == record.hash);
+            if !valid {
+                first_invalid = Some(record.sequence_number);
+                break;
+            }
+            previous_hash = record.hash.clone();
+        }
+
+        ArchiveVerification {
+            records: self.records.len() as u64,
+            head_hash: head_hash(&self.records).to_string(),
+            first_invalid,
+        }
+    }
+}
+
+impl TransactionArchive {
+    pub fn set_backend(&mut self, backend: Arc<dyn TransactionBackend>) {
+        self.backend = Some(backend);
+    }
+
+    fn touch(&self, handle: u64) {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.last_access.lock().unwrap_or_else(PoisonError::into_inner).insert(handle, tick);
+    }
+
+    // Seals a settled transaction into the archive; returns its sequence number and hash, or
+    // the transaction back if it could not be hashed
+    pub fn seal(&mut self, tx: Transaction) -> Result<(u64, String), (Box<Transaction>, String)> {
+        let previous_hash = head_hash(&self.records).to_string();
+        let (hash, size_bytes) = match record_hash(&previous_hash, &tx) {
+            Ok(hashed) => hashed,
+            Err(e) => return Err((Box::new(tx), e)),
+        };
+        let sequence_number = self.records.len() as u64 + 1;
+        let handle = tx.id;
+
+        self.by_handle.insert(handle, self.records.len());
+        // Copies the record list only if a snapshot still shares it
+        Arc::make_mut(&mut self.records).push(Arc::new(ArchivedTransaction {
+            sequence_number,
+            archived_at: Utc::now(),
+            previous_hash,
+            hash: hash.clone(),
+            handle,
+            size_bytes,
+            transaction: Some(Arc::new(tx)),
+            loyalty_addendum: None,
+        }));
+        self.resident_bytes += size_bytes;
+        self.touch(handle);
+        Ok((sequence_number, hash))
+    }
+
+    // BACKUP: Seals a transaction restored from a backup under its original archive time, with
+    // its loyalty addendum. The chain is recomputed as it is rebuilt, so a record that does not
+    // hash to `hash` fails the restore.
+    pub fn restore(&mut self, tx: Transaction, archived_at: DateTime<Utc>, hash: &str, loyalty_addendum: Option<LoyaltyActivity>) -> Result<(), String> {
+        let (handle, sequence_number) = (tx.id, self.records.len() as u64 + 1);
+        let (_, sealed_hash) = self.seal(tx).map_err(|(_, e)| e)?;
+        if sealed_hash != hash {
+            return Err(format!("Archive record {} (transaction {}) failed its hash check", sequence_number, handle));
+        }
+        if let Some(record) = Arc::make_mut(&mut self.records).last_mut() {
+            let record = Arc::make_mut(record);
+            record.archived_at = archived_at;
+            record.loyalty_addendum = loyalty_addendum;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn resident_bytes(&self) -> usize {
+        self.resident_bytes
+    }
+
+    pub fn evicted_count(&self) -> usize {
+        self.evicted
+    }
+
+    // Evicted transactions are reloaded from the backend for the caller; they stay evicted
+    pub fn get(&self, handle: u64) -> Option<Result<Arc<Transaction>, String>> {
+        let record = &self.records[*self.by_handle.get(&handle)?];
+        if record.transaction.is_some() {
+            self.touch(handle);
+        }
+        Some(view(&self.backend, record))
+    }
+
+    pub fn snapshot(&self) -> ArchiveSnapshot {
+        ArchiveSnapshot {
+            records: Arc::clone(&self.records),
+            backend: self.backend.clone(),
+            taken_at: Utc::now(),
+        }
+    }
+
+    pub fn backend(&self) -> Option<Arc<dyn TransactionBackend>> {
+        self.backend.clone()
+    }
+
+    // Least recently used resident transactions whose eviction would bring resident records
+    // within the budget. Without a backend nothing can be evicted.
+    pub fn eviction_candidates(&self, budget_bytes: usize) -> Vec<(u64, Arc<Transaction>)> {
+        if self.backend.is_none() || self.resident_bytes <= budget_bytes {
+            return Vec::new();
+        }
+
+        let mut by_age: Vec<(u64, u64)> = self.last_access.lock().unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(&handle, &tick)| (tick, handle))
+            .collect();
+        by_age.sort_unstable();
+
+        let mut resident_bytes = self.resident_bytes;
+        let mut candidates = Vec::new();
+        for (_, handle) in by_age {
+            if resident_bytes <= budget_bytes {
+                break;
+            }
+            let record = &self.records[self.by_handle[&handle]];
+            if let Some(tx) = &record.transaction {
+                resident_bytes -= record.size_bytes;
+                candidates.push((handle, Arc::clone(tx)));
+            }
+        }
+        candidates
+    }
+
+    // Drops a transaction already saved to the backend from memory, unless the record was
+    // replaced since it was saved; returns whether it was evicted
+    pub fn mark_evicted(&mut self, handle: u64, saved: &Arc<Transaction>) -> bool {
+        let index = match self.by_handle.get(&handle) {
+            Some(&index) => index,
+            None => return false,
+        };
+        if !self.records[index].transaction.as_ref().is_some_and(|tx| Arc::ptr_eq(tx, saved)) {
+            return false;
+        }
+
+        let record = Arc::make_mut(&mut Arc::make_mut(&mut self.records)[index]);
+        record.transaction = None;
+        self.resident_bytes -= record.size_bytes;
+        self.last_access.get_mut().unwrap_or_else(PoisonError::into_inner).remove(&handle);
+        self.evicted += 1;
+        true
+    }
+
+    // Replaces a record's transaction; an evicted record is written back and stays evicted
+    fn replace(&mut self, index: usize, tx: Transaction) -> Result<(), String> {
+        let record = Arc::make_mut(&mut Arc::make_mut(&mut self.records)[index]);
+        match (&record.transaction, &self.backend) {
+            (Some(_), _) => record.transaction = Some(Arc::new(tx)),
+            (None, Some(backend)) => backend.save(&tx)?,
+            (None, None) => return Err(format!("Transaction {} was evicted and no backend is configured", record.handle)),
+        }
+        Ok(())
+    }
+
+    // LOYALTY: Activity quoted by the loyalty provider may arrive after commit. It is added to
+    // the record's addendum, which must be for the same account as any activity sealed with the
+    // transaction; returns the combined activity, or None when the handle is not archived.
+    pub fn record_loyalty(&mut self, handle: u64, account_id: &str, points_accrued: i64, points_redeemed: i64) -> Option<Result<LoyaltyActivity, KernelError>> {
+        let index = *self.by_handle.get(&handle)?;
+        let sealed = match load(&self.backend, &self.records[index]) {
+            Ok(tx) => tx.loyalty.clone(),
+            Err(e) => return Some(Err(KernelError::Internal(e))),
+        };
+        let record = &self.records[index];
+        let account = sealed.as_ref().or(record.loyalty_addendum.as_ref()).map(|a| a.account_id.as_str());
+        if account.is_some_and(|account| account != account_id) {
+            return Some(Err(KernelError::invalid_state("Transaction already has loyalty activity for another account")));
+        }
+
+        let record = Arc::make_mut(&mut Arc::make_mut(&mut self.records)[index]);
+        let addendum = record.loyalty_addendum.get_or_insert_with(|| LoyaltyActivity {
+            account_id: account_id.to_string(),
+            ..LoyaltyActivity::default()
+        });
+        addendum.points_accrued += points_accrued;
+        addendum.points_redeemed += points_redeemed;
+        Some(Ok(with_addendum(sealed, addendum)))
+    }
+
+    // REGULATORY COMPLIANCE: Erasure is the only permitted change to archived records. The
+    // customer token is replaced; it is not covered by the record hash, so the chain is left as it
+    // was. The anonymization itself is journaled and signed. Evicted records are reloaded,
+    // redacted and written back. Returns the number of records changed.
+    pub fn redact_customer(&mut self, token: &str, pseudonym: &str) -> Result<usize, String> {
+        let mut redacted = 0;
+        for i in 0..self.records.len() {
+            let tx = load(&self.backend, &self.records[i])?;
+            if tx.customer.as_ref().is_none_or(|c| c.token != token) {
+                continue;
+            }
+            let mut tx = Arc::unwrap_or_clone(tx);
+            if let Some(customer) = tx.customer.as_mut() {
+                customer.token = pseudonym.to_string();
+            }
+            self.replace(i, tx)?;
+            redacted += 1;
+        }
+        Ok(redacted)
+    }
+}
@@ -0,0 +1,485 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Structured audit log
+//! AUDIT COMPLIANCE: The journal records every mutation for recovery; auditors and loss
+//! prevention want the business events only, one readable line each. The audit log follows
+//! the journal as it is written and keeps a JSON line for each sign-on, operator assignment,
+//! void, override, return, abandoned sale, refund limit, hold resolution, reprint, no-sale and
+//! drawer event, with the store, terminal and operator it concerns and a correlation ID tying
+//! it to its transaction, drawer session or shift. Operator IDs are protected by the PII
+//! policy as records are written.
+//! With a data directory the log is `audit.jsonl` there, rotated by size into `audit.1.jsonl`
+//! (newest) to `audit.<n>.jsonl`; without one the most recent records are kept in memory.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cash::CashMovementKind;
+use crate::journal::{JournalEntry, JournalOperation};
+use crate::peripheral::DrawerOpenReason;
+use crate::pii::{self, PiiCategory};
+use crate::refund::RefundLimit;
+use crate::reports::{csv_field, ReportPeriod};
+use crate::security::HoldResolution;
+use crate::{LineKind, PK_INVALID_HANDLE};
+
+// Records kept without a data directory
+const MEMORY_RECORDS: usize = 10_000;
+// Exports reread the files when a rotation moved them meanwhile, at most this often
+const EXPORT_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    // An operator's shift started
+    SignOn,
+    OperatorAssign,
+    LineVoid { line_id: u32, sku: Option<String>, amount_minor: Option<i64>, reason: String },
+    PriceOverride { line_id: u32, sku: String, amount_minor: i64, reason_code: Option<String>, approved_by: Option<String> },
+    Return {
+        line_id: u32,
+        sku: String,
+        amount_minor: i64,
+        reason_code: Option<String>,
+        approved_by: Option<String>,
+        original_receipt: Option<String>,
+    },
+    // The value of the lines not voided when the sale was abandoned
+    TransactionAbandon { amount_minor: i64, reason: String },
+    RefundLimit { limits: Vec<RefundLimit>, amount_minor: i64, overridden_by: Option<String> },
+    HoldResolve { line_id: u32, resolution: HoldResolution },
+    ReceiptReprint { printers: Vec<String> },
+    NoSale { driver: String, error: Option<String> },
+    // A drawer opened for a cash tender or cash movement
+    DrawerOpen { reason: DrawerOpenReason, driver: String, error: Option<String> },
+    DrawerSessionOpen { currency: String, float_minor: i64 },
+    CashMovement { kind: CashMovementKind, amount_minor: i64 },
+    DrawerSessionClose { expected_minor: i64, counted_minor: i64, variance_minor: i64 },
+}
+
+impl AuditEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            AuditEvent::SignOn => "sign_on",
+            AuditEvent::OperatorAssign => "operator_assign",
+            AuditEvent::LineVoid { .. } => "line_void",
+            AuditEvent::PriceOverride { .. } => "price_override",
+            AuditEvent::Return { .. } => "return",
+            AuditEvent::TransactionAbandon { .. } => "transaction_abandon",
+            AuditEvent::RefundLimit { .. } => "refund_limit",
+            AuditEvent::HoldResolve { .. } => "hold_resolve",
+            AuditEvent::ReceiptReprint { .. } => "receipt_reprint",
+            AuditEvent::NoSale { .. } => "no_sale",
+            AuditEvent::DrawerOpen { .. } => "drawer_open",
+            AuditEvent::DrawerSessionOpen { .. } => "drawer_session_open",
+            AuditEvent::CashMovement { .. } => "cash_movement",
+            AuditEvent::DrawerSessionClose { .. } => "drawer_session_close",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    // The journal entry the event was read from
+    pub sequence_number: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub store: Option<String>,
+    pub terminal_id: Option<String>,
+    pub operator_id: Option<String>,
+    // `txn-<handle>`, `drawer-<sequence>` or `shift-<sequence>`, naming the journal entry that
+    // opened the drawer session or started the shift
+    pub correlation_id: Option<String>,
+    pub transaction_handle: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditLogExport {
+    pub period: ReportPeriod,
+    pub records: Vec<AuditRecord>,
+}
+
+impl AuditLogExport {
+    // The event's own fields go in `details`, as JSON
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("timestamp,sequence_number,event,store,terminal_id,operator_id,correlation_id,transaction_handle,details\n");
+        let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or(""));
+        for record in &self.records {
+            let mut details = serde_json::to_value(&record.event).unwrap_or_default();
+            if let Some(fields) = details.as_object_mut() {
+                fields.remove("event");
+            }
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                record.timestamp.to_rfc3339(),
+                record.sequence_number,
+                record.event.name(),
+                optional(&record.store),
+                optional(&record.terminal_id),
+                optional(&record.operator_id),
+                optional(&record.correlation_id),
+                record.transaction_handle.map(|h| h.to_string()).unwrap_or_default(),
+                csv_field(&details.to_string()),
+            ));
+        }
+        csv
+    }
+}
+
+#[derive(Default)]
+struct TransactionContext {
+    store: Option<String>,
+    terminal_id: Option<String>,
+    operator_id: Option<String>,
+    // SKU and amount of each line not voided
+    lines: HashMap<u32, (String, i64)>,
+}
+
+enum Sink {
+    File {
+        path: PathBuf,
+        writer: BufWriter<File>,
+        written_bytes: u64,
+    },
+    Memory(VecDeque<AuditRecord>),
+}
+
+struct AuditState {
+    sink: Sink,
+    // Rotate once the current file reaches this size; zero never rotates
+    max_bytes: u64,
+    // Rotated files kept besides the current one; at least one
+    keep_files: usize,
+    // Bumped by every rotation, so an export can tell the files moved under it
+    rotations: u64,
+    transactions: HashMap<u64, TransactionContext>,
+    // Journal sequence of the open drawer session per terminal, and of the shift per operator
+    drawer_sessions: HashMap<String, u64>,
+    shifts: HashMap<String, u64>,
+}
+
+// Internally locked; records are written under the journal lock, in journal order
+pub(crate) struct AuditLog {
+    state: Mutex<AuditState>,
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("audit");
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("jsonl");
+    path.with_file_name(format!("{}.{}.{}", stem, index, extension))
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))
+}
+
+impl AuditLog {
+    pub fn in_memory(max_bytes: u64, keep_files: usize) -> Self {
+        Self::with_sink(Sink::Memory(VecDeque::new()), max_bytes, keep_files)
+    }
+
+    // Opens (or creates) the audit log file in append mode
+    pub fn open(path: &Path, max_bytes: u64, keep_files: usize) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create audit log directory {}: {}", parent.display(), e))?;
+        }
+        let file = open_append(path)?;
+        let written_bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let sink = Sink::File { path: path.to_path_buf(), writer: BufWriter::new(file), written_bytes };
+        Ok(Self::with_sink(sink, max_bytes, keep_files))
+    }
+
+    fn with_sink(sink: Sink, max_bytes: u64, keep_files: usize) -> Self {
+        Self {
+            state: Mutex::new(AuditState {
+                sink,
+                max_bytes,
+                keep_files,
+                rotations: 0,
+                transactions: HashMap::new(),
+                drawer_sessions: HashMap::new(),
+                shifts: HashMap::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, AuditState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Applies to the next record written; rotated files beyond `keep_files` go at the next
+    // rotation
+    pub fn set_rotation(&self, max_bytes: u64, keep_files: usize) {
+        let mut state = self.state();
+        state.max_bytes = max_bytes;
+        state.keep_files = keep_files;
+    }
+
+    // Called for every journal entry as it is appended. Failing to write the audit log never
+    // fails the operation; the journal still holds the event.
+    pub fn observe(&self, entry: &JournalEntry) {
+        let mut state = self.state();
+        if let Some(record) = state.track(entry) {
+            if let Err(e) = state.write(&record) {
+                eprintln!("WARNING: Failed to write audit record for journal entry {}: {}", entry.sequence_number, e);
+            }
+        }
+    }
+
+    // The records in the period, oldest first
+    pub fn export(&self, period: ReportPeriod) -> Result<AuditLogExport, String> {
+        let in_period = |record: &AuditRecord| record.timestamp >= period.from && record.timestamp < period.to;
+        for _ in 0..EXPORT_ATTEMPTS {
+            // Files are read without the lock, which journal appends wait on
+            let (path, keep_files, rotations) = {
+                let mut state = self.state();
+                let (keep_files, rotations) = (state.keep_files, state.rotations);
+                match &mut state.sink {
+                    Sink::Memory(records) => {
+                        let records = records.iter().filter(|r| in_period(r)).cloned().collect();
+                        return Ok(AuditLogExport { period, records });
+                    },
+                    Sink::File { path, writer, .. } => {
+                        writer.flush().map_err(|e| format!("Failed to flush audit log: {}", e))?;
+                        (path.clone(), keep_files, rotations)
+                    },
+                }
+            };
+
+            let mut records = Vec::new();
+            let mut files: Vec<PathBuf> = (1..=keep_files).rev().map(|index| rotated_path(&path, index)).collect();
+            files.push(path);
+            for file in files.iter().filter(|file| file.exists()) {
+                let reader = BufReader::new(File::open(file)
+                    .map_err(|e| format!("Failed to read audit log {}: {}", file.display(), e))?);
+                for line in reader.lines().map_while(Result::ok) {
+                    match serde_json::from_str::<AuditRecord>(&line) {
+                        Ok(record) if in_period(&record) => records.push(record),
+                        Ok(_) => {},
+                        Err(e) => eprintln!("WARNING: Skipping unreadable audit line in {}: {}", file.display(), e),
+                    }
+                }
+            }
+            if self.state().rotations == rotations {
+                return Ok(AuditLogExport { period, records });
+            }
+        }
+        Err("Audit log kept rotating during the export".to_string())
+    }
+}
+
+impl AuditState {
+    fn correlation_id(&self, handle: u64, terminal_id: Option<&str>, operator_id: Option<&str>) -> Option<String> {
+        if handle != PK_INVALID_HANDLE {
+            return Some(format!("txn-{}", handle));
+        }
+        terminal_id.and_then(|t| self.drawer_sessions.get(t)).map(|seq| format!("drawer-{}", seq))
+            .or_else(|| operator_id.and_then(|o| self.shifts.get(o)).map(|seq| format!("shift-{}", seq)))
+    }
+
+    // Updates the context kept for open transactions, drawer sessions and shifts, and returns
+    // the record for a business event
+    fn track(&mut self, entry: &JournalEntry) -> Option<AuditRecord> {
+        let handle = entry.transaction_handle;
+        // Terminal and operator named by the entry itself, for events outside transactions
+        let mut terminal_id = None;
+        let mut operator_id = None;
+        let event = match &entry.operation {
+            JournalOperation::TransactionBegin { store, .. } => {
+                self.transactions.insert(handle, TransactionContext { store: Some(store.clone()), ..TransactionContext::default() });
+                None
+            },
+            JournalOperation::OperatorAssign { terminal_id: terminal, operator_id: operator } => {
+                let tx = self.transactions.entry(handle).or_default();
+                if terminal.is_some() {
+                    tx.terminal_id = terminal.clone();
+                }
+                if operator.is_some() {
+                    tx.operator_id = operator.clone();
+                }
+                Some(AuditEvent::OperatorAssign)
+            },
+            JournalOperation::LineAdd { line_id, sku, qty, unit_minor, kind, reason_code, approved_by, original_receipt, .. } => {
+                let amount_minor = match kind {
+                    LineKind::Return | LineKind::DepositReturn => -(unit_minor * i64::from(*qty)),
+                    _ => unit_minor * i64::from(*qty),
+                };
+                self.transactions.entry(handle).or_default().lines.insert(*line_id, (sku.to_string(), amount_minor));
+                let (line_id, sku, reason_code, approved_by) = (*line_id, sku.to_string(), reason_code.clone(), approved_by.clone());
+                match kind {
+                    LineKind::Adjustment => Some(AuditEvent::PriceOverride { line_id, sku, amount_minor, reason_code, approved_by }),
+                    LineKind::Return => Some(AuditEvent::Return { line_id, sku, amount_minor, reason_code, approved_by, original_receipt: original_receipt.clone() }),
+                    _ => None,
+                }
+            },
+            JournalOperation::LineVoid { line_id, reason } => {
+                let line = self.transactions.get_mut(&handle).and_then(|tx| tx.lines.remove(line_id));
+                Some(AuditEvent::LineVoid {
+                    line_id: *line_id,
+                    sku: line.as_ref().map(|l| l.0.clone()),
+                    amount_minor: line.map(|l| l.1),
+                    reason: reason.clone(),
+                })
+            },
+            JournalOperation::TransactionAbandon { reason } => {
+                let amount_minor = self.transactions.get(&handle).map(|tx| tx.lines.values().map(|l| l.1).sum()).unwrap_or(0);
+                Some(AuditEvent::TransactionAbandon { amount_minor, reason: reason.clone() })
+            },
+            JournalOperation::RefundLimitTriggered { limits, amount_minor, overridden_by } => {
+                Some(AuditEvent::RefundLimit { limits: limits.clone(), amount_minor: *amount_minor, overridden_by: overridden_by.clone() })
+            },
+            JournalOperation::LineHoldResolve { line_id, resolution, operator_id: operator } => {
+                operator_id = operator.clone();
+                Some(AuditEvent::HoldResolve { line_id: *line_id, resolution: *resolution })
+            },
+            JournalOperation::ReceiptReprint { printers, terminal_id: terminal } => {
+                terminal_id = terminal.clone();
+                Some(AuditEvent::ReceiptReprint { printers: printers.clone() })
+            },
+            JournalOperation::ShiftStart { operator_id: operator } => {
+                self.shifts.insert(operator.clone(), entry.sequence_number);
+                operator_id = Some(operator.clone());
+                Some(AuditEvent::SignOn)
+            },
+            JournalOperation::DrawerOpen { reason, driver, terminal_id: terminal, operator_id: operator, error } => {
+                terminal_id = terminal.clone();
+                operator_id = operator.clone();
+                let (driver, error) = (driver.clone(), error.clone());
+                match reason {
+                    DrawerOpenReason::NoSale => Some(AuditEvent::NoSale { driver, error }),
+                    reason => Some(AuditEvent::DrawerOpen { reason: *reason, driver, error }),
+                }
+            },
+            JournalOperation::DrawerSessionOpen { terminal_id: terminal, operator_id: operator, currency, float_minor } => {
+                self.drawer_sessions.insert(terminal.clone(), entry.sequence_number);
+                terminal_id = Some(terminal.clone());
+                operator_id = operator.clone();
+                Some(AuditEvent::DrawerSessionOpen { currency: currency.clone(), float_minor: *float_minor })
+            },
+            JournalOperation::CashMovement { terminal_id: terminal, kind, amount_minor, operator_id: operator, .. } => {
+                terminal_id = Some(terminal.clone());
+                operator_id = operator.clone();
+                Some(AuditEvent::CashMovement { kind: *kind, amount_minor: *amount_minor })
+            },
+            JournalOperation::DrawerSessionClose { terminal_id: terminal, expected_minor, counted_minor, variance_minor, operator_id: operator } => {
+                terminal_id = Some(terminal.clone());
+                operator_id = operator.clone();
+                Some(AuditEvent::DrawerSessionClose {
+                    expected_minor: *expected_minor,
+                    counted_minor: *counted_minor,
+                    variance_minor: *variance_minor,
+                })
+            },
+            _ => None,
+        };
+
+        let record = event.map(|event| {
+            let tx = self.transactions.get(&handle);
+            let terminal_id = terminal_id.or_else(|| tx.and_then(|tx| tx.terminal_id.clone()));
+            let operator_id = operator_id.or_else(|| tx.and_then(|tx| tx.operator_id.clone()));
+            AuditRecord {
+                timestamp: entry.timestamp,
+                sequence_number: entry.sequence_number,
+                correlation_id: self.correlation_id(handle, terminal_id.as_deref(), operator_id.as_deref()),
+                store: tx.and_then(|tx| tx.store.clone()),
+                terminal_id,
+                operator_id: operator_id.map(|o| pii::protect(PiiCategory::OperatorId, &o).into_owned()),
+                event,
+                transaction_handle: (handle != PK_INVALID_HANDLE).then_some(handle),
+            }
+        });
+
+        match &entry.operation {
+            JournalOperation::TransactionCommit { .. } | JournalOperation::TransactionAbandon { .. } => {
+                self.transactions.remove(&handle);
+            },
+            JournalOperation::DrawerSessionClose { terminal_id, .. } => {
+                self.drawer_sessions.remove(terminal_id);
+            },
+            _ => {},
+        }
+        record
+    }
+
+    fn write(&mut self, record: &AuditRecord) -> Result<(), String> {
+        let (max_bytes, keep_files) = (self.max_bytes, self.keep_files);
+        let rotate = match &mut self.sink {
+            Sink::Memory(records) => {
+                records.push_back(record.clone());
+                while records.len() > MEMORY_RECORDS {
+                    records.pop_front();
+                }
+                return Ok(());
+            },
+            Sink::File { path, writer, written_bytes } => {
+                let line = serde_json::to_string(record)
+                    .map_err(|e| format!("Failed to serialize audit record: {}", e))?;
+                writeln!(writer, "{}", line)
+                    .and_then(|_| writer.flush())
+                    .map_err(|e| format!("Failed to write audit log {}: {}", path.display(), e))?;
+                *written_bytes += line.len() as u64 + 1;
+                max_bytes > 0 && *written_bytes >= max_bytes
+            },
+        };
+        if rotate {
+            self.rotate(keep_files)?;
+        }
+        Ok(())
+    }
+
+    // Shifts `audit.<i>.jsonl` to `audit.<i+1>.jsonl`, dropping the oldest, and starts a new file
+    fn rotate(&mut self, keep_files: usize) -> Result<(), String> {
+        let Sink::File { path, writer, written_bytes } = &mut self.sink else {
+            return Ok(());
+        };
+        writer.flush().map_err(|e| format!("Failed to flush audit log: {}", e))?;
+        self.rotations += 1;
+
+        let rename = |from: &Path, to: &Path| fs::rename(from, to)
+            .map_err(|e| format!("Failed to rotate audit log {}: {}", from.display(), e));
+        // Files beyond the retention, left by a larger `keep_files` earlier, go as well
+        let mut index = keep_files;
+        while rotated_path(path, index).exists() {
+            index += 1;
+        }
+        for stale in keep_files..index {
+            let _ = fs::remove_file(rotated_path(path, stale));
+        }
+        for index in (1..keep_files).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                rename(&from, &rotated_path(path, index + 1))?;
+            }
+        }
+        rename(path, &rotated_path(path, 1))?;
+        *writer = BufWriter::new(open_append(path)?);
+        *written_bytes = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Backup and restore
+//! AUDIT COMPLIANCE: A backup is a directory holding the journal, the archive (every sealed
+//! transaction with its place in the hash chain), the open transactions and a manifest with
+//! the format version, the handle sequences and a SHA-256 digest of each file. The state is
+//! captured under the exclusive store lock in one step (the journal position, a copy-on-write
+//! archive snapshot and copies of the open transactions), so a backup is consistent to a
+//! single instant, and then written without the lock: the journal is copied through the
+//! captured position and the files are hashed while sales go on. It is written under a temporary name
+//! and renamed when complete, so a crash never leaves a backup that looks finished.
+//! Restore verifies everything before changing anything: file digests, journal checksums and
+//! sequence continuity, and the archive hash chain, recomputed record by record. It fills an
+//! empty kernel only; a kernel that has journaled anything refuses it.
+//! Backups can also be taken on a schedule, keeping the most recent ones.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::archive::{ArchiveSnapshot, TransactionArchive};
+use crate::journal::{Journal, JournalEntry};
+use crate::loyalty::LoyaltyActivity;
+use crate::{write_store_for_api, KernelError, Transaction};
+
+// 2: archive hashes leave out the customer token; archive lines carry loyalty addenda
+pub(crate) const BACKUP_FORMAT_VERSION: u32 = 2;
+pub(crate) const RESTORE_NOT_EMPTY: &str = "Restore requires an empty kernel";
+
+const MANIFEST_FILE: &str = "manifest.json";
+const JOURNAL_FILE: &str = "journal.wal";
+const ARCHIVE_FILE: &str = "archive.jsonl";
+const ACTIVE_FILE: &str = "active.jsonl";
+const BACKUP_PREFIX: &str = "backup-";
+const PARTIAL_SUFFIX: &str = ".partial";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub kernel_version: String,
+    pub created_at: DateTime<Utc>,
+    // The journal is backed up through this sequence number
+    pub journal_through: u64,
+    pub next_transaction_handle: u64,
+    pub next_quote_handle: u64,
+    pub archive_records: u64,
+    pub archive_head_hash: String,
+    pub active_transactions: u64,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveLine {
+    sequence_number: u64,
+    archived_at: DateTime<Utc>,
+    hash: String,
+    transaction: Transaction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    loyalty_addendum: Option<LoyaltyActivity>,
+}
+
+// The kernel state as of one instant, captured under the store lock
+pub(crate) struct BackupSnapshot {
+    pub journal: Arc<Journal>,
+    pub journal_through: u64,
+    pub next_transaction_handle: u64,
+    pub next_quote_handle: u64,
+    pub archive: ArchiveSnapshot,
+    pub active: Vec<Transaction>,
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> String {
+    format!("Failed to write backup {}: {}", path.display(), e)
+}
+
+fn digest(dir: &Path, name: &str) -> Result<BackupFile, String> {
+    let content = fs::read(dir.join(name))
+        .map_err(|e| format!("Failed to read backup file {}: {}", name, e))?;
+    Ok(BackupFile {
+        name: name.to_string(),
+        bytes: content.len() as u64,
+        sha256: Sha256::digest(&content).iter().map(|b| format!("{:02x}", b)).collect(),
+    })
+}
+
+fn write_lines<T: Serialize>(path: &Path, items: impl IntoIterator<Item = Result<T, String>>) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| io_error(path, e))?;
+    let mut writer = BufWriter::new(file);
+    for item in items {
+        let line = serde_json::to_string(&item?).map_err(|e| io_error(path, e))?;
+        writeln!(writer, "{}", line).map_err(|e| io_error(path, e))?;
+    }
+    writer.flush().and_then(|_| writer.get_ref().sync_all()).map_err(|e| io_error(path, e))
+}
+
+impl BackupSnapshot {
+    // Writes the backup into a new directory under `root`; returns its path and manifest
+    pub fn write(self, root: &Path) -> Result<(PathBuf, BackupManifest), String> {
+        let created_at = Utc::now();
+        let name = format!("{}{}-{}", BACKUP_PREFIX, created_at.format("%Y%m%dT%H%M%S%.3fZ"), self.journal_through);
+        let dir = root.join(&name);
+        let partial = root.join(format!("{}{}", name, PARTIAL_SUFFIX));
+        fs::create_dir_all(&partial).map_err(|e| io_error(&partial, e))?;
+
+        let mut entries = Vec::new();
+        self.journal.scan_after(0, |entry| {
+            let within = entry.sequence_number <= self.journal_through;
+            if within {
+                entries.push(Ok(entry.clone()));
+            }
+            within
+        })?;
+        write_lines(&partial.join(JOURNAL_FILE), entries)?;
+
+        let mut records = Vec::new();
+        self.archive.for_each_record(|record, tx| {
+            records.push(Ok(ArchiveLine {
+                sequence_number: record.sequence_number,
+                archived_at: record.archived_at,
+                hash: record.hash.clone(),
+                transaction: tx.clone(),
+                loyalty_addendum: record.loyalty_addendum.clone(),
+            }));
+            Ok(())
+        })?;
+        write_lines(&partial.join(ARCHIVE_FILE), records)?;
+        write_lines(&partial.join(ACTIVE_FILE), self.active.iter().map(Ok))?;
+
+        let manifest = BackupManifest {
+            format_version: BACKUP_FORMAT_VERSION,
+            kernel_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at,
+            journal_through: self.journal_through,
+            next_transaction_handle: self.next_transaction_handle,
+            next_quote_handle: self.next_quote_handle,
+            archive_records: self.archive.len() as u64,
+            archive_head_hash: self.archive.head_hash().to_string(),
+            active_transactions: self.active.len() as u64,
+            files: [JOURNAL_FILE, ARCHIVE_FILE, ACTIVE_FILE].iter()
+                .map(|name| digest(&partial, name))
+                .collect::<Result<_, _>>()?,
+        };
+        let content = serde_json::to_vec_pretty(&manifest).map_err(|e| io_error(&partial, e))?;
+        fs::write(partial.join(MANIFEST_FILE), content).map_err(|e| io_error(&partial, e))?;
+        fs::rename(&partial, &dir).map_err(|e| io_error(&dir, e))?;
+        Ok((dir, manifest))
+    }
+}
+
+// A backup that passed verification, ready to be restored
+pub(crate) struct VerifiedBackup {
+    pub manifest: BackupManifest,
+    pub entries: Vec<JournalEntry>,
+    pub archive: TransactionArchive,
+    pub active: Vec<Transaction>,
+}
+
+fn read_lines<T: for<'de> Deserialize<'de>>(dir: &Path, name: &str) -> Result<Vec<T>, KernelError> {
+    let file = File::open(dir.join(name))
+        .map_err(|e| KernelError::Internal(format!("Failed to read backup file {}: {}", name, e)))?;
+    BufReader::new(file).lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.map_err(|e| KernelError::Internal(format!("Failed to read backup file {}: {}", name, e)))?;
+            serde_json::from_str(&line)
+                .map_err(|e| KernelError::Validation(format!("Backup file {} line {} is invalid: {}", name, i + 1, e)))
+        })
+        .collect()
+}
+
+// Checks a backup directory without restoring it
+pub(crate) fn verify(dir: &Path) -> Result<VerifiedBackup, KernelError> {
+    let content = fs::read(dir.join(MANIFEST_FILE))
+        .map_err(|e| KernelError::Internal(format!("Failed to read backup manifest: {}", e)))?;
+    let manifest: BackupManifest = serde_json::from_slice(&content)
+        .map_err(|e| KernelError::Validation(format!("Backup manifest is invalid: {}", e)))?;
+    if manifest.format_version != BACKUP_FORMAT_VERSION {
+        return Err(KernelError::Validation(format!("Backup format version {} is not supported", manifest.format_version)));
+    }
+    for expected in &manifest.files {
+        let actual = digest(dir, &expected.name).map_err(KernelError::Internal)?;
+        if actual.bytes != expected.bytes || actual.sha256 != expected.sha256 {
+            return Err(KernelError::Validation(format!("Backup file {} does not match its digest", expected.name)));
+        }
+    }
+
+    let entries: Vec<JournalEntry> = read_lines(dir, JOURNAL_FILE)?;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.sequence_number != i as u64 + 1 || !entry.checksum_valid() {
+            return Err(KernelError::Validation(format!("Backup journal entry {} is corrupt or out of sequence", entry.sequence_number)));
+        }
+    }
+    if entries.last().map_or(0, |e| e.sequence_number) != manifest.journal_through {
+        return Err(KernelError::validation("Backup journal does not reach the manifest's sequence number"));
+    }
+
+    let mut archive = TransactionArchive::default();
+    for line in read_lines::<ArchiveLine>(dir, ARCHIVE_FILE)? {
+        if line.sequence_number != archive.len() as u64 + 1 {
+            return Err(KernelError::Validation(format!("Backup archive record {} is out of sequence", line.sequence_number)));
+        }
+        line.transaction.check_hierarchy().map_err(KernelError::Validation)?;
+        archive.restore(line.transaction, line.archived_at, &line.hash, line.loyalty_addendum).map_err(KernelError::Validation)?;
+    }
+    if archive.len() as u64 != manifest.archive_records || archive.snapshot().head_hash() != manifest.archive_head_hash {
+        return Err(KernelError::validation("Backup archive does not match its manifest"));
+    }
+
+    let active: Vec<Transaction> = read_lines(dir, ACTIVE_FILE)?;
+    if active.len() as u64 != manifest.active_transactions {
+        return Err(KernelError::validation("Backup open transactions do not match the manifest"));
+    }
+    for tx in &active {
+        tx.check_hierarchy().map_err(KernelError::Validation)?;
+    }
+    Ok(VerifiedBackup { manifest, entries, archive, active })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupSchedule {
+    pub directory: String,
+    pub interval_secs: u64,
+    // Most recent backups kept in the directory; 0 keeps them all
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupStatus {
+    pub schedule: Option<BackupSchedule>,
+    pub last_backup: Option<String>,
+    pub last_backup_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    status: BackupStatus,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+// Takes backups on a background thread
+#[derive(Clone, Default)]
+pub(crate) struct BackupScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+}
+
+impl BackupScheduler {
+    fn state(&self) -> MutexGuard<'_, SchedulerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn status(&self) -> BackupStatus {
+        self.state().status.clone()
+    }
+
+    pub fn record(&self, result: &Result<(PathBuf, BackupManifest), KernelError>) {
+        let mut state = self.state();
+        match result {
+            Ok((dir, manifest)) => {
+                state.status.last_backup = Some(dir.display().to_string());
+                state.status.last_backup_at = Some(manifest.created_at);
+                state.status.last_error = None;
+            },
+            Err(e) => {
+                eprintln!("WARNING: Backup failed: {}", e);
+                state.status.last_error = Some(e.to_string());
+            },
+        }
+    }
+
+    // Replaces the schedule; None stops scheduled backups
+    pub fn schedule(&self, schedule: Option<BackupSchedule>) -> Result<(), KernelError> {
+        let mut state = self.state();
+        if let Some(stop) = state.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        state.status.schedule = schedule.clone();
+        let schedule = match schedule {
+            Some(schedule) => schedule,
+            None => return Ok(()),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        state.stop = Some(Arc::clone(&stop));
+        drop(state);
+        let scheduler = self.clone();
+        thread::Builder::new()
+            .name("pk-backup".to_string())
+            .spawn(move || scheduler.run(schedule, &stop))
+            .map(|_| ())
+            .map_err(|e| KernelError::Internal(format!("Failed to start backup scheduler: {}", e)))
+    }
+
+    fn run(&self, schedule: BackupSchedule, stop: &AtomicBool) {
+        let root = PathBuf::from(&schedule.directory);
+        let interval = Duration::from_secs(schedule.interval_secs.max(1));
+        let mut last = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(250).min(interval));
+            if last.elapsed() < interval {
+                continue;
+            }
+            last = Instant::now();
+
+            let result = create_backup(&root);
+            self.record(&result);
+            if result.is_ok() && schedule.keep > 0 {
+                if let Err(e) = prune(&root, schedule.keep) {
+                    eprintln!("WARNING: Old backups not pruned: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// Captures the store and writes the backup without holding the lock
+pub(crate) fn create_backup(root: &Path) -> Result<(PathBuf, BackupManifest), KernelError> {
+    let snapshot = write_store_for_api()?.backup_snapshot();
+    snapshot.write(root).map_err(KernelError::Internal)
+}
+
+// Removes all but the `keep` most recent finished backups under `root`
+fn prune(root: &Path, keep: usize) -> Result<(), String> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(root)
+        .map_err(|e| format!("Failed to list backups in {}: {}", root.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir() && path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(BACKUP_PREFIX) && !n.ends_with(PARTIAL_SUFFIX)))
+        .collect();
+    // Names start with the creation time, so they sort oldest first
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for path in &backups[..excess] {
+        fs::remove_dir_all(path).map_err(|e| format!("Failed to remove backup {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
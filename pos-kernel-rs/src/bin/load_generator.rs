@@ -0,0 +1,225 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Load generator for the kernel HTTP service
+//! Drives concurrent lanes through the service API the way the .NET client does: open a
+//! session, begin a transaction, scan lines while polling totals, pay in cash, close the
+//! session. Reports per-operation latency percentiles and overall throughput.
+//!
+//! Usage: pos-kernel-load [--target HOST:PORT] [--lanes N] [--transactions N] [--lines N]
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+const USAGE: &str = "Usage: pos-kernel-load [--target HOST:PORT] [--lanes N] [--transactions N] [--lines N]";
+
+struct Options {
+    target: String,
+    lanes: usize,
+    transactions: usize,
+    lines: usize,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut options = Self { target: "127.0.0.1:8080".to_string(), lanes: 4, transactions: 100, lines: 20 };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+            let count = || value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", arg, value));
+            match arg.as_str() {
+                "--target" => options.target = value.clone(),
+                "--lanes" => options.lanes = count()?,
+                "--transactions" => options.transactions = count()?,
+                "--lines" => options.lines = count()?,
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+        if options.lanes == 0 {
+            return Err("--lanes must be at least 1".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// Per-operation latencies for one lane, merged at the end of the run
+#[derive(Default)]
+struct Latencies {
+    samples: BTreeMap<&'static str, Vec<Duration>>,
+    errors: usize,
+}
+
+impl Latencies {
+    fn merge(&mut self, other: Latencies) {
+        for (operation, samples) in other.samples {
+            self.samples.entry(operation).or_default().extend(samples);
+        }
+        self.errors += other.errors;
+    }
+}
+
+struct Client<'a> {
+    target: &'a str,
+    latencies: Latencies,
+}
+
+impl Client<'_> {
+    // One request per connection keeps the client independent of the server's keep-alive policy
+    fn call(&mut self, operation: &'static str, method: &str, path: &str, body: Option<Value>) -> Result<Value, String> {
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method, path, self.target, body.len(), body
+        );
+
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(self.target).map_err(|e| format!("Connect failed: {}", e))?;
+        stream.write_all(request.as_bytes()).map_err(|e| format!("Write failed: {}", e))?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| format!("Read failed: {}", e))?;
+        self.latencies.samples.entry(operation).or_default().push(start.elapsed());
+
+        let (head, payload) = response.split_once("\r\n\r\n").ok_or("Malformed HTTP response")?;
+        let status = head.split_whitespace().nth(1).unwrap_or("");
+        if !status.starts_with('2') {
+            return Err(format!("{} {} returned {}", method, path, status));
+        }
+        let value: Value = serde_json::from_str(payload).map_err(|e| format!("Invalid JSON from {}: {}", path, e))?;
+        if value["success"] == json!(false) {
+            return Err(format!("{} {} failed: {}", method, path, value["error"]));
+        }
+        Ok(value)
+    }
+
+    fn checkout(&mut self, lane: usize, lines: usize) -> Result<(), String> {
+        let session = self.call("open_session", "POST", "/api/sessions", Some(json!({
+            "terminal_id": format!("LOAD-{:02}", lane),
+            "operator_id": "LOAD",
+        })))?;
+        let session_id = session["session_id"].as_str().ok_or("Missing session_id")?.to_string();
+        let base = format!("/api/sessions/{}/transactions", session_id);
+
+        let transaction = self.call("begin_transaction", "POST", &base, Some(json!({
+            "session_id": session_id,
+            "store": "LOAD",
+            "currency": "USD",
+        })))?;
+        let transaction_id = transaction["transaction_id"].as_str().ok_or("Missing transaction_id")?.to_string();
+        let path = format!("{}/{}", base, transaction_id);
+
+        for i in 0..lines {
+            self.call("add_line", "POST", &format!("{}/lines", path), Some(json!({
+                "session_id": session_id,
+                "transaction_id": transaction_id,
+                "product_id": format!("SKU-{:04}", i % 50),
+                "quantity": 1,
+                "unit_price": 1.99,
+            })))?;
+            // Customer display refresh after every scan
+            self.call("get_transaction", "GET", &path, None)?;
+        }
+
+        let totals = self.call("get_transaction", "GET", &path, None)?;
+        let total = totals["total"].as_f64().ok_or("Missing total")?;
+        self.call("add_payment", "POST", &format!("{}/payment", path), Some(json!({
+            "session_id": session_id,
+            "transaction_id": transaction_id,
+            "amount": total,
+            "payment_type": "cash",
+        })))?;
+
+        self.call("close_session", "DELETE", &format!("/api/sessions/{}", session_id), None)?;
+        Ok(())
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "Driving {} lanes x {} transactions x {} lines against {}",
+        options.lanes, options.transactions, options.lines, options.target
+    );
+
+    let start = Instant::now();
+    let mut latencies = Latencies::default();
+    thread::scope(|scope| {
+        let lanes: Vec<_> = (0..options.lanes).map(|lane| {
+            let options = &options;
+            scope.spawn(move || {
+                let mut client = Client { target: &options.target, latencies: Latencies::default() };
+                for _ in 0..options.transactions {
+                    if let Err(e) = client.checkout(lane, options.lines) {
+                        eprintln!("WARNING: lane {}: {}", lane, e);
+                        client.latencies.errors += 1;
+                    }
+                }
+                client.latencies
+            })
+        }).collect();
+        for lane in lanes {
+            latencies.merge(lane.join().expect("lane thread panicked"));
+        }
+    });
+    let elapsed = start.elapsed();
+
+    println!("{:<18} {:>8} {:>10} {:>10} {:>10} {:>10}", "operation", "count", "p50 ms", "p95 ms", "p99 ms", "max ms");
+    let mut requests = 0;
+    for (operation, samples) in &mut latencies.samples {
+        samples.sort();
+        requests += samples.len();
+        println!(
+            "{:<18} {:>8} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+            operation,
+            samples.len(),
+            millis(percentile(samples, 0.50)),
+            millis(percentile(samples, 0.95)),
+            millis(percentile(samples, 0.99)),
+            millis(percentile(samples, 1.0)),
+        );
+    }
+
+    let completed = options.lanes * options.transactions - latencies.errors;
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{} transactions ({} failed) and {} requests in {:.2}s: {:.1} tx/s, {:.1} req/s",
+        completed, latencies.errors, requests, seconds, completed as f64 / seconds, requests as f64 / seconds
+    );
+
+    if latencies.errors > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
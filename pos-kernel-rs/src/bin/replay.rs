@@ -0,0 +1,203 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Journal replay for support
+//! Replays a copy of a lane's journal file entry by entry and prints what each entry changed
+//! in the kernel state it describes. Breakpoints stop before the entry with that sequence
+//! number and read commands from standard input:
+//!   s          apply the next entry and stop again
+//!   c          continue to the next breakpoint
+//!   e          print the next entry as journaled
+//!   p [HANDLE] print the whole state, or one transaction, as JSON
+//!   b SEQ      add a breakpoint
+//!   q          quit
+//! The end of standard input continues without stopping.
+//!
+//! Usage: pk-replay JOURNAL [--break SEQ]... [--step] [--from SEQ] [--handle HANDLE]
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use pos_kernel::replay::{JournalReplay, ReplayStep};
+
+const USAGE: &str = "Usage: pk-replay JOURNAL [--break SEQ]... [--step] [--from SEQ] [--handle HANDLE]";
+
+struct Options {
+    journal: PathBuf,
+    breakpoints: BTreeSet<u64>,
+    // Stop before every entry
+    step: bool,
+    // Entries before this sequence number are applied without printing
+    from: u64,
+    // Only entries of this transaction are printed
+    handle: Option<u64>,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut journal = None;
+        let mut options = Self { journal: PathBuf::new(), breakpoints: BTreeSet::new(), step: false, from: 0, handle: None };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut number = || {
+                let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+                value.parse::<u64>().map_err(|_| format!("Invalid value for {}: {}", arg, value))
+            };
+            match arg.as_str() {
+                "--break" => {
+                    let sequence = number()?;
+                    options.breakpoints.insert(sequence);
+                },
+                "--step" => options.step = true,
+                "--from" => options.from = number()?,
+                "--handle" => options.handle = Some(number()?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown argument: {}", arg)),
+                _ if journal.is_none() => journal = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+        options.journal = journal.ok_or("Missing journal file")?;
+        Ok(options)
+    }
+}
+
+fn print_value(value: &Option<serde_json::Value>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+fn print_step(step: &ReplayStep) {
+    let entry = &step.entry;
+    if let Some(expected) = step.expected_sequence {
+        println!("!! Sequence gap: expected #{}, found #{}", expected, entry.sequence_number);
+    }
+    let handle = match entry.transaction_handle {
+        0 => String::new(),
+        handle => format!(" txn {}", handle),
+    };
+    let checksum = if entry.checksum_valid { "" } else { "  !! CHECKSUM MISMATCH" };
+    println!("#{} {}{} {}{}", entry.sequence_number, entry.timestamp.to_rfc3339(), handle, entry.operation, checksum);
+    for change in &step.changes {
+        println!("    {}: {} -> {}", change.path, print_value(&change.before), print_value(&change.after));
+    }
+}
+
+enum Resume {
+    Step,
+    Continue,
+    Quit,
+}
+
+fn prompt(replay: &JournalReplay, options: &mut Options, input: &mut impl BufRead) -> Resume {
+    loop {
+        print!("(pk-replay) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if input.read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            options.step = false;
+            options.breakpoints.clear();
+            return Resume::Continue;
+        }
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("s"), _) => return Resume::Step,
+            (Some("c"), _) | (None, _) => return Resume::Continue,
+            (Some("q"), _) => return Resume::Quit,
+            (Some("e"), _) => match replay.peek() {
+                Some(entry) => println!("{}", serde_json::to_string_pretty(&entry).unwrap_or_default()),
+                None => println!("No entries left"),
+            },
+            (Some("p"), None) => println!("{}", serde_json::to_string_pretty(replay.state()).unwrap_or_default()),
+            (Some("p"), Some(handle)) => {
+                match handle.parse::<u64>().ok().and_then(|h| replay.state().transactions.get(&h)) {
+                    Some(tx) => println!("{}", serde_json::to_string_pretty(tx).unwrap_or_default()),
+                    None => println!("No transaction {}", handle),
+                }
+            },
+            (Some("b"), Some(sequence)) => match sequence.parse::<u64>() {
+                Ok(sequence) => {
+                    options.breakpoints.insert(sequence);
+                },
+                Err(_) => println!("Invalid sequence number: {}", sequence),
+            },
+            _ => println!("Commands: s (step), c (continue), e (next entry), p [HANDLE] (state), b SEQ (break), q (quit)"),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut options = match Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut replay = match JournalReplay::open(&options.journal) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Replaying {} entries from {}", replay.len(), options.journal.display());
+    if replay.unreadable_lines() > 0 {
+        println!("!! {} unreadable lines skipped", replay.unreadable_lines());
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let (mut gaps, mut checksum_failures) = (0, 0);
+    while let Some(next) = replay.peek() {
+        if options.step || options.breakpoints.contains(&next.sequence_number) {
+            println!("-- Before #{} {}", next.sequence_number, next.operation);
+            match prompt(&replay, &mut options, &mut input) {
+                Resume::Step => options.step = true,
+                Resume::Continue => options.step = false,
+                Resume::Quit => return ExitCode::SUCCESS,
+            }
+        }
+        let Some(step) = replay.step() else { break };
+        gaps += step.expected_sequence.is_some() as usize;
+        checksum_failures += !step.entry.checksum_valid as usize;
+        let shown = step.entry.sequence_number >= options.from
+            && options.handle.is_none_or(|handle| handle == step.entry.transaction_handle);
+        if shown {
+            print_step(&step);
+        }
+    }
+
+    let state = replay.state();
+    let count = |name: &str| state.transactions.values().filter(|tx| tx.state == name).count();
+    println!(
+        "Replayed through #{}: {} transactions ({} building, {} layaway, {} committed, {} cancelled), {} sequence gaps, {} checksum failures",
+        state.last_sequence,
+        state.transactions.len(),
+        count("building"),
+        count("layaway"),
+        count("committed"),
+        count("cancelled"),
+        gaps,
+        checksum_failures,
+    );
+    if gaps > 0 || checksum_failures > 0 || replay.unreadable_lines() > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Drawer cash management
+//! AUDIT COMPLIANCE: Cash is accounted for per drawer session, opened on a terminal with a
+//! counted float and closed with a counted total. Sales committed on the terminal add their net
+//! cash (cash tendered less change given); safe drops and pickups take cash out of the drawer
+//! and loans from the safe put cash in. Every movement is counted by denomination and kept on
+//! the session, so the close compares the count with the expected total: float + cash sales +
+//! loans - drops - pickups. Denomination values are minor units chosen by the host; the kernel
+//! does not know a currency's notes and coins.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::KernelError;
+
+pub const NO_SESSION: &str = "No drawer session open for terminal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CashMovementKind {
+    // The cashier moves excess cash from the drawer into the safe
+    Drop = 0,
+    // A supervisor removes cash from the drawer
+    Pickup = 1,
+    // A supervisor adds cash from the safe, typically change
+    Loan = 2,
+}
+
+impl CashMovementKind {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(CashMovementKind::Drop),
+            1 => Some(CashMovementKind::Pickup),
+            2 => Some(CashMovementKind::Loan),
+            _ => None,
+        }
+    }
+
+    fn sign(self) -> i64 {
+        match self {
+            CashMovementKind::Drop | CashMovementKind::Pickup => -1,
+            CashMovementKind::Loan => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DenominationCount {
+    pub value_minor: i64,
+    pub count: u32,
+}
+
+// Validates a count and returns its total; each denomination may appear once
+pub(crate) fn count_total(counts: &[DenominationCount]) -> Result<i64, KernelError> {
+    let mut total: i64 = 0;
+    for (i, denomination) in counts.iter().enumerate() {
+        if denomination.value_minor <= 0 {
+            return Err(KernelError::validation("Denomination value must be positive"));
+        }
+        if counts[..i].iter().any(|d| d.value_minor == denomination.value_minor) {
+            return Err(KernelError::validation("Denomination counted twice"));
+        }
+        total = denomination.value_minor.checked_mul(i64::from(denomination.count))
+            .and_then(|amount| total.checked_add(amount))
+            .ok_or_else(|| KernelError::validation("Cash count out of range"))?;
+    }
+    Ok(total)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CashMovement {
+    pub kind: CashMovementKind,
+    pub amount_minor: i64,
+    pub denominations: Vec<DenominationCount>,
+    pub operator_id: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawerSession {
+    pub terminal_id: String,
+    pub operator_id: Option<String>,
+    pub currency: String,
+    pub opened_at: DateTime<Utc>,
+    pub opening_float: Vec<DenominationCount>,
+    pub float_minor: i64,
+    // Net cash of committed transactions in the session currency; refunds count negative
+    pub cash_sales_minor: i64,
+    pub cash_transactions: u32,
+    pub movements: Vec<CashMovement>,
+    pub expected_minor: i64,
+}
+
+impl DrawerSession {
+    fn movement_total(&self, kind: CashMovementKind) -> i64 {
+        self.movements.iter()
+            .filter(|m| m.kind == kind)
+            .map(|m| m.amount_minor)
+            .sum()
+    }
+}
+
+// A closed session with its count; the variance is counted less expected, negative when short
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawerSessionReport {
+    #[serde(flatten)]
+    pub session: DrawerSession,
+    pub dropped_minor: i64,
+    pub picked_up_minor: i64,
+    pub loaned_minor: i64,
+    pub counted: Vec<DenominationCount>,
+    pub counted_minor: i64,
+    pub variance_minor: i64,
+    pub closed_by: Option<String>,
+    pub closed_at: DateTime<Utc>,
+}
+
+// Open sessions by terminal, and the last closed session of each terminal
+#[derive(Debug, Default)]
+pub(crate) struct DrawerSessions {
+    open: HashMap<String, DrawerSession>,
+    closed: HashMap<String, DrawerSessionReport>,
+}
+
+impl DrawerSessions {
+    pub fn open(&mut self, terminal_id: &str, operator_id: Option<String>, currency: &str, opening_float: Vec<DenominationCount>, now: DateTime<Utc>) -> Result<i64, KernelError> {
+        if self.open.contains_key(terminal_id) {
+            return Err(KernelError::invalid_state("Drawer session already open for terminal"));
+        }
+        let float_minor = count_total(&opening_float)?;
+        self.open.insert(terminal_id.to_string(), DrawerSession {
+            terminal_id: terminal_id.to_string(),
+            operator_id,
+            currency: currency.to_string(),
+            opened_at: now,
+            opening_float,
+            float_minor,
+            cash_sales_minor: 0,
+            cash_transactions: 0,
+            movements: Vec::new(),
+            expected_minor: float_minor,
+        });
+        Ok(float_minor)
+    }
+
+    pub fn session(&self, terminal_id: &str) -> Option<&DrawerSession> {
+        self.open.get(terminal_id)
+    }
+
+    pub fn last_report(&self, terminal_id: &str) -> Option<&DrawerSessionReport> {
+        self.closed.get(terminal_id)
+    }
+
+    // Drops and pickups may not take out more cash than the drawer is expected to hold;
+    // returns the amount moved and the expected total after it
+    pub fn record_movement(&mut self, terminal_id: &str, movement: CashMovement) -> Result<(i64, i64), KernelError> {
+        let session = self.open.get_mut(terminal_id).ok_or_else(|| KernelError::not_found(NO_SESSION))?;
+        let amount_minor = movement.amount_minor;
+        if amount_minor <= 0 {
+            return Err(KernelError::validation("Cash movement amount must be positive"));
+        }
+        if movement.kind.sign() < 0 && amount_minor > session.expected_minor {
+            return Err(KernelError::validation("Cash movement exceeds expected drawer cash"));
+        }
+        let expected_minor = session.expected_minor.checked_add(movement.kind.sign() * amount_minor)
+            .ok_or_else(|| KernelError::validation("Cash count out of range"))?;
+        session.expected_minor = expected_minor;
+        session.movements.push(movement);
+        Ok((amount_minor, expected_minor))
+    }
+
+    // Transactions in another currency, or on a terminal without an open session, are not
+    // counted against a drawer
+    pub fn record_sale(&mut self, terminal_id: &str, currency: &str, net_cash_minor: i64) {
+        let session = match self.open.get_mut(terminal_id) {
+            Some(session) if session.currency == currency && net_cash_minor != 0 => session,
+            _ => return,
+        };
+        session.cash_sales_minor = session.cash_sales_minor.saturating_add(net_cash_minor);
+        session.cash_transactions += 1;
+        session.expected_minor = session.expected_minor.saturating_add(net_cash_minor);
+    }
+
+    pub fn close(&mut self, terminal_id: &str, counted: Vec<DenominationCount>, closed_by: Option<String>, now: DateTime<Utc>) -> Result<&DrawerSessionReport, KernelError> {
+        let counted_minor = count_total(&counted)?;
+        let session = self.open.remove(terminal_id).ok_or_else(|| KernelError::not_found(NO_SESSION))?;
+        let report = DrawerSessionReport {
+            dropped_minor: session.movement_total(CashMovementKind::Drop),
+            picked_up_minor: session.movement_total(CashMovementKind::Pickup),
+            loaned_minor: session.movement_total(CashMovementKind::Loan),
+            variance_minor: counted_minor.saturating_sub(session.expected_minor),
+            counted,
+            counted_minor,
+            closed_by,
+            closed_at: now,
+            session,
+        };
+        self.closed.insert(terminal_id.to_string(), report);
+        Ok(&self.closed[terminal_id])
+    }
+}
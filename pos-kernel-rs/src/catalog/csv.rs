@@ -0,0 +1,353 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! CSV product catalog
+//! A reference `ProductProvider` held in memory and imported from CSV, so a demo store or a
+//! single shop runs scan, price lookup and add line without an external catalog service. The
+//! first row names the columns, in any order: `sku`, `currency` and `unit_minor` are required,
+//! `name`, `description`, `department`, `barcode` and `flags` optional; other columns are
+//! ignored. A product has one row per currency it is priced in. `barcode` holds any number of
+//! scannable codes separated by `|`; EAN and UPC codes are check-digit verified and indexed by
+//! GTIN, so they resolve however the scanner reports them. A code belongs to one SKU, and a
+//! re-imported SKU keeps only the codes its new rows list. `flags` holds flag names separated
+//! by `|` (open_price, weighed, age_restricted, not_discountable, not_returnable,
+//! discontinued). An import is checked in full before any of it is applied.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{PoisonError, RwLock};
+
+use super::{Product, ProductFlags, ProductProvider};
+use crate::scan;
+
+// Rows accepted in one import
+pub const MAX_IMPORT_ROWS: usize = 1_000_000;
+
+#[derive(Default)]
+struct Products {
+    // Keyed by SKU, then currency code
+    by_sku: HashMap<String, HashMap<String, Product>>,
+    // Scanned codes (GTIN-14 for GS1 codes) per SKU, as last imported
+    sku_codes: HashMap<String, Vec<String>>,
+    // Scanned code to SKU, rebuilt from `sku_codes` on each import
+    codes: HashMap<String, String>,
+}
+
+#[derive(Default)]
+pub struct CsvCatalog {
+    products: RwLock<Products>,
+}
+
+impl CsvCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_csv(csv: &str) -> Result<Self, String> {
+        let catalog = Self::new();
+        catalog.import(csv)?;
+        Ok(catalog)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let csv = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read catalog {}: {}", path, e))?;
+        Self::from_csv(&csv)
+    }
+
+    // Adds or replaces the products in `csv`; returns the number of rows imported
+    pub fn import(&self, csv: &str) -> Result<usize, String> {
+        let rows = parse_rows(csv)?;
+        let mut rows = rows.into_iter();
+        let header = rows.next().ok_or("Catalog is empty")?;
+        let columns = Columns::new(&header)?;
+
+        let mut imported: Vec<(usize, Row)> = Vec::new();
+        let mut seen = HashMap::new();
+        // Scanned code to the SKU and row that first listed it
+        let mut code_rows: HashMap<String, (String, usize)> = HashMap::new();
+        for (index, row) in rows.enumerate() {
+            // Row numbers as a spreadsheet shows them, counting the header
+            let row_number = index + 2;
+            if row.len() == 1 && row[0].trim().is_empty() {
+                continue;
+            }
+            if imported.len() >= MAX_IMPORT_ROWS {
+                return Err(format!("Catalog has more than {} rows", MAX_IMPORT_ROWS));
+            }
+            let row = columns.row(&row)
+                .map_err(|e| format!("Catalog row {}: {}", row_number, e))?;
+            let key = (row.product.sku.clone(), row.currency.clone());
+            if let Some(first) = seen.insert(key, row_number) {
+                return Err(format!("Catalog row {}: duplicates row {}", row_number, first));
+            }
+            // The rows of one SKU share its codes across currencies
+            for code in &row.codes {
+                match code_rows.get(code) {
+                    Some((sku, first)) if *sku != row.product.sku => {
+                        return Err(format!("Catalog row {}: barcode '{}' is already on row {}", row_number, code, first));
+                    },
+                    Some(_) => {},
+                    None => {
+                        code_rows.insert(code.clone(), (row.product.sku.clone(), row_number));
+                    },
+                }
+            }
+            imported.push((row_number, row));
+        }
+
+        let count = imported.len();
+        let imported_skus: HashSet<String> = imported.iter().map(|(_, row)| row.product.sku.clone()).collect();
+        let mut products = self.products.write().unwrap_or_else(PoisonError::into_inner);
+        // Products this import leaves in place keep their codes
+        for (row_number, row) in &imported {
+            for code in &row.codes {
+                if let Some(owner) = products.codes.get(code).filter(|owner| !imported_skus.contains(*owner)) {
+                    return Err(format!("Catalog row {}: barcode '{}' already belongs to SKU '{}'", row_number, code, owner));
+                }
+            }
+        }
+
+        for sku in &imported_skus {
+            products.sku_codes.remove(sku);
+        }
+        for (_, row) in imported {
+            let sku_codes = products.sku_codes.entry(row.product.sku.clone()).or_default();
+            for code in row.codes {
+                if !sku_codes.contains(&code) {
+                    sku_codes.push(code);
+                }
+            }
+            products.by_sku.entry(row.product.sku.clone()).or_default()
+                .insert(row.currency, row.product);
+        }
+        let codes = products.sku_codes.iter()
+            .flat_map(|(sku, codes)| codes.iter().map(move |code| (code.clone(), sku.clone())))
+            .collect();
+        products.codes = codes;
+        Ok(count)
+    }
+
+    // Number of distinct SKUs
+    pub fn len(&self) -> usize {
+        self.products.read().unwrap_or_else(PoisonError::into_inner).by_sku.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ProductProvider for CsvCatalog {
+    fn product(&self, sku: &str, currency: &str) -> Option<Product> {
+        let products = self.products.read().unwrap_or_else(PoisonError::into_inner);
+        products.by_sku.get(sku)?.get(&currency.to_uppercase()).cloned()
+    }
+
+    fn lookup_code(&self, code: &str, currency: &str) -> Option<Product> {
+        let sku = {
+            let products = self.products.read().unwrap_or_else(PoisonError::into_inner);
+            products.codes.get(code).cloned()
+        };
+        self.product(sku.as_deref().unwrap_or(code), currency)
+    }
+}
+
+struct Row {
+    currency: String,
+    product: Product,
+    codes: Vec<String>,
+}
+
+// Positions of the known columns in the header row
+struct Columns {
+    sku: usize,
+    currency: usize,
+    unit_minor: usize,
+    name: Option<usize>,
+    description: Option<usize>,
+    department: Option<usize>,
+    barcode: Option<usize>,
+    flags: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &[String]) -> Result<Self, String> {
+        let find = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let require = |name: &str| find(name).ok_or_else(|| format!("Catalog has no '{}' column", name));
+        Ok(Self {
+            sku: require("sku")?,
+            currency: require("currency")?,
+            unit_minor: require("unit_minor")?,
+            name: find("name"),
+            description: find("description"),
+            department: find("department"),
+            barcode: find("barcode"),
+            flags: find("flags"),
+        })
+    }
+
+    fn row(&self, fields: &[String]) -> Result<Row, String> {
+        let field = |index: usize| fields.get(index).map(|f| f.trim()).unwrap_or("");
+        let optional = |index: Option<usize>| index.map(field).filter(|f| !f.is_empty()).map(str::to_string);
+
+        let sku = field(self.sku);
+        if sku.is_empty() {
+            return Err("SKU is empty".to_string());
+        }
+        let currency = field(self.currency).to_uppercase();
+        if currency.is_empty() {
+            return Err("currency is empty".to_string());
+        }
+        let unit_minor = field(self.unit_minor).parse::<i64>()
+            .map_err(|_| format!("invalid unit_minor '{}'", field(self.unit_minor)))?;
+        if unit_minor < 0 {
+            return Err("unit_minor is negative".to_string());
+        }
+        let flags = match optional(self.flags) {
+            Some(flags) => parse_flags(&flags)?,
+            None => ProductFlags::default(),
+        };
+        let codes = match optional(self.barcode) {
+            Some(barcodes) => barcodes.split('|')
+                .map(str::trim)
+                .filter(|code| !code.is_empty())
+                .map(|code| scan::parse(code).map(|scan| scan.code).map_err(|e| format!("barcode '{}': {}", code, e)))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let product = Product {
+            sku: sku.to_string(),
+            name: optional(self.name),
+            description: optional(self.description),
+            department: optional(self.department),
+            unit_minor,
+            flags,
+        };
+        Ok(Row { currency, product, codes })
+    }
+}
+
+fn parse_flags(value: &str) -> Result<ProductFlags, String> {
+    value.split('|').map(str::trim).filter(|name| !name.is_empty())
+        .try_fold(ProductFlags::default(), |flags, name| {
+            let flag = match name.to_ascii_lowercase().as_str() {
+                "open_price" => ProductFlags::OPEN_PRICE,
+                "weighed" => ProductFlags::WEIGHED,
+                "age_restricted" => ProductFlags::AGE_RESTRICTED,
+                "not_discountable" => ProductFlags::NOT_DISCOUNTABLE,
+                "not_returnable" => ProductFlags::NOT_RETURNABLE,
+                "discontinued" => ProductFlags::DISCONTINUED,
+                _ => return Err(format!("unknown flag '{}'", name)),
+            };
+            Ok(flags | flag)
+        })
+}
+
+// RFC 4180 records: quoted fields may hold separators, doubled quotes and line breaks
+fn parse_rows(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let csv = csv.strip_prefix('\u{feff}').unwrap_or(csv);
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {},
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("Catalog ends inside a quoted field".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "sku,currency,unit_minor,barcode\n";
+
+    fn rows(csv: &str) -> Vec<Vec<String>> {
+        parse_rows(csv).expect("catalog parses")
+    }
+
+    fn import_error(catalog: &CsvCatalog, csv: &str) -> String {
+        catalog.import(csv).expect_err("import should be rejected")
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        assert_eq!(rows("a,\"b,c\",d\n"), [["a", "b,c", "d"]]);
+        assert_eq!(rows("\"say \"\"hi\"\"\",\"\"\n"), [["say \"hi\"", ""]]);
+        assert_eq!(rows("\"two\nlines\",x"), [["two\nlines", "x"]]);
+        assert_eq!(parse_rows("a,\"open\n"), Err("Catalog ends inside a quoted field".to_string()));
+    }
+
+    #[test]
+    fn parses_crlf_and_bom() {
+        assert_eq!(rows("\u{feff}a,b\r\nc,d\r\n"), [["a", "b"], ["c", "d"]]);
+        assert_eq!(rows("\"x\r\ny\",z\r\n"), [["x\r\ny", "z"]]);
+        assert_eq!(rows("a,\r\n,b"), [["a", ""], ["", "b"]]);
+    }
+
+    #[test]
+    fn rejects_barcodes_on_two_skus() {
+        let catalog = CsvCatalog::new();
+        let csv = format!("{}A,EUR,100,4006381333931\nA,USD,110,4006381333931\nB,EUR,200,036000291452|4006381333931\n", HEADER);
+        assert_eq!(import_error(&catalog, &csv), "Catalog row 4: barcode '04006381333931' is already on row 2");
+        assert!(catalog.is_empty());
+
+        catalog.import(&format!("{}A,EUR,100,4006381333931\n", HEADER)).expect("catalog imports");
+        assert_eq!(
+            import_error(&catalog, &format!("{}B,EUR,200,4006381333931\n", HEADER)),
+            "Catalog row 2: barcode '04006381333931' already belongs to SKU 'A'"
+        );
+        assert_eq!(catalog.lookup_code("04006381333931", "EUR").map(|p| p.sku), Some("A".to_string()));
+    }
+
+    #[test]
+    fn reimport_rebuilds_barcodes() {
+        let catalog = CsvCatalog::from_csv(&format!("{}A,EUR,100,4006381333931\nB,EUR,200,036000291452\n", HEADER))
+            .expect("catalog imports");
+        assert_eq!(catalog.lookup_code("00036000291452", "EUR").map(|p| p.sku), Some("B".to_string()));
+
+        // A's code moves to B; B's old code is dropped
+        catalog.import(&format!("{}A,EUR,100,\nB,EUR,200,4006381333931\n", HEADER)).expect("catalog re-imports");
+        assert_eq!(catalog.lookup_code("04006381333931", "EUR").map(|p| p.sku), Some("B".to_string()));
+        assert!(catalog.lookup_code("00036000291452", "EUR").is_none());
+        assert_eq!(catalog.len(), 2);
+    }
+}
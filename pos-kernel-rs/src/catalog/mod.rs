@@ -0,0 +1,145 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Product catalog hook
+//! ARCHITECTURAL PRINCIPLE: Product master data lives in user space. A registered
+//! `ProductProvider` resolves SKUs and scanned codes to the product's name, description,
+//! shelf price and flags. Lines added by SKU take their display metadata from it, and the
+//! price the lane supplies is checked against the shelf price according to the configured
+//! `CatalogValidation`, so a stale price list on one lane cannot undercharge. Without a
+//! catalog, lines carry whatever metadata and price user space supplies.
+
+pub mod csv;
+
+use serde::Serialize;
+
+use crate::pricing::ProductInfo;
+use crate::{write_store_for_api, KernelError};
+
+pub use csv::CsvCatalog;
+
+// Product flags, combined as a bit set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ProductFlags(pub u32);
+
+impl ProductFlags {
+    // The price is keyed in at the lane (open department, deli by price); never checked
+    pub const OPEN_PRICE: Self = Self(1);
+    // Priced per unit of weight; the line carries a scale reading
+    pub const WEIGHED: Self = Self(1 << 1);
+    pub const AGE_RESTRICTED: Self = Self(1 << 2);
+    pub const NOT_DISCOUNTABLE: Self = Self(1 << 3);
+    pub const NOT_RETURNABLE: Self = Self(1 << 4);
+    // No longer sold; adding it is rejected
+    pub const DISCONTINUED: Self = Self(1 << 5);
+
+    pub fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl std::ops::BitOr for ProductFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Product {
+    pub sku: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    // Merchandising department, for movement reports
+    pub department: Option<String>,
+    // Shelf price in minor units of the requested currency
+    pub unit_minor: i64,
+    pub flags: ProductFlags,
+}
+
+// A product resolved through a price provider carries no flags
+impl From<ProductInfo> for Product {
+    fn from(info: ProductInfo) -> Self {
+        Product { sku: info.sku, name: info.name, description: info.description, department: None, unit_minor: info.unit_minor, flags: ProductFlags::default() }
+    }
+}
+
+pub trait ProductProvider: Send + Sync {
+    // The product sold under `sku`, priced in `currency`, or None when the SKU is unknown
+    fn product(&self, sku: &str, currency: &str) -> Option<Product>;
+
+    // Resolves a scanned code (the 14-digit GTIN of a GS1 barcode, otherwise the scanned text);
+    // by default the code is the SKU
+    fn lookup_code(&self, code: &str, currency: &str) -> Option<Product> {
+        self.product(code, currency)
+    }
+}
+
+// How lines added by SKU are checked against the catalog
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CatalogValidation {
+    // Metadata is filled in; prices and unknown SKUs are accepted
+    Off,
+    // A known product must be sold at its shelf price unless it is open-priced
+    #[default]
+    Price,
+    // As `Price`, and SKUs missing from the catalog are rejected
+    Strict,
+}
+
+impl std::str::FromStr for CatalogValidation {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "off" => Ok(Self::Off),
+            "price" => Ok(Self::Price),
+            "strict" => Ok(Self::Strict),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(crate) const NOT_IN_CATALOG: &str = "Product not found in catalog";
+pub(crate) const DISCONTINUED: &str = "Product is discontinued";
+pub(crate) const PRICE_MISMATCH: &str = "Price does not match catalog";
+
+// Checks a line about to be added against its catalog entry
+pub(crate) fn check_line(product: Option<&Product>, unit_minor: i64, validation: CatalogValidation) -> Result<(), KernelError> {
+    let product = match product {
+        Some(product) => product,
+        None if validation == CatalogValidation::Strict => return Err(KernelError::not_found(NOT_IN_CATALOG)),
+        None => return Ok(()),
+    };
+    if product.flags.contains(ProductFlags::DISCONTINUED) {
+        return Err(KernelError::validation(DISCONTINUED));
+    }
+    let price_checked = validation != CatalogValidation::Off && !product.flags.contains(ProductFlags::OPEN_PRICE);
+    if price_checked && unit_minor != product.unit_minor {
+        return Err(KernelError::validation(PRICE_MISMATCH));
+    }
+    Ok(())
+}
+
+// Registers (or replaces) the store's product catalog
+pub fn set_product_provider(provider: Box<dyn ProductProvider>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.product_provider = Some(provider);
+    Ok(())
+}
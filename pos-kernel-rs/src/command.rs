@@ -0,0 +1,199 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Message commands
+//! ARCHITECTURAL PRINCIPLE: Integrations that cannot call the C ABI send transaction commands
+//! as JSON messages instead: `{"id": "...", "command": "begin" | "add_line" | "tender" |
+//! "finalize", ...}`. Each command runs through the same entry point as the matching `pk_*`
+//! call and is answered with a reply carrying the message ID, the result code and, for
+//! `begin`, the new handle.
+//! Queues deliver at least once, so the ID is an idempotency key: a message seen before is
+//! answered with the reply it got then, without running the command again. Replies are kept
+//! for the last `command_idempotency_window` IDs; outcomes worth retrying (timeouts, capacity,
+//! internal errors) are not kept, so a redelivery tries again. A tender also carries the ID as
+//! its tender token, so it is never applied twice, even beyond the window or across restarts.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{last_error, PkResult, ResultCode};
+
+// Also the tender token limit, since a tender's ID becomes its token
+pub(crate) const MAX_COMMAND_ID_LEN: usize = 64;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Begin {
+        store: String,
+        currency: String,
+        #[serde(default = "default_decimal_places")]
+        decimal_places: u8,
+    },
+    AddLine { handle: u64, sku: String, qty: i32, unit_minor: i64 },
+    // A cash tender
+    Tender { handle: u64, amount_minor: i64 },
+    Finalize { handle: u64 },
+}
+
+fn default_decimal_places() -> u8 {
+    2
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandMessage {
+    pub id: String,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandReply {
+    pub id: String,
+    pub ok: bool,
+    // The `ResultCode` of the call
+    pub code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // This ID was processed before; the reply is the one given then
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+}
+
+fn code_name(code: i32) -> &'static str {
+    match code {
+        1 => "Not found",
+        2 => "Invalid state",
+        3 => "Validation failed",
+        4 => "Insufficient buffer",
+        5 => "Timed out",
+        6 => "Permission denied",
+        7 => "Capacity exceeded",
+        8 => "Parent line voided",
+        9 => "Currency mismatch",
+        _ => "Internal error",
+    }
+}
+
+impl CommandReply {
+    fn new(id: String, result: PkResult, handle: Option<u64>) -> Self {
+        let ok = result.code == ResultCode::Ok as i32;
+        let error = (!ok).then(|| last_error::get().unwrap_or_else(|| code_name(result.code).to_string()));
+        Self { id, ok, code: result.code, handle: handle.filter(|_| ok), error, duplicate: false }
+    }
+
+    pub(crate) fn invalid(id: String, error: String) -> Self {
+        Self { id, ok: false, code: ResultCode::ValidationFailed as i32, handle: None, error: Some(error), duplicate: false }
+    }
+
+    // A redelivery after one of these should run the command again
+    fn retryable(&self) -> bool {
+        [ResultCode::TimedOut as i32, ResultCode::CapacityExceeded as i32, ResultCode::InternalError as i32].contains(&self.code)
+    }
+}
+
+fn execute(id: &str, command: &Command) -> CommandReply {
+    last_error::clear();
+    let id = id.to_string();
+    // SAFETY: every pointer passed below comes from a live String for the duration of the call
+    unsafe {
+        match command {
+            Command::Begin { store, currency, decimal_places } => {
+                let mut handle = crate::PK_INVALID_HANDLE;
+                let result = crate::pk_begin_transaction(store.as_ptr(), store.len(), currency.as_ptr(), currency.len(), *decimal_places, &mut handle);
+                CommandReply::new(id, result, Some(handle))
+            },
+            Command::AddLine { handle, sku, qty, unit_minor } => {
+                CommandReply::new(id, crate::pk_add_line(*handle, sku.as_ptr(), sku.len(), *qty, *unit_minor), None)
+            },
+            Command::Tender { handle, amount_minor } => {
+                let result = crate::pk_add_cash_tender_with_token(*handle, *amount_minor, id.as_ptr(), id.len());
+                CommandReply::new(id, result, None)
+            },
+            Command::Finalize { handle } => CommandReply::new(id, crate::pk_finalize_transaction(*handle), None),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Replies {
+    by_id: HashMap<String, CommandReply>,
+    // Oldest first
+    order: VecDeque<String>,
+}
+
+// Runs command messages, answering redeliveries from the replies kept
+pub(crate) struct CommandProcessor {
+    window: Mutex<usize>,
+    replies: Mutex<Replies>,
+}
+
+impl CommandProcessor {
+    pub fn new(window: usize) -> Self {
+        Self { window: Mutex::new(window), replies: Mutex::new(Replies::default()) }
+    }
+
+    fn replies(&self) -> MutexGuard<'_, Replies> {
+        self.replies.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Takes effect at the next message; called under the store lock, so it does not wait for
+    // the replies, which are held while a command runs
+    pub fn set_window(&self, window: usize) {
+        *self.window.lock().unwrap_or_else(PoisonError::into_inner) = window;
+    }
+
+    fn trim(&self, replies: &mut Replies) {
+        let window = *self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        while replies.order.len() > window {
+            if let Some(id) = replies.order.pop_front() {
+                replies.by_id.remove(&id);
+            }
+        }
+    }
+
+    // Messages are processed one at a time, so a redelivery racing the original cannot run twice
+    pub fn process(&self, payload: &[u8]) -> CommandReply {
+        let message: CommandMessage = match serde_json::from_slice(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                let id = serde_json::from_slice::<serde_json::Value>(payload).ok()
+                    .and_then(|v| v.get("id").and_then(|id| id.as_str()).map(str::to_string))
+                    .unwrap_or_default();
+                return CommandReply::invalid(id, format!("Invalid command: {}", e));
+            },
+        };
+        if message.id.is_empty() || message.id.len() > MAX_COMMAND_ID_LEN || message.id.chars().any(char::is_control) {
+            return CommandReply::invalid(message.id, format!("Command ID must be 1 to {} printable bytes", MAX_COMMAND_ID_LEN));
+        }
+
+        let mut replies = self.replies();
+        if let Some(reply) = replies.by_id.get(&message.id) {
+            return CommandReply { duplicate: true, ..reply.clone() };
+        }
+        let reply = execute(&message.id, &message.command);
+        if !reply.retryable() {
+            replies.by_id.insert(message.id.clone(), reply.clone());
+            replies.order.push_back(message.id);
+            self.trim(&mut replies);
+        }
+        reply
+    }
+}
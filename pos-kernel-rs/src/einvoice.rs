@@ -0,0 +1,537 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Structured B2B e-invoices
+//! A committed transaction plus supplied seller and buyer details is exported as a UBL 2.1
+//! invoice (EN 16931 core) or as Factur-X: the UN/CEFACT CII XML at the BASIC profile, either
+//! on its own or embedded as `factur-x.xml` in a generated PDF.
+//! The kernel records a single tax breakdown per transaction, not per line, so invoices are
+//! limited to one VAT category. Net line amounts are derived from the recorded taxable basis
+//! (which matters for tax-inclusive prices); manual adjustments become document allowances.
+//! The generated PDF carries the Factur-X XMP metadata and associated-file relationship but
+//! uses a standard, non-embedded font and no output intent; hosts that must file validated
+//! PDF/A-3 should run it through their PDF/A toolchain.
+
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::escpos::MinorFormat;
+use crate::export::TransactionExport;
+use crate::xml::XmlWriter;
+use crate::{KernelError, LineKind, TxState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum InvoiceFormat {
+    Ubl = 1,
+    FacturXXml = 2,
+    FacturXPdf = 3,
+}
+
+impl InvoiceFormat {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Ubl),
+            2 => Some(Self::FacturXXml),
+            3 => Some(Self::FacturXPdf),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvoiceParty {
+    pub name: String,
+    pub vat_id: Option<String>,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub postal_code: Option<String>,
+    // ISO 3166-1 alpha-2
+    pub country_code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvoiceRequest {
+    pub invoice_number: String,
+    // Defaults to the commit date
+    pub issue_date: Option<NaiveDate>,
+    pub buyer_reference: Option<String>,
+    pub seller: InvoiceParty,
+    pub buyer: InvoiceParty,
+}
+
+struct TaxCategory {
+    code: &'static str,
+    percent: String,
+    exemption_reason: Option<&'static str>,
+}
+
+struct InvoiceLine {
+    id: u32,
+    sku: String,
+    name: String,
+    qty: i32,
+    net_minor: i64,
+}
+
+struct Allowance {
+    reason: String,
+    amount_minor: i64,
+}
+
+// Currency-neutral invoice content shared by the UBL and CII writers
+struct Invoice<'a> {
+    request: &'a InvoiceRequest,
+    issue_date: NaiveDate,
+    currency: String,
+    money: MinorFormat,
+    category: TaxCategory,
+    lines: Vec<InvoiceLine>,
+    allowances: Vec<Allowance>,
+    line_extension_minor: i64,
+    allowance_minor: i64,
+    tax_exclusive_minor: i64,
+    tax_minor: i64,
+    tax_inclusive_minor: i64,
+    prepaid_minor: i64,
+}
+
+impl Invoice<'_> {
+    fn amount(&self, minor: i64) -> String {
+        self.money.format(minor)
+    }
+
+    fn payable_minor(&self) -> i64 {
+        (self.tax_inclusive_minor - self.prepaid_minor).max(0)
+    }
+}
+
+pub fn export(tx: &TransactionExport, request: &InvoiceRequest, format: InvoiceFormat) -> Result<Vec<u8>, KernelError> {
+    let invoice = build(tx, request)?;
+    match format {
+        InvoiceFormat::Ubl => Ok(write_ubl(&invoice).into_bytes()),
+        InvoiceFormat::FacturXXml => Ok(write_cii(&invoice).into_bytes()),
+        InvoiceFormat::FacturXPdf => Ok(write_pdf(&invoice, &write_cii(&invoice))),
+    }
+}
+
+fn build<'a>(tx: &TransactionExport, request: &'a InvoiceRequest) -> Result<Invoice<'a>, KernelError> {
+    if tx.state != TxState::Committed {
+        return Err(KernelError::invalid_state("Transaction is not committed"));
+    }
+    if tx.total_minor < 0 {
+        return Err(KernelError::validation("Refund transactions require a credit note"));
+    }
+    if request.invoice_number.is_empty() || request.seller.name.is_empty() || request.buyer.name.is_empty() {
+        return Err(KernelError::validation("Invoice number, seller name and buyer name are required"));
+    }
+    if tx.taxes.len() > 1 || tx.taxes.iter().any(|t| t.exempt_minor != 0) {
+        return Err(KernelError::validation("E-invoices support a single VAT category per transaction"));
+    }
+
+    let tax = tx.taxes.first();
+    let category = match tax {
+        Some(t) if t.rate_basis_points > 0 => TaxCategory {
+            code: "S",
+            percent: MinorFormat::new(2, '.').map_err(KernelError::Internal)?.format(t.rate_basis_points as i64),
+            exemption_reason: None,
+        },
+        Some(_) => TaxCategory { code: "Z", percent: "0.00".to_string(), exemption_reason: None },
+        None => TaxCategory { code: "E", percent: "0.00".to_string(), exemption_reason: Some("Exempt from VAT") },
+    };
+
+    // Recorded amounts are gross when the tax is inclusive; scale them onto the taxable basis
+    let recorded: Vec<_> = tx.lines.iter().filter(|l| l.qty != 0).collect();
+    let recorded_minor: i64 = recorded.iter().map(|l| l.total_minor).sum();
+    let basis_minor = tax.map_or(recorded_minor, |t| t.taxable_minor);
+    let tax_minor = tax.map_or(0, |t| t.tax_minor);
+    let mut nets: Vec<i64> = recorded.iter()
+        .map(|l| scale(l.total_minor, basis_minor, recorded_minor))
+        .collect();
+    // Rounding remainder goes to the first line so the nets add up to the basis exactly
+    let remainder = basis_minor - nets.iter().sum::<i64>();
+    if let Some(first) = nets.first_mut() {
+        *first += remainder;
+    }
+
+    let mut lines = Vec::new();
+    let mut allowances = Vec::new();
+    for (line, net_minor) in recorded.iter().zip(nets) {
+        if line.kind == LineKind::Adjustment {
+            allowances.push(Allowance {
+                reason: line.reason_code.clone().unwrap_or_else(|| "Discount".to_string()),
+                amount_minor: -net_minor,
+            });
+        } else {
+            lines.push(InvoiceLine {
+                id: line.line_id,
+                sku: line.sku.to_string(),
+                name: line.product_name.clone().unwrap_or_else(|| line.sku.to_string()),
+                qty: line.qty,
+                net_minor,
+            });
+        }
+    }
+
+    let line_extension_minor: i64 = lines.iter().map(|l| l.net_minor).sum();
+    let allowance_minor: i64 = allowances.iter().map(|a| a.amount_minor).sum();
+    let issue_date = request.issue_date
+        .or_else(|| tx.committed_at.map(|t| t.date_naive()))
+        .unwrap_or_else(|| Utc::now().date_naive());
+
+    Ok(Invoice {
+        request,
+        issue_date,
+        currency: tx.currency.clone(),
+        money: MinorFormat::new(tx.decimal_places, '.').map_err(KernelError::Internal)?,
+        category,
+        lines,
+        allowances,
+        line_extension_minor,
+        allowance_minor,
+        tax_exclusive_minor: line_extension_minor - allowance_minor,
+        tax_minor,
+        tax_inclusive_minor: line_extension_minor - allowance_minor + tax_minor,
+        prepaid_minor: tx.tendered_minor - tx.change_minor,
+    })
+}
+
+fn scale(amount_minor: i64, basis_minor: i64, recorded_minor: i64) -> i64 {
+    if recorded_minor == 0 || basis_minor == recorded_minor {
+        return amount_minor;
+    }
+    let scaled = amount_minor as i128 * basis_minor as i128;
+    let recorded = recorded_minor as i128;
+    ((scaled + scaled.signum() * recorded.abs() / 2) / recorded) as i64
+}
+
+fn write_ubl(invoice: &Invoice) -> String {
+    let currency = [("currencyID", invoice.currency.as_str())];
+    let mut xml = XmlWriter::new();
+    xml.open("Invoice", &[
+        ("xmlns", "urn:oasis:names:specification:ubl:schema:xsd:Invoice-2"),
+        ("xmlns:cac", "urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2"),
+        ("xmlns:cbc", "urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2"),
+    ]);
+    xml.element("cbc:CustomizationID", &[], "urn:cen.eu:en16931:2017");
+    xml.element("cbc:ID", &[], &invoice.request.invoice_number);
+    xml.element("cbc:IssueDate", &[], &invoice.issue_date.format("%Y-%m-%d").to_string());
+    xml.element("cbc:InvoiceTypeCode", &[], "380");
+    xml.element("cbc:DocumentCurrencyCode", &[], &invoice.currency);
+    xml.optional("cbc:BuyerReference", invoice.request.buyer_reference.as_deref());
+
+    xml.open("cac:AccountingSupplierParty", &[]);
+    write_ubl_party(&mut xml, &invoice.request.seller);
+    xml.close();
+    xml.open("cac:AccountingCustomerParty", &[]);
+    write_ubl_party(&mut xml, &invoice.request.buyer);
+    xml.close();
+
+    for allowance in &invoice.allowances {
+        xml.open("cac:AllowanceCharge", &[]);
+        xml.element("cbc:ChargeIndicator", &[], "false");
+        xml.element("cbc:AllowanceChargeReason", &[], &allowance.reason);
+        xml.element("cbc:Amount", &currency, &invoice.amount(allowance.amount_minor));
+        write_ubl_category(&mut xml, "cac:TaxCategory", &invoice.category, false);
+        xml.close();
+    }
+
+    xml.open("cac:TaxTotal", &[]);
+    xml.element("cbc:TaxAmount", &currency, &invoice.amount(invoice.tax_minor));
+    xml.open("cac:TaxSubtotal", &[]);
+    xml.element("cbc:TaxableAmount", &currency, &invoice.amount(invoice.tax_exclusive_minor));
+    xml.element("cbc:TaxAmount", &currency, &invoice.amount(invoice.tax_minor));
+    write_ubl_category(&mut xml, "cac:TaxCategory", &invoice.category, true);
+    xml.close();
+    xml.close();
+
+    xml.open("cac:LegalMonetaryTotal", &[]);
+    xml.element("cbc:LineExtensionAmount", &currency, &invoice.amount(invoice.line_extension_minor));
+    xml.element("cbc:TaxExclusiveAmount", &currency, &invoice.amount(invoice.tax_exclusive_minor));
+    xml.element("cbc:TaxInclusiveAmount", &currency, &invoice.amount(invoice.tax_inclusive_minor));
+    xml.element("cbc:AllowanceTotalAmount", &currency, &invoice.amount(invoice.allowance_minor));
+    xml.element("cbc:PrepaidAmount", &currency, &invoice.amount(invoice.prepaid_minor));
+    xml.element("cbc:PayableAmount", &currency, &invoice.amount(invoice.payable_minor()));
+    xml.close();
+
+    for line in &invoice.lines {
+        xml.open("cac:InvoiceLine", &[]);
+        xml.element("cbc:ID", &[], &line.id.to_string());
+        xml.element("cbc:InvoicedQuantity", &[("unitCode", "C62")], &line.qty.to_string());
+        xml.element("cbc:LineExtensionAmount", &currency, &invoice.amount(line.net_minor));
+        xml.open("cac:Item", &[]);
+        xml.element("cbc:Name", &[], &line.name);
+        xml.open("cac:SellersItemIdentification", &[]);
+        xml.element("cbc:ID", &[], &line.sku);
+        xml.close();
+        write_ubl_category(&mut xml, "cac:ClassifiedTaxCategory", &invoice.category, false);
+        xml.close();
+        // Price per base quantity keeps the line amount exact without fractional unit prices
+        xml.open("cac:Price", &[]);
+        xml.element("cbc:PriceAmount", &currency, &invoice.amount(line.net_minor.abs()));
+        xml.element("cbc:BaseQuantity", &[("unitCode", "C62")], &line.qty.abs().to_string());
+        xml.close();
+        xml.close();
+    }
+
+    xml.finish()
+}
+
+fn write_ubl_party(xml: &mut XmlWriter, party: &InvoiceParty) {
+    xml.open("cac:Party", &[]);
+    xml.open("cac:PostalAddress", &[]);
+    xml.optional("cbc:StreetName", party.street.as_deref());
+    xml.optional("cbc:CityName", party.city.as_deref());
+    xml.optional("cbc:PostalZone", party.postal_code.as_deref());
+    xml.open("cac:Country", &[]);
+    xml.element("cbc:IdentificationCode", &[], &party.country_code);
+    xml.close();
+    xml.close();
+    if let Some(vat_id) = party.vat_id.as_deref() {
+        xml.open("cac:PartyTaxScheme", &[]);
+        xml.element("cbc:CompanyID", &[], vat_id);
+        xml.open("cac:TaxScheme", &[]);
+        xml.element("cbc:ID", &[], "VAT");
+        xml.close();
+        xml.close();
+    }
+    xml.open("cac:PartyLegalEntity", &[]);
+    xml.element("cbc:RegistrationName", &[], &party.name);
+    xml.close();
+    xml.close();
+}
+
+fn write_ubl_category(xml: &mut XmlWriter, element: &str, category: &TaxCategory, with_reason: bool) {
+    xml.open(element, &[]);
+    xml.element("cbc:ID", &[], category.code);
+    xml.element("cbc:Percent", &[], &category.percent);
+    if with_reason {
+        xml.optional("cbc:TaxExemptionReason", category.exemption_reason);
+    }
+    xml.open("cac:TaxScheme", &[]);
+    xml.element("cbc:ID", &[], "VAT");
+    xml.close();
+    xml.close();
+}
+
+pub const FACTURX_BASIC_GUIDELINE: &str = "urn:cen.eu:en16931:2017#compliant#urn:factur-x.eu:1p0:basic";
+
+fn write_cii(invoice: &Invoice) -> String {
+    let mut xml = XmlWriter::new();
+    xml.open("rsm:CrossIndustryInvoice", &[
+        ("xmlns:rsm", "urn:un:unece:uncefact:data:standard:CrossIndustryInvoice:100"),
+        ("xmlns:ram", "urn:un:unece:uncefact:data:standard:ReusableAggregateBusinessInformationEntity:100"),
+        ("xmlns:udt", "urn:un:unece:uncefact:data:standard:UnqualifiedDataType:100"),
+    ]);
+    xml.open("rsm:ExchangedDocumentContext", &[]);
+    xml.open("ram:GuidelineSpecifiedDocumentContextParameter", &[]);
+    xml.element("ram:ID", &[], FACTURX_BASIC_GUIDELINE);
+    xml.close();
+    xml.close();
+
+    xml.open("rsm:ExchangedDocument", &[]);
+    xml.element("ram:ID", &[], &invoice.request.invoice_number);
+    xml.element("ram:TypeCode", &[], "380");
+    xml.open("ram:IssueDateTime", &[]);
+    xml.element("udt:DateTimeString", &[("format", "102")], &invoice.issue_date.format("%Y%m%d").to_string());
+    xml.close();
+    xml.close();
+
+    xml.open("rsm:SupplyChainTradeTransaction", &[]);
+    for line in &invoice.lines {
+        xml.open("ram:IncludedSupplyChainTradeLineItem", &[]);
+        xml.open("ram:AssociatedDocumentLineDocument", &[]);
+        xml.element("ram:LineID", &[], &line.id.to_string());
+        xml.close();
+        xml.open("ram:SpecifiedTradeProduct", &[]);
+        xml.element("ram:SellerAssignedID", &[], &line.sku);
+        xml.element("ram:Name", &[], &line.name);
+        xml.close();
+        xml.open("ram:SpecifiedLineTradeAgreement", &[]);
+        xml.open("ram:NetPriceProductTradePrice", &[]);
+        xml.element("ram:ChargeAmount", &[], &invoice.amount(line.net_minor.abs()));
+        xml.element("ram:BasisQuantity", &[("unitCode", "C62")], &line.qty.abs().to_string());
+        xml.close();
+        xml.close();
+        xml.open("ram:SpecifiedLineTradeDelivery", &[]);
+        xml.element("ram:BilledQuantity", &[("unitCode", "C62")], &line.qty.to_string());
+        xml.close();
+        xml.open("ram:SpecifiedLineTradeSettlement", &[]);
+        write_cii_category(&mut xml, "ram:ApplicableTradeTax", &invoice.category);
+        xml.open("ram:SpecifiedTradeSettlementLineMonetarySummation", &[]);
+        xml.element("ram:LineTotalAmount", &[], &invoice.amount(line.net_minor));
+        xml.close();
+        xml.close();
+        xml.close();
+    }
+
+    xml.open("ram:ApplicableHeaderTradeAgreement", &[]);
+    xml.optional("ram:BuyerReference", invoice.request.buyer_reference.as_deref());
+    write_cii_party(&mut xml, "ram:SellerTradeParty", &invoice.request.seller);
+    write_cii_party(&mut xml, "ram:BuyerTradeParty", &invoice.request.buyer);
+    xml.close();
+    xml.open("ram:ApplicableHeaderTradeDelivery", &[]);
+    xml.close();
+
+    xml.open("ram:ApplicableHeaderTradeSettlement", &[]);
+    xml.element("ram:InvoiceCurrencyCode", &[], &invoice.currency);
+    xml.open("ram:ApplicableTradeTax", &[]);
+    xml.element("ram:CalculatedAmount", &[], &invoice.amount(invoice.tax_minor));
+    xml.element("ram:TypeCode", &[], "VAT");
+    xml.optional("ram:ExemptionReason", invoice.category.exemption_reason);
+    xml.element("ram:BasisAmount", &[], &invoice.amount(invoice.tax_exclusive_minor));
+    xml.element("ram:CategoryCode", &[], invoice.category.code);
+    xml.element("ram:RateApplicablePercent", &[], &invoice.category.percent);
+    xml.close();
+    for allowance in &invoice.allowances {
+        xml.open("ram:SpecifiedTradeAllowanceCharge", &[]);
+        xml.open("ram:ChargeIndicator", &[]);
+        xml.element("udt:Indicator", &[], "false");
+        xml.close();
+        xml.element("ram:ActualAmount", &[], &invoice.amount(allowance.amount_minor));
+        xml.element("ram:Reason", &[], &allowance.reason);
+        write_cii_category(&mut xml, "ram:CategoryTradeTax", &invoice.category);
+        xml.close();
+    }
+    xml.open("ram:SpecifiedTradeSettlementHeaderMonetarySummation", &[]);
+    xml.element("ram:LineTotalAmount", &[], &invoice.amount(invoice.line_extension_minor));
+    xml.element("ram:AllowanceTotalAmount", &[], &invoice.amount(invoice.allowance_minor));
+    xml.element("ram:TaxBasisTotalAmount", &[], &invoice.amount(invoice.tax_exclusive_minor));
+    xml.element("ram:TaxTotalAmount", &[("currencyID", invoice.currency.as_str())], &invoice.amount(invoice.tax_minor));
+    xml.element("ram:GrandTotalAmount", &[], &invoice.amount(invoice.tax_inclusive_minor));
+    xml.element("ram:TotalPrepaidAmount", &[], &invoice.amount(invoice.prepaid_minor));
+    xml.element("ram:DuePayableAmount", &[], &invoice.amount(invoice.payable_minor()));
+    xml.close();
+    xml.close();
+
+    xml.finish()
+}
+
+fn write_cii_party(xml: &mut XmlWriter, element: &str, party: &InvoiceParty) {
+    xml.open(element, &[]);
+    xml.element("ram:Name", &[], &party.name);
+    xml.open("ram:PostalTradeAddress", &[]);
+    xml.optional("ram:PostcodeCode", party.postal_code.as_deref());
+    xml.optional("ram:LineOne", party.street.as_deref());
+    xml.optional("ram:CityName", party.city.as_deref());
+    xml.element("ram:CountryID", &[], &party.country_code);
+    xml.close();
+    if let Some(vat_id) = party.vat_id.as_deref() {
+        xml.open("ram:SpecifiedTaxRegistration", &[]);
+        xml.element("ram:ID", &[("schemeID", "VA")], vat_id);
+        xml.close();
+    }
+    xml.close();
+}
+
+fn write_cii_category(xml: &mut XmlWriter, element: &str, category: &TaxCategory) {
+    xml.open(element, &[]);
+    xml.element("ram:TypeCode", &[], "VAT");
+    xml.element("ram:CategoryCode", &[], category.code);
+    xml.element("ram:RateApplicablePercent", &[], &category.percent);
+    xml.close();
+}
+
+// One-page PDF with a plain-text rendering of the invoice and the CII XML attached as
+// `factur-x.xml` (AFRelationship /Data), with Factur-X XMP metadata
+fn write_pdf(invoice: &Invoice, cii: &str) -> Vec<u8> {
+    let mut text = vec![
+        format!("INVOICE {}", invoice.request.invoice_number),
+        format!("Date: {}", invoice.issue_date.format("%Y-%m-%d")),
+        String::new(),
+        format!("Seller: {}", invoice.request.seller.name),
+        format!("Buyer: {}", invoice.request.buyer.name),
+        String::new(),
+    ];
+    for line in &invoice.lines {
+        text.push(format!("{} x {}  {} {}", line.qty, line.name, invoice.amount(line.net_minor), invoice.currency));
+    }
+    for allowance in &invoice.allowances {
+        text.push(format!("{}  -{} {}", allowance.reason, invoice.amount(allowance.amount_minor), invoice.currency));
+    }
+    text.push(String::new());
+    text.push(format!("Net: {} {}", invoice.amount(invoice.tax_exclusive_minor), invoice.currency));
+    text.push(format!("VAT {} % ({}): {} {}", invoice.category.percent, invoice.category.code, invoice.amount(invoice.tax_minor), invoice.currency));
+    text.push(format!("Total: {} {}", invoice.amount(invoice.tax_inclusive_minor), invoice.currency));
+    text.push(format!("Paid: {} {}", invoice.amount(invoice.prepaid_minor), invoice.currency));
+
+    let mut content = String::from("BT\n/F1 10 Tf\n14 TL\n50 800 Td\n");
+    for line in &text {
+        content.push_str(&format!("({}) '\n", pdf_text(line)));
+    }
+    content.push_str("ET\n");
+
+    let now = Utc::now();
+    let pdf_date = now.format("D:%Y%m%d%H%M%SZ").to_string();
+    let xmp = format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "<rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\"><pdfaid:part>3</pdfaid:part><pdfaid:conformance>B</pdfaid:conformance></rdf:Description>\n",
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Invoice {}</rdf:li></rdf:Alt></dc:title></rdf:Description>\n",
+            "<rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"><xmp:CreateDate>{}</xmp:CreateDate></rdf:Description>\n",
+            "<rdf:Description rdf:about=\"\" xmlns:fx=\"urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#\"><fx:DocumentType>INVOICE</fx:DocumentType><fx:DocumentFileName>factur-x.xml</fx:DocumentFileName><fx:Version>1.0</fx:Version><fx:ConformanceLevel>BASIC</fx:ConformanceLevel></rdf:Description>\n",
+            "</rdf:RDF></x:xmpmeta>\n<?xpacket end=\"w\"?>"
+        ),
+        crate::xml::escape(&invoice.request.invoice_number),
+        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R /Metadata 7 0 R /Names << /EmbeddedFiles << /Names [(factur-x.xml) 5 0 R] >> >> /AF [5 0 R] >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Contents 4 0 R /Resources << /Font << /F1 6 0 R >> >> >>".to_string(),
+        pdf_stream("", content.as_bytes()),
+        "<< /Type /Filespec /F (factur-x.xml) /UF (factur-x.xml) /Desc (Factur-X invoice) /AFRelationship /Data /EF << /F 8 0 R /UF 8 0 R >> >>".to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+        pdf_stream("/Type /Metadata /Subtype /XML", xmp.as_bytes()),
+        pdf_stream(&format!("/Type /EmbeddedFile /Subtype /text#2Fxml /Params << /ModDate ({}) /Size {} >>", pdf_date, cii.len()), cii.as_bytes()),
+    ];
+
+    let mut pdf = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    let id = format!("{:032x}", uuid::Uuid::new_v4().as_u128());
+    pdf.extend_from_slice(format!(
+        "trailer\n<< /Size {} /Root 1 0 R /ID [<{}> <{}>] >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1, id, id, xref
+    ).as_bytes());
+    pdf
+}
+
+fn pdf_stream(dictionary: &str, data: &[u8]) -> String {
+    // Stream contents are ASCII/UTF-8 text here, so the object can be assembled as a string
+    format!("<< {} /Length {} >>\nstream\n{}\nendstream", dictionary, data.len(), String::from_utf8_lossy(data))
+}
+
+// PDF literal string for the standard font: escapes delimiters, replaces non-ASCII text
+fn pdf_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,412 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! ERP connectors
+//! ARCHITECTURAL PRINCIPLE: The ERP is a user-space integration. Each registered connector
+//! pairs an `ErpTransport` (HTTP client, message queue, file drop) with a JSON payload
+//! template, so every ERP gets the document shape it expects without kernel changes. When a
+//! sale commits, its export is mapped through each connector's template and queued in the
+//! connector's outbox. Outboxes are pushed in order by a persistence worker, without the store
+//! lock. A transient failure leaves the message at the head of the outbox to be retried the
+//! next time the outbox runs (the next commit, or `pk_retry_erp_pushes`), until
+//! `erp_retry_limit` attempts move it to the dead-letter queue. A message whose template cannot
+//! be rendered, or that the ERP rejects outright, is dead-lettered at once. Dead letters are
+//! kept for inspection and can be requeued once the cause is fixed. Each attempt is published
+//! as an `ErpPush` event.
+//!
+//! Template syntax: the template is a JSON document rendered against the transaction export.
+//! - A string that is exactly `{{path}}` is replaced by the value at the dotted path, keeping
+//!   its JSON type; a missing value renders as null
+//! - `{{path}}` inside a longer string is interpolated as text
+//! - `{"$each": "path", "$map": template}` renders `template` once per element of the array at
+//!   `path`. Paths inside resolve against the element first, then the whole export.
+//! - Anything else is copied as is
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushError {
+    // The ERP may accept the message later (timeout, 5xx, connection refused); it is retried
+    Transient(String),
+    // The ERP rejected the message; it is dead-lettered
+    Rejected(String),
+}
+
+pub trait ErpTransport: Send + Sync {
+    // Identifies the connector in events and status reports
+    fn name(&self) -> &str;
+
+    // Sends one rendered payload
+    fn push(&self, payload: &str) -> Result<(), PushError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct PayloadTemplate {
+    root: Value,
+}
+
+impl PayloadTemplate {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let root: Value = serde_json::from_str(source)
+            .map_err(|e| format!("Invalid ERP payload template: {}", e))?;
+        check_template(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn render(&self, document: &Value) -> Result<Value, String> {
+        render(&self.root, &[document])
+    }
+}
+
+fn check_template(node: &Value) -> Result<(), String> {
+    match node {
+        Value::Object(map) if map.contains_key("$each") => {
+            if !map.get("$each").is_some_and(Value::is_string) || !map.contains_key("$map") || map.len() != 2 {
+                return Err("Invalid ERP payload template: $each takes a path and a $map template".to_string());
+            }
+            check_template(&map["$map"])
+        },
+        Value::Object(map) => map.values().try_for_each(check_template),
+        Value::Array(items) => items.iter().try_for_each(check_template),
+        _ => Ok(()),
+    }
+}
+
+// Looks a dotted path up in the innermost scope that has it
+fn lookup<'a>(scopes: &[&'a Value], path: &str) -> Option<&'a Value> {
+    if path == "." {
+        return scopes.last().copied();
+    }
+    scopes.iter().rev().find_map(|scope| {
+        path.split('.').try_fold(*scope, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+    })
+}
+
+fn render(node: &Value, scopes: &[&Value]) -> Result<Value, String> {
+    match node {
+        Value::String(text) => Ok(render_text(text, scopes)),
+        Value::Object(map) => match map.get("$each") {
+            Some(Value::String(path)) => {
+                let items = match lookup(scopes, path) {
+                    Some(Value::Array(items)) => items,
+                    None | Some(Value::Null) => return Ok(Value::Array(Vec::new())),
+                    Some(_) => return Err(format!("ERP payload template: '{}' is not an array", path)),
+                };
+                items.iter()
+                    .map(|item| {
+                        let mut inner = scopes.to_vec();
+                        inner.push(item);
+                        render(&map["$map"], &inner)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Value::Array)
+            },
+            _ => map.iter()
+                .map(|(key, value)| Ok((key.clone(), render(value, scopes)?)))
+                .collect::<Result<Map<_, _>, String>>()
+                .map(Value::Object),
+        },
+        Value::Array(items) => items.iter()
+            .map(|item| render(item, scopes))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        other => Ok(other.clone()),
+    }
+}
+
+fn render_text(text: &str, scopes: &[&Value]) -> Value {
+    let trimmed = text.trim();
+    if let Some(path) = trimmed.strip_prefix("{{").and_then(|t| t.strip_suffix("}}")) {
+        if !path.contains("{{") && !path.contains("}}") {
+            return lookup(scopes, path.trim()).cloned().unwrap_or(Value::Null);
+        }
+    }
+
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        match lookup(scopes, rest[start + 2..end].trim()) {
+            Some(Value::String(s)) => rendered.push_str(s),
+            Some(Value::Null) | None => {},
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    Value::String(rendered)
+}
+
+pub(crate) const DEAD_LETTER_NOT_FOUND: &str = "ERP dead letter not found";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErpMessageState {
+    Queued,
+    Delivered,
+    DeadLettered,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErpMessageStatus {
+    pub message_id: u64,
+    pub receipt_number: u64,
+    pub state: ErpMessageState,
+    pub attempts: u32,
+    pub queued_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectorStatus {
+    pub name: String,
+    pub delivered: u64,
+    pub last_error: Option<String>,
+    pub queued: Vec<ErpMessageStatus>,
+    // Oldest first
+    pub dead_letters: Vec<ErpMessageStatus>,
+}
+
+// One attempt at a message, as published in events
+#[derive(Debug, Clone)]
+pub(crate) struct PushOutcome {
+    pub connector: String,
+    pub message: ErpMessageStatus,
+}
+
+struct ErpMessage {
+    status: ErpMessageStatus,
+    // None when the template could not be rendered
+    payload: Option<Arc<str>>,
+}
+
+struct Connector {
+    transport: Arc<dyn ErpTransport>,
+    template: PayloadTemplate,
+    queue: VecDeque<ErpMessage>,
+    dead_letters: VecDeque<ErpMessage>,
+    delivered: u64,
+    pushing: bool,
+    last_error: Option<String>,
+}
+
+struct OutboxState {
+    connectors: Vec<Connector>,
+    next_message_id: u64,
+    retry_limit: u32,
+    dead_letter_limit: usize,
+}
+
+pub(crate) struct ErpOutbox {
+    state: Mutex<OutboxState>,
+}
+
+impl ErpOutbox {
+    pub fn new(retry_limit: u32, dead_letter_limit: usize) -> Self {
+        Self {
+            state: Mutex::new(OutboxState { connectors: Vec::new(), next_message_id: 0, retry_limit, dead_letter_limit }),
+        }
+    }
+
+    // Outboxes stay consistent if a transport panics mid-push, so poisoning is not fatal
+    fn state(&self) -> MutexGuard<'_, OutboxState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_limits(&self, retry_limit: u32, dead_letter_limit: usize) {
+        let mut state = self.state();
+        state.retry_limit = retry_limit;
+        state.dead_letter_limit = dead_letter_limit;
+        for connector in &mut state.connectors {
+            let excess = connector.dead_letters.len().saturating_sub(dead_letter_limit);
+            connector.dead_letters.drain(..excess);
+        }
+    }
+
+    pub fn has_connectors(&self) -> bool {
+        !self.state().connectors.is_empty()
+    }
+
+    // Maps the export through every connector's template and queues the payloads
+    pub fn enqueue(&self, receipt_number: u64, document: &Value) {
+        let mut state = self.state();
+        let state = &mut *state;
+        for connector in &mut state.connectors {
+            state.next_message_id += 1;
+            let mut status = ErpMessageStatus {
+                message_id: state.next_message_id,
+                receipt_number,
+                state: ErpMessageState::Queued,
+                attempts: 0,
+                queued_at: Utc::now(),
+                finished_at: None,
+                last_error: None,
+            };
+            match connector.template.render(document) {
+                Ok(payload) => connector.queue.push_back(ErpMessage { status, payload: Some(payload.to_string().into()) }),
+                Err(e) => {
+                    eprintln!("WARNING: ERP payload for transaction {} not rendered: {}", receipt_number, e);
+                    status.state = ErpMessageState::DeadLettered;
+                    status.finished_at = Some(Utc::now());
+                    status.last_error = Some(e);
+                    connector.dead_letter(ErpMessage { status, payload: None }, state.dead_letter_limit);
+                },
+            }
+        }
+    }
+
+    // Pushes queued messages until every outbox is empty or stalled; a connector whose push
+    // fails is tried once per run. Messages are pushed without the outbox lock.
+    pub fn run(&self) -> Vec<PushOutcome> {
+        let mut outcomes = Vec::new();
+        let mut stalled = Vec::new();
+        loop {
+            let claimed = {
+                let mut state = self.state();
+                state.connectors.iter_mut().enumerate()
+                    .filter(|(index, c)| !c.pushing && !stalled.contains(index))
+                    .find_map(|(index, c)| {
+                        let payload = Arc::clone(c.queue.front()?.payload.as_ref()?);
+                        c.pushing = true;
+                        Some((index, Arc::clone(&c.transport), payload))
+                    })
+            };
+            let (index, transport, payload) = match claimed {
+                Some(claimed) => claimed,
+                None => break,
+            };
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| transport.push(&payload)))
+                .unwrap_or_else(|_| Err(PushError::Rejected("ERP transport panicked".to_string())));
+            let outcome = match self.state().finish(index, result) {
+                Some(outcome) => outcome,
+                None => continue,
+            };
+            if outcome.message.state == ErpMessageState::Queued {
+                stalled.push(index);
+            }
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    // Moves a dead letter back to the end of its connector's outbox. Dead letters whose
+    // template failed to render have no payload to push and stay put.
+    pub fn requeue(&self, message_id: u64) -> Result<(), KernelError> {
+        let mut state = self.state();
+        for connector in &mut state.connectors {
+            let index = connector.dead_letters.iter()
+                .position(|m| m.status.message_id == message_id && m.payload.is_some());
+            if let Some(mut message) = index.and_then(|i| connector.dead_letters.remove(i)) {
+                message.status.state = ErpMessageState::Queued;
+                message.status.attempts = 0;
+                message.status.finished_at = None;
+                connector.queue.push_back(message);
+                return Ok(());
+            }
+        }
+        Err(KernelError::not_found(DEAD_LETTER_NOT_FOUND))
+    }
+
+    pub fn status(&self) -> Vec<ConnectorStatus> {
+        self.state().connectors.iter()
+            .map(|c| ConnectorStatus {
+                name: c.transport.name().to_string(),
+                delivered: c.delivered,
+                last_error: c.last_error.clone(),
+                queued: c.queue.iter().map(|m| m.status.clone()).collect(),
+                dead_letters: c.dead_letters.iter().map(|m| m.status.clone()).collect(),
+            })
+            .collect()
+    }
+}
+
+impl Connector {
+    fn dead_letter(&mut self, message: ErpMessage, limit: usize) {
+        self.dead_letters.push_back(message);
+        let excess = self.dead_letters.len().saturating_sub(limit);
+        for dropped in self.dead_letters.drain(..excess) {
+            eprintln!("WARNING: ERP dead letter {} for transaction {} dropped", dropped.status.message_id, dropped.status.receipt_number);
+        }
+    }
+}
+
+impl OutboxState {
+    // Records the attempt on the message at the head of the connector's outbox
+    fn finish(&mut self, index: usize, result: Result<(), PushError>) -> Option<PushOutcome> {
+        let (retry_limit, dead_letter_limit) = (self.retry_limit, self.dead_letter_limit);
+        let connector = &mut self.connectors[index];
+        connector.pushing = false;
+        let name = connector.transport.name().to_string();
+        let message = &mut connector.queue.front_mut()?.status;
+
+        message.attempts += 1;
+        let error = match result {
+            Ok(()) => None,
+            Err(PushError::Transient(e)) if message.attempts < retry_limit => {
+                message.last_error = Some(e.clone());
+                connector.last_error = Some(e);
+                return Some(PushOutcome { connector: name, message: message.clone() });
+            },
+            Err(PushError::Transient(e) | PushError::Rejected(e)) => Some(e),
+        };
+        message.state = if error.is_some() { ErpMessageState::DeadLettered } else { ErpMessageState::Delivered };
+        message.finished_at = Some(Utc::now());
+        message.last_error = error.clone();
+        connector.last_error = error;
+
+        let status = message.clone();
+        let message = connector.queue.pop_front()?;
+        match status.state {
+            ErpMessageState::Delivered => connector.delivered += 1,
+            _ => connector.dead_letter(message, dead_letter_limit),
+        }
+        Some(PushOutcome { connector: name, message: status })
+    }
+}
+
+// Registers an ERP connector; committed sales are pushed through `transport` as rendered by
+// `template`, a JSON payload template
+pub fn add_erp_connector(transport: Box<dyn ErpTransport>, template: &str) -> Result<(), KernelError> {
+    let template = PayloadTemplate::parse(template).map_err(KernelError::Validation)?;
+    let store = write_store_for_api()?;
+    store.erp_outbox.state().connectors.push(Connector {
+        transport: Arc::from(transport),
+        template,
+        queue: VecDeque::new(),
+        dead_letters: VecDeque::new(),
+        delivered: 0,
+        pushing: false,
+        last_error: None,
+    });
+    Ok(())
+}
@@ -0,0 +1,99 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Kernel errors
+//! Hosts branch on result codes, so a store operation's failure is classified where it is
+//! raised: each variant carries the `ResultCode` the FFI returns for it, and the message says
+//! why, for logs and the command API. There is deliberately no conversion from `String`; text
+//! from a provider, plugin or the journal is classified explicitly where it enters the kernel.
+
+use std::fmt;
+
+use crate::ResultCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KernelError {
+    NotFound(String),
+    InvalidState(String),
+    Validation(String),
+    TimedOut(String),
+    PermissionDenied(String),
+    // BACKPRESSURE: An active-transaction cap was reached
+    CapacityExceeded,
+    // NRF COMPLIANCE: Modifiers and deposits cannot attach to a voided line
+    ParentLineVoided,
+    CurrencyMismatch,
+    // Persistence, journal and serialization failures
+    Internal(String),
+}
+
+impl KernelError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
+    }
+
+    pub fn invalid_state(message: impl Into<String>) -> Self {
+        Self::InvalidState(message.into())
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation(message.into())
+    }
+
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::PermissionDenied(message.into())
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into())
+    }
+
+    pub fn code(&self) -> ResultCode {
+        match self {
+            Self::NotFound(_) => ResultCode::NotFound,
+            Self::InvalidState(_) => ResultCode::InvalidState,
+            Self::Validation(_) => ResultCode::ValidationFailed,
+            Self::TimedOut(_) => ResultCode::TimedOut,
+            Self::PermissionDenied(_) => ResultCode::PermissionDenied,
+            Self::CapacityExceeded => ResultCode::CapacityExceeded,
+            Self::ParentLineVoided => ResultCode::ParentLineVoided,
+            Self::CurrencyMismatch => ResultCode::CurrencyMismatch,
+            Self::Internal(_) => ResultCode::InternalError,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(m)
+            | Self::InvalidState(m)
+            | Self::Validation(m)
+            | Self::TimedOut(m)
+            | Self::PermissionDenied(m)
+            | Self::Internal(m) => m,
+            Self::CapacityExceeded => "Active transaction capacity reached",
+            Self::ParentLineVoided => "Parent line item is voided",
+            Self::CurrencyMismatch => "Tender currency does not match transaction currency",
+        }
+    }
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for KernelError {}
@@ -0,0 +1,389 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! ESC/POS receipt rendering
+//! Renders a `ReceiptDocument` into a ready-to-print ESC/POS byte stream for thermal printers.
+//! Text is transcoded to the selected single-byte code page (unmappable characters print as
+//! '?'). Labels and the decimal separator are supplied by the caller; the kernel defaults are
+//! placeholders, not a locale.
+
+use crate::peripheral::DrawerPin;
+use crate::receipt::{ReceiptDocument, ReceiptLine};
+use crate::TenderKind;
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+const LF: u8 = 0x0A;
+
+pub const DEFAULT_DRAWER_PULSE_MS: u16 = 50;
+// Off time after the pulse, so the solenoid is not driven again while it is still hot
+const DRAWER_OFF_MS: u16 = 500;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodePage {
+    Pc437 = 0,
+    Wpc1252 = 16,
+}
+
+impl CodePage {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(CodePage::Pc437),
+            16 => Some(CodePage::Wpc1252),
+            _ => None,
+        }
+    }
+
+    // ESC t n selection value
+    fn selector(self) -> u8 {
+        self as i32 as u8
+    }
+
+    fn encode_char(self, c: char) -> u8 {
+        if c.is_ascii() {
+            return c as u8;
+        }
+        let high = match self {
+            CodePage::Pc437 => &PC437_HIGH,
+            CodePage::Wpc1252 => &WPC1252_HIGH,
+        };
+        high.iter()
+            .position(|&h| h == c)
+            .map(|i| 0x80 + i as u8)
+            .unwrap_or(b'?')
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        text.chars()
+            .filter(|c| !c.is_control())
+            .map(|c| self.encode_char(c))
+            .collect()
+    }
+}
+
+// Characters for bytes 0x80..=0xFF; '\0' marks bytes with no assigned character
+const PC437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+const WPC1252_HIGH: [char; 128] = {
+    let mut table = ['\0'; 128];
+    let specials = [
+        '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+        '\0', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+    ];
+    let mut i = 0;
+    while i < 32 {
+        table[i] = specials[i];
+        i += 1;
+    }
+    // 0xA0..=0xFF match Latin-1
+    while i < 128 {
+        table[i] = match char::from_u32(0x80 + i as u32) {
+            Some(c) => c,
+            None => '\0',
+        };
+        i += 1;
+    }
+    table
+};
+
+#[derive(Debug, Clone)]
+pub struct ReceiptLabels {
+    pub subtotal: String,
+    pub discount: String,
+    pub tax: String,
+    pub total: String,
+    pub change: String,
+    pub tip: String,
+    pub cash: String,
+    pub points: String,
+    pub card: String,
+    // Printed under the receipt number of a reprint
+    pub copy: String,
+}
+
+impl Default for ReceiptLabels {
+    fn default() -> Self {
+        Self {
+            subtotal: "SUBTOTAL".to_string(),
+            discount: "DISCOUNT".to_string(),
+            tax: "TAX".to_string(),
+            total: "TOTAL".to_string(),
+            change: "CHANGE".to_string(),
+            tip: "TIP".to_string(),
+            cash: "CASH".to_string(),
+            points: "POINTS".to_string(),
+            card: "CARD".to_string(),
+            copy: "COPY".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EscPosOptions {
+    pub code_page: CodePage,
+    pub columns: usize,
+    pub decimal_separator: char,
+    pub labels: ReceiptLabels,
+    // CODE128 barcode of the transaction ID
+    pub print_barcode: bool,
+    pub qr_payload: Option<String>,
+    pub cut: bool,
+    pub open_drawer: bool,
+    // Marks the receipt as a reprint
+    pub copy: bool,
+}
+
+impl Default for EscPosOptions {
+    fn default() -> Self {
+        Self {
+            code_page: CodePage::Pc437,
+            columns: 42,
+            decimal_separator: '.',
+            labels: ReceiptLabels::default(),
+            print_barcode: false,
+            qr_payload: None,
+            cut: true,
+            open_drawer: false,
+            copy: false,
+        }
+    }
+}
+
+// ESC p m t1 t2: pulse the drawer connector; times are in 2 ms units, at most 510 ms
+pub fn drawer_kick(pin: DrawerPin, pulse_ms: u16) -> [u8; 5] {
+    let units = |ms: u16| (ms / 2).clamp(1, 255) as u8;
+    let connector = match pin {
+        DrawerPin::Pin2 => 0,
+        DrawerPin::Pin5 => 1,
+    };
+    [ESC, b'p', connector, units(pulse_ms), units(DRAWER_OFF_MS)]
+}
+
+// Formats minor units with a currency's decimal places; grouping is left to templates
+#[derive(Debug, Clone, Copy)]
+pub struct MinorFormat {
+    decimal_places: u8,
+    decimal_separator: char,
+    scale: u64,
+}
+
+impl MinorFormat {
+    // Amounts are i64, so a scale past 10^19 cannot be represented
+    pub fn new(decimal_places: u8, decimal_separator: char) -> Result<Self, String> {
+        let scale = 10u64.checked_pow(u32::from(decimal_places))
+            .ok_or_else(|| format!("Cannot format amounts with {} decimal places", decimal_places))?;
+        Ok(Self { decimal_places, decimal_separator, scale })
+    }
+
+    pub fn format(&self, amount_minor: i64) -> String {
+        let sign = if amount_minor < 0 { "-" } else { "" };
+        let magnitude = amount_minor.unsigned_abs();
+        if self.decimal_places == 0 {
+            return format!("{}{}", sign, magnitude);
+        }
+        format!(
+            "{}{}{}{:0width$}",
+            sign,
+            magnitude / self.scale,
+            self.decimal_separator,
+            magnitude % self.scale,
+            width = self.decimal_places as usize
+        )
+    }
+}
+
+pub fn format_minor(amount_minor: i64, decimal_places: u8, decimal_separator: char) -> Result<String, String> {
+    MinorFormat::new(decimal_places, decimal_separator).map(|f| f.format(amount_minor))
+}
+
+struct EscPosWriter<'a> {
+    out: Vec<u8>,
+    options: &'a EscPosOptions,
+}
+
+impl<'a> EscPosWriter<'a> {
+    fn new(options: &'a EscPosOptions) -> Self {
+        let mut out = vec![ESC, b'@', ESC, b't', options.code_page.selector()];
+        out.reserve(1024);
+        Self { out, options }
+    }
+
+    fn align(&mut self, alignment: u8) {
+        self.out.extend_from_slice(&[ESC, b'a', alignment]);
+    }
+
+    fn bold(&mut self, on: bool) {
+        self.out.extend_from_slice(&[ESC, b'E', on as u8]);
+    }
+
+    fn text_line(&mut self, text: &str) {
+        let mut encoded = self.options.code_page.encode(text);
+        encoded.truncate(self.options.columns);
+        self.out.extend_from_slice(&encoded);
+        self.out.push(LF);
+    }
+
+    // Left text and right-aligned amount on one line; the left text is truncated to fit
+    fn columns_line(&mut self, left: &str, right: &str) {
+        let columns = self.options.columns;
+        let right = self.options.code_page.encode(right);
+        let mut left = self.options.code_page.encode(left);
+        left.truncate(columns.saturating_sub(right.len() + 1));
+        let padding = columns.saturating_sub(left.len() + right.len());
+        self.out.extend_from_slice(&left);
+        self.out.extend(std::iter::repeat_n(b' ', padding));
+        self.out.extend_from_slice(&right);
+        self.out.push(LF);
+    }
+
+    fn separator(&mut self) {
+        self.out.extend(std::iter::repeat_n(b'-', self.options.columns));
+        self.out.push(LF);
+    }
+
+    fn barcode_code128(&mut self, data: &str) {
+        let data = self.options.code_page.encode(data);
+        // Height 80 dots, module width 2, HRI below; "{B" selects code set B
+        self.out.extend_from_slice(&[GS, b'h', 80, GS, b'w', 2, GS, b'H', 2]);
+        self.out.extend_from_slice(&[GS, b'k', 73, (data.len() + 2).min(255) as u8, b'{', b'B']);
+        self.out.extend_from_slice(&data[..data.len().min(253)]);
+        self.out.push(LF);
+    }
+
+    fn qr_code(&mut self, payload: &str) {
+        let data = payload.as_bytes();
+        let store_len = data.len() + 3;
+        // Model 2, module size 6, error correction M
+        self.out.extend_from_slice(&[GS, b'(', b'k', 4, 0, 49, 65, 50, 0]);
+        self.out.extend_from_slice(&[GS, b'(', b'k', 3, 0, 49, 67, 6]);
+        self.out.extend_from_slice(&[GS, b'(', b'k', 3, 0, 49, 69, 49]);
+        self.out.extend_from_slice(&[GS, b'(', b'k', (store_len & 0xFF) as u8, (store_len >> 8) as u8, 49, 80, 48]);
+        self.out.extend_from_slice(data);
+        self.out.extend_from_slice(&[GS, b'(', b'k', 3, 0, 49, 81, 48]);
+        self.out.push(LF);
+    }
+
+    fn drawer_kick(&mut self) {
+        self.out.extend_from_slice(&drawer_kick(DrawerPin::Pin2, DEFAULT_DRAWER_PULSE_MS));
+    }
+
+    fn cut(&mut self) {
+        // Feed and partial cut
+        self.out.extend_from_slice(&[GS, b'V', 66, 0]);
+    }
+}
+
+pub fn render(receipt: &ReceiptDocument, options: &EscPosOptions) -> Result<Vec<u8>, String> {
+    let mut w = EscPosWriter::new(options);
+    let labels = &options.labels;
+    let money = MinorFormat::new(receipt.header.decimal_places, options.decimal_separator)?;
+    let percent = MinorFormat::new(2, options.decimal_separator)?;
+    let amount = |minor: i64| money.format(minor);
+
+    w.align(1);
+    for line in &receipt.header.text {
+        w.text_line(line);
+    }
+    w.text_line(&receipt.header.store);
+    w.text_line(&format!("#{}", receipt.header.transaction_id));
+    if options.copy {
+        w.bold(true);
+        w.text_line(&labels.copy);
+        w.bold(false);
+    }
+    if let Some(committed_at) = receipt.header.committed_at {
+        w.text_line(&committed_at.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+    }
+    w.align(0);
+    w.separator();
+
+    for line in &receipt.lines {
+        render_line(&mut w, line, 0, &amount);
+    }
+    for discount in &receipt.discounts {
+        let label = discount.reason_code.as_deref().unwrap_or(&labels.discount);
+        w.columns_line(label, &amount(discount.amount_minor));
+    }
+    w.separator();
+
+    w.columns_line(&labels.subtotal, &amount(receipt.subtotal_minor));
+    for tax in &receipt.taxes {
+        let rate = percent.format(tax.rate_basis_points as i64);
+        w.columns_line(&format!("{} {} {}%", labels.tax, tax.rate_code, rate), &amount(tax.tax_minor));
+    }
+    w.bold(true);
+    w.columns_line(&labels.total, &amount(receipt.total_minor));
+    w.bold(false);
+
+    for tender in &receipt.tenders {
+        let label = match tender.kind {
+            TenderKind::Cash => &labels.cash,
+            TenderKind::Points => &labels.points,
+            TenderKind::Card => &labels.card,
+        };
+        w.columns_line(label, &amount(tender.amount_minor));
+        if let Some(tip_minor) = tender.tip_minor.filter(|t| *t > 0) {
+            w.columns_line(&format!("  {}", labels.tip), &amount(tip_minor));
+        }
+    }
+    w.columns_line(&labels.change, &amount(receipt.change_minor));
+    w.separator();
+
+    w.align(1);
+    for line in &receipt.footer {
+        w.text_line(line);
+    }
+    if options.print_barcode {
+        w.barcode_code128(&receipt.header.transaction_id.to_string());
+    }
+    if let Some(payload) = options.qr_payload.as_deref() {
+        w.qr_code(payload);
+    }
+    w.align(0);
+    w.out.extend_from_slice(&[LF, LF, LF]);
+
+    if options.open_drawer {
+        w.drawer_kick();
+    }
+    if options.cut {
+        w.cut();
+    }
+    Ok(w.out)
+}
+
+fn render_line(w: &mut EscPosWriter, line: &ReceiptLine, depth: usize, amount: &dyn Fn(i64) -> String) {
+    let name = line.description.as_deref().unwrap_or(&line.sku);
+    let indent = "  ".repeat(depth);
+    let left = if line.qty == 1 {
+        format!("{}{}", indent, name)
+    } else {
+        format!("{}{} x {}", indent, line.qty, name)
+    };
+    w.columns_line(&left, &amount(line.total_minor));
+    for child in &line.children {
+        render_line(w, child, depth + 1, amount);
+    }
+}
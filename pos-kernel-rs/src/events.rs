@@ -0,0 +1,220 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Kernel event feed
+//! Business events are published to registered sinks (service-layer integrations) and queued
+//! for polling by FFI hosts. The queue is bounded; when full, the oldest event is dropped.
+//! The bus is internally locked so events can be published under a shared store lock.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::anomaly::AnomalyAlert;
+use crate::cash::CashMovementKind;
+use crate::erp::ErpMessageStatus;
+use crate::inventory::StockMovement;
+use crate::peripheral::{DeviceHealth, DeviceKind, DrawerOpenReason, KitchenAction, KitchenTicket, PrintJobStatus, RecyclerOperation};
+use crate::receipt::DeliveryReason;
+use crate::refund::RefundLimit;
+use crate::security::HoldResolution;
+use crate::sync::SyncConflict;
+use crate::{CustomerRef, OrderReference, OrderReferenceField};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum EventKind {
+    TransactionStarted { store: String, currency: String },
+    LineAdded { line_id: u32, sku: Arc<str>, qty: i32, unit_minor: i64, parent_line_id: Option<u32> },
+    LineVoided { line_id: u32, reason: String },
+    TenderAdded { amount_minor: i64, tendered_minor: i64 },
+    TransactionCommitted {
+        total_minor: i64,
+        tendered_minor: i64,
+        change_minor: i64,
+        customer: Option<CustomerRef>,
+        order_reference: Option<OrderReference>,
+    },
+    OrderReferenceSet { field: OrderReferenceField, value: Option<String> },
+    CustomerAttached { customer: CustomerRef },
+    LayawayCancelled { restocking_fee_minor: i64, refund_minor: i64 },
+    TransactionAbandoned { reason: String },
+    PickupOrderImported { pickup_code: String },
+    PickupOrderRecalled { pickup_code: String, terminal_id: Option<String> },
+    StockMoved { movement: StockMovement },
+    LinesMoved { line_ids: Vec<u32>, transaction_handle: u64 },
+    ReceiptDelivery { deliverer: String, reason: DeliveryReason, error: Option<String> },
+    DrawerOpen { reason: DrawerOpenReason, driver: String, error: Option<String> },
+    KitchenTicket { ticket: KitchenTicket },
+    KitchenDelivery { sink: String, action: KitchenAction, error: Option<String> },
+    ReceiptPrint { printer: String, job: PrintJobStatus },
+    ErpPush { connector: String, message: ErpMessageStatus },
+    SyncConflict { conflict: SyncConflict },
+    LineHeld { line_id: u32, module: String, reason: String },
+    FuelAuthorized { pump_id: u32, preset_minor: i64 },
+    FuelDispensed { pump_id: u32, volume_milli: i64, amount_minor: i64, refunded_minor: i64 },
+    CashMovement { terminal_id: String, kind: CashMovementKind, amount_minor: i64, expected_minor: i64 },
+    RecyclerActivity {
+        terminal_id: String,
+        recycler: String,
+        operation: RecyclerOperation,
+        requested_minor: i64,
+        amount_minor: i64,
+        error: Option<String>,
+    },
+    PeripheralStatusChanged {
+        terminal_id: String,
+        device: String,
+        kind: DeviceKind,
+        health: DeviceHealth,
+        previous: DeviceHealth,
+        detail: Option<String>,
+    },
+    DrawerSessionClosed { terminal_id: String, expected_minor: i64, counted_minor: i64, variance_minor: i64 },
+    LineHoldResolved { line_id: u32, resolution: HoldResolution },
+    TabOpened { auth_reference: String, authorized_minor: i64 },
+    TabAuthorizationRequired { total_minor: i64, authorized_minor: i64 },
+    AnomalyDetected { alert: AnomalyAlert },
+    RefundLimitTriggered {
+        limits: Vec<RefundLimit>,
+        amount_minor: i64,
+        operator_id: Option<String>,
+        overridden_by: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KernelEvent {
+    pub sequence_number: u64,
+    pub timestamp: DateTime<Utc>,
+    pub transaction_handle: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+// Service-layer integrations (CRM, displays, inventory) receive events synchronously
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &KernelEvent);
+}
+
+pub struct EventBus {
+    state: Mutex<EventBusState>,
+}
+
+struct EventBusState {
+    next_sequence: u64,
+    pending: VecDeque<KernelEvent>,
+    capacity: usize,
+    dropped: u64,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(EventBusState {
+                next_sequence: 1,
+                pending: VecDeque::new(),
+                capacity,
+                dropped: 0,
+                sinks: Vec::new(),
+            }),
+        }
+    }
+
+    // The queue stays consistent if a sink panics mid-publish, so poisoning is not fatal
+    fn state(&self) -> MutexGuard<'_, EventBusState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state();
+        state.capacity = capacity;
+        while state.pending.len() > state.capacity {
+            state.pending.pop_front();
+            state.dropped += 1;
+        }
+    }
+
+    #[allow(dead_code)] // Registered by the service layer
+    pub fn add_sink(&self, sink: Box<dyn EventSink>) {
+        self.state().sinks.push(sink);
+    }
+
+    pub fn publish(&self, transaction_handle: u64, kind: EventKind) {
+        let mut state = self.state();
+        let event = KernelEvent {
+            sequence_number: state.next_sequence,
+            timestamp: Utc::now(),
+            transaction_handle,
+            kind,
+        };
+        state.next_sequence += 1;
+
+        for sink in &state.sinks {
+            sink.on_event(&event);
+        }
+
+        if state.capacity == 0 {
+            return;
+        }
+        if state.pending.len() >= state.capacity {
+            state.pending.pop_front();
+            state.dropped += 1;
+        }
+        state.pending.push_back(event);
+    }
+
+    // REGULATORY COMPLIANCE: Replaces a customer token in queued events; returns events changed
+    pub fn redact_customer(&self, token: &str, pseudonym: &str) -> usize {
+        let mut redacted = 0;
+        for event in self.state().pending.iter_mut() {
+            let customer = match &mut event.kind {
+                EventKind::TransactionCommitted { customer: Some(customer), .. } => customer,
+                EventKind::CustomerAttached { customer } => customer,
+                _ => continue,
+            };
+            if customer.token == token {
+                customer.token = pseudonym.to_string();
+                redacted += 1;
+            }
+        }
+        redacted
+    }
+
+    pub fn peek(&self) -> Option<KernelEvent> {
+        self.state().pending.front().cloned()
+    }
+
+    // Removes the oldest event if it is still the one with the given sequence number
+    pub fn pop(&self, sequence_number: u64) -> Option<KernelEvent> {
+        let mut state = self.state();
+        if state.pending.front()?.sequence_number != sequence_number {
+            return None;
+        }
+        state.pending.pop_front()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    pub fn dropped_count(&self) -> u64 {
+        self.state().dropped
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Culture-neutral transaction export
+//! Amounts are exported in minor units with the currency's decimal places; formatting is a
+//! user-space concern.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::fiscal::{FiscalRegistration, TseSignature};
+use crate::loyalty::LoyaltyActivity;
+use crate::fuel::FuelSale;
+use crate::inventory::StockReservation;
+use crate::peripheral::WeightReading;
+use crate::reports::csv_field;
+use crate::security::LineHold;
+use crate::{CustomerRef, LayawayInfo, Line, LineKind, OrderReference, TaxEntry, Tender, Transaction, TransactionKind, TxState};
+
+#[derive(Debug, Serialize)]
+pub struct LineExport {
+    pub line_id: u32,
+    pub sku: Arc<str>,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub total_minor: i64,
+    pub parent_line_id: Option<u32>,
+    pub kind: LineKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    pub product_name: Option<String>,
+    pub product_description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_receipt: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionExport {
+    pub id: u64,
+    pub kind: TransactionKind,
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminal_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator_id: Option<String>,
+    pub currency: String,
+    pub decimal_places: u8,
+    pub state: TxState,
+    pub started_at: DateTime<Utc>,
+    pub committed_at: Option<DateTime<Utc>>,
+    pub lines: Vec<LineExport>,
+    pub taxes: Vec<TaxEntry>,
+    pub tenders: Vec<Tender>,
+    pub total_minor: i64,
+    pub tendered_minor: i64,
+    pub change_minor: i64,
+    pub attributes: BTreeMap<String, String>,
+    pub order_reference: OrderReference,
+    pub customer: Option<CustomerRef>,
+    pub loyalty: Option<LoyaltyActivity>,
+    pub layaway: Option<LayawayInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tse_signature: Option<TseSignature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fiscal_registration: Option<FiscalRegistration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub converted_to: Option<u64>,
+    // Scale readings of weighed lines, by line ID
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub weight_readings: BTreeMap<u32, WeightReading>,
+    // Lines awaiting security confirmation, by line ID
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub held_lines: BTreeMap<u32, LineHold>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fuel_sales: Vec<FuelSale>,
+    // Stock reserved for lines, by line ID
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stock_reservations: BTreeMap<u32, StockReservation>,
+}
+
+impl From<&Line> for LineExport {
+    fn from(line: &Line) -> Self {
+        Self {
+            line_id: line.line_id,
+            sku: line.sku.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            total_minor: line.total_minor(),
+            parent_line_id: line.parent_line_item_id,
+            kind: line.kind,
+            reason_code: line.reason_code.clone(),
+            approved_by: line.approved_by.clone(),
+            product_name: line.product_name.clone(),
+            product_description: line.product_description.clone(),
+            original_receipt: line.original_receipt.clone(),
+        }
+    }
+}
+
+impl From<&Transaction> for TransactionExport {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            id: tx.id,
+            kind: tx.kind,
+            store: tx.store.clone(),
+            terminal_id: tx.terminal_id.clone(),
+            operator_id: tx.operator_id.clone(),
+            currency: tx.currency.code.clone(),
+            decimal_places: tx.currency.decimal_places,
+            state: tx.state.clone(),
+            started_at: tx.started_at,
+            committed_at: tx.committed_at,
+            lines: tx.lines.iter().map(LineExport::from).collect(),
+            taxes: tx.taxes.clone(),
+            tenders: tx.tenders.clone(),
+            total_minor: tx.total_minor(),
+            tendered_minor: tx.tendered_minor,
+            change_minor: tx.change_minor(),
+            attributes: tx.attributes.clone(),
+            order_reference: tx.order_reference.clone(),
+            customer: tx.customer.clone(),
+            loyalty: tx.loyalty.clone(),
+            layaway: tx.layaway.clone(),
+            tse_signature: tx.tse_signature.clone(),
+            fiscal_registration: tx.fiscal_registration.clone(),
+            quote_reference: tx.quote_reference.clone(),
+            converted_to: tx.converted_to,
+            weight_readings: tx.weight_readings.clone(),
+            held_lines: tx.held_lines.clone(),
+            fuel_sales: tx.fuel_sales.clone(),
+            stock_reservations: tx.stock_reservations.clone(),
+        }
+    }
+}
+
+pub(crate) fn transaction_to_json(tx: &Transaction) -> Result<String, String> {
+    serde_json::to_string(&TransactionExport::from(tx))
+        .map_err(|e| format!("Failed to serialize transaction: {}", e))
+}
+
+// Spreadsheet reconciliation: a `transaction` row with the totals, then a `line` row per line
+pub(crate) const TRANSACTION_CSV_HEADER: &str = "record,transaction_id,store,terminal_id,operator_id,currency,decimal_places,state,started_at,committed_at,line_id,parent_line_id,sku,product_name,kind,qty,unit_minor,reason_code,approved_by,amount_minor,exclusive_tax_minor,tendered_minor,change_minor\n";
+
+pub(crate) fn transaction_csv_rows(tx: &Transaction) -> String {
+    let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or(""));
+    let transaction = format!(
+        "{},{},{},{},{},{},{:?},{},{}",
+        tx.id,
+        csv_field(&tx.store),
+        optional(&tx.terminal_id),
+        optional(&tx.operator_id),
+        csv_field(&tx.currency.code),
+        tx.currency.decimal_places,
+        tx.state,
+        tx.started_at.to_rfc3339(),
+        tx.committed_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+    );
+    let mut rows = format!(
+        "transaction,{},,,,,,,,,,{},{},{},{}\n",
+        transaction,
+        tx.total_minor(),
+        tx.exclusive_tax_minor(),
+        tx.tendered_minor,
+        tx.change_minor(),
+    );
+    for line in tx.lines.iter() {
+        rows.push_str(&format!(
+            "line,{},{},{},{},{},{:?},{},{},{},{},{},,,\n",
+            transaction,
+            line.line_id,
+            line.parent_line_item_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&line.sku),
+            optional(&line.product_name),
+            line.kind,
+            line.qty,
+            line.unit_minor,
+            optional(&line.reason_code),
+            optional(&line.approved_by),
+            line.total_minor(),
+        ));
+    }
+    rows
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Fault injection for resilience testing
+//! Built with the `fault-injection` feature only. A test build arms failure points with a
+//! probability, so host-side recovery can be exercised against the failures the kernel really
+//! produces: a journal write or sync that fails, a lock that is not acquired in time, and a
+//! persistence worker that panics while holding the store lock (the next call then recovers
+//! the store). Without the feature every point is a constant `false` and compiles away.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    // Writing an entry to the journal file fails; the entry is not journaled
+    JournalWrite,
+    // Syncing the journal fails; commits waiting on the sync are not acknowledged
+    JournalSync,
+    // A store or shard lock is reported as not acquired within the lock timeout
+    LockTimeout,
+    // An archive job panics under the store write lock. Fires on the persistence threads
+    // only, where the panic is caught; a panic inside an FFI call would abort.
+    WorkerPanic,
+}
+
+#[cfg(feature = "fault-injection")]
+pub(crate) use armed::{arm, fires, seed};
+
+#[cfg(not(feature = "fault-injection"))]
+#[inline(always)]
+pub(crate) fn fires(_fault: Fault) -> bool {
+    false
+}
+
+#[cfg(feature = "fault-injection")]
+mod armed {
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+    use super::Fault;
+    use crate::pipeline;
+
+    pub(crate) const MAX_PPM: u32 = 1_000_000;
+
+    const ALL: [Fault; 4] = [Fault::JournalWrite, Fault::JournalSync, Fault::LockTimeout, Fault::WorkerPanic];
+    const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    // Chance per pass through each point, in parts per million
+    static PROBABILITY_PPM: [AtomicU32; 4] = [const { AtomicU32::new(0) }; 4];
+    static STATE: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+
+    impl Fault {
+        fn name(self) -> &'static str {
+            match self {
+                Fault::JournalWrite => "journal_write",
+                Fault::JournalSync => "journal_sync",
+                Fault::LockTimeout => "lock_timeout",
+                Fault::WorkerPanic => "worker_panic",
+            }
+        }
+    }
+
+    // Arms the named point; a probability of zero disarms it
+    pub(crate) fn arm(name: &str, probability_ppm: u32) -> Result<(), String> {
+        if probability_ppm > MAX_PPM {
+            return Err(format!("Fault probability {} ppm exceeds {}", probability_ppm, MAX_PPM));
+        }
+        let fault = ALL.into_iter().find(|f| f.name() == name)
+            .ok_or_else(|| format!("Unknown fault point '{}'", name))?;
+        PROBABILITY_PPM[fault as usize].store(probability_ppm, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Restarts the pseudo-random sequence; a single-threaded run then fails at the same passes
+    pub(crate) fn seed(seed: u64) {
+        STATE.store(if seed == 0 { DEFAULT_SEED } else { seed }, Ordering::Relaxed);
+    }
+
+    pub(crate) fn fires(fault: Fault) -> bool {
+        let probability_ppm = PROBABILITY_PPM[fault as usize].load(Ordering::Relaxed);
+        if probability_ppm == 0 || (fault == Fault::WorkerPanic && !pipeline::on_worker()) {
+            return false;
+        }
+        
+        let fired = next() % u64::from(MAX_PPM) < u64::from(probability_ppm);
+        if fired {
+            eprintln!("WARNING: Injected fault: {}", fault.name());
+        }
+        fired
+    }
+
+    // xorshift64; statistical quality is beside the point, only cheap and repeatable matter
+    fn next() -> u64 {
+        fn step(mut x: u64) -> u64 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        }
+        let previous = STATE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap_or(DEFAULT_SEED);
+        step(previous)
+    }
+}
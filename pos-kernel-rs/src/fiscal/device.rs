@@ -0,0 +1,221 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fiscal printers and registering devices
+//! Country modules (Italy RT, Polish online cash registers, ...) implement `FiscalDevice`. The
+//! kernel opens and closes the fiscal day on request and registers every committed sale as a
+//! `FiscalDocument`, mapped from the receipt document, so the commit flow is the same in every
+//! jurisdiction. A registration failure is recorded on the transaction; the sale is not undone.
+//! The device is never called under the store lock: opening and closing the day check the
+//! state, call the device and then apply its result with the state checked again, and
+//! registrations are made by a persistence worker after the commit.
+
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{read_store_for_api, write_store_for_api, KernelError};
+use crate::receipt::{ReceiptDocument, ReceiptLine};
+use crate::{LineKind, TaxEntry, TenderKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FiscalDocumentType {
+    Sale,
+    // Net-negative transaction (returns exceed sales)
+    Refund,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FiscalItem {
+    pub line_id: u32,
+    pub parent_line_id: Option<u32>,
+    pub sku: String,
+    pub description: Option<String>,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub total_minor: i64,
+    pub kind: LineKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FiscalPayment {
+    pub kind: TenderKind,
+    pub amount_minor: i64,
+}
+
+// Jurisdiction-neutral view of a committed sale, as registered with the fiscal device
+#[derive(Debug, Clone, Serialize)]
+pub struct FiscalDocument {
+    pub document_type: FiscalDocumentType,
+    pub transaction_id: u64,
+    pub store: String,
+    pub currency: String,
+    pub decimal_places: u8,
+    pub issued_at: Option<DateTime<Utc>>,
+    // Linked items (modifiers, deposits) follow their parent
+    pub items: Vec<FiscalItem>,
+    // Manual adjustments, as negative amounts
+    pub adjustments: Vec<FiscalItem>,
+    pub taxes: Vec<TaxEntry>,
+    pub total_minor: i64,
+    pub payments: Vec<FiscalPayment>,
+    pub change_minor: i64,
+}
+
+impl FiscalDocument {
+    pub fn from_receipt(receipt: &ReceiptDocument) -> Self {
+        let mut items = Vec::new();
+        for line in &receipt.lines {
+            Self::push_items(&mut items, line, None);
+        }
+
+        let adjustments = receipt.discounts.iter()
+            .map(|d| FiscalItem {
+                line_id: d.line_id,
+                parent_line_id: None,
+                sku: String::new(),
+                description: d.reason_code.clone(),
+                qty: 1,
+                unit_minor: d.amount_minor,
+                total_minor: d.amount_minor,
+                kind: LineKind::Adjustment,
+            })
+            .collect();
+
+        let payments = receipt.tenders.iter()
+            .map(|t| FiscalPayment { kind: t.kind, amount_minor: t.amount_minor })
+            .collect();
+
+        Self {
+            document_type: if receipt.total_minor < 0 { FiscalDocumentType::Refund } else { FiscalDocumentType::Sale },
+            transaction_id: receipt.header.transaction_id,
+            store: receipt.header.store.clone(),
+            currency: receipt.header.currency.clone(),
+            decimal_places: receipt.header.decimal_places,
+            issued_at: receipt.header.committed_at,
+            items,
+            adjustments,
+            taxes: receipt.taxes.clone(),
+            total_minor: receipt.total_minor,
+            payments,
+            change_minor: receipt.change_minor,
+        }
+    }
+
+    fn push_items(items: &mut Vec<FiscalItem>, line: &ReceiptLine, parent_line_id: Option<u32>) {
+        items.push(FiscalItem {
+            line_id: line.line_id,
+            parent_line_id,
+            sku: line.sku.clone(),
+            description: line.description.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            total_minor: line.total_minor,
+            kind: line.kind,
+        });
+        for child in &line.children {
+            Self::push_items(items, child, Some(line.line_id));
+        }
+    }
+}
+
+// What the device assigned to a registered document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FiscalRegistration {
+    pub document_number: String,
+    pub fiscal_day: u64,
+    pub registered_at: DateTime<Utc>,
+    // Device-specific reference (e.g. RT document ID, JPK number), printed on the receipt
+    pub device_reference: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FiscalDay {
+    pub day_number: u64,
+    pub opened_at: DateTime<Utc>,
+    pub documents_registered: u64,
+    pub total_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FiscalDayReport {
+    pub day_number: u64,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+    pub documents_registered: u64,
+    pub total_minor: i64,
+    // Device closing report reference (e.g. Z-report number)
+    pub device_report: Option<String>,
+}
+
+pub trait FiscalDevice: Send + Sync {
+    // Jurisdiction identifier, e.g. "IT"
+    fn jurisdiction(&self) -> &str;
+
+    // Opens the fiscal day on the device; returns the device's day number
+    fn open_day(&self) -> Result<u64, String>;
+
+    fn register_document(&self, day_number: u64, document: &FiscalDocument) -> Result<FiscalRegistration, String>;
+
+    // Closes the fiscal day (Z-report); returns the device's report reference, if any
+    fn close_day(&self, day_number: u64) -> Result<Option<String>, String>;
+}
+
+// Registers (or replaces) the store's fiscal device. Fails while a fiscal day is open.
+pub fn set_fiscal_device(device: Box<dyn FiscalDevice>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    if store.fiscal_day.is_some() {
+        return Err(KernelError::invalid_state("Fiscal day is open"));
+    }
+    store.fiscal_device = Some(Arc::from(device));
+    Ok(())
+}
+
+// Serializes opening and closing the day, whose device calls are made without the store lock
+fn day_lock() -> MutexGuard<'static, ()> {
+    static DAY_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    DAY_LOCK.get_or_init(|| Mutex::new(())).lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// REGULATORY COMPLIANCE: Opens the fiscal day on the device
+pub(crate) fn open_day() -> Result<FiscalDay, KernelError> {
+    let _day = day_lock();
+    let (device, _) = read_store_for_api()?.fiscal_day_device(false)?;
+    let day_number = device.open_day().map_err(KernelError::Internal)?;
+    write_store_for_api()?.record_fiscal_day_open(day_number)
+}
+
+// REGULATORY COMPLIANCE: Closes the fiscal day; the day stays open if the device fails
+pub(crate) fn close_day() -> Result<FiscalDayReport, KernelError> {
+    let _day = day_lock();
+    let (device, day_number) = read_store_for_api()?.fiscal_day_device(true)?;
+    let device_report = device.close_day(day_number).map_err(KernelError::Internal)?;
+    write_store_for_api()?.record_fiscal_day_close(day_number, device_report)
+}
+
+// A committed sale's fiscal document, built under the store lock and registered without it
+pub(crate) struct DocumentRegistration {
+    pub device: Arc<dyn FiscalDevice>,
+    pub day_number: u64,
+    pub document: FiscalDocument,
+}
+
+impl DocumentRegistration {
+    pub fn register(&self) -> Result<FiscalRegistration, String> {
+        self.device.register_document(self.day_number, &self.document)
+    }
+}
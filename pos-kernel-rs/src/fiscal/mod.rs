@@ -0,0 +1,51 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fiscal compliance extension points
+//! REGULATORY COMPLIANCE: Jurisdiction-specific fiscal requirements are plugins. The kernel
+//! supplies neutral transaction data (the receipt document) and records what plugins return;
+//! country modules implement the traits in this module.
+
+pub mod device;
+pub mod qr;
+pub mod tse;
+
+pub use device::{FiscalDay, FiscalDayReport, FiscalDevice, FiscalDocument, FiscalRegistration};
+pub use qr::{FiscalQrProvider, ZatcaQrProvider};
+pub use tse::{KassenSichVQrProvider, TseDevice, TseSignature, TseStart};
+
+// A committed sale's device calls (the TSE finish and the fiscal registration), prepared under
+// the store lock and made by a persistence worker without it. The receipt carries their
+// results, so it is delivered once they are applied.
+pub(crate) struct CommitFiscalization {
+    pub handle: u64,
+    pub tse: Option<tse::TseFinish>,
+    pub document: Option<device::DocumentRegistration>,
+}
+
+pub(crate) struct FiscalizationOutcome {
+    pub tse: Option<Result<TseSignature, String>>,
+    pub registration: Option<Result<FiscalRegistration, String>>,
+}
+
+impl CommitFiscalization {
+    pub fn run(&self) -> FiscalizationOutcome {
+        FiscalizationOutcome {
+            tse: self.tse.as_ref().map(tse::TseFinish::sign),
+            registration: self.document.as_ref().map(device::DocumentRegistration::register),
+        }
+    }
+}
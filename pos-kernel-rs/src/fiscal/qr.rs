@@ -0,0 +1,81 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fiscal QR code payloads
+//! Several jurisdictions require a machine-verifiable QR code on every receipt. A registered
+//! `FiscalQrProvider` builds the payload from the receipt document; the payload is attached to
+//! the document (`fiscal_qr`) and printed by the ESC/POS renderer.
+
+use base64::Engine;
+
+use crate::escpos::format_minor;
+use crate::{write_store_for_api, KernelError};
+use crate::receipt::ReceiptDocument;
+
+pub trait FiscalQrProvider: Send + Sync {
+    // Jurisdiction identifier, e.g. "SA"
+    fn jurisdiction(&self) -> &str;
+
+    fn payload(&self, receipt: &ReceiptDocument) -> Result<String, String>;
+}
+
+// Registers (or replaces) the store's fiscal QR provider
+pub fn set_qr_provider(provider: Box<dyn FiscalQrProvider>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.fiscal_qr_provider = Some(provider);
+    Ok(())
+}
+
+// Reference implementation: Saudi Arabia (ZATCA) simplified tax invoice QR, phase 1.
+// Base64 of TLV fields: 1 seller name, 2 VAT registration number, 3 timestamp (ISO 8601),
+// 4 invoice total including VAT, 5 VAT total.
+pub struct ZatcaQrProvider {
+    pub seller_name: String,
+    pub vat_number: String,
+}
+
+impl ZatcaQrProvider {
+    fn push_tlv(out: &mut Vec<u8>, tag: u8, value: &str) -> Result<(), String> {
+        let bytes = value.as_bytes();
+        let len = u8::try_from(bytes.len())
+            .map_err(|_| format!("ZATCA QR field {} exceeds 255 bytes", tag))?;
+        out.push(tag);
+        out.push(len);
+        out.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl FiscalQrProvider for ZatcaQrProvider {
+    fn jurisdiction(&self) -> &str {
+        "SA"
+    }
+
+    fn payload(&self, receipt: &ReceiptDocument) -> Result<String, String> {
+        let committed_at = receipt.header.committed_at
+            .ok_or("Fiscal QR requires a committed transaction")?;
+        let dp = receipt.header.decimal_places;
+        let vat_minor: i64 = receipt.taxes.iter().map(|t| t.tax_minor).sum();
+
+        let mut tlv = Vec::with_capacity(128);
+        Self::push_tlv(&mut tlv, 1, &self.seller_name)?;
+        Self::push_tlv(&mut tlv, 2, &self.vat_number)?;
+        Self::push_tlv(&mut tlv, 3, &committed_at.format("%Y-%m-%dT%H:%M:%SZ").to_string())?;
+        Self::push_tlv(&mut tlv, 4, &format_minor(receipt.total_minor, dp, '.')?)?;
+        Self::push_tlv(&mut tlv, 5, &format_minor(vat_minor, dp, '.')?)?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(tlv))
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! German KassenSichV technical security equipment (TSE)
+//! A registered `TseDevice` (certified hardware or cloud TSE) is started when a sale begins and
+//! finished at commit with the DSFinV-K `Kassenbeleg-V1` process data. The resulting signature
+//! is stored on the transaction and carried on the receipt. The finish is prepared under the
+//! store lock at commit and sent to the TSE by a persistence worker without it (see
+//! `CommitFiscalization`); the receipt is delivered after it. A TSE failure never blocks the sale: it is recorded on the transaction so the
+//! receipt can state that the TSE failed.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::escpos::MinorFormat;
+use crate::fiscal::qr::FiscalQrProvider;
+use crate::{write_store_for_api, KernelError};
+use crate::receipt::ReceiptDocument;
+use crate::TenderKind;
+
+pub const PROCESS_TYPE_RECEIPT: &str = "Kassenbeleg-V1";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TseStart {
+    pub transaction_number: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TseSignature {
+    pub serial_number: String,
+    pub transaction_number: u64,
+    pub signature_counter: u64,
+    pub signature_algorithm: String,
+    // Base64, as returned by the TSE
+    pub signature: String,
+    pub public_key: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub process_type: String,
+    pub process_data: String,
+}
+
+pub trait TseDevice: Send + Sync {
+    fn serial_number(&self) -> String;
+
+    fn start_transaction(&self, client_id: &str, process_type: &str, process_data: &str) -> Result<TseStart, String>;
+
+    fn finish_transaction(&self, client_id: &str, transaction_number: u64, process_type: &str, process_data: &str) -> Result<TseSignature, String>;
+}
+
+pub(crate) struct TseRegistration {
+    pub device: Arc<dyn TseDevice>,
+    // Cash register serial (Kassen-Seriennummer) registered with the TSE as client ID
+    pub client_id: String,
+}
+
+// Registers (or replaces) the store's TSE; subsequent sales are signed
+pub fn set_tse(device: Box<dyn TseDevice>, client_id: String) -> Result<(), KernelError> {
+    if client_id.is_empty() {
+        return Err(KernelError::validation("TSE client ID must not be empty"));
+    }
+    let mut store = write_store_for_api()?;
+    store.tse = Some(TseRegistration { device: Arc::from(device), client_id });
+    Ok(())
+}
+
+// A committed sale's TSE finish, prepared under the store lock and signed without it
+pub(crate) struct TseFinish {
+    pub device: Arc<dyn TseDevice>,
+    pub client_id: String,
+    pub transaction_number: u64,
+    pub process_data: String,
+}
+
+impl TseFinish {
+    pub fn sign(&self) -> Result<TseSignature, String> {
+        self.device.finish_transaction(&self.client_id, self.transaction_number, PROCESS_TYPE_RECEIPT, &self.process_data)
+    }
+}
+
+// DSFinV-K VAT rate slots A-E (19 %, 7 %, 10.7 %, 5.5 %, 0 %), in basis points
+const VAT_SLOTS: [i32; 5] = [1900, 700, 1070, 550, 0];
+
+// DSFinV-K `Kassenbeleg-V1` process data: "Beleg^<gross per VAT slot>^<payments>".
+// Gross amounts per slot come from the recorded tax breakdown (net basis plus tax); with no
+// breakdown the total is reported at the normal rate; exempt amounts go to the 0 % slot. Payments are "amount:Bar" or
+// "amount:Unbar", with the currency appended when it is not EUR.
+pub fn process_data(receipt: &ReceiptDocument) -> Result<String, String> {
+    let money = MinorFormat::new(receipt.header.decimal_places, '.')?;
+    let mut gross = [0i64; 5];
+    if receipt.taxes.is_empty() {
+        gross[0] = receipt.total_minor;
+    }
+    for tax in &receipt.taxes {
+        let slot = VAT_SLOTS.iter().position(|r| *r == tax.rate_basis_points).unwrap_or(0);
+        gross[slot] += tax.taxable_minor + tax.tax_minor;
+        gross[4] += tax.exempt_minor;
+    }
+    let gross: Vec<String> = gross.iter().map(|g| money.format(*g)).collect();
+
+    let currency_suffix = if receipt.header.currency == "EUR" {
+        String::new()
+    } else {
+        format!(":{}", receipt.header.currency)
+    };
+    let mut payments: Vec<String> = receipt.tenders.iter()
+        .filter(|t| t.amount_minor != 0)
+        .map(|t| {
+            let kind = if t.kind == TenderKind::Cash { "Bar" } else { "Unbar" };
+            format!("{}:{}{}", money.format(t.amount_minor), kind, currency_suffix)
+        })
+        .collect();
+    if receipt.change_minor > 0 {
+        payments.push(format!("{}:Bar{}", money.format(-receipt.change_minor), currency_suffix));
+    }
+
+    Ok(format!("Beleg^{}^{}", gross.join("_"), payments.join("_")))
+}
+
+// Receipt QR code payload per DSFinV-K (version V0)
+pub struct KassenSichVQrProvider {
+    pub client_id: String,
+}
+
+impl FiscalQrProvider for KassenSichVQrProvider {
+    fn jurisdiction(&self) -> &str {
+        "DE"
+    }
+
+    fn payload(&self, receipt: &ReceiptDocument) -> Result<String, String> {
+        let sig = receipt.tse_signature.as_ref()
+            .ok_or("Transaction has no TSE signature")?;
+        let time = |t: &DateTime<Utc>| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        Ok(format!(
+            "V0;{};{};{};{};{};{};{};{};utcTime;{};{}",
+            self.client_id,
+            sig.process_type,
+            sig.process_data,
+            sig.transaction_number,
+            sig.signature_counter,
+            time(&sig.started_at),
+            time(&sig.finished_at),
+            sig.signature_algorithm,
+            sig.signature,
+            sig.public_key
+        ))
+    }
+}
@@ -0,0 +1,69 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Fuel prepay
+//! A forecourt prepay runs in three steps on one transaction. The preset adds a line for the
+//! grade at the preset amount, which the customer tenders like any other line. Once it is paid
+//! in full the pump is authorized; the forecourt controller releases it on the
+//! `FuelAuthorized` event. The dispenser reading then replaces the preset line with the
+//! dispensed amount, and prepay not used is refunded against the tenders that paid it, newest
+//! first, so a cash prepay is paid out as cash and a card prepay is reversed on the card.
+//! Points tenders are never refunded as money. The transaction cannot be finalized while any
+//! pump is still preset or dispensing. Volumes are thousandths of the dispenser's unit.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FuelState {
+    Preset,
+    Authorized,
+    Dispensed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuelSale {
+    pub pump_id: u32,
+    pub sku: String,
+    pub preset_minor: i64,
+    pub preset_line_id: u32,
+    pub state: FuelState,
+    // Set from the dispenser reading
+    pub line_id: Option<u32>,
+    pub volume_milli: Option<i64>,
+    pub dispensed_minor: Option<i64>,
+    pub refunded_minor: i64,
+}
+
+impl FuelSale {
+    pub(crate) fn new(pump_id: u32, sku: String, preset_minor: i64, preset_line_id: u32) -> Self {
+        Self {
+            pump_id,
+            sku,
+            preset_minor,
+            preset_line_id,
+            state: FuelState::Preset,
+            line_id: None,
+            volume_milli: None,
+            dispensed_minor: None,
+            refunded_minor: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state != FuelState::Dispensed
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! SKU interning
+//! Large baskets repeat the same SKUs. Lines share one immutable copy of each SKU from a
+//! per-store pool instead of owning a string each, which saves an allocation per scan and lets
+//! journal entries, events and exports reference the SKU without copying it.
+
+use std::collections::HashSet;
+use std::sync::{Arc, PoisonError, RwLock};
+
+// Internally locked so lines can be added under the shared store lock; lookups of known SKUs
+// only take the read lock
+#[derive(Default)]
+pub(crate) struct SkuPool {
+    skus: RwLock<HashSet<Arc<str>>>,
+}
+
+impl SkuPool {
+    pub fn intern(&self, sku: &str) -> Arc<str> {
+        if let Some(interned) = self.skus.read().unwrap_or_else(PoisonError::into_inner).get(sku) {
+            return Arc::clone(interned);
+        }
+        let mut skus = self.skus.write().unwrap_or_else(PoisonError::into_inner);
+        match skus.get(sku) {
+            Some(interned) => Arc::clone(interned),
+            None => {
+                let interned: Arc<str> = Arc::from(sku);
+                skus.insert(Arc::clone(&interned));
+                interned
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.skus.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Inventory reservation hook
+//! ARCHITECTURAL PRINCIPLE: Stock levels are user-space business data. A registered
+//! `InventoryProvider` reserves stock for each sale line as it is added, before the
+//! transaction is locked, and the reservation is released when the line is voided, the
+//! transaction is abandoned or its layaway is cancelled. A committed sale keeps its
+//! reservations; the inventory system settles them against the sale.
+//! Checkout does not depend on the inventory system: by default a shortage or an unreachable
+//! provider adds the line unreserved and journals why, and each can be configured to reject
+//! the line instead.
+//! Stock actually leaves or returns to the shelf when a sale commits. Each committed sale or
+//! return line is then published as a `StockMovement` event, so inventory systems can follow
+//! stock as it moves instead of polling exports. Lines voided before commit never moved stock
+//! and are not published.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{write_store_for_api, KernelError, LineKind, Transaction};
+
+// Stock set aside for one line; released with the same values it was reserved with, even
+// after the line moves to another transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StockReservation {
+    pub store: String,
+    pub sku: String,
+    pub qty: i32,
+    // The transaction the line was added to
+    pub transaction_handle: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReserveError {
+    // Less than the requested quantity is available
+    Shortage { available: i64 },
+    // The inventory system could not be reached or failed
+    Unavailable(String),
+}
+
+pub trait InventoryProvider: Send + Sync {
+    fn reserve(&self, reservation: &StockReservation) -> Result<(), ReserveError>;
+
+    fn release(&self, reservation: &StockReservation) -> Result<(), String>;
+}
+
+// What adding a line does when its stock cannot be reserved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InventoryFailurePolicy {
+    // The line is added unreserved
+    #[default]
+    Allow,
+    Reject,
+}
+
+impl std::str::FromStr for InventoryFailurePolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "allow" => Ok(Self::Allow),
+            "reject" => Ok(Self::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(crate) const INSUFFICIENT_STOCK: &str = "Insufficient stock for SKU";
+pub(crate) const INVENTORY_UNAVAILABLE: &str = "Inventory service unavailable";
+
+// The outcome of reserving stock for a line about to be added
+pub(crate) enum LineStock {
+    Reserved(StockReservation),
+    // Allowed through unreserved, with the reason journaled against the line
+    Unreserved(String),
+}
+
+// Reserves stock for a line, applying the failure policies
+pub(crate) fn reserve_line(
+    provider: &dyn InventoryProvider,
+    reservation: StockReservation,
+    on_shortage: InventoryFailurePolicy,
+    on_unavailable: InventoryFailurePolicy,
+) -> Result<LineStock, KernelError> {
+    match provider.reserve(&reservation) {
+        Ok(()) => Ok(LineStock::Reserved(reservation)),
+        Err(ReserveError::Shortage { available }) => match on_shortage {
+            InventoryFailurePolicy::Allow => Ok(LineStock::Unreserved(format!("{} available", available))),
+            InventoryFailurePolicy::Reject => Err(KernelError::validation(INSUFFICIENT_STOCK)),
+        },
+        Err(ReserveError::Unavailable(e)) => {
+            eprintln!("WARNING: Inventory reservation failed for SKU {}: {}", reservation.sku, e);
+            match on_unavailable {
+                InventoryFailurePolicy::Allow => Ok(LineStock::Unreserved(INVENTORY_UNAVAILABLE.to_string())),
+                InventoryFailurePolicy::Reject => Err(KernelError::TimedOut(INVENTORY_UNAVAILABLE.to_string())),
+            }
+        },
+    }
+}
+
+// A failed release is logged; the inventory system is expected to expire stale reservations
+pub(crate) fn release(provider: &dyn InventoryProvider, reservations: &[StockReservation]) {
+    for reservation in reservations {
+        if let Err(e) = provider.release(reservation) {
+            eprintln!("WARNING: Inventory release failed for SKU {}: {}", reservation.sku, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StockMovementKind {
+    Sale,
+    Return,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StockMovement {
+    pub store: String,
+    pub terminal_id: Option<String>,
+    pub line_id: u32,
+    pub sku: Arc<str>,
+    // Signed change to stock on hand: negative for a sale, positive for a return
+    pub qty: i32,
+    pub kind: StockMovementKind,
+    // Stock was reserved for the line and is settled by this movement
+    pub reserved: bool,
+}
+
+// The stock moved by a committed transaction, in line order
+pub(crate) fn movements(tx: &Transaction) -> Vec<StockMovement> {
+    tx.lines.iter()
+        .filter(|line| line.qty != 0)
+        .filter_map(|line| {
+            let (kind, qty) = match line.kind {
+                LineKind::Sale => (StockMovementKind::Sale, -line.qty),
+                LineKind::Return => (StockMovementKind::Return, line.qty),
+                LineKind::Deposit | LineKind::DepositReturn | LineKind::Adjustment => return None,
+            };
+            Some(StockMovement {
+                store: tx.store.clone(),
+                terminal_id: tx.terminal_id.clone(),
+                line_id: line.line_id,
+                sku: Arc::clone(&line.sku),
+                qty,
+                kind,
+                reserved: tx.stock_reservations.contains_key(&line.line_id),
+            })
+        })
+        .collect()
+}
+
+// Registers (or replaces) the store's inventory provider
+pub fn set_inventory_provider(provider: Box<dyn InventoryProvider>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.inventory_provider = Some(provider);
+    Ok(())
+}
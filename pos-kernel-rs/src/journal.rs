@@ -0,0 +1,862 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Append-only transaction journal (write-ahead log)
+//! Every kernel mutation is recorded as a sequenced entry. Entries are kept in memory and,
+//! when a journal file is configured, appended to it as JSON lines. The journal is internally
+//! locked so operations running under a shared store lock can record concurrently.
+//!
+//! Appends reach the operating system immediately but are not synced one by one. Commits are
+//! made durable by group commit: a lane acknowledging a commit waits on a `SyncTicket`, one
+//! waiter syncs the file for every entry written so far, and lanes that commit meanwhile are
+//! covered by that sync or the next one. An optional window holds each sync back briefly so
+//! more commits share it.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditLog;
+use crate::cash::{CashMovementKind, DenominationCount};
+use crate::faults::{self, Fault};
+use crate::pii::{self, PiiCategory};
+use crate::peripheral::{DrawerOpenReason, RecyclerOperation, WeightReading};
+use crate::refund::RefundLimit;
+use crate::scan::Scan;
+use crate::security::HoldResolution;
+use crate::sync::SyncReceipt;
+use crate::{LineKind, OrderReferenceField, TaxEntry, TenderKind, TransactionKind};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum JournalOperation {
+    TransactionBegin {
+        store: String,
+        currency: String,
+        decimal_places: u8,
+        #[serde(default)]
+        kind: TransactionKind,
+    },
+    LineAdd {
+        line_id: u32,
+        sku: Arc<str>,
+        qty: i32,
+        unit_minor: i64,
+        parent_line_id: Option<u32>,
+        kind: LineKind,
+        reason_code: Option<String>,
+        approved_by: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        product_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        product_description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        original_receipt: Option<String>,
+    },
+    LineVoid { line_id: u32, reason: String },
+    TenderAdd {
+        amount_minor: i64,
+        #[serde(default)]
+        kind: TenderKind,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        points: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        // EMV: The device authorization reference of a card tender
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth_reference: Option<String>,
+    },
+    TransactionCommit {
+        total_minor: i64,
+        tendered_minor: i64,
+        // Sequence number of the transaction's begin entry, where its entries start
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        begin_sequence: Option<u64>,
+    },
+    TransactionArchive { sequence_number: u64, hash: String },
+    AttributeSet { key: String, value: String },
+    AttributeRemove { key: String },
+    TaxAdd { entry: TaxEntry },
+    TaxClear,
+    // The tax entries that follow were calculated from fallback rates; the tax service failed
+    TaxFallback,
+    // `redacted` lists the sequence numbers of the entries rewritten, so a replica can rewrite
+    // its copies without learning the token
+    CustomerAnonymize {
+        pseudonym: String,
+        transactions_updated: usize,
+        signature: String,
+        #[serde(default)]
+        redacted: Vec<u64>,
+    },
+    OrderReferenceSet { field: OrderReferenceField, value: Option<String> },
+    CustomerAttach { token: String, tier: Option<String> },
+    LoyaltyRecord { account_id: String, points_accrued: i64, points_redeemed: i64 },
+    LayawayCreate { deposit_minor: i64 },
+    LayawayPayment { amount_minor: i64 },
+    LayawayCancel { restocking_fee_minor: i64, refund_minor: i64 },
+    QuoteSave { reference: String },
+    QuoteConvert { transaction_handle: u64 },
+    PickupImport { code: String },
+    PickupRecall { terminal_id: Option<String> },
+    TransactionClone { source_handle: u64, current_prices: bool },
+    LinesMove { line_ids: Vec<u32>, transaction_handle: u64 },
+    TabOpen { auth_reference: String, authorized_minor: i64 },
+    TabAuthIncrement { additional_minor: i64, authorized_minor: i64 },
+    TabCapture { captured_minor: i64, tip_minor: i64 },
+    TseStart { transaction_number: u64 },
+    TseFinish { transaction_number: u64, signature_counter: u64, signature: String },
+    OperatorAssign { terminal_id: Option<String>, operator_id: Option<String> },
+    FiscalDayOpen { day_number: u64 },
+    FiscalRegister { day_number: u64, document_number: String },
+    FiscalDayClose { day_number: u64, documents_registered: u64, total_minor: i64 },
+    ShiftStart { operator_id: String },
+    RefundLimitTriggered { limits: Vec<RefundLimit>, amount_minor: i64, overridden_by: Option<String> },
+    LinesFire { line_ids: Vec<u32> },
+    WeightCapture { line_id: Option<u32>, reading: WeightReading },
+    ItemScan { line_id: u32, scan: Scan },
+    RfidRead { line_id: u32, epcs: Vec<String> },
+    // The cached price a line was added at was this many seconds old
+    StalePrice { line_id: u32, age_secs: u64 },
+    // The line was added without reserving stock
+    StockUnreserved { line_id: u32, reason: String },
+    TransactionAbandon { reason: String },
+    // SYNC: The central service acknowledged this terminal's journal up to `through_sequence`
+    SyncAcknowledge { through_sequence: u64, accepted: usize, conflicts: usize },
+    // SYNC: As the central service, a batch from `origin` was acknowledged through
+    // `through_sequence`, with the receipts it accepted for the first time
+    SyncIngest { origin: String, through_sequence: u64, receipts: Vec<SyncReceipt> },
+    // REPLICATION: This kernel took over from its primary after replicating this far
+    ReplicaPromote { replicated_through: u64 },
+    // BACKUP: This kernel was restored from a backup taken through `journal_through`
+    BackupRestore { backup_id: String, journal_through: u64 },
+    // RULES: The store's rules script was installed (its SHA-256) or removed (None)
+    RulesScriptSet { digest: Option<String> },
+    LineHold { line_id: u32, module: String, reason: String },
+    FuelPreset { pump_id: u32, line_id: u32, preset_minor: i64 },
+    FuelAuthorize { pump_id: u32 },
+    FuelDispense { pump_id: u32, line_id: u32, volume_milli: i64, amount_minor: i64, refunded_minor: i64 },
+    LineHoldResolve { line_id: u32, resolution: HoldResolution, operator_id: Option<String> },
+    ReceiptReprint { printers: Vec<String>, terminal_id: Option<String> },
+    DrawerSessionOpen { terminal_id: String, operator_id: Option<String>, currency: String, float_minor: i64 },
+    CashMovement {
+        terminal_id: String,
+        kind: CashMovementKind,
+        amount_minor: i64,
+        denominations: Vec<DenominationCount>,
+        operator_id: Option<String>,
+    },
+    RecyclerActivity {
+        terminal_id: String,
+        recycler: String,
+        operation: RecyclerOperation,
+        requested_minor: i64,
+        // Counted in for a deposit, paid out for a dispense, held for an inventory read
+        denominations: Vec<DenominationCount>,
+        amount_minor: i64,
+        expected_minor: Option<i64>,
+        error: Option<String>,
+    },
+    DrawerSessionClose {
+        terminal_id: String,
+        expected_minor: i64,
+        counted_minor: i64,
+        variance_minor: i64,
+        operator_id: Option<String>,
+    },
+    DrawerOpen {
+        reason: DrawerOpenReason,
+        driver: String,
+        terminal_id: Option<String>,
+        operator_id: Option<String>,
+        error: Option<String>,
+    },
+}
+
+impl JournalOperation {
+    // REGULATORY COMPLIANCE: Applies the PII policy to tagged fields
+    fn protect_pii(mut self) -> Self {
+        fn protect(category: PiiCategory, value: &mut String) {
+            let protected = pii::protect(category, value);
+            if protected != value.as_str() {
+                let protected = protected.into_owned();
+                *value = protected;
+            }
+        }
+        
+        match &mut self {
+            JournalOperation::LineAdd { approved_by: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::OperatorAssign { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::ShiftStart { operator_id } => protect(PiiCategory::OperatorId, operator_id),
+            JournalOperation::DrawerOpen { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::LineHoldResolve { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::DrawerSessionOpen { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::CashMovement { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::DrawerSessionClose { operator_id: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::RefundLimitTriggered { overridden_by: Some(operator), .. } => protect(PiiCategory::OperatorId, operator),
+            JournalOperation::CustomerAttach { token, .. } => protect(PiiCategory::CustomerRef, token),
+            JournalOperation::LoyaltyRecord { account_id, .. } => protect(PiiCategory::CustomerRef, account_id),
+            JournalOperation::AttributeSet { value, .. } => protect(PiiCategory::AttributeValue, value),
+            _ => {}
+        }
+        self
+    }
+}
+
+// 1: customer tokens are left out of the checksum; 2: CRC-32 replaces the byte sum
+const CHECKSUM_VERSION: u8 = 2;
+
+// Entries a journal with a file takes between checkpoints
+const CHECKPOINT_INTERVAL: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence_number: u64,
+    pub timestamp: DateTime<Utc>,
+    pub transaction_handle: u64,
+    pub operation: JournalOperation,
+    pub checksum: u32,
+    // 0 for entries journaled before checksums were versioned
+    #[serde(default)]
+    pub checksum_version: u8,
+}
+
+// The checksum of an operation under a checksum version. A customer token is blanked, so
+// erasing it leaves the checksum, and with it replication handshakes and resume tokens,
+// unchanged. Entries written before version 2 keep their byte-sum checksums.
+fn checksum(operation: &JournalOperation, version: u8) -> Result<u32, String> {
+    let payload = match operation {
+        JournalOperation::CustomerAttach { tier, .. } if version > 0 => {
+            serde_json::to_string(&JournalOperation::CustomerAttach { token: String::new(), tier: tier.clone() })
+        },
+        _ => serde_json::to_string(operation),
+    };
+    let payload = payload.map_err(|e| format!("Failed to serialize journal operation: {}", e))?;
+    Ok(match version {
+        0 | 1 => payload.bytes().fold(0u32, |acc, b| acc.wrapping_add(b as u32)),
+        _ => crc32(payload.as_bytes()),
+    })
+}
+
+// CRC-32 (IEEE 802.3, as used by zip and PNG)
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| CRC32_TABLE[((crc ^ u32::from(*b)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+impl JournalEntry {
+    // False once the operation no longer matches the checksum recorded with it
+    pub fn checksum_valid(&self) -> bool {
+        checksum(&self.operation, self.checksum_version).is_ok_and(|checksum| checksum == self.checksum)
+    }
+
+    // The entry as it leaves the kernel, with the PII policy applied; the checksum stays the
+    // journaled one, so check it before protecting
+    pub(crate) fn protected(&self) -> Self {
+        Self { operation: self.operation.clone().protect_pii(), ..self.clone() }
+    }
+}
+
+pub struct Journal {
+    state: Mutex<JournalState>,
+    // Present when the journal has a file to make durable
+    sync: Option<Arc<GroupCommit>>,
+}
+
+struct JournalState {
+    // The file's most recent entries, from the last checkpoint on; a journal without a file
+    // holds them all
+    entries: Vec<JournalEntry>,
+    // Entries held after the last checkpoint
+    checkpoint_len: usize,
+    next_sequence: u64,
+    // Sequence number of the most recent commit entry; acknowledged only once durable
+    commit_sequence: u64,
+    writer: Option<BufWriter<File>>,
+    path: Option<PathBuf>,
+    // REPLICATION: A replica's journal only takes entries replicated from its primary
+    read_only: bool,
+    // AUDIT COMPLIANCE: Follows the entries appended here; replicated entries were audited on
+    // the primary
+    audit: Option<Arc<AuditLog>>,
+}
+
+// Shared by all lanes waiting for their commits to become durable
+struct GroupCommit {
+    // Handle used for syncing; replaced when the journal file is rewritten
+    file: Mutex<File>,
+    // Highest sequence number handed to the operating system
+    written: AtomicU64,
+    window_micros: AtomicU64,
+    state: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+#[derive(Default)]
+struct SyncState {
+    durable: u64,
+    syncing: bool,
+}
+
+// Durability of everything up to `sequence`, waited for after the store lock is released
+pub struct SyncTicket {
+    sync: Option<Arc<GroupCommit>>,
+    sequence: u64,
+}
+
+impl Journal {
+    pub fn in_memory() -> Self {
+        Self {
+            state: Mutex::new(JournalState {
+                entries: Vec::new(),
+                checkpoint_len: 0,
+                next_sequence: 1,
+                commit_sequence: 0,
+                writer: None,
+                path: None,
+                read_only: false,
+                audit: None,
+            }),
+            sync: None,
+        }
+    }
+
+    // A panic while appending can at worst leave the entry in progress unwritten, so the
+    // state stays usable after poisoning
+    fn state(&self) -> MutexGuard<'_, JournalState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Opens (or creates) a journal file in append mode, continuing its sequence numbering
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create journal directory {}: {}", parent.display(), e))?;
+        }
+
+        let mut next_sequence = 1;
+        if path.exists() {
+            let file = File::open(path)
+                .map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                match serde_json::from_str::<JournalEntry>(&line) {
+                    Ok(entry) => next_sequence = entry.sequence_number + 1,
+                    Err(e) => eprintln!("WARNING: Skipping unreadable journal line in {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+        let sync_file = file.try_clone()
+            .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+
+        // Entries already in the file are treated as durable
+        let durable = next_sequence - 1;
+        Ok(Self {
+            state: Mutex::new(JournalState {
+                entries: Vec::new(),
+                checkpoint_len: 0,
+                next_sequence,
+                commit_sequence: durable,
+                writer: Some(BufWriter::new(file)),
+                path: Some(path.to_path_buf()),
+                read_only: false,
+                audit: None,
+            }),
+            sync: Some(Arc::new(GroupCommit {
+                file: Mutex::new(sync_file),
+                written: AtomicU64::new(durable),
+                window_micros: AtomicU64::new(0),
+                state: Mutex::new(SyncState { durable, syncing: false }),
+                synced: Condvar::new(),
+            })),
+        })
+    }
+
+    pub fn append(&self, transaction_handle: u64, operation: JournalOperation) -> Result<u64, String> {
+        let sequence_number = self.state().append(transaction_handle, operation)?;
+        if let Some(sync) = &self.sync {
+            sync.written.fetch_max(sequence_number, Ordering::Release);
+        }
+        Ok(sequence_number)
+    }
+
+    // REPLICATION: Sequence number of the last entry written
+    pub fn last_sequence(&self) -> u64 {
+        self.state().next_sequence - 1
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.state().read_only = read_only;
+    }
+
+    pub fn set_audit_log(&self, audit: Arc<AuditLog>) {
+        self.state().audit = Some(audit);
+    }
+
+    // REPLICATION: Appends an entry received from the primary as is, keeping its sequence
+    // number, timestamp and checksum. An entry already held is skipped (false); one beyond the
+    // next sequence number means entries were lost in between. Commits are made durable before
+    // returning, as they were on the primary. An anonymization rewrites the entries it lists,
+    // as it did on the primary.
+    pub fn append_replicated(&self, entry: JournalEntry) -> Result<bool, String> {
+        if !entry.checksum_valid() {
+            return Err(format!("Replicated journal entry {} failed its checksum", entry.sequence_number));
+        }
+
+        let sequence_number = entry.sequence_number;
+        let commit = matches!(entry.operation, JournalOperation::TransactionCommit { .. });
+        let redaction = match &entry.operation {
+            JournalOperation::CustomerAnonymize { pseudonym, redacted, .. } => Some((pseudonym.clone(), redacted.clone())),
+            _ => None,
+        };
+        let mut state = self.state();
+        if !state.append_entry(entry)? {
+            return Ok(false);
+        }
+        if let Some((pseudonym, redacted)) = redaction {
+            state.redact_customer(|entry, _| redacted.binary_search(&entry.sequence_number).is_ok(), &pseudonym)?;
+            self.reopen_sync_file(&state)?;
+        }
+        drop(state);
+        if let Some(sync) = &self.sync {
+            sync.written.fetch_max(sequence_number, Ordering::Release);
+            if commit {
+                sync.sync_through(sequence_number)?;
+            }
+        }
+        Ok(true)
+    }
+
+    // How long a sync waits for further commits to share it; zero syncs at once
+    pub fn set_group_commit_window(&self, window: Duration) {
+        if let Some(sync) = &self.sync {
+            sync.window_micros.store(window.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    // Taken under the store lock once an operation may have committed
+    pub fn commit_ticket(&self) -> SyncTicket {
+        SyncTicket { sync: self.sync.clone(), sequence: self.state().commit_sequence }
+    }
+
+    // Records an operation; journaling failures are reported but never undo a completed operation
+    pub fn record(&self, transaction_handle: u64, operation: JournalOperation) {
+        if let Err(e) = self.append(transaction_handle, operation) {
+            eprintln!("WARNING: Failed to journal operation for transaction {}: {}", transaction_handle, e);
+        }
+    }
+
+    pub fn entries_for(&self, transaction_handle: u64) -> Vec<JournalEntry> {
+        self.state().entries.iter()
+            .filter(|e| e.transaction_handle == transaction_handle)
+            .cloned()
+            .collect()
+    }
+
+    // Runs `inspect` over the entries held in memory, in sequence order, without copying them
+    pub fn with_entries<R>(&self, inspect: impl FnOnce(&[JournalEntry]) -> R) -> R {
+        inspect(&self.state().entries)
+    }
+
+    // A journal with a file is due a checkpoint once CHECKPOINT_INTERVAL entries were taken
+    // since the last one
+    pub fn checkpoint_due(&self) -> bool {
+        let state = self.state();
+        state.path.is_some() && state.entries.len() >= state.checkpoint_len + CHECKPOINT_INTERVAL
+    }
+
+    // Drops the entries before `keep_from` from memory; they stay in the file, where
+    // `scan_after` reads them. A journal without a file keeps every entry, as it has nowhere
+    // else to read them from.
+    pub fn checkpoint(&self, keep_from: u64) {
+        let mut state = self.state();
+        if state.path.is_none() {
+            return;
+        }
+        let dropped = state.entries.partition_point(|e| e.sequence_number < keep_from);
+        state.entries.drain(..dropped);
+        state.checkpoint_len = state.entries.len();
+    }
+
+    // Visits the entries after `sequence` in order until `visit` returns false. Entries written
+    // before this process opened the journal, or dropped at a checkpoint, are not held in
+    // memory and are read back from the file, without the journal lock.
+    pub fn scan_after(&self, sequence: u64, mut visit: impl FnMut(&JournalEntry) -> bool) -> Result<(), String> {
+        let path = {
+            let state = self.state();
+            match (state.entries.first(), &state.path) {
+                (Some(first), _) if first.sequence_number <= sequence + 1 => None,
+                (_, None) => None,
+                (_, Some(path)) => Some(path.clone()),
+            }
+        };
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let state = self.state();
+                let start = state.entries.partition_point(|e| e.sequence_number <= sequence);
+                for entry in &state.entries[start..] {
+                    if !visit(entry) {
+                        break;
+                    }
+                }
+                return Ok(());
+            },
+        };
+
+        let file = File::open(&path)
+            .map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+        // Unreadable lines were reported when the journal was opened; the last line may still
+        // be being written
+        let entries = BufReader::new(file).lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<JournalEntry>(&line).ok())
+            .filter(|entry| entry.sequence_number > sequence);
+        for entry in entries {
+            if !visit(&entry) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the sequence numbers of the entries rewritten
+    pub fn redact_customer(&self, token: &str, pseudonym: &str) -> Result<Vec<u64>, String> {
+        let mut state = self.state();
+        let redacted = state.redact_customer(|_, attached| attached == token, pseudonym)?;
+        self.reopen_sync_file(&state)?;
+        Ok(redacted)
+    }
+
+    // The rewritten file was synced before it replaced the old one
+    fn reopen_sync_file(&self, state: &JournalState) -> Result<(), String> {
+        if let (Some(sync), Some(writer)) = (&self.sync, state.writer.as_ref()) {
+            let file = writer.get_ref().try_clone()
+                .map_err(|e| format!("Failed to reopen journal for syncing: {}", e))?;
+            *sync.file.lock().unwrap_or_else(PoisonError::into_inner) = file;
+        }
+        Ok(())
+    }
+}
+
+impl SyncTicket {
+    // Returns once every entry up to the ticket's sequence number is on stable storage
+    pub fn wait(self) -> Result<(), String> {
+        match self.sync {
+            Some(sync) => sync.sync_through(self.sequence),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GroupCommit {
+    // Syncs are never left half-done by a panic, so poisoning is not fatal
+    fn state(&self) -> MutexGuard<'_, SyncState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sync_through(&self, sequence: u64) -> Result<(), String> {
+        let mut state = self.state();
+        loop {
+            if state.durable >= sequence {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+
+        // This lane leads the next sync; lanes arriving meanwhile wait for its outcome
+        state.syncing = true;
+        drop(state);
+
+        let window = self.window_micros.load(Ordering::Relaxed);
+        if window > 0 {
+            std::thread::sleep(Duration::from_micros(window));
+        }
+        let target = self.written.load(Ordering::Acquire);
+        let result = match faults::fires(Fault::JournalSync) {
+            true => Err("Failed to sync journal: injected fault".to_string()),
+            false => self.file.lock().unwrap_or_else(PoisonError::into_inner)
+                .sync_data()
+                .map_err(|e| format!("Failed to sync journal: {}", e)),
+        };
+
+        let mut state = self.state();
+        state.syncing = false;
+        if result.is_ok() {
+            state.durable = state.durable.max(target);
+        }
+        self.synced.notify_all();
+        result
+    }
+}
+
+impl JournalState {
+    fn append(&mut self, transaction_handle: u64, operation: JournalOperation) -> Result<u64, String> {
+        if self.read_only {
+            return Err("Journal is a read-only replica".to_string());
+        }
+
+        let checksum = checksum(&operation, CHECKSUM_VERSION)?;
+
+        let entry = JournalEntry {
+            sequence_number: self.next_sequence,
+            timestamp: Utc::now(),
+            transaction_handle,
+            operation,
+            checksum,
+            checksum_version: CHECKSUM_VERSION,
+        };
+
+        if let Some(writer) = self.writer.as_mut() {
+            let line = serde_json::to_string(&entry)
+                .map_err(|e| format!("Failed to serialize journal entry: {}", e))?;
+            if faults::fires(Fault::JournalWrite) {
+                return Err("Failed to write journal entry: injected fault".to_string());
+            }
+            writeln!(writer, "{}", line)
+                .and_then(|_| writer.flush())
+                .map_err(|e| format!("Failed to write journal entry: {}", e))?;
+        }
+
+        self.next_sequence += 1;
+        let sequence_number = entry.sequence_number;
+        if matches!(entry.operation, JournalOperation::TransactionCommit { .. }) {
+            self.commit_sequence = sequence_number;
+        }
+        if let Some(audit) = &self.audit {
+            audit.observe(&entry);
+        }
+        self.entries.push(entry);
+        Ok(sequence_number)
+    }
+
+    fn append_entry(&mut self, entry: JournalEntry) -> Result<bool, String> {
+        if entry.sequence_number < self.next_sequence {
+            return Ok(false);
+        }
+        if entry.sequence_number > self.next_sequence {
+            return Err(format!("Replicated journal skipped from {} to {}", self.next_sequence - 1, entry.sequence_number));
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            let line = serde_json::to_string(&entry)
+                .map_err(|e| format!("Failed to serialize journal entry: {}", e))?;
+            writeln!(writer, "{}", line)
+                .and_then(|_| writer.flush())
+                .map_err(|e| format!("Failed to write journal entry: {}", e))?;
+        }
+
+        self.next_sequence += 1;
+        if matches!(entry.operation, JournalOperation::TransactionCommit { .. }) {
+            self.commit_sequence = entry.sequence_number;
+        }
+        self.entries.push(entry);
+        Ok(true)
+    }
+
+    // REGULATORY COMPLIANCE: Replaces the token in the journaled customer references `select`
+    // picks (given the entry and its token), in the journal file (rewritten via a temporary file
+    // and rename) and then in memory, so a failed rewrite leaves both as they were. Checksums
+    // leave the token out and are unchanged, except that entries journaled before that
+    // (version 0) get a current checksum; sequence numbers and amounts are unchanged. Returns
+    // the sequence numbers rewritten in the file, or in memory for a journal without one.
+    fn redact_customer(&mut self, select: impl Fn(&JournalEntry, &str) -> bool, pseudonym: &str) -> Result<Vec<u64>, String> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => return self.redact_entries(&select, pseudonym),
+        };
+
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush().map_err(|e| format!("Failed to flush journal: {}", e))?;
+        }
+
+        let file = File::open(&path)
+            .map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+        let mut file_redacted = Vec::new();
+        let mut lines = Vec::new();
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let mut entry = match serde_json::from_str::<JournalEntry>(&line) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    lines.push(line);
+                    continue;
+                }
+            };
+
+            if Self::redact_entry(&mut entry, &select, pseudonym)? {
+                file_redacted.push(entry.sequence_number);
+                lines.push(serde_json::to_string(&entry)
+                    .map_err(|e| format!("Failed to serialize journal entry: {}", e))?);
+            } else {
+                lines.push(line);
+            }
+        }
+
+        let temp_path = path.with_extension("redact.tmp");
+        let mut temp = BufWriter::new(File::create(&temp_path)
+            .map_err(|e| format!("Failed to create {}: {}", temp_path.display(), e))?);
+        for line in &lines {
+            writeln!(temp, "{}", line)
+                .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+        }
+        temp.flush()
+            .and_then(|_| temp.get_ref().sync_all())
+            .map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+        drop(temp);
+
+        self.writer = None;
+        std::fs::rename(&temp_path, &path)
+            .map_err(|e| format!("Failed to replace journal {}: {}", path.display(), e))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to reopen journal {}: {}", path.display(), e))?;
+        self.writer = Some(BufWriter::new(file));
+
+        self.redact_entries(&select, pseudonym)?;
+        Ok(file_redacted)
+    }
+
+    fn redact_entries(&mut self, select: &impl Fn(&JournalEntry, &str) -> bool, pseudonym: &str) -> Result<Vec<u64>, String> {
+        let mut redacted = Vec::new();
+        for entry in self.entries.iter_mut() {
+            if Self::redact_entry(entry, select, pseudonym)? {
+                redacted.push(entry.sequence_number);
+            }
+        }
+        Ok(redacted)
+    }
+
+    fn redact_entry(entry: &mut JournalEntry, select: &impl Fn(&JournalEntry, &str) -> bool, pseudonym: &str) -> Result<bool, String> {
+        match &entry.operation {
+            JournalOperation::CustomerAttach { token, .. } if select(entry, token) => {},
+            _ => return Ok(false),
+        }
+        if let JournalOperation::CustomerAttach { token, .. } = &mut entry.operation {
+            *token = pseudonym.to_string();
+        }
+
+        if entry.checksum_version == 0 {
+            entry.checksum = checksum(&entry.operation, CHECKSUM_VERSION)?;
+            entry.checksum_version = CHECKSUM_VERSION;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(key: &str, value: &str) -> JournalOperation {
+        JournalOperation::AttributeSet { key: key.to_string(), value: value.to_string() }
+    }
+
+    #[test]
+    fn checksums_are_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let journal = Journal::in_memory();
+        journal.append(1, attribute("ab", "cd")).expect("entry appends");
+        let mut entry = journal.entries_for(1).remove(0);
+        assert_eq!(entry.checksum_version, CHECKSUM_VERSION);
+        assert!(entry.checksum_valid());
+
+        // Reordered bytes leave a byte sum unchanged but not a CRC
+        entry.operation = attribute("ba", "cd");
+        assert!(!entry.checksum_valid());
+    }
+
+    #[test]
+    fn byte_sum_checksums_still_verify() {
+        let operation = attribute("ab", "cd");
+        let payload = serde_json::to_string(&operation).expect("operation serializes");
+        let mut entry = JournalEntry {
+            sequence_number: 1,
+            timestamp: Utc::now(),
+            transaction_handle: 1,
+            operation,
+            checksum: payload.bytes().map(u32::from).sum(),
+            checksum_version: 1,
+        };
+        assert!(entry.checksum_valid());
+        entry.checksum_version = 0;
+        assert!(entry.checksum_valid());
+        entry.checksum_version = CHECKSUM_VERSION;
+        assert!(!entry.checksum_valid());
+    }
+
+    #[test]
+    fn checkpoints_keep_entries_in_the_file() {
+        let path = std::env::temp_dir().join(format!("pk-journal-{}.wal", uuid::Uuid::new_v4()));
+        let journal = Journal::open(&path).expect("journal opens");
+        let count = CHECKPOINT_INTERVAL as u64 + 10;
+        for sequence in 1..=count {
+            journal.append(sequence % 3, attribute("n", &sequence.to_string())).expect("entry appends");
+        }
+        assert!(journal.checkpoint_due());
+
+        journal.checkpoint(count - 4);
+        assert!(!journal.checkpoint_due());
+        let held = journal.with_entries(|entries| entries.iter().map(|e| e.sequence_number).collect::<Vec<_>>());
+        assert_eq!(held, [count - 4, count - 3, count - 2, count - 1, count]);
+
+        let mut scanned = Vec::new();
+        journal.scan_after(0, |entry| {
+            scanned.push(entry.sequence_number);
+            true
+        }).expect("journal scans");
+        assert_eq!(scanned, (1..=count).collect::<Vec<_>>());
+
+        // A journal without a file has nowhere to read dropped entries from
+        let in_memory = Journal::in_memory();
+        in_memory.append(1, attribute("n", "1")).expect("entry appends");
+        in_memory.checkpoint(2);
+        assert_eq!(in_memory.entries_for(1).len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,38 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per-thread last-error detail
+//! Result codes say what kind of failure occurred; the last error says why, in text for logs
+//! and support. Each FFI thread sees only its own failures. Like `errno`, the message is kept
+//! until the next failure that records one, so hosts read it right after the failing call.
+
+use std::cell::RefCell;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn set(message: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+pub(crate) fn get() -> Option<String> {
+    LAST_ERROR.with(|e| e.borrow().clone())
+}
+
+pub(crate) fn clear() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+}
@@ -17,9 +17,84 @@
 //! POS Kernel Rust Implementation - Minimal Working Version
 //! Focus: Get the Rust service compiling and running with basic functionality
 
-use std::collections::HashMap;
-use std::sync::{OnceLock, RwLock};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+pub mod accounting;
+pub mod analytics;
+pub mod anomaly;
+pub mod archive;
+mod audit;
+mod backup;
+pub mod cash;
+pub mod catalog;
+mod command;
+pub mod einvoice;
+pub mod error;
+pub mod erp;
+pub mod escpos;
+mod events;
+pub mod export;
+mod faults;
+pub mod fiscal;
+pub mod fuel;
+mod intern;
+pub mod inventory;
+mod journal;
+mod last_error;
+mod lock;
+mod metrics;
+mod nats;
+mod persistence;
+mod pinned;
+mod pipeline;
+mod plugin;
+mod pool;
+pub mod loyalty;
+pub mod pci;
+pub mod peripheral;
+pub mod pickup;
+pub mod pii;
+pub mod poslog;
+pub mod pricing;
+pub mod privacy;
+pub mod receipt;
+pub mod refund;
+pub mod replay;
+mod replication;
+pub mod rfid;
+pub mod reports;
+pub mod rules;
+pub mod sales;
+pub mod scan;
+pub mod security;
+mod shard;
+mod signing;
+mod subscription;
+pub mod sync;
+pub mod tax;
+mod totals;
+pub mod template;
+mod verify;
+mod xml;
+
+use events::{EventBus, EventKind};
+use journal::{Journal, JournalOperation};
+use loyalty::LoyaltyActivity;
+use pci::TextField;
+use peripheral::DisplayUpdateKind;
+use pricing::PriceProvider;
+use privacy::AnonymizationRecord;
+use receipt::{DeliveryReason, ReceiptDeliverer};
+
+pub use error::KernelError;
+pub use pinned::PkLineView;
 
 // === RESULT CODES ===
 
@@ -31,6 +106,13 @@ pub enum ResultCode {
     ValidationFailed = 3,
     InsufficientBuffer = 4,
     TimedOut = 5,
+    PermissionDenied = 6,
+    // BACKPRESSURE: An active-transaction cap was reached; retry after open sales settle
+    CapacityExceeded = 7,
+    // NRF COMPLIANCE: The parent line was voided; modifiers and deposits cannot attach to it
+    ParentLineVoided = 8,
+    // The tender was stated in a currency other than the transaction's
+    CurrencyMismatch = 9,
     InternalError = 255
 }
 
@@ -44,16 +126,47 @@ pub struct PkResult {
 impl PkResult {
     fn ok() -> Self { Self { code: 0, reserved: 0 } }
     fn err(c: ResultCode) -> Self { Self { code: c as i32, reserved: 0 } }
+    fn from_error(error: &KernelError) -> Self { Self::err(error.code()) }
 }
 
 pub type PkTransactionHandle = u64;
 pub const PK_INVALID_HANDLE: PkTransactionHandle = 0;
+// Quote handles use their own sequence, flagged by the high bit, so quotes never consume
+// transaction sequence numbers
+pub const PK_QUOTE_HANDLE_FLAG: PkTransactionHandle = 1 << 63;
 
 // === BASIC DATA TYPES ===
 
-#[derive(Debug, Clone)]
+// SECURITY: Permissions are granted to operators by user space; the kernel only enforces them
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    ManualAdjustment = 1,
+    // SECURITY: Approves returns that exceed a refund velocity limit
+    RefundOverride = 2,
+    // SECURITY: Accepts self-checkout lines held by the line security module
+    SecurityOverride = 3,
+    // SECURITY: Picks up cash from a drawer or loans cash to it from the safe
+    CashManagement = 4,
+}
+
+impl Permission {
+    fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Permission::ManualAdjustment),
+            2 => Some(Permission::RefundOverride),
+            3 => Some(Permission::SecurityOverride),
+            4 => Some(Permission::CashManagement),
+            _ => None,
+        }
+    }
+}
+
+// No currency in use has more than four; receipts and exports format amounts up to this many
+const MAX_CURRENCY_DECIMAL_PLACES: u8 = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Currency {
-    #[allow(dead_code)] // Stored for future audit and display purposes
     code: String,
     decimal_places: u8,
 }
@@ -62,6 +175,10 @@ impl Currency {
     fn new(code: &str, decimal_places: u8) -> Result<Self, &'static str> {
         let code_upper = code.to_uppercase();
         
+        if decimal_places > MAX_CURRENCY_DECIMAL_PLACES {
+            return Err("Currency decimal places out of range");
+        }
+        
         // ARCHITECTURAL PRINCIPLE: Kernel is culture-neutral - client provides decimal places
         // Currency formatting and rules are user-space concerns, not kernel concerns
         Ok(Currency {
@@ -75,38 +192,158 @@ impl Currency {
     }
 }
 
-#[derive(Debug, Clone)]
+// REGULATORY COMPLIANCE: Line types are kept distinct so deposits can be excluded from
+// discounting and reported separately for container-deposit filings
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LineKind {
+    Sale = 0,
+    Deposit = 1,
+    DepositReturn = 2,
+    Adjustment = 3,
+    // Merchandise return; unit price is stored as a positive value and the line is refunded
+    Return = 4,
+}
+
+impl LineKind {
+    fn is_discountable(&self) -> bool {
+        matches!(self, LineKind::Sale)
+    }
+}
+
+// Typical baskets fit inline, so most transactions never allocate for their lines
+const INLINE_LINES: usize = 8;
+type LineItems = SmallVec<[Line; INLINE_LINES]>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Line {
-    #[allow(dead_code)] // Stored for audit trail and transaction display
-    sku: String,
+    // Immutable per-transaction identifier assigned at creation; never derived from position
+    line_id: u32,
+    // Interned from the store's SKU pool
+    sku: Arc<str>,
     qty: i32,
     unit_minor: i64,
     // NRF COMPLIANCE: Support linked items (parent-child relationships) ONLY
     parent_line_item_id: Option<u32>,
+    kind: LineKind,
+    // AUDIT COMPLIANCE: Manual adjustments record why and who approved them
+    reason_code: Option<String>,
+    approved_by: Option<String>,
+    // Display metadata supplied by user space (product catalog); never used in calculations
+    product_name: Option<String>,
+    product_description: Option<String>,
+    // Receipt number of the sale a returned item was bought on, when presented
+    original_receipt: Option<String>,
 }
 
 impl Line {
-    fn new(sku: String, qty: i32, unit_minor: i64) -> Self {
+    fn new(sku: Arc<str>, qty: i32, unit_minor: i64) -> Self {
         Self { 
+            line_id: 0, // Assigned by Transaction::push_line
             sku, 
             qty, 
             unit_minor,
             parent_line_item_id: None, // Initialize as top-level item
+            kind: LineKind::Sale,
+            reason_code: None,
+            approved_by: None,
+            product_name: None,
+            product_description: None,
+            original_receipt: None,
         }
     }
     
     // Constructor for child items with parent reference
-    fn new_with_parent(sku: String, qty: i32, unit_minor: i64, parent_line_id: u32) -> Self {
+    fn new_with_parent(sku: Arc<str>, qty: i32, unit_minor: i64, parent_line_id: u32) -> Self {
         Self { 
+            line_id: 0, // Assigned by Transaction::push_line
             sku, 
             qty, 
             unit_minor,
             parent_line_item_id: Some(parent_line_id),
+            kind: LineKind::Sale,
+            reason_code: None,
+            approved_by: None,
+            product_name: None,
+            product_description: None,
+            original_receipt: None,
+        }
+    }
+    
+    // Deposit lines are children of the item that triggered them, so voiding the
+    // item cascades to its deposit
+    fn new_deposit(sku: Arc<str>, qty: i32, unit_minor: i64, triggering_line_id: u32) -> Self {
+        Self {
+            line_id: 0, // Assigned by Transaction::push_line
+            sku,
+            qty,
+            unit_minor,
+            parent_line_item_id: Some(triggering_line_id),
+            kind: LineKind::Deposit,
+            reason_code: None,
+            approved_by: None,
+            product_name: None,
+            product_description: None,
+            original_receipt: None,
+        }
+    }
+    
+    fn new_deposit_return(sku: Arc<str>, qty: i32, unit_minor: i64) -> Self {
+        Self {
+            line_id: 0, // Assigned by Transaction::push_line
+            sku,
+            qty,
+            unit_minor,
+            parent_line_item_id: None,
+            kind: LineKind::DepositReturn,
+            reason_code: None,
+            approved_by: None,
+            product_name: None,
+            product_description: None,
+            original_receipt: None,
+        }
+    }
+    
+    // Manual negative-price adjustment (goodwill credit, damage allowance)
+    fn new_adjustment(sku: Arc<str>, amount_minor: i64, reason_code: String, approved_by: String) -> Self {
+        Self {
+            line_id: 0, // Assigned by Transaction::push_line
+            sku,
+            qty: 1,
+            unit_minor: amount_minor,
+            parent_line_item_id: None,
+            kind: LineKind::Adjustment,
+            reason_code: Some(reason_code),
+            approved_by: Some(approved_by),
+            product_name: None,
+            product_description: None,
+            original_receipt: None,
+        }
+    }
+    
+    fn new_return(sku: Arc<str>, qty: i32, unit_minor: i64, original_receipt: Option<String>, reason_code: Option<String>, approved_by: Option<String>) -> Self {
+        Self {
+            line_id: 0, // Assigned by Transaction::push_line
+            sku,
+            qty,
+            unit_minor,
+            parent_line_item_id: None,
+            kind: LineKind::Return,
+            reason_code,
+            approved_by,
+            product_name: None,
+            product_description: None,
+            original_receipt,
         }
     }
     
     fn total_minor(&self) -> i64 {
-        self.unit_minor * self.qty as i64
+        let gross = self.unit_minor * self.qty as i64;
+        match self.kind {
+            // Deposit refunds and returns reduce the amount due; unit price is stored as a positive value
+            LineKind::DepositReturn | LineKind::Return => -gross,
+            _ => gross,
+        }
     }
     
     fn get_parent_line_item_id(&self) -> Option<u32> {
@@ -119,754 +356,10988 @@ impl Line {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TenderKind {
+    #[default]
+    Cash = 0,
+    Points = 1,
+    Card = 2,
+}
+
+// TAB: Sub-state of a card tender held against an open tab
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CardAuthState {
+    PreAuthorized,
+    Captured,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardAuthorization {
+    pub auth_reference: String,
+    pub state: CardAuthState,
+    pub authorized_minor: i64,
+    pub tip_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tender {
+    pub kind: TenderKind,
+    pub amount_minor: i64,
+    // LOYALTY: Points burned for a Points tender; `amount_minor` holds their monetary value
+    pub points: Option<i64>,
+    // TAB: Pre-authorized card tenders carry `amount_minor` 0 until captured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card: Option<CardAuthorization>,
+    // IDEMPOTENCY: Client-generated token; a resubmission carrying it is not tendered again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Tender {
+    fn cash(amount_minor: i64) -> Self {
+        Self { kind: TenderKind::Cash, amount_minor, points: None, card: None, token: None }
+    }
+    
+    fn points(points: i64, amount_minor: i64) -> Self {
+        Self { kind: TenderKind::Points, amount_minor, points: Some(points), card: None, token: None }
+    }
+    
+    fn card_pre_auth(auth_reference: String, authorized_minor: i64) -> Self {
+        Self {
+            kind: TenderKind::Card,
+            amount_minor: 0,
+            points: None,
+            card: Some(CardAuthorization {
+                auth_reference,
+                state: CardAuthState::PreAuthorized,
+                authorized_minor,
+                tip_minor: 0,
+            }),
+            token: None,
+        }
+    }
+    
+    // EMV: A card payment the payment device has already approved and captured
+    fn card(auth_reference: String, amount_minor: i64) -> Self {
+        Self {
+            kind: TenderKind::Card,
+            amount_minor,
+            points: None,
+            card: Some(CardAuthorization {
+                auth_reference,
+                state: CardAuthState::Captured,
+                authorized_minor: amount_minor,
+                tip_minor: 0,
+            }),
+            token: None,
+        }
+    }
+    
+    fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+// CRM INTEGRATION: Opaque customer token issued by user space; the kernel never resolves it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomerRef {
+    pub token: String,
+    pub tier: Option<String>,
+}
+
+// OMNICHANNEL: Typed external order references; values are opaque, culture-neutral text
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OrderReferenceField {
+    ExternalOrderId = 1,
+    Channel = 2,
+    PickupReference = 3,
+}
+
+impl OrderReferenceField {
+    fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(OrderReferenceField::ExternalOrderId),
+            2 => Some(OrderReferenceField::Channel),
+            3 => Some(OrderReferenceField::PickupReference),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderReference {
+    pub external_order_id: Option<String>,
+    pub channel: Option<String>,
+    // Pickup number, table number or similar fulfilment reference
+    pub pickup_reference: Option<String>,
+}
+
+impl OrderReference {
+    fn field_mut(&mut self, field: OrderReferenceField) -> &mut Option<String> {
+        match field {
+            OrderReferenceField::ExternalOrderId => &mut self.external_order_id,
+            OrderReferenceField::Channel => &mut self.channel,
+            OrderReferenceField::PickupReference => &mut self.pickup_reference,
+        }
+    }
+    
+    fn field(&self, field: OrderReferenceField) -> Option<&str> {
+        match field {
+            OrderReferenceField::ExternalOrderId => self.external_order_id.as_deref(),
+            OrderReferenceField::Channel => self.channel.as_deref(),
+            OrderReferenceField::PickupReference => self.pickup_reference.as_deref(),
+        }
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.external_order_id.is_none() && self.channel.is_none() && self.pickup_reference.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TransactionKind {
+    #[default]
+    Sale,
+    // QUOTE: Prices a basket only; never tendered or committed
+    Quote,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TxState {
     Building,
     Committed,
+    // LAYAWAY: Lines are frozen and partial payments are accepted until fully paid
+    Layaway,
+    Cancelled,
 }
 
-#[derive(Debug)]
+impl TxState {
+    // State codes reported through pk_get_totals
+    fn code(&self) -> u32 {
+        match self {
+            TxState::Building => 0,
+            TxState::Committed => 1,
+            TxState::Layaway => 2,
+            TxState::Cancelled => 3,
+        }
+    }
+}
+
+// ARCHITECTURAL PRINCIPLE: Tax calculation is a user-space concern; the kernel records the
+// breakdown supplied by the tax engine. Exclusive (not `inclusive`) tax is added to the total.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaxEntry {
+    pub jurisdiction: String,
+    pub rate_code: String,
+    pub rate_basis_points: i32,
+    // Net basis the tax was calculated on
+    pub taxable_minor: i64,
+    pub tax_minor: i64,
+    pub exempt_minor: i64,
+    pub inclusive: bool,
+    // The line the entry taxes, when the engine breaks tax down per line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LayawayInfo {
+    pub deposit_minor: i64,
+    pub payment_count: u32,
+    pub restocking_fee_minor: Option<i64>,
+    pub refund_minor: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LineBounds {
+    max_qty: u32,
+    max_unit_minor: u64,
+    max_lines: usize,
+    max_children: usize,
+    max_depth: usize,
+}
+
+// Serialized in full by the persistence backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Transaction {
-    #[allow(dead_code)] // Stored for audit trail and transaction identification
     id: u64,
-    #[allow(dead_code)] // Stored for transaction context and audit purposes
+    kind: TransactionKind,
     store: String,
+    // Lane and operator context, set by the client; used for audit and loss prevention
+    terminal_id: Option<String>,
+    operator_id: Option<String>,
     currency: Currency,
-    lines: Vec<Line>,
+    lines: LineItems,
+    next_line_id: u32,
+    tenders: Vec<Tender>,
     tendered_minor: i64,
+    taxes: Vec<TaxEntry>,
     state: TxState,
+    started_at: DateTime<Utc>,
+    committed_at: Option<DateTime<Utc>>,
+    // ARCHITECTURAL PRINCIPLE: Neutral user-space context (external order id, channel, ...)
+    // kept out of kernel domain types
+    attributes: BTreeMap<String, String>,
+    order_reference: OrderReference,
+    customer: Option<CustomerRef>,
+    loyalty: Option<LoyaltyActivity>,
+    layaway: Option<LayawayInfo>,
+    // REGULATORY COMPLIANCE: KassenSichV TSE state for this transaction
+    tse_start: Option<fiscal::TseStart>,
+    tse_signature: Option<fiscal::TseSignature>,
+    tse_error: Option<String>,
+    fiscal_registration: Option<fiscal::FiscalRegistration>,
+    fiscal_error: Option<String>,
+    quote_reference: Option<String>,
+    converted_to: Option<u64>,
+    // SYNC: Journal sequence number of the begin entry, where the transaction's entries start
+    #[serde(default)]
+    begin_sequence: Option<u64>,
+    // KDS: Lines sent to the kitchen
+    #[serde(default)]
+    fired_lines: BTreeSet<u32>,
+    // REGULATORY COMPLIANCE: Scale readings behind weighed lines, by line ID
+    #[serde(default)]
+    weight_readings: BTreeMap<u32, peripheral::WeightReading>,
+    // SECURITY: Lines held by the line security module, by line ID
+    #[serde(default)]
+    held_lines: BTreeMap<u32, security::LineHold>,
+    // RFID: Tags read into the transaction (pure-identity URI) and the line each was added to
+    #[serde(default)]
+    rfid_tags: BTreeMap<String, u32>,
+    // FUEL: Prepaid pumps, in preset order
+    #[serde(default)]
+    fuel_sales: Vec<fuel::FuelSale>,
+    // INVENTORY: Stock reserved for lines, by line ID
+    #[serde(default)]
+    stock_reservations: BTreeMap<u32, inventory::StockReservation>,
+    // OMNICHANNEL: Set on an online order imported for in-store pickup
+    #[serde(default)]
+    pickup: Option<pickup::PickupOrder>,
+    // Published after every modification; read without the store lock
+    #[serde(skip)]
+    totals: Arc<totals::CachedTotals>,
+    // Zero-copy line view handed to user space; dropped on modification
+    #[serde(skip)]
+    pinned_lines: pinned::PinSlot,
 }
 
 impl Transaction {
-    fn new(id: u64, store: String, currency: Currency) -> Self {
+    fn new(id: u64, kind: TransactionKind, store: String, currency: Currency) -> Self {
         Self {
             id,
+            kind,
             store,
+            terminal_id: None,
+            operator_id: None,
             currency,
-            lines: Vec::new(),
+            lines: LineItems::new(),
+            next_line_id: 1,
+            tenders: Vec::new(),
             tendered_minor: 0,
+            taxes: Vec::new(),
             state: TxState::Building,
+            started_at: Utc::now(),
+            committed_at: None,
+            attributes: BTreeMap::new(),
+            order_reference: OrderReference::default(),
+            customer: None,
+            loyalty: None,
+            layaway: None,
+            tse_start: None,
+            tse_signature: None,
+            tse_error: None,
+            fiscal_registration: None,
+            fiscal_error: None,
+            quote_reference: None,
+            converted_to: None,
+            begin_sequence: None,
+            fired_lines: BTreeSet::new(),
+            weight_readings: BTreeMap::new(),
+            held_lines: BTreeMap::new(),
+            rfid_tags: BTreeMap::new(),
+            fuel_sales: Vec::new(),
+            stock_reservations: BTreeMap::new(),
+            pickup: None,
+            totals: Arc::new(totals::CachedTotals::default()),
+            pinned_lines: pinned::PinSlot::default(),
+        }
+    }
+    
+    fn cached_totals(&self) -> Arc<totals::CachedTotals> {
+        Arc::clone(&self.totals)
+    }
+    
+    // Pointers handed out by `pk_pin_lines` are invalid from here on
+    fn release_pinned_lines(&mut self) {
+        self.pinned_lines.release();
+    }
+    
+    fn publish_totals(&self) {
+        self.totals.publish(self.totals_snapshot());
+    }
+    
+    fn totals_snapshot(&self) -> totals::TotalsSnapshot {
+        totals::TotalsSnapshot {
+            total_minor: self.total_minor(),
+            tendered_minor: self.tendered_minor,
+            change_minor: self.change_minor(),
+            line_count: self.line_count(),
+            state: self.state.code(),
         }
     }
     
     fn total_minor(&self) -> i64 {
+        self.lines_total_minor() + self.exclusive_tax_minor()
+    }
+    
+    fn lines_total_minor(&self) -> i64 {
         self.lines.iter().map(|l| l.total_minor()).sum()
     }
     
+    // Price reductions given on the transaction (negative adjustments), as a positive amount
+    fn savings_minor(&self) -> i64 {
+        -self.lines.iter()
+            .filter(|l| l.kind == LineKind::Adjustment)
+            .map(|l| l.total_minor().min(0))
+            .sum::<i64>()
+    }
+    
+    fn exclusive_tax_minor(&self) -> i64 {
+        self.taxes.iter().filter(|t| !t.inclusive).map(|t| t.tax_minor).sum()
+    }
+    
     fn change_minor(&self) -> i64 {
         (self.tendered_minor - self.total_minor()).max(0)
     }
     
-    fn add_line(&mut self, sku: String, qty: i32, unit_minor: i64) {
-        self.lines.push(Line::new(sku, qty, unit_minor));
+    // Assigns the next stable line ID and appends the line; returns the assigned ID
+    fn push_line(&mut self, mut line: Line) -> u32 {
+        let line_id = self.next_line_id;
+        self.next_line_id += 1;
+        line.line_id = line_id;
+        self.lines.push(line);
+        line_id
+    }
+    
+    // Rejects a line beyond the configured bounds, or one whose amount would overflow the
+    // transaction total; the detail is left in the thread's last error
+    fn check_new_line(&self, bounds: LineBounds, qty: i32, unit_minor: i64, parent_line_id: Option<u32>) -> Result<(), KernelError> {
+        let lines_total: i128 = self.lines.iter().map(|l| l.total_minor() as i128).sum();
+        let amount = unit_minor.unsigned_abs() as i128 * qty.unsigned_abs() as i128;
+        let children = |parent| self.lines.iter().filter(|l| l.parent_line_item_id == Some(parent)).count();
+        
+        let detail = if qty.unsigned_abs() > bounds.max_qty {
+            format!("Quantity {} exceeds the limit of {}", qty, bounds.max_qty)
+        } else if unit_minor.unsigned_abs() > bounds.max_unit_minor {
+            format!("Unit amount {} exceeds the limit of {}", unit_minor, bounds.max_unit_minor)
+        } else if lines_total.abs() + amount > i64::MAX as i128 {
+            "Line amount would overflow the transaction total".to_string()
+        } else if self.lines.len() >= bounds.max_lines {
+            format!("Transaction already has {} lines (limit {})", self.lines.len(), bounds.max_lines)
+        } else if let Some(depth) = parent_line_id.and_then(|p| self.line_depth(p)).filter(|&d| d >= bounds.max_depth) {
+            format!("Parent line is already {} levels deep (limit {})", depth, bounds.max_depth)
+        } else if let Some(parent) = parent_line_id.filter(|&p| children(p) >= bounds.max_children) {
+            format!("Line {} already has {} child lines (limit {})", parent, children(parent), bounds.max_children)
+        } else {
+            return Ok(());
+        };
+        last_error::set(detail);
+        Err(KernelError::validation(OUT_OF_BOUNDS))
+    }
+    
+    fn find_line(&self, line_id: u32) -> Option<&Line> {
+        self.lines.iter().find(|l| l.line_id == line_id)
+    }
+    
+    fn find_line_mut(&mut self, line_id: u32) -> Option<&mut Line> {
+        self.lines.iter_mut().find(|l| l.line_id == line_id)
+    }
+    
+    fn add_line(&mut self, sku: Arc<str>, qty: i32, unit_minor: i64) -> u32 {
+        self.push_line(Line::new(sku, qty, unit_minor))
+    }
+    
+    fn add_line_with_metadata(&mut self, sku: Arc<str>, qty: i32, unit_minor: i64, product_name: Option<String>, product_description: Option<String>) -> u32 {
+        let mut line = Line::new(sku, qty, unit_minor);
+        line.product_name = product_name;
+        line.product_description = product_description;
+        self.push_line(line)
     }
     
     // NRF COMPLIANCE: Add child item with parent reference
-    fn add_child_line(&mut self, sku: String, qty: i32, unit_minor: i64, parent_line_id: u32) -> Result<(), String> {
-        // Validate parent exists
-        if parent_line_id == 0 || parent_line_id as usize > self.lines.len() {
-            return Err("Invalid parent line item ID".to_string());
+    fn add_child_line(&mut self, sku: Arc<str>, qty: i32, unit_minor: i64, parent_line_id: u32) -> Result<u32, KernelError> {
+        // Validate parent exists and is still live (voided lines keep qty 0)
+        match self.find_line(parent_line_id) {
+            None => return Err(KernelError::not_found("Invalid parent line item ID")),
+            Some(parent) if parent.qty == 0 => return Err(KernelError::ParentLineVoided),
+            Some(_) => {}
         }
         
-        self.lines.push(Line::new_with_parent(sku, qty, unit_minor, parent_line_id));
-        Ok(())
+        Ok(self.push_line(Line::new_with_parent(sku, qty, unit_minor, parent_line_id)))
     }
     
-    fn add_tender(&mut self, amount_minor: i64) {
-        self.tendered_minor += amount_minor;
-        if self.tendered_minor >= self.total_minor() {
-            self.state = TxState::Committed;
+    fn add_deposit_line(&mut self, sku: Arc<str>, qty: i32, unit_minor: i64, triggering_line_id: u32) -> Result<u32, KernelError> {
+        let triggering_line = self.find_line(triggering_line_id)
+            .ok_or_else(|| KernelError::not_found("Invalid triggering line item ID"))?;
+        
+        if triggering_line.kind != LineKind::Sale {
+            return Err(KernelError::validation("Deposits must be linked to a sale item"));
+        }
+        if triggering_line.qty == 0 {
+            return Err(KernelError::ParentLineVoided);
+        }
+        
+        Ok(self.push_line(Line::new_deposit(sku, qty, unit_minor, triggering_line_id)))
+    }
+    
+    fn add_deposit_return_line(&mut self, sku: Arc<str>, qty: i32, unit_minor: i64) -> u32 {
+        self.push_line(Line::new_deposit_return(sku, qty, unit_minor))
+    }
+    
+    fn add_adjustment_line(&mut self, sku: Arc<str>, amount_minor: i64, reason_code: String, approved_by: String) -> u32 {
+        self.push_line(Line::new_adjustment(sku, amount_minor, reason_code, approved_by))
+    }
+    
+    fn add_return_line(&mut self, line: Line) -> u32 {
+        self.push_line(line)
+    }
+    
+    // Amount refunded for returned merchandise (positive)
+    fn refund_minor(&self) -> i64 {
+        self.lines.iter()
+            .filter(|l| l.kind == LineKind::Return)
+            .map(|l| -l.total_minor())
+            .sum()
+    }
+    
+    // Total of lines eligible for discounting (excludes deposits, deposit refunds and returns)
+    #[allow(dead_code)] // TODO: Will be used when discount support is added to the kernel
+    fn discountable_total_minor(&self) -> i64 {
+        self.lines.iter()
+            .filter(|l| l.kind.is_discountable())
+            .map(|l| l.total_minor())
+            .sum()
+    }
+    
+    // REGULATORY COMPLIANCE: Deposits charged and deposits refunded are reported separately
+    fn deposit_totals(&self) -> (i64, i64) {
+        let mut charged = 0;
+        let mut refunded = 0;
+        for line in &self.lines {
+            match line.kind {
+                LineKind::Deposit => charged += line.total_minor(),
+                LineKind::DepositReturn => refunded += -line.total_minor(),
+                LineKind::Sale | LineKind::Adjustment | LineKind::Return => {}
+            }
         }
+        (charged, refunded)
+    }
+    
+    // Rejects a tender beyond the configured limit, or one that would overflow the amount
+    // tendered; the detail is left in the thread's last error
+    fn check_new_tender(&self, max_tender_minor: u64, amount_minor: i64) -> Result<(), KernelError> {
+        let detail = if amount_minor.unsigned_abs() > max_tender_minor {
+            format!("Tender amount {} exceeds the limit of {}", amount_minor, max_tender_minor)
+        } else if self.tendered_minor.checked_add(amount_minor).is_none() {
+            "Tender would overflow the amount tendered".to_string()
+        } else {
+            return Ok(());
+        };
+        last_error::set(detail);
+        Err(KernelError::validation(OUT_OF_BOUNDS))
+    }
+    
+    fn add_tender(&mut self, tender: Tender) {
+        self.tendered_minor += tender.amount_minor;
+        self.tenders.push(tender);
+    }
+    
+    // A net refund (negative total) is settled once the full refund has been paid out
+    fn fully_tendered(&self) -> bool {
+        let total_minor = self.total_minor();
+        (total_minor >= 0 && self.tendered_minor >= total_minor) || (total_minor < 0 && self.tendered_minor <= total_minor)
+    }
+    
+    // Voided lines keep their place with a zero quantity
+    fn has_live_lines(&self) -> bool {
+        self.lines.iter().any(|l| l.qty != 0)
+    }
+    
+    // TAB: The card authorization held against an open tab, if any
+    fn open_tab_authorization(&self) -> Option<&CardAuthorization> {
+        self.tenders.iter()
+            .filter_map(|t| t.card.as_ref())
+            .find(|c| c.state == CardAuthState::PreAuthorized)
+    }
+    
+    fn open_tab_authorization_mut(&mut self) -> Option<&mut Tender> {
+        self.tenders.iter_mut()
+            .find(|t| t.card.as_ref().is_some_and(|c| c.state == CardAuthState::PreAuthorized))
+    }
+    
+    fn points_tendered(&self) -> i64 {
+        self.tenders.iter().filter_map(|t| t.points).sum()
     }
     
     fn line_count(&self) -> u32 {
         self.lines.len() as u32
     }
     
-    // NRF COMPLIANCE: Find all child items recursively for void cascade
-    fn find_all_children(&self, parent_line_number: u32) -> Vec<u32> {
-        let mut children = Vec::new();
+    // NRF COMPLIANCE: Find all child items for void cascade (returns stable line IDs), depth-first
+    // with each child followed by its own descendants. Lines already visited are not walked
+    // again, so a cyclic hierarchy cannot loop without end.
+    fn find_all_children(&self, parent_line_id: u32) -> Vec<u32> {
+        let direct_children = |parent| self.lines.iter()
+            .rev()
+            .filter(move |l| l.get_parent_line_item_id() == Some(parent))
+            .map(|l| l.line_id);
         
-        // Find direct children
-        for (index, line) in self.lines.iter().enumerate() {
-            if let Some(parent_id) = line.get_parent_line_item_id() {
-                if parent_id == parent_line_number {
-                    let child_line_number = (index + 1) as u32; // 1-based line numbers
-                    children.push(child_line_number);
-                    
-                    // Recursively find grandchildren
-                    let grandchildren = self.find_all_children(child_line_number);
-                    children.extend(grandchildren);
-                }
+        let mut children = Vec::new();
+        let mut visited = HashSet::from([parent_line_id]);
+        let mut pending: Vec<u32> = direct_children(parent_line_id).collect();
+        while let Some(line_id) = pending.pop() {
+            if !visited.insert(line_id) {
+                continue;
             }
+            children.push(line_id);
+            pending.extend(direct_children(line_id));
         }
         
         children
     }
     
-    // NRF COMPLIANCE: Get parent line item ID for a given line
-    fn get_line_parent_id(&self, line_index: u32) -> Option<u32> {
-        if line_index == 0 || line_index as usize > self.lines.len() {
-            return None;
+    // NRF COMPLIANCE: Number of ancestors above a line; None when the line is missing or its
+    // parent chain is broken or loops back on itself
+    fn line_depth(&self, line_id: u32) -> Option<usize> {
+        let mut depth = 0;
+        let mut line = self.find_line(line_id)?;
+        while let Some(parent_id) = line.parent_line_item_id {
+            depth += 1;
+            if depth > self.lines.len() {
+                return None;
+            }
+            line = self.find_line(parent_id)?;
         }
-        
-        self.lines[(line_index - 1) as usize].get_parent_line_item_id()
+        Some(depth)
     }
     
-    // NRF COMPLIANCE: Void a single line item (used by cascade)
-    fn void_single_line_item(&mut self, line_number: u32, _reason: &str) -> Result<(), String> {
-        if line_number == 0 || line_number as usize > self.lines.len() {
-            return Err("Invalid line number".to_string());
+    // Lines the kernel adds always reference an existing earlier line; lines loaded from
+    // outside the kernel are checked for dangling and cyclic parent references
+    fn check_hierarchy(&self) -> Result<(), String> {
+        match self.lines.iter().find(|l| self.line_depth(l.line_id).is_none()) {
+            Some(line) => Err(format!("Line {} of transaction {} has a missing or cyclic parent chain", line.line_id, self.id)),
+            None => Ok(()),
         }
+    }
+    
+    // NRF COMPLIANCE: Get parent line item ID for a given line
+    fn get_line_parent_id(&self, line_id: u32) -> Option<u32> {
+        self.find_line(line_id)?.get_parent_line_item_id()
+    }
+    
+    // NRF COMPLIANCE: A line and all of its descendants move and void as one group
+    fn line_group(&self, line_id: u32) -> Vec<u32> {
+        let mut group = vec![line_id];
+        group.extend(self.find_all_children(line_id));
+        group
+    }
+    
+    // Removes the given lines, preserving their order
+    fn take_lines(&mut self, line_ids: &HashSet<u32>) -> LineItems {
+        let (taken, kept) = std::mem::take(&mut self.lines)
+            .into_iter()
+            .partition(|l| line_ids.contains(&l.line_id));
+        self.lines = kept;
+        taken
+    }
+    
+    // NRF COMPLIANCE: Void a single line item (used by cascade)
+    fn void_single_line_item(&mut self, line_id: u32, _reason: &str) -> Result<(), KernelError> {
+        let line = self.find_line_mut(line_id)
+            .ok_or_else(|| KernelError::not_found("Invalid line ID"))?;
         
-        let line_index = (line_number - 1) as usize;
         // For now, just mark as voided (would need voided flag in real implementation)
         // TODO: Add voided flag to Line struct for proper void tracking
         // TODO: Use _reason parameter for audit logging when void tracking is implemented
-        self.lines[line_index].qty = 0; // Simple void implementation
+        line.qty = 0; // Simple void implementation
+        
+        Ok(())
+    }
+}
+
+// === SYSTEM CONFIGURATION ===
+
+#[derive(Debug)]
+pub struct SystemConfig {
+    max_transaction_attributes: usize,
+    max_attribute_key_len: usize,
+    max_attribute_value_len: usize,
+    max_customer_token_len: usize,
+    max_order_reference_len: usize,
+    // Checked when a transaction or quote begins; currency codes are always three letters
+    max_store_name_len: usize,
+    max_currency_decimal_places: u8,
+    max_tender_token_len: usize,
+    max_pending_events: usize,
+    // Per terminal; zero leaves customer display updates to registered sinks
+    max_pending_display_updates: usize,
+    // Attempts at a print job before a transient printer failure marks it failed
+    print_retry_limit: u32,
+    // Printed or failed jobs kept per printer for status reports
+    print_job_history: usize,
+    // Attempts at an ERP push before a transient failure dead-letters the order
+    erp_retry_limit: u32,
+    // Dead-lettered ERP orders kept per connector for inspection and requeueing
+    erp_dead_letter_limit: usize,
+    // Business dates start at this local hour, `business_day_utc_offset_minutes` from UTC
+    business_day_start_hour: u32,
+    business_day_utc_offset_minutes: i32,
+    // Business dates of hourly sales kept for dashboards
+    sales_aggregation_days: u32,
+    // Command message IDs whose replies are kept, so a redelivered message is not run again
+    command_idempotency_window: usize,
+    // The audit log file is rotated at this size, keeping `audit_log_files` rotated files;
+    // zero never rotates
+    audit_log_max_bytes: u64,
+    audit_log_files: usize,
+    // Opt-in: committed sales are summarized for the registered analytics sinks
+    basket_analytics_enabled: bool,
+    // Committed transactions forwarded upstream per sync batch
+    sync_batch_size: usize,
+    // After a failed sync, commits wait this long before trying the upstream again
+    sync_retry_interval_secs: u64,
+    // Receipts the central sync ledger remembers for duplicate detection, oldest forgotten first
+    sync_ledger_receipts: usize,
+    // A peripheral not reported on for this long counts as offline; 0 keeps reports indefinitely
+    peripheral_status_ttl_secs: u64,
+    // Line bounds, checked before a line is added; the defaults keep any transaction total far
+    // inside i64. Voided lines still count toward the line limit.
+    max_line_qty: u32,
+    max_unit_minor: u64,
+    max_lines_per_transaction: usize,
+    max_children_per_line: usize,
+    // Most levels of children below a top-level line
+    max_line_depth: usize,
+    // Largest single tender (or refund payout); keeps the amount tendered far inside i64
+    max_tender_minor: u64,
+    // COMPATIBILITY: A fully tendered sale stays open for adjustments until
+    // pk_finalize_transaction commits it; when on, the tender that pays it in full commits it,
+    // as earlier releases did
+    commit_on_full_tender: bool,
+    // Number of independently locked partitions of the active transaction table
+    transaction_shards: usize,
+    // LOYALTY: `points_rate_points` points are worth `points_rate_minor` minor units
+    points_rate_points: i64,
+    points_rate_minor: i64,
+    max_points_per_transaction: i64, // 0 = no cap
+    layaway_min_deposit_percent: i64,
+    // TAB: Capture (total plus tip) may exceed the authorized amount by this percentage
+    tab_capture_tolerance_percent: i64,
+    // REGULATORY COMPLIANCE: How tagged personal data is written to the journal and logs
+    pii_operator_ids: pii::PiiMode,
+    pii_customer_refs: pii::PiiMode,
+    pii_attribute_values: pii::PiiMode,
+    // SECURITY: Void and price-override alert thresholds per operator/terminal; 0 disables
+    anomaly_window_secs: i64,
+    anomaly_max_voids: usize,
+    anomaly_max_overrides: usize,
+    // SECURITY: Refund velocity limits; 0 disables an amount or count limit
+    refund_max_per_transaction_minor: i64,
+    refund_max_count_per_shift: u32,
+    refund_max_amount_per_shift_minor: i64,
+    refund_require_receipt: bool,
+    // Reports reuse an archive snapshot for up to this many seconds; 0 takes one per query
+    report_snapshot_interval_secs: u64,
+    // Archived transactions kept in memory, in estimated bytes; beyond it the least recently
+    // used are evicted to the persistence backend. 0 = unlimited. Transactions still in the
+    // active table (open, or settled and awaiting archival) are not counted; they are bounded
+    // by the active-transaction caps instead
+    memory_budget_bytes: usize,
+    // Longest an FFI call waits for a store or transaction lock before returning TimedOut;
+    // 0 waits indefinitely
+    lock_timeout_ms: u64,
+    // AUDIT COMPLIANCE: Journal syncs wait this long for more commits to share them; 0 syncs
+    // at once (commits arriving during a sync still share the next one)
+    journal_group_commit_micros: u64,
+    // Background workers for archival, eviction and receipt delivery; with 0, or a full queue,
+    // jobs wait in the pipeline's overflow queue and never run on the request path
+    persistence_workers: usize,
+    persistence_queue_capacity: usize,
+    // Blank transactions kept allocated for new sales; 0 allocates each one on begin
+    transaction_pool_size: usize,
+    // BACKPRESSURE: Most sales open in the building state at once per store and per terminal;
+    // 0 = unlimited. Settled sales, layaways and quotes do not count.
+    max_active_per_store: usize,
+    max_active_per_terminal: usize,
+    // CATALOG: How lines added by SKU are checked against a registered product catalog
+    catalog_validation: catalog::CatalogValidation,
+    // PRICING: Prices resolved for priced lines are reused for this long (0 = not cached);
+    // past it the stale policy applies, for at most `price_max_stale_secs` (0 = no limit)
+    price_cache_ttl_secs: u64,
+    price_max_stale_secs: u64,
+    price_stale_policy: pricing::StalePricePolicy,
+    // INVENTORY: Whether a line is added unreserved or rejected when its stock is short, or
+    // when the inventory provider cannot be reached
+    inventory_on_shortage: inventory::InventoryFailurePolicy,
+    inventory_on_unavailable: inventory::InventoryFailurePolicy,
+    // TAX: Breakdowns from the tax provider are reused for an unchanged basket for this long;
+    // 0 calls the provider every time
+    tax_cache_ttl_secs: u64,
+}
+
+impl Default for SystemConfig {
+    fn default() -> Self {
+        SystemConfig {
+            max_transaction_attributes: 32,
+            max_attribute_key_len: 64,
+            max_attribute_value_len: 1024,
+            max_customer_token_len: 128,
+            max_order_reference_len: 64,
+            max_store_name_len: 64,
+            max_currency_decimal_places: 4,
+            max_tender_token_len: 64,
+            max_pending_events: 1024,
+            max_pending_display_updates: 256,
+            print_retry_limit: 5,
+            print_job_history: 32,
+            erp_retry_limit: 5,
+            erp_dead_letter_limit: 256,
+            business_day_start_hour: 0,
+            business_day_utc_offset_minutes: 0,
+            sales_aggregation_days: 90,
+            command_idempotency_window: 10_000,
+            audit_log_max_bytes: 16 * 1024 * 1024,
+            audit_log_files: 10,
+            basket_analytics_enabled: false,
+            sync_batch_size: 100,
+            sync_retry_interval_secs: 30,
+            sync_ledger_receipts: 100_000,
+            peripheral_status_ttl_secs: 0,
+            max_line_qty: 99_999,
+            max_unit_minor: 10_000_000_000,
+            max_lines_per_transaction: 1000,
+            max_children_per_line: 100,
+            max_line_depth: 8,
+            max_tender_minor: 10_000_000_000,
+            commit_on_full_tender: false,
+            transaction_shards: 16,
+            points_rate_points: 100,
+            points_rate_minor: 100,
+            max_points_per_transaction: 0,
+            layaway_min_deposit_percent: 10,
+            tab_capture_tolerance_percent: 20,
+            pii_operator_ids: pii::PiiMode::Plain,
+            pii_customer_refs: pii::PiiMode::Plain,
+            pii_attribute_values: pii::PiiMode::Plain,
+            anomaly_window_secs: 3600,
+            anomaly_max_voids: 0,
+            anomaly_max_overrides: 0,
+            refund_max_per_transaction_minor: 0,
+            refund_max_count_per_shift: 0,
+            refund_max_amount_per_shift_minor: 0,
+            refund_require_receipt: false,
+            report_snapshot_interval_secs: 0,
+            memory_budget_bytes: 0,
+            lock_timeout_ms: lock::DEFAULT_TIMEOUT_MS,
+            journal_group_commit_micros: 0,
+            persistence_workers: pipeline::DEFAULT_WORKERS,
+            persistence_queue_capacity: pipeline::DEFAULT_QUEUE_CAPACITY,
+            transaction_pool_size: pool::DEFAULT_SIZE,
+            max_active_per_store: 0,
+            max_active_per_terminal: 0,
+            catalog_validation: catalog::CatalogValidation::Price,
+            price_cache_ttl_secs: 300,
+            price_max_stale_secs: 86_400,
+            price_stale_policy: pricing::StalePricePolicy::Serve,
+            inventory_on_shortage: inventory::InventoryFailurePolicy::Allow,
+            inventory_on_unavailable: inventory::InventoryFailurePolicy::Allow,
+            tax_cache_ttl_secs: 300,
+        }
+    }
+}
+
+impl SystemConfig {
+    fn business_day(&self) -> sales::BusinessDay {
+        sales::BusinessDay { utc_offset_minutes: self.business_day_utc_offset_minutes, start_hour: self.business_day_start_hour }
+    }
+    
+    // Applies a single named setting; values arrive as text from user space
+    fn set(&mut self, key: &str, value: &str) -> Result<(), KernelError> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, KernelError> {
+            value.trim().parse::<T>()
+                .map_err(|_| KernelError::Validation(format!("Invalid value '{}' for setting '{}'", value, key)))
+        }
         
+        match key {
+            "max_transaction_attributes" => self.max_transaction_attributes = parse(key, value)?,
+            "max_attribute_key_len" => self.max_attribute_key_len = parse(key, value)?,
+            "max_attribute_value_len" => self.max_attribute_value_len = parse(key, value)?,
+            "max_customer_token_len" => self.max_customer_token_len = parse(key, value)?,
+            "max_order_reference_len" => self.max_order_reference_len = parse(key, value)?,
+            "max_store_name_len" => self.max_store_name_len = parse(key, value)?,
+            "max_currency_decimal_places" => match parse(key, value)? {
+                places @ 0..=MAX_CURRENCY_DECIMAL_PLACES => self.max_currency_decimal_places = places,
+                _ => return Err(KernelError::Validation(format!("Setting 'max_currency_decimal_places' must be 0 to {}", MAX_CURRENCY_DECIMAL_PLACES))),
+            },
+            "max_tender_token_len" => self.max_tender_token_len = parse(key, value)?,
+            "max_pending_events" => self.max_pending_events = parse(key, value)?,
+            "max_pending_display_updates" => self.max_pending_display_updates = parse(key, value)?,
+            "print_retry_limit" => self.print_retry_limit = parse(key, value)?,
+            "print_job_history" => self.print_job_history = parse(key, value)?,
+            "erp_retry_limit" => self.erp_retry_limit = parse(key, value)?,
+            "erp_dead_letter_limit" => self.erp_dead_letter_limit = parse(key, value)?,
+            "business_day_start_hour" => match parse(key, value)? {
+                hour @ 0..=23 => self.business_day_start_hour = hour,
+                _ => return Err(KernelError::validation("Setting 'business_day_start_hour' must be 0 to 23")),
+            },
+            "business_day_utc_offset_minutes" => match parse(key, value)? {
+                offset @ -1440..=1440 => self.business_day_utc_offset_minutes = offset,
+                _ => return Err(KernelError::validation("Setting 'business_day_utc_offset_minutes' must be within a day")),
+            },
+            "sales_aggregation_days" => match parse(key, value)? {
+                0 => return Err(KernelError::validation("Setting 'sales_aggregation_days' must be at least 1")),
+                days => self.sales_aggregation_days = days,
+            },
+            "command_idempotency_window" => self.command_idempotency_window = parse(key, value)?,
+            "audit_log_max_bytes" => self.audit_log_max_bytes = parse(key, value)?,
+            "audit_log_files" => match parse(key, value)? {
+                0 => return Err(KernelError::validation("Setting 'audit_log_files' must be at least 1")),
+                files => self.audit_log_files = files,
+            },
+            "basket_analytics_enabled" => self.basket_analytics_enabled = parse(key, value)?,
+            "sync_batch_size" => self.sync_batch_size = parse(key, value)?,
+            "sync_retry_interval_secs" => self.sync_retry_interval_secs = parse(key, value)?,
+            "sync_ledger_receipts" => match parse(key, value)? {
+                0 => return Err(KernelError::validation("Setting 'sync_ledger_receipts' must be at least 1")),
+                receipts => self.sync_ledger_receipts = receipts,
+            },
+            "peripheral_status_ttl_secs" => self.peripheral_status_ttl_secs = parse(key, value)?,
+            "max_line_qty" => self.max_line_qty = parse(key, value)?,
+            "max_unit_minor" => self.max_unit_minor = parse(key, value)?,
+            "max_lines_per_transaction" => self.max_lines_per_transaction = parse(key, value)?,
+            "max_children_per_line" => self.max_children_per_line = parse(key, value)?,
+            "max_line_depth" => self.max_line_depth = parse(key, value)?,
+            "max_tender_minor" => self.max_tender_minor = parse(key, value)?,
+            "commit_on_full_tender" => self.commit_on_full_tender = parse(key, value)?,
+            "transaction_shards" => match parse(key, value)? {
+                0 => return Err(KernelError::validation("Setting 'transaction_shards' must be at least 1")),
+                shards => self.transaction_shards = shards,
+            },
+            "points_rate_points" => self.points_rate_points = parse(key, value)?,
+            "points_rate_minor" => self.points_rate_minor = parse(key, value)?,
+            "max_points_per_transaction" => self.max_points_per_transaction = parse(key, value)?,
+            "layaway_min_deposit_percent" => match parse(key, value)? {
+                percent @ 0..=100 => self.layaway_min_deposit_percent = percent,
+                _ => return Err(KernelError::validation("Setting 'layaway_min_deposit_percent' must be 0 to 100")),
+            },
+            "tab_capture_tolerance_percent" => match parse(key, value)? {
+                percent @ 0..=1000 => self.tab_capture_tolerance_percent = percent,
+                _ => return Err(KernelError::validation("Setting 'tab_capture_tolerance_percent' must be 0 to 1000")),
+            },
+            "pii_operator_ids" => self.pii_operator_ids = parse(key, value)?,
+            "pii_customer_refs" => self.pii_customer_refs = parse(key, value)?,
+            "pii_attribute_values" => self.pii_attribute_values = parse(key, value)?,
+            "anomaly_window_secs" => self.anomaly_window_secs = parse(key, value)?,
+            "anomaly_max_voids" => self.anomaly_max_voids = parse(key, value)?,
+            "anomaly_max_overrides" => self.anomaly_max_overrides = parse(key, value)?,
+            "refund_max_per_transaction_minor" => self.refund_max_per_transaction_minor = parse(key, value)?,
+            "refund_max_count_per_shift" => self.refund_max_count_per_shift = parse(key, value)?,
+            "refund_max_amount_per_shift_minor" => self.refund_max_amount_per_shift_minor = parse(key, value)?,
+            "refund_require_receipt" => self.refund_require_receipt = parse(key, value)?,
+            "report_snapshot_interval_secs" => self.report_snapshot_interval_secs = parse(key, value)?,
+            "memory_budget_bytes" => self.memory_budget_bytes = parse(key, value)?,
+            "lock_timeout_ms" => self.lock_timeout_ms = parse(key, value)?,
+            "journal_group_commit_micros" => self.journal_group_commit_micros = parse(key, value)?,
+            "persistence_workers" => self.persistence_workers = parse(key, value)?,
+            "persistence_queue_capacity" => match parse(key, value)? {
+                0 => return Err(KernelError::validation("Setting 'persistence_queue_capacity' must be at least 1")),
+                capacity => self.persistence_queue_capacity = capacity,
+            },
+            "transaction_pool_size" => self.transaction_pool_size = parse(key, value)?,
+            "max_active_per_store" => self.max_active_per_store = parse(key, value)?,
+            "max_active_per_terminal" => self.max_active_per_terminal = parse(key, value)?,
+            "catalog_validation" => self.catalog_validation = parse(key, value)?,
+            "price_cache_ttl_secs" => self.price_cache_ttl_secs = parse(key, value)?,
+            "price_max_stale_secs" => self.price_max_stale_secs = parse(key, value)?,
+            "price_stale_policy" => self.price_stale_policy = parse(key, value)?,
+            "inventory_on_shortage" => self.inventory_on_shortage = parse(key, value)?,
+            "inventory_on_unavailable" => self.inventory_on_unavailable = parse(key, value)?,
+            "tax_cache_ttl_secs" => self.tax_cache_ttl_secs = parse(key, value)?,
+            _ => return Err(KernelError::Validation(format!("Unknown setting '{}'", key))),
+        }
         Ok(())
     }
+    
+    fn price_policy(&self) -> pricing::PricePolicy {
+        pricing::PricePolicy {
+            ttl_secs: self.price_cache_ttl_secs,
+            max_stale_secs: self.price_max_stale_secs,
+            stale: self.price_stale_policy,
+        }
+    }
+    
+    fn line_bounds(&self) -> LineBounds {
+        LineBounds {
+            max_qty: self.max_line_qty,
+            max_unit_minor: self.max_unit_minor,
+            max_lines: self.max_lines_per_transaction,
+            max_children: self.max_children_per_line,
+            max_depth: self.max_line_depth,
+        }
+    }
 }
 
 // === KERNEL STORE ===
 
+const TRANSACTION_NOT_FOUND: &str = "Transaction not found";
+const NOT_BUILDING: &str = "Transaction not in building state";
+const OUT_OF_BOUNDS: &str = "Value out of bounds";
+const INVALID_PARAMETER: &str = "Invalid parameter";
+const LINES_HELD: &str = "Lines await security confirmation";
+
+#[derive(Debug, Serialize)]
+struct StoreStats {
+    active_transactions: usize,
+    archived_transactions: usize,
+    transaction_shards: usize,
+    transactions_per_shard: Vec<usize>,
+    pending_events: usize,
+    dropped_events: u64,
+    interned_skus: usize,
+    resident_archive_bytes: usize,
+    evicted_transactions: usize,
+    persistence_workers: usize,
+    pending_persistence_jobs: usize,
+    transaction_pool: pool::PoolStats,
+    cached_prices: usize,
+    cached_tax_baskets: usize,
+    poison_recoveries: u64,
+}
+
 pub struct LegalKernelStore {
     next_tx_id: AtomicU64,
-    active_transactions: HashMap<u64, Transaction>,
+    next_quote_id: AtomicU64,
+    saved_quotes: HashMap<String, u64>,
+    // OMNICHANNEL: Imported online orders by pickup code
+    pickup_orders: HashMap<String, u64>,
+    active_transactions: shard::TransactionShards,
+    // AUDIT COMPLIANCE: Committed transactions are sealed here and never mutated
+    archive: archive::TransactionArchive,
+    report_snapshot: Mutex<Option<archive::ArchiveSnapshot>>,
+    operator_permissions: HashMap<String, HashSet<Permission>>,
+    system_config: SystemConfig,
+    journal: Arc<Journal>,
+    skus: intern::SkuPool,
+    events: EventBus,
+    display: peripheral::display::DisplayFeed,
+    price_provider: Option<Arc<dyn PriceProvider>>,
+    price_cache: Arc<pricing::PriceCache>,
+    product_provider: Option<Box<dyn catalog::ProductProvider>>,
+    inventory_provider: Option<Box<dyn inventory::InventoryProvider>>,
+    tax_provider: Option<Box<dyn tax::TaxProvider>>,
+    tax_cache: tax::TaxCache,
+    tax_fallback_rates: Vec<tax::FallbackRate>,
+    receipt_deliverers: Vec<Arc<dyn ReceiptDeliverer>>,
+    // Loaded from a plugin; Rust integrators pass their provider to the loyalty calls instead
+    loyalty_provider: Option<Arc<dyn loyalty::LoyaltyProvider>>,
+    plugins: Vec<plugin::PluginInfo>,
+    // RULES: Store-specific policies run at the hook points
+    rules: Option<Arc<rules::Script>>,
+    drawer_driver: Option<Arc<dyn peripheral::DrawerDriver>>,
+    recyclers: HashMap<String, Arc<dyn peripheral::CashRecycler>>,
+    peripherals: peripheral::status::PeripheralRegistry,
+    kitchen_router: Option<Box<dyn peripheral::KitchenRouter>>,
+    kitchen_sinks: Vec<Arc<dyn peripheral::KitchenSink>>,
+    analytics_sinks: Vec<Arc<dyn analytics::AnalyticsSink>>,
+    line_security: Option<Box<dyn security::LineSecurity>>,
+    spooler: Arc<peripheral::printer::Spooler>,
+    erp_outbox: Arc<erp::ErpOutbox>,
+    // SYNC: Forwards this terminal's commits to the central service
+    sync_forwarder: Option<Arc<sync::Forwarder>>,
+    // SYNC: Batches received from terminals, when this kernel is the central service
+    sync_ledger: Mutex<sync::SyncLedger>,
+    replication: replication::Replication,
+    backup_scheduler: backup::BackupScheduler,
+    // Transaction commands received as messages, from the NATS consumer or in process
+    commands: Arc<command::CommandProcessor>,
+    nats: nats::NatsConsumer,
+    audit: Arc<audit::AuditLog>,
+    // Hourly sales, added to on every commit
+    sales: sales::SalesAggregates,
+    pipeline: pipeline::Pipeline,
+    // An eviction job is queued and has not started yet
+    eviction_queued: AtomicBool,
+    transaction_pool: Mutex<pool::TransactionPool>,
+    // BACKPRESSURE: Serializes begins while a store cap is enforced
+    admission: Mutex<()>,
+    // SECURITY: HMAC key for signed kernel records; supplied by user space, never exported
+    signing_key: Option<Vec<u8>>,
+    receipt_layout: receipt::ReceiptLayout,
+    receipt_templates: HashMap<String, template::Template>,
+    fiscal_qr_provider: Option<Box<dyn fiscal::FiscalQrProvider>>,
+    tse: Option<fiscal::tse::TseRegistration>,
+    fiscal_device: Option<Arc<dyn fiscal::FiscalDevice>>,
+    fiscal_day: Option<fiscal::FiscalDay>,
+    account_mapping: Option<accounting::AccountMapping>,
+    last_fiscal_day_report: Option<fiscal::FiscalDayReport>,
+    anomalies: Mutex<anomaly::AnomalyTracker>,
+    refunds: refund::RefundTracker,
+    drawer_sessions: cash::DrawerSessions,
+    // RESILIENCE: Times the store lock was found poisoned and recovered
+    poison_recoveries: u64,
 }
 
 impl LegalKernelStore {
-    fn new() -> Self {
+    fn new(journal: Journal) -> Self {
+        let audit = Arc::new(audit::AuditLog::in_memory(
+            SystemConfig::default().audit_log_max_bytes,
+            SystemConfig::default().audit_log_files,
+        ));
+        journal.set_audit_log(Arc::clone(&audit));
         Self {
             next_tx_id: AtomicU64::new(1),
-            active_transactions: HashMap::new(),
+            next_quote_id: AtomicU64::new(1),
+            saved_quotes: HashMap::new(),
+            pickup_orders: HashMap::new(),
+            active_transactions: shard::TransactionShards::new(SystemConfig::default().transaction_shards),
+            archive: archive::TransactionArchive::default(),
+            report_snapshot: Mutex::new(None),
+            operator_permissions: HashMap::new(),
+            system_config: SystemConfig::default(),
+            journal: Arc::new(journal),
+            skus: intern::SkuPool::default(),
+            events: EventBus::new(SystemConfig::default().max_pending_events),
+            display: peripheral::display::DisplayFeed::new(SystemConfig::default().max_pending_display_updates),
+            price_provider: None,
+            price_cache: Arc::default(),
+            product_provider: None,
+            inventory_provider: None,
+            tax_provider: None,
+            tax_cache: tax::TaxCache::default(),
+            tax_fallback_rates: Vec::new(),
+            receipt_deliverers: Vec::new(),
+            loyalty_provider: None,
+            plugins: Vec::new(),
+            rules: None,
+            drawer_driver: None,
+            recyclers: HashMap::new(),
+            peripherals: peripheral::status::PeripheralRegistry::default(),
+            kitchen_router: None,
+            kitchen_sinks: Vec::new(),
+            analytics_sinks: Vec::new(),
+            line_security: None,
+            spooler: Arc::new(peripheral::printer::Spooler::new(
+                SystemConfig::default().print_retry_limit,
+                SystemConfig::default().print_job_history,
+            )),
+            erp_outbox: Arc::new(erp::ErpOutbox::new(
+                SystemConfig::default().erp_retry_limit,
+                SystemConfig::default().erp_dead_letter_limit,
+            )),
+            sync_forwarder: None,
+            sync_ledger: Mutex::new(sync::SyncLedger::default()),
+            replication: replication::Replication::default(),
+            backup_scheduler: backup::BackupScheduler::default(),
+            commands: Arc::new(command::CommandProcessor::new(SystemConfig::default().command_idempotency_window)),
+            nats: nats::NatsConsumer::default(),
+            audit,
+            sales: sales::SalesAggregates::new(SystemConfig::default().business_day(), SystemConfig::default().sales_aggregation_days),
+            pipeline: pipeline::Pipeline::start(
+                SystemConfig::default().persistence_workers,
+                SystemConfig::default().persistence_queue_capacity,
+                run_persistence_job,
+            ),
+            eviction_queued: AtomicBool::new(false),
+            transaction_pool: Mutex::new(pool::TransactionPool::new(SystemConfig::default().transaction_pool_size)),
+            admission: Mutex::new(()),
+            signing_key: None,
+            receipt_layout: receipt::ReceiptLayout::default(),
+            receipt_templates: HashMap::new(),
+            fiscal_qr_provider: None,
+            tse: None,
+            fiscal_device: None,
+            fiscal_day: None,
+            account_mapping: None,
+            last_fiscal_day_report: None,
+            anomalies: Mutex::new(anomaly::AnomalyTracker::default()),
+            refunds: refund::RefundTracker::default(),
+            drawer_sessions: cash::DrawerSessions::default(),
+            poison_recoveries: 0,
         }
     }
     
-    fn begin_transaction_legal(&mut self, store: String, currency: Currency) -> Result<u64, String> {
-        let id = self.next_tx_id.fetch_add(1, Ordering::SeqCst);
-        let transaction = Transaction::new(id, store, currency);
-        self.active_transactions.insert(id, transaction);
-        Ok(id)
+    // Replaces the in-memory audit log, before any entry is journaled
+    fn set_audit_log(&mut self, audit: Arc<audit::AuditLog>) {
+        self.journal.set_audit_log(Arc::clone(&audit));
+        self.audit = audit;
     }
     
-    fn add_line_legal(&mut self, handle: u64, sku: String, qty: i32, unit_minor: i64) -> Result<(), String> {
-        let tx = self.active_transactions.get_mut(&handle)
-            .ok_or("Transaction not found")?;
-        
-        if tx.state != TxState::Building {
-            return Err("Transaction not in building state".to_string());
+    // Looks a transaction up in the active store, then in the archive
+    fn transaction(&self, handle: u64) -> Result<shard::TxRef<'_>, KernelError> {
+        if let Some(tx) = self.active_transactions.get(handle)? {
+            return Ok(shard::TxRef::Active(tx));
         }
-        
-        tx.add_line(sku, qty, unit_minor);
-        Ok(())
-    }
-    
-    // NRF COMPLIANCE: Add child line item with parent reference
-    fn add_child_line_legal(&mut self, handle: u64, sku: String, qty: i32, unit_minor: i64, parent_line_id: u32) -> Result<(), String> {
-        let tx = self.active_transactions.get_mut(&handle)
-            .ok_or("Transaction not found")?;
-        
-        if tx.state != TxState::Building {
-            return Err("Transaction not in building state".to_string());
+        match self.archive.get(handle).ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))? {
+            Ok(tx) => Ok(shard::TxRef::Archived(tx)),
+            Err(e) => {
+                eprintln!("WARNING: Could not reload archived transaction {}: {}", handle, e);
+                Err(KernelError::not_found(TRANSACTION_NOT_FOUND))
+            }
         }
-        
-        tx.add_child_line(sku, qty, unit_minor, parent_line_id)
     }
     
-    fn add_cash_tender_legal(&mut self, handle: u64, amount_minor: i64) -> Result<(), String> {
-        let tx = self.active_transactions.get_mut(&handle)
-            .ok_or("Transaction not found")?;
-        
-        if tx.state != TxState::Building {
-            return Err("Transaction not in building state".to_string());
+    fn set_config(&mut self, key: &str, value: &str) -> Result<(), KernelError> {
+        let pool = (self.system_config.persistence_workers, self.system_config.persistence_queue_capacity);
+        self.system_config.set(key, value)?;
+        if pool != (self.system_config.persistence_workers, self.system_config.persistence_queue_capacity) {
+            self.pipeline.restart(self.system_config.persistence_workers, self.system_config.persistence_queue_capacity);
         }
-        
-        tx.add_tender(amount_minor);
+        self.events.set_capacity(self.system_config.max_pending_events);
+        self.display.set_capacity(self.system_config.max_pending_display_updates);
+        self.spooler.set_limits(self.system_config.print_retry_limit, self.system_config.print_job_history);
+        self.erp_outbox.set_limits(self.system_config.erp_retry_limit, self.system_config.erp_dead_letter_limit);
+        self.commands.set_window(self.system_config.command_idempotency_window);
+        self.audit.set_rotation(self.system_config.audit_log_max_bytes, self.system_config.audit_log_files);
+        if self.sales.business_day() != self.system_config.business_day() {
+            self.rebuild_sales();
+        }
+        self.sales.set_retention(self.system_config.sales_aggregation_days);
+        self.pool().resize(self.system_config.transaction_pool_size);
+        self.refill_transaction_pool();
+        if self.system_config.transaction_shards != self.active_transactions.shard_count() {
+            self.active_transactions.reshard(self.system_config.transaction_shards);
+        }
+        self.discard_report_snapshot();
+        self.enforce_memory_budget();
+        lock::set_timeout_ms(self.system_config.lock_timeout_ms);
+        self.journal.set_group_commit_window(std::time::Duration::from_micros(self.system_config.journal_group_commit_micros));
+        pii::set_modes(
+            self.system_config.pii_operator_ids,
+            self.system_config.pii_customer_refs,
+            self.system_config.pii_attribute_values,
+        );
         Ok(())
     }
     
-    fn get_transaction_totals(&self, handle: u64) -> Result<(i64, i64, i64, u32), String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        
-        let state_code = match tx.state {
-            TxState::Building => 0,
-            TxState::Committed => 1,
-        };
-        
-        Ok((tx.total_minor(), tx.tendered_minor, tx.change_minor(), state_code))
+    // AUDIT COMPLIANCE: Recomputes the transaction's totals from its journal entries and compares
+    // them with the transaction and its cached totals
+    fn verify_transaction(&self, handle: u64) -> Result<verify::Verification, KernelError> {
+        let tx = self.transaction(handle)?;
+        let entries = self.journal.entries_for(handle);
+        Ok(verify::verify(&tx, &entries, totals::lookup(handle)))
     }
     
-    fn get_line_count_legal(&self, handle: u64) -> Result<u32, String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        Ok(tx.line_count())
+    // The journal and the transaction's begin entry, read under the store lock; its history is
+    // then read from the journal without the lock
+    fn history_source(&self, handle: u64) -> (Arc<Journal>, Option<u64>) {
+        let begin_sequence = self.transaction(handle).ok().and_then(|tx| tx.begin_sequence);
+        (Arc::clone(&self.journal), begin_sequence)
     }
     
-    fn get_currency_decimal_places(&self, handle: u64) -> Result<u8, String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        Ok(tx.currency.decimal_places())
+    // AUDIT COMPLIANCE: Store-wide self-check of the journal and active transactions; the
+    // archive is checked by the caller on the returned snapshot, without the store lock
+    fn verify_store(&self) -> (verify::StoreVerification, archive::ArchiveSnapshot) {
+        let next_handles = (self.next_tx_id.load(Ordering::SeqCst), self.next_quote_id.load(Ordering::SeqCst));
+        let verification = self.journal.with_entries(|entries| {
+            let mut check = verify::StoreCheck::new(entries, next_handles);
+            self.active_transactions.for_each(|tx| check.check_active(tx));
+            check.finish()
+        });
+        (verification, self.archive.snapshot())
     }
     
-    // ARCHITECTURAL FIX: Update get_line_item_details to return parent_line_item_id instead of preparation notes
-    fn get_line_item_details(&self, handle: u64, line_index: u32) -> Result<(String, i32, i64, Option<u32>), String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        
-        if line_index as usize >= tx.lines.len() {
-            return Err("Line index out of range".to_string());
+    // PERIPHERAL: Customer display updates built from the transaction as it is now
+    fn publish_display(&self, handle: u64, build: impl FnOnce(&Transaction) -> Vec<DisplayUpdateKind>) {
+        if !self.display.is_active() {
+            return;
+        }
+        if let Ok(Some(tx)) = self.active_transactions.get(handle) {
+            self.display.publish(&tx, build(&tx));
         }
-        
-        let line = &tx.lines[line_index as usize];
-        Ok((line.sku.clone(), line.qty, line.unit_minor, line.parent_line_item_id))
     }
     
-    // NRF COMPLIANCE: Get parent line item ID for a given line
-    fn get_line_parent_id(&self, handle: u64, line_number: u32) -> Result<Option<u32>, String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        
-        Ok(tx.get_line_parent_id(line_number))
+    fn next_display_update_json(&self, terminal_id: &str) -> Option<(u64, Result<String, KernelError>)> {
+        self.display.peek(terminal_id).map(|update| {
+            let json = serde_json::to_string(&update)
+                .map_err(|e| KernelError::Internal(format!("Failed to serialize display update: {}", e)));
+            (update.sequence_number, json)
+        })
     }
     
-    // NRF COMPLIANCE: Find all children of a line item (for void cascade)
-    fn find_line_children(&self, handle: u64, parent_line_number: u32) -> Result<Vec<u32>, String> {
-        let tx = self.active_transactions.get(&handle)
-            .ok_or("Transaction not found")?;
-        
-        Ok(tx.find_all_children(parent_line_number))
+    // The sequence number is read with the transaction locked. An update is published after
+    // its change is made, so every update up to that number is for a change the view shows.
+    fn cart_view_json(&self, terminal_id: &str) -> Result<String, KernelError> {
+        let (mut handle, _) = self.display.current(terminal_id).ok_or_else(|| KernelError::not_found("No cart for terminal"))?;
+        let view = loop {
+            let tx = self.transaction(handle)?;
+            let (current, sequence_number) = self.display.current(terminal_id).ok_or_else(|| KernelError::not_found("No cart for terminal"))?;
+            // A new transaction started on the terminal meanwhile
+            if current != handle {
+                handle = current;
+                continue;
+            }
+            break peripheral::display::CartView::new(&tx, terminal_id, sequence_number);
+        };
+        serde_json::to_string(&view)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize cart view: {}", e)))
     }
     
-    // ARCHITECTURAL COMPONENT: Voids a line item with NRF-compliant cascade to child items.
-    // Critical NRF requirement: When parent items are voided, all linked child items must be voided.
-    // 
-    // # Safety
-    // The caller must ensure that:
-    // - `handle` refers to a valid, active transaction
-    // - `line_number` is within the valid range of line items (1-based)
-    // - `reason_ptr` points to valid memory containing a UTF-8 encoded reason string
-    // - `reason_len` accurately represents the length of the data at `reason_ptr`
-    fn void_line_with_cascade(&mut self, handle: u64, line_number: u32, reason: &str) -> Result<(), String> {
-        let tx = self.active_transactions.get_mut(&handle)
-            .ok_or("Transaction not found")?;
+    fn journal_line_added(&self, handle: u64, line_id: u32) {
+        if let Ok(Some(tx)) = self.active_transactions.get(handle) {
+            self.journal_line(&tx, line_id);
+        }
+    }
+    
+    // AUDIT COMPLIANCE: Journal the line exactly as stored, including its assigned line ID.
+    // Takes the transaction already locked by the caller.
+    fn journal_line(&self, tx: &Transaction, line_id: u32) {
+        let handle = tx.id;
+        let line = match tx.find_line(line_id) {
+            Some(line) => line,
+            None => return,
+        };
         
-        if tx.state != TxState::Building {
-            return Err("Cannot void items in committed transaction".to_string());
+        self.events.publish(handle, EventKind::LineAdded {
+            line_id,
+            sku: line.sku.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            parent_line_id: line.parent_line_item_id,
+        });
+        if self.display.is_active() {
+            self.display.publish(tx, vec![DisplayUpdateKind::item_added(line), DisplayUpdateKind::subtotal(tx)]);
         }
         
-        // Find all child items recursively
-        let children = tx.find_all_children(line_number);
+        self.journal.record(handle, JournalOperation::LineAdd {
+            line_id,
+            sku: line.sku.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            parent_line_id: line.parent_line_item_id,
+            kind: line.kind,
+            reason_code: line.reason_code.clone(),
+            approved_by: line.approved_by.clone(),
+            product_name: line.product_name.clone(),
+            product_description: line.product_description.clone(),
+            original_receipt: line.original_receipt.clone(),
+        });
+        
+        // TAB: Ask user space for an incremental authorization once the tab outgrows its pre-auth
+        let tab_shortfall = tx.open_tab_authorization().and_then(|card| {
+            let total_minor = tx.total_minor();
+            (total_minor > card.authorized_minor).then_some((total_minor, card.authorized_minor))
+        });
+        if let Some((total_minor, authorized_minor)) = tab_shortfall {
+            self.events.publish(handle, EventKind::TabAuthorizationRequired { total_minor, authorized_minor });
+        }
+    }
+    
+    fn grant_permission(&mut self, operator_id: String, permission: Permission) {
+        self.operator_permissions.entry(operator_id).or_default().insert(permission);
+    }
+    
+    fn revoke_permission(&mut self, operator_id: &str, permission: Permission) {
+        if let Some(permissions) = self.operator_permissions.get_mut(operator_id) {
+            permissions.remove(&permission);
+        }
+    }
+    
+    fn has_permission(&self, operator_id: &str, permission: Permission) -> bool {
+        self.operator_permissions.get(operator_id)
+            .is_some_and(|permissions| permissions.contains(&permission))
+    }
+    
+    // Runs under the shared store lock: lanes begin sales without waiting on each other, and
+    // handles come from the atomic sequence
+    // Store name and currency of a new transaction or quote; the reason one is refused goes to
+    // the last error
+    fn check_begin_parameters(&self, store: &str, currency_code: &str, decimal_places: u8) -> Result<Currency, KernelError> {
+        let config = &self.system_config;
+        let detail = if store.len() > config.max_store_name_len {
+            format!("Store name is {} bytes (limit {})", store.len(), config.max_store_name_len)
+        } else if store.trim().is_empty() || store.chars().any(char::is_control) {
+            "Store name must be non-blank printable text".to_string()
+        } else if let Err(e) = pci::screen(TextField::StoreName, store) {
+            e.message().to_string()
+        } else if currency_code.len() != 3 || !currency_code.bytes().all(|b| b.is_ascii_alphabetic()) {
+            "Currency code must be three letters, such as USD".to_string()
+        } else if decimal_places > config.max_currency_decimal_places {
+            format!("Currency has {} decimal places (limit {})", decimal_places, config.max_currency_decimal_places)
+        } else {
+            return Currency::new(currency_code, decimal_places).map_err(KernelError::validation);
+        };
+        last_error::set(detail);
+        Err(KernelError::validation(INVALID_PARAMETER))
+    }
+    
+    fn begin_transaction_legal(&self, store: String, currency: Currency) -> Result<u64, KernelError> {
+        self.replication.check_writable()?;
+        let _admission = (self.system_config.max_active_per_store > 0)
+            .then(|| self.admission.lock().unwrap_or_else(PoisonError::into_inner));
+        self.check_capacity(Some(&store), None, 1)?;
+        let id = self.next_tx_id.fetch_add(1, Ordering::SeqCst);
+        self.open_transaction(id, TransactionKind::Sale, store, currency)
+    }
+    
+    // QUOTE: Quotes draw from their own sequence so they never consume transaction numbers
+    fn begin_quote(&self, store: String, currency: Currency) -> Result<u64, KernelError> {
+        self.replication.check_writable()?;
+        let id = PK_QUOTE_HANDLE_FLAG | self.next_quote_id.fetch_add(1, Ordering::SeqCst);
+        self.open_transaction(id, TransactionKind::Quote, store, currency)
+    }
+    
+    // BACKPRESSURE: Refuses to open `additional` sales beyond the store's or the terminal's
+    // cap, so a runaway client cannot grow the active table without bound. Pickup orders
+    // waiting for their customer are not counted.
+    fn check_capacity(&self, store: Option<&str>, terminal_id: Option<&str>, additional: usize) -> Result<(), KernelError> {
+        fn exceeded(scope: &str, id: &str, open: usize, limit: usize) -> KernelError {
+            last_error::set(format!("{} '{}' has {} open sales (limit {})", scope, id, open, limit));
+            KernelError::CapacityExceeded
+        }
+        
+        let config = &self.system_config;
+        let open_sales = |belongs: &dyn Fn(&Transaction) -> bool| self.active_transactions.count_where(|tx| {
+            tx.kind == TransactionKind::Sale && tx.state == TxState::Building
+                && !tx.pickup.as_ref().is_some_and(pickup::PickupOrder::is_waiting) && belongs(tx)
+        });
+        
+        if let Some(store) = store.filter(|_| config.max_active_per_store > 0) {
+            let open = open_sales(&|tx| tx.store == store);
+            if open + additional > config.max_active_per_store {
+                return Err(exceeded("Store", store, open, config.max_active_per_store));
+            }
+        }
+        if let Some(terminal_id) = terminal_id.filter(|_| config.max_active_per_terminal > 0) {
+            let open = open_sales(&|tx| tx.terminal_id.as_deref() == Some(terminal_id));
+            if open + additional > config.max_active_per_terminal {
+                return Err(exceeded("Terminal", terminal_id, open, config.max_active_per_terminal));
+            }
+        }
+        Ok(())
+    }
+    
+    fn open_transaction(&self, id: u64, kind: TransactionKind, store: String, currency: Currency) -> Result<u64, KernelError> {
+        let entry = self.active_transactions.entry(id)?;
+        let begin_sequence = self.journal.append(id, JournalOperation::TransactionBegin {
+            store: store.clone(),
+            currency: currency.code.clone(),
+            decimal_places: currency.decimal_places,
+            kind,
+        });
+        if let Err(e) = &begin_sequence {
+            eprintln!("WARNING: Failed to journal operation for transaction {}: {}", id, e);
+        }
+        if kind == TransactionKind::Sale {
+            self.events.publish(id, EventKind::TransactionStarted {
+                store: store.clone(),
+                currency: currency.code.clone(),
+            });
+        }
+        let mut transaction = self.pool().acquire(id, kind, store, currency);
+        transaction.begin_sequence = begin_sequence.ok();
+        if kind == TransactionKind::Sale {
+            self.start_tse(&mut transaction);
+        }
+        entry.insert(transaction);
+        self.refill_transaction_pool();
+        Ok(id)
+    }
+    
+    fn pool(&self) -> MutexGuard<'_, pool::TransactionPool> {
+        self.transaction_pool.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    
+    fn refill_transaction_pool(&self) {
+        if self.pool().needs_refill() {
+            self.pipeline.submit(pipeline::Job::RefillPool);
+        }
+    }
+    
+    fn restock_transaction_pool(&self) {
+        let shortfall = self.pool().shortfall();
+        let shells = pool::allocate(shortfall);
+        self.pool().restock(shells);
+    }
+    
+    // REGULATORY COMPLIANCE: KassenSichV requires the TSE transaction to start with the sale
+    fn start_tse(&self, tx: &mut Transaction) {
+        let tse = match self.tse.as_ref() {
+            Some(tse) => tse,
+            None => return,
+        };
+        
+        match tse.device.start_transaction(&tse.client_id, fiscal::tse::PROCESS_TYPE_RECEIPT, "") {
+            Ok(start) => {
+                self.journal.record(tx.id, JournalOperation::TseStart { transaction_number: start.transaction_number });
+                tx.tse_start = Some(start);
+            },
+            Err(e) => {
+                eprintln!("WARNING: TSE start failed for transaction {}: {}", tx.id, e);
+                tx.tse_error = Some(e);
+            }
+        }
+    }
+    
+    // REGULATORY COMPLIANCE: Prepares the TSE finish with the final receipt data; it is signed
+    // without the store lock
+    fn prepare_tse_finish(&self, handle: u64) -> Option<Result<fiscal::tse::TseFinish, String>> {
+        let tse = self.tse.as_ref()?;
+        let tx = self.transaction(handle).ok()?;
+        let start = match (&tx.tse_start, &tx.tse_error) {
+            (Some(start), _) => start,
+            (None, Some(_)) => return None,
+            (None, None) => return Some(Err("TSE transaction was not started".to_string())),
+        };
+        
+        let process_data = match fiscal::tse::process_data(&receipt::ReceiptDocument::build(&tx, &self.receipt_layout)) {
+            Ok(process_data) => process_data,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok(fiscal::tse::TseFinish {
+            device: Arc::clone(&tse.device),
+            client_id: tse.client_id.clone(),
+            transaction_number: start.transaction_number,
+            process_data,
+        }))
+    }
+    
+    // Applies a committed sale's device results if it is still committed, then delivers its
+    // receipt and runs the rest of its post-commit work
+    fn apply_fiscalization(&mut self, fiscalization: &fiscal::CommitFiscalization, outcome: fiscal::FiscalizationOutcome) {
+        let handle = fiscalization.handle;
+        let current = self.active_transactions.get_mut(handle).is_some_and(|tx| tx.state == TxState::Committed);
+        if !current {
+            eprintln!("WARNING: Fiscal results for transaction {} discarded: it is no longer committed", handle);
+            return;
+        }
+        
+        match outcome.tse {
+            Some(Ok(signature)) => {
+                self.journal.record(handle, JournalOperation::TseFinish {
+                    transaction_number: signature.transaction_number,
+                    signature_counter: signature.signature_counter,
+                    signature: signature.signature.clone(),
+                });
+                if let Some(mut tx) = self.active_transactions.get_mut(handle) {
+                    tx.tse_signature = Some(signature);
+                }
+            },
+            Some(Err(e)) => self.record_tse_error(handle, e),
+            None => {},
+        }
+        
+        if let (Some(result), Some(document)) = (outcome.registration, &fiscalization.document) {
+            match result {
+                Ok(registration) => {
+                    self.journal.record(handle, JournalOperation::FiscalRegister {
+                        day_number: registration.fiscal_day,
+                        document_number: registration.document_number.clone(),
+                    });
+                    match self.fiscal_day.as_mut() {
+                        Some(day) if day.day_number == document.day_number => {
+                            day.documents_registered += 1;
+                            day.total_minor += document.document.total_minor;
+                        },
+                        _ => eprintln!("WARNING: Transaction {} was registered after fiscal day {} closed; the day's totals omit it", handle, document.day_number),
+                    }
+                    if let Some(mut tx) = self.active_transactions.get_mut(handle) {
+                        tx.fiscal_registration = Some(registration);
+                    }
+                },
+                Err(e) => self.record_fiscal_error(handle, document.device.jurisdiction(), e),
+            }
+        }
+        self.publish_commit(handle);
+    }
+    
+    fn record_tse_error(&mut self, handle: u64, e: String) {
+        eprintln!("WARNING: TSE finish failed for transaction {}: {}", handle, e);
+        if let Some(mut tx) = self.active_transactions.get_mut(handle) {
+            tx.tse_error = Some(e);
+        }
+    }
+    
+    // QUOTE: Saves the quote under a user-space reference so it can be recalled later
+    fn save_quote(&mut self, handle: u64, reference: String) -> Result<(), KernelError> {
+        pci::screen(TextField::QuoteReference, &reference)?;
+        
+        if reference.is_empty() || reference.len() > self.system_config.max_attribute_key_len {
+            return Err(KernelError::validation("Quote reference length out of range"));
+        }
+        
+        if self.saved_quotes.get(&reference).is_some_and(|h| *h != handle) {
+            return Err(KernelError::invalid_state("Quote reference already in use"));
+        }
+        
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.kind != TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Transaction is not a quote"));
+        }
+        
+        if let Some(previous) = tx.quote_reference.replace(reference.clone()) {
+            self.saved_quotes.remove(&previous);
+        }
+        self.saved_quotes.insert(reference.clone(), handle);
+        self.journal.record(handle, JournalOperation::QuoteSave { reference });
+        Ok(())
+    }
+    
+    fn recall_quote(&self, reference: &str) -> Result<u64, KernelError> {
+        self.saved_quotes.get(reference)
+            .copied()
+            .ok_or_else(|| KernelError::not_found("Quote not found"))
+    }
+    
+    // OMNICHANNEL: Imports a paid online order as an open sale held under its pickup code. The
+    // lines go through the usual checks (and stock reservation); if any is refused the
+    // transaction is abandoned and the import fails as a whole.
+    fn import_pickup_order(&mut self, json: &str) -> Result<u64, KernelError> {
+        let order = pickup::parse(json).map_err(KernelError::Validation)?;
+        let code = order.pickup_code.clone();
+        pci::screen(TextField::PickupCode, &code)?;
+        
+        if code.is_empty() || code.len() > self.system_config.max_order_reference_len {
+            return Err(KernelError::validation("Pickup code length out of range"));
+        }
+        
+        // Codes are free again once their sale is completed or abandoned
+        let open = |handle: &u64| self.active_transactions.get(*handle)
+            .is_ok_and(|tx| tx.is_some_and(|tx| tx.state == TxState::Building));
+        self.pickup_orders = std::mem::take(&mut self.pickup_orders).into_iter()
+            .filter(|(_, handle)| open(handle))
+            .collect();
+        if self.pickup_orders.contains_key(&code) {
+            return Err(KernelError::invalid_state(pickup::PICKUP_CODE_IN_USE));
+        }
+        
+        let currency = self.check_begin_parameters(&order.store, &order.currency, order.decimal_places)?;
+        let handle = self.begin_transaction_legal(order.store.clone(), currency)?;
+        if let Err(e) = self.fill_pickup_order(handle, order) {
+            if let Err(abandon) = self.abandon_transaction(handle, "Online order import failed") {
+                eprintln!("WARNING: Failed online order {} not abandoned: {}", handle, abandon);
+            }
+            return Err(e);
+        }
+        
+        self.pickup_orders.insert(code.clone(), handle);
+        self.events.publish(handle, EventKind::PickupOrderImported { pickup_code: code });
+        Ok(handle)
+    }
+    
+    fn fill_pickup_order(&self, handle: u64, order: pickup::OnlineOrder) -> Result<(), KernelError> {
+        let channel = order.channel.unwrap_or_else(|| pickup::DEFAULT_CHANNEL.to_string());
+        self.set_order_reference(handle, OrderReferenceField::Channel, Some(channel))?;
+        self.set_order_reference(handle, OrderReferenceField::PickupReference, Some(order.pickup_code.clone()))?;
+        if let Some(external_order_id) = order.external_order_id {
+            self.set_order_reference(handle, OrderReferenceField::ExternalOrderId, Some(external_order_id))?;
+        }
+        if let Some(token) = order.customer_token {
+            self.attach_customer(handle, token, None)?;
+        }
+        for line in order.lines {
+            self.add_line_legal(handle, line.sku, line.qty, line.unit_minor)?;
+        }
+        
+        // Payments are checked together before any is recorded, so a refused order is never
+        // left partly tendered
+        let payments: Vec<Tender> = order.payments.into_iter()
+            .map(|p| Ok(Tender::card(pci::screen(TextField::AuthorizationReference, &p.reference)?.into_owned(), p.amount_minor)))
+            .collect::<Result<_, KernelError>>()?;
+        if payments.iter().any(|t| t.card.as_ref().is_some_and(|c| c.auth_reference.is_empty() || c.auth_reference.len() > self.system_config.max_attribute_key_len)) {
+            return Err(KernelError::validation("Authorization reference length out of range"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        let paid_minor = payments.iter().try_fold(0i64, |sum, t| sum.checked_add(t.amount_minor))
+            .ok_or_else(|| KernelError::validation("Online payments overflow"))?;
+        if paid_minor > tx.total_minor() {
+            return Err(KernelError::validation("Online payments exceed the order total"));
+        }
+        for tender in &payments {
+            tx.check_new_tender(self.system_config.max_tender_minor, tender.amount_minor)?;
+        }
+        
+        let imported = pickup::PickupOrder { code: order.pickup_code, imported_at: Utc::now(), recalled_at: None };
+        tx.pickup = Some(imported.clone());
+        for tender in payments {
+            let (amount_minor, auth_reference) = (tender.amount_minor, tender.card.as_ref().map(|c| c.auth_reference.clone()));
+            tx.add_tender(tender);
+            self.journal.record(handle, JournalOperation::TenderAdd { amount_minor, kind: TenderKind::Card, points: None, token: None, auth_reference });
+            self.events.publish(handle, EventKind::TenderAdded { amount_minor, tendered_minor: tx.tendered_minor });
+        }
+        drop(tx);
+        self.journal.record(handle, JournalOperation::PickupImport { code: imported.code });
+        Ok(())
+    }
+    
+    // OMNICHANNEL: Brings a waiting pickup order to a lane. It can be recalled again (by another
+    // lane, say) until its sale is completed or abandoned.
+    fn recall_pickup_order(&mut self, code: &str, terminal_id: Option<String>) -> Result<u64, KernelError> {
+        let handle = *self.pickup_orders.get(code).ok_or_else(|| KernelError::not_found(pickup::PICKUP_NOT_FOUND))?;
+        let tx = self.active_transactions.get(handle)?;
+        if !tx.is_some_and(|tx| tx.state == TxState::Building) {
+            self.pickup_orders.remove(code);
+            return Err(KernelError::not_found(pickup::PICKUP_NOT_FOUND));
+        }
+        
+        if terminal_id.is_some() {
+            self.assign_operator(handle, terminal_id.clone(), None)?;
+        }
+        if let Some(mut tx) = self.active_transactions.get_mut(handle) {
+            if let Some(order) = tx.pickup.as_mut() {
+                order.recalled_at.get_or_insert_with(Utc::now);
+            }
+        }
+        self.journal.record(handle, JournalOperation::PickupRecall { terminal_id: terminal_id.clone() });
+        self.events.publish(handle, EventKind::PickupOrderRecalled { pickup_code: code.to_string(), terminal_id });
+        Ok(handle)
+    }
+    
+    // Begins a sale carrying the given lines plus the source's store, currency, customer and
+    // (optionally) attributes and order reference. Line IDs are preserved so parent links carry over unchanged.
+    fn begin_derived_transaction(&mut self, source_handle: u64, lines: LineItems, include_attributes: bool) -> Result<u64, KernelError> {
+        let source = self.transaction(source_handle)?;
+        let (store, currency) = (source.store.clone(), source.currency.clone());
+        let customer = source.customer.clone();
+        let (terminal_id, operator_id) = (source.terminal_id.clone(), source.operator_id.clone());
+        let (attributes, order_reference) = if include_attributes {
+            (source.attributes.clone(), source.order_reference.clone())
+        } else {
+            (BTreeMap::new(), OrderReference::default())
+        };
+        drop(source);
+        let line_ids: Vec<u32> = lines.iter().map(|l| l.line_id).collect();
+        
+        self.check_capacity(None, terminal_id.as_deref(), 1)?;
+        let id = self.begin_transaction_legal(store, currency)?;
+        if let Some(mut tx) = self.active_transactions.get_mut(id) {
+            tx.next_line_id = line_ids.iter().copied().max().unwrap_or(0) + 1;
+            tx.lines = lines;
+            tx.attributes = attributes;
+            tx.order_reference = order_reference;
+            tx.customer = customer;
+        }
+        if terminal_id.is_some() || operator_id.is_some() {
+            self.assign_operator(id, terminal_id, operator_id)?;
+        }
+        
+        for line_id in line_ids {
+            self.journal_line_added(id, line_id);
+        }
+        Ok(id)
+    }
+    
+    // QUOTE: Creates a real sale with the quote's lines and prices; the quote is retained,
+    // marked as converted, and cannot be converted twice
+    fn convert_quote(&mut self, handle: u64) -> Result<u64, KernelError> {
+        let quote = self.transaction(handle)?;
+        
+        if quote.kind != TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Transaction is not a quote"));
+        }
+        
+        if quote.converted_to.is_some() {
+            return Err(KernelError::invalid_state("Quote already converted"));
+        }
+        
+        let lines = quote.lines.clone();
+        drop(quote);
+        let id = self.begin_derived_transaction(handle, lines, true)?;
+        
+        if let Some(mut quote) = self.active_transactions.get_mut(handle) {
+            quote.converted_to = Some(id);
+        }
+        self.journal.record(handle, JournalOperation::QuoteConvert { transaction_handle: id });
+        Ok(id)
+    }
+    
+    // ARCHITECTURAL PRINCIPLE: Scanning runs under the shared store lock and locks only the
+    // transaction's shard, so lanes scan in parallel
+    fn add_line_legal(&self, handle: u64, sku: String, qty: i32, unit_minor: i64) -> Result<u32, KernelError> {
+        if self.product_provider.is_some() {
+            return self.add_line_with_metadata_legal(handle, sku, qty, unit_minor, None, None);
+        }
+        pci::screen(TextField::Sku, &sku)?;
+        let mut stock = self.reserve_stock(handle, &sku, qty)?;
+        let sku = self.skus.intern(&sku);
+        
+        let added = self.active_transactions.write(handle).and_then(|mut tx| {
+            if tx.state != TxState::Building {
+                return Err(KernelError::invalid_state(NOT_BUILDING));
+            }
+            
+            tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, None)?;
+            let line_id = tx.add_line(sku, qty, unit_minor);
+            self.journal_line(&tx, line_id);
+            self.attach_stock(&mut tx, line_id, &mut stock);
+            self.screen_line(&mut tx, line_id);
+            Ok(line_id)
+        });
+        self.release_unattached_stock(stock);
+        added
+    }
+    
+    // CATALOG: Metadata the caller leaves out is taken from the product catalog, and the price
+    // is checked against it
+    fn add_line_with_metadata_legal(&self, handle: u64, sku: String, qty: i32, unit_minor: i64, product_name: Option<String>, product_description: Option<String>) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let (product_name, product_description) = match self.catalog_product(handle, &sku, unit_minor, self.system_config.catalog_validation)? {
+            Some(product) => (product_name.or(product.name), product_description.or(product.description)),
+            None => (product_name, product_description),
+        };
+        self.push_line_with_metadata(handle, sku, qty, unit_minor, product_name, product_description)
+    }
+    
+    // The catalog entry for a line about to be added by SKU, checked against `validation`;
+    // None without a catalog
+    fn catalog_product(&self, handle: u64, sku: &str, unit_minor: i64, validation: catalog::CatalogValidation) -> Result<Option<catalog::Product>, KernelError> {
+        let provider = match self.product_provider.as_ref() {
+            Some(provider) => provider,
+            None => return Ok(None),
+        };
+        let currency = self.transaction(handle)?.currency.code.clone();
+        let product = provider.product(sku, &currency);
+        catalog::check_line(product.as_ref(), unit_minor, validation)?;
+        Ok(product)
+    }
+    
+    // PRICING: Adds a line priced by the price provider, through the price cache. A stale
+    // price that stood in for the provider is journaled with its age. The catalog still
+    // supplies the metadata and rejects discontinued products, but the price is the provider's.
+    fn add_priced_line(&self, handle: u64, sku: String, qty: i32) -> Result<(u32, i64), KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let provider = self.price_provider.as_ref()
+            .ok_or_else(|| KernelError::invalid_state("No price provider registered"))?;
+        let currency = self.transaction(handle)?.currency.code.clone();
+        let (unit_minor, source) = self.price_cache.resolve(provider, &sku, &currency, self.system_config.price_policy(), |refresh| {
+            self.pipeline.submit(pipeline::Job::RefreshPrice(Box::new(refresh)));
+        })?;
+        
+        let (product_name, product_description) = match self.catalog_product(handle, &sku, unit_minor, catalog::CatalogValidation::Off)? {
+            Some(product) => (product.name, product.description),
+            None => (None, None),
+        };
+        let line_id = self.push_line_with_metadata(handle, sku, qty, unit_minor, product_name, product_description)?;
+        if let pricing::PriceSource::Stale(age_secs) = source {
+            self.journal.record(handle, JournalOperation::StalePrice { line_id, age_secs });
+        }
+        Ok((line_id, unit_minor))
+    }
+    
+    // Resolves a scanned code through the product catalog, or through the price provider when
+    // no catalog is registered
+    fn lookup_product(&self, code: &str, currency: &str) -> Result<Option<catalog::Product>, KernelError> {
+        if let Some(catalog) = self.product_provider.as_ref() {
+            return Ok(catalog.lookup_code(code, currency));
+        }
+        let provider = self.price_provider.as_ref()
+            .ok_or_else(|| KernelError::invalid_state("No price provider registered"))?;
+        Ok(provider.lookup(code, currency).map(catalog::Product::from))
+    }
+    
+    fn push_line_with_metadata(&self, handle: u64, sku: String, qty: i32, unit_minor: i64, product_name: Option<String>, product_description: Option<String>) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let sku = self.skus.intern(&sku);
+        
+        let product_name = pci::screen_opt(TextField::ProductName, product_name)?;
+        let product_description = pci::screen_opt(TextField::ProductDescription, product_description)?;
+        
+        let mut stock = self.reserve_stock(handle, &sku, qty)?;
+        let added = self.active_transactions.write(handle).and_then(|mut tx| {
+            if tx.state != TxState::Building {
+                return Err(KernelError::invalid_state(NOT_BUILDING));
+            }
+            
+            tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, None)?;
+            let line_id = tx.add_line_with_metadata(sku, qty, unit_minor, product_name, product_description);
+            self.journal_line(&tx, line_id);
+            self.attach_stock(&mut tx, line_id, &mut stock);
+            self.screen_line(&mut tx, line_id);
+            Ok(line_id)
+        });
+        self.release_unattached_stock(stock);
+        added
+    }
+    
+    // INVENTORY: Reserves stock through the inventory provider for a line about to be added,
+    // before the transaction is locked; None without a provider
+    fn reserve_stock(&self, handle: u64, sku: &str, qty: i32) -> Result<Option<inventory::LineStock>, KernelError> {
+        let provider = match self.inventory_provider.as_deref() {
+            Some(provider) => provider,
+            None => return Ok(None),
+        };
+        let tx = self.transaction(handle)?;
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        let reservation = inventory::StockReservation { store: tx.store.clone(), sku: sku.to_string(), qty, transaction_handle: handle };
+        drop(tx);
+        
+        let (on_shortage, on_unavailable) = (self.system_config.inventory_on_shortage, self.system_config.inventory_on_unavailable);
+        inventory::reserve_line(provider, reservation, on_shortage, on_unavailable).map(Some)
+    }
+    
+    // INVENTORY: Records the stock reserved for a line just added, under the caller's lock on
+    // the transaction so a void cannot miss it; a line let through unreserved is journaled
+    fn attach_stock(&self, tx: &mut Transaction, line_id: u32, stock: &mut Option<inventory::LineStock>) {
+        match stock.take() {
+            Some(inventory::LineStock::Reserved(reservation)) => {
+                tx.stock_reservations.insert(line_id, reservation);
+            },
+            Some(inventory::LineStock::Unreserved(reason)) => {
+                self.journal.record(tx.id, JournalOperation::StockUnreserved { line_id, reason });
+            },
+            None => {},
+        }
+    }
+    
+    // INVENTORY: Gives back stock reserved for a line that was then not added
+    fn release_unattached_stock(&self, stock: Option<inventory::LineStock>) {
+        if let Some(inventory::LineStock::Reserved(reservation)) = stock {
+            self.release_stock(&[reservation]);
+        }
+    }
+    
+    fn release_stock(&self, reservations: &[inventory::StockReservation]) {
+        if let (Some(provider), false) = (self.inventory_provider.as_deref(), reservations.is_empty()) {
+            inventory::release(provider, reservations);
+        }
+    }
+    
+    // NRF COMPLIANCE: Add child line item with parent reference
+    fn add_child_line_legal(&self, handle: u64, sku: String, qty: i32, unit_minor: i64, parent_line_id: u32) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let mut stock = self.reserve_stock(handle, &sku, qty)?;
+        let sku = self.skus.intern(&sku);
+        
+        let added = self.active_transactions.write(handle).and_then(|mut tx| {
+            if tx.state != TxState::Building {
+                return Err(KernelError::invalid_state(NOT_BUILDING));
+            }
+            
+            tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, Some(parent_line_id))?;
+            let line_id = tx.add_child_line(sku, qty, unit_minor, parent_line_id)?;
+            self.journal_line(&tx, line_id);
+            self.attach_stock(&mut tx, line_id, &mut stock);
+            self.screen_line(&mut tx, line_id);
+            Ok(line_id)
+        });
+        self.release_unattached_stock(stock);
+        added
+    }
+    
+    // REGULATORY COMPLIANCE: Add container deposit linked to the triggering item
+    fn add_deposit_line_legal(&self, handle: u64, sku: String, qty: i32, unit_minor: i64, triggering_line_id: u32) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let sku = self.skus.intern(&sku);
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, Some(triggering_line_id))?;
+        let line_id = tx.add_deposit_line(sku, qty, unit_minor, triggering_line_id)?;
+        drop(tx);
+        self.journal_line_added(handle, line_id);
+        Ok(line_id)
+    }
+    
+    fn add_deposit_return_line_legal(&self, handle: u64, sku: String, qty: i32, unit_minor: i64) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let sku = self.skus.intern(&sku);
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, None)?;
+        let line_id = tx.add_deposit_return_line(sku, qty, unit_minor);
+        drop(tx);
+        self.journal_line_added(handle, line_id);
+        Ok(line_id)
+    }
+    
+    // AUDIT COMPLIANCE: Negative adjustments require a reason code and an approving supervisor
+    fn add_adjustment_line_legal(&self, handle: u64, sku: String, amount_minor: i64, reason_code: String, supervisor_id: String) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let sku = self.skus.intern(&sku);
+        pci::screen(TextField::OperatorId, &supervisor_id)?;
+        let reason_code = pci::screen(TextField::Reason, &reason_code)?.into_owned();
+        
+        if !self.has_permission(&supervisor_id, Permission::ManualAdjustment) {
+            return Err(KernelError::permission_denied("Supervisor lacks manual adjustment permission"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.check_new_line(self.system_config.line_bounds(), 1, amount_minor, None)?;
+        let line_id = tx.add_adjustment_line(sku, amount_minor, reason_code, supervisor_id);
+        drop(tx);
+        self.journal_line_added(handle, line_id);
+        self.record_anomaly(handle, anomaly::AnomalyKind::PriceOverride);
+        Ok(line_id)
+    }
+    
+    // SECURITY: Merchandise returns are checked against the refund velocity limits. A triggered
+    // limit is journaled and published; the return is blocked unless the supervisor holds the
+    // RefundOverride permission, in which case the supervisor is recorded on the line.
+    #[allow(clippy::too_many_arguments)]
+    fn add_return_line_legal(
+        &mut self,
+        handle: u64,
+        sku: String,
+        qty: i32,
+        unit_minor: i64,
+        original_receipt: Option<String>,
+        reason_code: Option<String>,
+        supervisor_id: Option<String>,
+    ) -> Result<u32, KernelError> {
+        pci::screen(TextField::Sku, &sku)?;
+        let sku = self.skus.intern(&sku);
+        if let Some(original_receipt) = &original_receipt {
+            pci::screen(TextField::OriginalReceipt, original_receipt)?;
+        }
+        if let Some(supervisor_id) = &supervisor_id {
+            pci::screen(TextField::OperatorId, supervisor_id)?;
+        }
+        let reason_code = pci::screen_opt(TextField::Reason, reason_code)?;
+        let amount_minor = unit_minor.checked_mul(qty as i64)
+            .ok_or_else(|| KernelError::validation("Return amount overflow"))?;
+        
+        let config = &self.system_config;
+        let limits = refund::RefundLimits {
+            max_per_transaction_minor: config.refund_max_per_transaction_minor,
+            max_count_per_shift: config.refund_max_count_per_shift,
+            max_amount_per_shift_minor: config.refund_max_amount_per_shift_minor,
+            require_receipt: config.refund_require_receipt,
+        };
+        
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.check_new_line(self.system_config.line_bounds(), qty, unit_minor, None)?;
+        
+        if tx.kind != TransactionKind::Sale || tx.layaway.is_some() || tx.open_tab_authorization().is_some() {
+            return Err(KernelError::invalid_state("Returns are only accepted on sales"));
+        }
+        
+        let shift_limited = limits.max_count_per_shift > 0 || limits.max_amount_per_shift_minor > 0;
+        if shift_limited && tx.operator_id.is_none() {
+            return Err(KernelError::invalid_state("Returns require an assigned operator"));
+        }
+        
+        let (operator_id, transaction_refund_minor) = (tx.operator_id.clone(), tx.refund_minor());
+        drop(tx);
+        
+        // Returns the operator holds open on other lanes count before they commit
+        let (mut other_pending_count, mut other_pending_minor) = (0, 0i64);
+        if let Some(operator) = operator_id.as_deref().filter(|_| shift_limited) {
+            self.active_transactions.for_each(|other| {
+                let refund_minor = other.refund_minor();
+                if other.id != handle && other.state == TxState::Building && refund_minor > 0
+                    && other.operator_id.as_deref() == Some(operator) {
+                    other_pending_count += 1;
+                    other_pending_minor = other_pending_minor.saturating_add(refund_minor);
+                }
+            });
+        }
+        let triggered = self.refunds.check(limits, refund::RefundRequest {
+            amount_minor,
+            transaction_refund_minor,
+            other_pending_count,
+            other_pending_minor,
+            has_receipt: original_receipt.is_some(),
+            operator_id: operator_id.as_deref(),
+        });
+        
+        let mut approved_by = None;
+        if !triggered.is_empty() {
+            let overridden_by = supervisor_id
+                .filter(|s| self.has_permission(s, Permission::RefundOverride));
+            self.journal.record(handle, JournalOperation::RefundLimitTriggered {
+                limits: triggered.clone(),
+                amount_minor,
+                overridden_by: overridden_by.clone(),
+            });
+            self.events.publish(handle, EventKind::RefundLimitTriggered {
+                limits: triggered,
+                amount_minor,
+                operator_id,
+                overridden_by: overridden_by.clone(),
+            });
+            if overridden_by.is_none() {
+                return Err(KernelError::permission_denied(refund::REFUND_LIMIT_EXCEEDED));
+            }
+            approved_by = overridden_by;
+        }
+        
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        let line_id = tx.add_return_line(Line::new_return(sku, qty, unit_minor, original_receipt, reason_code, approved_by));
+        drop(tx);
+        self.journal_line_added(handle, line_id);
+        Ok(line_id)
+    }
+    
+    // FUEL: Adds the grade's preset line for a pump; the pump is authorized once it is paid
+    fn fuel_preset(&self, handle: u64, pump_id: u32, sku: String, preset_minor: i64) -> Result<u32, KernelError> {
+        if pump_id == 0 {
+            return Err(KernelError::validation("Invalid pump ID"));
+        }
+        
+        if preset_minor <= 0 {
+            return Err(KernelError::validation("Fuel preset must be positive"));
+        }
+        
+        pci::screen(TextField::Sku, &sku)?;
+        let interned = self.skus.intern(&sku);
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot take fuel prepays"));
+        }
+        
+        if tx.fuel_sales.iter().any(|f| f.pump_id == pump_id && f.is_open()) {
+            return Err(KernelError::invalid_state("Pump already has an open fuel sale"));
+        }
+        
+        tx.check_new_line(self.system_config.line_bounds(), 1, preset_minor, None)?;
+        let line_id = tx.add_line(interned, 1, preset_minor);
+        tx.fuel_sales.push(fuel::FuelSale::new(pump_id, sku, preset_minor, line_id));
+        self.journal_line(&tx, line_id);
+        self.journal.record(handle, JournalOperation::FuelPreset { pump_id, line_id, preset_minor });
+        Ok(line_id)
+    }
+    
+    // FUEL: Releases the pump once the prepay is tendered in full
+    fn fuel_authorize(&self, handle: u64, pump_id: u32) -> Result<(), KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if !tx.held_lines.is_empty() {
+            return Err(KernelError::invalid_state(LINES_HELD));
+        }
+        
+        if !tx.fully_tendered() {
+            return Err(KernelError::invalid_state("Fuel prepay not fully tendered"));
+        }
+        
+        let sale = tx.fuel_sales.iter_mut()
+            .find(|f| f.pump_id == pump_id && f.is_open())
+            .ok_or_else(|| KernelError::not_found("No fuel sale for pump"))?;
+        if sale.state != fuel::FuelState::Preset {
+            return Err(KernelError::invalid_state("Pump already authorized"));
+        }
+        sale.state = fuel::FuelState::Authorized;
+        let preset_minor = sale.preset_minor;
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::FuelAuthorize { pump_id });
+        self.events.publish(handle, EventKind::FuelAuthorized { pump_id, preset_minor });
+        Ok(())
+    }
+    
+    // FUEL: Settles a pump from the dispenser reading. The preset line is voided and replaced by
+    // one at the dispensed amount, and unused prepay is refunded against the tenders that paid
+    // it, newest first. Returns the amount refunded.
+    fn fuel_complete(&self, handle: u64, pump_id: u32, volume_milli: i64, amount_minor: i64) -> Result<i64, KernelError> {
+        const SETTLED: &str = "Fuel preset settled";
+        
+        if volume_milli < 0 || amount_minor < 0 {
+            return Err(KernelError::validation("Invalid dispenser reading"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        let index = tx.fuel_sales.iter()
+            .position(|f| f.pump_id == pump_id && f.is_open())
+            .ok_or_else(|| KernelError::not_found("No fuel sale for pump"))?;
+        let sale = &tx.fuel_sales[index];
+        if sale.state != fuel::FuelState::Authorized {
+            return Err(KernelError::invalid_state("Pump is not authorized"));
+        }
+        
+        if amount_minor > sale.preset_minor {
+            return Err(KernelError::validation("Dispensed amount exceeds the prepay"));
+        }
+        
+        let (preset_line_id, unused_minor) = (sale.preset_line_id, sale.preset_minor - amount_minor);
+        let sku = self.skus.intern(&sale.sku);
+        tx.void_single_line_item(preset_line_id, SETTLED)?;
+        let line_id = tx.add_line(sku, 1, amount_minor);
+        
+        // Points are never paid back as money
+        let mut remaining = unused_minor.min(tx.tendered_minor - tx.total_minor()).max(0);
+        let mut refunds = Vec::new();
+        for tender in tx.tenders.iter().rev().filter(|t| t.amount_minor > 0 && t.kind != TenderKind::Points) {
+            if remaining == 0 {
+                break;
+            }
+            let refund_minor = tender.amount_minor.min(remaining);
+            remaining -= refund_minor;
+            refunds.push(Tender {
+                kind: tender.kind,
+                amount_minor: -refund_minor,
+                points: None,
+                card: tender.card.clone(),
+                token: None,
+            });
+        }
+        let refunded_minor: i64 = refunds.iter().map(|t| -t.amount_minor).sum();
+        
+        self.journal.record(handle, JournalOperation::LineVoid { line_id: preset_line_id, reason: SETTLED.to_string() });
+        self.events.publish(handle, EventKind::LineVoided { line_id: preset_line_id, reason: SETTLED.to_string() });
+        self.journal_line(&tx, line_id);
+        for refund in refunds {
+            let (amount_minor, kind) = (refund.amount_minor, refund.kind);
+            let auth_reference = refund.card.as_ref().map(|c| c.auth_reference.clone());
+            tx.add_tender(refund);
+            self.journal.record(handle, JournalOperation::TenderAdd { amount_minor, kind, points: None, token: None, auth_reference });
+            self.events.publish(handle, EventKind::TenderAdded { amount_minor, tendered_minor: tx.tendered_minor });
+        }
+        
+        let sale = &mut tx.fuel_sales[index];
+        sale.state = fuel::FuelState::Dispensed;
+        sale.line_id = Some(line_id);
+        sale.volume_milli = Some(volume_milli);
+        sale.dispensed_minor = Some(amount_minor);
+        sale.refunded_minor = refunded_minor;
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::FuelDispense { pump_id, line_id, volume_milli, amount_minor, refunded_minor });
+        self.events.publish(handle, EventKind::FuelDispensed { pump_id, volume_milli, amount_minor, refunded_minor });
+        self.publish_display(handle, |tx| vec![DisplayUpdateKind::ItemVoided { line_id: preset_line_id }, DisplayUpdateKind::total_due(tx)]);
+        Ok(refunded_minor)
+    }
+    
+    // SECURITY: Runs the line security module on a line just added, under the caller's lock on
+    // the transaction, so no tender can slip in before the hold is placed
+    fn screen_line(&self, tx: &mut Transaction, line_id: u32) {
+        let (module, line) = match (self.line_security.as_ref(), tx.find_line(line_id)) {
+            (Some(module), Some(line)) => (module, line),
+            _ => return,
+        };
+        let verdict = module.screen(&security::LineScreening {
+            transaction_handle: tx.id,
+            terminal_id: tx.terminal_id.as_deref(),
+            line_id,
+            parent_line_id: line.parent_line_item_id,
+            sku: &line.sku,
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+        });
+        let reason = match verdict {
+            security::SecurityVerdict::Accept => return,
+            // Reasons are masked, never rejected
+            security::SecurityVerdict::Hold { reason } => pci::screen(TextField::Reason, &reason).map(Cow::into_owned).unwrap_or_default(),
+        };
+        
+        let module = module.name().to_string();
+        tx.held_lines.insert(line_id, security::LineHold { module: module.clone(), reason: reason.clone(), held_at: Utc::now() });
+        self.journal.record(tx.id, JournalOperation::LineHold { line_id, module: module.clone(), reason: reason.clone() });
+        self.events.publish(tx.id, EventKind::LineHeld { line_id, module, reason });
+    }
+    
+    // SECURITY: Settles a held line. Overrides need an attendant holding SecurityOverride; a
+    // rejected line is voided.
+    fn resolve_line_hold(&self, handle: u64, line_id: u32, resolution: security::HoldResolution, operator_id: Option<String>) -> Result<(), KernelError> {
+        if let Some(operator_id) = &operator_id {
+            if operator_id.is_empty() || operator_id.len() > self.system_config.max_attribute_key_len {
+                return Err(KernelError::validation("Operator ID length out of range"));
+            }
+            pci::screen(TextField::OperatorId, operator_id)?;
+        }
+        
+        let permitted = operator_id.as_deref().is_some_and(|o| self.has_permission(o, Permission::SecurityOverride));
+        if resolution == security::HoldResolution::Overridden && !permitted {
+            return Err(KernelError::permission_denied("Operator lacks security override permission"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if !tx.held_lines.contains_key(&line_id) {
+            return Err(KernelError::invalid_state("Line is not held"));
+        }
+        
+        if resolution != security::HoldResolution::Rejected {
+            tx.held_lines.remove(&line_id);
+        }
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::LineHoldResolve { line_id, resolution, operator_id });
+        self.events.publish(handle, EventKind::LineHoldResolved { line_id, resolution });
+        if resolution == security::HoldResolution::Rejected {
+            self.void_line_with_cascade(handle, line_id, "Rejected by line security")?;
+        }
+        Ok(())
+    }
+    
+    // Resolves a raw scan to a product and adds it as one line. A price or net weight encoded in
+    // a variable-measure code prices the line and is kept as its weight reading.
+    fn scan_item(&self, handle: u64, raw: &str) -> Result<u32, KernelError> {
+        pci::screen(TextField::ScanCode, raw)?;
+        let scan = scan::parse(raw).map_err(KernelError::Validation)?;
+        let currency = self.transaction(handle)?.currency.clone();
+        let product = self.lookup_product(&scan.code, &currency.code)?
+            .ok_or_else(|| KernelError::not_found("Product not found for barcode"))?;
+        if product.flags.contains(catalog::ProductFlags::DISCONTINUED) {
+            return Err(KernelError::validation(catalog::DISCONTINUED));
+        }
+        
+        let unit_minor = match scan.price {
+            Some(price) => price.scaled(currency.decimal_places)
+                .ok_or_else(|| KernelError::validation("Barcode price does not fit the currency"))?,
+            None => product.unit_minor,
+        };
+        let reading = match scan.net_weight {
+            Some(weight) => {
+                let net_milli = weight.scaled(3).ok_or_else(|| KernelError::validation("Barcode weight out of range"))?;
+                Some(peripheral::WeightReading::new(net_milli, 0, peripheral::WeightUnit::Kilogram).map_err(KernelError::Validation)?)
+            },
+            None => None,
+        };
+        
+        let line_id = self.push_line_with_metadata(handle, product.sku, 1, unit_minor, product.name, product.description)?;
+        self.journal.record(handle, JournalOperation::ItemScan { line_id, scan });
+        if let Some(reading) = reading {
+            self.capture_weight(handle, Some(line_id), reading)?;
+        }
+        Ok(line_id)
+    }
+    
+    // CATALOG: Resolves a scanned code or SKU without adding it, for price checks and item
+    // lookup at the lane
+    fn product_json(&self, raw: &str, currency_code: &str) -> Result<String, KernelError> {
+        pci::screen(TextField::ScanCode, raw)?;
+        let scan = scan::parse(raw).map_err(KernelError::Validation)?;
+        let product = self.lookup_product(&scan.code, &currency_code.to_uppercase())?
+            .ok_or_else(|| KernelError::not_found("Product not found for barcode"))?;
+        serde_json::to_string(&product).map_err(|e| KernelError::Internal(format!("Failed to serialize product: {}", e)))
+    }
+    
+    // RFID: Adds the products read from a basket of tags as one line per product, all or none.
+    // Tags are resolved by GTIN, through the catalog or the price provider, before the
+    // transaction is locked. Discontinued products are left unresolved.
+    fn add_rfid_batch(&self, handle: u64, tags: &[String]) -> Result<rfid::RfidBatch, KernelError> {
+        if tags.is_empty() || tags.len() > rfid::MAX_BATCH_TAGS {
+            return Err(KernelError::validation("RFID batch size out of range"));
+        }
+        if self.product_provider.is_none() && self.price_provider.is_none() {
+            return Err(KernelError::invalid_state("No price provider registered"));
+        }
+        let currency = self.transaction(handle)?.currency.clone();
+        
+        let mut batch = rfid::RfidBatch::default();
+        let mut seen = HashSet::new();
+        let mut unknown = HashSet::new();
+        // Products in the order first read, with the tags read for each
+        let mut products: Vec<(String, catalog::Product, Vec<String>)> = Vec::new();
+        for tag in tags {
+            let sgtin = match rfid::decode(tag) {
+                Ok(sgtin) => sgtin,
+                Err(_) => {
+                    batch.ignored.push(pci::screen(TextField::RfidTag, tag.trim())?.into_owned());
+                    continue;
+                }
+            };
+            let epc = sgtin.uri();
+            if !seen.insert(epc.clone()) {
+                batch.duplicates += 1;
+                continue;
+            }
+            let gtin = sgtin.gtin();
+            if let Some((_, _, epcs)) = products.iter_mut().find(|(g, _, _)| *g == gtin) {
+                epcs.push(epc);
+                continue;
+            }
+            let product = if unknown.contains(&gtin) {
+                None
+            } else {
+                self.lookup_product(&gtin, &currency.code)?
+                    .filter(|p| !p.flags.contains(catalog::ProductFlags::DISCONTINUED))
+            };
+            match product {
+                Some(product) => products.push((gtin, product, vec![epc])),
+                None => {
+                    unknown.insert(gtin);
+                    batch.unresolved.push(epc);
+                }
+            }
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        // Tags already on the transaction were read by an earlier batch
+        for (_, _, epcs) in &mut products {
+            let read = epcs.len();
+            epcs.retain(|epc| !tx.rfid_tags.contains_key(epc));
+            batch.duplicates += read - epcs.len();
+        }
+        products.retain(|(_, _, epcs)| !epcs.is_empty());
+        
+        // A line that does not fit takes the lines added before it back out
+        let (line_count, next_line_id) = (tx.lines.len(), tx.next_line_id);
+        for (_, product, epcs) in &products {
+            let added = pci::screen(TextField::Sku, &product.sku).and_then(|_| {
+                let qty = epcs.len() as i32;
+                tx.check_new_line(self.system_config.line_bounds(), qty, product.unit_minor, None)?;
+                let name = pci::screen_opt(TextField::ProductName, product.name.clone())?;
+                let description = pci::screen_opt(TextField::ProductDescription, product.description.clone())?;
+                Ok(tx.add_line_with_metadata(self.skus.intern(&product.sku), qty, product.unit_minor, name, description))
+            });
+            match added {
+                Ok(line_id) => batch.line_ids.push(line_id),
+                Err(e) => {
+                    tx.lines.truncate(line_count);
+                    tx.next_line_id = next_line_id;
+                    return Err(e);
+                }
+            }
+        }
+        
+        for (&line_id, (_, _, epcs)) in batch.line_ids.iter().zip(products) {
+            tx.rfid_tags.extend(epcs.iter().map(|epc| (epc.clone(), line_id)));
+            self.journal_line(&tx, line_id);
+            self.journal.record(handle, JournalOperation::RfidRead { line_id, epcs });
+            self.screen_line(&mut tx, line_id);
+        }
+        Ok(batch)
+    }
+    
+    // REGULATORY COMPLIANCE: Records a scale reading on a weighed line, or against the
+    // transaction alone when no line is given
+    fn capture_weight(&self, handle: u64, line_id: Option<u32>, reading: peripheral::WeightReading) -> Result<(), KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if let Some(line_id) = line_id {
+            let line = tx.find_line(line_id).ok_or_else(|| KernelError::not_found("Invalid line ID"))?;
+            if line.qty == 0 {
+                return Err(KernelError::invalid_state("Voided lines cannot be weighed"));
+            }
+            if line.kind != LineKind::Sale {
+                return Err(KernelError::invalid_state("Only sale lines can be weighed"));
+            }
+            if tx.weight_readings.contains_key(&line_id) {
+                return Err(KernelError::invalid_state("Line already has a weight reading"));
+            }
+            tx.weight_readings.insert(line_id, reading.clone());
+        }
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::WeightCapture { line_id, reading });
+        Ok(())
+    }
+    
+    // KDS: Sends every item with lines not yet fired to the kitchen; returns the lines fired
+    fn fire_lines(&self, handle: u64) -> Result<usize, KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot be fired"));
+        }
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        let (tickets, line_ids) = peripheral::kitchen::fire(&tx, self.kitchen_router.as_deref());
+        tx.fired_lines.extend(line_ids.iter().copied());
+        drop(tx);
+        
+        if !line_ids.is_empty() {
+            self.journal.record(handle, JournalOperation::LinesFire { line_ids: line_ids.clone() });
+        }
+        self.send_kitchen_tickets(handle, tickets);
+        Ok(line_ids.len())
+    }
+    
+    // Published for FFI hosts and queued for the sinks; delivered here when the queue is full
+    fn send_kitchen_tickets(&self, handle: u64, tickets: Vec<peripheral::KitchenTicket>) {
+        for ticket in tickets {
+            self.events.publish(handle, EventKind::KitchenTicket { ticket: ticket.clone() });
+            if self.kitchen_sinks.is_empty() {
+                continue;
+            }
+            let delivery = peripheral::kitchen::KitchenDelivery { handle, ticket, sinks: self.kitchen_sinks.clone() };
+            self.pipeline.submit(pipeline::Job::DeliverKitchenTicket(Box::new(delivery)));
+        }
+    }
+    
+    fn publish_kitchen_outcomes(&self, delivery: &peripheral::kitchen::KitchenDelivery, outcomes: Vec<(String, Result<(), String>)>) {
+        for (sink, outcome) in outcomes {
+            if let Err(e) = &outcome {
+                eprintln!("WARNING: Kitchen ticket for transaction {} not sent to {}: {}", delivery.handle, sink, e);
+            }
+            self.events.publish(delivery.handle, EventKind::KitchenDelivery { sink, action: delivery.ticket.action, error: outcome.err() });
+        }
+    }
+    
+    // SECURITY: Starts an operator's shift, resetting their refund velocity counters
+    fn start_shift(&mut self, operator_id: String) -> Result<(), KernelError> {
+        self.replication.check_writable()?;
+        if operator_id.is_empty() || operator_id.len() > self.system_config.max_attribute_key_len {
+            return Err(KernelError::validation("Operator ID length out of range"));
+        }
+        pci::screen(TextField::OperatorId, &operator_id)?;
+        
+        self.refunds.start_shift(&operator_id, Utc::now());
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::ShiftStart { operator_id });
+        Ok(())
+    }
+    
+    fn shift_refunds_json(&self, operator_id: &str) -> Result<String, KernelError> {
+        let shift = self.refunds.shift(operator_id)
+            .ok_or_else(|| KernelError::not_found("No shift recorded for operator"))?;
+        serde_json::to_string(shift)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize shift refunds: {}", e)))
+    }
+    
+    fn check_terminal_ids(&self, terminal_id: Option<&str>, operator_id: Option<&str>) -> Result<(), KernelError> {
+        let max_len = self.system_config.max_attribute_key_len;
+        for (field, value) in [(TextField::TerminalId, terminal_id), (TextField::OperatorId, operator_id)] {
+            let Some(value) = value else { continue };
+            if value.is_empty() || value.len() > max_len {
+                return Err(KernelError::validation("Terminal or operator ID length out of range"));
+            }
+            pci::screen(field, value)?;
+        }
+        Ok(())
+    }
+    
+    // AUDIT COMPLIANCE: Opens the terminal's drawer session with its counted float
+    fn open_drawer_session(&mut self, terminal_id: String, operator_id: Option<String>, currency_code: &str, opening_float: Vec<cash::DenominationCount>) -> Result<(), KernelError> {
+        self.replication.check_writable()?;
+        self.check_terminal_ids(Some(&terminal_id), operator_id.as_deref())?;
+        if currency_code.len() != 3 || !currency_code.bytes().all(|b| b.is_ascii_alphabetic()) {
+            return Err(KernelError::validation("Currency code must be three letters, such as USD"));
+        }
+        let currency = currency_code.to_ascii_uppercase();
+        
+        let float_minor = self.drawer_sessions.open(&terminal_id, operator_id.clone(), &currency, opening_float, Utc::now())?;
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::DrawerSessionOpen {
+            terminal_id,
+            operator_id,
+            currency,
+            float_minor,
+        });
+        Ok(())
+    }
+    
+    // AUDIT COMPLIANCE: Records a safe drop, pickup or loan against the terminal's drawer
+    // session. SECURITY: Pickups and loans need an operator holding CashManagement. Returns the
+    // expected drawer total and, when a driver is registered, the drawer opening to perform.
+    fn record_cash_movement(&mut self, terminal_id: String, kind: cash::CashMovementKind, denominations: Vec<cash::DenominationCount>, operator_id: Option<String>) -> Result<(i64, Option<peripheral::drawer::DrawerOpen>), KernelError> {
+        self.replication.check_writable()?;
+        self.check_terminal_ids(Some(&terminal_id), operator_id.as_deref())?;
+        if kind != cash::CashMovementKind::Drop
+            && !operator_id.as_deref().is_some_and(|o| self.has_permission(o, Permission::CashManagement)) {
+            return Err(KernelError::permission_denied("Operator lacks cash management permission"));
+        }
+        
+        let movement = cash::CashMovement {
+            kind,
+            amount_minor: cash::count_total(&denominations)?,
+            denominations: denominations.clone(),
+            operator_id: operator_id.clone(),
+            recorded_at: Utc::now(),
+        };
+        let (amount_minor, expected_minor) = self.drawer_sessions.record_movement(&terminal_id, movement)?;
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::CashMovement {
+            terminal_id: terminal_id.clone(),
+            kind,
+            amount_minor,
+            denominations,
+            operator_id: operator_id.clone(),
+        });
+        self.events.publish(PK_INVALID_HANDLE, EventKind::CashMovement {
+            terminal_id: terminal_id.clone(),
+            kind,
+            amount_minor,
+            expected_minor,
+        });
+        
+        let open = self.drawer_driver.as_ref().map(|driver| peripheral::drawer::DrawerOpen {
+            handle: PK_INVALID_HANDLE,
+            terminal_id: Some(terminal_id),
+            operator_id,
+            reason: peripheral::DrawerOpenReason::CashMovement,
+            driver: Arc::clone(driver),
+        });
+        Ok((expected_minor, open))
+    }
+    
+    // AUDIT COMPLIANCE: Closes the terminal's drawer session against the counted cash; the
+    // report stays available until the next session on the terminal closes
+    fn close_drawer_session(&mut self, terminal_id: &str, counted: Vec<cash::DenominationCount>, operator_id: Option<String>) -> Result<String, KernelError> {
+        self.replication.check_writable()?;
+        self.check_terminal_ids(Some(terminal_id), operator_id.as_deref())?;
+        
+        let report = self.drawer_sessions.close(terminal_id, counted, operator_id.clone(), Utc::now())?;
+        let (expected_minor, counted_minor, variance_minor) = (report.session.expected_minor, report.counted_minor, report.variance_minor);
+        let json = serde_json::to_string(report)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize drawer session: {}", e)));
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::DrawerSessionClose {
+            terminal_id: terminal_id.to_string(),
+            expected_minor,
+            counted_minor,
+            variance_minor,
+            operator_id,
+        });
+        self.events.publish(PK_INVALID_HANDLE, EventKind::DrawerSessionClosed {
+            terminal_id: terminal_id.to_string(),
+            expected_minor,
+            counted_minor,
+            variance_minor,
+        });
+        json
+    }
+    
+    // The open session, or the terminal's last closed session report
+    fn drawer_session_json(&self, terminal_id: &str) -> Result<String, KernelError> {
+        let json = match (self.drawer_sessions.session(terminal_id), self.drawer_sessions.last_report(terminal_id)) {
+            (Some(session), _) => serde_json::to_string(session),
+            (None, Some(report)) => serde_json::to_string(report),
+            (None, None) => return Err(KernelError::not_found("No drawer session recorded for terminal")),
+        };
+        json.map_err(|e| KernelError::Internal(format!("Failed to serialize drawer session: {}", e)))
+    }
+    
+    // Refunds must be paid out by refund tenders and may not exceed the refund due
+    fn add_refund_tender_legal(&self, handle: u64, amount_minor: i64) -> Result<bool, KernelError> {
+        if amount_minor <= 0 {
+            return Err(KernelError::validation("Refund exceeds amount due"));
+        }
+        
+        self.add_tender_legal(handle, Tender::cash(-amount_minor), |tx| match amount_minor > tx.tendered_minor - tx.total_minor() {
+            true => Err(KernelError::validation("Refund exceeds amount due")),
+            false => Ok(()),
+        })
+    }
+    
+    // AUDIT COMPLIANCE: Sets the terminal and operator working the transaction; None keeps the
+    // current value
+    fn assign_operator(&mut self, handle: u64, terminal_id: Option<String>, operator_id: Option<String>) -> Result<(), KernelError> {
+        self.check_terminal_ids(terminal_id.as_deref(), operator_id.as_deref())?;
+        
+        // Moving an open sale onto another terminal counts against that terminal's cap
+        let tx = self.transaction(handle)?;
+        let counted = tx.kind == TransactionKind::Sale && tx.state == TxState::Building && tx.terminal_id != terminal_id;
+        drop(tx);
+        if counted {
+            self.check_capacity(None, terminal_id.as_deref(), 1)?;
+        }
+        
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if terminal_id.is_some() {
+            tx.terminal_id = terminal_id;
+        }
+        if operator_id.is_some() {
+            tx.operator_id = operator_id;
+        }
+        let (terminal_id, operator_id) = (tx.terminal_id.clone(), tx.operator_id.clone());
+        self.journal.record(handle, JournalOperation::OperatorAssign { terminal_id, operator_id });
+        Ok(())
+    }
+    
+    // SECURITY: Counts a void or price override against the transaction's operator and terminal
+    fn record_anomaly(&self, handle: u64, kind: anomaly::AnomalyKind) {
+        let tx = match self.active_transactions.get(handle) {
+            Ok(Some(tx)) => tx,
+            _ => return,
+        };
+        
+        let thresholds = anomaly::AnomalyThresholds {
+            window_secs: self.system_config.anomaly_window_secs,
+            max_voids: self.system_config.anomaly_max_voids,
+            max_overrides: self.system_config.anomaly_max_overrides,
+        };
+        let alerts = self.anomalies.lock().unwrap_or_else(PoisonError::into_inner).record(kind, tx.operator_id.as_deref(), tx.terminal_id.as_deref(), thresholds, Utc::now());
+        for alert in alerts {
+            self.events.publish(handle, EventKind::AnomalyDetected { alert });
+        }
+    }
+    
+    fn anomaly_counters_report(&self, format: reports::ReportFormat) -> Result<String, KernelError> {
+        let counters = self.anomalies.lock().unwrap_or_else(PoisonError::into_inner).counters(self.system_config.anomaly_window_secs, Utc::now());
+        reports::render(&counters, anomaly::AnomalyCounters::to_csv, format).map_err(KernelError::Internal)
+    }
+    
+    // AUDIT COMPLIANCE: Every path that commits a transaction journals and publishes it here
+    fn record_commit(&mut self, handle: u64) {
+        match self.active_transactions.get_mut(handle) {
+            Some(mut tx) => tx.committed_at = Some(Utc::now()),
+            None => return,
+        }
+        
+        let tx = match self.active_transactions.get_mut(handle) {
+            Some(tx) => tx,
+            None => return,
+        };
+        
+        let refund_minor = tx.refund_minor();
+        if let (Some(operator_id), true) = (tx.operator_id.as_deref(), refund_minor > 0) {
+            self.refunds.record(operator_id, refund_minor, Utc::now());
+        }
+        if let Some(terminal_id) = tx.terminal_id.as_deref() {
+            // Change is always given in cash
+            let cash_minor: i64 = tx.tenders.iter()
+                .filter(|t| t.kind == TenderKind::Cash)
+                .map(|t| t.amount_minor)
+                .sum();
+            self.drawer_sessions.record_sale(terminal_id, &tx.currency.code, cash_minor - tx.change_minor());
+        }
+        self.sales.record(&tx);
+        
+        let (total_minor, tendered_minor, change_minor) = (tx.total_minor(), tx.tendered_minor, tx.change_minor());
+        let customer = tx.customer.clone();
+        let order_reference = (!tx.order_reference.is_empty()).then(|| tx.order_reference.clone());
+        
+        self.journal.record(handle, JournalOperation::TransactionCommit {
+            total_minor,
+            tendered_minor,
+            begin_sequence: tx.begin_sequence,
+        });
+        self.events.publish(handle, EventKind::TransactionCommitted {
+            total_minor,
+            tendered_minor,
+            change_minor,
+            customer,
+            order_reference,
+        });
+        for movement in inventory::movements(&tx) {
+            self.events.publish(handle, EventKind::StockMoved { movement });
+        }
+        drop(tx);
+        self.publish_display(handle, |tx| vec![DisplayUpdateKind::change_due(tx)]);
+        
+        // The receipt carries the TSE signature and the fiscal registration, so it waits for the
+        // devices
+        let tse = match self.prepare_tse_finish(handle) {
+            Some(Ok(finish)) => Some(finish),
+            Some(Err(e)) => {
+                self.record_tse_error(handle, e);
+                None
+            },
+            None => None,
+        };
+        let document = match self.prepare_fiscal_document(handle) {
+            Some(Ok(document)) => Some(document),
+            Some(Err((jurisdiction, e))) => {
+                self.record_fiscal_error(handle, &jurisdiction, e);
+                None
+            },
+            None => None,
+        };
+        match (tse, document) {
+            (None, None) => self.publish_commit(handle),
+            (tse, document) => {
+                let fiscalization = fiscal::CommitFiscalization { handle, tse, document };
+                self.submit_job(pipeline::Job::Fiscalize(Box::new(fiscalization)));
+            },
+        }
+    }
+    
+    // Post-commit work that needs the transaction complete, with its TSE signature
+    fn publish_commit(&mut self, handle: u64) {
+        // Delivery failures are reported as events; the receipt can be re-delivered on request.
+        // Archival follows in the background; until then the transaction stays in the active
+        // store in its committed state.
+        match self.receipt_delivery(handle, DeliveryReason::Commit) {
+            Ok(Some(delivery)) => self.submit_job(pipeline::Job::DeliverReceipt(Box::new(delivery))),
+            Ok(None) => {},
+            Err(e) => eprintln!("WARNING: Receipt for transaction {} not delivered: {}", handle, e),
+        }
+        if self.spooler.has_printers() {
+            match self.spool_receipt(handle, None, false) {
+                Ok(printers) if !printers.is_empty() => self.submit_job(pipeline::Job::Print(Arc::clone(&self.spooler))),
+                Ok(_) => {},
+                Err(e) => eprintln!("WARNING: Receipt for transaction {} not printed: {}", handle, e),
+            }
+        }
+        if self.erp_outbox.has_connectors() {
+            match self.transaction_snapshot(handle).and_then(|export| serde_json::to_value(export).map_err(|e| KernelError::internal(e.to_string()))) {
+                Ok(document) => {
+                    self.erp_outbox.enqueue(handle, &document);
+                    self.submit_job(pipeline::Job::PushErp(Arc::clone(&self.erp_outbox)));
+                },
+                Err(e) => eprintln!("WARNING: Transaction {} not pushed to ERP: {}", handle, e),
+            }
+        }
+        if let Some(delivery) = self.basket_delivery(handle) {
+            self.submit_job(pipeline::Job::PublishBasket(Box::new(delivery)));
+        }
+        if let Some(pending) = self.sync_batch(false) {
+            self.submit_job(pipeline::Job::Sync(Box::new(pending)));
+        }
+        if let Some(open) = self.cash_drawer_open(handle) {
+            self.submit_job(pipeline::Job::OpenDrawer(Box::new(open)));
+        }
+        if let Some(dispense) = self.change_dispense(handle) {
+            self.submit_job(pipeline::Job::DispenseChange(Box::new(dispense)));
+        }
+        self.submit_job(pipeline::Job::Archive { handle });
+    }
+    
+    // Queues post-commit work for the persistence workers, which apply it under their own
+    // store lock once this one is released
+    fn submit_job(&self, job: pipeline::Job) {
+        self.pipeline.submit(job);
+    }
+    
+    // AUDIT COMPLIANCE: Moves a settled (committed or cancelled) transaction from the active
+    // store into the archive, where it counts against the memory budget and can be evicted
+    fn archive_transaction(&mut self, handle: u64) {
+        let tx = match self.active_transactions.remove(handle) {
+            Some(tx) => tx,
+            None => return,
+        };
+        
+        match self.archive.seal(tx) {
+            Ok((sequence_number, hash)) => {
+                self.journal.record(handle, JournalOperation::TransactionArchive { sequence_number, hash });
+                self.checkpoint_journal();
+            },
+            Err((tx, e)) => {
+                eprintln!("WARNING: Failed to archive transaction {}: {}", handle, e);
+                self.active_transactions.insert(*tx);
+            }
+        }
+        self.enforce_memory_budget();
+    }
+    
+    // The journal keeps the entries of every open transaction in memory, from the oldest
+    // one's begin on
+    fn checkpoint_journal(&self) {
+        if !self.journal.checkpoint_due() {
+            return;
+        }
+        let mut keep_from = self.journal.last_sequence() + 1;
+        self.active_transactions.for_each(|tx| {
+            if let Some(begin_sequence) = tx.begin_sequence {
+                keep_from = keep_from.min(begin_sequence);
+            }
+        });
+        self.journal.checkpoint(keep_from);
+    }
+    
+    // Evicted transactions stay reachable through the archive; a failed eviction leaves them
+    // in memory and is retried on the next commit. At most one eviction job is queued at a time.
+    fn enforce_memory_budget(&mut self) {
+        let budget_bytes = self.system_config.memory_budget_bytes;
+        if budget_bytes == 0 || self.archive.resident_bytes() <= budget_bytes {
+            return;
+        }
+        if !self.eviction_queued.swap(true, Ordering::Relaxed) {
+            self.submit_job(pipeline::Job::Evict);
+        }
+    }
+    
+    fn store_stats(&self) -> StoreStats {
+        let transactions_per_shard = self.active_transactions.shard_sizes();
+        StoreStats {
+            active_transactions: transactions_per_shard.iter().sum(),
+            archived_transactions: self.archive.len(),
+            transaction_shards: transactions_per_shard.len(),
+            transactions_per_shard,
+            pending_events: self.events.pending_count(),
+            dropped_events: self.events.dropped_count(),
+            interned_skus: self.skus.len(),
+            resident_archive_bytes: self.archive.resident_bytes(),
+            evicted_transactions: self.archive.evicted_count(),
+            persistence_workers: self.pipeline.workers(),
+            pending_persistence_jobs: self.pipeline.pending(),
+            transaction_pool: self.pool().stats(),
+            cached_prices: self.price_cache.len(),
+            cached_tax_baskets: self.tax_cache.len(),
+            poison_recoveries: self.poison_recoveries,
+        }
+    }
+    
+    // RESILIENCE: Repairs what an operation cut short by a panic can leave behind, before the
+    // poisoned store lock is cleared. Cached totals are republished from each transaction, the
+    // queued-job flags are reset so eviction and pool refills resume, and committed transactions
+    // still in the active store are queued for archival again. Transactions whose line hierarchy
+    // or journal disagree with them are reported and left as they are for investigation.
+    fn recover_from_poison(&mut self) {
+        self.poison_recoveries += 1;
+        self.eviction_queued.store(false, Ordering::Relaxed);
+        self.pool().clear_refill();
+        
+        let mut unarchived = Vec::new();
+        let mut suspect = 0;
+        for tx in self.active_transactions.values_mut() {
+            tx.publish_totals();
+            if matches!(tx.state, TxState::Committed | TxState::Cancelled) {
+                unarchived.push(tx.id);
+            }
+            let problem = match tx.check_hierarchy() {
+                Err(e) => Some(e),
+                Ok(()) if !verify::verify(tx, &self.journal.entries_for(tx.id), None).consistent => {
+                    Some("totals disagree with the journal".to_string())
+                },
+                Ok(()) => None,
+            };
+            if let Some(problem) = problem {
+                eprintln!("CRITICAL: Transaction {} left inconsistent by a panic: {}", tx.id, problem);
+                suspect += 1;
+            }
+        }
+        
+        eprintln!(
+            "CRITICAL: Kernel store lock was poisoned; recovered with {} transactions requeued for archival and {} inconsistent",
+            unarchived.len(), suspect
+        );
+        for handle in unarchived {
+            self.submit_job(pipeline::Job::Archive { handle });
+        }
+        self.refill_transaction_pool();
+    }
+    
+    // ARCHITECTURAL PRINCIPLE: Reporting runs on an immutable archive snapshot taken under the
+    // shared store lock; the caller releases the lock before querying it
+    fn report_snapshot(&self) -> archive::ArchiveSnapshot {
+        let interval_secs = self.system_config.report_snapshot_interval_secs;
+        if interval_secs == 0 {
+            return self.archive.snapshot();
+        }
+        
+        let mut cached = self.report_snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+        match cached.as_ref() {
+            Some(snapshot) if (Utc::now() - snapshot.taken_at).num_seconds() < interval_secs as i64 => snapshot.clone(),
+            _ => cached.insert(self.archive.snapshot()).clone(),
+        }
+    }
+    
+    fn discard_report_snapshot(&mut self) {
+        *self.report_snapshot.get_mut().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+    
+    // Hands the committed transaction to every registered deliverer; fails if any deliverer failed
+    // Re-delivery on request runs synchronously so the caller learns the outcome
+    fn deliver_receipt(&mut self, handle: u64, reason: DeliveryReason) -> Result<(), KernelError> {
+        match self.receipt_delivery(handle, reason)? {
+            Some(delivery) => {
+                let outcomes = delivery.deliver();
+                self.publish_receipt_outcomes(handle, reason, outcomes)
+            },
+            None => Ok(()),
+        }
+    }
+    
+    // Builds the receipt for delivery; None when no deliverer is registered
+    fn receipt_delivery(&self, handle: u64, reason: DeliveryReason) -> Result<Option<pipeline::ReceiptDelivery>, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Committed {
+            return Err(KernelError::invalid_state("Receipts are only delivered for committed transactions"));
+        }
+        
+        if self.receipt_deliverers.is_empty() {
+            return Ok(None);
+        }
+        
+        Ok(Some(pipeline::ReceiptDelivery {
+            handle,
+            reason,
+            receipt: self.build_receipt(&tx),
+            deliverers: self.receipt_deliverers.clone(),
+        }))
+    }
+    
+    // Summarizes a committed sale; None unless basket analytics is on and a sink is registered
+    fn basket_delivery(&self, handle: u64) -> Option<analytics::BasketDelivery> {
+        if !self.system_config.basket_analytics_enabled || self.analytics_sinks.is_empty() {
+            return None;
+        }
+        
+        let tx = self.transaction(handle).ok()?;
+        let department = |sku: &str| self.product_provider.as_ref()?.product(sku, &tx.currency.code)?.department;
+        Some(analytics::BasketDelivery {
+            basket: analytics::BasketSummary::build(&tx, department)?,
+            sinks: self.analytics_sinks.clone(),
+        })
+    }
+    
+    fn publish_receipt_outcomes(&self, handle: u64, reason: DeliveryReason, outcomes: Vec<(String, Result<(), String>)>) -> Result<(), KernelError> {
+        let mut failed = false;
+        for (deliverer, outcome) in outcomes {
+            failed |= outcome.is_err();
+            self.events.publish(handle, EventKind::ReceiptDelivery { deliverer, reason, error: outcome.err() });
+        }
+        
+        if failed {
+            return Err(KernelError::internal("Receipt delivery failed"));
+        }
+        Ok(())
+    }
+    
+    // PERIPHERAL: Queues the committed receipt on the printers serving the terminal (the
+    // transaction's own when None); returns their names
+    fn spool_receipt(&self, handle: u64, terminal_id: Option<&str>, reprint: bool) -> Result<Vec<String>, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Committed {
+            return Err(KernelError::invalid_state("Receipts are only delivered for committed transactions"));
+        }
+        
+        let terminal_id = terminal_id.or(tx.terminal_id.as_deref());
+        self.spooler.enqueue(&self.build_receipt(&tx), terminal_id, reprint).map_err(KernelError::Validation)
+    }
+    
+    // PERIPHERAL: Reprints are journaled, since a duplicate receipt can be presented again for a
+    // return
+    fn reprint_receipt(&mut self, handle: u64, terminal_id: Option<String>) -> Result<(), KernelError> {
+        if terminal_id.as_ref().is_some_and(|t| t.is_empty() || t.len() > self.system_config.max_attribute_key_len) {
+            return Err(KernelError::validation("Terminal ID length out of range"));
+        }
+        
+        let printers = self.spool_receipt(handle, terminal_id.as_deref(), true)?;
+        if printers.is_empty() {
+            return Err(KernelError::invalid_state("No receipt printer for terminal"));
+        }
+        
+        self.journal.record(handle, JournalOperation::ReceiptReprint { printers, terminal_id });
+        self.submit_job(pipeline::Job::Print(Arc::clone(&self.spooler)));
+        Ok(())
+    }
+    
+    fn publish_print_outcomes(&self, outcomes: Vec<peripheral::printer::PrintOutcome>) {
+        for outcome in outcomes {
+            self.events.publish(outcome.job.receipt_number, EventKind::ReceiptPrint { printer: outcome.printer, job: outcome.job });
+        }
+    }
+    
+    // REPLICATION: Turns this kernel into a read-only standby of the primary at `address`
+    fn start_replica(&self, address: &str) -> Result<(), KernelError> {
+        if self.active_transactions.count_where(|_| true) > 0 {
+            return Err(KernelError::invalid_state("A replica cannot have open transactions"));
+        }
+        self.replication.start_replica(address)?;
+        self.journal.set_read_only(true);
+        Ok(())
+    }
+    
+    // REPLICATION: Takes over from a failed primary. Handles continue past every handle in the
+    // replicated journal, so no receipt number is issued twice.
+    fn promote_replica(&mut self) -> Result<(), KernelError> {
+        let replicated_through = self.replication.promote()?;
+        self.journal.set_read_only(false);
+        self.resume_from_journal().map_err(KernelError::Internal)?;
+        
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::ReplicaPromote { replicated_through });
+        Ok(())
+    }
+    
+    // RESILIENCE: Picks up where the journal leaves off, on start, promotion and restore.
+    // Handles continue past every handle journaled, so a terminal never reissues a receipt
+    // number, and the central sync ledger is rebuilt from the batches it journaled.
+    fn resume_from_journal(&mut self) -> Result<(), String> {
+        let (mut last_tx, mut last_quote) = (0, 0);
+        self.journal.scan_after(0, |entry| {
+            match entry.transaction_handle & PK_QUOTE_HANDLE_FLAG {
+                0 => last_tx = last_tx.max(entry.transaction_handle),
+                _ => last_quote = last_quote.max(entry.transaction_handle & !PK_QUOTE_HANDLE_FLAG),
+            }
+            true
+        })?;
+        self.next_tx_id.fetch_max(last_tx + 1, Ordering::SeqCst);
+        self.next_quote_id.fetch_max(last_quote + 1, Ordering::SeqCst);
+        
+        let ledger = sync::SyncLedger::from_journal(&self.journal, self.system_config.sync_ledger_receipts)?;
+        *self.sync_ledger.lock().unwrap_or_else(PoisonError::into_inner) = ledger;
+        self.refunds = refund::RefundTracker::from_journal(&self.journal)?;
+        Ok(())
+    }
+    
+    // BACKUP: The state a backup is written from, captured in one step under the exclusive
+    // store lock so the journal position, archive and open transactions agree
+    fn backup_snapshot(&self) -> backup::BackupSnapshot {
+        let mut active = Vec::new();
+        self.active_transactions.for_each(|tx| active.push(tx.clone()));
+        backup::BackupSnapshot {
+            journal: Arc::clone(&self.journal),
+            journal_through: self.journal.last_sequence(),
+            next_transaction_handle: self.next_tx_id.load(Ordering::SeqCst),
+            next_quote_handle: self.next_quote_id.load(Ordering::SeqCst),
+            archive: self.archive.snapshot(),
+            active,
+        }
+    }
+    
+    // BACKUP: Fills an empty kernel from a verified backup. The journal is replayed as
+    // written, the archive takes the rebuilt hash chain, and open transactions are reinstated
+    // with their quote references and pickup codes.
+    fn restore_backup(&mut self, backup_id: String, restored: backup::VerifiedBackup) -> Result<(), KernelError> {
+        self.replication.check_writable()?;
+        if self.journal.last_sequence() > 0 || self.archive.len() > 0 || self.active_transactions.count_where(|_| true) > 0 {
+            return Err(KernelError::invalid_state(backup::RESTORE_NOT_EMPTY));
+        }
+        
+        for entry in restored.entries {
+            self.journal.append_replicated(entry).map_err(KernelError::Internal)?;
+        }
+        let mut archive = restored.archive;
+        if let Some(backend) = self.archive.backend() {
+            archive.set_backend(backend);
+        }
+        self.archive = archive;
+        *self.report_snapshot.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        
+        for tx in restored.active {
+            if let Some(reference) = &tx.quote_reference {
+                self.saved_quotes.insert(reference.clone(), tx.id);
+            }
+            if let Some(pickup) = &tx.pickup {
+                self.pickup_orders.insert(pickup.code.clone(), tx.id);
+            }
+            self.active_transactions.insert(tx);
+        }
+        self.next_tx_id.fetch_max(restored.manifest.next_transaction_handle, Ordering::SeqCst);
+        self.next_quote_id.fetch_max(restored.manifest.next_quote_handle, Ordering::SeqCst);
+        self.resume_from_journal().map_err(KernelError::Internal)?;
+        self.rebuild_sales();
+        
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::BackupRestore {
+            backup_id,
+            journal_through: restored.manifest.journal_through,
+        });
+        Ok(())
+    }
+    
+    // Rebuilds the hourly sales from the archive and the committed transactions not yet archived
+    fn rebuild_sales(&mut self) {
+        self.sales.reset(self.system_config.business_day(), self.system_config.sales_aggregation_days);
+        let sales = &mut self.sales;
+        if let Err(e) = self.archive.snapshot().scan(|transactions| transactions.for_each(|tx| sales.record(&tx))) {
+            eprintln!("WARNING: Hourly sales not rebuilt from the archive: {}", e);
+        }
+        self.active_transactions.for_each(|tx| sales.record(tx));
+    }
+    
+    // SYNC: Claims the next batch of commits to forward upstream; None when no upstream is
+    // registered, nothing is pending or a batch is already in flight. Commits made while the
+    // upstream is unreachable only retry it every `sync_retry_interval_secs`; `force` retries
+    // at once.
+    fn sync_batch(&self, force: bool) -> Option<sync::PendingBatch> {
+        let forwarder = self.sync_forwarder.as_ref()?;
+        let config = &self.system_config;
+        let (batch, commits) = forwarder.claim(&self.journal, config.sync_batch_size, force, config.sync_retry_interval_secs)?;
+        Some(sync::PendingBatch {
+            forwarder: Arc::clone(forwarder),
+            journal: Arc::clone(&self.journal),
+            batch,
+            commits,
+        })
+    }
+    
+    // SYNC: Records the central service's answer to a batch, and claims the next one while the
+    // cursor keeps moving. Unreachable upstreams are retried on the next commit or pk_sync_now.
+    fn finish_sync(&self, pending: &sync::PendingBatch, result: Result<sync::SyncResponse, String>) -> Option<sync::PendingBatch> {
+        let moved = pending.forwarder.finish(&result);
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("WARNING: Sync upstream unreachable, continuing offline: {}", e);
+                return None;
+            },
+        };
+        
+        if let Some(through_sequence) = moved {
+            self.journal.record(PK_INVALID_HANDLE, JournalOperation::SyncAcknowledge {
+                through_sequence,
+                accepted: response.accepted.len(),
+                conflicts: response.conflicts.len(),
+            });
+        }
+        for conflict in response.conflicts {
+            self.events.publish(PK_INVALID_HANDLE, EventKind::SyncConflict { conflict });
+        }
+        // Another forwarder may have been registered meanwhile
+        let current = self.sync_forwarder.as_ref().is_some_and(|f| Arc::ptr_eq(f, &pending.forwarder));
+        moved.filter(|_| current).and_then(|_| self.sync_batch(true))
+    }
+    
+    // SYNC: The central service's side of a batch; conflicts are published here as well
+    fn ingest_sync_batch(&self, batch: &sync::SyncBatch) -> Result<sync::SyncResponse, KernelError> {
+        self.replication.check_writable()?;
+        let mut ledger = self.sync_ledger.lock().unwrap_or_else(PoisonError::into_inner);
+        let (response, ingested) = ledger.ingest(batch, self.system_config.sync_ledger_receipts);
+        // Journaled before the terminal hears of it; the ledger is rebuilt from these entries
+        if let Some(operation) = ingested {
+            self.journal.record(PK_INVALID_HANDLE, operation);
+        }
+        drop(ledger);
+        for conflict in &response.conflicts {
+            self.events.publish(PK_INVALID_HANDLE, EventKind::SyncConflict { conflict: conflict.clone() });
+        }
+        Ok(response)
+    }
+    
+    fn publish_erp_outcomes(&self, outcomes: Vec<erp::PushOutcome>) {
+        for outcome in outcomes {
+            self.events.publish(outcome.message.receipt_number, EventKind::ErpPush { connector: outcome.connector, message: outcome.message });
+        }
+    }
+    
+    // PERIPHERAL: The drawer opens for a committed transaction that took or paid out cash
+    fn cash_drawer_open(&self, handle: u64) -> Option<peripheral::drawer::DrawerOpen> {
+        let driver = self.drawer_driver.as_ref()?;
+        let tx = self.active_transactions.get(handle).ok()??;
+        if !tx.tenders.iter().any(|t| t.kind == TenderKind::Cash && t.amount_minor != 0) {
+            return None;
+        }
+        // A recycler on the terminal takes the drawer's place
+        if tx.terminal_id.as_ref().is_some_and(|t| self.recyclers.contains_key(t)) {
+            return None;
+        }
+        Some(peripheral::drawer::DrawerOpen {
+            handle,
+            terminal_id: tx.terminal_id.clone(),
+            operator_id: tx.operator_id.clone(),
+            reason: peripheral::DrawerOpenReason::CashTender,
+            driver: Arc::clone(driver),
+        })
+    }
+    
+    // PERIPHERAL: The terminal's recycler pays out the change and cash refunds of a committed
+    // transaction
+    fn change_dispense(&self, handle: u64) -> Option<peripheral::recycler::ChangeDispense> {
+        let tx = self.active_transactions.get(handle).ok()??;
+        let terminal_id = tx.terminal_id.clone()?;
+        let recycler = self.recyclers.get(&terminal_id)?;
+        let refunded_minor: i64 = tx.tenders.iter()
+            .filter(|t| t.kind == TenderKind::Cash && t.amount_minor < 0)
+            .map(|t| -t.amount_minor)
+            .sum();
+        let amount_minor = tx.change_minor() + refunded_minor;
+        (amount_minor > 0).then(|| peripheral::recycler::ChangeDispense {
+            handle,
+            terminal_id,
+            amount_minor,
+            recycler: Arc::clone(recycler),
+        })
+    }
+    
+    fn record_change_dispense(&self, dispense: &peripheral::recycler::ChangeDispense, outcome: Result<Vec<cash::DenominationCount>, String>) -> Result<i64, KernelError> {
+        let recorded = self.record_recycler_activity(
+            dispense.handle,
+            &dispense.terminal_id,
+            dispense.recycler.name(),
+            peripheral::RecyclerOperation::Dispense,
+            dispense.amount_minor,
+            outcome,
+        );
+        let (_, dispensed_minor) = recorded?;
+        if dispensed_minor < dispense.amount_minor {
+            eprintln!("WARNING: Recycler dispensed {} of {} for transaction {}", dispensed_minor, dispense.amount_minor, dispense.handle);
+        }
+        Ok(dispensed_minor)
+    }
+    
+    // Finds the recycler for a deposit toward a Building transaction's balance due; returns the
+    // terminal, the balance due and the recycler
+    fn recycler_deposit(&self, handle: u64) -> Result<(String, i64, Arc<dyn peripheral::CashRecycler>), KernelError> {
+        let tx = self.transaction(handle)?;
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        let terminal_id = tx.terminal_id.clone().ok_or_else(|| KernelError::not_found("No cash recycler for terminal"))?;
+        let recycler = self.recyclers.get(&terminal_id).ok_or_else(|| KernelError::not_found("No cash recycler for terminal"))?;
+        let due_minor = tx.total_minor().saturating_sub(tx.tendered_minor);
+        if due_minor <= 0 {
+            return Err(KernelError::invalid_state("Transaction has no balance due"));
+        }
+        Ok((terminal_id, due_minor, Arc::clone(recycler)))
+    }
+    
+    // AUDIT COMPLIANCE: Journals what a recycler reported, with the terminal's expected drawer
+    // cash. A count the kernel cannot total is journaled as a device error. Returns the counts
+    // and their total.
+    fn record_recycler_activity(
+        &self,
+        handle: u64,
+        terminal_id: &str,
+        recycler: &str,
+        operation: peripheral::RecyclerOperation,
+        requested_minor: i64,
+        outcome: Result<Vec<cash::DenominationCount>, String>,
+    ) -> Result<(Vec<cash::DenominationCount>, i64), KernelError> {
+        let outcome = outcome.map_err(KernelError::Internal)
+            .and_then(|counts| cash::count_total(&counts).map(|total| (counts, total)));
+        let (denominations, amount_minor, error) = match &outcome {
+            Ok((counts, total)) => (counts.clone(), *total, None),
+            Err(e) => {
+                eprintln!("WARNING: Cash recycler {} failed: {}", recycler, e);
+                (Vec::new(), 0, Some(e.to_string()))
+            },
+        };
+        let expected_minor = self.drawer_sessions.session(terminal_id).map(|s| s.expected_minor);
+        self.journal.record(handle, JournalOperation::RecyclerActivity {
+            terminal_id: terminal_id.to_string(),
+            recycler: recycler.to_string(),
+            operation,
+            requested_minor,
+            denominations,
+            amount_minor,
+            expected_minor,
+            error: error.clone(),
+        });
+        self.events.publish(handle, EventKind::RecyclerActivity {
+            terminal_id: terminal_id.to_string(),
+            recycler: recycler.to_string(),
+            operation,
+            requested_minor,
+            amount_minor,
+            error,
+        });
+        outcome
+    }
+    
+    fn recycler_inventory_json(&self, terminal_id: &str, recycler: &str, outcome: Result<Vec<cash::DenominationCount>, String>) -> Result<String, KernelError> {
+        let (inventory, inventory_minor) = self.record_recycler_activity(
+            PK_INVALID_HANDLE,
+            terminal_id,
+            recycler,
+            peripheral::RecyclerOperation::Inventory,
+            0,
+            outcome,
+        )?;
+        let expected_minor = self.drawer_sessions.session(terminal_id).map(|s| s.expected_minor);
+        let report = peripheral::RecyclerInventory {
+            recycler: recycler.to_string(),
+            inventory,
+            inventory_minor,
+            expected_minor,
+            variance_minor: expected_minor.map(|expected| inventory_minor.saturating_sub(expected)),
+        };
+        serde_json::to_string(&report)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize recycler inventory: {}", e)))
+    }
+    
+    // PERIPHERAL: Keeps a driver's report on its device; a change of health is published
+    fn report_peripheral_status(&self, terminal_id: &str, device: &str, kind: peripheral::DeviceKind, health: peripheral::DeviceHealth, detail: Option<&str>) -> Result<(), KernelError> {
+        let max_len = self.system_config.max_attribute_key_len;
+        for (field, value) in [(TextField::TerminalId, terminal_id), (TextField::DeviceName, device)] {
+            if value.is_empty() || value.len() > max_len {
+                return Err(KernelError::validation("Terminal or device name length out of range"));
+            }
+            pci::screen(field, value)?;
+        }
+        let detail = match detail {
+            Some(detail) if detail.len() > self.system_config.max_attribute_value_len => {
+                return Err(KernelError::validation("Peripheral status detail too long"));
+            },
+            detail => pci::screen_opt(TextField::DeviceDetail, detail.map(str::to_string))?,
+        };
+        
+        let status = peripheral::DeviceStatus {
+            device: device.to_string(),
+            kind,
+            health,
+            detail: detail.clone(),
+            reported_at: Some(Utc::now()),
+        };
+        if let Some(previous) = self.peripherals.report(terminal_id, status) {
+            self.events.publish(PK_INVALID_HANDLE, EventKind::PeripheralStatusChanged {
+                terminal_id: terminal_id.to_string(),
+                device: device.to_string(),
+                kind,
+                health,
+                previous,
+                detail,
+            });
+        }
+        Ok(())
+    }
+    
+    fn terminal_health(&self, terminal_id: &str) -> peripheral::TerminalHealth {
+        let printers = self.spooler.status();
+        self.peripherals.terminal_health(terminal_id, &printers, self.system_config.peripheral_status_ttl_secs, Utc::now())
+    }
+    
+    // Every terminal with reported devices or its own printers, worst health first
+    fn readiness(&self) -> peripheral::Readiness {
+        let printers = self.spooler.status();
+        let mut terminals: BTreeSet<String> = self.peripherals.terminals().into_iter().collect();
+        terminals.extend(printers.iter().filter_map(|p| p.terminal_id.clone()));
+        
+        let now = Utc::now();
+        let mut degraded: Vec<peripheral::TerminalHealth> = terminals.iter()
+            .map(|t| self.peripherals.terminal_health(t, &printers, self.system_config.peripheral_status_ttl_secs, now))
+            .filter(|t| t.health != peripheral::DeviceHealth::Ok)
+            .collect();
+        degraded.sort_by_key(|t| std::cmp::Reverse(t.health));
+        peripheral::Readiness {
+            health: degraded.first().map_or(peripheral::DeviceHealth::Ok, |t| t.health),
+            degraded,
+        }
+    }
+    
+    // PERIPHERAL: A no-sale opens the drawer outside any transaction
+    fn no_sale(&self, terminal_id: Option<String>, operator_id: Option<String>) -> Result<peripheral::drawer::DrawerOpen, KernelError> {
+        self.replication.check_writable()?;
+        self.check_terminal_ids(terminal_id.as_deref(), operator_id.as_deref())?;
+        
+        let driver = self.drawer_driver.clone().ok_or_else(|| KernelError::invalid_state("No cash drawer driver registered"))?;
+        Ok(peripheral::drawer::DrawerOpen {
+            handle: PK_INVALID_HANDLE,
+            terminal_id,
+            operator_id,
+            reason: peripheral::DrawerOpenReason::NoSale,
+            driver,
+        })
+    }
+    
+    // AUDIT COMPLIANCE: Every drawer opening is journaled with the driver's outcome
+    fn record_drawer_open(&self, open: &peripheral::drawer::DrawerOpen, outcome: Result<(), String>) -> Result<(), KernelError> {
+        let driver = open.driver.name().to_string();
+        let error = outcome.as_ref().err().cloned();
+        if let Some(e) = &error {
+            eprintln!("WARNING: Cash drawer did not open: {}", e);
+        }
+        self.journal.record(open.handle, JournalOperation::DrawerOpen {
+            reason: open.reason,
+            driver: driver.clone(),
+            terminal_id: open.terminal_id.clone(),
+            operator_id: open.operator_id.clone(),
+            error: error.clone(),
+        });
+        self.events.publish(open.handle, EventKind::DrawerOpen { reason: open.reason, driver, error });
+        outcome.map_err(KernelError::Internal)
+    }
+    
+    fn add_cash_tender_legal(&self, handle: u64, amount_minor: i64, token: Option<String>, currency: Option<&str>) -> Result<bool, KernelError> {
+        if let Some(currency) = currency {
+            self.check_tender_currency(handle, currency)?;
+        }
+        if self.token_tendered(handle, token.as_deref())? {
+            return Ok(false);
+        }
+        self.add_tender_legal(handle, Tender::cash(amount_minor).with_token(token), |_| Ok(()))
+    }
+    
+    // EMV: Records a card payment approved by the payment device. The device result can reach
+    // the kernel more than once (a callback retried after a timeout), so an authorization
+    // reference already tendered on the transaction tenders nothing and succeeds again.
+    fn add_card_tender_legal(&self, handle: u64, amount_minor: i64, auth_reference: String) -> Result<bool, KernelError> {
+        let auth_reference = pci::screen(TextField::AuthorizationReference, &auth_reference)?.into_owned();
+        
+        if auth_reference.is_empty() || auth_reference.len() > self.system_config.max_attribute_key_len {
+            return Err(KernelError::validation("Authorization reference length out of range"));
+        }
+        
+        // The retry may come after the sale was committed and archived
+        let tx = self.transaction(handle)?;
+        if tx.tenders.iter().any(|t| t.card.as_ref().is_some_and(|c| c.auth_reference == auth_reference)) {
+            return Ok(false);
+        }
+        drop(tx);
+        
+        // Card payments never produce change
+        self.add_tender_legal(handle, Tender::card(auth_reference, amount_minor), |tx| {
+            match amount_minor > tx.total_minor().saturating_sub(tx.tendered_minor) {
+                true => Err(KernelError::validation("Card tender exceeds balance due")),
+                false => Ok(()),
+            }
+        })
+    }
+    
+    // An amount worked out in another currency would be recorded at face value, so a tender that
+    // states its currency must state the transaction's (codes compare case-insensitively)
+    fn check_tender_currency(&self, handle: u64, currency: &str) -> Result<(), KernelError> {
+        let tx = self.transaction(handle)?;
+        if !tx.currency.code.eq_ignore_ascii_case(currency) {
+            return Err(KernelError::CurrencyMismatch);
+        }
+        Ok(())
+    }
+    
+    // IDEMPOTENCY: Whether a tender was already recorded under a client token. Looked up before
+    // any state check, so a retry still succeeds after the original tender committed the sale.
+    fn token_tendered(&self, handle: u64, token: Option<&str>) -> Result<bool, KernelError> {
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        if token.is_empty() || token.len() > self.system_config.max_tender_token_len {
+            return Err(KernelError::validation("Tender token length out of range"));
+        }
+        pci::screen(TextField::TenderToken, token)?;
+        
+        let tx = self.transaction(handle)?;
+        Ok(tx.tenders.iter().any(|t| t.token.as_deref() == Some(token)))
+    }
+    
+    // LOYALTY: Converts points to a monetary value at the configured rate; points can never
+    // produce change, and total points per transaction are capped by configuration
+    fn points_tender_value(&self, handle: u64, points: i64) -> Result<i64, KernelError> {
+        let value_minor = self.points_value(points)?;
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        self.check_points_tender(&tx, points, value_minor)?;
+        Ok(value_minor)
+    }
+    
+    fn points_value(&self, points: i64) -> Result<i64, KernelError> {
+        let config = &self.system_config;
+        if points <= 0 || config.points_rate_points <= 0 || config.points_rate_minor <= 0 {
+            return Err(KernelError::validation("Invalid points redemption"));
+        }
+        
+        let value_minor = points.checked_mul(config.points_rate_minor)
+            .ok_or_else(|| KernelError::validation("Points value overflow"))? / config.points_rate_points;
+        if value_minor == 0 {
+            return Err(KernelError::validation("Points value rounds to zero"));
+        }
+        Ok(value_minor)
+    }
+    
+    fn check_points_tender(&self, tx: &Transaction, points: i64, value_minor: i64) -> Result<(), KernelError> {
+        let max_points = self.system_config.max_points_per_transaction;
+        if max_points > 0 && tx.points_tendered() + points > max_points {
+            return Err(KernelError::validation("Points redemption cap exceeded"));
+        }
+        
+        if value_minor > tx.total_minor() - tx.tendered_minor {
+            return Err(KernelError::validation("Points value exceeds amount due"));
+        }
+        Ok(())
+    }
+    
+    // Returns the tender's value and whether it committed the sale
+    fn add_points_tender_legal(&self, handle: u64, points: i64) -> Result<(i64, bool), KernelError> {
+        let value_minor = self.points_value(points)?;
+        let committed = self.add_tender_legal(handle, Tender::points(points, value_minor), |tx| self.check_points_tender(tx, points, value_minor))?;
+        Ok((value_minor, committed))
+    }
+    
+    // Tenders are applied under the transaction's shard lock, so `check` sees the amounts as
+    // they are by then; a token or authorization reference already tendered tenders nothing.
+    // The sale stays open once fully tendered. Only the commit_on_full_tender compatibility
+    // setting commits it here instead of in finalize_transaction, and then the caller records
+    // the commit with the store held exclusively. Returns whether the sale committed.
+    fn add_tender_legal(&self, handle: u64, tender: Tender, check: impl FnOnce(&Transaction) -> Result<(), KernelError>) -> Result<bool, KernelError> {
+        let (commit_on_full_tender, max_tender_minor) = (self.system_config.commit_on_full_tender, self.system_config.max_tender_minor);
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        let repeated = tx.tenders.iter().any(|t| {
+            (tender.token.is_some() && t.token == tender.token)
+                || tender.card.as_ref().is_some_and(|card| t.card.as_ref().is_some_and(|c| c.auth_reference == card.auth_reference))
+        });
+        if repeated {
+            return Ok(false);
+        }
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot be tendered"));
+        }
+        
+        if tx.open_tab_authorization().is_some() {
+            return Err(KernelError::invalid_state("Open tabs are settled by closing the tab"));
+        }
+        
+        if !tx.held_lines.is_empty() {
+            return Err(KernelError::invalid_state(LINES_HELD));
+        }
+        
+        if !tx.has_live_lines() {
+            return Err(KernelError::invalid_state("Transaction has no lines to tender"));
+        }
+        
+        if tender.amount_minor > 0 && tx.total_minor() < 0 {
+            return Err(KernelError::invalid_state("A refund is due; use a refund tender"));
+        }
+        
+        check(&tx)?;
+        tx.check_new_tender(max_tender_minor, tender.amount_minor)?;
+        let (kind, amount_minor, points) = (tender.kind, tender.amount_minor, tender.points);
+        let token = tender.token.clone();
+        let auth_reference = tender.card.as_ref().map(|c| c.auth_reference.clone());
+        tx.add_tender(tender);
+        let committed = commit_on_full_tender && tx.fully_tendered();
+        if committed {
+            tx.state = TxState::Committed;
+        }
+        let tendered_minor = tx.tendered_minor;
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::TenderAdd { amount_minor, kind, points, token, auth_reference });
+        self.events.publish(handle, EventKind::TenderAdded { amount_minor, tendered_minor });
+        self.publish_display(handle, |tx| vec![DisplayUpdateKind::total_due(tx)]);
+        Ok(committed)
+    }
+    
+    // Commits a fully tendered sale; TSE signing and receipt delivery follow from here, so they
+    // see any adjustments made after the last tender
+    fn finalize_transaction(&mut self, handle: u64) -> Result<(), KernelError> {
+        let tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot be finalized"));
+        }
+        
+        if tx.open_tab_authorization().is_some() {
+            return Err(KernelError::invalid_state("Open tabs are settled by closing the tab"));
+        }
+        
+        if !tx.held_lines.is_empty() {
+            return Err(KernelError::invalid_state(LINES_HELD));
+        }
+        
+        if tx.fuel_sales.iter().any(fuel::FuelSale::is_open) {
+            return Err(KernelError::invalid_state("Fuel sale awaits the dispenser reading"));
+        }
+        
+        if !tx.has_live_lines() || !tx.fully_tendered() {
+            return Err(KernelError::invalid_state("Transaction not fully tendered"));
+        }
+        drop(tx);
+        
+        // RULES: Lines the hook adds must be tendered too
+        self.run_rules(rules::Hook::BeforeFinalize, handle, None)?;
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        if !tx.fully_tendered() {
+            return Err(KernelError::invalid_state("Transaction not fully tendered"));
+        }
+        
+        tx.state = TxState::Committed;
+        drop(tx);
+        self.record_commit(handle);
+        Ok(())
+    }
+    
+    // RULES: Runs the rules script's `hook` for the transaction and applies the actions it asks
+    // for. Only a reject fails the operation; a hook that fails, or an action that cannot be
+    // applied, is reported and skipped.
+    fn run_rules(&self, hook: rules::Hook, handle: u64, line_id: Option<u32>) -> Result<(), KernelError> {
+        let script = match &self.rules {
+            Some(script) if script.handles(hook) => Arc::clone(script),
+            _ => return Ok(()),
+        };
+        let transaction = serde_json::to_value(self.transaction_snapshot(handle)?)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize transaction: {}", e)))?;
+        let mut inputs = Vec::new();
+        if let Some(line_id) = line_id {
+            inputs.push(("line", rules::find_line(&transaction, line_id)));
+        }
+        inputs.push(("transaction", transaction));
+        
+        let actions = match script.run(hook, inputs) {
+            Ok(actions) => actions,
+            Err(e) => {
+                eprintln!("WARNING: Rules script {} hook skipped: {}", hook, e);
+                return Ok(());
+            },
+        };
+        if let Some(rules::Action::Reject(message)) = actions.iter().find(|a| matches!(a, rules::Action::Reject(_))) {
+            return Err(KernelError::InvalidState(format!("Rejected by store rules: {}", message)));
+        }
+        for action in actions {
+            let applied = match action {
+                rules::Action::AddLine { sku, qty, unit_minor } => self.add_line_legal(handle, sku, qty, unit_minor).map(|_| ()),
+                rules::Action::SetAttribute { key, value } => self.set_transaction_attribute(handle, key, value),
+                rules::Action::Reject(_) => Ok(()),
+            };
+            if let Err(e) = applied {
+                eprintln!("WARNING: Rules script {} action not applied to transaction {}: {}", hook, handle, e);
+            }
+        }
+        Ok(())
+    }
+    
+    // RULES: The after_add_line hook, for lines a lane adds
+    fn after_add_line(&self, handle: u64, line_id: u32) {
+        if let Err(e) = self.run_rules(rules::Hook::AfterAddLine, handle, Some(line_id)) {
+            eprintln!("WARNING: Rules script after_add_line hook skipped: {}", e);
+        }
+    }
+    
+    // LAYAWAY: Freezes the basket and records the initial deposit; the deposit must meet the
+    // configured minimum and must not pay the basket in full (that is a normal sale)
+    fn create_layaway(&mut self, handle: u64, deposit_minor: i64) -> Result<(), KernelError> {
+        let (min_percent, max_tender_minor) = (self.system_config.layaway_min_deposit_percent, self.system_config.max_tender_minor);
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot be placed on layaway"));
+        }
+        
+        if tx.open_tab_authorization().is_some() {
+            return Err(KernelError::invalid_state("Open tabs cannot be placed on layaway"));
+        }
+        
+        if !tx.held_lines.is_empty() {
+            return Err(KernelError::invalid_state(LINES_HELD));
+        }
+        
+        let total_minor = tx.total_minor();
+        if tx.lines.is_empty() || total_minor <= 0 {
+            return Err(KernelError::validation("Layaway requires a basket with a positive total"));
+        }
+        
+        let remaining_minor = total_minor - tx.tendered_minor;
+        if deposit_minor <= 0 || deposit_minor >= remaining_minor {
+            return Err(KernelError::validation("Layaway deposit must be positive and less than the amount due"));
+        }
+        
+        tx.check_new_tender(max_tender_minor, deposit_minor)?;
+        // In i128, so a large basket cannot wrap the comparison
+        let paid_minor = i128::from(tx.tendered_minor) + i128::from(deposit_minor);
+        if paid_minor * 100 < i128::from(total_minor) * i128::from(min_percent) {
+            return Err(KernelError::validation("Layaway deposit below configured minimum"));
+        }
+        
+        tx.tendered_minor += deposit_minor;
+        tx.tenders.push(Tender::cash(deposit_minor));
+        tx.state = TxState::Layaway;
+        tx.layaway = Some(LayawayInfo { deposit_minor, ..LayawayInfo::default() });
+        
+        let tendered_minor = tx.tendered_minor;
+        self.journal.record(handle, JournalOperation::LayawayCreate { deposit_minor });
+        self.events.publish(handle, EventKind::TenderAdded { amount_minor: deposit_minor, tendered_minor });
+        Ok(())
+    }
+    
+    // LAYAWAY: Partial payment against a stored layaway; completes the sale when fully paid.
+    // Returns true when the layaway was converted to a completed sale.
+    fn add_layaway_payment(&mut self, handle: u64, amount_minor: i64) -> Result<bool, KernelError> {
+        let max_tender_minor = self.system_config.max_tender_minor;
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Layaway {
+            return Err(KernelError::invalid_state("Transaction is not an open layaway"));
+        }
+        
+        if amount_minor <= 0 {
+            return Err(KernelError::validation("Payment amount must be positive"));
+        }
+        
+        tx.check_new_tender(max_tender_minor, amount_minor)?;
+        tx.tendered_minor += amount_minor;
+        tx.tenders.push(Tender::cash(amount_minor));
+        if let Some(layaway) = tx.layaway.as_mut() {
+            layaway.payment_count += 1;
+        }
+        
+        let tendered_minor = tx.tendered_minor;
+        let completed = tendered_minor >= tx.total_minor();
+        if completed {
+            tx.state = TxState::Committed;
+        }
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::LayawayPayment { amount_minor });
+        self.events.publish(handle, EventKind::TenderAdded { amount_minor, tendered_minor });
+        self.publish_display(handle, |tx| vec![DisplayUpdateKind::total_due(tx)]);
+        if completed {
+            self.record_commit(handle);
+        }
+        Ok(completed)
+    }
+    
+    // LAYAWAY: Cancels an open layaway, retaining the restocking fee; returns the refund due
+    fn cancel_layaway(&mut self, handle: u64, restocking_fee_minor: i64) -> Result<i64, KernelError> {
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Layaway {
+            return Err(KernelError::invalid_state("Transaction is not an open layaway"));
+        }
+        
+        if restocking_fee_minor < 0 || restocking_fee_minor > tx.tendered_minor {
+            return Err(KernelError::validation("Restocking fee must be between zero and the amount paid"));
+        }
+        
+        let refund_minor = tx.tendered_minor - restocking_fee_minor;
+        tx.state = TxState::Cancelled;
+        if let Some(layaway) = tx.layaway.as_mut() {
+            layaway.restocking_fee_minor = Some(restocking_fee_minor);
+            layaway.refund_minor = Some(refund_minor);
+        }
+        let released: Vec<_> = std::mem::take(&mut tx.stock_reservations).into_values().collect();
+        drop(tx);
+        self.release_stock(&released);
+        
+        self.journal.record(handle, JournalOperation::LayawayCancel { restocking_fee_minor, refund_minor });
+        self.events.publish(handle, EventKind::LayawayCancelled { restocking_fee_minor, refund_minor });
+        self.submit_job(pipeline::Job::Archive { handle });
+        Ok(refund_minor)
+    }
+    
+    // Cancels a sale the customer walked away from before paying, releasing its reserved stock.
+    // The lines stay on the transaction for the audit trail.
+    fn abandon_transaction(&mut self, handle: u64, reason: &str) -> Result<(), KernelError> {
+        let reason = pci::screen(TextField::Reason, reason)?.into_owned();
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if !tx.tenders.is_empty() {
+            return Err(KernelError::invalid_state("Tendered transactions cannot be abandoned"));
+        }
+        
+        tx.state = TxState::Cancelled;
+        let released: Vec<_> = std::mem::take(&mut tx.stock_reservations).into_values().collect();
+        drop(tx);
+        self.release_stock(&released);
+        
+        self.journal.record(handle, JournalOperation::TransactionAbandon { reason: reason.clone() });
+        self.events.publish(handle, EventKind::TransactionAbandoned { reason });
+        self.submit_job(pipeline::Job::Archive { handle });
+        Ok(())
+    }
+    
+    // "Same again": starts a new sale with the source's sale lines (and their children), at the
+    // original prices or re-priced through the registered price provider. Voided lines, manual
+    // adjustments and deposit returns are not repeated. The source may be in any state.
+    fn clone_transaction(&mut self, source_handle: u64, current_prices: bool) -> Result<u64, KernelError> {
+        let source = self.transaction(source_handle)?;
+        
+        if source.kind != TransactionKind::Sale {
+            return Err(KernelError::invalid_state("Only sales can be cloned"));
+        }
+        
+        if current_prices && self.price_provider.is_none() {
+            return Err(KernelError::invalid_state("No price provider registered"));
+        }
+        
+        let mut skipped = HashSet::new();
+        for line in &source.lines {
+            let repeatable = line.qty != 0 && matches!(line.kind, LineKind::Sale | LineKind::Deposit);
+            if !repeatable {
+                skipped.extend(source.line_group(line.line_id));
+            }
+        }
+        
+        let currency_code = source.currency.code.clone();
+        let mut lines: LineItems = source.lines.iter()
+            .filter(|l| !skipped.contains(&l.line_id))
+            .cloned()
+            .collect();
+        drop(source);
+        
+        if lines.is_empty() {
+            return Err(KernelError::invalid_state("Transaction has no lines to repeat"));
+        }
+        
+        if let (true, Some(provider)) = (current_prices, self.price_provider.as_ref()) {
+            for line in &mut lines {
+                if let Some(unit_minor) = provider.current_price(&line.sku, &currency_code) {
+                    line.unit_minor = unit_minor;
+                }
+            }
+        }
+        
+        let id = self.begin_derived_transaction(source_handle, lines, false)?;
+        self.journal.record(id, JournalOperation::TransactionClone { source_handle, current_prices });
+        Ok(id)
+    }
+    
+    // Split operations move lines out of an untendered Building sale
+    fn validate_split_source(&self, handle: u64) -> Result<shard::TxRef<'_>, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind != TransactionKind::Sale {
+            return Err(KernelError::invalid_state("Only sales can be split"));
+        }
+        
+        if !tx.tenders.is_empty() {
+            return Err(KernelError::invalid_state("Tendered transactions cannot be split"));
+        }
+        
+        if !tx.taxes.is_empty() {
+            return Err(KernelError::invalid_state("Clear taxes before splitting; they are recalculated per transaction"));
+        }
+        
+        Ok(tx)
+    }
+    
+    // NRF COMPLIANCE: Moves each group of selected lines (with their children) into a new
+    // transaction. Selected child lines must be selected together with their parent. All groups
+    // are validated before anything moves, so the split is all-or-nothing.
+    // Returns the new transaction handles in group order.
+    fn split_transaction(&mut self, handle: u64, groups: &[Vec<u32>]) -> Result<Vec<u64>, KernelError> {
+        let tx = self.validate_split_source(handle)?;
+        
+        if groups.is_empty() || groups.iter().any(|g| g.is_empty()) {
+            return Err(KernelError::validation("Each split must select at least one line"));
+        }
+        
+        let mut claimed = HashSet::new();
+        let mut moves = Vec::with_capacity(groups.len());
+        for group in groups {
+            let selected: HashSet<u32> = group.iter().copied().collect();
+            let mut moved = HashSet::new();
+            for &line_id in group {
+                let line = tx.find_line(line_id)
+                    .ok_or_else(|| KernelError::not_found("Invalid line ID"))?;
+                
+                if line.parent_line_item_id.is_some_and(|p| !selected.contains(&p)) {
+                    return Err(KernelError::validation("Child lines must move with their parent"));
+                }
+                moved.extend(tx.line_group(line_id));
+            }
+            
+            if moved.iter().any(|id| !claimed.insert(*id)) {
+                return Err(KernelError::validation("Line selected in more than one split"));
+            }
+            moves.push(moved);
+        }
+        drop(tx);
+        
+        self.move_line_groups(handle, moves)
+    }
+    
+    // NRF COMPLIANCE: Splits a transaction N ways by distributing its line groups (a root line and
+    // its children) so the resulting totals are as even as possible. The source keeps the first
+    // share; returns the N-1 new transaction handles.
+    fn split_transaction_even(&mut self, handle: u64, ways: usize) -> Result<Vec<u64>, KernelError> {
+        let tx = self.validate_split_source(handle)?;
+        
+        if ways < 2 {
+            return Err(KernelError::validation("Split requires at least two ways"));
+        }
+        
+        let mut weighted_groups: Vec<(i64, Vec<u32>)> = tx.lines.iter()
+            .filter(|l| l.parent_line_item_id.is_none())
+            .map(|root| {
+                let group = tx.line_group(root.line_id);
+                let weight = group.iter()
+                    .filter_map(|id| tx.find_line(*id))
+                    .map(|l| l.total_minor())
+                    .sum();
+                (weight, group)
+            })
+            .collect();
+        drop(tx);
+        
+        if weighted_groups.len() < ways {
+            return Err(KernelError::validation("Not enough line groups to split"));
+        }
+        
+        // Largest groups first, each onto the currently lightest share
+        weighted_groups.sort_by_key(|(weight, _)| std::cmp::Reverse(*weight));
+        let mut shares: Vec<(i64, HashSet<u32>)> = vec![(0, HashSet::new()); ways];
+        for (weight, group) in weighted_groups {
+            if let Some(share) = shares.iter_mut().min_by_key(|(total, ids)| (*total, ids.len())) {
+                share.0 += weight;
+                share.1.extend(group);
+            }
+        }
+        
+        if shares.iter().any(|(_, ids)| ids.is_empty()) {
+            return Err(KernelError::validation("Line groups cannot be distributed evenly"));
+        }
+        
+        let moves = shares.into_iter().skip(1).map(|(_, ids)| ids).collect();
+        self.move_line_groups(handle, moves)
+    }
+    
+    fn move_line_groups(&mut self, handle: u64, moves: Vec<HashSet<u32>>) -> Result<Vec<u64>, KernelError> {
+        // Every share must fit before lines start leaving the source
+        let source = self.transaction(handle)?;
+        let (store, terminal_id) = (source.store.clone(), source.terminal_id.clone());
+        drop(source);
+        self.check_capacity(Some(&store), terminal_id.as_deref(), moves.len())?;
+        
+        let mut new_handles = Vec::with_capacity(moves.len());
+        for moved in moves {
+            // Fired lines stay fired, and weighed, held, tagged and reserved lines keep their
+            // readings, holds, tags and stock, on the transaction they move to
+            let (lines, fired, weights, holds, tags, stock) = match self.active_transactions.get_mut(handle) {
+                Some(mut tx) => {
+                    let fired: BTreeSet<u32> = tx.fired_lines.iter().copied().filter(|id| moved.contains(id)).collect();
+                    tx.fired_lines.retain(|id| !moved.contains(id));
+                    let weights = moved.iter().filter_map(|id| tx.weight_readings.remove_entry(id)).collect();
+                    let holds = moved.iter().filter_map(|id| tx.held_lines.remove_entry(id)).collect();
+                    let tags: BTreeMap<String, u32> = tx.rfid_tags.iter()
+                        .filter(|(_, id)| moved.contains(id))
+                        .map(|(epc, id)| (epc.clone(), *id))
+                        .collect();
+                    tx.rfid_tags.retain(|_, id| !moved.contains(id));
+                    let stock = moved.iter().filter_map(|id| tx.stock_reservations.remove_entry(id)).collect();
+                    (tx.take_lines(&moved), fired, weights, holds, tags, stock)
+                },
+                None => return Err(KernelError::not_found(TRANSACTION_NOT_FOUND)),
+            };
+            let line_ids: Vec<u32> = lines.iter().map(|l| l.line_id).collect();
+            let new_handle = self.begin_derived_transaction(handle, lines, true)?;
+            if let Some(mut tx) = self.active_transactions.get_mut(new_handle) {
+                tx.fired_lines = fired;
+                tx.weight_readings = weights;
+                tx.held_lines = holds;
+                tx.rfid_tags = tags;
+                tx.stock_reservations = stock;
+            }
+            
+            self.journal.record(handle, JournalOperation::LinesMove { line_ids: line_ids.clone(), transaction_handle: new_handle });
+            self.events.publish(handle, EventKind::LinesMoved { line_ids, transaction_handle: new_handle });
+            new_handles.push(new_handle);
+        }
+        Ok(new_handles)
+    }
+    
+    // TAB: Opens a tab against a card pre-authorization obtained by user space. The transaction
+    // stays in Building state so lines can be added over the life of the tab.
+    fn open_tab(&mut self, handle: u64, auth_reference: String, authorized_minor: i64) -> Result<(), KernelError> {
+        let auth_reference = pci::screen(TextField::AuthorizationReference, &auth_reference)?.into_owned();
+        
+        if auth_reference.is_empty() || auth_reference.len() > self.system_config.max_attribute_key_len {
+            return Err(KernelError::validation("Authorization reference length out of range"));
+        }
+        
+        if authorized_minor <= 0 {
+            return Err(KernelError::validation("Authorized amount must be positive"));
+        }
+        
+        let max_tender_minor = self.system_config.max_tender_minor;
+        if authorized_minor.unsigned_abs() > max_tender_minor {
+            last_error::set(format!("Authorized amount {} exceeds the limit of {}", authorized_minor, max_tender_minor));
+            return Err(KernelError::validation(OUT_OF_BOUNDS));
+        }
+        
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.kind == TransactionKind::Quote {
+            return Err(KernelError::invalid_state("Quotes cannot be opened as tabs"));
+        }
+        
+        if tx.open_tab_authorization().is_some() {
+            return Err(KernelError::invalid_state("Tab already open"));
+        }
+        
+        if tx.tendered_minor > 0 {
+            return Err(KernelError::invalid_state("Tabs must be opened before other tenders"));
+        }
+        
+        tx.tenders.push(Tender::card_pre_auth(auth_reference.clone(), authorized_minor));
+        self.journal.record(handle, JournalOperation::TabOpen { auth_reference: auth_reference.clone(), authorized_minor });
+        self.events.publish(handle, EventKind::TabOpened { auth_reference, authorized_minor });
+        Ok(())
+    }
+    
+    // TAB: Records an incremental authorization; returns the new authorized amount
+    fn increment_tab_authorization(&mut self, handle: u64, additional_minor: i64) -> Result<i64, KernelError> {
+        if additional_minor <= 0 {
+            return Err(KernelError::validation("Incremental authorization must be positive"));
+        }
+        
+        let max_tender_minor = self.system_config.max_tender_minor;
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        let card = tx.open_tab_authorization_mut()
+            .and_then(|t| t.card.as_mut())
+            .ok_or_else(|| KernelError::invalid_state("No open tab on transaction"))?;
+        let authorized_minor = match card.authorized_minor.checked_add(additional_minor) {
+            Some(authorized_minor) if authorized_minor.unsigned_abs() <= max_tender_minor => authorized_minor,
+            _ => {
+                last_error::set(format!("Authorized amount would exceed the limit of {}", max_tender_minor));
+                return Err(KernelError::validation(OUT_OF_BOUNDS));
+            }
+        };
+        card.authorized_minor = authorized_minor;
+        
+        self.journal.record(handle, JournalOperation::TabAuthIncrement { additional_minor, authorized_minor });
+        Ok(authorized_minor)
+    }
+    
+    // TAB: Captures the card for the basket total plus tip and commits the transaction. The tip
+    // is held on the tender and is not part of the sale total or change.
+    // Returns the captured amount (total plus tip).
+    fn close_tab(&mut self, handle: u64, tip_minor: i64) -> Result<i64, KernelError> {
+        let (tolerance_percent, max_tender_minor) = (self.system_config.tab_capture_tolerance_percent, self.system_config.max_tender_minor);
+        let mut tx = self.active_transactions.get_mut(handle)
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tip_minor < 0 {
+            return Err(KernelError::validation("Tip must not be negative"));
+        }
+        
+        if !tx.held_lines.is_empty() {
+            return Err(KernelError::invalid_state(LINES_HELD));
+        }
+        
+        // The tip is charged to the card like a tender, so it has the same bound
+        if tip_minor.unsigned_abs() > max_tender_minor {
+            last_error::set(format!("Tip {} exceeds the limit of {}", tip_minor, max_tender_minor));
+            return Err(KernelError::validation(OUT_OF_BOUNDS));
+        }
+        
+        let total_minor = tx.total_minor();
+        let captured_minor = match total_minor.checked_add(tip_minor) {
+            Some(captured_minor) => captured_minor,
+            None => {
+                last_error::set("Capture would overflow the amount charged".to_string());
+                return Err(KernelError::validation(OUT_OF_BOUNDS));
+            }
+        };
+        let authorized_minor = tx.open_tab_authorization()
+            .ok_or_else(|| KernelError::invalid_state("No open tab on transaction"))?
+            .authorized_minor;
+        
+        // Compared in i128 so that no authorization or tolerance can wrap the check
+        if i128::from(captured_minor) * 100 > i128::from(authorized_minor) * (100 + i128::from(tolerance_percent)) {
+            return Err(KernelError::validation("Capture exceeds authorized amount"));
+        }
+        
+        if let Some(tender) = tx.open_tab_authorization_mut() {
+            tender.amount_minor = total_minor;
+            if let Some(card) = tender.card.as_mut() {
+                card.state = CardAuthState::Captured;
+                card.tip_minor = tip_minor;
+            }
+        }
+        tx.tendered_minor += total_minor;
+        tx.state = TxState::Committed;
+        
+        let tendered_minor = tx.tendered_minor;
+        drop(tx);
+        
+        self.journal.record(handle, JournalOperation::TabCapture { captured_minor, tip_minor });
+        self.events.publish(handle, EventKind::TenderAdded { amount_minor: total_minor, tendered_minor });
+        self.publish_display(handle, |tx| vec![DisplayUpdateKind::total_due(tx)]);
+        self.record_commit(handle);
+        Ok(captured_minor)
+    }
+    
+    // TAB: Returns (authorized, total) for the open tab
+    fn get_tab_status(&self, handle: u64) -> Result<(i64, i64), KernelError> {
+        let tx = self.transaction(handle)?;
+        let card = tx.open_tab_authorization()
+            .ok_or_else(|| KernelError::invalid_state("No open tab on transaction"))?;
+        Ok((card.authorized_minor, tx.total_minor()))
+    }
+    
+    // CRM INTEGRATION: Attach (or replace) the customer reference while the transaction is open
+    fn attach_customer(&self, handle: u64, token: String, tier: Option<String>) -> Result<(), KernelError> {
+        pci::screen(TextField::CustomerToken, &token)?;
+        let tier = pci::screen_opt(TextField::CustomerTier, tier)?;
+        
+        if token.is_empty() || token.len() > self.system_config.max_customer_token_len {
+            return Err(KernelError::validation("Customer token length out of range"));
+        }
+        
+        if tier.as_ref().is_some_and(|t| t.len() > self.system_config.max_customer_token_len) {
+            return Err(KernelError::validation("Customer tier too long"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        let customer = CustomerRef { token, tier };
+        tx.customer = Some(customer.clone());
+        
+        self.journal.record(handle, JournalOperation::CustomerAttach {
+            token: customer.token.clone(),
+            tier: customer.tier.clone(),
+        });
+        self.events.publish(handle, EventKind::CustomerAttached { customer });
+        Ok(())
+    }
+    
+    // LOYALTY INTEGRATION: Activity is informational and may be recorded after commit. Once the
+    // transaction is archived it goes to the record's addendum; the sealed transaction is left
+    // as it was.
+    fn record_loyalty_activity(&mut self, handle: u64, account_id: &str, points_accrued: i64, points_redeemed: i64) -> Result<LoyaltyActivity, KernelError> {
+        if account_id.is_empty() || points_accrued < 0 || points_redeemed < 0 {
+            return Err(KernelError::validation("Invalid loyalty activity"));
+        }
+        pci::screen(TextField::LoyaltyAccount, account_id)?;
+        
+        let record = |tx: &mut Transaction| {
+            let activity = tx.loyalty.get_or_insert_with(|| LoyaltyActivity {
+                account_id: account_id.to_string(),
+                ..LoyaltyActivity::default()
+            });
+            
+            if activity.account_id != account_id {
+                return Err(KernelError::invalid_state("Transaction already has loyalty activity for another account"));
+            }
+            
+            activity.points_accrued += points_accrued;
+            activity.points_redeemed += points_redeemed;
+            Ok(activity.clone())
+        };
+        
+        let activity = match self.active_transactions.get_mut(handle) {
+            Some(mut tx) => record(&mut tx)?,
+            None => self.archive.record_loyalty(handle, account_id, points_accrued, points_redeemed)
+                .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))??,
+        };
+        
+        self.journal.record(handle, JournalOperation::LoyaltyRecord {
+            account_id: account_id.to_string(),
+            points_accrued,
+            points_redeemed,
+        });
+        Ok(activity)
+    }
+    
+    fn get_loyalty_activity(&self, handle: u64) -> Result<Option<LoyaltyActivity>, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.loyalty.clone())
+    }
+    
+    fn transaction_snapshot(&self, handle: u64) -> Result<export::TransactionExport, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(export::TransactionExport::from(&*tx))
+    }
+    
+    // REGULATORY COMPLIANCE: GDPR erasure. Replaces the customer token with a random pseudonym on
+    // every transaction, in the journal and in queued events, then journals a signed record of
+    // the operation. Tiers, amounts and all other transaction data are left unchanged.
+    fn anonymize_customer(&mut self, token: &str) -> Result<AnonymizationRecord, KernelError> {
+        self.replication.check_writable()?;
+        if token.is_empty() || token.len() > self.system_config.max_customer_token_len {
+            return Err(KernelError::validation("Customer token length out of range"));
+        }
+        
+        let key = self.signing_key.clone()
+            .ok_or_else(|| KernelError::invalid_state("No signing key configured"))?;
+        
+        // The journal is rewritten first: if that fails, nothing has been changed
+        let pseudonym = privacy::new_pseudonym();
+        let redacted = self.journal.redact_customer(token, &pseudonym).map_err(KernelError::Internal)?;
+        let journal_entries_updated = redacted.len();
+        
+        let mut transactions_updated = 0;
+        for tx in self.active_transactions.values_mut() {
+            if let Some(customer) = tx.customer.as_mut().filter(|c| c.token == token) {
+                customer.token = pseudonym.clone();
+                transactions_updated += 1;
+            }
+        }
+        transactions_updated += self.archive.redact_customer(token, &pseudonym).map_err(KernelError::Internal)?;
+        // REGULATORY COMPLIANCE: Reports must not serve the erased token from a cached snapshot
+        self.discard_report_snapshot();
+        
+        let events_updated = self.events.redact_customer(token, &pseudonym);
+        
+        let mut record = AnonymizationRecord {
+            pseudonym,
+            anonymized_at: Utc::now(),
+            transactions_updated,
+            journal_entries_updated,
+            events_updated,
+            signature: String::new(),
+        };
+        record.signature = signing::sign_hex(&key, record.signed_payload().as_bytes()).map_err(KernelError::Internal)?;
+        
+        self.journal.record(0, JournalOperation::CustomerAnonymize {
+            pseudonym: record.pseudonym.clone(),
+            transactions_updated,
+            signature: record.signature.clone(),
+            redacted,
+        });
+        Ok(record)
+    }
+    
+    fn get_customer(&self, handle: u64) -> Result<Option<CustomerRef>, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.customer.clone())
+    }
+    
+    // Returns the oldest pending event's sequence number with its JSON
+    fn next_event_json(&self) -> Option<(u64, Result<String, KernelError>)> {
+        self.events.peek().map(|event| {
+            let json = serde_json::to_string(&event)
+                .map_err(|e| KernelError::Internal(format!("Failed to serialize event: {}", e)));
+            (event.sequence_number, json)
+        })
+    }
+    
+    // ARCHITECTURAL PRINCIPLE: Keys and values are opaque to the kernel; only limits are enforced
+    fn set_transaction_attribute(&self, handle: u64, key: String, value: String) -> Result<(), KernelError> {
+        pci::screen(TextField::AttributeKey, &key)?;
+        let value = pci::screen(TextField::AttributeValue, &value)?.into_owned();
+        
+        if key.is_empty() || key.len() > self.system_config.max_attribute_key_len {
+            return Err(KernelError::validation("Attribute key length out of range"));
+        }
+        
+        if value.len() > self.system_config.max_attribute_value_len {
+            return Err(KernelError::validation("Attribute value too long"));
+        }
+        
+        let max_attributes = self.system_config.max_transaction_attributes;
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if !tx.attributes.contains_key(&key) && tx.attributes.len() >= max_attributes {
+            return Err(KernelError::validation("Too many transaction attributes"));
+        }
+        
+        tx.attributes.insert(key.clone(), value.clone());
+        self.journal.record(handle, JournalOperation::AttributeSet { key, value });
+        Ok(())
+    }
+    
+    // Records one entry of the tax engine's breakdown. The breakdown describes the basket as it
+    // was when calculated; user space clears and re-adds it after basket changes.
+    fn add_tax_entry(&self, handle: u64, entry: TaxEntry) -> Result<(), KernelError> {
+        self.check_tax_entry(&entry)?;
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.taxes.push(entry.clone());
+        self.journal.record(handle, JournalOperation::TaxAdd { entry });
+        Ok(())
+    }
+    
+    fn check_tax_entry(&self, entry: &TaxEntry) -> Result<(), KernelError> {
+        let max_len = self.system_config.max_attribute_key_len;
+        if entry.jurisdiction.is_empty() || entry.jurisdiction.len() > max_len
+            || entry.rate_code.is_empty() || entry.rate_code.len() > max_len {
+            return Err(KernelError::validation("Tax jurisdiction or rate code length out of range"));
+        }
+        
+        if entry.rate_basis_points < 0 || entry.taxable_minor < 0 || entry.exempt_minor < 0 {
+            return Err(KernelError::validation("Tax rate and amounts must not be negative"));
+        }
+        Ok(())
+    }
+    
+    // TAX: Replaces the transaction's tax breakdown with the tax provider's for the current
+    // basket. The provider is called without the transaction lock; if the basket changed
+    // meanwhile, nothing is recorded and the caller recalculates.
+    fn calculate_taxes(&self, handle: u64) -> Result<tax::TaxSource, KernelError> {
+        let provider = self.tax_provider.as_deref()
+            .ok_or_else(|| KernelError::invalid_state(tax::NO_TAX_PROVIDER))?;
+        let tx = self.transaction(handle)?;
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        let request = tax::TaxRequest::from_transaction(&tx);
+        drop(tx);
+        
+        let (entries, source) = self.tax_cache.calculate(provider, &request, self.system_config.tax_cache_ttl_secs, &self.tax_fallback_rates)?;
+        for entry in &entries {
+            self.check_tax_entry(entry)?;
+            if entry.line_id.is_some_and(|id| !request.lines.iter().any(|l| l.line_id == id)) {
+                return Err(KernelError::not_found("Invalid line ID"));
+            }
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        if tax::TaxRequest::from_transaction(&tx) != request {
+            return Err(KernelError::invalid_state("Basket changed during tax calculation"));
+        }
+        
+        tx.taxes = entries.clone();
+        self.journal.record(handle, JournalOperation::TaxClear);
+        if source == tax::TaxSource::Fallback {
+            self.journal.record(handle, JournalOperation::TaxFallback);
+        }
+        for entry in entries {
+            self.journal.record(handle, JournalOperation::TaxAdd { entry });
+        }
+        Ok(source)
+    }
+    
+    fn clear_taxes(&self, handle: u64) -> Result<(), KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        tx.taxes.clear();
+        self.journal.record(handle, JournalOperation::TaxClear);
+        Ok(())
+    }
+    
+    fn receipt_document(&self, handle: u64) -> Result<receipt::ReceiptDocument, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if tx.state != TxState::Committed {
+            return Err(KernelError::invalid_state("Receipts are only produced for committed transactions"));
+        }
+        
+        Ok(self.build_receipt(&tx))
+    }
+    
+    // REGULATORY COMPLIANCE: Receipts carry the fiscal QR payload when a provider is registered;
+    // a provider failure is reported and the receipt is produced without it
+    fn build_receipt(&self, tx: &Transaction) -> receipt::ReceiptDocument {
+        let mut document = receipt::ReceiptDocument::build(tx, &self.receipt_layout);
+        if let Some(provider) = self.fiscal_qr_provider.as_ref() {
+            match provider.payload(&document) {
+                Ok(payload) => document.fiscal_qr = Some(payload),
+                Err(e) => eprintln!("WARNING: Fiscal QR ({}) failed for transaction {}: {}", provider.jurisdiction(), tx.id, e),
+            }
+        }
+        document
+    }
+    
+    fn render_receipt_template(&self, handle: u64, name: &str) -> Result<String, KernelError> {
+        let template = self.receipt_templates.get(name)
+            .ok_or_else(|| KernelError::not_found(format!("Receipt template '{}' not loaded", name)))?;
+        template.render(&self.receipt_document(handle)?).map_err(KernelError::Internal)
+    }
+    
+    fn receipt_json(&self, handle: u64) -> Result<String, KernelError> {
+        serde_json::to_string(&self.receipt_document(handle)?)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize receipt: {}", e)))
+    }
+    
+    // The fiscal device, and the open day's number when `open` (else checks no day is open)
+    fn fiscal_day_device(&self, open: bool) -> Result<(Arc<dyn fiscal::FiscalDevice>, u64), KernelError> {
+        self.replication.check_writable()?;
+        let device = self.fiscal_device.as_ref()
+            .ok_or_else(|| KernelError::not_found("No fiscal device registered"))?;
+        match (&self.fiscal_day, open) {
+            (Some(day), true) => Ok((Arc::clone(device), day.day_number)),
+            (None, false) => Ok((Arc::clone(device), 0)),
+            (Some(_), false) => Err(KernelError::invalid_state("Fiscal day already open")),
+            (None, true) => Err(KernelError::invalid_state("Fiscal day not open")),
+        }
+    }
+    
+    // REGULATORY COMPLIANCE: Records the day the device opened
+    fn record_fiscal_day_open(&mut self, day_number: u64) -> Result<fiscal::FiscalDay, KernelError> {
+        self.replication.check_writable()?;
+        if self.fiscal_day.is_some() {
+            return Err(KernelError::invalid_state("Fiscal day already open"));
+        }
+        
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::FiscalDayOpen { day_number });
+        let day = fiscal::FiscalDay {
+            day_number,
+            opened_at: Utc::now(),
+            documents_registered: 0,
+            total_minor: 0,
+        };
+        self.fiscal_day = Some(day.clone());
+        Ok(day)
+    }
+    
+    // REGULATORY COMPLIANCE: Records the close of the day the device closed
+    fn record_fiscal_day_close(&mut self, day_number: u64, device_report: Option<String>) -> Result<fiscal::FiscalDayReport, KernelError> {
+        self.replication.check_writable()?;
+        let day = self.fiscal_day.take_if(|day| day.day_number == day_number)
+            .ok_or_else(|| KernelError::invalid_state("Fiscal day not open"))?;
+        
+        self.journal.record(PK_INVALID_HANDLE, JournalOperation::FiscalDayClose {
+            day_number: day.day_number,
+            documents_registered: day.documents_registered,
+            total_minor: day.total_minor,
+        });
+        let report = fiscal::FiscalDayReport {
+            day_number: day.day_number,
+            opened_at: day.opened_at,
+            closed_at: Utc::now(),
+            documents_registered: day.documents_registered,
+            total_minor: day.total_minor,
+            device_report,
+        };
+        self.last_fiscal_day_report = Some(report.clone());
+        Ok(report)
+    }
+    
+    // REGULATORY COMPLIANCE: Builds a committed sale's fiscal document, if a fiscal device is
+    // registered; it is registered without the store lock. Errors carry the jurisdiction.
+    fn prepare_fiscal_document(&self, handle: u64) -> Option<Result<fiscal::device::DocumentRegistration, (String, String)>> {
+        let device = self.fiscal_device.as_ref()?;
+        let tx = self.transaction(handle).ok()?;
+        let day = match self.fiscal_day.as_ref() {
+            Some(day) => day,
+            None => return Some(Err((device.jurisdiction().to_string(), "Fiscal day not open".to_string()))),
+        };
+        Some(Ok(fiscal::device::DocumentRegistration {
+            device: Arc::clone(device),
+            day_number: day.day_number,
+            document: fiscal::FiscalDocument::from_receipt(&self.build_receipt(&tx)),
+        }))
+    }
+    
+    fn record_fiscal_error(&mut self, handle: u64, jurisdiction: &str, e: String) {
+        eprintln!("WARNING: Fiscal registration ({}) failed for transaction {}: {}", jurisdiction, handle, e);
+        if let Some(mut tx) = self.active_transactions.get_mut(handle) {
+            tx.fiscal_error = Some(e);
+        }
+    }
+    
+    // REGULATORY COMPLIANCE: TSE signature of a committed transaction; a TSE failure is an error
+    fn tse_signature_json(&self, handle: u64) -> Result<String, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if let Some(e) = &tx.tse_error {
+            return Err(KernelError::InvalidState(format!("TSE failed: {}", e)));
+        }
+        let signature = match (&tx.tse_signature, &tx.tse_start) {
+            (Some(signature), _) => signature,
+            (None, Some(_)) if tx.state == TxState::Committed => return Err(KernelError::not_found("TSE signature pending")),
+            (None, _) => return Err(KernelError::not_found("Transaction has no TSE signature")),
+        };
+        serde_json::to_string(signature)
+            .map_err(|e| KernelError::Internal(format!("Failed to serialize TSE signature: {}", e)))
+    }
+    
+    // OMNICHANNEL: Sets (Some) or clears (None) one order reference field
+    fn set_order_reference(&self, handle: u64, field: OrderReferenceField, value: Option<String>) -> Result<(), KernelError> {
+        let value = pci::screen_opt(TextField::OrderReference, value)?;
+        
+        if value.as_ref().is_some_and(|v| v.is_empty() || v.len() > self.system_config.max_order_reference_len) {
+            return Err(KernelError::validation("Order reference length out of range"));
+        }
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        *tx.order_reference.field_mut(field) = value.clone();
+        self.journal.record(handle, JournalOperation::OrderReferenceSet { field, value: value.clone() });
+        self.events.publish(handle, EventKind::OrderReferenceSet { field, value });
+        Ok(())
+    }
+    
+    fn get_order_reference(&self, handle: u64, field: OrderReferenceField) -> Result<Option<String>, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.order_reference.field(field).map(str::to_string))
+    }
+    
+    fn remove_transaction_attribute(&self, handle: u64, key: &str) -> Result<(), KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state(NOT_BUILDING));
+        }
+        
+        if tx.attributes.remove(key).is_none() {
+            return Err(KernelError::not_found("Attribute not found"));
+        }
+        
+        self.journal.record(handle, JournalOperation::AttributeRemove { key: key.to_string() });
+        Ok(())
+    }
+    
+    fn get_transaction_attribute(&self, handle: u64, key: &str) -> Result<Option<String>, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.attributes.get(key).cloned())
+    }
+    
+    fn export_transaction_json(&self, handle: u64) -> Result<String, KernelError> {
+        let tx = self.transaction(handle)?;
+        export::transaction_to_json(&tx).map_err(KernelError::Internal)
+    }
+    
+    // NRF COMPLIANCE: POSLog XML of a committed transaction
+    fn export_transaction_poslog(&self, handle: u64) -> Result<String, KernelError> {
+        let tx = self.transaction(handle)?;
+        poslog::to_poslog(&[export::TransactionExport::from(&*tx)]).map_err(KernelError::Internal)
+    }
+    
+    // REGULATORY COMPLIANCE: Structured B2B e-invoice of a committed transaction
+    fn export_einvoice(&self, handle: u64, request: &einvoice::InvoiceRequest, format: einvoice::InvoiceFormat) -> Result<Vec<u8>, KernelError> {
+        let tx = self.transaction(handle)?;
+        einvoice::export(&export::TransactionExport::from(&*tx), request, format)
+    }
+    
+    // REGULATORY COMPLIANCE: Tax collected per currency, jurisdiction and rate over a period
+    fn get_currency_decimal_places(&self, handle: u64) -> Result<u8, KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.currency.decimal_places())
+    }
+    
+    // ARCHITECTURAL FIX: Update get_line_item_details to return parent_line_item_id instead of preparation notes
+    fn get_line_item_details(&self, handle: u64, line_index: u32) -> Result<(Arc<str>, i32, i64, Option<u32>), KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        if line_index as usize >= tx.lines.len() {
+            return Err(KernelError::not_found("Line index out of range"));
+        }
+        
+        let line = &tx.lines[line_index as usize];
+        Ok((line.sku.clone(), line.qty, line.unit_minor, line.parent_line_item_id))
+    }
+    
+    // Pinned buffers are offered for open transactions only. A settled transaction lingers in
+    // the active table until a persistence worker archives it, and a pin taken then would be
+    // freed at a point the host cannot observe
+    fn pin_lines(&self, handle: u64) -> Result<Arc<pinned::PinnedLines>, KernelError> {
+        let tx = self.active_transactions.get(handle)?
+            .ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        if matches!(tx.state, TxState::Committed | TxState::Cancelled) {
+            return Err(KernelError::not_found(TRANSACTION_NOT_FOUND));
+        }
+        Ok(tx.pinned_lines.pin(&tx.lines))
+    }
+    
+    fn release_pinned_lines(&self, handle: u64) -> Result<(), KernelError> {
+        let mut tx = self.active_transactions.write(handle)?;
+        tx.release_pinned_lines();
+        Ok(())
+    }
+    
+    fn get_line_kind(&self, handle: u64, line_index: u32) -> Result<LineKind, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        tx.lines.get(line_index as usize)
+            .map(|line| line.kind)
+            .ok_or_else(|| KernelError::not_found("Line index out of range"))
+    }
+    
+    fn get_line_product_metadata(&self, handle: u64, line_index: u32) -> Result<(Option<String>, Option<String>), KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        let line = tx.lines.get(line_index as usize)
+            .ok_or_else(|| KernelError::not_found("Line index out of range"))?;
+        Ok((line.product_name.clone(), line.product_description.clone()))
+    }
+    
+    fn get_line_adjustment_info(&self, handle: u64, line_index: u32) -> Result<(String, String), KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        let line = tx.lines.get(line_index as usize)
+            .ok_or_else(|| KernelError::not_found("Line index out of range"))?;
+        
+        match (&line.reason_code, &line.approved_by) {
+            (Some(reason), Some(approver)) => Ok((reason.clone(), approver.clone())),
+            _ => Err(KernelError::invalid_state("Line is not an adjustment")),
+        }
+    }
+    
+    fn get_deposit_totals(&self, handle: u64) -> Result<(i64, i64), KernelError> {
+        let tx = self.transaction(handle)?;
+        Ok(tx.deposit_totals())
+    }
+    
+    // Maps a display position (0-based index) to the line's stable ID
+    fn get_line_id(&self, handle: u64, line_index: u32) -> Result<u32, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        tx.lines.get(line_index as usize)
+            .map(|line| line.line_id)
+            .ok_or_else(|| KernelError::not_found("Line index out of range"))
+    }
+    
+    // NRF COMPLIANCE: Get parent line item ID for a given line
+    fn get_line_parent_id(&self, handle: u64, line_id: u32) -> Result<Option<u32>, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        Ok(tx.get_line_parent_id(line_id))
+    }
+    
+    // NRF COMPLIANCE: Find all children of a line item (for void cascade)
+    fn find_line_children(&self, handle: u64, parent_line_id: u32) -> Result<Vec<u32>, KernelError> {
+        let tx = self.transaction(handle)?;
+        
+        Ok(tx.find_all_children(parent_line_id))
+    }
+    
+    // ARCHITECTURAL COMPONENT: Voids a line item with NRF-compliant cascade to child items.
+    // Critical NRF requirement: When parent items are voided, all linked child items must be voided.
+    // 
+    // # Safety
+    // The caller must ensure that:
+    // - `handle` refers to a valid, active transaction
+    // - `line_id` is the stable ID of a line item in the transaction
+    // - `reason_ptr` points to valid memory containing a UTF-8 encoded reason string
+    // - `reason_len` accurately represents the length of the data at `reason_ptr`
+    fn void_line_with_cascade(&self, handle: u64, line_id: u32, reason: &str) -> Result<(), KernelError> {
+        let reason = &pci::screen(TextField::Reason, reason)?;
+        
+        let mut tx = self.active_transactions.write(handle)?;
+        
+        if tx.state != TxState::Building {
+            return Err(KernelError::invalid_state("Cannot void items in committed transaction"));
+        }
+        
+        // FUEL: A preset not yet authorized is cancelled with its line; an authorized pump may
+        // already be dispensing
+        if let Some(index) = tx.fuel_sales.iter().position(|f| f.preset_line_id == line_id && f.is_open()) {
+            if tx.fuel_sales[index].state != fuel::FuelState::Preset {
+                return Err(KernelError::invalid_state("Authorized fuel cannot be voided"));
+            }
+            tx.fuel_sales.remove(index);
+        }
+        
+        // Find all child items recursively
+        let children = tx.find_all_children(line_id);
+        
+        // KDS: Built before the lines lose their quantities
+        let kitchen_void = peripheral::kitchen::void(&tx, line_id, &children, self.kitchen_router.as_deref());
         
         // Void children first (reverse hierarchy order) 
-        for child_line_number in children.iter().rev() {
-            tx.void_single_line_item(*child_line_number, &format!("Parent voided: {}", reason))?;
+        for child_line_id in children.iter().rev() {
+            tx.void_single_line_item(*child_line_id, &format!("Parent voided: {}", reason))?;
+        }
+        
+        // Void parent item
+        tx.void_single_line_item(line_id, reason)?;
+        // A voided line no longer needs confirming, its tags may be read again, and its stock
+        // goes back on the shelf
+        let mut released = Vec::new();
+        for voided in children.iter().chain([&line_id]) {
+            tx.held_lines.remove(voided);
+            released.extend(tx.stock_reservations.remove(voided));
+        }
+        tx.rfid_tags.retain(|_, tagged| *tagged != line_id && !children.contains(tagged));
+        drop(tx);
+        self.release_stock(&released);
+        
+        for child_line_id in children.iter().rev() {
+            let child_reason = format!("Parent voided: {}", reason);
+            self.journal.record(handle, JournalOperation::LineVoid { line_id: *child_line_id, reason: child_reason.clone() });
+            self.events.publish(handle, EventKind::LineVoided { line_id: *child_line_id, reason: child_reason });
+        }
+        self.journal.record(handle, JournalOperation::LineVoid { line_id, reason: reason.to_string() });
+        self.events.publish(handle, EventKind::LineVoided { line_id, reason: reason.to_string() });
+        self.send_kitchen_tickets(handle, kitchen_void.into_iter().collect());
+        self.publish_display(handle, |tx| {
+            children.iter().rev().copied().chain([line_id])
+                .map(|line_id| DisplayUpdateKind::ItemVoided { line_id })
+                .chain([DisplayUpdateKind::subtotal(tx)])
+                .collect()
+        });
+        self.record_anomaly(handle, anomaly::AnomalyKind::Void);
+        
+        Ok(())
+    }
+}
+
+// === GLOBAL STORE ===
+
+static LEGAL_KERNEL_STORE: OnceLock<RwLock<LegalKernelStore>> = OnceLock::new();
+
+fn legal_kernel_store() -> &'static RwLock<LegalKernelStore> {
+    LEGAL_KERNEL_STORE.get_or_init(|| {
+        // Journal persistence is enabled by pointing POS_KERNEL_DATA_DIR at a data directory;
+        // otherwise the journal is kept in memory only. Evicted transactions and the audit log
+        // are written to the same directory; without one, the memory budget cannot be enforced
+        // and only the most recent audit records are kept.
+        let data_dir = std::env::var("POS_KERNEL_DATA_DIR").ok().map(std::path::PathBuf::from);
+        let journal = match &data_dir {
+            Some(data_dir) => {
+                let path = data_dir.join("journal.wal");
+                Journal::open(&path).unwrap_or_else(|e| {
+                    eprintln!("CRITICAL: Could not open journal: {}. Falling back to in-memory journal.", e);
+                    Journal::in_memory()
+                })
+            },
+            None => Journal::in_memory(),
+        };
+        
+        let mut store = LegalKernelStore::new(journal);
+        if let Err(e) = store.resume_from_journal() {
+            eprintln!("CRITICAL: Could not resume from the journal: {}. Handles may repeat earlier receipt numbers.", e);
+        }
+        if let Some(data_dir) = &data_dir {
+            match persistence::FileBackend::open(&data_dir.join("transactions")) {
+                Ok(backend) => store.archive.set_backend(Arc::new(backend)),
+                Err(e) => eprintln!("WARNING: {}. Archived transactions will not be evicted.", e),
+            }
+            let config = &store.system_config;
+            match audit::AuditLog::open(&data_dir.join("audit.jsonl"), config.audit_log_max_bytes, config.audit_log_files) {
+                Ok(audit) => store.set_audit_log(Arc::new(audit)),
+                Err(e) => eprintln!("WARNING: {}. The audit log is kept in memory only.", e),
+            }
+        }
+        RwLock::new(store)
+    })
+}
+
+// ARCHITECTURAL PRINCIPLE: Lane operations run under the shared store lock and lock only the
+// shard of their transaction: beginning sales and quotes, lines, voids, adjustments, taxes,
+// attributes, customers, order references, pins and every read. Handles come from atomic
+// sequences. The exclusive lock is left to work that changes store-wide state: commits (the
+// archive, fiscal day, loyalty and refund counters), transactions opened from others (splits,
+// clones, conversions), configuration, permissions and provider registration.
+//
+// A poisoned store is recovered by the caller that finds it (see recover_store) and the lock
+// taken again; a wait beyond the lock timeout is TimedOut, with the detail left in the thread's
+// last error
+fn read_store() -> Result<RwLockReadGuard<'static, LegalKernelStore>, ResultCode> {
+    for _ in 0..2 {
+        let acquired = lock::read(legal_kernel_store(), "kernel store");
+        match acquired {
+            Ok(Ok(store)) => return Ok(store),
+            Ok(Err(poisoned)) => {
+                drop(poisoned);
+                recover_store()?;
+            },
+            Err(_) => return Err(ResultCode::TimedOut),
+        }
+    }
+    Err(ResultCode::InternalError)
+}
+
+fn write_store() -> Result<RwLockWriteGuard<'static, LegalKernelStore>, ResultCode> {
+    for _ in 0..2 {
+        let acquired = lock::write(legal_kernel_store(), "kernel store");
+        match acquired {
+            Ok(Ok(store)) => return Ok(store),
+            Ok(Err(poisoned)) => {
+                drop(poisoned);
+                recover_store()?;
+            },
+            Err(_) => return Err(ResultCode::TimedOut),
+        }
+    }
+    Err(ResultCode::InternalError)
+}
+
+// Store access for the Rust-side APIs in modules (provider registration, loyalty): the same
+// lock timeout and poison recovery as FFI calls, with failures reported as messages
+pub(crate) fn read_store_for_api() -> Result<RwLockReadGuard<'static, LegalKernelStore>, KernelError> {
+    read_store().map_err(store_lock_error)
+}
+
+pub(crate) fn write_store_for_api() -> Result<RwLockWriteGuard<'static, LegalKernelStore>, KernelError> {
+    write_store().map_err(store_lock_error)
+}
+
+fn store_lock_error(code: ResultCode) -> KernelError {
+    match code {
+        ResultCode::TimedOut => KernelError::TimedOut("Kernel store lock not acquired".to_string()),
+        _ => KernelError::internal("Kernel store lock not acquired"),
+    }
+}
+
+// RESILIENCE: A panic while the store lock is held (a persistence job; FFI entry points abort
+// on panic) poisons it, which would otherwise fail every later call. The store is taken
+// exclusively, repaired and checked by recover_from_poison, and the poison cleared. Callers
+// racing here find it already cleared and return at once.
+fn recover_store() -> Result<(), ResultCode> {
+    let store = legal_kernel_store();
+    let mut guard = match lock::write(store, "kernel store") {
+        Ok(acquired) => acquired.unwrap_or_else(PoisonError::into_inner),
+        Err(_) => return Err(ResultCode::TimedOut),
+    };
+    if store.is_poisoned() {
+        guard.recover_from_poison();
+        store.clear_poison();
+    }
+    Ok(())
+}
+
+// Entry point of the persistence workers. Workers are not FFI callers, so they wait for the
+// store lock without a timeout, and hold it only for the part of a job that needs it.
+fn run_persistence_job(job: pipeline::Job) {
+    let store = legal_kernel_store();
+    if store.is_poisoned() && recover_store().is_err() {
+        eprintln!("WARNING: Kernel store lock poisoned and not yet recovered");
+    }
+    match job {
+        pipeline::Job::Archive { handle } => match store.write() {
+            Ok(_) if faults::fires(faults::Fault::WorkerPanic) => panic!("Injected fault: archive job panicked"),
+            Ok(mut s) => s.archive_transaction(handle),
+            Err(_) => eprintln!("CRITICAL: Transaction {} not archived: kernel store lock poisoned", handle),
+        },
+        // Transactions are written to the backend without the lock and dropped from memory
+        // under it, unless they were redacted in the meantime
+        pipeline::Job::Evict => {
+            let (candidates, backend) = match store.read() {
+                Ok(s) => {
+                    s.eviction_queued.store(false, Ordering::Relaxed);
+                    (s.archive.eviction_candidates(s.system_config.memory_budget_bytes), s.archive.backend())
+                },
+                Err(_) => return,
+            };
+            let backend = match backend {
+                Some(backend) => backend,
+                None => return,
+            };
+            let saved: Vec<_> = candidates.into_iter()
+                .filter(|(handle, tx)| backend.save(tx)
+                    .map_err(|e| eprintln!("WARNING: Failed to evict archived transaction {}: {}", handle, e))
+                    .is_ok())
+                .collect();
+            if let Ok(mut s) = store.write() {
+                for (handle, tx) in saved {
+                    if s.archive.mark_evicted(handle, &tx) {
+                        s.pool().recycle(tx);
+                    }
+                }
+            }
+        },
+        // The pool has its own lock; the store is only read to reach it
+        pipeline::Job::RefillPool => {
+            if let Ok(s) = store.read() {
+                s.restock_transaction_pool();
+            }
+        },
+        pipeline::Job::DeliverReceipt(delivery) => {
+            let outcomes = delivery.deliver();
+            if let Ok(s) = store.read() {
+                let _ = s.publish_receipt_outcomes(delivery.handle, delivery.reason, outcomes);
+            }
+        },
+        // The devices are called without the lock; their results are applied under it
+        pipeline::Job::Fiscalize(fiscalization) => {
+            let outcome = fiscalization.run();
+            match store.write() {
+                Ok(mut s) => s.apply_fiscalization(&fiscalization, outcome),
+                Err(_) => eprintln!("CRITICAL: Fiscal results for transaction {} not applied: kernel store lock poisoned", fiscalization.handle),
+            }
+        },
+        pipeline::Job::OpenDrawer(open) => {
+            let outcome = open.open();
+            if let Ok(s) = store.read() {
+                let _ = s.record_drawer_open(&open, outcome);
+            }
+        },
+        pipeline::Job::DeliverKitchenTicket(delivery) => {
+            let outcomes = delivery.deliver();
+            if let Ok(s) = store.read() {
+                s.publish_kitchen_outcomes(&delivery, outcomes);
+            }
+        },
+        // Printers are driven through the spooler's own lock, without the store's
+        pipeline::Job::Print(spooler) => {
+            let outcomes = spooler.run();
+            if let Ok(s) = store.read() {
+                s.publish_print_outcomes(outcomes);
+            }
+        },
+        // Each batch is built under the store lock and sent without it
+        pipeline::Job::Sync(pending) => {
+            let mut next = Some(*pending);
+            while let Some(mut pending) = next {
+                let result = pending.submit();
+                next = match store.read() {
+                    Ok(s) => s.finish_sync(&pending, result),
+                    Err(_) => None,
+                };
+            }
+        },
+        // Like printers, ERP connectors are pushed through the outbox's own lock
+        pipeline::Job::PushErp(outbox) => {
+            let outcomes = outbox.run();
+            if let Ok(s) = store.read() {
+                s.publish_erp_outcomes(outcomes);
+            }
+        },
+        pipeline::Job::DispenseChange(dispense) => {
+            let outcome = dispense.dispense();
+            if let Ok(s) = store.read() {
+                let _ = s.record_change_dispense(&dispense, outcome);
+            }
+        },
+        // Failures are only logged; the store is not needed
+        pipeline::Job::PublishBasket(delivery) => delivery.publish(),
+        // The cache and provider travel with the job; the store is not needed
+        pipeline::Job::RefreshPrice(refresh) => refresh.run(),
+    }
+}
+
+// AUDIT COMPLIANCE: Calls that may commit acknowledge only once the commit's journal entry is
+// durable. The store lock is released first, so lanes committing together share one sync. If
+// the sync fails the commit stands in memory but is not acknowledged.
+fn await_durable_commit(kernel_store: RwLockWriteGuard<'static, LegalKernelStore>) -> Result<(), ResultCode> {
+    let ticket = kernel_store.journal.commit_ticket();
+    drop(kernel_store);
+    ticket.wait().map_err(|e| {
+        eprintln!("CRITICAL: {}", e);
+        last_error::set(e);
+        ResultCode::InternalError
+    })
+}
+
+// === UTILITY FUNCTIONS ===
+
+unsafe fn read_str(ptr: *const u8, len: usize) -> String {
+    if ptr.is_null() || len == 0 {
+        return String::new();
+    }
+    
+    let slice = std::slice::from_raw_parts(ptr, len);
+    String::from_utf8_lossy(slice).into_owned()
+}
+
+unsafe fn write_str(s: &str, buffer: *mut u8, buffer_size: usize, out_required: *mut usize) -> PkResult {
+    write_bytes(s.as_bytes(), buffer, buffer_size, out_required)
+}
+
+unsafe fn write_bytes(bytes: &[u8], buffer: *mut u8, buffer_size: usize, out_required: *mut usize) -> PkResult {
+    if !out_required.is_null() {
+        *out_required = bytes.len();
+    }
+    
+    if buffer.is_null() || buffer_size == 0 {
+        return if bytes.is_empty() {
+            PkResult::ok()
+        } else {
+            PkResult::err(ResultCode::InsufficientBuffer)
+        };
+    }
+    
+    if bytes.len() > buffer_size {
+        return PkResult::err(ResultCode::InsufficientBuffer);
+    }
+    
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+    PkResult::ok()
+}
+
+// === FFI FUNCTIONS ===
+
+#[no_mangle]
+pub extern "C" fn pk_result_is_ok(result: PkResult) -> bool {
+    metrics::instrument!("pk_result_is_ok");
+    result.code == 0
+}
+
+#[no_mangle]
+pub extern "C" fn pk_result_get_code(result: PkResult) -> i32 {
+    metrics::instrument!("pk_result_get_code");
+    result.code
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the detail for the most recent failure on the calling thread
+/// that recorded one (for example, which lock a TimedOut call was waiting for). The message is
+/// written without a null terminator; an empty message means no detail has been recorded.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_last_error(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_last_error");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    write_str(&last_error::get().unwrap_or_default(), buffer, buffer_size, out_required_size)
+}
+
+#[no_mangle]
+pub extern "C" fn pk_clear_last_error() {
+    metrics::instrument!("pk_clear_last_error");
+    last_error::clear();
+}
+
+#[no_mangle]
+pub extern "C" fn pk_get_version() -> *const std::os::raw::c_char {
+    metrics::instrument!("pk_get_version");
+    static VERSION: &[u8] = b"0.4.0-minimal\0";
+    VERSION.as_ptr() as *const std::os::raw::c_char
+}
+
+/// ARCHITECTURAL COMPONENT: Gets terminal initialization status and parameters.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_id_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_id_len` accurately represents the length of the data at `terminal_id_ptr`
+#[no_mangle]
+pub unsafe extern "C" fn pk_initialize_terminal(
+    terminal_id_ptr: *const u8,
+    terminal_id_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_initialize_terminal");
+    if terminal_id_ptr.is_null() || terminal_id_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let _terminal_id = read_str(terminal_id_ptr, terminal_id_len);
+    
+    // Initialize the kernel store
+    let _ = legal_kernel_store();
+    
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Begins a new transaction in the kernel store.
+/// Returns `ValidationFailed`, with the reason in the last error, for a store name that is blank,
+/// contains control characters or exceeds `max_store_name_len` bytes, a currency code that is
+/// not three letters, or more than `max_currency_decimal_places` decimal places (4 by default,
+/// at most 6).
+/// Returns `CapacityExceeded` when the store already has `max_active_per_store` open sales;
+/// the last error names the limit. HTTP hosts answer it with 503 and a `Retry-After` header.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` points to valid memory containing a UTF-8 encoded store name
+/// - `store_len` accurately represents the length of the data at `store_ptr`
+/// - `currency_ptr` points to valid memory containing a UTF-8 encoded currency code
+/// - `currency_len` accurately represents the length of the data at `currency_ptr`
+/// - `currency_decimal_places` specifies the decimal places for currency (user-space decision)
+/// - `out_handle` points to valid memory where the transaction handle can be written
+/// - All pointers remain valid for the duration of this call
+#[no_mangle]
+pub unsafe extern "C" fn pk_begin_transaction(
+    store_ptr: *const u8,
+    store_len: usize,
+    currency_ptr: *const u8,
+    currency_len: usize,
+    currency_decimal_places: u8,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_begin_transaction");
+    if store_ptr.is_null() || currency_ptr.is_null() || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let store = read_str(store_ptr, store_len);
+    let currency_code = read_str(currency_ptr, currency_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    // ARCHITECTURAL PRINCIPLE: Kernel is culture-neutral - client provides all currency info
+    let currency = match kernel_store.check_begin_parameters(&store, &currency_code, currency_decimal_places) {
+        Ok(c) => c,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    match kernel_store.begin_transaction_legal(store, currency) {
+        Ok(handle) => {
+            *out_handle = handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a line item to an existing transaction.
+/// Lines beyond the configured bounds (`max_line_qty`, `max_unit_minor`,
+/// `max_lines_per_transaction`, `max_children_per_line`, `max_line_depth`) or whose amount would
+/// overflow the transaction total return `ValidationFailed`, with the reason in the last error.
+/// With a product catalog registered, the line takes its name and description from the catalog
+/// and is checked as `catalog_validation` says: a price other than the shelf price or a
+/// discontinued product returns `ValidationFailed`, and under "strict" an unknown SKU `NotFound`.
+/// With an inventory provider registered, stock is reserved for the line first. A shortage or
+/// an unreachable provider adds the line unreserved (journaled) unless `inventory_on_shortage`
+/// or `inventory_on_unavailable` is "reject", which returns `ValidationFailed` or `TimedOut`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `sku_len` accurately represents the length of the data at `sku_ptr`
+/// - The memory pointed to by `sku_ptr` remains valid for the duration of this call
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_add_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_line_legal(handle, sku, qty, unit_minor) {
+        Ok(line_id) => {
+            kernel_store.after_add_line(handle, line_id);
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a line priced by the registered price provider rather than
+/// the caller. Prices are cached for `price_cache_ttl_secs`; once stale, `price_stale_policy`
+/// decides: "serve" (default) adds the line at the stale price and refreshes it in the
+/// background, "refresh" asks the provider first and falls back to the stale price if it
+/// fails, "reject" asks the provider and fails with it. A stale price is never used more than
+/// `price_max_stale_secs` past its TTL, and every line added at one is journaled with the
+/// price's age. Returns `NotFound` when the provider has no price for the SKU, `TimedOut`
+/// when the provider failed and no usable price is cached, and `InvalidState` when no
+/// provider is registered. The line ID and the unit price used are written on success.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to `sku_len` bytes of UTF-8 encoded SKU
+/// - `out_line_id` and `out_unit_minor` point to valid memory for the results
+/// - `qty` is greater than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_priced_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    out_line_id: *mut u32,
+    out_unit_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_add_priced_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 || out_line_id.is_null() || out_unit_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_priced_line(handle, sku, qty) {
+        Ok((line_id, unit_minor)) => {
+            kernel_store.after_add_line(handle, line_id);
+            *out_line_id = line_id;
+            *out_unit_minor = unit_minor;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Drops cached prices after a price change, for one SKU or, with
+/// `sku_ptr` null, all of them. The next priced line for the SKU asks the price provider.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` is null or points to `sku_len` bytes of UTF-8 encoded SKU
+#[no_mangle]
+pub unsafe extern "C" fn pk_invalidate_price_cache(
+    sku_ptr: *const u8,
+    sku_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_invalidate_price_cache");
+    let sku = (!sku_ptr.is_null() && sku_len > 0).then(|| read_str(sku_ptr, sku_len));
+    match read_store() {
+        Ok(s) => {
+            s.price_cache.invalidate(sku.as_deref());
+            PkResult::ok()
+        },
+        Err(code) => PkResult::err(code)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pk_add_cash_tender(
+    handle: PkTransactionHandle,
+    amount_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_add_cash_tender");
+    add_cash_tender(handle, amount_minor, None, None)
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a cash tender carrying a client-generated token of up to
+/// `max_tender_token_len` bytes. Resubmitting a token already tendered on the transaction
+/// tenders nothing and returns `Ok` again, even after the sale has been committed, so a host
+/// may safely retry a tender whose reply was lost.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `token_ptr` points to valid memory containing a UTF-8 encoded token string
+/// - `token_len` accurately represents the length of the data at `token_ptr`
+/// - `handle` refers to a valid transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_cash_tender_with_token(
+    handle: PkTransactionHandle,
+    amount_minor: i64,
+    token_ptr: *const u8,
+    token_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_add_cash_tender_with_token");
+    if token_ptr.is_null() || token_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    add_cash_tender(handle, amount_minor, Some(read_str(token_ptr, token_len)), None)
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a cash tender stated in the currency it was computed in (an
+/// ISO 4217 code such as "EUR"). Returns `CurrencyMismatch`, tendering nothing, when that is
+/// not the transaction's currency.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `currency_ptr` points to valid memory containing a UTF-8 encoded currency code
+/// - `currency_len` accurately represents the length of the data at `currency_ptr`
+/// - `handle` refers to a valid transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_cash_tender_in_currency(
+    handle: PkTransactionHandle,
+    amount_minor: i64,
+    currency_ptr: *const u8,
+    currency_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_add_cash_tender_in_currency");
+    if currency_ptr.is_null() || currency_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    add_cash_tender(handle, amount_minor, None, Some(read_str(currency_ptr, currency_len)))
+}
+
+fn add_cash_tender(handle: PkTransactionHandle, amount_minor: i64, token: Option<String>, currency: Option<String>) -> PkResult {
+    if handle == PK_INVALID_HANDLE || amount_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match apply_tender(handle, |s| s.add_cash_tender_legal(handle, amount_minor, token, currency.as_deref()).map(|committed| ((), committed))) {
+        Ok(()) => PkResult::ok(),
+        Err(result) => result
+    }
+}
+
+// SCALABILITY: Tenders run under the shared store lock and the transaction's shard lock, so
+// lanes tender concurrently. Only under commit_on_full_tender, where a tender may commit the
+// sale, is the store taken exclusively and the call acknowledged once the commit is durable.
+// `tender` returns its value and whether the sale committed.
+fn apply_tender<T>(handle: u64, tender: impl FnOnce(&LegalKernelStore) -> Result<(T, bool), KernelError>) -> Result<T, PkResult> {
+    let kernel_store = read_store().map_err(PkResult::err)?;
+    if !kernel_store.system_config.commit_on_full_tender {
+        return tender(&kernel_store).map(|(value, _)| value).map_err(|e| PkResult::from_error(&e));
+    }
+    drop(kernel_store);
+    
+    let mut kernel_store = write_store().map_err(PkResult::err)?;
+    let (value, committed) = tender(&kernel_store).map_err(|e| PkResult::from_error(&e))?;
+    if committed {
+        kernel_store.record_commit(handle);
+    }
+    await_durable_commit(kernel_store).map_err(PkResult::err)?;
+    Ok(value)
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a card tender approved by the payment device, identified by
+/// the device's authorization reference (PANs in it are masked). This is the capture step of a
+/// card-present payment: the host runs the device session (prompt, timeout, cancel) and calls
+/// this once the device reports an approval. A card tender cannot exceed the balance due.
+/// Resubmitting an authorization reference already tendered on the transaction tenders
+/// nothing and returns `Ok` again, so a retried device callback is safe.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `auth_ptr` points to valid memory containing a UTF-8 encoded authorization reference
+/// - `auth_len` accurately represents the length of the data at `auth_ptr`
+/// - `handle` refers to a valid transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_card_tender(
+    handle: PkTransactionHandle,
+    amount_minor: i64,
+    auth_ptr: *const u8,
+    auth_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_add_card_tender");
+    if handle == PK_INVALID_HANDLE || amount_minor <= 0 || auth_ptr.is_null() || auth_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let auth_reference = read_str(auth_ptr, auth_len);
+    match apply_tender(handle, |s| s.add_card_tender_legal(handle, amount_minor, auth_reference).map(|committed| ((), committed))) {
+        Ok(()) => PkResult::ok(),
+        Err(result) => result
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Takes cash toward the balance due through the cash recycler of the
+/// transaction's terminal and adds what the device counted as a cash tender, written to
+/// `out_amount_minor`. The deposit is journaled whatever happens next; if the tender is
+/// then refused (the sale changed state meanwhile), the host must return the cash. Change is
+/// paid out by the recycler when the sale is committed.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction assigned to a terminal
+/// - `out_amount_minor` is null or points to valid memory for the amount tendered
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_recycler_cash_tender(
+    handle: PkTransactionHandle,
+    out_amount_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_add_recycler_cash_tender");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let deposit = match read_store() {
+        Ok(s) => s.recycler_deposit(handle),
+        Err(code) => return PkResult::err(code)
+    };
+    let (terminal_id, due_minor, recycler) = match deposit {
+        Ok(deposit) => deposit,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    // The customer inserts cash while the device counts; the store lock is not held meanwhile
+    let outcome = recycler.accept_deposit(&terminal_id, due_minor);
+    
+    let result = apply_tender(handle, |s| {
+        s.record_recycler_activity(handle, &terminal_id, recycler.name(), peripheral::RecyclerOperation::Deposit, due_minor, outcome)
+            .and_then(|(_, amount_minor)| match amount_minor {
+                0 => Ok((0, false)),
+                _ => s.add_cash_tender_legal(handle, amount_minor, None, None).map(|committed| (amount_minor, committed)),
+            })
+    });
+    
+    match result {
+        Ok(amount_minor) => {
+            if !out_amount_minor.is_null() {
+                *out_amount_minor = amount_minor;
+            }
+            PkResult::ok()
+        },
+        Err(result) => result
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Reads the notes and coins held by a terminal's cash recycler and
+/// writes them as JSON, with their total, the drawer session's expected cash and the variance
+/// (held less expected) when a session is open. The reading is journaled.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_recycler_inventory_json(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_recycler_inventory_json");
+    if terminal_ptr.is_null() || terminal_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    
+    let recycler = match read_store() {
+        Ok(s) => s.recyclers.get(&terminal_id).cloned(),
+        Err(code) => return PkResult::err(code)
+    };
+    let recycler = match recycler {
+        Some(recycler) => recycler,
+        None => return PkResult::err(ResultCode::NotFound)
+    };
+    
+    let outcome = recycler.inventory(&terminal_id);
+    let json = match read_store() {
+        Ok(s) => s.recycler_inventory_json(&terminal_id, recycler.name(), outcome),
+        Err(code) => return PkResult::err(code)
+    };
+    match json {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Commits a fully tendered sale. Tendering in full leaves the sale
+/// open so lines, tenders and other adjustments can follow; this call is what moves it to
+/// Committed, signs it with the TSE and delivers the receipt. The `commit_on_full_tender`
+/// compatibility setting restores committing on the tender that pays in full.
+/// Returns `InvalidState` for a transaction that is not open or not fully tendered.
+#[no_mangle]
+pub extern "C" fn pk_finalize_transaction(handle: PkTransactionHandle) -> PkResult {
+    metrics::instrument!("pk_finalize_transaction");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let result = kernel_store.finalize_transaction(handle);
+    if result.is_ok() {
+        if let Err(code) = await_durable_commit(kernel_store) {
+            return PkResult::err(code);
+        }
+    }
+    
+    match result {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+// The cached cell while the transaction is active; an archived one is read under the store lock
+fn cached_or_stored_totals(handle: u64) -> Option<totals::TotalsSnapshot> {
+    totals::lookup(handle).or_else(|| {
+        let store = read_store().ok()?;
+        let tx = store.transaction(handle).ok()?;
+        Some(tx.totals_snapshot())
+    })
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves transaction totals and state information.
+/// Reads an active transaction's cached totals without taking the store lock, so polling
+/// displays never wait behind lane activity. Settled transactions are read from the store.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_total` points to valid memory where the total amount can be written
+/// - `out_tendered` points to valid memory where the tendered amount can be written
+/// - `out_change` points to valid memory where the change amount can be written
+/// - `out_state` points to valid memory where the transaction state can be written
+/// - All output pointers remain valid for the duration of this call
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_totals(
+    handle: PkTransactionHandle,
+    out_total: *mut i64,
+    out_tendered: *mut i64,
+    out_change: *mut i64,
+    out_state: *mut i32
+) -> PkResult {
+    metrics::instrument!("pk_get_totals");
+    if handle == PK_INVALID_HANDLE || out_total.is_null() || out_tendered.is_null() || out_change.is_null() || out_state.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match cached_or_stored_totals(handle) {
+        Some(totals) => {
+            *out_total = totals.total_minor;
+            *out_tendered = totals.tendered_minor;
+            *out_change = totals.change_minor;
+            *out_state = totals.state as i32;
+            PkResult::ok()
+        },
+        None => PkResult::err(ResultCode::NotFound)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the number of line items in a transaction.
+/// Served from the cached totals, like `pk_get_totals`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_count` points to valid memory where the line count can be written
+/// - The output pointer remains valid for the duration of this call
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_count(
+    handle: PkTransactionHandle,
+    out_count: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_get_line_count");
+    if handle == PK_INVALID_HANDLE || out_count.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match cached_or_stored_totals(handle) {
+        Some(totals) => {
+            *out_count = totals.line_count;
+            PkResult::ok()
+        },
+        None => PkResult::err(ResultCode::NotFound)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the number of decimal places for the transaction's currency.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_decimal_places` points to valid memory where the decimal places value can be written
+/// - The output pointer remains valid for the duration of this call
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_currency_decimal_places(
+    handle: PkTransactionHandle,
+    out_decimal_places: *mut u8
+) -> PkResult {
+    metrics::instrument!("pk_get_currency_decimal_places");
+    if handle == PK_INVALID_HANDLE || out_decimal_places.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_currency_decimal_places(handle) {
+        Ok(decimal_places) => {
+            *out_decimal_places = decimal_places;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves details of a specific line item with parent relationship.
+/// NRF COMPLIANCE: Returns parent_line_item_id for hierarchical display.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_index` is within the valid range of line items (0 to line_count-1)
+/// - `out_sku_ptr` points to valid memory buffer for the SKU string
+/// - `out_sku_len` specifies the size of the buffer, receives actual string length
+/// - `out_qty`, `out_unit_minor`, and `out_parent_id` point to valid memory for output values
+/// - `out_has_parent` points to valid memory for parent existence flag
+/// - All output pointers remain valid for the duration of this call
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_item_with_parent(
+    handle: PkTransactionHandle,
+    line_index: u32,
+    out_sku_ptr: *mut u8,
+    out_sku_len: *mut usize,
+    out_qty: *mut i32,
+    out_unit_minor: *mut i64,
+    out_parent_id: *mut u32,
+    out_has_parent: *mut bool
+) -> PkResult {
+    metrics::instrument!("pk_get_line_item_with_parent");
+    if handle == PK_INVALID_HANDLE || out_sku_ptr.is_null() || out_sku_len.is_null() || out_qty.is_null() || out_unit_minor.is_null() || out_parent_id.is_null() || out_has_parent.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_item_details(handle, line_index) {
+        Ok((sku, qty, unit_minor, parent_id)) => {
+            let sku_bytes = sku.as_bytes();
+            let buffer_size = *out_sku_len;
+            
+            if sku_bytes.len() >= buffer_size {
+                // Buffer too small, return required size
+                *out_sku_len = sku_bytes.len() + 1; // +1 for null terminator
+                return PkResult::err(ResultCode::InsufficientBuffer);
+            }
+            
+            // Copy SKU to output buffer
+            std::ptr::copy_nonoverlapping(sku_bytes.as_ptr(), out_sku_ptr, sku_bytes.len());
+            *out_sku_ptr.add(sku_bytes.len()) = 0; // Null terminator
+            
+            *out_sku_len = sku_bytes.len();
+            *out_qty = qty;
+            *out_unit_minor = unit_minor;
+            
+            // Set parent information
+            if let Some(parent) = parent_id {
+                *out_parent_id = parent;
+                *out_has_parent = true;
+            } else {
+                *out_parent_id = 0;
+                *out_has_parent = false;
+            }
+            
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a child line item to an existing transaction with parent reference.
+/// NRF COMPLIANCE: Supports linked items (parent-child relationships). Returns
+/// `ParentLineVoided` when the parent has been voided.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `sku_len` accurately represents the length of the data at `sku_ptr`
+/// - The memory pointed to by `sku_ptr` remains valid for the duration of this call
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+/// - `parent_line_id` is the stable line ID of the parent item within the transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_child_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64,
+    parent_line_id: u32
+) -> PkResult {
+    metrics::instrument!("pk_add_child_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_child_line_legal(handle, sku, qty, unit_minor, parent_line_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a line item referencing a parent by stable line ID.
+/// Returns `ParentLineVoided` when the parent has been voided.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `sku_len` accurately represents the length of the data at `sku_ptr`
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_line_with_parent(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64,
+    parent_line_id: u32  // 0 means no parent
+) -> PkResult {
+    metrics::instrument!("pk_add_line_with_parent");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_child_line_legal(handle, sku, qty, unit_minor, parent_line_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Voids a line item with NRF-compliant cascade to child items.
+/// Critical NRF requirement: When parent items are voided, all linked child items must be voided.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_id` is the stable line ID of the item to void (see `pk_get_line_id`)
+/// - `reason_ptr` points to valid memory containing a UTF-8 encoded reason string
+/// - `reason_len` accurately represents the length of the data at `reason_ptr`
+#[no_mangle]
+pub unsafe extern "C" fn pk_void_line_item_with_cascade(
+    handle: PkTransactionHandle,
+    line_id: u32,
+    reason_ptr: *const u8,
+    reason_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_void_line_item_with_cascade");
+    if handle == PK_INVALID_HANDLE || line_id == 0 || reason_ptr.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let reason = read_str(reason_ptr, reason_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    // Use the NRF void cascade logic
+    match kernel_store.void_line_with_cascade(handle, line_id, &reason) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the parent line item ID for a specific line item.
+/// NRF COMPLIANCE: Supports querying linked items hierarchy.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `line_id` is the stable line ID of a line item in the transaction
+/// - `out_parent_id` and `out_has_parent` point to valid memory for output values
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_parent_id(
+    handle: PkTransactionHandle,
+    line_id: u32,
+    out_parent_id: *mut u32,
+    out_has_parent: *mut bool
+) -> PkResult {
+    metrics::instrument!("pk_get_line_parent_id");
+    if handle == PK_INVALID_HANDLE || line_id == 0 || out_parent_id.is_null() || out_has_parent.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_parent_id(handle, line_id) {
+        Ok(parent_id_opt) => {
+            if let Some(parent_id) = parent_id_opt {
+                *out_parent_id = parent_id;
+                *out_has_parent = true;
+            } else {
+                *out_parent_id = 0;
+                *out_has_parent = false;
+            }
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Finds all child line items of a parent (for void cascade).
+/// NRF COMPLIANCE: Supports void cascade for linked items. Children are returned as stable line IDs.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `parent_line_id` is the stable line ID of a line item in the transaction
+/// - `out_children_ptr` points to a writable buffer of `*out_children_len` u32 values
+/// - `out_children_len` points to valid memory holding the buffer capacity; receives the child count
+#[no_mangle]
+pub unsafe extern "C" fn pk_find_line_children(
+    handle: PkTransactionHandle,
+    parent_line_id: u32,
+    out_children_ptr: *mut u32,
+    out_children_len: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_find_line_children");
+    if handle == PK_INVALID_HANDLE || parent_line_id == 0 || out_children_ptr.is_null() || out_children_len.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.find_line_children(handle, parent_line_id) {
+        Ok(children) => {
+            let buffer_size = *out_children_len;
+            
+            if children.len() > buffer_size {
+                // Buffer too small, return required size
+                *out_children_len = children.len();
+                return PkResult::err(ResultCode::InsufficientBuffer);
+            }
+            
+            // Copy child line numbers to output buffer
+            for (i, &child_id) in children.iter().enumerate() {
+                *out_children_ptr.add(i) = child_id;
+            }
+            
+            *out_children_len = children.len();
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a container deposit line linked to the item that triggered it.
+/// REGULATORY COMPLIANCE: Deposits are typed distinctly so they are never discounted and can be
+/// reported separately. The deposit is a child of the triggering line, so voiding the item
+/// cascades to its deposit; a voided item returns `ParentLineVoided`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `sku_len` accurately represents the length of the data at `sku_ptr`
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+/// - `triggering_line_id` is the stable line ID of the sale item carrying the deposit
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_deposit_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64,
+    triggering_line_id: u32
+) -> PkResult {
+    metrics::instrument!("pk_add_deposit_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 || unit_minor < 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_deposit_line_legal(handle, sku, qty, unit_minor, triggering_line_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a deposit-return (refund) line for returned containers.
+/// `unit_minor` is the positive deposit value per container; the line reduces the amount due.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `sku_len` accurately represents the length of the data at `sku_ptr`
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_deposit_return_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_add_deposit_return_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 || unit_minor < 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_deposit_return_line_legal(handle, sku, qty, unit_minor) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the line type of a line item (see `LineKind`).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_index` is within the valid range of line items (0 to line_count-1)
+/// - `out_kind` points to valid memory where the line kind code can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_kind(
+    handle: PkTransactionHandle,
+    line_index: u32,
+    out_kind: *mut i32
+) -> PkResult {
+    metrics::instrument!("pk_get_line_kind");
+    if handle == PK_INVALID_HANDLE || out_kind.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_kind(handle, line_index) {
+        Ok(kind) => {
+            *out_kind = kind as i32;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Pins the lines of an active transaction for zero-copy reads.
+/// Writes a pointer to `out_count` consecutive `PkLineView` entries owned by the kernel. The
+/// entries (and the SKU bytes they point to) stay valid until the next call that takes the
+/// transaction for modification, the transaction settles, or `pk_release_lines` is called.
+/// Pinning an unmodified transaction again returns the same buffer.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_lines` points to valid memory where the buffer pointer can be written
+/// - `out_count` points to valid memory where the line count can be written
+/// - The buffer is not read after it has been invalidated
+#[no_mangle]
+pub unsafe extern "C" fn pk_pin_lines(
+    handle: PkTransactionHandle,
+    out_lines: *mut *const PkLineView,
+    out_count: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_pin_lines");
+    if handle == PK_INVALID_HANDLE || out_lines.is_null() || out_count.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.pin_lines(handle) {
+        Ok(pinned) => {
+            // The transaction keeps the buffer alive after this reference is dropped
+            *out_lines = pinned.as_ptr();
+            *out_count = pinned.len();
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Releases the pinned line buffer of an active transaction.
+/// Pointers obtained from `pk_pin_lines` for the transaction must not be used afterwards.
+#[no_mangle]
+pub extern "C" fn pk_release_lines(handle: PkTransactionHandle) -> PkResult {
+    metrics::instrument!("pk_release_lines");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.release_pinned_lines(handle) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves deposit totals for regulatory reporting.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_deposits_minor` points to valid memory for the total deposits charged
+/// - `out_deposit_returns_minor` points to valid memory for the total deposits refunded
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_deposit_totals(
+    handle: PkTransactionHandle,
+    out_deposits_minor: *mut i64,
+    out_deposit_returns_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_get_deposit_totals");
+    if handle == PK_INVALID_HANDLE || out_deposits_minor.is_null() || out_deposit_returns_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_deposit_totals(handle) {
+        Ok((deposits, returns)) => {
+            *out_deposits_minor = deposits;
+            *out_deposit_returns_minor = returns;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Grants a permission to an operator (e.g. a supervisor).
+/// Authentication is a user-space concern; the kernel only enforces granted permissions.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` points to valid memory containing a UTF-8 encoded operator ID
+/// - `operator_len` accurately represents the length of the data at `operator_ptr`
+/// - `permission` is a valid `Permission` code
+#[no_mangle]
+pub unsafe extern "C" fn pk_grant_permission(
+    operator_ptr: *const u8,
+    operator_len: usize,
+    permission: i32
+) -> PkResult {
+    metrics::instrument!("pk_grant_permission");
+    if operator_ptr.is_null() || operator_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let permission = match Permission::from_code(permission) {
+        Some(p) => p,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let operator_id = read_str(operator_ptr, operator_len);
+    if let Err(e) = pci::screen(TextField::OperatorId, &operator_id) {
+        return PkResult::from_error(&e);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.grant_permission(operator_id, permission);
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Revokes a permission previously granted to an operator.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` points to valid memory containing a UTF-8 encoded operator ID
+/// - `operator_len` accurately represents the length of the data at `operator_ptr`
+/// - `permission` is a valid `Permission` code
+#[no_mangle]
+pub unsafe extern "C" fn pk_revoke_permission(
+    operator_ptr: *const u8,
+    operator_len: usize,
+    permission: i32
+) -> PkResult {
+    metrics::instrument!("pk_revoke_permission");
+    if operator_ptr.is_null() || operator_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let permission = match Permission::from_code(permission) {
+        Some(p) => p,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let operator_id = read_str(operator_ptr, operator_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.revoke_permission(&operator_id, permission);
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a manual negative-price adjustment line (goodwill credit,
+/// damage allowance). AUDIT COMPLIANCE: Requires a reason code and a supervisor holding the
+/// ManualAdjustment permission; both are recorded on the line.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr`, `reason_ptr` and `supervisor_ptr` point to valid UTF-8 encoded strings
+/// - `sku_len`, `reason_len` and `supervisor_len` accurately represent their lengths
+/// - `handle` refers to a valid, active transaction
+/// - `amount_minor` is less than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_adjustment_line(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    amount_minor: i64,
+    reason_ptr: *const u8,
+    reason_len: usize,
+    supervisor_ptr: *const u8,
+    supervisor_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_add_adjustment_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || amount_minor >= 0
+        || reason_ptr.is_null() || reason_len == 0 || supervisor_ptr.is_null() || supervisor_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    let reason_code = read_str(reason_ptr, reason_len);
+    let supervisor_id = read_str(supervisor_ptr, supervisor_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    if !kernel_store.has_permission(&supervisor_id, Permission::ManualAdjustment) {
+        return PkResult::err(ResultCode::PermissionDenied);
+    }
+    
+    match kernel_store.add_adjustment_line_legal(handle, sku, amount_minor, reason_code, supervisor_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the reason code and approving supervisor of an
+/// adjustment line. Strings are written without a null terminator; required sizes are
+/// always reported through `out_reason_len` and `out_supervisor_len`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_index` is within the valid range of line items (0 to line_count-1)
+/// - `reason_buffer` and `supervisor_buffer` point to writable buffers of the given sizes
+/// - `out_reason_len` and `out_supervisor_len` point to valid memory for the required sizes
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_adjustment_info(
+    handle: PkTransactionHandle,
+    line_index: u32,
+    reason_buffer: *mut u8,
+    reason_buffer_size: usize,
+    out_reason_len: *mut usize,
+    supervisor_buffer: *mut u8,
+    supervisor_buffer_size: usize,
+    out_supervisor_len: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_line_adjustment_info");
+    if handle == PK_INVALID_HANDLE || out_reason_len.is_null() || out_supervisor_len.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_adjustment_info(handle, line_index) {
+        Ok((reason, supervisor)) => {
+            let reason_result = write_str(&reason, reason_buffer, reason_buffer_size, out_reason_len);
+            let supervisor_result = write_str(&supervisor, supervisor_buffer, supervisor_buffer_size, out_supervisor_len);
+            if reason_result.code != 0 {
+                reason_result
+            } else {
+                supervisor_result
+            }
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Maps a display position to the line's stable line ID.
+/// Line IDs are assigned at creation and never change; display positions are for
+/// presentation only. All line-addressing APIs (parents, voids) take stable line IDs.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_index` is within the valid range of line items (0 to line_count-1)
+/// - `out_line_id` points to valid memory where the line ID can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_id(
+    handle: PkTransactionHandle,
+    line_index: u32,
+    out_line_id: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_get_line_id");
+    if handle == PK_INVALID_HANDLE || out_line_id.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_id(handle, line_index) {
+        Ok(line_id) => {
+            *out_line_id = line_id;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Applies a named kernel setting (e.g. `max_transaction_attributes`).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `key_ptr` and `value_ptr` point to valid UTF-8 encoded strings
+/// - `key_len` and `value_len` accurately represent their lengths
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_config(
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_config");
+    if key_ptr.is_null() || key_len == 0 || value_ptr.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let key = read_str(key_ptr, key_len);
+    let value = read_str(value_ptr, value_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.set_config(&key, &value) {
+        Ok(_) => PkResult::ok(),
+        Err(_) => PkResult::err(ResultCode::ValidationFailed)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Attaches a user-space key/value attribute to a transaction.
+/// Keys and values are opaque to the kernel; count and length limits are configurable.
+/// Setting an existing key replaces its value. Attributes are journaled and exported.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `key_ptr` and `value_ptr` point to valid UTF-8 encoded strings
+/// - `key_len` and `value_len` accurately represent their lengths
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_transaction_attribute(
+    handle: PkTransactionHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_ptr: *const u8,
+    value_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_transaction_attribute");
+    if handle == PK_INVALID_HANDLE || key_ptr.is_null() || key_len == 0 || value_ptr.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let key = read_str(key_ptr, key_len);
+    let value = read_str(value_ptr, value_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.set_transaction_attribute(handle, key, value) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves a transaction attribute value. The value is written
+/// without a null terminator; the required size is always reported through `out_value_len`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `key_ptr` points to a valid UTF-8 encoded string of `key_len` bytes
+/// - `value_buffer` points to a writable buffer of `value_buffer_size` bytes
+/// - `out_value_len` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_transaction_attribute(
+    handle: PkTransactionHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    value_buffer: *mut u8,
+    value_buffer_size: usize,
+    out_value_len: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_transaction_attribute");
+    if handle == PK_INVALID_HANDLE || key_ptr.is_null() || key_len == 0 || out_value_len.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let key = read_str(key_ptr, key_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_transaction_attribute(handle, &key) {
+        Ok(Some(value)) => write_str(&value, value_buffer, value_buffer_size, out_value_len),
+        Ok(None) => PkResult::err(ResultCode::NotFound),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Removes a transaction attribute.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `key_ptr` points to a valid UTF-8 encoded string of `key_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_remove_transaction_attribute(
+    handle: PkTransactionHandle,
+    key_ptr: *const u8,
+    key_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_remove_transaction_attribute");
+    if handle == PK_INVALID_HANDLE || key_ptr.is_null() || key_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let key = read_str(key_ptr, key_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.remove_transaction_attribute(handle, &key) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Exports a transaction (lines, tenders, attributes) as JSON.
+/// Amounts are in minor units; the JSON is written without a null terminator and the
+/// required size is always reported through `out_required_size`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_export_transaction_json(
+    handle: PkTransactionHandle,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_export_transaction_json");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.export_transaction_json(handle) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a line item carrying display metadata from the product catalog.
+/// The kernel stores name and description for display layers but never interprets them.
+/// Zero-length name or description means "not supplied"; with a product catalog registered, a
+/// field not supplied is taken from the catalog and the line is checked as for `pk_add_line`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
+/// - `name_ptr` and `description_ptr` are null or point to valid UTF-8 encoded strings
+/// - `sku_len`, `name_len` and `description_len` accurately represent their lengths
+/// - `handle` refers to a valid, active transaction
+/// - `qty` is greater than zero
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_line_with_metadata(
+    handle: PkTransactionHandle,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    qty: i32,
+    unit_minor: i64,
+    name_ptr: *const u8,
+    name_len: usize,
+    description_ptr: *const u8,
+    description_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_add_line_with_metadata");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    let product_name = Some(read_str(name_ptr, name_len)).filter(|n| !n.is_empty());
+    let product_description = Some(read_str(description_ptr, description_len)).filter(|d| !d.is_empty());
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_line_with_metadata_legal(handle, sku, qty, unit_minor, product_name, product_description) {
+        Ok(line_id) => {
+            kernel_store.after_add_line(handle, line_id);
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the product name and description of a line item.
+/// Strings are written without a null terminator; required sizes (0 when not supplied)
+/// are always reported through `out_name_len` and `out_description_len`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `line_index` is within the valid range of line items (0 to line_count-1)
+/// - `name_buffer` and `description_buffer` point to writable buffers of the given sizes
+/// - `out_name_len` and `out_description_len` point to valid memory for the required sizes
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_line_product_metadata(
+    handle: PkTransactionHandle,
+    line_index: u32,
+    name_buffer: *mut u8,
+    name_buffer_size: usize,
+    out_name_len: *mut usize,
+    description_buffer: *mut u8,
+    description_buffer_size: usize,
+    out_description_len: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_line_product_metadata");
+    if handle == PK_INVALID_HANDLE || out_name_len.is_null() || out_description_len.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_line_product_metadata(handle, line_index) {
+        Ok((name, description)) => {
+            let name_result = write_str(name.as_deref().unwrap_or(""), name_buffer, name_buffer_size, out_name_len);
+            let description_result = write_str(description.as_deref().unwrap_or(""), description_buffer, description_buffer_size, out_description_len);
+            if name_result.code != 0 {
+                name_result
+            } else {
+                description_result
+            }
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Attaches an opaque customer reference (and optional tier) to a
+/// transaction, before or after lines are added. Replaces any existing reference.
+/// CRM INTEGRATION: The reference is journaled, published as an event and exported.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `token_ptr` points to a valid UTF-8 encoded string of `token_len` bytes
+/// - `tier_ptr` is null or points to a valid UTF-8 encoded string of `tier_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_attach_customer(
+    handle: PkTransactionHandle,
+    token_ptr: *const u8,
+    token_len: usize,
+    tier_ptr: *const u8,
+    tier_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_attach_customer");
+    if handle == PK_INVALID_HANDLE || token_ptr.is_null() || token_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let token = read_str(token_ptr, token_len);
+    let tier = Some(read_str(tier_ptr, tier_len)).filter(|t| !t.is_empty());
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.attach_customer(handle, token, tier) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the customer reference attached to a transaction.
+/// Strings are written without a null terminator; required sizes (tier is 0 when not
+/// supplied) are always reported. Returns NotFound when no customer is attached.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `token_buffer` and `tier_buffer` point to writable buffers of the given sizes
+/// - `out_token_len` and `out_tier_len` point to valid memory for the required sizes
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_customer(
+    handle: PkTransactionHandle,
+    token_buffer: *mut u8,
+    token_buffer_size: usize,
+    out_token_len: *mut usize,
+    tier_buffer: *mut u8,
+    tier_buffer_size: usize,
+    out_tier_len: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_customer");
+    if handle == PK_INVALID_HANDLE || out_token_len.is_null() || out_tier_len.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_customer(handle) {
+        Ok(Some(customer)) => {
+            let token_result = write_str(&customer.token, token_buffer, token_buffer_size, out_token_len);
+            let tier_result = write_str(customer.tier.as_deref().unwrap_or(""), tier_buffer, tier_buffer_size, out_tier_len);
+            if token_result.code != 0 {
+                token_result
+            } else {
+                tier_result
+            }
+        },
+        Ok(None) => PkResult::err(ResultCode::NotFound),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Dequeues the oldest pending kernel event as JSON.
+/// The event is only removed once it has been copied successfully; on InsufficientBuffer
+/// the required size is reported and the event stays queued. Returns NotFound when empty.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_next_event_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_next_event_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let (sequence_number, json) = match kernel_store.next_event_json() {
+        Some((sequence_number, Ok(json))) => (sequence_number, json),
+        Some((_, Err(_))) => return PkResult::err(ResultCode::InternalError),
+        None => {
+            *out_required_size = 0;
+            return PkResult::err(ResultCode::NotFound);
+        }
+    };
+    
+    // Another poller may have taken the event meanwhile; only that exact event is removed
+    let result = write_str(&json, buffer, buffer_size, out_required_size);
+    if result.code == 0 {
+        kernel_store.events.pop(sequence_number);
+    }
+    result
+}
+
+/// ARCHITECTURAL COMPONENT: Dequeues the oldest pending customer display update for a
+/// terminal as JSON: item added or voided, subtotal, total due, change due, all in minor units
+/// with the currency's decimal places. A null or empty terminal ID reads the updates of
+/// transactions with no terminal assigned. The update is only removed once it has been copied
+/// successfully. Returns NotFound when the terminal has none pending.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` is null or points to `terminal_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_next_display_update_json(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_next_display_update_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let (sequence_number, json) = match kernel_store.next_display_update_json(&terminal_id) {
+        Some((sequence_number, Ok(json))) => (sequence_number, json),
+        Some((_, Err(_))) => return PkResult::err(ResultCode::InternalError),
+        None => {
+            *out_required_size = 0;
+            return PkResult::err(ResultCode::NotFound);
+        }
+    };
+    
+    let result = write_str(&json, buffer, buffer_size, out_required_size);
+    if result.code == 0 {
+        kernel_store.display.pop(&terminal_id, sequence_number);
+    }
+    result
+}
+
+/// ARCHITECTURAL COMPONENT: The cart of a terminal's current transaction (the one its latest
+/// display update was for) as JSON, shaped for a customer-facing second display: live items
+/// in hierarchy order, each with its parent and depth, and the subtotal, savings so far,
+/// total, amount tendered and amount due. `sequence_number` is the last display update the
+/// view reflects; the display then follows `pk_next_display_update_json` from the next one,
+/// and takes a fresh view after a gap. A null or empty terminal ID reads the cart of
+/// transactions with no terminal assigned. Returns NotFound before the terminal's first update.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` is null or points to `terminal_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_cart_view_json(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_cart_view_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.cart_view_json(&terminal_id) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records loyalty activity (points accrued and redeemed) on a
+/// transaction. Activity accumulates per transaction and may be recorded after commit,
+/// since accrual is typically quoted by the loyalty provider around commit.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `account_ptr` points to a valid UTF-8 encoded account ID of `account_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_record_loyalty_activity(
+    handle: PkTransactionHandle,
+    account_ptr: *const u8,
+    account_len: usize,
+    points_accrued: i64,
+    points_redeemed: i64
+) -> PkResult {
+    metrics::instrument!("pk_record_loyalty_activity");
+    if handle == PK_INVALID_HANDLE || account_ptr.is_null() || account_len == 0 || points_accrued < 0 || points_redeemed < 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let account_id = read_str(account_ptr, account_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.record_loyalty_activity(handle, &account_id, points_accrued, points_redeemed) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the loyalty points accrued and redeemed on a transaction.
+/// Returns NotFound when no loyalty activity has been recorded.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `out_points_accrued` and `out_points_redeemed` point to valid memory for output values
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_loyalty_activity(
+    handle: PkTransactionHandle,
+    out_points_accrued: *mut i64,
+    out_points_redeemed: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_get_loyalty_activity");
+    if handle == PK_INVALID_HANDLE || out_points_accrued.is_null() || out_points_redeemed.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_loyalty_activity(handle) {
+        Ok(Some(activity)) => {
+            *out_points_accrued = activity.points_accrued;
+            *out_points_redeemed = activity.points_redeemed;
+            PkResult::ok()
+        },
+        Ok(None) => PkResult::err(ResultCode::NotFound),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a loyalty POINTS tender. Points are converted to a monetary
+/// value at the configured rate (`points_rate_points` points = `points_rate_minor` minor units,
+/// rounded down). Redemption may not exceed the amount due or the per-transaction points cap.
+/// Both the points burned and their value are recorded in the tender entry.
+/// Burning points with the loyalty program is a user-space concern (see `loyalty::redeem_as_tender`).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_value_minor` is null or points to valid memory for the tender's monetary value
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_points_tender(
+    handle: PkTransactionHandle,
+    points: i64,
+    out_value_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_add_points_tender");
+    if handle == PK_INVALID_HANDLE || points <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match apply_tender(handle, |s| s.add_points_tender_legal(handle, points)) {
+        Ok(value_minor) => {
+            if !out_value_minor.is_null() {
+                *out_value_minor = value_minor;
+            }
+            PkResult::ok()
+        },
+        Err(result) => result
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Converts a Building transaction into a layaway with an initial
+/// deposit. The basket is frozen; the deposit must meet `layaway_min_deposit_percent` of the
+/// total and be less than the amount due. State becomes Layaway (state code 2).
+/// 
+/// # Safety
+/// The caller must ensure that `handle` refers to a valid, active transaction.
+#[no_mangle]
+pub unsafe extern "C" fn pk_create_layaway(
+    handle: PkTransactionHandle,
+    deposit_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_create_layaway");
+    if handle == PK_INVALID_HANDLE || deposit_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.create_layaway(handle, deposit_minor) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Takes a partial payment against an open layaway. When the layaway
+/// is fully paid it converts to a completed sale (state Committed) and `out_completed` is set.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid layaway transaction
+/// - `out_completed` is null or points to valid memory for the completion flag
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_layaway_payment(
+    handle: PkTransactionHandle,
+    amount_minor: i64,
+    out_completed: *mut bool
+) -> PkResult {
+    metrics::instrument!("pk_add_layaway_payment");
+    if handle == PK_INVALID_HANDLE || amount_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let result = kernel_store.add_layaway_payment(handle, amount_minor);
+    if result.is_ok() {
+        if let Err(code) = await_durable_commit(kernel_store) {
+            return PkResult::err(code);
+        }
+    }
+    
+    match result {
+        Ok(completed) => {
+            if !out_completed.is_null() {
+                *out_completed = completed;
+            }
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Cancels an open layaway, retaining `restocking_fee_minor` from the
+/// payments received. The refund due to the customer is written to `out_refund_minor`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid layaway transaction
+/// - `out_refund_minor` points to valid memory for the refund amount
+#[no_mangle]
+pub unsafe extern "C" fn pk_cancel_layaway(
+    handle: PkTransactionHandle,
+    restocking_fee_minor: i64,
+    out_refund_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_cancel_layaway");
+    if handle == PK_INVALID_HANDLE || restocking_fee_minor < 0 || out_refund_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.cancel_layaway(handle, restocking_fee_minor) {
+        Ok(refund_minor) => {
+            *out_refund_minor = refund_minor;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Abandons a sale that was never tendered (the customer walked away
+/// or the lane was reset). The transaction is cancelled with its lines kept for the audit
+/// trail, and stock reserved for its lines is released to the inventory provider.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `reason_ptr` points to `reason_len` bytes of UTF-8 encoded reason
+#[no_mangle]
+pub unsafe extern "C" fn pk_abandon_transaction(
+    handle: PkTransactionHandle,
+    reason_ptr: *const u8,
+    reason_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_abandon_transaction");
+    if handle == PK_INVALID_HANDLE || reason_ptr.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let reason = read_str(reason_ptr, reason_len);
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.abandon_transaction(handle, &reason) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Begins a quote (estimate). Quotes accept lines like a normal
+/// transaction and report totals, but can never be tendered or committed. Quote handles carry
+/// `PK_QUOTE_HANDLE_FLAG` and do not consume transaction sequence numbers. The store name and
+/// currency are checked as in `pk_begin_transaction`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` and `currency_ptr` point to valid UTF-8 encoded strings
+/// - `store_len` and `currency_len` accurately represent their lengths
+/// - `out_handle` points to valid memory where the quote handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_begin_quote(
+    store_ptr: *const u8,
+    store_len: usize,
+    currency_ptr: *const u8,
+    currency_len: usize,
+    currency_decimal_places: u8,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_begin_quote");
+    if store_ptr.is_null() || currency_ptr.is_null() || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let store = read_str(store_ptr, store_len);
+    let currency_code = read_str(currency_ptr, currency_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let currency = match kernel_store.check_begin_parameters(&store, &currency_code, currency_decimal_places) {
+        Ok(c) => c,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    match kernel_store.begin_quote(store, currency) {
+        Ok(handle) => {
+            *out_handle = handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Saves a quote under a user-space reference for later recall.
+/// Saving again under a new reference replaces the old one.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid quote
+/// - `reference_ptr` points to a valid UTF-8 encoded string of `reference_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_save_quote(
+    handle: PkTransactionHandle,
+    reference_ptr: *const u8,
+    reference_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_save_quote");
+    if handle == PK_INVALID_HANDLE || reference_ptr.is_null() || reference_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let reference = read_str(reference_ptr, reference_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.save_quote(handle, reference) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Recalls a saved quote by reference.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `reference_ptr` points to a valid UTF-8 encoded string of `reference_len` bytes
+/// - `out_handle` points to valid memory where the quote handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_recall_quote(
+    reference_ptr: *const u8,
+    reference_len: usize,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_recall_quote");
+    if reference_ptr.is_null() || reference_len == 0 || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let reference = read_str(reference_ptr, reference_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.recall_quote(&reference) {
+        Ok(handle) => {
+            *out_handle = handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Imports an online order for in-store pickup as an open sale held
+/// under its pickup code, and returns its handle. `order_ptr` is JSON: `pickup_code`, `store`,
+/// `currency`, optional `decimal_places` (default 2), `external_order_id`, `channel` (default
+/// "online") and `customer_token`, `lines` (each `sku`, `qty`, `unit_minor`) and `payments`
+/// captured online (each `amount_minor` and the gateway `reference`, recorded as captured card
+/// tenders). Payments may not exceed the order total. The order waits outside any terminal
+/// until recalled with `pk_recall_pickup_order`; a code can be reused once its sale is
+/// completed or abandoned.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `order_ptr` points to a valid UTF-8 encoded string of `order_len` bytes
+/// - `out_handle` points to valid memory where the transaction handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_import_pickup_order(
+    order_ptr: *const u8,
+    order_len: usize,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_import_pickup_order");
+    if order_ptr.is_null() || order_len == 0 || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let order = read_str(order_ptr, order_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.import_pickup_order(&order) {
+        Ok(handle) => {
+            *out_handle = handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Recalls a pickup order by its code, assigning it to the terminal
+/// when `terminal_ptr` is not null. The lane then verifies the goods against the lines,
+/// adjusts as needed, tenders any balance and finalizes the sale as usual.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `code_ptr` points to a valid UTF-8 encoded string of `code_len` bytes
+/// - `terminal_ptr` is null or points to `terminal_len` bytes of UTF-8
+/// - `out_handle` points to valid memory where the transaction handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_recall_pickup_order(
+    code_ptr: *const u8,
+    code_len: usize,
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_recall_pickup_order");
+    if code_ptr.is_null() || code_len == 0 || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let code = read_str(code_ptr, code_len);
+    let terminal_id = (!terminal_ptr.is_null()).then(|| read_str(terminal_ptr, terminal_len));
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.recall_pickup_order(&code, terminal_id) {
+        Ok(handle) => {
+            *out_handle = handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Converts a quote into a real Building transaction, preserving
+/// lines (including line IDs and parent links), prices, attributes and customer reference.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid quote
+/// - `out_handle` points to valid memory where the new transaction handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_convert_quote(
+    handle: PkTransactionHandle,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_convert_quote");
+    if handle == PK_INVALID_HANDLE || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.convert_quote(handle) {
+        Ok(new_handle) => {
+            *out_handle = new_handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Opens a tab against a card pre-authorization. Card authorization
+/// itself is performed by user space; the kernel tracks the authorized amount as a
+/// PreAuthorized card tender. Lines may be added for the life of the tab.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `auth_ref_ptr` points to a valid UTF-8 encoded string of `auth_ref_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_open_tab(
+    handle: PkTransactionHandle,
+    auth_ref_ptr: *const u8,
+    auth_ref_len: usize,
+    authorized_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_open_tab");
+    if handle == PK_INVALID_HANDLE || auth_ref_ptr.is_null() || auth_ref_len == 0 || authorized_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let auth_reference = read_str(auth_ref_ptr, auth_ref_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.open_tab(handle, auth_reference, authorized_minor) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records an incremental authorization on an open tab. Hosts are
+/// prompted by `TabAuthorizationRequired` events when the tab total exceeds the authorization.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a transaction with an open tab
+/// - `out_authorized_minor` is null or points to valid memory for the new authorized amount
+#[no_mangle]
+pub unsafe extern "C" fn pk_increment_tab_authorization(
+    handle: PkTransactionHandle,
+    additional_minor: i64,
+    out_authorized_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_increment_tab_authorization");
+    if handle == PK_INVALID_HANDLE || additional_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.increment_tab_authorization(handle, additional_minor) {
+        Ok(authorized_minor) => {
+            if !out_authorized_minor.is_null() {
+                *out_authorized_minor = authorized_minor;
+            }
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the authorized amount and current total of an open tab.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a transaction with an open tab
+/// - `out_authorized_minor` and `out_total_minor` point to valid memory
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_tab_status(
+    handle: PkTransactionHandle,
+    out_authorized_minor: *mut i64,
+    out_total_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_get_tab_status");
+    if handle == PK_INVALID_HANDLE || out_authorized_minor.is_null() || out_total_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_tab_status(handle) {
+        Ok((authorized_minor, total_minor)) => {
+            *out_authorized_minor = authorized_minor;
+            *out_total_minor = total_minor;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Closes a tab, capturing the card for the total plus tip and
+/// committing the transaction. The capture may exceed the authorization by at most
+/// `tab_capture_tolerance_percent`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a transaction with an open tab
+/// - `out_captured_minor` is null or points to valid memory for the captured amount
+#[no_mangle]
+pub unsafe extern "C" fn pk_close_tab(
+    handle: PkTransactionHandle,
+    tip_minor: i64,
+    out_captured_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_close_tab");
+    if handle == PK_INVALID_HANDLE || tip_minor < 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let result = kernel_store.close_tab(handle, tip_minor);
+    if result.is_ok() {
+        if let Err(code) = await_durable_commit(kernel_store) {
+            return PkResult::err(code);
+        }
+    }
+    
+    match result {
+        Ok(captured_minor) => {
+            if !out_captured_minor.is_null() {
+                *out_captured_minor = captured_minor;
+            }
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Splits selected lines out of a Building transaction into a new
+/// transaction. Children move with their parent; a child may only be selected together with
+/// its parent. The new transaction keeps the original line IDs, store, currency, attributes
+/// and customer reference. Tendered transactions cannot be split.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `line_ids` points to `line_id_count` valid line IDs
+/// - `out_handle` points to valid memory where the new transaction handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_split_transaction(
+    handle: PkTransactionHandle,
+    line_ids: *const u32,
+    line_id_count: usize,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_split_transaction");
+    if handle == PK_INVALID_HANDLE || line_ids.is_null() || line_id_count == 0 || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let selected = std::slice::from_raw_parts(line_ids, line_id_count).to_vec();
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.split_transaction(handle, &[selected]) {
+        Ok(new_handles) => {
+            *out_handle = new_handles[0];
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Splits a Building transaction `ways` ways, distributing line
+/// groups (a line and its children) so the totals are as even as possible. The source keeps
+/// the first share and the `ways - 1` new handles are written to `out_handles`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `out_handles` points to memory for at least `handles_capacity` handles
+#[no_mangle]
+pub unsafe extern "C" fn pk_split_transaction_even(
+    handle: PkTransactionHandle,
+    ways: u32,
+    out_handles: *mut PkTransactionHandle,
+    handles_capacity: usize
+) -> PkResult {
+    metrics::instrument!("pk_split_transaction_even");
+    if handle == PK_INVALID_HANDLE || ways < 2 || out_handles.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    if handles_capacity < (ways - 1) as usize {
+        return PkResult::err(ResultCode::InsufficientBuffer);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.split_transaction_even(handle, ways as usize) {
+        Ok(new_handles) => {
+            let out = std::slice::from_raw_parts_mut(out_handles, new_handles.len());
+            out.copy_from_slice(&new_handles);
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+pub const PK_CLONE_ORIGINAL_PRICES: u32 = 0;
+pub const PK_CLONE_CURRENT_PRICES: u32 = 1;
+
+/// ARCHITECTURAL COMPONENT: Starts a new Building transaction repeating the sale lines of an
+/// existing transaction (open or completed). With `PK_CLONE_CURRENT_PRICES`, lines are re-priced
+/// through the registered price provider; SKUs it does not price keep their original price.
+/// Voided lines, manual adjustments and deposit returns are not repeated.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `source_handle` refers to a valid transaction
+/// - `out_handle` points to valid memory where the new transaction handle can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_clone_transaction(
+    source_handle: PkTransactionHandle,
+    price_mode: u32,
+    out_handle: *mut PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_clone_transaction");
+    if source_handle == PK_INVALID_HANDLE || out_handle.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let current_prices = match price_mode {
+        PK_CLONE_ORIGINAL_PRICES => false,
+        PK_CLONE_CURRENT_PRICES => true,
+        _ => return PkResult::err(ResultCode::ValidationFailed),
+    };
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.clone_transaction(source_handle, current_prices) {
+        Ok(new_handle) => {
+            *out_handle = new_handle;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Sets an external order reference field (`OrderReferenceField`:
+/// 1 = external order id, 2 = sales channel, 3 = pickup/table reference). A zero-length
+/// value clears the field. Values are limited to `max_order_reference_len` bytes.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `value_ptr` points to a valid UTF-8 encoded string of `value_len` bytes when `value_len` > 0
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_order_reference(
+    handle: PkTransactionHandle,
+    field_code: i32,
+    value_ptr: *const u8,
+    value_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_order_reference");
+    let field = match OrderReferenceField::from_code(field_code) {
+        Some(f) => f,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    if handle == PK_INVALID_HANDLE || (value_len > 0 && value_ptr.is_null()) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let value = if value_len == 0 { None } else { Some(read_str(value_ptr, value_len)) };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.set_order_reference(handle, field, value) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves an external order reference field as UTF-8 (no null
+/// terminator). Returns NotFound when the field is not set.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_order_reference(
+    handle: PkTransactionHandle,
+    field_code: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_order_reference");
+    let field = match OrderReferenceField::from_code(field_code) {
+        Some(f) => f,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.get_order_reference(handle, field) {
+        Ok(Some(value)) => write_str(&value, buffer, buffer_size, out_required_size),
+        Ok(None) => PkResult::err(ResultCode::NotFound),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Re-delivers the receipt of a committed transaction through every
+/// registered receipt deliverer. Each attempt is reported as a `ReceiptDelivery` event.
+/// Returns InternalError when any deliverer failed.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_redeliver_receipt(
+    handle: PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_redeliver_receipt");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let committed = kernel_store.transaction(handle)
+        .map(|tx| tx.state == TxState::Committed);
+    match committed {
+        Err(e) => return PkResult::from_error(&e),
+        Ok(false) => return PkResult::err(ResultCode::InvalidState),
+        Ok(true) => {}
+    }
+    
+    match kernel_store.deliver_receipt(handle, DeliveryReason::Redelivery) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Starts a fuel prepay on a pump: adds a line for the grade `sku`
+/// at the preset amount, to be tendered like any other line. The pump is released with
+/// `pk_fuel_authorize` and settled with `pk_fuel_complete`; until then the transaction cannot
+/// be finalized.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `sku_ptr` points to `sku_len` bytes of UTF-8
+/// - `out_line_id` points to valid memory where the preset line ID can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_fuel_preset(
+    handle: PkTransactionHandle,
+    pump_id: u32,
+    sku_ptr: *const u8,
+    sku_len: usize,
+    preset_minor: i64,
+    out_line_id: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_fuel_preset");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || out_line_id.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.fuel_preset(handle, pump_id, sku, preset_minor) {
+        Ok(line_id) => {
+            *out_line_id = line_id;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Authorizes a preset pump once the transaction is tendered in full,
+/// publishing a `FuelAuthorized` event for the forecourt controller. Returns `InvalidState`
+/// while the prepay is not fully tendered.
+#[no_mangle]
+pub extern "C" fn pk_fuel_authorize(handle: PkTransactionHandle, pump_id: u32) -> PkResult {
+    metrics::instrument!("pk_fuel_authorize");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.fuel_authorize(handle, pump_id) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Settles an authorized pump from the dispenser reading: the volume
+/// in thousandths of the dispenser's unit and the amount dispensed, which may not exceed the
+/// preset. The preset line is replaced by a line at the dispensed amount, and unused prepay is
+/// refunded against the tenders that paid it (newest first; points are not refunded). The
+/// amount refunded is written to `out_refunded_minor`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `out_refunded_minor` points to valid memory where the refund can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_fuel_complete(
+    handle: PkTransactionHandle,
+    pump_id: u32,
+    volume_milli: i64,
+    amount_minor: i64,
+    out_refunded_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_fuel_complete");
+    if handle == PK_INVALID_HANDLE || out_refunded_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.fuel_complete(handle, pump_id, volume_milli, amount_minor) {
+        Ok(refunded_minor) => {
+            *out_refunded_minor = refunded_minor;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Settles a line held by the line security module: `resolution` 0
+/// confirms it (the module saw the condition clear), 1 overrides it, which requires an
+/// operator holding the SecurityOverride permission, and 2 rejects it, voiding the line and
+/// its children. The transaction cannot be tendered or finalized while any line is held.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` is null or points to `operator_len` bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_resolve_line_hold(
+    handle: PkTransactionHandle,
+    line_id: u32,
+    resolution: i32,
+    operator_ptr: *const u8,
+    operator_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_resolve_line_hold");
+    let resolution = match security::HoldResolution::from_code(resolution) {
+        Some(r) => r,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let operator_id = (!operator_ptr.is_null()).then(|| read_str(operator_ptr, operator_len));
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.resolve_line_hold(handle, line_id, resolution, operator_id) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds one item from raw scanner output: the code is parsed (AIM
+/// symbology identifier, EAN/UPC check digit, GS1 application identifiers), resolved to a SKU,
+/// description and price through the registered product catalog (or, without one, the price
+/// provider), and added as a line of quantity one. A price encoded in the barcode (AI 392n)
+/// overrides the provider's, and a net weight (AI 310n) is recorded as the line's weight
+/// reading. Returns `NotFound` when the provider does not know the code, `ValidationFailed`
+/// for a discontinued product and `InvalidState` when no provider is registered.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `scan_ptr` points to `scan_len` bytes of scanner output
+/// - `out_line_id` points to valid memory where the line ID can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_scan_item(
+    handle: PkTransactionHandle,
+    scan_ptr: *const u8,
+    scan_len: usize,
+    out_line_id: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_scan_item");
+    if handle == PK_INVALID_HANDLE || scan_ptr.is_null() || scan_len == 0 || out_line_id.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let raw = read_str(scan_ptr, scan_len);
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.scan_item(handle, &raw) {
+        Ok(line_id) => {
+            *out_line_id = line_id;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Looks up a product without adding it to a transaction, as JSON:
+/// SKU, name, description, shelf price in minor units of `currency` and flags (a bit set of
+/// `catalog::ProductFlags`). The code is parsed like `pk_scan_item` input, so a barcode resolves
+/// by GTIN and anything else as a SKU. The registered product catalog is used, or the price
+/// provider (with no flags) when there is none. Returns `NotFound` for an unknown code and
+/// `InvalidState` when neither is registered.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `code_ptr` points to `code_len` bytes of UTF-8 encoded scanner output or SKU
+/// - `currency_ptr` points to `currency_len` bytes of UTF-8 encoded currency code
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_lookup_product_json(
+    code_ptr: *const u8,
+    code_len: usize,
+    currency_ptr: *const u8,
+    currency_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_lookup_product_json");
+    if code_ptr.is_null() || code_len == 0 || currency_ptr.is_null() || currency_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let code = read_str(code_ptr, code_len);
+    let currency_code = read_str(currency_ptr, currency_len);
+    let json = match read_store() {
+        Ok(s) => s.product_json(&code, &currency_code),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match json {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Registers the reference CSV product catalog built from `csv_ptr`,
+/// replacing any registered catalog. The CSV layout is described in `catalog::csv`. Nothing is
+/// registered when any row is invalid; the row and reason are in the last error. The number
+/// of rows imported is written to `out_count`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `csv_ptr` points to `csv_len` bytes of UTF-8 encoded CSV
+/// - `out_count` points to valid memory where the count can be written
+#[no_mangle]
+pub unsafe extern "C" fn pk_load_product_catalog_csv(
+    csv_ptr: *const u8,
+    csv_len: usize,
+    out_count: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_load_product_catalog_csv");
+    if csv_ptr.is_null() || csv_len == 0 || out_count.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let csv = read_str(csv_ptr, csv_len);
+    let catalog = catalog::CsvCatalog::new();
+    let loaded = catalog.import(&csv)
+        .map_err(KernelError::Validation)
+        .and_then(|count| catalog::set_product_provider(Box::new(catalog)).map(|_| count));
+    
+    match loaded {
+        Ok(count) => {
+            *out_count = count;
+            PkResult::ok()
+        },
+        Err(e) => {
+            let result = PkResult::from_error(&e);
+            last_error::set(e.to_string());
+            result
+        }
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds the items of a basket read by an RFID reader in one step.
+/// `tags_ptr` holds the tags read, one per line, as SGTIN-96 hex EPCs or pure-identity URIs.
+/// Tags are deduplicated, within the batch and against tags already read into the transaction,
+/// and resolved by GTIN like `pk_scan_item` scans (discontinued products are unresolved); each
+/// product becomes one line with the number of its tags as the quantity. Either every line is added or none is. The
+/// result is written as JSON: the line IDs added, the number of duplicate reads, tags that are
+/// not SGTINs (ignored) and tags whose product the provider does not know (unresolved). The
+/// lines stand even if `buffer` is too small; reading the same basket again adds nothing and
+/// reports its tags as duplicates.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `tags_ptr` points to `tags_len` bytes of UTF-8 encoded tags
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_add_rfid_items(
+    handle: PkTransactionHandle,
+    tags_ptr: *const u8,
+    tags_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_add_rfid_items");
+    if handle == PK_INVALID_HANDLE || tags_ptr.is_null() || tags_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let tags: Vec<String> = read_str(tags_ptr, tags_len).lines()
+        .filter(|tag| !tag.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let json = kernel_store.add_rfid_batch(handle, &tags).and_then(|batch| serde_json::to_string(&batch)
+        .map_err(|e| KernelError::Internal(format!("Failed to serialize RFID batch: {}", e))));
+    match json {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Reprints the receipt of a committed transaction, by its receipt
+/// number (the transaction ID printed on it), on the printers serving a terminal: the given
+/// one, or the transaction's own when `terminal_ptr` is null. The reprint is marked as a copy
+/// and journaled. Printing follows in the background; see `pk_get_printer_status_json`.
+/// Returns `InvalidState` when no printer serves the terminal.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` is null or points to `terminal_len` bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_reprint_receipt(
+    receipt_number: PkTransactionHandle,
+    terminal_ptr: *const u8,
+    terminal_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_reprint_receipt");
+    if receipt_number == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let terminal_id = (!terminal_ptr.is_null()).then(|| read_str(terminal_ptr, terminal_len));
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.reprint_receipt(receipt_number, terminal_id) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retries the print jobs held by stalled printers, for a host to call
+/// once a paper jam is cleared or a printer is back online. Jobs are also retried whenever a
+/// new receipt is queued on their printer.
+#[no_mangle]
+pub extern "C" fn pk_retry_print_jobs() -> PkResult {
+    metrics::instrument!("pk_retry_print_jobs");
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let spooler = Arc::clone(&kernel_store.spooler);
+    kernel_store.submit_job(pipeline::Job::Print(spooler));
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Receipt printer status as JSON: per printer, its terminal, whether
+/// it is ready, printing or stalled on a failed job, the last error it reported, the jobs still
+/// queued and the most recent jobs printed or failed (`print_job_history` per printer), each
+/// with its receipt number, attempts and last error.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_printer_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_printer_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = kernel_store.spooler.status();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retries the orders held in stalled ERP connector outboxes, for a
+/// host to call once the ERP is reachable again. Outboxes are also retried whenever a sale
+/// commits.
+#[no_mangle]
+pub extern "C" fn pk_retry_erp_pushes() -> PkResult {
+    metrics::instrument!("pk_retry_erp_pushes");
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let outbox = Arc::clone(&kernel_store.erp_outbox);
+    kernel_store.submit_job(pipeline::Job::PushErp(outbox));
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Moves a dead-lettered ERP order back to its connector's outbox and
+/// pushes it again, with a fresh retry budget. Orders whose payload template failed to render
+/// cannot be requeued; fix the template and re-register the connector instead.
+#[no_mangle]
+pub extern "C" fn pk_requeue_erp_dead_letter(message_id: u64) -> PkResult {
+    metrics::instrument!("pk_requeue_erp_dead_letter");
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    if let Err(e) = kernel_store.erp_outbox.requeue(message_id) {
+        return PkResult::from_error(&e);
+    }
+    let outbox = Arc::clone(&kernel_store.erp_outbox);
+    kernel_store.submit_job(pipeline::Job::PushErp(outbox));
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: ERP connector status as JSON: per connector, the orders delivered,
+/// the last error reported, the orders still queued and the dead letters kept
+/// (`erp_dead_letter_limit` per connector), each with its message ID, receipt number,
+/// attempts and last error.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_erp_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_erp_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = kernel_store.erp_outbox.status();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Forwards committed transactions to the central service now, for a
+/// host to call when connectivity returns. Commits also trigger forwarding; while the upstream
+/// is unreachable the terminal keeps selling and the backlog waits in the journal.
+#[no_mangle]
+pub extern "C" fn pk_sync_now() -> PkResult {
+    metrics::instrument!("pk_sync_now");
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    if kernel_store.sync_forwarder.is_none() {
+        return PkResult::err(ResultCode::InvalidState);
+    }
+    if let Some(pending) = kernel_store.sync_batch(true) {
+        kernel_store.submit_job(pipeline::Job::Sync(Box::new(pending)));
+    }
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Store-and-forward status as JSON: the terminal's node ID, whether
+/// the last attempt reached the central service, the journal sequence acknowledged, the
+/// commits still to be forwarded, the last error and the most recent conflicts reported.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_sync_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_sync_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = match &kernel_store.sync_forwarder {
+        Some(forwarder) => forwarder.status(&kernel_store.journal),
+        None => return PkResult::err(ResultCode::InvalidState),
+    };
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: The central service's side of store-and-forward sync. Checks a
+/// batch forwarded by a terminal (`batch_ptr`, JSON as sent to its `SyncUpstream`) for sequence
+/// gaps and duplicate receipt numbers, and writes the response to return to the terminal: the
+/// sequence acknowledged, the receipt numbers accepted and the conflicts found. Storing the
+/// accepted transactions is left to the caller.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `batch_ptr` points to a valid UTF-8 encoded string of `batch_len` bytes
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_ingest_sync_batch(
+    batch_ptr: *const u8,
+    batch_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_ingest_sync_batch");
+    if batch_ptr.is_null() || batch_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let batch: sync::SyncBatch = match serde_json::from_str(&read_str(batch_ptr, batch_len)) {
+        Ok(batch) => batch,
+        Err(_) => return PkResult::err(ResultCode::ValidationFailed),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let response = match kernel_store.ingest_sync_batch(&batch) {
+        Ok(response) => response,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    drop(kernel_store);
+    
+    match serde_json::to_string(&response) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Sets the shared secret a replication primary and its replicas
+/// authenticate each other with (at least 16 bytes). Required before either role is started;
+/// a new secret applies to later connections. The secret is held in memory only.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `secret_ptr` points to `secret_len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_replication_secret(
+    secret_ptr: *const u8,
+    secret_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_replication_secret");
+    if secret_ptr.is_null() || secret_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let secret = std::slice::from_raw_parts(secret_ptr, secret_len).to_vec();
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.replication.set_secret(secret) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Makes this kernel a replication primary, streaming its journal to
+/// standbys that connect to `address_ptr` (a TCP listen address such as "10.0.0.5:7400", or a
+/// bare port such as "7400" to listen on the loopback interface only). Requires a replication
+/// secret (`pk_set_replication_secret`). A promoted replica can become a primary in turn.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `address_ptr` points to a valid UTF-8 encoded string of `address_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_start_replication_primary(
+    address_ptr: *const u8,
+    address_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_start_replication_primary");
+    if address_ptr.is_null() || address_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let address = read_str(address_ptr, address_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.replication.start_primary(&address) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Makes this kernel a warm standby of the primary at `address_ptr`
+/// ("host:port"), which must have the same replication secret. The local journal must be empty
+/// or a copy of the primary's; replication resumes after its last entry and reconnects
+/// whenever the primary is lost. A replica is read-only (InvalidState) until promoted with
+/// `pk_promote_replica`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `address_ptr` points to a valid UTF-8 encoded string of `address_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_start_replica(
+    address_ptr: *const u8,
+    address_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_start_replica");
+    if address_ptr.is_null() || address_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let address = read_str(address_ptr, address_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.start_replica(&address) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Promotes a replica once its primary has failed: replication stops,
+/// the journal becomes writable and transaction handles continue past the last one
+/// replicated. Fence the old primary first; see the failover procedure in the C ABI guide.
+#[no_mangle]
+pub extern "C" fn pk_promote_replica() -> PkResult {
+    metrics::instrument!("pk_promote_replica");
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.promote_replica() {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Replication status as JSON: the role (Standalone, Primary, Replica
+/// or Promoted), the address listened on or replicated from, for a primary the replicas
+/// connected and how far each has been sent, for a replica whether it is connected and the
+/// last sequence number received, the last error and when the kernel was promoted.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_replication_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_replication_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = kernel_store.replication.status();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Takes a backup into a new directory under `directory_ptr` and
+/// writes its manifest as JSON: the format version, when it was taken, how far the journal
+/// reaches, the handle sequences, the archive's record count and head hash, and the size and
+/// SHA-256 digest of each file. Selling pauses only while the state is captured, not while
+/// it is written.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `directory_ptr` points to a valid UTF-8 encoded string of `directory_len` bytes
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_create_backup(
+    directory_ptr: *const u8,
+    directory_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_create_backup");
+    if directory_ptr.is_null() || directory_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let directory = read_str(directory_ptr, directory_len);
+    
+    let result = backup::create_backup(std::path::Path::new(&directory));
+    if let Ok(kernel_store) = read_store() {
+        kernel_store.backup_scheduler.record(&result);
+    }
+    
+    let manifest = match result {
+        Ok((_, manifest)) => manifest,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    match serde_json::to_string(&manifest) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Checks the backup in `path_ptr` without restoring it: the
+/// manifest version, each file's digest, the journal's checksums and sequence, and the
+/// archive hash chain, recomputed record by record. Fails with `ValidationFailed` if any
+/// check fails.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `path_ptr` points to a valid UTF-8 encoded string of `path_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_verify_backup(
+    path_ptr: *const u8,
+    path_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_verify_backup");
+    if path_ptr.is_null() || path_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let path = read_str(path_ptr, path_len);
+    
+    match backup::verify(std::path::Path::new(&path)) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Restores the backup in `path_ptr` into this kernel, which must be
+/// empty: nothing journaled, archived or open. The backup is verified in full before anything
+/// changes. The journal, archive and open transactions are reinstated and handles continue
+/// where the backup left off; the restore itself is journaled.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `path_ptr` points to a valid UTF-8 encoded string of `path_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_restore_backup(
+    path_ptr: *const u8,
+    path_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_restore_backup");
+    if path_ptr.is_null() || path_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let path = std::path::PathBuf::from(read_str(path_ptr, path_len));
+    
+    let restored = match backup::verify(&path) {
+        Ok(restored) => restored,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    let backup_id = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.restore_backup(backup_id, restored) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Takes a backup under `directory_ptr` every `interval_secs`,
+/// keeping the `keep` most recent (0 keeps them all). Replaces any earlier schedule; an
+/// interval of 0 stops scheduled backups. Failures are reported in the backup status.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `directory_ptr` points to a valid UTF-8 encoded string of `directory_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_schedule_backups(
+    directory_ptr: *const u8,
+    directory_len: usize,
+    interval_secs: u64,
+    keep: u32
+) -> PkResult {
+    metrics::instrument!("pk_schedule_backups");
+    let schedule = match interval_secs {
+        0 => None,
+        _ if directory_ptr.is_null() || directory_len == 0 => return PkResult::err(ResultCode::ValidationFailed),
+        _ => Some(backup::BackupSchedule {
+            directory: read_str(directory_ptr, directory_len),
+            interval_secs,
+            keep: keep as usize,
+        }),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.backup_scheduler.schedule(schedule) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Backup status as JSON: the schedule, if any, the last backup
+/// taken and when, and the error from the last attempt if it failed.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_backup_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_backup_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = kernel_store.backup_scheduler.status();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Reads the journal after a resume token, as JSON: `entries`, each
+/// with the `resume_token` that resumes after it, the `resume_token` after the last entry
+/// returned, and the journal's `last_sequence`. An empty token reads from the start. Returns
+/// at most `max_entries` (0 for the default of 1000); when nothing is past the token, waits up
+/// to `wait_ms` (at most 30 seconds) for the next entry, so calling it in a loop streams the
+/// journal. For exactly-once processing, store the token of each entry processed in the same
+/// commit as its effects and resume from it. A token that does not match this journal returns
+/// `InvalidState`; a malformed one `ValidationFailed`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `token_ptr` points to a valid UTF-8 encoded string of `token_len` bytes, or `token_len` is 0
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_read_journal_json(
+    token_ptr: *const u8,
+    token_len: usize,
+    max_entries: u32,
+    wait_ms: u32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_read_journal_json");
+    if (token_ptr.is_null() && token_len > 0) || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let token = match token_len {
+        0 => String::new(),
+        _ => read_str(token_ptr, token_len),
+    };
+    let token = match subscription::ResumeToken::parse(&token) {
+        Ok(t) => t,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    let page = match subscription::read(token, max_entries as usize, std::time::Duration::from_millis(wait_ms.into())) {
+        Ok(page) => page,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    match serde_json::to_string(&page) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Loads the tax, pricing, loyalty and receipt plugins listed in the
+/// JSON manifest at `path_ptr` and registers them in place of any provider set before. Call
+/// once at startup; every plugin is loaded and initialized before any is registered, so on
+/// failure (`ValidationFailed`, with the plugin and reason in the last error) nothing changes.
+/// The plugin ABI is described in the C ABI guide.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `path_ptr` points to a valid UTF-8 encoded string of `path_len` bytes
+/// - The libraries the manifest lists implement the plugin ABI
+#[no_mangle]
+pub unsafe extern "C" fn pk_load_plugins(
+    path_ptr: *const u8,
+    path_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_load_plugins");
+    if path_ptr.is_null() || path_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let path = read_str(path_ptr, path_len);
+    
+    match plugin::load_plugins(std::path::Path::new(&path)) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: The loaded plugins as a JSON array of name, kind and library path.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_plugins_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_plugins_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let plugins = kernel_store.plugins.clone();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&plugins) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+fn loyalty_plugin() -> Result<Arc<dyn loyalty::LoyaltyProvider>, PkResult> {
+    let kernel_store = read_store().map_err(PkResult::err)?;
+    kernel_store.loyalty_provider.clone()
+        .ok_or_else(|| PkResult::from_error(&KernelError::invalid_state(plugin::NO_LOYALTY_PLUGIN)))
+}
+
+/// ARCHITECTURAL COMPONENT: Quotes accrual for the transaction's customer with the loaded
+/// loyalty plugin and records it on the transaction. Returns `InvalidState` when no loyalty
+/// plugin is loaded.
+#[no_mangle]
+pub extern "C" fn pk_accrue_loyalty(handle: PkTransactionHandle) -> PkResult {
+    metrics::instrument!("pk_accrue_loyalty");
+    let provider = match loyalty_plugin() {
+        Ok(p) => p,
+        Err(result) => return result
+    };
+    
+    match loyalty::accrue(&*provider, handle) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Redeems `points` for the transaction's customer with the loaded
+/// loyalty plugin and records the redemption on the transaction. Returns `InvalidState` when
+/// no loyalty plugin is loaded.
+#[no_mangle]
+pub extern "C" fn pk_redeem_loyalty(handle: PkTransactionHandle, points: i64) -> PkResult {
+    metrics::instrument!("pk_redeem_loyalty");
+    let provider = match loyalty_plugin() {
+        Ok(p) => p,
+        Err(result) => return result
+    };
+    
+    match loyalty::redeem(&*provider, handle, points) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Installs the store's rules script, replacing any earlier one; an
+/// empty script removes it. The script's hooks run after lines are added by `pk_add_line`,
+/// `pk_add_line_with_metadata` and `pk_add_priced_line`, and before `pk_finalize_transaction`
+/// commits, where a `reject` fails the finalize with `InvalidState`. A script that does
+/// not parse returns `ValidationFailed` with the line and reason in the last error. The
+/// script language is described in the C ABI guide.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `source_ptr` points to a valid UTF-8 encoded string of `source_len` bytes, or `source_len` is 0
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_rules_script(
+    source_ptr: *const u8,
+    source_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_rules_script");
+    if source_ptr.is_null() && source_len > 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let source = match source_len {
+        0 => String::new(),
+        _ => read_str(source_ptr, source_len),
+    };
+    
+    match rules::set_rules_script(&source) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Runs one command message and writes its reply as JSON. The
+/// message is `{"id": ..., "command": ...}` with `begin` (`store`, `currency`, optional
+/// `decimal_places`), `add_line` (`handle`, `sku`, `qty`, `unit_minor`), `tender` (`handle`,
+/// `amount_minor`, in cash) or `finalize` (`handle`). The reply carries the `id`, `ok`, the
+/// result `code`, the `handle` from a `begin` and the `error` when it failed. A message whose
+/// ID was seen within `command_idempotency_window` is answered with the earlier reply, marked
+/// `duplicate`, and not run again. This is the in-process form of the NATS consumer; the call
+/// itself succeeds whenever the reply is written, whatever the command's outcome.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `message_ptr` points to `message_len` bytes of UTF-8 JSON
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_execute_command_json(
+    message_ptr: *const u8,
+    message_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_execute_command_json");
+    if message_ptr.is_null() || message_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let message = std::slice::from_raw_parts(message_ptr, message_len);
+    
+    // The command takes the store lock itself
+    let processor = match read_store() {
+        Ok(s) => Arc::clone(&s.commands),
+        Err(code) => return PkResult::err(code)
+    };
+    let reply = processor.process(message);
+    
+    match serde_json::to_string(&reply) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Sets the credentials the NATS consumer sends when it connects:
+/// a user and password, or a token (`token_ptr`) with the user and password null. All three
+/// null connects without authentication. They are held in memory only, are never exported or
+/// shown in `pk_get_nats_status_json`, and are used from the next connection.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - each of `user_ptr`, `password_ptr` and `token_ptr` is null or points to a valid UTF-8
+///   encoded string of the given length
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_nats_credentials(
+    user_ptr: *const u8,
+    user_len: usize,
+    password_ptr: *const u8,
+    password_len: usize,
+    token_ptr: *const u8,
+    token_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_nats_credentials");
+    let optional = |ptr: *const u8, len: usize| (!ptr.is_null() && len > 0).then(|| read_str(ptr, len));
+    let credentials = match (optional(user_ptr, user_len), optional(password_ptr, password_len), optional(token_ptr, token_len)) {
+        (Some(user), Some(password), None) => Some(nats::NatsCredentials::UserPassword { user, password }),
+        (None, None, Some(token)) => Some(nats::NatsCredentials::Token(token)),
+        (None, None, None) => None,
+        _ => return PkResult::err(ResultCode::ValidationFailed),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.nats.set_credentials(credentials);
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Consumes command messages from the NATS server at `address_ptr`
+/// ("host:port"), subscribed to `subject_ptr` and, when `queue_group_ptr` is given, in that
+/// queue group so several kernels share the subject. Each message runs as with
+/// `pk_execute_command_json` and its reply is published to the message's reply subject. The
+/// consumer reconnects whenever the server is lost; see `pk_get_nats_status_json`. It
+/// authenticates with `pk_set_nats_credentials`. Messages over 64 KB are skipped and answered
+/// with a validation error. Returns `InvalidState` when a consumer is already started.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `address_ptr` and `subject_ptr` point to valid UTF-8 encoded strings of the given lengths
+/// - `queue_group_ptr` is null or points to `queue_group_len` bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_start_nats_consumer(
+    address_ptr: *const u8,
+    address_len: usize,
+    subject_ptr: *const u8,
+    subject_len: usize,
+    queue_group_ptr: *const u8,
+    queue_group_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_start_nats_consumer");
+    if address_ptr.is_null() || address_len == 0 || subject_ptr.is_null() || subject_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let address = read_str(address_ptr, address_len);
+    let subject = read_str(subject_ptr, subject_len);
+    let queue_group = match queue_group_ptr.is_null() || queue_group_len == 0 {
+        true => None,
+        false => Some(read_str(queue_group_ptr, queue_group_len)),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.nats.start(&address, &subject, queue_group.as_deref()) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Stops the NATS command consumer. A message being run is finished
+/// and answered first.
+#[no_mangle]
+pub extern "C" fn pk_stop_nats_consumer() -> PkResult {
+    metrics::instrument!("pk_stop_nats_consumer");
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.nats.stop();
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: NATS consumer status as JSON: the server `address`, `subject` and
+/// `queue_group`, whether it is `connected`, the messages `processed` and how many of those
+/// were `duplicates`, and the last connection error and when it happened.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_nats_status_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_nats_status_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let status = kernel_store.nats.status();
+    drop(kernel_store);
+    
+    match serde_json::to_string(&status) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records a driver's report on a device at a terminal. `kind` is 0
+/// printer, 1 drawer, 2 scanner, 3 scale, 4 display, 5 recycler, 6 payment terminal or 7
+/// other; `health` is 0 ok, 1 degraded (working, needs attention) or 2 offline. `detail_ptr`
+/// optionally describes the condition, such as "Paper low". A change of health is published
+/// as a `PeripheralStatusChanged` event.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` and `device_ptr` point to valid UTF-8 encoded strings of the given lengths
+/// - `detail_ptr` is null or points to `detail_len` bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_report_peripheral_status(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    device_ptr: *const u8,
+    device_len: usize,
+    kind: i32,
+    health: i32,
+    detail_ptr: *const u8,
+    detail_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_report_peripheral_status");
+    if terminal_ptr.is_null() || terminal_len == 0 || device_ptr.is_null() || device_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    let (kind, health) = match (peripheral::DeviceKind::from_code(kind), peripheral::DeviceHealth::from_code(health)) {
+        (Some(kind), Some(health)) => (kind, health),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    let device = read_str(device_ptr, device_len);
+    let detail = (!detail_ptr.is_null() && detail_len > 0).then(|| read_str(detail_ptr, detail_len));
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.report_peripheral_status(&terminal_id, &device, kind, health, detail.as_deref()) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: A terminal's peripheral health as JSON: its overall health (the
+/// worst of its devices) and each device with its kind, health, detail and report time.
+/// Reports older than `peripheral_status_ttl_secs` show as offline, and receipt printers
+/// stalled on failing jobs as degraded. A terminal nothing was reported for is Ok with no
+/// devices.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_terminal_peripherals_json(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_terminal_peripherals_json");
+    if terminal_ptr.is_null() || terminal_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    let health = match read_store() {
+        Ok(s) => s.terminal_health(&terminal_id),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match serde_json::to_string(&health) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Readiness summary for health checks, as JSON: the overall
+/// peripheral health and every terminal that is degraded or has a device offline, worst first.
+/// Degraded peripherals do not stop the kernel taking sales; the kernel itself is ready
+/// whenever this call succeeds.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_readiness_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_readiness_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let readiness = match read_store() {
+        Ok(s) => s.readiness(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match serde_json::to_string(&readiness) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records a scale reading for Weights & Measures audits: the gross
+/// weight and the tare in thousandths of `uom` ("kg", "g", "lb" or "oz"), with the net weight
+/// the kernel derives. A `line_id` of zero records the reading against the transaction alone;
+/// otherwise it is kept on that line, which must be a live sale line without a reading, and
+/// appears in the transaction export and on the receipt. Every reading is journaled.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `uom_ptr` points to `uom_len` bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_capture_weight(
+    handle: PkTransactionHandle,
+    line_id: u32,
+    gross_milli: i64,
+    tare_milli: i64,
+    uom_ptr: *const u8,
+    uom_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_capture_weight");
+    if handle == PK_INVALID_HANDLE || uom_ptr.is_null() || uom_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let unit = match peripheral::WeightUnit::parse(&read_str(uom_ptr, uom_len)) {
+        Some(unit) => unit,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    let reading = match peripheral::WeightReading::new(gross_milli, tare_milli, unit) {
+        Ok(reading) => reading,
+        Err(e) => {
+            last_error::set(e);
+            return PkResult::err(ResultCode::ValidationFailed);
+        }
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.capture_weight(handle, (line_id != 0).then_some(line_id), reading) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Fires the transaction's unfired lines to the kitchen. Each item
+/// goes out complete, the top-level line with its live modifiers, tagged with the routing
+/// categories the registered kitchen router gives its SKUs; modifiers added to an item already
+/// fired send the item again as a modification. Tickets are published as `KitchenTicket`
+/// events and sent to the registered kitchen sinks. Writes the number of lines fired, which is
+/// zero when everything was already fired.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid, active transaction
+/// - `out_fired` points to valid memory for the count
+#[no_mangle]
+pub unsafe extern "C" fn pk_fire_lines(
+    handle: PkTransactionHandle,
+    out_fired: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_fire_lines");
+    if handle == PK_INVALID_HANDLE || out_fired.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.fire_lines(handle) {
+        Ok(fired) => {
+            *out_fired = fired as u32;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: No-sale: opens the cash drawer outside any transaction through
+/// the registered drawer driver. Terminal and operator IDs are optional (null or empty) and
+/// are journaled with the opening and the driver's outcome, which is also published as a
+/// `DrawerOpen` event. Returns `InvalidState` when no driver is registered and
+/// `InternalError` when the driver failed to open the drawer.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` and `operator_ptr` are null or point to `terminal_len` and `operator_len`
+///   bytes of UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn pk_open_drawer_no_sale(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    operator_ptr: *const u8,
+    operator_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_open_drawer_no_sale");
+    let terminal_id = (!terminal_ptr.is_null() && terminal_len > 0).then(|| read_str(terminal_ptr, terminal_len));
+    let operator_id = (!operator_ptr.is_null() && operator_len > 0).then(|| read_str(operator_ptr, operator_len));
+    
+    let open = match read_store() {
+        Ok(s) => s.no_sale(terminal_id, operator_id),
+        Err(code) => return PkResult::err(code)
+    };
+    let open = match open {
+        Ok(open) => open,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    // The driver may wait on hardware, so it runs without the store lock
+    let outcome = open.open();
+    let recorded = match read_store() {
+        Ok(s) => s.record_drawer_open(&open, outcome),
+        Err(code) => return PkResult::err(code)
+    };
+    match recorded {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Sets the HMAC key used to sign kernel-issued records. The key is
+/// held in memory only and is never exported.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `key_ptr` points to `key_len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_signing_key(
+    key_ptr: *const u8,
+    key_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_signing_key");
+    if key_ptr.is_null() || key_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let key = std::slice::from_raw_parts(key_ptr, key_len).to_vec();
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    pii::set_hash_key(Some(key.clone()));
+    kernel_store.signing_key = Some(key);
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Anonymizes a customer (GDPR erasure). The token is replaced with a
+/// random pseudonym on all transactions, in the journal and in queued events; financial data is
+/// unchanged. The signed anonymization record is written as JSON (no null terminator).
+/// Requires a signing key (`pk_set_signing_key`). The buffer must hold at least
+/// `AnonymizationRecord::MAX_JSON_LEN` (512) bytes; this is checked before anything is changed.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `token_ptr` points to a valid UTF-8 encoded string of `token_len` bytes
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_anonymize_customer(
+    token_ptr: *const u8,
+    token_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_anonymize_customer");
+    if token_ptr.is_null() || token_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    if buffer.is_null() || buffer_size < AnonymizationRecord::MAX_JSON_LEN {
+        *out_required_size = AnonymizationRecord::MAX_JSON_LEN;
+        return PkResult::err(ResultCode::InsufficientBuffer);
+    }
+    
+    let token = read_str(token_ptr, token_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let record = match kernel_store.anonymize_customer(&token) {
+        Ok(record) => record,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    
+    match serde_json::to_string(&record) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records one entry of the user-space tax engine's breakdown
+/// (jurisdiction, rate code, rate in basis points, taxable/tax/exempt amounts). Exclusive
+/// taxes (`inclusive` false) are added to the transaction total.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `jurisdiction_ptr` and `rate_code_ptr` point to valid UTF-8 encoded strings of the given lengths
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pk_add_tax(
+    handle: PkTransactionHandle,
+    jurisdiction_ptr: *const u8,
+    jurisdiction_len: usize,
+    rate_code_ptr: *const u8,
+    rate_code_len: usize,
+    rate_basis_points: i32,
+    taxable_minor: i64,
+    tax_minor: i64,
+    exempt_minor: i64,
+    inclusive: bool
+) -> PkResult {
+    metrics::instrument!("pk_add_tax");
+    if handle == PK_INVALID_HANDLE || jurisdiction_ptr.is_null() || jurisdiction_len == 0 || rate_code_ptr.is_null() || rate_code_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let entry = TaxEntry {
+        jurisdiction: read_str(jurisdiction_ptr, jurisdiction_len),
+        rate_code: read_str(rate_code_ptr, rate_code_len),
+        rate_basis_points,
+        taxable_minor,
+        tax_minor,
+        exempt_minor,
+        inclusive,
+        line_id: None,
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_tax_entry(handle, entry) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Clears the recorded tax breakdown (before recalculation).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+#[no_mangle]
+pub unsafe extern "C" fn pk_clear_taxes(
+    handle: PkTransactionHandle
+) -> PkResult {
+    metrics::instrument!("pk_clear_taxes");
+    if handle == PK_INVALID_HANDLE {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.clear_taxes(handle) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Replaces the tax breakdown with the registered tax provider's for
+/// the current basket. Results are cached per basket for `tax_cache_ttl_secs`. When the
+/// provider fails, the fallback rates registered with `tax::set_fallback_rates` are applied to
+/// each sale line and journaled as a fallback; with none, `TimedOut` is returned. Where the
+/// breakdown came from is written to `out_source`: 0 the provider, 1 the cache, 2 the fallback
+/// rates. `InvalidState` means no provider is registered or the basket changed during the
+/// calculation and it should be repeated.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid Building transaction
+/// - `out_source` points to valid memory for the source
+#[no_mangle]
+pub unsafe extern "C" fn pk_calculate_taxes(
+    handle: PkTransactionHandle,
+    out_source: *mut i32
+) -> PkResult {
+    metrics::instrument!("pk_calculate_taxes");
+    if handle == PK_INVALID_HANDLE || out_source.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.calculate_taxes(handle) {
+        Ok(source) => {
+            *out_source = source as i32;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+pub const PK_RECEIPT_HEADER: i32 = 1;
+pub const PK_RECEIPT_FOOTER: i32 = 2;
+
+/// ARCHITECTURAL COMPONENT: Sets the store's receipt header or footer text block
+/// (`PK_RECEIPT_HEADER` / `PK_RECEIPT_FOOTER`). Lines are separated by '\n' and printed verbatim;
+/// a zero-length block clears the section.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `text_ptr` points to a valid UTF-8 encoded string of `text_len` bytes when `text_len` > 0
+#[no_mangle]
+pub unsafe extern "C" fn pk_set_receipt_text(
+    section: i32,
+    text_ptr: *const u8,
+    text_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_receipt_text");
+    if text_len > 0 && text_ptr.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let lines: Vec<String> = if text_len == 0 {
+        Vec::new()
+    } else {
+        read_str(text_ptr, text_len).lines().map(str::to_string).collect()
+    };
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match section {
+        PK_RECEIPT_HEADER => kernel_store.receipt_layout.header_lines = lines,
+        PK_RECEIPT_FOOTER => kernel_store.receipt_layout.footer_lines = lines,
+        _ => return PkResult::err(ResultCode::ValidationFailed),
+    }
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Produces the structured receipt document of a committed
+/// transaction as JSON (no null terminator). The document is culture-neutral: amounts are in
+/// minor units with the currency's decimal places, and rendering is left to user space.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_receipt_json(
+    handle: PkTransactionHandle,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_receipt_json");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.receipt_json(handle) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+pub const PK_ESCPOS_CUT: u32 = 1;
+pub const PK_ESCPOS_OPEN_DRAWER: u32 = 2;
+pub const PK_ESCPOS_BARCODE: u32 = 4;
+
+/// ARCHITECTURAL COMPONENT: Renders the receipt of a committed transaction as an ESC/POS byte
+/// stream. `code_page` is an ESC/POS code page number (0 = PC437, 16 = WPC1252); `flags`
+/// combine `PK_ESCPOS_CUT`, `PK_ESCPOS_OPEN_DRAWER` and `PK_ESCPOS_BARCODE`. An optional
+/// QR payload is printed after the footer; without one, the fiscal QR payload is printed when
+/// a fiscal QR provider is registered. Labels use the kernel placeholders; localized
+/// layouts use the template engine or the Rust `escpos` API.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `qr_ptr` points to a valid UTF-8 encoded string of `qr_len` bytes when `qr_len` > 0
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pk_render_receipt_escpos(
+    handle: PkTransactionHandle,
+    code_page: i32,
+    columns: u32,
+    flags: u32,
+    qr_ptr: *const u8,
+    qr_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_render_receipt_escpos");
+    let code_page = match escpos::CodePage::from_code(code_page) {
+        Some(c) => c,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    if handle == PK_INVALID_HANDLE || columns < 16 || out_required_size.is_null() || (qr_len > 0 && qr_ptr.is_null()) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let options = escpos::EscPosOptions {
+        code_page,
+        columns: columns as usize,
+        print_barcode: flags & PK_ESCPOS_BARCODE != 0,
+        qr_payload: (qr_len > 0).then(|| read_str(qr_ptr, qr_len)),
+        cut: flags & PK_ESCPOS_CUT != 0,
+        open_drawer: flags & PK_ESCPOS_OPEN_DRAWER != 0,
+        ..escpos::EscPosOptions::default()
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.receipt_document(handle) {
+        Ok(document) => {
+            let options = escpos::EscPosOptions {
+                qr_payload: options.qr_payload.or_else(|| document.fiscal_qr.clone()),
+                ..options
+            };
+            match escpos::render(&document, &options) {
+                Ok(bytes) => write_bytes(&bytes, buffer, buffer_size, out_required_size),
+                Err(_) => PkResult::err(ResultCode::ValidationFailed)
+            }
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Loads (or reloads) a named receipt template from a file. Stores
+/// customize layout, header/footer blocks and per-line formats without recompiling; see the
+/// `template` module for the syntax. Parse errors leave any previously loaded template in place.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `name_ptr` and `path_ptr` point to valid UTF-8 encoded strings of the given lengths
+#[no_mangle]
+pub unsafe extern "C" fn pk_load_receipt_template(
+    name_ptr: *const u8,
+    name_len: usize,
+    path_ptr: *const u8,
+    path_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_load_receipt_template");
+    if name_ptr.is_null() || name_len == 0 || path_ptr.is_null() || path_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let name = read_str(name_ptr, name_len);
+    let path = read_str(path_ptr, path_len);
+    
+    let template = match template::Template::load(std::path::Path::new(&path)) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("WARNING: {}", e);
+            return PkResult::err(ResultCode::ValidationFailed);
+        }
+    };
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.receipt_templates.insert(name, template);
+    PkResult::ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Renders the receipt of a committed transaction through a loaded
+/// template, as UTF-8 text (no null terminator).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `name_ptr` points to a valid UTF-8 encoded template name of `name_len` bytes
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_render_receipt_template(
+    handle: PkTransactionHandle,
+    name_ptr: *const u8,
+    name_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_render_receipt_template");
+    if handle == PK_INVALID_HANDLE || name_ptr.is_null() || name_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let name = read_str(name_ptr, name_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.render_receipt_template(handle, &name) {
+        Ok(text) => write_str(&text, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Registers the reference fiscal QR provider for Saudi Arabia (ZATCA
+/// simplified tax invoice, phase 1) with the seller name and VAT registration number. Other
+/// jurisdictions register their provider through `fiscal::qr::set_qr_provider`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `seller_ptr` and `vat_ptr` point to valid UTF-8 encoded strings of the given lengths
+#[no_mangle]
+pub unsafe extern "C" fn pk_configure_zatca_qr(
+    seller_ptr: *const u8,
+    seller_len: usize,
+    vat_ptr: *const u8,
+    vat_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_configure_zatca_qr");
+    if seller_ptr.is_null() || seller_len == 0 || seller_len > 255 || vat_ptr.is_null() || vat_len == 0 || vat_len > 255 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let provider = fiscal::ZatcaQrProvider {
+        seller_name: read_str(seller_ptr, seller_len),
+        vat_number: read_str(vat_ptr, vat_len),
+    };
+    
+    match fiscal::qr::set_qr_provider(Box::new(provider)) {
+        Ok(_) => PkResult::ok(),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves the fiscal QR payload of a committed transaction's
+/// receipt (no null terminator). Returns NotFound when no provider is registered or the
+/// provider could not produce a payload.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_fiscal_qr_payload(
+    handle: PkTransactionHandle,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_fiscal_qr_payload");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.receipt_document(handle).map(|d| d.fiscal_qr) {
+        Ok(Some(payload)) => write_str(&payload, buffer, buffer_size, out_required_size),
+        Ok(None) => PkResult::err(ResultCode::NotFound),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the KassenSichV TSE signature of a committed transaction as
+/// JSON (transaction number, signature counter, algorithm, signature, times, process data).
+/// Returns NotFound when no TSE is registered or the transaction was not signed, and
+/// InvalidState when the TSE failed for this transaction. The TSE is called after the commit
+/// returns, by a persistence worker, so a transaction just committed may return NotFound until
+/// `pk_wait_for_persistence` has returned.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_tse_signature_json(
+    handle: PkTransactionHandle,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_tse_signature_json");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.tse_signature_json(handle) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Opens the fiscal day on the registered fiscal device. Committed
+/// sales are registered with the device while the day is open.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `out_day_number` points to valid memory for the device's day number
+#[no_mangle]
+pub unsafe extern "C" fn pk_open_fiscal_day(out_day_number: *mut u64) -> PkResult {
+    metrics::instrument!("pk_open_fiscal_day");
+    if out_day_number.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match fiscal::device::open_day() {
+        Ok(day) => {
+            *out_day_number = day.day_number;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Closes the fiscal day (device closing report). Outputs the number
+/// of documents registered and their total; the full report is available from
+/// `pk_get_fiscal_day_report_json`. The day stays open if the device fails.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `out_documents_registered` and `out_total_minor` point to valid memory
+#[no_mangle]
+pub unsafe extern "C" fn pk_close_fiscal_day(
+    out_documents_registered: *mut u64,
+    out_total_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_close_fiscal_day");
+    if out_documents_registered.is_null() || out_total_minor.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match fiscal::device::close_day() {
+        Ok(report) => {
+            *out_documents_registered = report.documents_registered;
+            *out_total_minor = report.total_minor;
+            PkResult::ok()
+        },
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Gets the report of the last closed fiscal day as JSON.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_fiscal_day_report_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_fiscal_day_report_json");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let report = match kernel_store.last_fiscal_day_report.as_ref() {
+        Some(report) => report,
+        None => return PkResult::err(ResultCode::NotFound)
+    };
+    
+    match serde_json::to_string(report) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Exports a committed transaction as an NRF ARTS POSLog XML document
+/// (retail transaction, line items with item links, taxes, tenders, totals). The XML is
+/// written without a null terminator and the required size is always reported.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_export_transaction_poslog(
+    handle: PkTransactionHandle,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_export_transaction_poslog");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.transaction(handle) {
+        Ok(_) => {},
+        Err(e) => return PkResult::from_error(&e)
+    }
+    
+    match kernel_store.export_transaction_poslog(handle) {
+        Ok(xml) => write_str(&xml, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+pub const PK_EINVOICE_UBL: i32 = 1;
+pub const PK_EINVOICE_FACTURX_XML: i32 = 2;
+pub const PK_EINVOICE_FACTURX_PDF: i32 = 3;
+
+/// ARCHITECTURAL COMPONENT: Exports a committed transaction as a B2B e-invoice: UBL 2.1 XML
+/// (`PK_EINVOICE_UBL`), Factur-X CII XML (`PK_EINVOICE_FACTURX_XML`) or a PDF with the CII XML
+/// embedded (`PK_EINVOICE_FACTURX_PDF`). The request is JSON with `invoice_number`, optional
+/// `issue_date` and `buyer_reference`, and `seller`/`buyer` parties (`name`, `vat_id`, `street`,
+/// `city`, `postal_code`, `country_code`). Returns InvalidState when the transaction cannot be
+/// invoiced (not committed, refund, or more than one VAT category).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `handle` refers to a valid committed transaction
+/// - `request_ptr` points to a valid UTF-8 encoded JSON string of `request_len` bytes
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_export_einvoice(
+    handle: PkTransactionHandle,
+    format: i32,
+    request_ptr: *const u8,
+    request_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_export_einvoice");
+    if handle == PK_INVALID_HANDLE || request_ptr.is_null() || request_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let format = match einvoice::InvoiceFormat::from_code(format) {
+        Some(f) => f,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let request = read_str(request_ptr, request_len);
+    let request: einvoice::InvoiceRequest = match serde_json::from_str(&request) {
+        Ok(r) => r,
+        Err(_) => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.transaction(handle) {
+        Ok(_) => {},
+        Err(e) => return PkResult::from_error(&e)
+    }
+    
+    match kernel_store.export_einvoice(handle, &request, format) {
+        Ok(bytes) => write_bytes(&bytes, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+pub const PK_REPORT_JSON: i32 = 1;
+pub const PK_REPORT_CSV: i32 = 2;
+
+// Report period from Unix timestamps (seconds, UTC), half-open [from, to)
+fn report_period(from_unix_secs: i64, to_unix_secs: i64) -> Option<reports::ReportPeriod> {
+    let from = DateTime::from_timestamp(from_unix_secs, 0)?;
+    let to = DateTime::from_timestamp(to_unix_secs, 0)?;
+    reports::ReportPeriod::new(from, to).ok()
+}
+
+/// ARCHITECTURAL COMPONENT: Tax summary report for filing preparation. Aggregates the taxable
+/// basis, tax collected and exempt amounts of transactions committed in [from, to) per
+/// currency, jurisdiction and rate, as JSON (`PK_REPORT_JSON`) or CSV (`PK_REPORT_CSV`).
+/// Timestamps are Unix seconds (UTC); amounts are in minor units.
+/// Reports run on an archive snapshot, which may be up to `report_snapshot_interval_secs` old.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_report_tax_summary(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_report_tax_summary");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let snapshot = match read_store() {
+        Ok(s) => s.report_snapshot(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let report = snapshot.scan(|transactions| reports::tax_summary(transactions, period))
+        .and_then(|report| reports::render(&report, reports::TaxSummaryReport::to_csv, format));
+    match report {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Tender summary report for transactions committed in [from, to),
+/// optionally for one store (`store_len` 0 for all): per currency and tender kind, the count
+/// and amount tendered, refunds paid out, change given and the net, and per terminal the
+/// over/short of drawer sessions closed in the period. With a store, only terminals that
+/// committed a transaction in it during the period are included, since sessions do not record
+/// a store. As JSON (`PK_REPORT_JSON`) or CSV (`PK_REPORT_CSV`), the CSV being the tender
+/// table, a blank line and the drawer table.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` is null or points to `store_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_report_tender_summary(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    store_ptr: *const u8,
+    store_len: usize,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_report_tender_summary");
+    if out_required_size.is_null() || (store_ptr.is_null() && store_len > 0) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    let store = match store_len {
+        0 => None,
+        _ => Some(read_str(store_ptr, store_len)),
+    };
+    
+    // Drawer closes are only kept in the journal
+    let (snapshot, closes) = match read_store() {
+        Ok(s) => {
+            let mut closes = Vec::new();
+            let scanned = s.journal.scan_after(0, |entry| {
+                if let JournalOperation::DrawerSessionClose { terminal_id, expected_minor, counted_minor, variance_minor, .. } = &entry.operation {
+                    if entry.timestamp >= period.from && entry.timestamp < period.to {
+                        closes.push((terminal_id.clone(), *expected_minor, *counted_minor, *variance_minor));
+                    }
+                }
+                true
+            });
+            match scanned {
+                Ok(()) => (s.report_snapshot(), closes),
+                Err(_) => return PkResult::err(ResultCode::InternalError)
+            }
+        },
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let report = snapshot.scan(|transactions| reports::tender_summary(transactions, period, store.as_deref()))
+        .and_then(|mut report| {
+            for (terminal_id, expected_minor, counted_minor, variance_minor) in &closes {
+                report.add_drawer_close(terminal_id, *expected_minor, *counted_minor, *variance_minor);
+            }
+            reports::render(&report, reports::TenderSummaryReport::to_csv, format)
+        });
+    match report {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Hourly sales for dashboards: per store, currency and hour starting
+/// in [from, to), the transactions, units, gross, net and average basket, and the same summed
+/// per business date over those hours. Optionally for one store (`store_len` 0 for all). The
+/// hours are added to on every commit, so the report does not scan the archive; they are kept
+/// for `sales_aggregation_days` business dates. As JSON (`PK_REPORT_JSON`) or CSV
+/// (`PK_REPORT_CSV`), the CSV being the hourly table, a blank line and the daily table.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` is null or points to `store_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_report_hourly_sales(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    store_ptr: *const u8,
+    store_len: usize,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_report_hourly_sales");
+    if out_required_size.is_null() || (store_ptr.is_null() && store_len > 0) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    let store = match store_len {
+        0 => None,
+        _ => Some(read_str(store_ptr, store_len)),
+    };
+    
+    let report = match read_store() {
+        Ok(s) => s.sales.report(period, store.as_deref()),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match reports::render(&report, sales::HourlySalesReport::to_csv, format) {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Live store figures for a manager's dashboard, as JSON: the open
+/// transactions, sales so far in the current business date per currency, transactions per hour
+/// of that date, the average service time from begin to commit, and the active terminals (an
+/// open transaction or a commit in the last 15 minutes). Optionally for one store (`store_len`
+/// 0 for all). Built from the hourly sales and the active store without scanning the archive,
+/// so it can be polled every few seconds.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` is null or points to `store_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_realtime_stats_json(
+    store_ptr: *const u8,
+    store_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_realtime_stats_json");
+    if out_required_size.is_null() || (store_ptr.is_null() && store_len > 0) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let store = match store_len {
+        0 => None,
+        _ => Some(read_str(store_ptr, store_len)),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let mut open_transactions = 0;
+    let mut open_terminals = BTreeSet::new();
+    kernel_store.active_transactions.for_each(|tx| {
+        let open = tx.state == TxState::Building && tx.kind == TransactionKind::Sale
+            && store.as_deref().is_none_or(|store| tx.store == store);
+        if open {
+            open_transactions += 1;
+            open_terminals.extend(tx.terminal_id.clone());
         }
-        
-        // Void parent item
-        tx.void_single_line_item(line_number, reason)?;
-        
-        Ok(())
+    });
+    let stats = kernel_store.sales.realtime(Utc::now(), store.as_deref(), open_transactions, open_terminals);
+    drop(kernel_store);
+    
+    match serde_json::to_string(&stats) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-// === GLOBAL STORE ===
-
-static LEGAL_KERNEL_STORE: OnceLock<RwLock<LegalKernelStore>> = OnceLock::new();
-
-fn legal_kernel_store() -> &'static RwLock<LegalKernelStore> {
-    LEGAL_KERNEL_STORE.get_or_init(|| {
-        let store = LegalKernelStore::new();
-        RwLock::new(store)
-    })
+/// ARCHITECTURAL COMPONENT: Void and override audit report for loss prevention: every line
+/// void, abandoned transaction, price override and return journaled in [from, to), in order,
+/// with the transaction's store, terminal and operator, the approving supervisor (overrides
+/// and returns), the reason code, the line and SKU, and the amount. With `operator_ptr`, only
+/// events on transactions worked by that operator. As JSON (`PK_REPORT_JSON`) or CSV
+/// (`PK_REPORT_CSV`). Reads the journal rather than the archive, so it covers transactions
+/// that were never committed.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` is null or points to `operator_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_report_void_audit(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    operator_ptr: *const u8,
+    operator_len: usize,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_report_void_audit");
+    if out_required_size.is_null() || (operator_ptr.is_null() && operator_len > 0) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    let operator_id = match operator_len {
+        0 => None,
+        _ => Some(read_str(operator_ptr, operator_len)),
+    };
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    let report = reports::audit_events(&kernel_store.journal, period, operator_id.as_deref());
+    drop(kernel_store);
+    
+    match report.and_then(|report| reports::render(&report, reports::AuditEventReport::to_csv, format)) {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
 }
 
-// === UTILITY FUNCTIONS ===
+/// ARCHITECTURAL COMPONENT: Streams transactions committed in [from, to) as CSV for
+/// spreadsheet reconciliation, one chunk per call: a `transaction` row with the totals, then a
+/// `line` row per line. Start with `after_sequence` 0, which also writes the header, and pass
+/// the returned `out_next_sequence` to fetch the next chunk; `out_done` is 1 once the archive
+/// is exhausted. A chunk holds as many whole transactions as fit in `buffer_size`, so the host
+/// sets the pace and the chunk size. When the next transaction alone does not fit, the result
+/// is `InsufficientBuffer` with the size needed and the position unchanged. Each chunk reads a
+/// report snapshot, which may be up to `report_snapshot_interval_secs` old; transactions
+/// committed while streaming are picked up by later chunks.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size`, `out_next_sequence` and `out_done` point to valid memory
+#[no_mangle]
+pub unsafe extern "C" fn pk_export_transactions_csv(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    after_sequence: u64,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize,
+    out_next_sequence: *mut u64,
+    out_done: *mut u32
+) -> PkResult {
+    metrics::instrument!("pk_export_transactions_csv");
+    if out_required_size.is_null() || out_next_sequence.is_null() || out_done.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    let period = match report_period(from_unix_secs, to_unix_secs) {
+        Some(p) => p,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let snapshot = match read_store() {
+        Ok(s) => s.report_snapshot(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let mut chunk = if after_sequence == 0 { export::TRANSACTION_CSV_HEADER.to_string() } else { String::new() };
+    let (mut next_sequence, mut done, mut included) = (after_sequence, true, 0);
+    let scanned = snapshot.scan_after(after_sequence, |sequence, tx| {
+        if tx.committed_at.is_some_and(|at| at >= period.from && at < period.to) {
+            let rows = export::transaction_csv_rows(tx);
+            if included > 0 && chunk.len() + rows.len() > buffer_size {
+                done = false;
+                return false;
+            }
+            chunk.push_str(&rows);
+            included += 1;
+        }
+        next_sequence = sequence;
+        true
+    });
+    if scanned.is_err() {
+        return PkResult::err(ResultCode::InternalError);
+    }
+    
+    let result = write_str(&chunk, buffer, buffer_size, out_required_size);
+    if result.code == ResultCode::Ok as i32 {
+        *out_next_sequence = next_sequence;
+        *out_done = done as u32;
+    } else {
+        *out_next_sequence = after_sequence;
+        *out_done = 0;
+    }
+    result
+}
 
-unsafe fn read_str(ptr: *const u8, len: usize) -> String {
-    if ptr.is_null() || len == 0 {
-        return String::new();
+/// ARCHITECTURAL COMPONENT: Exports the structured audit log for [from, to): one record per
+/// sign-on, operator assignment, void, override, return, abandoned sale, refund limit, hold
+/// resolution, reprint, no-sale and drawer event, in journal order, with the timestamp, store,
+/// terminal, operator and correlation ID (`txn-<handle>`, `drawer-<sequence>` or
+/// `shift-<sequence>`). As JSON (`PK_REPORT_JSON`) or CSV (`PK_REPORT_CSV`, with the event's
+/// own fields as JSON in `details`). Covers the rotated files still kept, or without a data
+/// directory the most recent records.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_export_audit_log(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_export_audit_log");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let slice = std::slice::from_raw_parts(ptr, len);
-    String::from_utf8_lossy(slice).into_owned()
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    // The files are read without the store lock
+    let audit = match read_store() {
+        Ok(s) => Arc::clone(&s.audit),
+        Err(code) => return PkResult::err(code)
+    };
+    match audit.export(period).and_then(|export| reports::render(&export, audit::AuditLogExport::to_csv, format)) {
+        Ok(export) => write_str(&export, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
 }
 
-// === FFI FUNCTIONS ===
+pub const PK_MOVEMENT_BY_SKU: i32 = 0;
+pub const PK_MOVEMENT_BY_DEPARTMENT: i32 = 1;
 
+/// ARCHITECTURAL COMPONENT: SKU movement report for transactions committed in [from, to),
+/// optionally for one store (`store_len` 0 for all): units sold, returned and net, revenue and
+/// the transactions per currency and SKU (`PK_MOVEMENT_BY_SKU`) or per catalog department
+/// (`PK_MOVEMENT_BY_DEPARTMENT`), top sellers first. Departments come from the product catalog
+/// as it is now; SKUs it does not assign one are grouped under no department. Returns the
+/// `limit` rows from `offset` (0 for the default of 100, at most 1000) with `total_rows` and
+/// the `next_offset`, as JSON (`PK_REPORT_JSON`) or CSV (`PK_REPORT_CSV`).
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `store_ptr` is null or points to `store_len` bytes of UTF-8
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub extern "C" fn pk_result_is_ok(result: PkResult) -> bool {
-    result.code == 0
+pub unsafe extern "C" fn pk_report_sku_movement(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    store_ptr: *const u8,
+    store_len: usize,
+    group: i32,
+    offset: u32,
+    limit: u32,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_report_sku_movement");
+    if out_required_size.is_null() || (store_ptr.is_null() && store_len > 0) {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, group, format) = match (report_period(from_unix_secs, to_unix_secs), reports::MovementGroup::from_code(group), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(g), Some(f)) => (p, g, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    let store = match store_len {
+        0 => None,
+        _ => Some(read_str(store_ptr, store_len)),
+    };
+    let department = |store: &LegalKernelStore, currency: &str, sku: &str| {
+        store.product_provider.as_ref()?.product(sku, currency)?.department
+    };
+    
+    let snapshot = match read_store() {
+        Ok(s) => s.report_snapshot(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    // The catalog is consulted under the store lock, once per SKU; the scans run without it
+    let report = match group {
+        reports::MovementGroup::Sku => snapshot.scan(|transactions| reports::movement(transactions, period, store.as_deref(), None, offset as usize, limit as usize))
+            .map_err(KernelError::Internal)
+            .and_then(|mut report| {
+                let kernel_store = read_store_for_api()?;
+                for row in &mut report.rows {
+                    row.department = row.sku.as_deref().and_then(|sku| department(&kernel_store, &row.currency, sku));
+                }
+                Ok(report)
+            }),
+        reports::MovementGroup::Department => snapshot.scan(|transactions| reports::moved_skus(transactions, period, store.as_deref()))
+            .map_err(KernelError::Internal)
+            .and_then(|skus| {
+                let kernel_store = read_store_for_api()?;
+                Ok(skus.into_iter()
+                    .filter_map(|(currency, sku)| department(&kernel_store, &currency, &sku).map(|d| ((currency, sku), d)))
+                    .collect::<HashMap<_, _>>())
+            })
+            .and_then(|departments| snapshot.scan(|transactions| reports::movement(transactions, period, store.as_deref(), Some(&departments), offset as usize, limit as usize))
+                .map_err(KernelError::Internal)),
+    };
+    
+    match report.and_then(|report| reports::render(&report, reports::MovementReport::to_csv, format).map_err(KernelError::Internal)) {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
 }
 
+/// ARCHITECTURAL COMPONENT: Configures the account mapping used by the accounting export, as
+/// JSON: `sales`, `tax_payable`, `cash_clearing`, `card_clearing`, `points_clearing`, and
+/// optional `tax_payable_by_jurisdiction`, `deposit_liability`, `discounts`, `tips_payable`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `mapping_ptr` points to a valid UTF-8 encoded JSON string of `mapping_len` bytes
 #[no_mangle]
-pub extern "C" fn pk_result_get_code(result: PkResult) -> i32 {
-    result.code
+pub unsafe extern "C" fn pk_set_account_mapping(
+    mapping_ptr: *const u8,
+    mapping_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_set_account_mapping");
+    if mapping_ptr.is_null() || mapping_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let mapping: accounting::AccountMapping = match serde_json::from_str(&read_str(mapping_ptr, mapping_len)) {
+        Ok(m) => m,
+        Err(_) => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    kernel_store.account_mapping = Some(mapping);
+    PkResult::ok()
 }
 
+/// ARCHITECTURAL COMPONENT: Exports transactions committed in [from, to) as balanced debit/credit
+/// vouchers, one per day and currency, as JSON (`PK_REPORT_JSON`) or CSV (`PK_REPORT_CSV`).
+/// Requires an account mapping (`pk_set_account_mapping`); returns InvalidState without one.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub extern "C" fn pk_get_version() -> *const std::os::raw::c_char {
-    static VERSION: &[u8] = b"0.4.0-minimal\0";
-    VERSION.as_ptr() as *const std::os::raw::c_char
+pub unsafe extern "C" fn pk_export_accounting_journal(
+    from_unix_secs: i64,
+    to_unix_secs: i64,
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_export_accounting_journal");
+    if out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let (period, format) = match (report_period(from_unix_secs, to_unix_secs), reports::ReportFormat::from_code(format)) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let (snapshot, mapping) = match read_store() {
+        Ok(s) => match s.account_mapping.clone() {
+            Some(mapping) => (s.report_snapshot(), mapping),
+            None => return PkResult::err(ResultCode::InvalidState)
+        },
+        Err(code) => return PkResult::err(code)
+    };
+    
+    // AUDIT COMPLIANCE: Balanced daily accounting vouchers for ERP import
+    let export = snapshot.scan(|transactions| accounting::export(transactions, &mapping, period))
+        .and_then(|export| export)
+        .and_then(|export| reports::render(&export, accounting::AccountingExport::to_csv, format));
+    match export {
+        Ok(export) => write_str(&export, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
+    }
 }
 
-/// ARCHITECTURAL COMPONENT: Gets terminal initialization status and parameters.
+/// ARCHITECTURAL COMPONENT: Sets the terminal and operator working a Building transaction, for
+/// audit trails and loss-prevention counters. A zero-length value keeps the current one.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `terminal_id_ptr` points to valid memory containing a UTF-8 encoded terminal ID
-/// - `terminal_id_len` accurately represents the length of the data at `terminal_id_ptr`
+/// - `handle` refers to a valid Building transaction
+/// - `terminal_ptr` and `operator_ptr` point to valid UTF-8 encoded strings of the given
+///   lengths when the lengths are non-zero
 #[no_mangle]
-pub unsafe extern "C" fn pk_initialize_terminal(
-    terminal_id_ptr: *const u8,
-    terminal_id_len: usize
+pub unsafe extern "C" fn pk_assign_operator(
+    handle: PkTransactionHandle,
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    operator_ptr: *const u8,
+    operator_len: usize
 ) -> PkResult {
-    if terminal_id_ptr.is_null() || terminal_id_len == 0 {
+    metrics::instrument!("pk_assign_operator");
+    if handle == PK_INVALID_HANDLE || (terminal_len == 0 && operator_len == 0) {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let _terminal_id = read_str(terminal_id_ptr, terminal_id_len);
+    let terminal_id = if terminal_len == 0 { None } else { Some(read_str(terminal_ptr, terminal_len)) };
+    let operator_id = if operator_len == 0 { None } else { Some(read_str(operator_ptr, operator_len)) };
     
-    // Initialize the kernel store
-    let _ = legal_kernel_store();
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
     
-    PkResult::ok()
+    match kernel_store.assign_operator(handle, terminal_id, operator_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
 }
 
-/// ARCHITECTURAL COMPONENT: Begins a new transaction in the kernel store.
+/// ARCHITECTURAL COMPONENT: Void and price-override counters per operator and terminal (in the
+/// configured window and in total, with alert counts), as JSON or CSV.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `store_ptr` points to valid memory containing a UTF-8 encoded store name
-/// - `store_len` accurately represents the length of the data at `store_ptr`
-/// - `currency_ptr` points to valid memory containing a UTF-8 encoded currency code
-/// - `currency_len` accurately represents the length of the data at `currency_ptr`
-/// - `currency_decimal_places` specifies the decimal places for currency (user-space decision)
-/// - `out_handle` points to valid memory where the transaction handle can be written
-/// - All pointers remain valid for the duration of this call
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_begin_transaction(
-    store_ptr: *const u8,
-    store_len: usize,
-    currency_ptr: *const u8,
-    currency_len: usize,
-    currency_decimal_places: u8,
-    out_handle: *mut PkTransactionHandle
+pub unsafe extern "C" fn pk_report_anomaly_counters(
+    format: i32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if store_ptr.is_null() || store_len == 0 || currency_ptr.is_null() || currency_len == 0 || out_handle.is_null() {
+    metrics::instrument!("pk_report_anomaly_counters");
+    if out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let store = read_str(store_ptr, store_len);
-    let currency_code = read_str(currency_ptr, currency_len);
-    
-    // ARCHITECTURAL PRINCIPLE: Kernel is culture-neutral - client provides all currency info
-    let currency = match Currency::new(&currency_code, currency_decimal_places) {
-        Ok(c) => c,
-        Err(_) => return PkResult::err(ResultCode::ValidationFailed)
+    let format = match reports::ReportFormat::from_code(format) {
+        Some(f) => f,
+        None => return PkResult::err(ResultCode::ValidationFailed)
     };
     
-    let mut kernel_store = match legal_kernel_store().write() {
+    let kernel_store = match read_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
     
-    match kernel_store.begin_transaction_legal(store, currency) {
-        Ok(handle) => {
-            *out_handle = handle;
-            PkResult::ok()
-        },
+    match kernel_store.anomaly_counters_report(format) {
+        Ok(report) => write_str(&report, buffer, buffer_size, out_required_size),
         Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Adds a line item to an existing transaction.
+/// ARCHITECTURAL COMPONENT: Starts an operator's shift, resetting the refund velocity counters
+/// (refund count and amount) the per-shift limits are checked against.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` points to valid memory containing a UTF-8 encoded operator ID
+/// - `operator_len` accurately represents the length of the data at `operator_ptr`
+#[no_mangle]
+pub unsafe extern "C" fn pk_start_shift(
+    operator_ptr: *const u8,
+    operator_len: usize
+) -> PkResult {
+    metrics::instrument!("pk_start_shift");
+    if operator_ptr.is_null() || operator_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let operator_id = read_str(operator_ptr, operator_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.start_shift(operator_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Adds a merchandise return line. `unit_minor` is the positive unit
+/// price refunded; the line reduces the amount due. SECURITY: The return is checked against the
+/// refund velocity limits; when a limit triggers, the return is refused with `PermissionDenied`
+/// unless `supervisor_ptr` names a supervisor holding the RefundOverride permission. Triggered
+/// limits are journaled and published as `RefundLimitTriggered` events either way. Returns the
+/// operator has open in other transactions count against the per-shift limits.
 /// 
 /// # Safety
 /// The caller must ensure that:
 /// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
 /// - `sku_len` accurately represents the length of the data at `sku_ptr`
-/// - The memory pointed to by `sku_ptr` remains valid for the duration of this call
+/// - `receipt_ptr`, `reason_ptr` and `supervisor_ptr` point to valid UTF-8 encoded strings of
+///   their given lengths when the lengths are non-zero (zero means not supplied)
 /// - `handle` refers to a valid, active transaction
 /// - `qty` is greater than zero
 #[no_mangle]
-pub unsafe extern "C" fn pk_add_line(
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pk_add_return_line(
     handle: PkTransactionHandle,
     sku_ptr: *const u8,
     sku_len: usize,
     qty: i32,
-    unit_minor: i64
+    unit_minor: i64,
+    receipt_ptr: *const u8,
+    receipt_len: usize,
+    reason_ptr: *const u8,
+    reason_len: usize,
+    supervisor_ptr: *const u8,
+    supervisor_len: usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+    metrics::instrument!("pk_add_return_line");
+    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 || unit_minor < 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let sku = read_str(sku_ptr, sku_len);
+    let original_receipt = if receipt_len == 0 { None } else { Some(read_str(receipt_ptr, receipt_len)) };
+    let reason_code = if reason_len == 0 { None } else { Some(read_str(reason_ptr, reason_len)) };
+    let supervisor_id = if supervisor_len == 0 { None } else { Some(read_str(supervisor_ptr, supervisor_len)) };
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.add_return_line_legal(handle, sku, qty, unit_minor, original_receipt, reason_code, supervisor_id) {
+        Ok(_) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Pays out part or all of the refund due on a transaction with a
+/// negative total. Once the full refund has been paid out, `pk_finalize_transaction` commits it.
+#[no_mangle]
+pub extern "C" fn pk_add_refund_tender(
+    handle: PkTransactionHandle,
+    amount_minor: i64
+) -> PkResult {
+    metrics::instrument!("pk_add_refund_tender");
+    if handle == PK_INVALID_HANDLE || amount_minor <= 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    match apply_tender(handle, |s| s.add_refund_tender_legal(handle, amount_minor).map(|committed| ((), committed))) {
+        Ok(()) => PkResult::ok(),
+        Err(result) => result
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Retrieves an operator's refunds in the current shift (shift start,
+/// refund count and amount refunded) as JSON.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `operator_ptr` points to valid memory containing a UTF-8 encoded operator ID
+/// - `operator_len` accurately represents the length of the data at `operator_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_shift_refunds_json(
+    operator_ptr: *const u8,
+    operator_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
+) -> PkResult {
+    metrics::instrument!("pk_get_shift_refunds_json");
+    if operator_ptr.is_null() || operator_len == 0 || out_required_size.is_null() {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    
+    let operator_id = read_str(operator_ptr, operator_len);
+    
+    let kernel_store = match read_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.shift_refunds_json(&operator_id) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+// Denomination values and counts are parallel arrays; None when either array is missing
+unsafe fn read_denominations(values: *const i64, counts: *const u32, denomination_count: usize) -> Option<Vec<cash::DenominationCount>> {
+    if denomination_count == 0 {
+        return Some(Vec::new());
+    }
+    if values.is_null() || counts.is_null() {
+        return None;
+    }
+    let values = std::slice::from_raw_parts(values, denomination_count);
+    let counts = std::slice::from_raw_parts(counts, denomination_count);
+    Some(values.iter().zip(counts)
+        .map(|(&value_minor, &count)| cash::DenominationCount { value_minor, count })
+        .collect())
+}
+
+/// ARCHITECTURAL COMPONENT: Opens a terminal's drawer session with its counted float. The
+/// float is given per denomination: `values` holds each denomination's value in minor units
+/// and `counts` the number of notes or coins counted, both `denomination_count` long (0 for an
+/// empty drawer). Cash sales committed on the terminal in `currency` count toward the session's
+/// expected total until it closes. One session may be open per terminal.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `operator_ptr` is null or points to `operator_len` bytes of UTF-8 encoded operator ID
+/// - `currency_ptr` points to `currency_len` bytes of UTF-8 encoded ISO 4217 currency code
+/// - `values` and `counts` each point to `denomination_count` valid elements
+#[no_mangle]
+pub unsafe extern "C" fn pk_open_drawer_session(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    operator_ptr: *const u8,
+    operator_len: usize,
+    currency_ptr: *const u8,
+    currency_len: usize,
+    values: *const i64,
+    counts: *const u32,
+    denomination_count: usize
+) -> PkResult {
+    metrics::instrument!("pk_open_drawer_session");
+    if terminal_ptr.is_null() || terminal_len == 0 || currency_ptr.is_null() || currency_len == 0 {
+        return PkResult::err(ResultCode::ValidationFailed);
+    }
+    let opening_float = match read_denominations(values, counts, denomination_count) {
+        Some(float) => float,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
+    
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    let operator_id = (!operator_ptr.is_null() && operator_len > 0).then(|| read_str(operator_ptr, operator_len));
+    let currency = read_str(currency_ptr, currency_len);
+    
+    let mut kernel_store = match write_store() {
+        Ok(s) => s,
+        Err(code) => return PkResult::err(code)
+    };
+    
+    match kernel_store.open_drawer_session(terminal_id, operator_id, &currency, opening_float) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => PkResult::from_error(&e)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Records a mid-shift cash movement against the terminal's open
+/// drawer session. `kind` is 0 for a safe drop, 1 for a pickup and 2 for a loan from the safe;
+/// the amount moved is given per denomination as for `pk_open_drawer_session`. Drops and
+/// pickups lower the expected drawer total and may not exceed it; loans raise it. SECURITY:
+/// Pickups and loans need an operator holding the CashManagement permission. When a cash drawer
+/// driver is registered, the drawer is opened and the opening journaled. The expected total
+/// after the movement is written to `out_expected_minor`.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `operator_ptr` is null or points to `operator_len` bytes of UTF-8 encoded operator ID
+/// - `values` and `counts` each point to `denomination_count` valid elements
+/// - `out_expected_minor` is null or points to valid memory for the expected total
+#[no_mangle]
+pub unsafe extern "C" fn pk_record_cash_movement(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    kind: i32,
+    operator_ptr: *const u8,
+    operator_len: usize,
+    values: *const i64,
+    counts: *const u32,
+    denomination_count: usize,
+    out_expected_minor: *mut i64
+) -> PkResult {
+    metrics::instrument!("pk_record_cash_movement");
+    if terminal_ptr.is_null() || terminal_len == 0 || denomination_count == 0 {
         return PkResult::err(ResultCode::ValidationFailed);
     }
+    let (kind, denominations) = match (cash::CashMovementKind::from_code(kind), read_denominations(values, counts, denomination_count)) {
+        (Some(kind), Some(denominations)) => (kind, denominations),
+        _ => return PkResult::err(ResultCode::ValidationFailed)
+    };
     
-    let sku = read_str(sku_ptr, sku_len);
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    let operator_id = (!operator_ptr.is_null() && operator_len > 0).then(|| read_str(operator_ptr, operator_len));
     
-    let mut kernel_store = match legal_kernel_store().write() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+    let recorded = match write_store() {
+        Ok(mut s) => s.record_cash_movement(terminal_id, kind, denominations, operator_id),
+        Err(code) => return PkResult::err(code)
+    };
+    let (expected_minor, open) = match recorded {
+        Ok(recorded) => recorded,
+        Err(e) => return PkResult::from_error(&e)
     };
+    if !out_expected_minor.is_null() {
+        *out_expected_minor = expected_minor;
+    }
     
-    match kernel_store.add_line_legal(handle, sku, qty, unit_minor) {
-        Ok(_) => PkResult::ok(),
-        Err(_) => PkResult::err(ResultCode::InternalError)
+    // The movement stands whether or not the drawer opens; the driver runs without the store lock
+    if let Some(open) = open {
+        let outcome = open.open();
+        if let Ok(s) = read_store() {
+            let _ = s.record_drawer_open(&open, outcome);
+        }
     }
+    PkResult::ok()
 }
 
+/// ARCHITECTURAL COMPONENT: Closes the terminal's drawer session against the counted cash,
+/// given per denomination as for `pk_open_drawer_session`, and writes the session report as
+/// JSON: float, cash sales, drops, pickups, loans, expected and counted totals, and the
+/// variance (counted less expected, negative when the drawer is short). The session closes
+/// even if `buffer` is too small; `pk_get_drawer_session_json` returns the report again.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `operator_ptr` is null or points to `operator_len` bytes of UTF-8 encoded operator ID
+/// - `values` and `counts` each point to `denomination_count` valid elements
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub extern "C" fn pk_add_cash_tender(
-    handle: PkTransactionHandle,
-    amount_minor: i64
+pub unsafe extern "C" fn pk_close_drawer_session(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    operator_ptr: *const u8,
+    operator_len: usize,
+    values: *const i64,
+    counts: *const u32,
+    denomination_count: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || amount_minor <= 0 {
+    metrics::instrument!("pk_close_drawer_session");
+    if terminal_ptr.is_null() || terminal_len == 0 || out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
+    let counted = match read_denominations(values, counts, denomination_count) {
+        Some(counted) => counted,
+        None => return PkResult::err(ResultCode::ValidationFailed)
+    };
     
-    let mut kernel_store = match legal_kernel_store().write() {
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    let operator_id = (!operator_ptr.is_null() && operator_len > 0).then(|| read_str(operator_ptr, operator_len));
+    
+    let mut kernel_store = match write_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
     
-    match kernel_store.add_cash_tender_legal(handle, amount_minor) {
-        Ok(_) => PkResult::ok(),
-        Err(_) => PkResult::err(ResultCode::InternalError)
+    match kernel_store.close_drawer_session(&terminal_id, counted, operator_id) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Retrieves transaction totals and state information.
+/// ARCHITECTURAL COMPONENT: Retrieves the terminal's open drawer session as JSON, with its
+/// float, cash sales, movements and expected total; when no session is open, the report of
+/// the last session closed on the terminal.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `handle` refers to a valid, active transaction
-/// - `out_total` points to valid memory where the total amount can be written
-/// - `out_tendered` points to valid memory where the tendered amount can be written
-/// - `out_change` points to valid memory where the change amount can be written
-/// - `out_state` points to valid memory where the transaction state can be written
-/// - All output pointers remain valid for the duration of this call
+/// - `terminal_ptr` points to valid memory containing a UTF-8 encoded terminal ID
+/// - `terminal_len` accurately represents the length of the data at `terminal_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_get_totals(
-    handle: PkTransactionHandle,
-    out_total: *mut i64,
-    out_tendered: *mut i64,
-    out_change: *mut i64,
-    out_state: *mut i32
+pub unsafe extern "C" fn pk_get_drawer_session_json(
+    terminal_ptr: *const u8,
+    terminal_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || out_total.is_null() || out_tendered.is_null() || out_change.is_null() || out_state.is_null() {
+    metrics::instrument!("pk_get_drawer_session_json");
+    if terminal_ptr.is_null() || terminal_len == 0 || out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
+    let terminal_id = read_str(terminal_ptr, terminal_len);
+    
+    let kernel_store = match read_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
     
-    match kernel_store.get_transaction_totals(handle) {
-        Ok((total, tendered, change, state)) => {
-            *out_total = total;
-            *out_tendered = tendered;
-            *out_change = change;
-            *out_state = state as i32;
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    match kernel_store.drawer_session_json(&terminal_id) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(e) => PkResult::from_error(&e)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Retrieves the number of line items in a transaction.
+/// ARCHITECTURAL COMPONENT: Queries the archive of committed and cancelled transactions.
+/// `query_ptr` holds a JSON object with optional `receipt_number`, `from`/`to` (RFC 3339,
+/// half-open on commit time, so a date range matches committed transactions only),
+/// `operator_id`, `sku` and `limit`; all given criteria must match. An empty query
+/// (`query_len` 0) returns every record. Records are written as a JSON array in archive order,
+/// each with its sequence number, hash chain values and transaction export.
+/// Like the reports, queries run on an archive snapshot (see `report_snapshot_interval_secs`).
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `handle` refers to a valid, active transaction
-/// - `out_count` points to valid memory where the line count can be written
-/// - The output pointer remains valid for the duration of this call
+/// - `query_ptr` points to valid memory containing UTF-8 encoded JSON when `query_len` is non-zero
+/// - `query_len` accurately represents the length of the data at `query_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_get_line_count(
-    handle: PkTransactionHandle,
-    out_count: *mut u32
+pub unsafe extern "C" fn pk_query_archive_json(
+    query_ptr: *const u8,
+    query_len: usize,
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || out_count.is_null() {
+    metrics::instrument!("pk_query_archive_json");
+    if (query_ptr.is_null() && query_len > 0) || out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+    let query = if query_len == 0 {
+        archive::ArchiveQuery::default()
+    } else {
+        match serde_json::from_str(&read_str(query_ptr, query_len)) {
+            Ok(q) => q,
+            Err(_) => return PkResult::err(ResultCode::ValidationFailed)
+        }
     };
     
-    match kernel_store.get_line_count_legal(handle) {
-        Ok(count) => {
-            *out_count = count;
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    let snapshot = match read_store() {
+        Ok(s) => s.report_snapshot(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    let records = snapshot.query(&query)
+        .and_then(|records| serde_json::to_string(&records).map_err(|e| e.to_string()));
+    match records {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Retrieves the number of decimal places for the transaction's currency.
+/// ARCHITECTURAL COMPONENT: Recomputes the archive hash chain. Writes the number of archived
+/// records and the sequence number of the first record that fails verification (0 when the
+/// whole chain verifies). The result is `ValidationFailed` when verification fails.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `handle` refers to a valid, active transaction
-/// - `out_decimal_places` points to valid memory where the decimal places value can be written
-/// - The output pointer remains valid for the duration of this call
+/// - `out_records` and `out_first_invalid` point to valid memory for the results
 #[no_mangle]
-pub unsafe extern "C" fn pk_get_currency_decimal_places(
-    handle: PkTransactionHandle,
-    out_decimal_places: *mut u8
+pub unsafe extern "C" fn pk_verify_archive(
+    out_records: *mut u64,
+    out_first_invalid: *mut u64
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || out_decimal_places.is_null() {
+    metrics::instrument!("pk_verify_archive");
+    if out_records.is_null() || out_first_invalid.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+    // Verifies the archive as it is now, not a cached report snapshot
+    let snapshot = match read_store() {
+        Ok(s) => s.archive.snapshot(),
+        Err(code) => return PkResult::err(code)
     };
     
-    match kernel_store.get_currency_decimal_places(handle) {
-        Ok(decimal_places) => {
-            *out_decimal_places = decimal_places;
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    let verification = snapshot.verify();
+    *out_records = verification.records;
+    *out_first_invalid = verification.first_invalid.unwrap_or(0);
+    if verification.first_invalid.is_some() {
+        return PkResult::err(ResultCode::ValidationFailed);
     }
+    PkResult::ok()
 }
 
-/// ARCHITECTURAL COMPONENT: Retrieves details of a specific line item with parent relationship.
-/// NRF COMPLIANCE: Returns parent_line_item_id for hierarchical display.
+// AUDIT COMPLIANCE: The transaction's journal entries in order, each with what it changed,
+// from its begin up to the next begin of the same handle: a handle journaled again after a
+// restart is a different transaction. Without a known begin, the latest such run is used.
+fn transaction_history(journal: &Journal, handle: u64, begin_sequence: Option<u64>) -> Result<replay::TransactionHistory, KernelError> {
+    let mut entries: Vec<journal::JournalEntry> = Vec::new();
+    journal.scan_after(begin_sequence.map_or(0, |s| s.saturating_sub(1)), |entry| {
+        if entry.transaction_handle != handle {
+            return true;
+        }
+        if matches!(entry.operation, JournalOperation::TransactionBegin { .. }) && !entries.is_empty() {
+            if begin_sequence.is_some() {
+                return false;
+            }
+            entries.clear();
+        }
+        entries.push(entry.clone());
+        true
+    }).map_err(KernelError::Internal)?;
+    if entries.is_empty() {
+        return Err(KernelError::not_found(TRANSACTION_NOT_FOUND));
+    }
+    Ok(replay::transaction_history(handle, entries))
+}
+
+/// ARCHITECTURAL COMPONENT: History of one transaction for reconstructing a disputed receipt:
+/// its journal entries in order, each with the sequence number, timestamp, operation as
+/// journaled, whether its checksum still matches, the terminal and operator working the
+/// transaction at the time, and the fields of the transaction it changed (`path`, `before`,
+/// `after`), followed by the transaction as the entries leave it. As JSON. Covers committed,
+/// archived and abandoned transactions as long as their entries are in the journal.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `handle` refers to a valid, active transaction
-/// - `line_index` is within the valid range of line items (0 to line_count-1)
-/// - `out_sku_ptr` points to valid memory buffer for the SKU string
-/// - `out_sku_len` specifies the size of the buffer, receives actual string length
-/// - `out_qty`, `out_unit_minor`, and `out_parent_id` point to valid memory for output values
-/// - `out_has_parent` points to valid memory for parent existence flag
-/// - All output pointers remain valid for the duration of this call
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_get_line_item_with_parent(
+pub unsafe extern "C" fn pk_get_transaction_history_json(
     handle: PkTransactionHandle,
-    line_index: u32,
-    out_sku_ptr: *mut u8,
-    out_sku_len: *mut usize,
-    out_qty: *mut i32,
-    out_unit_minor: *mut i64,
-    out_parent_id: *mut u32,
-    out_has_parent: *mut bool
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || out_sku_ptr.is_null() || out_sku_len.is_null() || out_qty.is_null() || out_unit_minor.is_null() || out_parent_id.is_null() || out_has_parent.is_null() {
+    metrics::instrument!("pk_get_transaction_history_json");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+    let (journal, begin_sequence) = match read_store() {
+        Ok(s) => s.history_source(handle),
+        Err(code) => return PkResult::err(code)
+    };
+    let history = match transaction_history(&journal, handle, begin_sequence) {
+        Ok(history) => history,
+        Err(e) => return PkResult::from_error(&e)
     };
     
-    match kernel_store.get_line_item_details(handle, line_index) {
-        Ok((sku, qty, unit_minor, parent_id)) => {
-            let sku_bytes = sku.as_bytes();
-            let buffer_size = *out_sku_len;
-            
-            if sku_bytes.len() >= buffer_size {
-                // Buffer too small, return required size
-                *out_sku_len = sku_bytes.len() + 1; // +1 for null terminator
-                return PkResult::err(ResultCode::InsufficientBuffer);
-            }
-            
-            // Copy SKU to output buffer
-            std::ptr::copy_nonoverlapping(sku_bytes.as_ptr(), out_sku_ptr, sku_bytes.len());
-            *out_sku_ptr.add(sku_bytes.len()) = 0; // Null terminator
-            
-            *out_sku_len = sku_bytes.len();
-            *out_qty = qty;
-            *out_unit_minor = unit_minor;
-            
-            // Set parent information
-            if let Some(parent) = parent_id {
-                *out_parent_id = parent;
-                *out_has_parent = true;
-            } else {
-                *out_parent_id = 0;
-                *out_has_parent = false;
-            }
-            
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    match serde_json::to_string(&history) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Adds a child line item to an existing transaction with parent reference.
-/// NRF COMPLIANCE: Supports linked items (parent-child relationships).
+/// ARCHITECTURAL COMPONENT: Internal consistency check for one transaction. Recomputes its
+/// totals from the journal entries alone (lines added, voided or moved out, taxes, tenders)
+/// and compares them with the transaction and with the cached totals displays poll. Writes a
+/// JSON report of the three sets of figures, each field that disagrees, and each line whose
+/// journaled kind, quantity or unit price differs from the line held. The result is
+/// `ValidationFailed` when they disagree.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `sku_ptr` points to valid memory containing a UTF-8 encoded SKU string
-/// - `sku_len` accurately represents the length of the data at `sku_ptr`
-/// - The memory pointed to by `sku_ptr` remains valid for the duration of this call
-/// - `handle` refers to a valid, active transaction
-/// - `qty` is greater than zero
-/// - `parent_line_id` is a valid parent line item ID within the transaction
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_add_child_line(
+pub unsafe extern "C" fn pk_verify_transaction(
     handle: PkTransactionHandle,
-    sku_ptr: *const u8,
-    sku_len: usize,
-    qty: i32,
-    unit_minor: i64,
-    parent_line_id: u32
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+    metrics::instrument!("pk_verify_transaction");
+    if handle == PK_INVALID_HANDLE || out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let sku = read_str(sku_ptr, sku_len);
-    
-    let mut kernel_store = match legal_kernel_store().write() {
+    // Exclusive, so no lane is caught between changing the transaction and journaling it
+    let kernel_store = match write_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
     
-    match kernel_store.add_child_line_legal(handle, sku, qty, unit_minor, parent_line_id) {
-        Ok(_) => PkResult::ok(),
-        Err(_) => PkResult::err(ResultCode::ValidationFailed)
+    let verification = match kernel_store.verify_transaction(handle) {
+        Ok(verification) => verification,
+        Err(e) => return PkResult::from_error(&e)
+    };
+    drop(kernel_store);
+    
+    let result = match serde_json::to_string(&verification) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => return PkResult::err(ResultCode::InternalError)
+    };
+    if result.code == 0 && !verification.consistent {
+        return PkResult::err(ResultCode::ValidationFailed);
     }
+    result
 }
 
+/// ARCHITECTURAL COMPONENT: Store-wide self-check for support tooling. Verifies that journal
+/// entries are in unbroken sequence and match their checksums, that every transaction handle
+/// was issued by the kernel and every active transaction has a journaled begin, that no line
+/// references a missing parent or lives on under a voided one, that each active transaction's
+/// totals agree with its journal (as `pk_verify_transaction` checks), and that the archive
+/// hash chain verifies. Writes a JSON report with the counts checked and one finding per
+/// problem, naming the check, handle, line and journal sequence number involved. The result
+/// is `ValidationFailed` when there are findings. The journal entries checked are those held
+/// in memory; a journal with a file drops, at periodic checkpoints, the entries before the
+/// oldest open transaction's begin.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_add_line_with_parent(
-    handle: PkTransactionHandle,
-    sku_ptr: *const u8,
-    sku_len: usize,
-    qty: i32,
-    unit_minor: i64,
-    parent_line_id: u32  // 0 means no parent
+pub unsafe extern "C" fn pk_verify_store(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || sku_ptr.is_null() || sku_len == 0 || qty <= 0 {
+    metrics::instrument!("pk_verify_store");
+    if out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let sku = read_str(sku_ptr, sku_len);
-    
-    let mut kernel_store = match legal_kernel_store().write() {
+    // Exclusive, so no lane is caught between changing a transaction and journaling it
+    let kernel_store = match write_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
+    let (verification, snapshot) = kernel_store.verify_store();
+    drop(kernel_store);
+    let verification = verification.check_archive(&snapshot);
     
-    match kernel_store.add_child_line_legal(handle, sku, qty, unit_minor, parent_line_id) {
-        Ok(_) => PkResult::ok(),
-        Err(_) => PkResult::err(ResultCode::ValidationFailed)
+    let result = match serde_json::to_string(&verification) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => return PkResult::err(ResultCode::InternalError)
+    };
+    if result.code == 0 && !verification.consistent {
+        return PkResult::err(ResultCode::ValidationFailed);
     }
+    result
 }
 
-/// ARCHITECTURAL COMPONENT: Voids a line item with NRF-compliant cascade to child items.
-/// Critical NRF requirement: When parent items are voided, all linked child items must be voided.
+/// ARCHITECTURAL COMPONENT: Store statistics as JSON: active transactions in total and per
+/// shard, archived transactions, pending and dropped events, interned SKUs, the memory held
+/// by resident archived transactions alongside the number evicted, persistence workers with
+/// the jobs they have queued or running, and the transaction pool (size, shells available,
+/// begins served from it or allocated, transactions recycled into it), and the number of
+/// times a poisoned store lock was recovered.
 /// 
 /// # Safety
 /// The caller must ensure that:
-/// - `handle` refers to a valid, active transaction
-/// - `line_number` is within the valid range of line items (1-based)
-/// - `reason_ptr` points to valid memory containing a UTF-8 encoded reason string
-/// - `reason_len` accurately represents the length of the data at `reason_ptr`
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
 #[no_mangle]
-pub unsafe extern "C" fn pk_void_line_item_with_cascade(
-    handle: PkTransactionHandle,
-    line_number: u32,
-    reason_ptr: *const u8,
-    reason_len: usize
+pub unsafe extern "C" fn pk_get_store_stats_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || line_number == 0 || reason_ptr.is_null() {
+    metrics::instrument!("pk_get_store_stats_json");
+    if out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let reason = read_str(reason_ptr, reason_len);
-    
-    let mut kernel_store = match legal_kernel_store().write() {
+    let kernel_store = match read_store() {
         Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
+        Err(code) => return PkResult::err(code)
     };
     
-    // Use the NRF void cascade logic
-    match kernel_store.void_line_with_cascade(handle, line_number, &reason) {
-        Ok(_) => PkResult::ok(),
-        Err(_) => PkResult::err(ResultCode::ValidationFailed)
+    match serde_json::to_string(&kernel_store.store_stats()) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Gets the parent line item ID for a specific line item.
-/// NRF COMPLIANCE: Supports querying linked items hierarchy.
+/// ARCHITECTURAL COMPONENT: Waits until background persistence (archival, eviction, receipt
+/// delivery) has caught up with every commit made so far, for at most `timeout_ms`
+/// milliseconds. Returns TimedOut if jobs were still queued or running when it expired. Use
+/// before shutdown, or before querying the archive for transactions just committed.
 #[no_mangle]
-pub unsafe extern "C" fn pk_get_line_parent_id(
-    handle: PkTransactionHandle,
-    line_number: u32,
-    out_parent_id: *mut u32,
-    out_has_parent: *mut bool
+pub extern "C" fn pk_wait_for_persistence(timeout_ms: u64) -> PkResult {
+    metrics::instrument!("pk_wait_for_persistence");
+    let idle = match read_store() {
+        Ok(s) => s.pipeline.idle(),
+        Err(code) => return PkResult::err(code)
+    };
+    
+    if idle.wait(std::time::Duration::from_millis(timeout_ms)) {
+        PkResult::ok()
+    } else {
+        last_error::set("Persistence jobs still pending".to_string());
+        PkResult::err(ResultCode::TimedOut)
+    }
+}
+
+/// ARCHITECTURAL COMPONENT: Call counts and latency of every `pk_*` entry point called so far,
+/// as JSON: per entry point its `calls`, total, mean and maximum time, the 50th and 99th
+/// percentiles (as the upper bound of the histogram bucket holding them) and the `histogram`,
+/// in microseconds. Only in builds with the `ffi-metrics` feature.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `buffer` points to a writable buffer of `buffer_size` bytes
+/// - `out_required_size` points to valid memory for the required size
+#[cfg(feature = "ffi-metrics")]
+#[no_mangle]
+pub unsafe extern "C" fn pk_get_metrics_json(
+    buffer: *mut u8,
+    buffer_size: usize,
+    out_required_size: *mut usize
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || line_number == 0 || out_parent_id.is_null() || out_has_parent.is_null() {
+    if out_required_size.is_null() {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
-    };
-    
-    match kernel_store.get_line_parent_id(handle, line_number) {
-        Ok(parent_id_opt) => {
-            if let Some(parent_id) = parent_id_opt {
-                *out_parent_id = parent_id;
-                *out_has_parent = true;
-            } else {
-                *out_parent_id = 0;
-                *out_has_parent = false;
-            }
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    match serde_json::to_string(&serde_json::json!({ "calls": metrics::snapshot() })) {
+        Ok(json) => write_str(&json, buffer, buffer_size, out_required_size),
+        Err(_) => PkResult::err(ResultCode::InternalError)
     }
 }
 
-/// ARCHITECTURAL COMPONENT: Finds all child line items of a parent (for void cascade).
-/// NRF COMPLIANCE: Supports void cascade for linked items.
+/// ARCHITECTURAL COMPONENT: Arms an injected failure point for resilience testing, firing on
+/// `probability_ppm` in a million passes through it; 0 disarms it. Points are `journal_write`
+/// and `journal_sync` (with a journal file configured), `lock_timeout` (the call returns
+/// TimedOut) and `worker_panic` (a persistence worker panics holding the store lock, which the
+/// next call recovers). Only in builds with the `fault-injection` feature.
+/// 
+/// # Safety
+/// The caller must ensure that:
+/// - `name_ptr` points to valid memory containing a UTF-8 encoded fault point name
+/// - `name_len` accurately represents the length of the data at `name_ptr`
+#[cfg(feature = "fault-injection")]
 #[no_mangle]
-pub unsafe extern "C" fn pk_find_line_children(
-    handle: PkTransactionHandle,
-    parent_line_number: u32,
-    out_children_ptr: *mut u32,
-    out_children_len: *mut usize
+pub unsafe extern "C" fn pk_set_fault(
+    name_ptr: *const u8,
+    name_len: usize,
+    probability_ppm: u32
 ) -> PkResult {
-    if handle == PK_INVALID_HANDLE || parent_line_number == 0 || out_children_ptr.is_null() || out_children_len.is_null() {
+    metrics::instrument!("pk_set_fault");
+    if name_ptr.is_null() || name_len == 0 {
         return PkResult::err(ResultCode::ValidationFailed);
     }
     
-    let kernel_store = match legal_kernel_store().read() {
-        Ok(s) => s,
-        Err(_) => return PkResult::err(ResultCode::InternalError)
-    };
-    
-    match kernel_store.find_line_children(handle, parent_line_number) {
-        Ok(children) => {
-            let buffer_size = *out_children_len;
-            
-            if children.len() > buffer_size {
-                // Buffer too small, return required size
-                *out_children_len = children.len();
-                return PkResult::err(ResultCode::InsufficientBuffer);
-            }
-            
-            // Copy child line numbers to output buffer
-            for (i, &child_id) in children.iter().enumerate() {
-                *out_children_ptr.add(i) = child_id;
-            }
-            
-            *out_children_len = children.len();
-            PkResult::ok()
-        },
-        Err(_) => PkResult::err(ResultCode::NotFound)
+    match faults::arm(&read_str(name_ptr, name_len), probability_ppm) {
+        Ok(()) => PkResult::ok(),
+        Err(e) => {
+            last_error::set(e);
+            PkResult::err(ResultCode::ValidationFailed)
+        }
     }
 }
+
+/// ARCHITECTURAL COMPONENT: Restarts the pseudo-random sequence deciding when armed faults
+/// fire, so a single-threaded test sees its failures at the same calls on every run. Only in
+/// builds with the `fault-injection` feature.
+#[cfg(feature = "fault-injection")]
+#[no_mangle]
+pub extern "C" fn pk_set_fault_seed(seed: u64) {
+    metrics::instrument!("pk_set_fault_seed");
+    faults::seed(seed);
+}
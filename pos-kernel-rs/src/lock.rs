@@ -0,0 +1,98 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Bounded lock acquisition
+//! `std::sync::RwLock` has no timed wait, so acquisition polls `try_read`/`try_write` with
+//! backoff until the configured timeout expires. A wedged lock then surfaces as TimedOut to
+//! the FFI caller instead of hanging it. A timeout of zero waits indefinitely.
+//!
+//! A polling writer does not queue ahead of new readers; under sustained read load a write
+//! lock may be acquired later than a blocking wait would, but never later than the timeout.
+
+use std::sync::{LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, TryLockResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::faults::{self, Fault};
+use crate::last_error;
+use crate::KernelError;
+
+pub(crate) const LOCK_TIMED_OUT: &str = "Timed out waiting for a kernel lock";
+
+pub(crate) const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_MS);
+
+const SPIN_ATTEMPTS: u32 = 64;
+const MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+pub(crate) fn set_timeout_ms(timeout_ms: u64) {
+    TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+}
+
+// Outer error: the wait timed out (detail recorded as the thread's last error).
+// Inner result: the lock was acquired, possibly poisoned, exactly as `RwLock::read` reports it.
+pub(crate) fn read<'a, T>(lock: &'a RwLock<T>, name: &str) -> Result<LockResult<RwLockReadGuard<'a, T>>, KernelError> {
+    acquire(|| lock.try_read(), || lock.read(), name, "read")
+}
+
+pub(crate) fn write<'a, T>(lock: &'a RwLock<T>, name: &str) -> Result<LockResult<RwLockWriteGuard<'a, T>>, KernelError> {
+    acquire(|| lock.try_write(), || lock.write(), name, "write")
+}
+
+fn acquire<G>(
+    try_lock: impl Fn() -> TryLockResult<G>,
+    lock: impl FnOnce() -> LockResult<G>,
+    name: &str,
+    mode: &str,
+) -> Result<LockResult<G>, KernelError> {
+    let timeout_ms = TIMEOUT_MS.load(Ordering::Relaxed);
+    if faults::fires(Fault::LockTimeout) {
+        last_error::set(format!("{}: {} lock on the {} not acquired (injected fault)", LOCK_TIMED_OUT, mode, name));
+        return Err(KernelError::TimedOut(LOCK_TIMED_OUT.to_string()));
+    }
+    if timeout_ms == 0 {
+        return Ok(lock());
+    }
+
+    let timeout = Duration::from_millis(timeout_ms);
+    let start = Instant::now();
+    let mut attempts = 0u32;
+    let mut backoff = Duration::from_micros(10);
+    loop {
+        match try_lock() {
+            Ok(guard) => return Ok(Ok(guard)),
+            Err(TryLockError::Poisoned(poisoned)) => return Ok(Err(PoisonError::new(poisoned.into_inner()))),
+            Err(TryLockError::WouldBlock) => {}
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            last_error::set(format!("{}: {} lock on the {} not acquired within {} ms", LOCK_TIMED_OUT, mode, name, timeout_ms));
+            return Err(KernelError::TimedOut(LOCK_TIMED_OUT.to_string()));
+        }
+
+        // Most contention is a scan or a totals read finishing; spin briefly before sleeping
+        attempts += 1;
+        if attempts < SPIN_ATTEMPTS {
+            thread::yield_now();
+        } else {
+            thread::sleep(backoff.min(timeout - elapsed));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
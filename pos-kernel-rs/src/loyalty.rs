@@ -0,0 +1,150 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Loyalty provider integration
+//! ARCHITECTURAL PRINCIPLE: Loyalty programs are user-space business rules. The service layer
+//! invokes a `LoyaltyProvider` around commit; the kernel only records the resulting activity
+//! on the transaction so receipts and exports can show it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::TransactionExport;
+use crate::{read_store_for_api, write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoyaltyAccount {
+    pub account_id: String,
+    pub tier: Option<String>,
+    pub points_balance: i64,
+}
+
+// Loyalty activity recorded on a transaction
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoyaltyActivity {
+    pub account_id: String,
+    pub points_accrued: i64,
+    pub points_redeemed: i64,
+}
+
+pub trait LoyaltyProvider: Send + Sync {
+    // Resolves the transaction's customer token to a loyalty account, if enrolled
+    fn identify(&self, customer_token: &str) -> Result<Option<LoyaltyAccount>, String>;
+
+    // Points the account would earn for the basket
+    fn quote_accrual(&self, account: &LoyaltyAccount, transaction: &TransactionExport) -> Result<i64, String>;
+
+    // Burns points from the account; called before the redemption is recorded
+    fn redeem(&self, account: &LoyaltyAccount, points: i64, transaction: &TransactionExport) -> Result<(), String>;
+
+    // Credits back points burned by `redeem` when the redemption could not be recorded on the
+    // transaction
+    fn reverse_redemption(&self, account: &LoyaltyAccount, points: i64, transaction: &TransactionExport) -> Result<(), String>;
+}
+
+fn identify_customer(provider: &dyn LoyaltyProvider, snapshot: &TransactionExport) -> Result<LoyaltyAccount, KernelError> {
+    let customer = snapshot.customer.as_ref()
+        .ok_or_else(|| KernelError::invalid_state("No customer attached to transaction"))?;
+    provider.identify(&customer.token).map_err(KernelError::Validation)?
+        .ok_or_else(|| KernelError::validation("Customer is not enrolled in loyalty"))
+}
+
+fn transaction_snapshot(handle: u64) -> Result<TransactionExport, KernelError> {
+    let store = read_store_for_api()?;
+    store.transaction_snapshot(handle)
+}
+
+// Quotes accrual for the attached customer and records it on the transaction
+pub fn accrue(provider: &dyn LoyaltyProvider, handle: u64) -> Result<LoyaltyActivity, KernelError> {
+    let snapshot = transaction_snapshot(handle)?;
+    let account = identify_customer(provider, &snapshot)?;
+    let points = provider.quote_accrual(&account, &snapshot).map_err(KernelError::Validation)?;
+
+    let mut store = write_store_for_api()?;
+    store.record_loyalty_activity(handle, &account.account_id, points, 0)
+}
+
+// Redeems points through the provider and records the redemption on the transaction
+pub fn redeem(provider: &dyn LoyaltyProvider, handle: u64, points: i64) -> Result<LoyaltyActivity, KernelError> {
+    if points <= 0 {
+        return Err(KernelError::validation("Points to redeem must be positive"));
+    }
+
+    let snapshot = transaction_snapshot(handle)?;
+    let account = identify_customer(provider, &snapshot)?;
+    if account.points_balance < points {
+        return Err(KernelError::validation("Insufficient loyalty points"));
+    }
+    provider.redeem(&account, points, &snapshot).map_err(KernelError::Validation)?;
+
+    let recorded = write_store_for_api()
+        .and_then(|mut store| store.record_loyalty_activity(handle, &account.account_id, 0, points));
+    if recorded.is_err() {
+        reverse_redemption(provider, &account, points, &snapshot);
+    }
+    recorded
+}
+
+// Burns points through the provider and applies them as a POINTS tender, recording the
+// redemption on the transaction's loyalty activity. The provider is called without the store
+// lock: the tender is checked first, the points are burned, and the tender is then applied
+// under the lock, checked again against the transaction as it is by then. Points burned for a
+// tender that can no longer be applied are credited back.
+pub fn redeem_as_tender(provider: &dyn LoyaltyProvider, handle: u64, points: i64) -> Result<i64, KernelError> {
+    if points <= 0 {
+        return Err(KernelError::validation("Points to redeem must be positive"));
+    }
+
+    let snapshot = transaction_snapshot(handle)?;
+    let account = identify_customer(provider, &snapshot)?;
+    if account.points_balance < points {
+        return Err(KernelError::validation("Insufficient loyalty points"));
+    }
+    // Validate the tender (rate, cap, amount due) before burning points with the provider
+    read_store_for_api()?.points_tender_value(handle, points)?;
+    provider.redeem(&account, points, &snapshot).map_err(KernelError::Validation)?;
+
+    let mut store = match write_store_for_api() {
+        Ok(store) => store,
+        Err(e) => {
+            reverse_redemption(provider, &account, points, &snapshot);
+            return Err(e);
+        },
+    };
+    let value_minor = match store.add_points_tender_legal(handle, points) {
+        Ok((value_minor, committed)) => {
+            if committed {
+                store.record_commit(handle);
+            }
+            value_minor
+        },
+        Err(e) => {
+            drop(store);
+            reverse_redemption(provider, &account, points, &snapshot);
+            return Err(e);
+        },
+    };
+    // The points are burned and tendered; the activity is informational
+    if let Err(e) = store.record_loyalty_activity(handle, &account.account_id, 0, points) {
+        eprintln!("WARNING: Loyalty redemption of {} points not recorded on transaction {}: {}", points, handle, e);
+    }
+    Ok(value_minor)
+}
+
+fn reverse_redemption(provider: &dyn LoyaltyProvider, account: &LoyaltyAccount, points: i64, snapshot: &TransactionExport) {
+    if let Err(e) = provider.reverse_redemption(account, points, snapshot) {
+        eprintln!("CRITICAL: {} loyalty points burned for transaction {} were not credited back: {}", points, snapshot.id, e);
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per-call FFI metrics
+//! Built with the `ffi-metrics` feature only. Every `pk_*` entry point starts with
+//! `instrument!`, which counts the call and records its latency in a fixed histogram when it
+//! returns. Embedded hosts have no metrics endpoint to scrape, so they read the figures with
+//! `pk_get_metrics_json`. Each entry point has its own static slot of atomics, so recording
+//! takes no lock; a slot joins the registry on its first call. Without the feature
+//! `instrument!` expands to nothing.
+
+// Times the enclosing entry point under `name`
+macro_rules! instrument {
+    ($name:literal) => {
+        #[cfg(feature = "ffi-metrics")]
+        let _call = {
+            static SLOT: $crate::metrics::Slot = $crate::metrics::Slot::new($name);
+            $crate::metrics::Call::start(&SLOT)
+        };
+    };
+}
+
+pub(crate) use instrument;
+
+#[cfg(feature = "ffi-metrics")]
+pub(crate) use recorded::{snapshot, Call, Slot};
+
+#[cfg(feature = "ffi-metrics")]
+mod recorded {
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Mutex, PoisonError};
+    use std::time::Instant;
+
+    use serde::Serialize;
+
+    // Upper bounds of the histogram buckets in microseconds; a last bucket takes the rest
+    const BUCKET_BOUNDS_US: [u64; 19] = [
+        1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000,
+        200_000, 500_000, 1_000_000,
+    ];
+    const BUCKETS: usize = BUCKET_BOUNDS_US.len() + 1;
+
+    static SLOTS: Mutex<Vec<&'static Slot>> = Mutex::new(Vec::new());
+
+    pub(crate) struct Slot {
+        name: &'static str,
+        registered: AtomicBool,
+        total_ns: AtomicU64,
+        max_ns: AtomicU64,
+        buckets: [AtomicU64; BUCKETS],
+    }
+
+    impl Slot {
+        pub const fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                registered: AtomicBool::new(false),
+                total_ns: AtomicU64::new(0),
+                max_ns: AtomicU64::new(0),
+                buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            }
+        }
+
+        fn record(&self, elapsed_ns: u64) {
+            let elapsed_us = elapsed_ns / 1_000;
+            let bucket = BUCKET_BOUNDS_US.partition_point(|bound| *bound < elapsed_us);
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            self.total_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+            self.max_ns.fetch_max(elapsed_ns, Ordering::Relaxed);
+        }
+    }
+
+    // Records the call's latency when dropped, however the entry point returns
+    pub(crate) struct Call {
+        slot: &'static Slot,
+        started: Instant,
+    }
+
+    impl Call {
+        pub fn start(slot: &'static Slot) -> Self {
+            if !slot.registered.swap(true, Ordering::Relaxed) {
+                SLOTS.lock().unwrap_or_else(PoisonError::into_inner).push(slot);
+            }
+            Self { slot, started: Instant::now() }
+        }
+    }
+
+    impl Drop for Call {
+        fn drop(&mut self) {
+            self.slot.record(u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX));
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct HistogramBucket {
+        // None for the last bucket, which has no upper bound
+        pub le_us: Option<u64>,
+        pub count: u64,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct CallMetrics {
+        pub name: &'static str,
+        pub calls: u64,
+        pub total_us: u64,
+        pub mean_us: u64,
+        pub max_us: u64,
+        // Upper bounds of the buckets holding the percentiles
+        pub p50_us: Option<u64>,
+        pub p99_us: Option<u64>,
+        pub histogram: Vec<HistogramBucket>,
+    }
+
+    fn percentile(counts: &[u64], calls: u64, per_mille: u64) -> Option<u64> {
+        let rank = (calls * per_mille).div_ceil(1000).max(1);
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKET_BOUNDS_US.get(i).copied();
+            }
+        }
+        None
+    }
+
+    // The entry points called so far, by name. Counters are read one by one while calls may
+    // still be recording, so a snapshot can be a call apart across its fields.
+    pub(crate) fn snapshot() -> Vec<CallMetrics> {
+        let slots = SLOTS.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let mut metrics: Vec<CallMetrics> = slots.into_iter()
+            .map(|slot| {
+                let counts: Vec<u64> = slot.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+                let calls = counts.iter().sum();
+                let total_us = slot.total_ns.load(Ordering::Relaxed) / 1_000;
+                CallMetrics {
+                    name: slot.name,
+                    calls,
+                    total_us,
+                    mean_us: total_us / calls.max(1),
+                    max_us: slot.max_ns.load(Ordering::Relaxed) / 1_000,
+                    p50_us: percentile(&counts, calls, 500),
+                    p99_us: percentile(&counts, calls, 990),
+                    histogram: counts.iter().enumerate()
+                        .map(|(i, count)| HistogramBucket { le_us: BUCKET_BOUNDS_US.get(i).copied(), count: *count })
+                        .collect(),
+                }
+            })
+            .collect();
+        metrics.sort_by_key(|m| m.name);
+        metrics
+    }
+}
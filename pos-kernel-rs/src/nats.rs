@@ -0,0 +1,339 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! NATS command consumer
+//! ARCHITECTURAL PRINCIPLE: Headless integrations send transaction commands over NATS. The
+//! kernel subscribes to a subject, optionally in a queue group so several kernels share the
+//! load, runs each message through the `CommandProcessor` and publishes the reply to the
+//! message's reply subject (a request/reply inbox). The client speaks the NATS text protocol
+//! over TCP directly: CONNECT, SUB, MSG, PUB and PING/PONG, without headers or TLS. A lost
+//! connection is retried every `RECONNECT_DELAY` and resubscribed.
+//! SECURITY: Anyone who can publish to the command subject can run transactions, so the
+//! server should require authentication and restrict the subject. The kernel connects with a
+//! user and password or a token (held in memory only, sent in CONNECT) and needs permission to
+//! subscribe to the command subject and to publish to reply subjects (`_INBOX.>`); only the
+//! integrations should be allowed to publish to the command subject. A message is at most
+//! `MAX_MESSAGE_BYTES`: a larger one is skipped as it arrives, never buffered, and answered
+//! with a validation error.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::command::CommandReply;
+use crate::{read_store_for_api, KernelError};
+
+pub(crate) const CONSUMER_STARTED: &str = "Command consumer already started";
+
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// An idle connection is pinged this often, so a dead server is noticed
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// Commands are small JSON documents; NATS servers allow 1 MB by default
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+// INFO is the longest protocol line and lists the cluster's URLs
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+// Sent in CONNECT
+pub(crate) enum NatsCredentials {
+    UserPassword { user: String, password: String },
+    Token(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConsumerStatus {
+    pub address: Option<String>,
+    pub subject: Option<String>,
+    pub queue_group: Option<String>,
+    pub connected: bool,
+    pub processed: u64,
+    pub duplicates: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+enum Frame {
+    Msg { reply_to: Option<String>, payload: Vec<u8> },
+    // A message over `MAX_MESSAGE_BYTES`, being discarded
+    Oversized { reply_to: Option<String>, size: usize },
+    Ping,
+    Error(String),
+    // INFO, +OK and PONG
+    Other,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    // Bytes of an oversized message (and its CRLF) still to discard
+    discard: usize,
+}
+
+impl Connection {
+    fn open(address: &str) -> Result<Self, String> {
+        let socket = address.to_socket_addrs().map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("{} did not resolve", address))?;
+        let stream = TcpStream::connect_timeout(&socket, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(Self { stream, buffer: Vec::new(), discard: 0 })
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        self.stream.write_all(data).and_then(|_| self.stream.flush()).map_err(|e| e.to_string())
+    }
+
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<(), String> {
+        let mut data = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        data.extend_from_slice(payload);
+        data.extend_from_slice(b"\r\n");
+        self.send(&data)
+    }
+
+    // The next complete frame, or None if none arrived within the read timeout
+    fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        loop {
+            if let Some(frame) = self.parse()? {
+                return Ok(Some(frame));
+            }
+            let mut chunk = [0u8; 8192];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err("Connection closed by server".to_string()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Result<Option<Frame>, String> {
+        if self.discard > 0 {
+            let skipped = self.discard.min(self.buffer.len());
+            self.buffer.drain(..skipped);
+            self.discard -= skipped;
+            if self.discard > 0 {
+                return Ok(None);
+            }
+        }
+        let end = match self.buffer.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None if self.buffer.len() > MAX_LINE_BYTES => return Err("Protocol line too long".to_string()),
+            None => return Ok(None),
+        };
+        let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        let mut words = line.split_whitespace();
+        let frame = match words.next().map(str::to_ascii_uppercase).as_deref() {
+            Some("MSG") => {
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                let args: Vec<&str> = words.collect();
+                let (reply_to, size) = match args[..] {
+                    [_, _, size] => (None, size),
+                    [_, _, reply_to, size] => (Some(reply_to.to_string()), size),
+                    _ => return Err(format!("Malformed MSG: {}", line)),
+                };
+                let size: usize = size.parse().map_err(|_| format!("Malformed MSG: {}", line))?;
+                let start = end + 2;
+                if size > MAX_MESSAGE_BYTES {
+                    self.buffer.drain(..start);
+                    self.discard = size + 2;
+                    return Ok(Some(Frame::Oversized { reply_to, size }));
+                }
+                if self.buffer.len() < start + size + 2 {
+                    return Ok(None);
+                }
+                let payload = self.buffer[start..start + size].to_vec();
+                self.buffer.drain(..start + size + 2);
+                return Ok(Some(Frame::Msg { reply_to, payload }));
+            },
+            Some("PING") => Frame::Ping,
+            Some("-ERR") => Frame::Error(line[4..].trim().trim_matches('\'').to_string()),
+            _ => Frame::Other,
+        };
+        self.buffer.drain(..end + 2);
+        Ok(Some(frame))
+    }
+}
+
+struct Shared {
+    status: Mutex<ConsumerStatus>,
+    stop: Mutex<Option<Arc<AtomicBool>>>,
+    credentials: Mutex<Option<NatsCredentials>>,
+}
+
+// Consumes command messages from a NATS subject on a background thread
+#[derive(Clone)]
+pub(crate) struct NatsConsumer {
+    shared: Arc<Shared>,
+}
+
+impl Default for NatsConsumer {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                status: Mutex::new(ConsumerStatus::default()),
+                stop: Mutex::new(None),
+                credentials: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+fn valid_token(token: &str) -> bool {
+    !token.is_empty() && !token.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+impl NatsConsumer {
+    fn status_mut(&self) -> MutexGuard<'_, ConsumerStatus> {
+        self.shared.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn status(&self) -> ConsumerStatus {
+        self.status_mut().clone()
+    }
+
+    fn record_error(&self, error: String) {
+        eprintln!("WARNING: Command consumer: {}", error);
+        let mut status = self.status_mut();
+        status.connected = false;
+        status.last_error = Some(error);
+        status.last_error_at = Some(Utc::now());
+    }
+
+    // Used from the next connection; None connects without authentication
+    pub fn set_credentials(&self, credentials: Option<NatsCredentials>) {
+        *self.shared.credentials.lock().unwrap_or_else(PoisonError::into_inner) = credentials;
+    }
+
+    // Subscribes to `subject` at the NATS server `address` ("host:port"), in `queue_group` if
+    // given, and keeps reconnecting until stopped
+    pub fn start(&self, address: &str, subject: &str, queue_group: Option<&str>) -> Result<(), KernelError> {
+        if !valid_token(subject) || !queue_group.is_none_or(valid_token) {
+            return Err(KernelError::validation("Subject and queue group must be non-empty and without spaces"));
+        }
+        let mut stop = self.shared.stop.lock().unwrap_or_else(PoisonError::into_inner);
+        if stop.is_some() {
+            return Err(KernelError::invalid_state(CONSUMER_STARTED));
+        }
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let consumer = self.clone();
+        let (address, subject, queue_group) = (address.to_string(), subject.to_string(), queue_group.map(str::to_string));
+        *self.status_mut() = ConsumerStatus {
+            address: Some(address.clone()),
+            subject: Some(subject.clone()),
+            queue_group: queue_group.clone(),
+            ..ConsumerStatus::default()
+        };
+        let thread_flag = Arc::clone(&flag);
+        thread::Builder::new()
+            .name("pk-nats".to_string())
+            .spawn(move || {
+                while !thread_flag.load(Ordering::Relaxed) {
+                    if let Err(e) = consumer.consume(&address, &subject, queue_group.as_deref(), &thread_flag) {
+                        consumer.record_error(e);
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+                consumer.status_mut().connected = false;
+            })
+            .map_err(|e| KernelError::Internal(format!("Failed to start command consumer: {}", e)))?;
+        *stop = Some(flag);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(flag) = self.shared.stop.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // One connection's lifetime; returns Ok only when stopped
+    fn consume(&self, address: &str, subject: &str, queue_group: Option<&str>, stop: &AtomicBool) -> Result<(), String> {
+        let mut connection = Connection::open(address)?;
+        let mut connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "pos-kernel",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol": 1,
+        });
+        match &*self.shared.credentials.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(NatsCredentials::UserPassword { user, password }) => {
+                connect["user"] = user.as_str().into();
+                connect["pass"] = password.as_str().into();
+            },
+            Some(NatsCredentials::Token(token)) => connect["auth_token"] = token.as_str().into(),
+            None => {},
+        }
+        let subscribe = match queue_group {
+            Some(group) => format!("SUB {} {} 1\r\n", subject, group),
+            None => format!("SUB {} 1\r\n", subject),
+        };
+        connection.send(format!("CONNECT {}\r\n{}PING\r\n", connect, subscribe).as_bytes())?;
+        self.status_mut().connected = true;
+
+        let mut last_activity = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            let frame = match connection.next_frame()? {
+                Some(frame) => frame,
+                None => {
+                    if last_activity.elapsed() >= PING_INTERVAL {
+                        connection.send(b"PING\r\n")?;
+                        last_activity = Instant::now();
+                    }
+                    continue;
+                },
+            };
+            last_activity = Instant::now();
+            match frame {
+                Frame::Ping => connection.send(b"PONG\r\n")?,
+                Frame::Error(e) => return Err(format!("Server error: {}", e)),
+                Frame::Other => {},
+                Frame::Oversized { reply_to, size } => {
+                    let error = format!("Message of {} bytes exceeds the maximum of {}", size, MAX_MESSAGE_BYTES);
+                    eprintln!("WARNING: Command consumer: {}", error);
+                    if let Some(reply_to) = reply_to {
+                        let json = serde_json::to_vec(&CommandReply::invalid(String::new(), error)).map_err(|e| e.to_string())?;
+                        connection.publish(&reply_to, &json)?;
+                    }
+                },
+                Frame::Msg { reply_to, payload } => {
+                    let processor = Arc::clone(&read_store_for_api().map_err(|e| e.to_string())?.commands);
+                    let reply = processor.process(&payload);
+                    {
+                        let mut status = self.status_mut();
+                        status.processed += 1;
+                        status.duplicates += reply.duplicate as u64;
+                    }
+                    if let Some(reply_to) = reply_to {
+                        let json = serde_json::to_vec(&reply).map_err(|e| e.to_string())?;
+                        connection.publish(&reply_to, &json)?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
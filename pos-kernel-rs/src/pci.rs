@@ -0,0 +1,296 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Card number (PAN) detection
+//! SECURITY: The kernel never stores a full primary account number. Every text input it keeps
+//! is screened by `screen` as a `TextField`, and the field decides what happens to a PAN in
+//! one place: identifiers (SKUs, attribute keys, customer tokens and loyalty accounts,
+//! operator, terminal and device IDs, codes and references used for lookup) are rejected;
+//! free text (product names and descriptions, reasons and reason codes, attribute values,
+//! customer tiers, authorization and order references, device details) is stored with the PAN
+//! masked to its first six and last four digits.
+//! A PAN candidate is a run of 12 to 19 digits, optionally separated by single spaces or
+//! dashes, with a card-scheme prefix that passes the Luhn check. Longer digit runs (serial
+//! numbers) are not treated as PANs, nor are unseparated runs of 12 to 14 digits carrying a
+//! valid GS1 check digit (UPC-A, EAN-13 and GTIN-14 barcodes).
+
+use std::borrow::Cow;
+
+use crate::{scan, KernelError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TextField {
+    StoreName,
+    Sku,
+    ScanCode,
+    ProductName,
+    ProductDescription,
+    AttributeKey,
+    AttributeValue,
+    Reason,
+    OriginalReceipt,
+    QuoteReference,
+    PickupCode,
+    OrderReference,
+    AuthorizationReference,
+    TenderToken,
+    CustomerToken,
+    CustomerTier,
+    LoyaltyAccount,
+    OperatorId,
+    TerminalId,
+    DeviceName,
+    DeviceDetail,
+    RfidTag,
+}
+
+impl TextField {
+    fn name(self) -> &'static str {
+        match self {
+            Self::StoreName => "Store name",
+            Self::Sku => "SKU",
+            Self::ScanCode => "Scan code",
+            Self::ProductName => "Product name",
+            Self::ProductDescription => "Product description",
+            Self::AttributeKey => "Attribute key",
+            Self::AttributeValue => "Attribute value",
+            Self::Reason => "Reason",
+            Self::OriginalReceipt => "Original receipt",
+            Self::QuoteReference => "Quote reference",
+            Self::PickupCode => "Pickup code",
+            Self::OrderReference => "Order reference",
+            Self::AuthorizationReference => "Authorization reference",
+            Self::TenderToken => "Tender token",
+            Self::CustomerToken => "Customer token",
+            Self::CustomerTier => "Customer tier",
+            Self::LoyaltyAccount => "Loyalty account",
+            Self::OperatorId => "Operator ID",
+            Self::TerminalId => "Terminal ID",
+            Self::DeviceName => "Device name",
+            Self::DeviceDetail => "Device detail",
+            Self::RfidTag => "RFID tag",
+        }
+    }
+
+    // Free text keeps its meaning with the PAN masked; an identifier with a masked PAN would
+    // match the wrong record, or none
+    fn masked(self) -> bool {
+        matches!(
+            self,
+            Self::ProductName
+                | Self::ProductDescription
+                | Self::AttributeValue
+                | Self::Reason
+                | Self::OrderReference
+                | Self::AuthorizationReference
+                | Self::CustomerTier
+                | Self::DeviceDetail
+                | Self::RfidTag
+        )
+    }
+}
+
+// Byte ranges of the PAN candidates in the text
+fn find_pans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || (i > 0 && bytes[i - 1].is_ascii_alphanumeric()) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i;
+        let mut digits = Vec::new();
+        let mut separated = false;
+        let mut j = i;
+        while j < bytes.len() {
+            if bytes[j].is_ascii_digit() {
+                digits.push(bytes[j] - b'0');
+                j += 1;
+                end = j;
+            } else if (bytes[j] == b' ' || bytes[j] == b'-') && j + 1 < bytes.len() && bytes[j + 1].is_ascii_digit() {
+                separated = true;
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        let followed_by_alnum = end < bytes.len() && bytes[end].is_ascii_alphanumeric();
+        let barcode = !separated && is_gs1_code(&text[start..end]);
+        if !followed_by_alnum && !barcode && is_pan(&digits) {
+            found.push((start, end));
+        }
+        i = end.max(i + 1);
+    }
+    found
+}
+
+// Issuers use every length from 12 to 19 digits, so each scheme's prefixes are checked at
+// all of them
+fn is_pan(digits: &[u8]) -> bool {
+    if !(12..=19).contains(&digits.len()) {
+        return false;
+    }
+    let prefix = |n: usize| digits.iter().take(n).fold(0u32, |acc, d| acc * 10 + *d as u32);
+    let scheme = match digits[0] {
+        // Mastercard 2-series
+        2 => (2221..=2720).contains(&prefix(4)),
+        // American Express, Diners Club, JCB
+        3 => matches!(prefix(2), 34 | 36 | 37 | 38 | 39)
+            || (300..=305).contains(&prefix(3))
+            || (3528..=3589).contains(&prefix(4)),
+        // Visa
+        4 => true,
+        // Mastercard, Maestro
+        5 => true,
+        // Discover, UnionPay, Maestro, RuPay
+        6 => true,
+        _ => false,
+    };
+    scheme && luhn_valid(digits)
+}
+
+fn is_gs1_code(digits: &str) -> bool {
+    let (body, check) = digits.split_at(digits.len() - 1);
+    (12..=14).contains(&digits.len()) && scan::check_digit(body) == check.as_bytes()[0] - b'0'
+}
+
+fn luhn_valid(digits: &[u8]) -> bool {
+    let sum: u32 = digits.iter().rev().enumerate()
+        .map(|(i, d)| {
+            let d = *d as u32;
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+pub fn contains_pan(text: &str) -> bool {
+    !find_pans(text).is_empty()
+}
+
+// Masks every PAN in the text to its first six and last four digits; separators are kept
+pub fn mask_pans(text: &str) -> String {
+    let pans = find_pans(text);
+    if pans.is_empty() {
+        return text.to_string();
+    }
+
+    let mut masked = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end) in pans {
+        masked.push_str(&text[last..start]);
+        let candidate = &text[start..end];
+        let digit_count = candidate.bytes().filter(u8::is_ascii_digit).count();
+        let mut seen = 0;
+        for c in candidate.chars() {
+            if c.is_ascii_digit() {
+                masked.push(if seen < 6 || seen >= digit_count - 4 { c } else { '*' });
+                seen += 1;
+            } else {
+                masked.push(c);
+            }
+        }
+        last = end;
+    }
+    masked.push_str(&text[last..]);
+    masked
+}
+
+// The text to store for `field`: masked free text, or an identifier unchanged; an identifier
+// that contains a PAN is rejected
+pub(crate) fn screen(field: TextField, text: &str) -> Result<Cow<'_, str>, KernelError> {
+    if !contains_pan(text) {
+        return Ok(Cow::Borrowed(text));
+    }
+    match field.masked() {
+        true => Ok(Cow::Owned(mask_pans(text))),
+        false => Err(KernelError::validation(format!("{} must not contain a card number", field.name()))),
+    }
+}
+
+pub(crate) fn screen_opt(field: TextField, text: Option<String>) -> Result<Option<String>, KernelError> {
+    text.map(|t| screen(field, &t).map(Cow::into_owned)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VISA: &str = "4111111111111111";
+
+    #[test]
+    fn finds_pans_of_every_scheme_length() {
+        for pan in ["500000000009", "4222222222222", "36227206271667", "378282246310005", VISA, "40000000000000006", "6011000000000000001"] {
+            assert_eq!(find_pans(pan), [(0, pan.len())], "{}", pan);
+        }
+        // Failing the Luhn check, with no card prefix, or too long
+        for digits in ["4111111111111112", "7111111111111114", "41111111111111111111"] {
+            assert!(!contains_pan(digits), "{}", digits);
+        }
+    }
+
+    #[test]
+    fn finds_pans_with_separators() {
+        assert_eq!(mask_pans("4111 1111 1111 1111"), "4111 11** **** 1111");
+        assert_eq!(mask_pans("card 4111-1111-1111-1111."), "card 4111-11**-****-1111.");
+        // Only single separators join a run
+        assert!(!contains_pan("4111  1111  1111  1111"));
+        assert!(!contains_pan("4111--1111--1111--1111"));
+    }
+
+    #[test]
+    fn runs_next_to_letters_or_digits_are_not_pans() {
+        assert!(!contains_pan(&format!("A{}", VISA)));
+        assert!(!contains_pan(&format!("{}B", VISA)));
+        assert!(!contains_pan(&format!("9{}", VISA)));
+        assert_eq!(find_pans(&format!("({}) x", VISA)), [(1, 17)]);
+        assert_eq!(find_pans(&format!("{},{}", VISA, VISA)), [(0, 16), (17, 33)]);
+    }
+
+    #[test]
+    fn barcodes_are_not_pans() {
+        // UPC-A, EAN-13 and GTIN-14 codes with Visa or Mastercard prefixes that pass the Luhn check
+        for barcode in ["549634060061", "4006381333924", "54006381333998"] {
+            assert!(luhn_valid(&barcode.bytes().map(|b| b - b'0').collect::<Vec<_>>()));
+            assert!(!contains_pan(barcode), "{}", barcode);
+        }
+        assert!(!contains_pan("Case of 12: 4006381333924"));
+        // Separated, the same digits read as a card number
+        assert_eq!(mask_pans("4006 3813 3392 4"), "4006 38** *392 4");
+    }
+
+    #[test]
+    fn screens_free_text_and_identifiers() {
+        let reason = format!("Customer said {}", VISA);
+        let masked = screen(TextField::Reason, &reason).expect("free text is masked");
+        assert_eq!(masked, "Customer said 411111******1111");
+        assert!(matches!(screen(TextField::Reason, "Damaged box"), Ok(Cow::Borrowed("Damaged box"))));
+
+        let rejected = screen(TextField::Sku, VISA).expect_err("identifiers are rejected");
+        assert_eq!(rejected.to_string(), KernelError::validation("SKU must not contain a card number").to_string());
+        assert_eq!(screen_opt(TextField::CustomerTier, Some(VISA.to_string())).expect("tier is masked"), Some("411111******1111".to_string()));
+    }
+}
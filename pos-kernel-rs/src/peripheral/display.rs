@@ -0,0 +1,269 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Customer-facing displays
+//! Pole displays and customer screens follow the transaction on their terminal through a
+//! dedicated feed: items added and voided, the subtotal after each change, the amount still
+//! due after each tender and the change due at commit. Updates carry minor units and the
+//! currency's decimal places, never formatted text, so the display decides presentation.
+//! Updates are keyed by the transaction's terminal (the empty ID when none is assigned);
+//! registered sinks receive them as they happen, and FFI hosts poll a bounded queue per
+//! terminal in which the oldest update is dropped when full.
+//!
+//! A second display mirroring the cart starts from a `CartView` of the terminal's current
+//! transaction (items in hierarchy order, running totals and savings), stamped with the
+//! sequence number of the last update it reflects, and then applies only the updates after it.
+//! A gap in sequence numbers means updates were dropped; the display takes a fresh view.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use serde::Serialize;
+
+use crate::{write_store_for_api, KernelError, Line, Transaction};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum DisplayUpdateKind {
+    ItemAdded {
+        line_id: u32,
+        parent_line_id: Option<u32>,
+        sku: Arc<str>,
+        description: Option<String>,
+        qty: i32,
+        unit_minor: i64,
+        total_minor: i64,
+    },
+    ItemVoided { line_id: u32 },
+    Subtotal { subtotal_minor: i64, savings_minor: i64, total_minor: i64 },
+    TotalDue { total_minor: i64, tendered_minor: i64, due_minor: i64 },
+    ChangeDue { total_minor: i64, tendered_minor: i64, change_minor: i64 },
+}
+
+impl DisplayUpdateKind {
+    pub(crate) fn item_added(line: &Line) -> Self {
+        DisplayUpdateKind::ItemAdded {
+            line_id: line.line_id,
+            parent_line_id: line.parent_line_item_id,
+            sku: line.sku.clone(),
+            description: line.product_name.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            total_minor: line.total_minor(),
+        }
+    }
+
+    pub(crate) fn subtotal(tx: &Transaction) -> Self {
+        DisplayUpdateKind::Subtotal { subtotal_minor: tx.lines_total_minor(), savings_minor: tx.savings_minor(), total_minor: tx.total_minor() }
+    }
+
+    pub(crate) fn total_due(tx: &Transaction) -> Self {
+        let (total_minor, tendered_minor) = (tx.total_minor(), tx.tendered_minor);
+        DisplayUpdateKind::TotalDue { total_minor, tendered_minor, due_minor: (total_minor - tendered_minor).max(0) }
+    }
+
+    pub(crate) fn change_due(tx: &Transaction) -> Self {
+        DisplayUpdateKind::ChangeDue { total_minor: tx.total_minor(), tendered_minor: tx.tendered_minor, change_minor: tx.change_minor() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayUpdate {
+    // Per terminal, so a display can tell when updates were dropped
+    pub sequence_number: u64,
+    pub terminal_id: String,
+    pub transaction_handle: u64,
+    pub currency: String,
+    pub decimal_places: u8,
+    #[serde(flatten)]
+    pub kind: DisplayUpdateKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CartItem {
+    pub line_id: u32,
+    pub parent_line_id: Option<u32>,
+    // 0 for a top-level item
+    pub depth: usize,
+    pub sku: Arc<str>,
+    pub description: Option<String>,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub total_minor: i64,
+}
+
+// The cart as a customer display shows it: live items, each followed by its children
+#[derive(Debug, Clone, Serialize)]
+pub struct CartView {
+    // The last update reflected; updates after it may repeat a change already in the view, so
+    // displays apply them by line ID
+    pub sequence_number: u64,
+    pub terminal_id: String,
+    pub transaction_handle: u64,
+    pub currency: String,
+    pub decimal_places: u8,
+    pub items: Vec<CartItem>,
+    pub subtotal_minor: i64,
+    pub savings_minor: i64,
+    pub total_minor: i64,
+    pub tendered_minor: i64,
+    pub due_minor: i64,
+}
+
+impl CartView {
+    pub(crate) fn new(tx: &Transaction, terminal_id: &str, sequence_number: u64) -> Self {
+        let live = |line_id: &u32| tx.find_line(*line_id).filter(|l| l.qty != 0);
+        let items = tx.lines.iter()
+            .filter(|l| l.parent_line_item_id.is_none())
+            .flat_map(|l| std::iter::once(l.line_id).chain(tx.find_all_children(l.line_id)))
+            .filter_map(|line_id| live(&line_id))
+            .map(|line| CartItem {
+                line_id: line.line_id,
+                parent_line_id: line.parent_line_item_id,
+                depth: tx.line_depth(line.line_id).unwrap_or(0),
+                sku: line.sku.clone(),
+                description: line.product_name.clone(),
+                qty: line.qty,
+                unit_minor: line.unit_minor,
+                total_minor: line.total_minor(),
+            })
+            .collect();
+        let (total_minor, tendered_minor) = (tx.total_minor(), tx.tendered_minor);
+        CartView {
+            sequence_number,
+            terminal_id: terminal_id.to_string(),
+            transaction_handle: tx.id,
+            currency: tx.currency.code.clone(),
+            decimal_places: tx.currency.decimal_places,
+            items,
+            subtotal_minor: tx.lines_total_minor(),
+            savings_minor: tx.savings_minor(),
+            total_minor,
+            tendered_minor,
+            due_minor: (total_minor - tendered_minor).max(0),
+        }
+    }
+}
+
+pub trait DisplaySink: Send + Sync {
+    fn on_update(&self, update: &DisplayUpdate);
+}
+
+pub(crate) struct DisplayFeed {
+    state: Mutex<DisplayState>,
+}
+
+struct DisplayState {
+    terminals: HashMap<String, TerminalQueue>,
+    capacity: usize,
+    // Sinks registered for one terminal, or for every terminal
+    sinks: Vec<(Option<String>, Box<dyn DisplaySink>)>,
+}
+
+#[derive(Default)]
+struct TerminalQueue {
+    next_sequence: u64,
+    pending: VecDeque<DisplayUpdate>,
+    // Transaction of the latest update, which the terminal's cart view shows
+    transaction_handle: u64,
+}
+
+impl DisplayFeed {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(DisplayState { terminals: HashMap::new(), capacity, sinks: Vec::new() }),
+        }
+    }
+
+    // The queues stay consistent if a sink panics mid-publish, so poisoning is not fatal
+    fn state(&self) -> MutexGuard<'_, DisplayState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state();
+        state.capacity = capacity;
+        for queue in state.terminals.values_mut() {
+            let excess = queue.pending.len().saturating_sub(capacity);
+            queue.pending.drain(..excess);
+        }
+    }
+
+    // Nothing is built for the feed when no one can read it
+    pub fn is_active(&self) -> bool {
+        let state = self.state();
+        state.capacity > 0 || !state.sinks.is_empty()
+    }
+
+    pub fn publish(&self, tx: &Transaction, kinds: Vec<DisplayUpdateKind>) {
+        let terminal_id = tx.terminal_id.as_deref().unwrap_or("");
+        let mut state = self.state();
+        let state = &mut *state;
+        let queue = state.terminals.entry(terminal_id.to_string()).or_default();
+        queue.transaction_handle = tx.id;
+        for kind in kinds {
+            queue.next_sequence += 1;
+            let update = DisplayUpdate {
+                sequence_number: queue.next_sequence,
+                terminal_id: terminal_id.to_string(),
+                transaction_handle: tx.id,
+                currency: tx.currency.code.clone(),
+                decimal_places: tx.currency.decimal_places,
+                kind,
+            };
+
+            for (_, sink) in state.sinks.iter().filter(|(t, _)| t.as_deref().is_none_or(|t| t == terminal_id)) {
+                sink.on_update(&update);
+            }
+
+            if state.capacity == 0 {
+                continue;
+            }
+            if queue.pending.len() >= state.capacity {
+                queue.pending.pop_front();
+            }
+            queue.pending.push_back(update);
+        }
+    }
+
+    // The terminal's current transaction and the sequence number of its latest update
+    pub fn current(&self, terminal_id: &str) -> Option<(u64, u64)> {
+        self.state().terminals.get(terminal_id)
+            .map(|queue| (queue.transaction_handle, queue.next_sequence))
+    }
+
+    pub fn peek(&self, terminal_id: &str) -> Option<DisplayUpdate> {
+        self.state().terminals.get(terminal_id)?.pending.front().cloned()
+    }
+
+    // Removes the terminal's oldest update if it is still the one with the given sequence number
+    pub fn pop(&self, terminal_id: &str, sequence_number: u64) -> Option<DisplayUpdate> {
+        let mut state = self.state();
+        let queue = state.terminals.get_mut(terminal_id)?;
+        if queue.pending.front()?.sequence_number != sequence_number {
+            return None;
+        }
+        queue.pending.pop_front()
+    }
+}
+
+// Registers a display sink for one terminal, or for every terminal when `terminal_id` is None
+pub fn add_display_sink(terminal_id: Option<&str>, sink: Box<dyn DisplaySink>) -> Result<(), KernelError> {
+    let store = write_store_for_api()?;
+    store.display.state().sinks.push((terminal_id.map(str::to_string), sink));
+    Ok(())
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Cash drawers
+//! The drawer opens when a transaction with cash tendered is committed and on an explicit
+//! no-sale. Drivers implement `DrawerDriver`: `EscPosDrawerKick` pulses the drawer port of an
+//! ESC/POS receipt printer, and OPOS or network drawers are user-space implementations. A
+//! commit never waits for the drawer; the kick is sent by a persistence worker. Every attempt
+//! is journaled with its outcome and published as a `DrawerOpen` event.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+use crate::escpos;
+use crate::{write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrawerOpenReason {
+    // A committed transaction took or paid out cash
+    CashTender,
+    NoSale,
+    // A safe drop, pickup or loan moved cash in or out
+    CashMovement,
+}
+
+pub trait DrawerDriver: Send + Sync {
+    // Identifies the driver in journal entries and events
+    fn name(&self) -> &str;
+
+    // Opens the terminal's drawer; None when the host did not assign a terminal
+    fn open(&self, terminal_id: Option<&str>, reason: DrawerOpenReason) -> Result<(), String>;
+}
+
+// Printer connector the drawer is wired to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawerPin {
+    #[default]
+    Pin2,
+    Pin5,
+}
+
+// Drawer wired to an ESC/POS receipt printer; the kick is written to the printer's port
+pub struct EscPosDrawerKick {
+    port: Mutex<Box<dyn Write + Send>>,
+    pin: DrawerPin,
+    pulse_ms: u16,
+}
+
+impl EscPosDrawerKick {
+    pub fn new(port: Box<dyn Write + Send>) -> Self {
+        Self { port: Mutex::new(port), pin: DrawerPin::default(), pulse_ms: escpos::DEFAULT_DRAWER_PULSE_MS }
+    }
+
+    pub fn with_pin(mut self, pin: DrawerPin) -> Self {
+        self.pin = pin;
+        self
+    }
+
+    // Longer pulses for heavier solenoids; the printer caps the pulse at 510 ms
+    pub fn with_pulse_ms(mut self, pulse_ms: u16) -> Self {
+        self.pulse_ms = pulse_ms;
+        self
+    }
+}
+
+impl DrawerDriver for EscPosDrawerKick {
+    fn name(&self) -> &str {
+        "escpos"
+    }
+
+    fn open(&self, _terminal_id: Option<&str>, _reason: DrawerOpenReason) -> Result<(), String> {
+        let mut port = self.port.lock().unwrap_or_else(PoisonError::into_inner);
+        port.write_all(&escpos::drawer_kick(self.pin, self.pulse_ms))
+            .and_then(|_| port.flush())
+            .map_err(|e| format!("Failed to send drawer kick: {}", e))
+    }
+}
+
+// A drawer opening decided under the store lock, sent to the driver without it
+pub(crate) struct DrawerOpen {
+    pub handle: u64,
+    pub terminal_id: Option<String>,
+    pub operator_id: Option<String>,
+    pub reason: DrawerOpenReason,
+    pub driver: Arc<dyn DrawerDriver>,
+}
+
+impl DrawerOpen {
+    pub fn open(&self) -> Result<(), String> {
+        self.driver.open(self.terminal_id.as_deref(), self.reason)
+    }
+}
+
+// Registers (or replaces) the lane's cash drawer driver
+pub fn set_drawer_driver(driver: Box<dyn DrawerDriver>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.drawer_driver = Some(Arc::from(driver));
+    Ok(())
+}
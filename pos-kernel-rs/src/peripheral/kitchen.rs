@@ -0,0 +1,219 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Kitchen display routing
+//! In food service, lines are fired to the kitchen on request (`pk_fire_lines`) rather than
+//! as they are rung up, so a server can hold a course. Each item is sent complete: the
+//! top-level line with all of its live modifiers. A modifier added to an item already fired
+//! sends the whole item again as a modification, and voiding a fired line sends a void for it
+//! and its modifiers as they were when fired.
+//! A `KitchenRouter` supplied by user space (menu configuration) names the routing categories
+//! (stations such as "grill" or "bar") of each SKU; with a router registered, items with no
+//! category anywhere in the item are marked fired but not sent. Without one, every item is
+//! sent uncategorized. Tickets are published as `KitchenTicket` events and handed to the
+//! registered `KitchenSink`s (WebSocket, queue) by a persistence worker.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{write_store_for_api, KernelError, Line, LineKind, OrderReference, Transaction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum KitchenAction {
+    Fire,
+    // New modifiers on an item already fired; the item is sent complete
+    Modify,
+    Void,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KitchenItem {
+    pub line_id: u32,
+    // Set on a voided modifier, naming the item it belonged to
+    pub parent_line_id: Option<u32>,
+    pub sku: String,
+    pub description: Option<String>,
+    pub qty: i32,
+    pub categories: Vec<String>,
+    pub modifiers: Vec<KitchenItem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KitchenTicket {
+    pub transaction_handle: u64,
+    pub terminal_id: Option<String>,
+    pub order_reference: OrderReference,
+    pub action: KitchenAction,
+    // Every category of every item, so a station can skip tickets that are not its own
+    pub categories: Vec<String>,
+    pub items: Vec<KitchenItem>,
+}
+
+pub trait KitchenRouter: Send + Sync {
+    // Routing categories of an item; empty when the item is not prepared in the kitchen
+    fn categories(&self, sku: &str) -> Vec<String>;
+}
+
+pub trait KitchenSink: Send + Sync {
+    // Identifies the sink in delivery events
+    fn name(&self) -> &str;
+
+    fn send(&self, ticket: &KitchenTicket) -> Result<(), String>;
+}
+
+impl KitchenItem {
+    // The line and its live sale modifiers, in entry order
+    pub(crate) fn build(tx: &Transaction, line: &Line, router: Option<&dyn KitchenRouter>) -> Self {
+        Self {
+            line_id: line.line_id,
+            parent_line_id: line.parent_line_item_id,
+            sku: line.sku.to_string(),
+            description: line.product_name.clone(),
+            qty: line.qty,
+            categories: router.map(|r| r.categories(&line.sku)).unwrap_or_default(),
+            modifiers: tx.lines.iter()
+                .filter(|c| c.parent_line_item_id == Some(line.line_id) && is_kitchen_line(c))
+                .map(|c| Self::build(tx, c, router))
+                .collect(),
+        }
+    }
+
+    fn has_category(&self) -> bool {
+        !self.categories.is_empty() || self.modifiers.iter().any(KitchenItem::has_category)
+    }
+
+    fn line_ids(&self, ids: &mut Vec<u32>) {
+        ids.push(self.line_id);
+        for modifier in &self.modifiers {
+            modifier.line_ids(ids);
+        }
+    }
+
+    fn collect_categories(&self, categories: &mut BTreeSet<String>) {
+        categories.extend(self.categories.iter().cloned());
+        for modifier in &self.modifiers {
+            modifier.collect_categories(categories);
+        }
+    }
+}
+
+impl KitchenTicket {
+    pub(crate) fn new(tx: &Transaction, action: KitchenAction, items: Vec<KitchenItem>) -> Self {
+        let mut categories = BTreeSet::new();
+        for item in &items {
+            item.collect_categories(&mut categories);
+        }
+        Self {
+            transaction_handle: tx.id,
+            terminal_id: tx.terminal_id.clone(),
+            order_reference: tx.order_reference.clone(),
+            action,
+            categories: categories.into_iter().collect(),
+            items,
+        }
+    }
+}
+
+// Voided lines, deposits, adjustments and returns never reach the kitchen
+pub(crate) fn is_kitchen_line(line: &Line) -> bool {
+    line.kind == LineKind::Sale && line.qty != 0
+}
+
+// Fire and modify tickets for every item with unfired lines; returns the tickets to send and
+// the lines now fired, including those of items held back for having no category
+pub(crate) fn fire(tx: &Transaction, router: Option<&dyn KitchenRouter>) -> (Vec<KitchenTicket>, Vec<u32>) {
+    let unfired = tx.lines.iter().filter(|l| is_kitchen_line(l) && !tx.fired_lines.contains(&l.line_id));
+    let roots: BTreeSet<u32> = unfired.filter_map(|l| root_of(tx, l.line_id)).collect();
+
+    let (mut fired, mut modified, mut line_ids) = (Vec::new(), Vec::new(), Vec::new());
+    for root in roots.into_iter().filter_map(|id| tx.find_line(id)).filter(|l| is_kitchen_line(l)) {
+        let item = KitchenItem::build(tx, root, router);
+        let mut ids = Vec::new();
+        item.line_ids(&mut ids);
+        line_ids.extend(ids.into_iter().filter(|id| !tx.fired_lines.contains(id)));
+        if router.is_some() && !item.has_category() {
+            continue;
+        }
+        match tx.fired_lines.contains(&root.line_id) {
+            true => modified.push(item),
+            false => fired.push(item),
+        }
+    }
+
+    let tickets = [(KitchenAction::Fire, fired), (KitchenAction::Modify, modified)].into_iter()
+        .filter(|(_, items)| !items.is_empty())
+        .map(|(action, items)| KitchenTicket::new(tx, action, items))
+        .collect();
+    (tickets, line_ids)
+}
+
+// Void ticket for a line about to be voided with its modifiers, when any of them was sent
+pub(crate) fn void(tx: &Transaction, line_id: u32, children: &[u32], router: Option<&dyn KitchenRouter>) -> Option<KitchenTicket> {
+    let line = tx.find_line(line_id).filter(|l| is_kitchen_line(l))?;
+    if !std::iter::once(&line_id).chain(children).any(|id| tx.fired_lines.contains(id)) {
+        return None;
+    }
+    let item = KitchenItem::build(tx, line, router);
+    if router.is_some() && !item.has_category() {
+        return None;
+    }
+    Some(KitchenTicket::new(tx, KitchenAction::Void, vec![item]))
+}
+
+// Top-level line of the item a line belongs to; None for a broken parent chain
+fn root_of(tx: &Transaction, line_id: u32) -> Option<u32> {
+    let mut line = tx.find_line(line_id)?;
+    for _ in 0..tx.lines.len() {
+        match line.parent_line_item_id {
+            Some(parent_id) => line = tx.find_line(parent_id)?,
+            None => return Some(line.line_id),
+        }
+    }
+    None
+}
+
+// A ticket built under the store lock, sent without it
+pub(crate) struct KitchenDelivery {
+    pub handle: u64,
+    pub ticket: KitchenTicket,
+    pub sinks: Vec<Arc<dyn KitchenSink>>,
+}
+
+impl KitchenDelivery {
+    // Outcome per sink name
+    pub fn deliver(&self) -> Vec<(String, Result<(), String>)> {
+        self.sinks.iter()
+            .map(|s| (s.name().to_string(), s.send(&self.ticket)))
+            .collect()
+    }
+}
+
+// Registers (or replaces) the menu's kitchen router
+pub fn set_kitchen_router(router: Box<dyn KitchenRouter>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.kitchen_router = Some(router);
+    Ok(())
+}
+
+// Registers an additional kitchen display sink
+pub fn add_kitchen_sink(sink: Box<dyn KitchenSink>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.kitchen_sinks.push(Arc::from(sink));
+    Ok(())
+}
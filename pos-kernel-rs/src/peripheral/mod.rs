@@ -0,0 +1,40 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Peripheral extension points
+//! ARCHITECTURAL PRINCIPLE: Devices at the lane (cash drawers and recyclers, displays, printers,
+//! scales) are driven by user-space drivers implementing the traits in this module. The kernel
+//! decides when a device acts, from the transaction's legal state, and journals what the driver
+//! reports; it never talks to hardware itself.
+
+pub mod display;
+pub mod drawer;
+pub mod kitchen;
+pub mod opos;
+pub mod printer;
+pub mod recycler;
+pub mod scale;
+pub mod status;
+
+pub use display::{DisplaySink, DisplayUpdate, DisplayUpdateKind};
+pub use drawer::{DrawerDriver, DrawerOpenReason, DrawerPin, EscPosDrawerKick};
+pub use kitchen::{KitchenAction, KitchenItem, KitchenRouter, KitchenSink, KitchenTicket};
+pub use opos::{UposCashDrawer, UposControl, UposDrawer, UposPrinter, UposReceiptPrinter};
+pub use printer::{PrintError, PrintJobState, PrintJobStatus, PrinterState, PrinterStatus, ReceiptPrinter};
+pub use recycler::{CashRecycler, RecyclerInventory, RecyclerOperation};
+pub use scale::{WeightReading, WeightUnit};
+pub use status::{DeviceHealth, DeviceKind, DeviceStatus, Readiness, TerminalHealth};
@@ -0,0 +1,306 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! OPOS / JavaPOS bridge
+//! Certified device stacks expose UnifiedPOS control objects: a device is opened by logical
+//! name, claimed for exclusive use, enabled, and then driven through its device methods, with
+//! vendor extensions reached through DirectIO. The host wraps its COM (OPOS) or JNI (JavaPOS)
+//! control in the `Upos*` traits; the adapters here run the open/claim/enable lifecycle on
+//! first use, map UnifiedPOS result codes onto the kernel's driver errors, and implement the
+//! kernel's peripheral traits, so a certified printer or drawer is registered like any other.
+//! A device lost to another application or powered off is claimed and enabled again on the
+//! next use. The adapters never touch the store; the kernel drives them without its lock.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::drawer::{DrawerDriver, DrawerOpenReason};
+use super::printer::{PrintError, ReceiptPrinter};
+use super::status::DeviceHealth;
+
+// UnifiedPOS result codes
+pub const OPOS_SUCCESS: i32 = 0;
+pub const OPOS_E_CLOSED: i32 = 101;
+pub const OPOS_E_CLAIMED: i32 = 102;
+pub const OPOS_E_NOTCLAIMED: i32 = 103;
+pub const OPOS_E_NOSERVICE: i32 = 104;
+pub const OPOS_E_DISABLED: i32 = 105;
+pub const OPOS_E_ILLEGAL: i32 = 106;
+pub const OPOS_E_NOHARDWARE: i32 = 107;
+pub const OPOS_E_OFFLINE: i32 = 108;
+pub const OPOS_E_NOEXIST: i32 = 109;
+pub const OPOS_E_EXISTS: i32 = 110;
+pub const OPOS_E_FAILURE: i32 = 111;
+pub const OPOS_E_TIMEOUT: i32 = 112;
+pub const OPOS_E_BUSY: i32 = 113;
+pub const OPOS_E_EXTENDED: i32 = 114;
+
+// POS printer station and extended result codes
+pub const PTR_S_RECEIPT: i32 = 2;
+pub const OPOS_EPTR_COVER_OPEN: i32 = 201;
+pub const OPOS_EPTR_REC_EMPTY: i32 = 203;
+
+pub const DEFAULT_CLAIM_TIMEOUT_MS: i32 = 1000;
+
+// The common control methods every UnifiedPOS device category shares
+pub trait UposControl: Send + Sync {
+    fn open(&self, logical_name: &str) -> i32;
+    fn claim_device(&self, timeout_ms: i32) -> i32;
+    fn set_device_enabled(&self, enabled: bool) -> i32;
+    fn release_device(&self) -> i32;
+    fn close(&self) -> i32;
+    // Vendor-specific command; `data` and `payload` are in/out as in the UnifiedPOS signature
+    fn direct_io(&self, command: i32, data: &mut i32, payload: &mut Vec<u8>) -> i32;
+    // ResultCodeExtended after a method returned OPOS_E_EXTENDED
+    fn result_code_extended(&self) -> i32 {
+        0
+    }
+}
+
+pub trait UposPrinter: UposControl {
+    fn print_normal(&self, station: i32, data: &[u8]) -> i32;
+}
+
+pub trait UposCashDrawer: UposControl {
+    fn open_drawer(&self) -> i32;
+}
+
+// Why a result code is not success, as kernel drivers report errors
+pub fn describe(result_code: i32, extended: i32) -> String {
+    let name = match result_code {
+        OPOS_E_CLOSED => "device closed",
+        OPOS_E_CLAIMED => "device claimed by another application",
+        OPOS_E_NOTCLAIMED => "device not claimed",
+        OPOS_E_NOSERVICE => "no service object",
+        OPOS_E_DISABLED => "device disabled",
+        OPOS_E_ILLEGAL => "illegal request",
+        OPOS_E_NOHARDWARE => "no hardware",
+        OPOS_E_OFFLINE => "device offline",
+        OPOS_E_NOEXIST => "file or object does not exist",
+        OPOS_E_EXISTS => "file or object exists",
+        OPOS_E_FAILURE => "device failure",
+        OPOS_E_TIMEOUT => "timed out",
+        OPOS_E_BUSY => "device busy",
+        OPOS_E_EXTENDED => return format!("UnifiedPOS extended error {}", extended),
+        _ => return format!("UnifiedPOS error {}", result_code),
+    };
+    format!("UnifiedPOS {} ({})", name, result_code)
+}
+
+// Result codes after which the device may recover without the request changing
+fn is_transient(result_code: i32) -> bool {
+    matches!(result_code, OPOS_E_CLAIMED | OPOS_E_OFFLINE | OPOS_E_NOHARDWARE | OPOS_E_TIMEOUT | OPOS_E_BUSY | OPOS_E_EXTENDED)
+}
+
+// Result codes meaning the claim or enable was lost, so the lifecycle is run again
+fn lost_session(result_code: i32) -> bool {
+    matches!(result_code, OPOS_E_CLOSED | OPOS_E_NOTCLAIMED | OPOS_E_DISABLED)
+}
+
+#[derive(Default)]
+struct SessionState {
+    opened: bool,
+    claimed: bool,
+    enabled: bool,
+    last_result: i32,
+}
+
+// The open/claim/enable lifecycle of one control
+pub struct UposSession<C: UposControl + ?Sized> {
+    logical_name: String,
+    claim_timeout_ms: i32,
+    state: Mutex<SessionState>,
+    control: Box<C>,
+}
+
+impl<C: UposControl + ?Sized> UposSession<C> {
+    pub fn new(logical_name: &str, control: Box<C>) -> Self {
+        Self {
+            logical_name: logical_name.to_string(),
+            claim_timeout_ms: DEFAULT_CLAIM_TIMEOUT_MS,
+            state: Mutex::new(SessionState::default()),
+            control,
+        }
+    }
+
+    pub fn with_claim_timeout_ms(mut self, timeout_ms: i32) -> Self {
+        self.claim_timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn logical_name(&self) -> &str {
+        &self.logical_name
+    }
+
+    // The worker thread holding the lock while it waits on the device may have panicked; the
+    // flags are re-established on the next call either way
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Opens, claims and enables the device as far as it is not already
+    fn ensure_ready(&self, state: &mut SessionState) -> i32 {
+        if !state.opened {
+            let code = self.control.open(&self.logical_name);
+            if code != OPOS_SUCCESS {
+                return code;
+            }
+            state.opened = true;
+        }
+        if !state.claimed {
+            let code = self.control.claim_device(self.claim_timeout_ms);
+            if code != OPOS_SUCCESS {
+                return code;
+            }
+            state.claimed = true;
+        }
+        if !state.enabled {
+            let code = self.control.set_device_enabled(true);
+            if code != OPOS_SUCCESS {
+                return code;
+            }
+            state.enabled = true;
+        }
+        OPOS_SUCCESS
+    }
+
+    // Runs a device method on the ready device. A lost claim or enable is re-established and
+    // the method tried once more.
+    pub fn call(&self, mut method: impl FnMut(&C) -> i32) -> Result<(), (i32, i32)> {
+        let mut state = self.state();
+        let mut code = self.ensure_ready(&mut state);
+        if code == OPOS_SUCCESS {
+            code = method(&self.control);
+            if lost_session(code) {
+                state.claimed = code == OPOS_E_DISABLED;
+                state.opened = code != OPOS_E_CLOSED;
+                state.enabled = false;
+                code = self.ensure_ready(&mut state);
+                if code == OPOS_SUCCESS {
+                    code = method(&self.control);
+                }
+            }
+        }
+        state.last_result = code;
+        match code {
+            OPOS_SUCCESS => Ok(()),
+            code => Err((code, self.control.result_code_extended())),
+        }
+    }
+
+    // DIRECTIO: Passes a vendor command through to the device unchanged
+    pub fn direct_io(&self, command: i32, data: &mut i32, payload: &mut Vec<u8>) -> Result<(), String> {
+        self.call(|control| control.direct_io(command, data, payload))
+            .map_err(|(code, extended)| describe(code, extended))
+    }
+
+    // Health as of the last call, for `report_peripheral_status`
+    pub fn health(&self) -> DeviceHealth {
+        match self.state().last_result {
+            OPOS_SUCCESS => DeviceHealth::Ok,
+            code if is_transient(code) => DeviceHealth::Degraded,
+            _ => DeviceHealth::Offline,
+        }
+    }
+
+    // Disables, releases and closes the device so another application can claim it
+    pub fn release(&self) {
+        let mut state = self.state();
+        if state.enabled {
+            self.control.set_device_enabled(false);
+        }
+        if state.claimed {
+            self.control.release_device();
+        }
+        if state.opened {
+            self.control.close();
+        }
+        *state = SessionState::default();
+    }
+}
+
+// How rendered ESC/POS receipts reach the printer's service object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UposPrintMode {
+    // PrintNormal on the receipt station; the service object must pass ESC/POS through
+    PrintNormal,
+    // A vendor DirectIO command that writes raw bytes
+    DirectIo(i32),
+}
+
+pub struct UposReceiptPrinter {
+    session: UposSession<dyn UposPrinter>,
+    mode: UposPrintMode,
+}
+
+impl UposReceiptPrinter {
+    pub fn new(logical_name: &str, control: Box<dyn UposPrinter>) -> Self {
+        Self { session: UposSession::new(logical_name, control), mode: UposPrintMode::PrintNormal }
+    }
+
+    pub fn with_mode(mut self, mode: UposPrintMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn session(&self) -> &UposSession<dyn UposPrinter> {
+        &self.session
+    }
+}
+
+impl ReceiptPrinter for UposReceiptPrinter {
+    fn name(&self) -> &str {
+        self.session.logical_name()
+    }
+
+    fn print(&self, data: &[u8]) -> Result<(), PrintError> {
+        let printed = match self.mode {
+            UposPrintMode::PrintNormal => self.session.call(|printer| printer.print_normal(PTR_S_RECEIPT, data)),
+            UposPrintMode::DirectIo(command) => self.session.call(|printer| printer.direct_io(command, &mut 0, &mut data.to_vec())),
+        };
+        printed.map_err(|(code, extended)| {
+            let error = describe(code, extended);
+            // Paper out and cover open clear once an operator intervenes
+            let recoverable = is_transient(code) || matches!(extended, OPOS_EPTR_COVER_OPEN | OPOS_EPTR_REC_EMPTY);
+            if recoverable { PrintError::Transient(error) } else { PrintError::Failed(error) }
+        })
+    }
+}
+
+pub struct UposDrawer {
+    session: UposSession<dyn UposCashDrawer>,
+}
+
+impl UposDrawer {
+    pub fn new(logical_name: &str, control: Box<dyn UposCashDrawer>) -> Self {
+        Self { session: UposSession::new(logical_name, control) }
+    }
+
+    pub fn session(&self) -> &UposSession<dyn UposCashDrawer> {
+        &self.session
+    }
+}
+
+impl DrawerDriver for UposDrawer {
+    fn name(&self) -> &str {
+        self.session.logical_name()
+    }
+
+    fn open(&self, _terminal_id: Option<&str>, _reason: DrawerOpenReason) -> Result<(), String> {
+        self.session.call(|drawer| drawer.open_drawer())
+            .map_err(|(code, extended)| describe(code, extended))
+    }
+}
@@ -0,0 +1,296 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Receipt printing
+//! Committed receipts are rendered to ESC/POS and spooled per printer, so a jammed or offline
+//! printer holds its receipts instead of losing them. Jobs print in order; a transient failure
+//! (`PrintError::Transient`: paper out, cover open, device offline) leaves the job at the head
+//! of its printer's queue, and it is retried the next time the spooler runs (a new receipt for
+//! the printer, or the host calling `pk_retry_print_jobs` once the jam is cleared) until the
+//! retry limit marks it failed. Receipts can be reprinted by receipt number (the transaction
+//! ID) with a copy marker. Printing runs on a persistence worker without the store lock; each
+//! attempt is published as a `ReceiptPrint` event.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::escpos::{self, EscPosOptions};
+use crate::receipt::ReceiptDocument;
+use crate::{write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrintError {
+    // The printer may recover on its own or once an operator intervenes; the job is retried
+    Transient(String),
+    Failed(String),
+}
+
+pub trait ReceiptPrinter: Send + Sync {
+    // Identifies the printer in events and status reports
+    fn name(&self) -> &str;
+
+    // Writes one rendered receipt to the device
+    fn print(&self, data: &[u8]) -> Result<(), PrintError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PrintJobState {
+    Queued,
+    Printed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrintJobStatus {
+    pub job_id: u64,
+    pub receipt_number: u64,
+    pub reprint: bool,
+    pub state: PrintJobState,
+    pub attempts: u32,
+    pub queued_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PrinterState {
+    Ready,
+    Printing,
+    // The job at the head of the queue failed and waits to be retried
+    Stalled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrinterStatus {
+    pub name: String,
+    pub terminal_id: Option<String>,
+    pub state: PrinterState,
+    pub last_error: Option<String>,
+    pub queued: Vec<PrintJobStatus>,
+    // Most recent printed or failed jobs, newest last
+    pub finished: Vec<PrintJobStatus>,
+}
+
+// One attempt at a job, as published in events
+#[derive(Debug, Clone)]
+pub(crate) struct PrintOutcome {
+    pub printer: String,
+    pub job: PrintJobStatus,
+}
+
+struct PrintJob {
+    status: PrintJobStatus,
+    data: Arc<[u8]>,
+}
+
+struct SpooledPrinter {
+    printer: Arc<dyn ReceiptPrinter>,
+    // Printers registered for one terminal, or for every terminal
+    terminal_id: Option<String>,
+    options: EscPosOptions,
+    queue: VecDeque<PrintJob>,
+    finished: VecDeque<PrintJobStatus>,
+    printing: bool,
+    last_error: Option<String>,
+}
+
+impl SpooledPrinter {
+    fn serves(&self, terminal_id: Option<&str>) -> bool {
+        self.terminal_id.is_none() || self.terminal_id.as_deref() == terminal_id
+    }
+
+    fn state(&self) -> PrinterState {
+        match self.queue.front() {
+            _ if self.printing => PrinterState::Printing,
+            Some(job) if job.status.attempts > 0 => PrinterState::Stalled,
+            _ => PrinterState::Ready,
+        }
+    }
+}
+
+struct SpoolState {
+    printers: Vec<SpooledPrinter>,
+    next_job_id: u64,
+    retry_limit: u32,
+    history: usize,
+}
+
+pub(crate) struct Spooler {
+    state: Mutex<SpoolState>,
+}
+
+impl Spooler {
+    pub fn new(retry_limit: u32, history: usize) -> Self {
+        Self {
+            state: Mutex::new(SpoolState { printers: Vec::new(), next_job_id: 0, retry_limit, history }),
+        }
+    }
+
+    // Queues stay consistent if a printer panics mid-job, so poisoning is not fatal
+    fn state(&self) -> MutexGuard<'_, SpoolState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_limits(&self, retry_limit: u32, history: usize) {
+        let mut state = self.state();
+        state.retry_limit = retry_limit;
+        state.history = history;
+        for printer in &mut state.printers {
+            let excess = printer.finished.len().saturating_sub(history);
+            printer.finished.drain(..excess);
+        }
+    }
+
+    pub fn has_printers(&self) -> bool {
+        !self.state().printers.is_empty()
+    }
+
+    // Renders the receipt for every printer serving the terminal and queues it; returns the
+    // names of the printers it was queued on
+    pub fn enqueue(&self, receipt: &ReceiptDocument, terminal_id: Option<&str>, reprint: bool) -> Result<Vec<String>, String> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let mut queued = Vec::new();
+        for printer in state.printers.iter_mut().filter(|p| p.serves(terminal_id)) {
+            let mut options = printer.options.clone();
+            options.copy = reprint;
+            if options.qr_payload.is_none() {
+                options.qr_payload = receipt.fiscal_qr.clone();
+            }
+
+            let data = escpos::render(receipt, &options)?.into();
+            state.next_job_id += 1;
+            printer.queue.push_back(PrintJob {
+                status: PrintJobStatus {
+                    job_id: state.next_job_id,
+                    receipt_number: receipt.header.transaction_id,
+                    reprint,
+                    state: PrintJobState::Queued,
+                    attempts: 0,
+                    queued_at: Utc::now(),
+                    finished_at: None,
+                    last_error: None,
+                },
+                data,
+            });
+            queued.push(printer.printer.name().to_string());
+        }
+        Ok(queued)
+    }
+
+    // Prints queued jobs until every queue is empty or stalled; a printer whose job fails is
+    // tried once per run. Jobs are printed without the spool lock, one at a time per printer.
+    pub fn run(&self) -> Vec<PrintOutcome> {
+        let mut outcomes = Vec::new();
+        let mut stalled = Vec::new();
+        loop {
+            let claimed = {
+                let mut state = self.state();
+                state.printers.iter_mut().enumerate()
+                    .filter(|(index, p)| !p.printing && !stalled.contains(index))
+                    .find_map(|(index, p)| {
+                        let data = Arc::clone(&p.queue.front()?.data);
+                        p.printing = true;
+                        Some((index, Arc::clone(&p.printer), data))
+                    })
+            };
+            let (index, printer, data) = match claimed {
+                Some(claimed) => claimed,
+                None => break,
+            };
+
+            // A panicking driver must not leave its printer marked as printing
+            let result = panic::catch_unwind(AssertUnwindSafe(|| printer.print(&data)))
+                .unwrap_or_else(|_| Err(PrintError::Failed("Printer driver panicked".to_string())));
+            let outcome = match self.state().finish(index, result) {
+                Some(outcome) => outcome,
+                None => continue,
+            };
+            if outcome.job.state == PrintJobState::Queued {
+                stalled.push(index);
+            }
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    pub fn status(&self) -> Vec<PrinterStatus> {
+        self.state().printers.iter()
+            .map(|p| PrinterStatus {
+                name: p.printer.name().to_string(),
+                terminal_id: p.terminal_id.clone(),
+                state: p.state(),
+                last_error: p.last_error.clone(),
+                queued: p.queue.iter().map(|j| j.status.clone()).collect(),
+                finished: p.finished.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+impl SpoolState {
+    // Records the attempt on the job at the head of the printer's queue
+    fn finish(&mut self, index: usize, result: Result<(), PrintError>) -> Option<PrintOutcome> {
+        let (retry_limit, history) = (self.retry_limit, self.history);
+        let printer = &mut self.printers[index];
+        printer.printing = false;
+        let name = printer.printer.name().to_string();
+        let job = &mut printer.queue.front_mut()?.status;
+
+        job.attempts += 1;
+        let error = match result {
+            Ok(()) => None,
+            Err(PrintError::Transient(e)) if job.attempts < retry_limit => {
+                job.last_error = Some(e.clone());
+                printer.last_error = Some(e);
+                return Some(PrintOutcome { printer: name, job: job.clone() });
+            },
+            Err(PrintError::Transient(e) | PrintError::Failed(e)) => Some(e),
+        };
+        job.state = if error.is_some() { PrintJobState::Failed } else { PrintJobState::Printed };
+        job.finished_at = Some(Utc::now());
+        job.last_error = error.clone();
+        printer.last_error = error;
+
+        let job = job.clone();
+        printer.queue.pop_front();
+        printer.finished.push_back(job.clone());
+        let excess = printer.finished.len().saturating_sub(history);
+        printer.finished.drain(..excess);
+        Some(PrintOutcome { printer: name, job })
+    }
+}
+
+// Registers a receipt printer for one terminal, or for every terminal when `terminal_id` is
+// None; receipts are rendered for it with `options`
+pub fn add_receipt_printer(terminal_id: Option<&str>, printer: Box<dyn ReceiptPrinter>, options: EscPosOptions) -> Result<(), KernelError> {
+    let store = write_store_for_api()?;
+    store.spooler.state().printers.push(SpooledPrinter {
+        printer: Arc::from(printer),
+        terminal_id: terminal_id.map(str::to_string),
+        options,
+        queue: VecDeque::new(),
+        finished: VecDeque::new(),
+        printing: false,
+        last_error: None,
+    });
+    Ok(())
+}
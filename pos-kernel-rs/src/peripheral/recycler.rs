@@ -0,0 +1,87 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Cash recyclers
+//! A recycler takes the place of the cash drawer on its terminal: it counts the cash a
+//! customer inserts, pays out change, and reports the notes and coins it holds. Drivers
+//! implement `CashRecycler`. A deposit is counted by the device before the kernel records it
+//! as a cash tender; change due on a transaction committed on the terminal is dispensed by a
+//! persistence worker, and the drawer is not kicked. Deposits and dispenses are journaled as
+//! denomination deltas and inventory reads as counts, each with the terminal's expected drawer
+//! cash, so the device's contents reconcile against the drawer session.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cash::DenominationCount;
+use crate::{write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecyclerOperation {
+    Deposit,
+    Dispense,
+    Inventory,
+}
+
+pub trait CashRecycler: Send + Sync {
+    // Identifies the recycler in journal entries and events
+    fn name(&self) -> &str;
+
+    // Takes in cash toward `due_minor` and returns what was counted; the customer may insert
+    // more than is due
+    fn accept_deposit(&self, terminal_id: &str, due_minor: i64) -> Result<Vec<DenominationCount>, String>;
+
+    // Pays out `amount_minor` and returns what was dispensed, which is less when the device
+    // runs short of a denomination
+    fn dispense(&self, terminal_id: &str, amount_minor: i64) -> Result<Vec<DenominationCount>, String>;
+
+    // The notes and coins currently held
+    fn inventory(&self, terminal_id: &str) -> Result<Vec<DenominationCount>, String>;
+}
+
+// Registers (or replaces) the recycler serving a terminal
+pub fn set_cash_recycler(terminal_id: &str, recycler: Box<dyn CashRecycler>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.recyclers.insert(terminal_id.to_string(), Arc::from(recycler));
+    Ok(())
+}
+
+// Change due on a committed transaction, decided under the store lock and dispensed without it
+pub(crate) struct ChangeDispense {
+    pub handle: u64,
+    pub terminal_id: String,
+    pub amount_minor: i64,
+    pub recycler: Arc<dyn CashRecycler>,
+}
+
+impl ChangeDispense {
+    pub fn dispense(&self) -> Result<Vec<DenominationCount>, String> {
+        self.recycler.dispense(&self.terminal_id, self.amount_minor)
+    }
+}
+
+// A recycler's contents against the terminal's drawer session; the variance is held less
+// expected and absent without an open session
+#[derive(Debug, Clone, Serialize)]
+pub struct RecyclerInventory {
+    pub recycler: String,
+    pub inventory: Vec<DenominationCount>,
+    pub inventory_minor: i64,
+    pub expected_minor: Option<i64>,
+    pub variance_minor: Option<i64>,
+}
@@ -0,0 +1,70 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Scale readings
+//! REGULATORY COMPLIANCE: Weights & Measures audits need the raw reading behind every weighed
+//! sale. The host captures the scale's gross weight and the tare, in thousandths of the unit
+//! the scale reports, and the kernel records them with the net weight: on the line priced
+//! from them, or against the transaction alone when the host weighs before adding the line.
+//! Lines carry whole quantities, so a weighed line is rung up as one item whose price the host
+//! computed from the net weight; the reading is what an auditor checks that price against.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeightUnit {
+    Kilogram,
+    Gram,
+    Pound,
+    Ounce,
+}
+
+impl WeightUnit {
+    // Unit symbols as scales report them
+    pub fn parse(symbol: &str) -> Option<Self> {
+        match symbol.to_ascii_lowercase().as_str() {
+            "kg" => Some(WeightUnit::Kilogram),
+            "g" => Some(WeightUnit::Gram),
+            "lb" => Some(WeightUnit::Pound),
+            "oz" => Some(WeightUnit::Ounce),
+            _ => None,
+        }
+    }
+}
+
+// Weights are in thousandths of `unit`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightReading {
+    pub gross_milli: i64,
+    pub tare_milli: i64,
+    pub net_milli: i64,
+    pub unit: WeightUnit,
+    pub captured_at: DateTime<Utc>,
+}
+
+impl WeightReading {
+    pub(crate) fn new(gross_milli: i64, tare_milli: i64, unit: WeightUnit) -> Result<Self, String> {
+        if gross_milli <= 0 {
+            return Err("Gross weight must be positive".to_string());
+        }
+        if tare_milli < 0 || tare_milli >= gross_milli {
+            return Err("Tare must be at least zero and less than the gross weight".to_string());
+        }
+        Ok(Self { gross_milli, tare_milli, net_milli: gross_milli - tare_milli, unit, captured_at: Utc::now() })
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Peripheral status registry
+//! Drivers report the condition of their devices as they observe it (paper low, drawer left
+//! open, scanner disconnected); the kernel keeps the latest report per device and terminal,
+//! and publishes a `PeripheralStatusChanged` event when a device's health changes. A terminal's
+//! health is the worst of its devices, together with what the kernel sees itself: a receipt
+//! printer whose spooled jobs are failing is degraded whatever its driver last reported.
+//! Reports older than `peripheral_status_ttl_secs` count as offline, so a driver that stops
+//! reporting is noticed. The registry has its own lock so drivers report without waiting on
+//! sales.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::printer::{PrinterState, PrinterStatus};
+use crate::{read_store_for_api, KernelError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceKind {
+    Printer = 0,
+    Drawer = 1,
+    Scanner = 2,
+    Scale = 3,
+    Display = 4,
+    Recycler = 5,
+    PaymentTerminal = 6,
+    Other = 7,
+}
+
+impl DeviceKind {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(DeviceKind::Printer),
+            1 => Some(DeviceKind::Drawer),
+            2 => Some(DeviceKind::Scanner),
+            3 => Some(DeviceKind::Scale),
+            4 => Some(DeviceKind::Display),
+            5 => Some(DeviceKind::Recycler),
+            6 => Some(DeviceKind::PaymentTerminal),
+            7 => Some(DeviceKind::Other),
+            _ => None,
+        }
+    }
+}
+
+// Ordered from best to worst, so a terminal's health is the maximum of its devices'
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DeviceHealth {
+    #[default]
+    Ok = 0,
+    // Working with a problem that needs attention, such as low paper
+    Degraded = 1,
+    Offline = 2,
+}
+
+impl DeviceHealth {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(DeviceHealth::Ok),
+            1 => Some(DeviceHealth::Degraded),
+            2 => Some(DeviceHealth::Offline),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub device: String,
+    pub kind: DeviceKind,
+    pub health: DeviceHealth,
+    pub detail: Option<String>,
+    // None for a status the kernel derives itself
+    pub reported_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalHealth {
+    pub terminal_id: String,
+    pub health: DeviceHealth,
+    pub devices: Vec<DeviceStatus>,
+}
+
+// Overall health for readiness checks, with the terminals that are not Ok
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub health: DeviceHealth,
+    pub degraded: Vec<TerminalHealth>,
+}
+
+#[derive(Default)]
+pub(crate) struct PeripheralRegistry {
+    // By terminal, then by device name
+    reports: Mutex<BTreeMap<String, BTreeMap<String, DeviceStatus>>>,
+}
+
+impl PeripheralRegistry {
+    fn reports(&self) -> MutexGuard<'_, BTreeMap<String, BTreeMap<String, DeviceStatus>>> {
+        self.reports.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Keeps the report; returns the device's previous health when it changed, or None for an
+    // unchanged health. A first report counts as a change from Ok.
+    pub fn report(&self, terminal_id: &str, status: DeviceStatus) -> Option<DeviceHealth> {
+        let mut reports = self.reports();
+        let devices = reports.entry(terminal_id.to_string()).or_default();
+        let previous = devices.get(&status.device).map_or(DeviceHealth::Ok, |s| s.health);
+        let health = status.health;
+        devices.insert(status.device.clone(), status);
+        (previous != health).then_some(previous)
+    }
+
+    pub fn terminals(&self) -> Vec<String> {
+        self.reports().keys().cloned().collect()
+    }
+
+    // The terminal's reported devices, aged by the TTL (0 never ages them), and the printers
+    // spooling for it
+    pub fn terminal_health(&self, terminal_id: &str, printers: &[PrinterStatus], ttl_secs: u64, now: DateTime<Utc>) -> TerminalHealth {
+        let mut devices: Vec<DeviceStatus> = self.reports().get(terminal_id)
+            .map(|devices| devices.values().cloned().collect())
+            .unwrap_or_default();
+        let ttl = Duration::seconds(i64::try_from(ttl_secs).unwrap_or(i64::MAX).min(i64::MAX / 1000));
+        for status in devices.iter_mut() {
+            if ttl_secs > 0 && status.reported_at.is_some_and(|at| now - at > ttl) {
+                status.health = DeviceHealth::Offline;
+                status.detail = Some("No status reported within the TTL".to_string());
+            }
+        }
+
+        let serving = printers.iter()
+            .filter(|p| p.terminal_id.is_none() || p.terminal_id.as_deref() == Some(terminal_id))
+            .filter(|p| p.state == PrinterState::Stalled);
+        for printer in serving {
+            match devices.iter_mut().find(|d| d.device == printer.name) {
+                Some(status) if status.health >= DeviceHealth::Degraded => {},
+                Some(status) => {
+                    status.health = DeviceHealth::Degraded;
+                    status.detail = printer.last_error.clone();
+                },
+                None => devices.push(DeviceStatus {
+                    device: printer.name.clone(),
+                    kind: DeviceKind::Printer,
+                    health: DeviceHealth::Degraded,
+                    detail: printer.last_error.clone(),
+                    reported_at: None,
+                }),
+            }
+        }
+
+        TerminalHealth {
+            terminal_id: terminal_id.to_string(),
+            health: devices.iter().map(|d| d.health).max().unwrap_or_default(),
+            devices,
+        }
+    }
+}
+
+// Records a driver's report; drivers may call this from their own threads
+pub fn report_peripheral_status(terminal_id: &str, device: &str, kind: DeviceKind, health: DeviceHealth, detail: Option<&str>) -> Result<(), KernelError> {
+    read_store_for_api()?.report_peripheral_status(terminal_id, device, kind, health, detail)
+}
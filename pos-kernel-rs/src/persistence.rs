@@ -0,0 +1,66 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Settled-transaction persistence
+//! Archived transactions evicted from memory under the memory budget are written to a backend
+//! and reloaded from it when accessed. The file backend keeps one JSON document per
+//! transaction, holding the kernel's full transaction state rather than the export.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Transaction;
+
+pub(crate) trait TransactionBackend: Send + Sync {
+    fn save(&self, tx: &Transaction) -> Result<(), String>;
+    fn load(&self, handle: u64) -> Result<Transaction, String>;
+}
+
+pub(crate) struct FileBackend {
+    dir: PathBuf,
+}
+
+impl FileBackend {
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create transaction directory {}: {}", dir.display(), e))?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    fn path(&self, handle: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", handle))
+    }
+}
+
+impl TransactionBackend for FileBackend {
+    // Written to a temporary file and renamed, so a crash never leaves a partial document
+    fn save(&self, tx: &Transaction) -> Result<(), String> {
+        let content = serde_json::to_vec(tx)
+            .map_err(|e| format!("Failed to serialize transaction {}: {}", tx.id, e))?;
+        let path = self.path(tx.id);
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| format!("Failed to write transaction {}: {}", tx.id, e))
+    }
+
+    fn load(&self, handle: u64) -> Result<Transaction, String> {
+        let content = fs::read(self.path(handle))
+            .map_err(|e| format!("Failed to read transaction {}: {}", handle, e))?;
+        serde_json::from_slice(&content)
+            .map_err(|e| format!("Failed to parse transaction {}: {}", handle, e))
+    }
+}
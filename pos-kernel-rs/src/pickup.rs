@@ -0,0 +1,99 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Online orders picked up in store
+//! OMNICHANNEL: An order placed and paid online is imported as an open sale carrying its lines
+//! and the captured online payments, held under its pickup code until the customer arrives.
+//! While it waits, the order belongs to no terminal and does not count against the store's cap
+//! on open sales. At pickup the lane recalls it by code, checks the goods against the lines,
+//! adjusts for anything substituted or out of stock, tenders any balance and finalizes it like
+//! any other sale.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// The order as sent by the e-commerce platform
+#[derive(Debug, Clone, Deserialize)]
+pub struct OnlineOrder {
+    pub pickup_code: String,
+    pub store: String,
+    pub currency: String,
+    #[serde(default = "default_decimal_places")]
+    pub decimal_places: u8,
+    #[serde(default)]
+    pub external_order_id: Option<String>,
+    // Recorded as the order reference channel; "online" when not given
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub customer_token: Option<String>,
+    pub lines: Vec<OnlineOrderLine>,
+    #[serde(default)]
+    pub payments: Vec<OnlinePayment>,
+}
+
+fn default_decimal_places() -> u8 {
+    2
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OnlineOrderLine {
+    pub sku: String,
+    pub qty: i32,
+    pub unit_minor: i64,
+}
+
+// A payment captured by the online payment gateway; recorded as a captured card tender
+#[derive(Debug, Clone, Deserialize)]
+pub struct OnlinePayment {
+    pub amount_minor: i64,
+    // Gateway capture reference
+    pub reference: String,
+}
+
+// Kept on the imported transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PickupOrder {
+    pub code: String,
+    pub imported_at: DateTime<Utc>,
+    // Set when a lane first recalls the order
+    pub recalled_at: Option<DateTime<Utc>>,
+}
+
+impl PickupOrder {
+    // Waiting for the customer, not yet at a lane
+    pub(crate) fn is_waiting(&self) -> bool {
+        self.recalled_at.is_none()
+    }
+}
+
+pub(crate) const PICKUP_NOT_FOUND: &str = "Pickup order not found";
+pub(crate) const PICKUP_CODE_IN_USE: &str = "Pickup code already in use";
+pub(crate) const DEFAULT_CHANNEL: &str = "online";
+
+pub(crate) fn parse(json: &str) -> Result<OnlineOrder, String> {
+    let order: OnlineOrder = serde_json::from_str(json)
+        .map_err(|e| format!("Invalid online order: {}", e))?;
+
+    if order.lines.is_empty() {
+        return Err("Online order has no lines".to_string());
+    }
+
+    if order.payments.iter().any(|p| p.amount_minor <= 0) {
+        return Err("Online payment must be positive".to_string());
+    }
+    Ok(order)
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Personal data tagging
+//! REGULATORY COMPLIANCE: Fields that may hold personal data are tagged with a `PiiCategory`.
+//! Output built from the journal and log output pass tagged values through `protect`, which
+//! applies the configured `PiiMode` per category: kept as is, replaced with a keyed hash
+//! (stable, so entries can still be correlated), or redacted. Hashes use HMAC-SHA256 under the
+//! kernel signing key when one is set, and plain SHA-256 otherwise.
+//! The journal (WAL) itself keeps the values as journaled: recovery, replay, history and
+//! anonymization rebuild state from it, so it is protected as a whole by the data directory's
+//! permissions and encryption at rest. The policy applies where entries leave the kernel:
+//! journal reads, transaction history, the audit log, the void audit report and sync
+//! documents. Transactions in memory, transaction exports and receipts keep the original
+//! values.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiCategory {
+    OperatorId,
+    CustomerRef,
+    AttributeValue,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiMode {
+    #[default]
+    Plain,
+    Hash,
+    Redact,
+}
+
+impl std::str::FromStr for PiiMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "plain" => Ok(Self::Plain),
+            "hash" => Ok(Self::Hash),
+            "redact" => Ok(Self::Redact),
+            _ => Err(()),
+        }
+    }
+}
+
+pub const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PiiPolicy {
+    pub operator_ids: PiiMode,
+    pub customer_refs: PiiMode,
+    pub attribute_values: PiiMode,
+    pub hash_key: Option<Vec<u8>>,
+}
+
+impl PiiPolicy {
+    fn mode(&self, category: PiiCategory) -> PiiMode {
+        match category {
+            PiiCategory::OperatorId => self.operator_ids,
+            PiiCategory::CustomerRef => self.customer_refs,
+            PiiCategory::AttributeValue => self.attribute_values,
+        }
+    }
+}
+
+fn policy() -> &'static RwLock<PiiPolicy> {
+    static POLICY: OnceLock<RwLock<PiiPolicy>> = OnceLock::new();
+    POLICY.get_or_init(|| RwLock::new(PiiPolicy::default()))
+}
+
+pub(crate) fn set_modes(operator_ids: PiiMode, customer_refs: PiiMode, attribute_values: PiiMode) {
+    if let Ok(mut policy) = policy().write() {
+        policy.operator_ids = operator_ids;
+        policy.customer_refs = customer_refs;
+        policy.attribute_values = attribute_values;
+    }
+}
+
+pub(crate) fn set_hash_key(key: Option<Vec<u8>>) {
+    if let Ok(mut policy) = policy().write() {
+        policy.hash_key = key;
+    }
+}
+
+// Applies the configured mode for the category; values are redacted if the policy is unreadable
+pub fn protect(category: PiiCategory, value: &str) -> Cow<'_, str> {
+    let policy = match policy().read() {
+        Ok(policy) => policy,
+        Err(_) => return Cow::Borrowed(REDACTED),
+    };
+
+    match policy.mode(category) {
+        PiiMode::Plain => Cow::Borrowed(value),
+        PiiMode::Redact => Cow::Borrowed(REDACTED),
+        PiiMode::Hash => {
+            let digest = match policy.hash_key.as_deref() {
+                Some(key) => crate::signing::sign_hex(key, value.as_bytes()).ok(),
+                None => None,
+            };
+            let digest = digest.unwrap_or_else(|| {
+                Sha256::digest(value.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+            });
+            Cow::Owned(format!("hash:{}", &digest[..32]))
+        }
+    }
+}
+
+// Tagged value for log and tracing output, e.g. `tracing::info!(customer = %pii::tagged(...))`
+pub struct Tagged<'a> {
+    category: PiiCategory,
+    value: &'a str,
+}
+
+pub fn tagged(category: PiiCategory, value: &str) -> Tagged<'_> {
+    Tagged { category, value }
+}
+
+impl fmt::Display for Tagged<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&protect(self.category, self.value))
+    }
+}
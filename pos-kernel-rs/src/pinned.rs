@@ -0,0 +1,107 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pinned line buffers
+//! Zero-copy bulk line reads: the kernel builds a C-layout view of a transaction's lines once
+//! and hands user space a pointer into it. The buffer belongs to the transaction and stays
+//! valid until the transaction is next modified, settles, or the buffer is released; pinning
+//! an unmodified transaction again returns the same buffer.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::Line;
+
+// One line as user space reads it; `sku_ptr` is not null-terminated
+#[repr(C)]
+pub struct PkLineView {
+    pub line_id: u32,
+    // 0 for top-level lines; line IDs start at 1
+    pub parent_line_id: u32,
+    pub kind: i32,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub total_minor: i64,
+    pub sku_ptr: *const u8,
+    pub sku_len: usize,
+}
+
+pub(crate) struct PinnedLines {
+    views: Vec<PkLineView>,
+    // Keeps the interned SKUs the views point into alive for the buffer's lifetime
+    _skus: Vec<Arc<str>>,
+}
+
+// SAFETY: The views are never modified after construction and only point into the SKUs
+// owned by the same buffer
+unsafe impl Send for PinnedLines {}
+unsafe impl Sync for PinnedLines {}
+
+impl PinnedLines {
+    fn new(lines: &[Line]) -> Self {
+        let views = lines.iter().map(|line| PkLineView {
+            line_id: line.line_id,
+            parent_line_id: line.parent_line_item_id.unwrap_or(0),
+            kind: line.kind as i32,
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            total_minor: line.total_minor(),
+            sku_ptr: line.sku.as_ptr(),
+            sku_len: line.sku.len(),
+        }).collect();
+        Self { views, _skus: lines.iter().map(|line| Arc::clone(&line.sku)).collect() }
+    }
+
+    pub fn as_ptr(&self) -> *const PkLineView {
+        self.views.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+}
+
+// A transaction's pinned buffer, if any. Pinning happens under shared access to the
+// transaction; any exclusive access drops the buffer.
+#[derive(Default)]
+pub(crate) struct PinSlot {
+    pinned: Mutex<Option<Arc<PinnedLines>>>,
+}
+
+impl PinSlot {
+    pub fn pin(&self, lines: &[Line]) -> Arc<PinnedLines> {
+        let mut pinned = self.pinned.lock().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(pinned.get_or_insert_with(|| Arc::new(PinnedLines::new(lines))))
+    }
+
+    pub fn release(&mut self) {
+        *self.pinned.get_mut().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+// Copies never share a pinned buffer: user space pins each transaction separately
+impl Clone for PinSlot {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for PinSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pinned = self.pinned.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("PinSlot").field("pinned_lines", &pinned.as_ref().map(|p| p.len())).finish()
+    }
+}
@@ -0,0 +1,289 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Background persistence pipeline
+//! ARCHITECTURAL PRINCIPLE: Work that follows a commit but does not decide its outcome
+//! (sealing into the archive, evicting to disk, signing with the TSE and registering with the fiscal device, delivering receipts and kitchen tickets
+//! downstream, pushing orders to the ERP, forwarding sales to the central service, publishing
+//! basket summaries, printing receipts, opening the cash drawer, dispensing change) is queued to a worker pool over a
+//! bounded channel, so the lane returns without waiting on disks, receipt services or devices.
+//! A job that finds the queue full, or no workers running, goes to an overflow queue instead;
+//! nothing is dropped, and no job runs on the request path under the store lock. Workers take
+//! the store lock themselves, and only for as long as the job needs it.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analytics::BasketDelivery;
+use crate::erp::ErpOutbox;
+use crate::fiscal::CommitFiscalization;
+use crate::peripheral::drawer::DrawerOpen;
+use crate::peripheral::kitchen::KitchenDelivery;
+use crate::peripheral::printer::Spooler;
+use crate::peripheral::recycler::ChangeDispense;
+use crate::pricing::PriceRefresh;
+use crate::receipt::{DeliveryReason, ReceiptDeliverer, ReceiptDocument};
+use crate::sync::PendingBatch;
+
+pub(crate) const DEFAULT_WORKERS: usize = 2;
+pub(crate) const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+const WORKER_NAME_PREFIX: &str = "pk-persist-";
+const OVERFLOW_THREAD_NAME: &str = "pk-persist-overflow";
+
+pub(crate) enum Job {
+    // Seal a committed transaction into the archive
+    Archive { handle: u64 },
+    // Bring resident archive records back under the memory budget
+    Evict,
+    // Top the transaction pool back up to its size
+    RefillPool,
+    DeliverReceipt(Box<ReceiptDelivery>),
+    // Sign a committed sale with the TSE and register it with the fiscal device; its receipt and
+    // the rest of its post-commit work follow
+    Fiscalize(Box<CommitFiscalization>),
+    // Kick the cash drawer for a committed cash transaction
+    OpenDrawer(Box<DrawerOpen>),
+    DeliverKitchenTicket(Box<KitchenDelivery>),
+    // Print the receipts queued on the printer spooler
+    Print(Arc<Spooler>),
+    // Pay out a committed transaction's change from the terminal's cash recycler
+    DispenseChange(Box<ChangeDispense>),
+    // Push the orders queued in the ERP connectors' outboxes
+    PushErp(Arc<ErpOutbox>),
+    // Forward committed transactions to the central service
+    Sync(Box<PendingBatch>),
+    // Hand a committed sale's basket summary to the analytics sinks
+    PublishBasket(Box<BasketDelivery>),
+    // Refresh a stale cached price from the price provider
+    RefreshPrice(Box<PriceRefresh>),
+}
+
+// A receipt built under the store lock, delivered without it
+pub(crate) struct ReceiptDelivery {
+    pub handle: u64,
+    pub reason: DeliveryReason,
+    pub receipt: ReceiptDocument,
+    pub deliverers: Vec<Arc<dyn ReceiptDeliverer>>,
+}
+
+impl ReceiptDelivery {
+    // Outcome per deliverer name
+    pub fn deliver(&self) -> Vec<(String, Result<(), String>)> {
+        self.deliverers.iter()
+            .map(|d| (d.name().to_string(), d.deliver(&self.receipt, self.reason)))
+            .collect()
+    }
+}
+
+// Jobs queued or running, across restarts of the pool
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    idle: Condvar,
+}
+
+impl Pending {
+    fn count(&self) -> MutexGuard<'_, usize> {
+        self.count.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finish(&self) {
+        let mut count = self.count();
+        *count -= 1;
+        if *count == 0 {
+            self.idle.notify_all();
+        }
+    }
+}
+
+// Jobs the worker queue could not take, with the queue they are fed into. The overflow thread
+// waits for room in the queue, or runs the jobs itself while no workers are running.
+#[derive(Default)]
+struct Overflow {
+    state: Mutex<OverflowState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct OverflowState {
+    jobs: VecDeque<Job>,
+    // None while no workers are running
+    sender: Option<SyncSender<Job>>,
+}
+
+impl Overflow {
+    fn state(&self) -> MutexGuard<'_, OverflowState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, job: Job) {
+        self.state().jobs.push_back(job);
+        self.ready.notify_one();
+    }
+}
+
+pub(crate) struct Pipeline {
+    // None while no workers are running
+    sender: Option<SyncSender<Job>>,
+    workers: usize,
+    pending: Arc<Pending>,
+    overflow: Arc<Overflow>,
+    run: fn(Job),
+}
+
+impl Pipeline {
+    pub fn start(workers: usize, capacity: usize, run: fn(Job)) -> Self {
+        let overflow = Arc::<Overflow>::default();
+        let (feeder, pending) = (Arc::clone(&overflow), Arc::<Pending>::default());
+        let feeder_pending = Arc::clone(&pending);
+        if let Err(e) = thread::Builder::new()
+            .name(OVERFLOW_THREAD_NAME.to_string())
+            .spawn(move || feed(&feeder, &feeder_pending, run)) {
+            eprintln!("CRITICAL: Could not start persistence overflow thread: {}", e);
+        }
+
+        let mut pipeline = Self { sender: None, workers: 0, pending, overflow, run };
+        pipeline.restart(workers, capacity);
+        pipeline
+    }
+
+    // Replaces the worker pool. Jobs already queued finish on the previous workers, which exit
+    // once their queue is drained. With zero workers every job goes through the overflow thread.
+    pub fn restart(&mut self, workers: usize, capacity: usize) {
+        self.sender = None;
+        self.workers = 0;
+        self.overflow.state().sender = None;
+        if workers == 0 {
+            return;
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..workers {
+            let (receiver, pending, run) = (Arc::clone(&receiver), Arc::clone(&self.pending), self.run);
+            let spawned = thread::Builder::new()
+                .name(format!("{}{}", WORKER_NAME_PREFIX, i))
+                .spawn(move || work(&receiver, &pending, run));
+            match spawned {
+                Ok(_) => self.workers += 1,
+                Err(e) => eprintln!("WARNING: Could not start persistence worker: {}", e),
+            }
+        }
+        if self.workers > 0 {
+            self.overflow.state().sender = Some(sender.clone());
+            self.sender = Some(sender);
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn pending(&self) -> usize {
+        *self.pending.count()
+    }
+
+    // Queues a job without blocking; one the worker queue cannot take goes to the overflow queue
+    pub fn submit(&self, job: Job) {
+        *self.pending.count() += 1;
+        let job = match &self.sender {
+            Some(sender) => match sender.try_send(job) {
+                Ok(()) => return,
+                Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => job,
+            },
+            None => job,
+        };
+        self.overflow.push(job);
+    }
+
+    // Taken under the store lock, waited on after releasing it
+    pub fn idle(&self) -> Idle {
+        Idle(Arc::clone(&self.pending))
+    }
+}
+
+pub(crate) struct Idle(Arc<Pending>);
+
+impl Idle {
+    // Waits until no job is queued or running; false if the timeout expired first
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.0.count();
+        while *count > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            count = self.0.idle.wait_timeout(count, remaining)
+                .unwrap_or_else(PoisonError::into_inner).0;
+        }
+        true
+    }
+}
+
+// Whether the calling thread is a persistence worker, where a job's panic is caught
+#[cfg(feature = "fault-injection")]
+pub(crate) fn on_worker() -> bool {
+    thread::current().name().is_some_and(|name| name.starts_with(WORKER_NAME_PREFIX))
+}
+
+// A panicking job is reported and counted as finished; the worker keeps serving the queue
+fn work(receiver: &Mutex<Receiver<Job>>, pending: &Pending, run: fn(Job)) {
+    loop {
+        let job = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        run_caught(job, pending, run);
+    }
+}
+
+fn run_caught(job: Job, pending: &Pending, run: fn(Job)) {
+    if panic::catch_unwind(AssertUnwindSafe(|| run(job))).is_err() {
+        eprintln!("CRITICAL: Persistence job panicked");
+    }
+    pending.finish();
+}
+
+// Moves overflowed jobs into the worker queue, waiting for room there, in the order they
+// arrived. Without workers the jobs are run here, one at a time.
+fn feed(overflow: &Overflow, pending: &Pending, run: fn(Job)) {
+    loop {
+        let (job, sender) = {
+            let mut state = overflow.state();
+            loop {
+                if let Some(job) = state.jobs.pop_front() {
+                    break (job, state.sender.clone());
+                }
+                state = overflow.ready.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+        // A queue whose workers are gone hands the job back to run here
+        let job = match sender {
+            Some(sender) => match sender.send(job) {
+                Ok(()) => continue,
+                Err(returned) => returned.0,
+            },
+            None => job,
+        };
+        run_caught(job, pending, run);
+    }
+}
@@ -0,0 +1,381 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Plugin host
+//! ARCHITECTURAL PRINCIPLE: Deployments add tax, pricing, loyalty and receipt behavior from
+//! shared libraries listed in a manifest, without rebuilding the kernel. A plugin implements
+//! the same hooks a Rust integrator would register, through a small C ABI that exchanges JSON,
+//! so the ABI does not change when a hook's request grows a field:
+//!
+//! ```c
+//! uint32_t pk_plugin_abi_version(void);                       // PK_PLUGIN_ABI_VERSION
+//! int32_t  pk_plugin_init(const uint8_t* config, size_t len); // 0 on success
+//! int32_t  pk_plugin_call(const uint8_t* method, size_t method_len,
+//!                         const uint8_t* request, size_t request_len,
+//!                         uint8_t* response, size_t response_size, size_t* out_response_len);
+//! ```
+//!
+//! `pk_plugin_call` writes a JSON response and returns 0, returns 1 with the size it needs
+//! when the response buffer is too small, or returns any other value with an error message in
+//! the buffer. It may be called from several threads at once. The methods by kind are:
+//! - tax: `calculate` (the tax request) returns the tax entries
+//! - pricing: `resolve_price` (`sku`, `currency`) returns `price_minor`, null when not priced
+//! - loyalty: `identify` (`customer_token`) returns the account or null; `quote_accrual`
+//!   (`account`, `transaction`) returns `points`; `redeem` (`account`, `points`, `transaction`);
+//!   `reverse_redemption` (the same) credits back points whose redemption was not applied
+//! - receipt: `deliver` (`receipt`, `reason`)
+//! - analytics: `publish` (`basket`)
+//!
+//! Every plugin is loaded and initialized before any is registered, so a manifest with one bad
+//! entry changes nothing. Libraries stay loaded for the life of the process.
+
+use std::collections::HashSet;
+use std::ffi::{c_void, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::analytics::{AnalyticsSink, BasketSummary};
+use crate::export::TransactionExport;
+use crate::loyalty::{LoyaltyAccount, LoyaltyProvider};
+use crate::pricing::PriceProvider;
+use crate::receipt::{DeliveryReason, ReceiptDeliverer, ReceiptDocument};
+use crate::tax::{TaxProvider, TaxRequest};
+use crate::{read_store_for_api, write_store_for_api, KernelError, TaxEntry};
+
+pub const PK_PLUGIN_ABI_VERSION: u32 = 1;
+
+pub(crate) const PLUGINS_LOADED: &str = "Plugins already loaded";
+pub(crate) const NO_LOYALTY_PLUGIN: &str = "No loyalty plugin loaded";
+
+const PLUGIN_OK: i32 = 0;
+const PLUGIN_BUFFER_TOO_SMALL: i32 = 1;
+const INITIAL_RESPONSE_BYTES: usize = 4096;
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type InitFn = unsafe extern "C" fn(*const u8, usize) -> i32;
+type CallFn = unsafe extern "C" fn(*const u8, usize, *const u8, usize, *mut u8, usize, *mut usize) -> i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginKind {
+    Tax,
+    Pricing,
+    Loyalty,
+    Receipt,
+    Analytics,
+}
+
+#[derive(Deserialize)]
+struct PluginManifest {
+    plugins: Vec<PluginEntry>,
+}
+
+#[derive(Deserialize)]
+struct PluginEntry {
+    name: String,
+    kind: PluginKind,
+    // Relative paths are resolved against the manifest's directory
+    library: String,
+    // Passed to `pk_plugin_init` as JSON
+    #[serde(default)]
+    config: Value,
+}
+
+// A loaded plugin, as reported by `pk_get_plugins_json`
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub kind: PluginKind,
+    pub library: String,
+}
+
+#[cfg(unix)]
+mod dl {
+    use std::ffi::{c_char, c_int, c_void, CStr};
+
+    const RTLD_NOW: c_int = 2;
+
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *mut c_char;
+    }
+
+    fn last_error() -> String {
+        // SAFETY: dlerror returns null or a C string valid until the next dl call
+        unsafe {
+            let error = dlerror();
+            match error.is_null() {
+                true => "unknown error".to_string(),
+                false => CStr::from_ptr(error).to_string_lossy().into_owned(),
+            }
+        }
+    }
+
+    pub fn open(path: &CStr) -> Result<*mut c_void, String> {
+        // SAFETY: path is a valid C string; loading runs the library's initializers
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+        match handle.is_null() {
+            true => Err(last_error()),
+            false => Ok(handle),
+        }
+    }
+
+    pub fn symbol(handle: *mut c_void, name: &CStr) -> Option<*mut c_void> {
+        // SAFETY: handle came from dlopen and is never closed
+        let symbol = unsafe { dlsym(handle, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+#[cfg(windows)]
+mod dl {
+    use std::ffi::{c_char, c_void, CStr};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryA(filename: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+        fn GetLastError() -> u32;
+    }
+
+    pub fn open(path: &CStr) -> Result<*mut c_void, String> {
+        // SAFETY: path is a valid C string; loading runs the library's initializers
+        let module = unsafe { LoadLibraryA(path.as_ptr()) };
+        match module.is_null() {
+            // SAFETY: no other call intervenes since LoadLibraryA
+            true => Err(format!("error {}", unsafe { GetLastError() })),
+            false => Ok(module),
+        }
+    }
+
+    pub fn symbol(module: *mut c_void, name: &CStr) -> Option<*mut c_void> {
+        // SAFETY: module came from LoadLibraryA and is never freed
+        let symbol = unsafe { GetProcAddress(module, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+struct Plugin {
+    info: PluginInfo,
+    call: CallFn,
+}
+
+impl Plugin {
+    fn load(entry: &PluginEntry, base: &Path) -> Result<Self, String> {
+        let path: PathBuf = base.join(&entry.library);
+        let error = |e: String| format!("Plugin {} ({}) not loaded: {}", entry.name, path.display(), e);
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wasm")) {
+            return Err(error("WASM modules are not supported by this build".to_string()));
+        }
+
+        let c_path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| error(e.to_string()))?;
+        let library = dl::open(&c_path).map_err(error)?;
+        let symbol = |name: &str| -> Result<*mut c_void, String> {
+            let c_name = CString::new(name).map_err(|e| error(e.to_string()))?;
+            dl::symbol(library, &c_name).ok_or_else(|| error(format!("{} is not exported", name)))
+        };
+
+        // SAFETY: the plugin ABI fixes these symbols' signatures
+        let (abi_version, init, call) = unsafe {
+            (
+                std::mem::transmute::<*mut c_void, AbiVersionFn>(symbol("pk_plugin_abi_version")?),
+                std::mem::transmute::<*mut c_void, InitFn>(symbol("pk_plugin_init")?),
+                std::mem::transmute::<*mut c_void, CallFn>(symbol("pk_plugin_call")?),
+            )
+        };
+        // SAFETY: as above
+        let version = unsafe { abi_version() };
+        if version != PK_PLUGIN_ABI_VERSION {
+            return Err(error(format!("plugin ABI version {} is not {}", version, PK_PLUGIN_ABI_VERSION)));
+        }
+        let config = entry.config.to_string();
+        // SAFETY: config outlives the call
+        let status = unsafe { init(config.as_ptr(), config.len()) };
+        if status != PLUGIN_OK {
+            return Err(error(format!("initialization failed with status {}", status)));
+        }
+
+        let info = PluginInfo { name: entry.name.clone(), kind: entry.kind, library: path.display().to_string() };
+        Ok(Self { info, call })
+    }
+
+    fn invoke<R: DeserializeOwned>(&self, method: &str, request: &impl Serialize) -> Result<R, String> {
+        let request = serde_json::to_vec(request)
+            .map_err(|e| format!("Plugin {} {} request not encoded: {}", self.info.name, method, e))?;
+        let mut response = vec![0u8; INITIAL_RESPONSE_BYTES];
+        loop {
+            let mut written = 0usize;
+            // SAFETY: every pointer is valid for its stated length for the duration of the call
+            let status = unsafe {
+                (self.call)(method.as_ptr(), method.len(), request.as_ptr(), request.len(),
+                    response.as_mut_ptr(), response.len(), &mut written)
+            };
+            let body = &response[..written.min(response.len())];
+            match status {
+                PLUGIN_OK => {
+                    return serde_json::from_slice(body)
+                        .map_err(|e| format!("Plugin {} returned an invalid {} response: {}", self.info.name, method, e));
+                },
+                PLUGIN_BUFFER_TOO_SMALL if written > response.len() && written <= MAX_RESPONSE_BYTES => {
+                    response.resize(written, 0);
+                },
+                PLUGIN_BUFFER_TOO_SMALL => {
+                    return Err(format!("Plugin {} {} response is too large", self.info.name, method));
+                },
+                _ => {
+                    return Err(format!("Plugin {} {} failed: {}", self.info.name, method, String::from_utf8_lossy(body)));
+                },
+            }
+        }
+    }
+}
+
+struct PluginTax(Arc<Plugin>);
+
+impl TaxProvider for PluginTax {
+    fn calculate(&self, request: &TaxRequest) -> Result<Vec<TaxEntry>, String> {
+        self.0.invoke("calculate", request)
+    }
+}
+
+#[derive(Deserialize)]
+struct PriceResponse {
+    price_minor: Option<i64>,
+}
+
+struct PluginPricing(Arc<Plugin>);
+
+impl PriceProvider for PluginPricing {
+    fn current_price(&self, sku: &str, currency: &str) -> Option<i64> {
+        self.resolve_price(sku, currency).ok().flatten()
+    }
+
+    fn resolve_price(&self, sku: &str, currency: &str) -> Result<Option<i64>, String> {
+        let response: PriceResponse = self.0.invoke("resolve_price", &json!({ "sku": sku, "currency": currency }))?;
+        Ok(response.price_minor)
+    }
+}
+
+#[derive(Deserialize)]
+struct AccrualResponse {
+    points: i64,
+}
+
+struct PluginLoyalty(Arc<Plugin>);
+
+impl LoyaltyProvider for PluginLoyalty {
+    fn identify(&self, customer_token: &str) -> Result<Option<LoyaltyAccount>, String> {
+        self.0.invoke("identify", &json!({ "customer_token": customer_token }))
+    }
+
+    fn quote_accrual(&self, account: &LoyaltyAccount, transaction: &TransactionExport) -> Result<i64, String> {
+        let response: AccrualResponse = self.0.invoke("quote_accrual", &json!({ "account": account, "transaction": transaction }))?;
+        Ok(response.points)
+    }
+
+    fn redeem(&self, account: &LoyaltyAccount, points: i64, transaction: &TransactionExport) -> Result<(), String> {
+        self.0.invoke::<Value>("redeem", &json!({ "account": account, "points": points, "transaction": transaction }))
+            .map(|_| ())
+    }
+
+    fn reverse_redemption(&self, account: &LoyaltyAccount, points: i64, transaction: &TransactionExport) -> Result<(), String> {
+        self.0.invoke::<Value>("reverse_redemption", &json!({ "account": account, "points": points, "transaction": transaction }))
+            .map(|_| ())
+    }
+}
+
+struct PluginReceipt(Arc<Plugin>);
+
+impl ReceiptDeliverer for PluginReceipt {
+    fn name(&self) -> &str {
+        &self.0.info.name
+    }
+
+    fn deliver(&self, receipt: &ReceiptDocument, reason: DeliveryReason) -> Result<(), String> {
+        self.0.invoke::<Value>("deliver", &json!({ "receipt": receipt, "reason": reason }))
+            .map(|_| ())
+    }
+}
+
+struct PluginAnalytics(Arc<Plugin>);
+
+impl AnalyticsSink for PluginAnalytics {
+    fn name(&self) -> &str {
+        &self.0.info.name
+    }
+
+    fn publish(&self, basket: &BasketSummary) -> Result<(), String> {
+        self.0.invoke::<Value>("publish", &json!({ "basket": basket }))
+            .map(|_| ())
+    }
+}
+
+// Loads and registers the plugins listed in the manifest at `manifest_path`; once per process
+pub fn load_plugins(manifest_path: &Path) -> Result<Vec<PluginInfo>, KernelError> {
+    if !read_store_for_api()?.plugins.is_empty() {
+        return Err(KernelError::invalid_state(PLUGINS_LOADED));
+    }
+
+    let content = fs::read_to_string(manifest_path)
+        .map_err(|e| KernelError::Internal(format!("Failed to read plugin manifest {}: {}", manifest_path.display(), e)))?;
+    let manifest: PluginManifest = serde_json::from_str(&content)
+        .map_err(|e| KernelError::Validation(format!("Invalid plugin manifest: {}", e)))?;
+
+    // Tax, pricing and loyalty each have one provider; receipt deliverers and analytics sinks
+    // accumulate
+    let mut single = HashSet::new();
+    for entry in &manifest.plugins {
+        let accumulates = matches!(entry.kind, PluginKind::Receipt | PluginKind::Analytics);
+        if !accumulates && !single.insert(entry.kind) {
+            return Err(KernelError::Validation(format!("Plugin manifest lists more than one {:?} plugin", entry.kind)));
+        }
+    }
+
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+    let plugins = manifest.plugins.iter()
+        .map(|entry| Plugin::load(entry, base).map(Arc::new))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(KernelError::Validation)?;
+
+    let mut store = write_store_for_api()?;
+    if !store.plugins.is_empty() {
+        return Err(KernelError::invalid_state(PLUGINS_LOADED));
+    }
+    for plugin in &plugins {
+        match plugin.info.kind {
+            PluginKind::Tax => {
+                store.tax_provider = Some(Box::new(PluginTax(Arc::clone(plugin))));
+                store.tax_cache.clear();
+            },
+            PluginKind::Pricing => {
+                store.price_provider = Some(Arc::new(PluginPricing(Arc::clone(plugin))));
+                store.price_cache.invalidate(None);
+            },
+            PluginKind::Loyalty => store.loyalty_provider = Some(Arc::new(PluginLoyalty(Arc::clone(plugin)))),
+            PluginKind::Receipt => store.receipt_deliverers.push(Arc::new(PluginReceipt(Arc::clone(plugin)))),
+            PluginKind::Analytics => store.analytics_sinks.push(Arc::new(PluginAnalytics(Arc::clone(plugin)))),
+        }
+        store.plugins.push(plugin.info.clone());
+    }
+    Ok(store.plugins.clone())
+}
@@ -0,0 +1,163 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Preallocated transaction pool
+//! Beginning a transaction takes a blank shell whose heap parts (totals cell, tender and tax
+//! buffers) already exist, so opening a sale at rush hour does not allocate for them. The
+//! pool is topped up by a persistence worker once it falls below half its size, and archived
+//! transactions evicted from memory hand their buffers back when nothing else references them.
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{Currency, Transaction, TransactionKind};
+
+pub(crate) const DEFAULT_SIZE: usize = 64;
+
+// Room reserved in every shell; a split tender or a second tax rate fits without growing
+const RESERVED_TENDERS: usize = 2;
+const RESERVED_TAXES: usize = 2;
+// Line buffers larger than this are freed rather than kept in an idle shell
+const MAX_RECYCLED_LINES: usize = 256;
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub(crate) struct PoolStats {
+    pub size: usize,
+    pub available: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub recycled: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct TransactionPool {
+    shells: Vec<Transaction>,
+    size: usize,
+    // A refill job is queued and has not restocked yet
+    refill_queued: bool,
+    hits: u64,
+    misses: u64,
+    recycled: u64,
+}
+
+impl TransactionPool {
+    pub fn new(size: usize) -> Self {
+        Self { shells: allocate(size), size, ..Self::default() }
+    }
+
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.shells.truncate(size);
+    }
+
+    // Falls back to a fresh allocation when the pool is empty
+    pub fn acquire(&mut self, id: u64, kind: TransactionKind, store: String, currency: Currency) -> Transaction {
+        let mut tx = match self.shells.pop() {
+            Some(shell) => {
+                self.hits += 1;
+                shell
+            },
+            None => {
+                self.misses += 1;
+                return Transaction::new(id, kind, store, currency);
+            }
+        };
+        tx.id = id;
+        tx.kind = kind;
+        tx.store = store;
+        tx.currency = currency;
+        tx.started_at = Utc::now();
+        tx
+    }
+
+    // True once the pool is below half its size and no refill is queued; marks one as queued
+    pub fn needs_refill(&mut self) -> bool {
+        if self.refill_queued || self.shells.len() * 2 >= self.size {
+            return false;
+        }
+        self.refill_queued = true;
+        true
+    }
+
+    pub fn shortfall(&self) -> usize {
+        self.size.saturating_sub(self.shells.len())
+    }
+
+    // A refill job that never ran (its worker panicked) must not block later refills
+    pub fn clear_refill(&mut self) {
+        self.refill_queued = false;
+    }
+
+    pub fn restock(&mut self, shells: Vec<Transaction>) {
+        self.refill_queued = false;
+        let room = self.shortfall();
+        self.shells.extend(shells.into_iter().take(room));
+    }
+
+    // Keeps the buffers of a transaction no longer referenced anywhere else
+    pub fn recycle(&mut self, tx: Arc<Transaction>) {
+        if self.shells.len() >= self.size {
+            return;
+        }
+        let tx = match Arc::try_unwrap(tx) {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+
+        // The old totals cell stays registered under the old handle, so only the buffers move
+        // into a new shell
+        let mut shell = empty();
+        let (mut lines, mut tenders, mut taxes) = (tx.lines, tx.tenders, tx.taxes);
+        if lines.capacity() <= MAX_RECYCLED_LINES {
+            lines.clear();
+            shell.lines = lines;
+        }
+        tenders.clear();
+        taxes.clear();
+        shell.tenders = tenders;
+        shell.taxes = taxes;
+        self.shells.push(reserve(shell));
+        self.recycled += 1;
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.size,
+            available: self.shells.len(),
+            hits: self.hits,
+            misses: self.misses,
+            recycled: self.recycled,
+        }
+    }
+}
+
+// Built without the store lock by the worker refilling the pool
+pub(crate) fn allocate(count: usize) -> Vec<Transaction> {
+    (0..count).map(|_| reserve(empty())).collect()
+}
+
+fn empty() -> Transaction {
+    let currency = Currency { code: String::new(), decimal_places: 0 };
+    Transaction::new(0, TransactionKind::Sale, String::new(), currency)
+}
+
+fn reserve(mut tx: Transaction) -> Transaction {
+    tx.tenders.reserve(RESERVED_TENDERS);
+    tx.taxes.reserve(RESERVED_TAXES);
+    tx
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! NRF ARTS POSLog export
+//! NRF COMPLIANCE: Committed transactions are serialized as POSLog `RetailTransaction`s for
+//! downstream retail analytics. Linked items (modifiers, deposits) reference their parent line
+//! through `ItemLink`; voided lines are kept with `VoidFlag`. Amounts are decimal strings in
+//! the transaction currency.
+
+use crate::escpos::MinorFormat;
+use crate::export::{LineExport, TransactionExport};
+use crate::xml::XmlWriter;
+use crate::{LineKind, TenderKind, TxState};
+
+pub const POSLOG_NAMESPACE: &str = "http://www.nrf-arts.org/IXRetail/namespace/";
+
+// Serializes committed transactions into one POSLog document
+pub fn to_poslog(transactions: &[TransactionExport]) -> Result<String, String> {
+    let mut xml = XmlWriter::new();
+    xml.open("POSLog", &[("xmlns", POSLOG_NAMESPACE), ("MajorVersion", "6"), ("MinorVersion", "0")]);
+    for tx in transactions {
+        if tx.state != TxState::Committed {
+            return Err(format!("Transaction {} is not committed", tx.id));
+        }
+        write_transaction(&mut xml, tx)?;
+    }
+    Ok(xml.finish())
+}
+
+fn write_transaction(xml: &mut XmlWriter, tx: &TransactionExport) -> Result<(), String> {
+    let money = MinorFormat::new(tx.decimal_places, '.')?;
+    let percent = MinorFormat::new(2, '.')?;
+    let amount = |minor: i64| money.format(minor);
+    let time = |t: &chrono::DateTime<chrono::Utc>| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    xml.open("Transaction", &[]);
+    xml.element("RetailStoreID", &[], &tx.store);
+    xml.element("SequenceNumber", &[], &tx.id.to_string());
+    xml.element("BeginDateTime", &[], &time(&tx.started_at));
+    if let Some(committed_at) = tx.committed_at.as_ref() {
+        xml.element("EndDateTime", &[], &time(committed_at));
+    }
+    xml.element("CurrencyCode", &[], &tx.currency);
+    xml.open("RetailTransaction", &[("TransactionStatus", "Finished")]);
+
+    for line in &tx.lines {
+        write_line(xml, line, &amount);
+    }
+
+    let mut sequence = tx.lines.iter().map(|l| l.line_id).max().unwrap_or(0);
+    for tax in &tx.taxes {
+        sequence += 1;
+        xml.open("LineItem", &[]);
+        xml.element("SequenceNumber", &[], &sequence.to_string());
+        xml.open("Tax", &[("TaxType", "Sales")]);
+        xml.element("TaxAuthority", &[], &tax.jurisdiction);
+        xml.element("TaxRuleID", &[], &tax.rate_code);
+        let inclusive = if tax.inclusive { "true" } else { "false" };
+        xml.element("TaxableAmount", &[("TaxIncludedInTaxableAmountFlag", inclusive)], &amount(tax.taxable_minor));
+        xml.element("Amount", &[], &amount(tax.tax_minor));
+        xml.element("Percent", &[], &percent.format(tax.rate_basis_points as i64));
+        if tax.exempt_minor != 0 {
+            xml.open("TaxExemption", &[]);
+            xml.element("ExemptTaxableAmount", &[], &amount(tax.exempt_minor));
+            xml.close();
+        }
+        xml.close();
+        xml.close();
+    }
+
+    for (i, tender) in tx.tenders.iter().enumerate() {
+        sequence += 1;
+        let tender_type = match tender.kind {
+            TenderKind::Cash => "Cash",
+            TenderKind::Card => "CreditDebit",
+            TenderKind::Points => "Loyalty",
+        };
+        xml.open("LineItem", &[]);
+        xml.element("SequenceNumber", &[], &sequence.to_string());
+        xml.open("Tender", &[("TenderType", tender_type), ("TypeCode", "Sale")]);
+        xml.element("Amount", &[], &amount(tender.amount_minor));
+        if let Some(points) = tender.points {
+            xml.element("LoyaltyPoints", &[], &points.to_string());
+        }
+        if let Some(card) = tender.card.as_ref() {
+            xml.open("Authorization", &[]);
+            xml.element("ReferenceNumber", &[], &card.auth_reference);
+            xml.element("AuthorizedAmount", &[], &amount(card.authorized_minor));
+            xml.close();
+            if card.tip_minor != 0 {
+                xml.element("TipAmount", &[], &amount(card.tip_minor));
+            }
+        }
+        // Change is reported once, on the last tender
+        if i + 1 == tx.tenders.len() && tx.change_minor > 0 {
+            xml.open("TenderChange", &[("TenderType", "Cash")]);
+            xml.element("Amount", &[], &amount(tx.change_minor));
+            xml.close();
+        }
+        xml.close();
+        xml.close();
+    }
+
+    let tax_minor: i64 = tx.taxes.iter().filter(|t| !t.inclusive).map(|t| t.tax_minor).sum();
+    xml.element("Total", &[("TotalType", "TransactionNetAmount")], &amount(tx.total_minor - tax_minor));
+    xml.element("Total", &[("TotalType", "TransactionTaxAmount")], &amount(tx.taxes.iter().map(|t| t.tax_minor).sum()));
+    xml.element("Total", &[("TotalType", "TransactionGrandAmount")], &amount(tx.total_minor));
+    xml.element("Total", &[("TotalType", "TransactionTenderApplied")], &amount(tx.tendered_minor));
+
+    if let Some(customer) = tx.customer.as_ref() {
+        xml.open("Customer", &[]);
+        xml.element("CustomerID", &[], &customer.token);
+        xml.optional("LoyaltyLevel", customer.tier.as_deref());
+        xml.close();
+    }
+    if let Some(order_id) = tx.order_reference.external_order_id.as_deref() {
+        xml.element("OrderID", &[], order_id);
+    }
+
+    xml.close();
+    xml.close();
+    Ok(())
+}
+
+fn write_line(xml: &mut XmlWriter, line: &LineExport, amount: &dyn Fn(i64) -> String) {
+    let void = if line.qty == 0 { "true" } else { "false" };
+    xml.open("LineItem", &[("VoidFlag", void)]);
+    xml.element("SequenceNumber", &[], &line.line_id.to_string());
+
+    if line.kind == LineKind::Adjustment {
+        // Manual adjustments are negative line amounts; POSLog discounts are positive
+        xml.open("Discount", &[("TypeCode", "Manual")]);
+        xml.element("Amount", &[], &amount(-line.total_minor));
+        xml.optional("ReasonCode", line.reason_code.as_deref());
+        xml.optional("ApprovedBy", line.approved_by.as_deref());
+        xml.close();
+        xml.close();
+        return;
+    }
+
+    let (element, item_type) = match line.kind {
+        LineKind::Deposit => ("Sale", "Deposit"),
+        LineKind::DepositReturn => ("Return", "Deposit"),
+        LineKind::Return => ("Return", "Stock"),
+        _ => ("Sale", "Stock"),
+    };
+    xml.open(element, &[("ItemType", item_type)]);
+    xml.element("ItemID", &[], &line.sku);
+    xml.optional("Description", line.product_name.as_deref());
+    xml.element("UnitListPrice", &[], &amount(line.unit_minor));
+    xml.element("ExtendedAmount", &[], &amount(line.total_minor));
+    xml.element("Quantity", &[], &line.qty.to_string());
+    if let Some(parent) = line.parent_line_id {
+        xml.element("ItemLink", &[], &parent.to_string());
+    }
+    xml.close();
+    xml.close();
+}
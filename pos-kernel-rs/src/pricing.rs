@@ -0,0 +1,233 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pricing hook
+//! ARCHITECTURAL PRINCIPLE: Prices are user-space business data. The kernel asks a registered
+//! `PriceProvider` for current prices only where an operation re-prices existing lines
+//! (for example, cloning a past transaction at today's prices), to resolve scanned barcodes
+//! to products, and to price lines added without a price.
+//! A provider is typically a callout to a central pricing service, so prices it resolves for
+//! priced lines are cached for `price_cache_ttl_secs`. Once an entry is stale the
+//! `StalePricePolicy` decides whether a scan waits for the service: by default the stale price
+//! is used and refreshed in the background, so scans stay fast while the service is slow or
+//! down, for at most `price_max_stale_secs` past its TTL.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+
+use crate::{write_store_for_api, KernelError};
+
+// A product resolved from a scanned code
+#[derive(Debug, Clone)]
+pub struct ProductInfo {
+    pub sku: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub unit_minor: i64,
+}
+
+pub trait PriceProvider: Send + Sync {
+    // Current unit price in minor units of `currency`, or None when the SKU is not priced
+    fn current_price(&self, sku: &str, currency: &str) -> Option<i64>;
+
+    // As `current_price`, distinguishing a SKU that is not priced (Ok(None)) from a pricing
+    // service that could not be reached (Err), which lets a cached price stand in
+    fn resolve_price(&self, sku: &str, currency: &str) -> Result<Option<i64>, String> {
+        Ok(self.current_price(sku, currency))
+    }
+
+    // Resolves a scanned code (the 14-digit GTIN of a GS1 barcode, otherwise the scanned text)
+    // to a product priced in `currency`; by default the code is the SKU
+    fn lookup(&self, code: &str, currency: &str) -> Option<ProductInfo> {
+        let unit_minor = self.current_price(code, currency)?;
+        Some(ProductInfo { sku: code.to_string(), name: None, description: None, unit_minor })
+    }
+}
+
+// Registers (or replaces) the store's price provider; prices cached from the previous one
+// are dropped
+pub fn set_price_provider(provider: Box<dyn PriceProvider>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.price_provider = Some(Arc::from(provider));
+    store.price_cache.invalidate(None);
+    Ok(())
+}
+
+// What a priced line does once its cached price is past the TTL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StalePricePolicy {
+    // The stale price is used and refreshed in the background
+    #[default]
+    Serve,
+    // The scan waits for the provider; the stale price is used only if the provider fails
+    Refresh,
+    // The scan waits for the provider and fails if it does
+    Reject,
+}
+
+impl std::str::FromStr for StalePricePolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "serve" => Ok(Self::Serve),
+            "refresh" => Ok(Self::Refresh),
+            "reject" => Ok(Self::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(crate) const PRICE_NOT_FOUND: &str = "No price for SKU";
+pub(crate) const PRICE_UNAVAILABLE: &str = "Price service unavailable";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PriceSource {
+    Cache,
+    Provider,
+    // A cached price past its TTL that was not refreshed first, with its age in seconds
+    Stale(u64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PricePolicy {
+    // 0 disables the cache: every priced line calls the provider
+    pub ttl_secs: u64,
+    // 0 = a stale price may stand in however old it is
+    pub max_stale_secs: u64,
+    pub stale: StalePricePolicy,
+}
+
+struct CachedPrice {
+    unit_minor: i64,
+    fetched_at: DateTime<Utc>,
+    // A background refresh is queued or running
+    refreshing: bool,
+}
+
+// Prices resolved for priced lines, per SKU and currency
+#[derive(Default)]
+pub(crate) struct PriceCache {
+    entries: Mutex<HashMap<(String, String), CachedPrice>>,
+}
+
+impl PriceCache {
+    fn entries(&self) -> MutexGuard<'_, HashMap<(String, String), CachedPrice>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn put(&self, key: (String, String), unit_minor: Option<i64>, now: DateTime<Utc>) {
+        let mut entries = self.entries();
+        match unit_minor {
+            Some(unit_minor) => {
+                entries.insert(key, CachedPrice { unit_minor, fetched_at: now, refreshing: false });
+            },
+            None => {
+                entries.remove(&key);
+            },
+        }
+    }
+
+    // Drops the cached prices of one SKU, or all of them
+    pub fn invalidate(&self, sku: Option<&str>) {
+        let mut entries = self.entries();
+        match sku {
+            Some(sku) => entries.retain(|(s, _), _| s != sku),
+            None => entries.clear(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    // Resolves the unit price of a priced line. The provider is called without the cache lock.
+    // A stale price served under `StalePricePolicy::Serve` queues a refresh through `refresh`,
+    // unless one is already pending.
+    pub fn resolve(
+        self: &Arc<Self>,
+        provider: &Arc<dyn PriceProvider>,
+        sku: &str,
+        currency: &str,
+        policy: PricePolicy,
+        refresh: impl FnOnce(PriceRefresh),
+    ) -> Result<(i64, PriceSource), KernelError> {
+        let key = (sku.to_string(), currency.to_string());
+        let now = Utc::now();
+        let mut stale = None;
+        if policy.ttl_secs > 0 {
+            let mut entries = self.entries();
+            if let Some(entry) = entries.get_mut(&key) {
+                let age_secs = u64::try_from((now - entry.fetched_at).num_seconds()).unwrap_or(0);
+                if age_secs < policy.ttl_secs {
+                    return Ok((entry.unit_minor, PriceSource::Cache));
+                }
+                let usable = policy.max_stale_secs == 0 || age_secs - policy.ttl_secs < policy.max_stale_secs;
+                if usable && policy.stale == StalePricePolicy::Serve {
+                    let unit_minor = entry.unit_minor;
+                    if !entry.refreshing {
+                        entry.refreshing = true;
+                        drop(entries);
+                        refresh(PriceRefresh { cache: Arc::clone(self), provider: Arc::clone(provider), key });
+                    }
+                    return Ok((unit_minor, PriceSource::Stale(age_secs)));
+                }
+                if usable && policy.stale == StalePricePolicy::Refresh {
+                    stale = Some((entry.unit_minor, age_secs));
+                }
+            }
+        }
+
+        match provider.resolve_price(sku, currency) {
+            Ok(unit_minor) => {
+                if policy.ttl_secs > 0 {
+                    self.put(key, unit_minor, Utc::now());
+                }
+                unit_minor.map(|u| (u, PriceSource::Provider)).ok_or_else(|| KernelError::not_found(PRICE_NOT_FOUND))
+            },
+            Err(e) => {
+                eprintln!("WARNING: Price service failed for SKU {}: {}", sku, e);
+                stale.map(|(u, age_secs)| (u, PriceSource::Stale(age_secs)))
+                    .ok_or_else(|| KernelError::TimedOut(PRICE_UNAVAILABLE.to_string()))
+            },
+        }
+    }
+}
+
+// A stale cached price refreshed off the request path
+pub(crate) struct PriceRefresh {
+    cache: Arc<PriceCache>,
+    provider: Arc<dyn PriceProvider>,
+    key: (String, String),
+}
+
+impl PriceRefresh {
+    // A failed refresh keeps the stale price, to be retried on the next scan
+    pub fn run(self) {
+        let (sku, currency) = (&self.key.0, &self.key.1);
+        match self.provider.resolve_price(sku, currency) {
+            Ok(unit_minor) => self.cache.put(self.key, unit_minor, Utc::now()),
+            Err(e) => {
+                eprintln!("WARNING: Price refresh failed for SKU {}: {}", sku, e);
+                if let Some(entry) = self.cache.entries().get_mut(&self.key) {
+                    entry.refreshing = false;
+                }
+            },
+        }
+    }
+}
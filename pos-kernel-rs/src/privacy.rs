@@ -0,0 +1,55 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Customer data privacy
+//! REGULATORY COMPLIANCE: GDPR erasure requests are served by replacing a customer token with a
+//! random pseudonym everywhere the kernel holds it. Amounts, lines and tenders are never touched,
+//! so financial records stay intact; the operation is evidenced by a signed record.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnonymizationRecord {
+    pub pseudonym: String,
+    pub anonymized_at: DateTime<Utc>,
+    pub transactions_updated: usize,
+    // Entries rewritten in the journal file; in memory for a kernel without a data directory
+    pub journal_entries_updated: usize,
+    pub events_updated: usize,
+    // HMAC-SHA256 (hex) over `signed_payload()`
+    pub signature: String,
+}
+
+impl AnonymizationRecord {
+    // Upper bound on the serialized record, so FFI callers can size buffers before anything is erased
+    pub const MAX_JSON_LEN: usize = 512;
+
+    pub fn signed_payload(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}",
+            self.pseudonym,
+            self.anonymized_at.to_rfc3339(),
+            self.transactions_updated,
+            self.journal_entries_updated,
+            self.events_updated
+        )
+    }
+}
+
+pub fn new_pseudonym() -> String {
+    format!("anon-{}", uuid::Uuid::new_v4().simple())
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Receipts
+//! A committed transaction is turned into a structured, culture-neutral `ReceiptDocument`:
+//! amounts stay in minor units and no text is localized, so rendering layers (printers,
+//! templates, digital receipts) decide presentation.
+//! ARCHITECTURAL PRINCIPLE: Receipt delivery (email, SMS, app) is a user-space integration.
+//! Registered `ReceiptDeliverer`s are handed the document after commit (by a persistence
+//! worker, off the request path), and again on request for re-delivery; the kernel never
+//! formats or addresses receipts itself.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::fiscal::{FiscalRegistration, TseSignature};
+use crate::loyalty::LoyaltyActivity;
+use crate::peripheral::WeightReading;
+use crate::{write_store_for_api, KernelError, Line, LineKind, OrderReference, TaxEntry, TenderKind, Transaction};
+
+// Store-supplied header and footer text blocks, printed verbatim
+#[derive(Debug, Clone, Default)]
+pub struct ReceiptLayout {
+    pub header_lines: Vec<String>,
+    pub footer_lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptHeader {
+    pub store: String,
+    pub transaction_id: u64,
+    pub started_at: DateTime<Utc>,
+    pub committed_at: Option<DateTime<Utc>>,
+    pub currency: String,
+    pub decimal_places: u8,
+    pub order_reference: OrderReference,
+    pub customer_tier: Option<String>,
+    pub text: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptLine {
+    pub line_id: u32,
+    pub sku: String,
+    pub description: Option<String>,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub total_minor: i64,
+    pub kind: LineKind,
+    // Net weight and tare of a weighed line, printed for Weights & Measures
+    pub weight: Option<WeightReading>,
+    // Modifiers, deposits and other linked items, in entry order
+    pub children: Vec<ReceiptLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptDiscount {
+    pub line_id: u32,
+    pub reason_code: Option<String>,
+    pub amount_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptTender {
+    pub kind: TenderKind,
+    pub amount_minor: i64,
+    pub points: Option<i64>,
+    pub tip_minor: Option<i64>,
+    pub auth_reference: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptDocument {
+    pub header: ReceiptHeader,
+    pub lines: Vec<ReceiptLine>,
+    pub discounts: Vec<ReceiptDiscount>,
+    pub taxes: Vec<TaxEntry>,
+    pub subtotal_minor: i64,
+    pub total_minor: i64,
+    pub tenders: Vec<ReceiptTender>,
+    pub tendered_minor: i64,
+    pub change_minor: i64,
+    pub loyalty: Option<LoyaltyActivity>,
+    pub footer: Vec<String>,
+    // REGULATORY COMPLIANCE: Payload for the jurisdiction's receipt QR code, when required
+    pub fiscal_qr: Option<String>,
+    pub tse_signature: Option<TseSignature>,
+    // Set when the TSE failed; the receipt must state that signing was not possible
+    pub tse_error: Option<String>,
+    pub fiscal_registration: Option<FiscalRegistration>,
+    pub fiscal_error: Option<String>,
+}
+
+impl ReceiptDocument {
+    // Voided lines (quantity zero) are omitted; manual adjustments are listed as discounts
+    pub(crate) fn build(tx: &Transaction, layout: &ReceiptLayout) -> Self {
+        let lines = tx.lines.iter()
+            .filter(|l| l.parent_line_item_id.is_none() && l.kind != LineKind::Adjustment && l.qty != 0)
+            .map(|l| Self::build_line(tx, l))
+            .collect();
+
+        let discounts = tx.lines.iter()
+            .filter(|l| l.kind == LineKind::Adjustment)
+            .map(|l| ReceiptDiscount {
+                line_id: l.line_id,
+                reason_code: l.reason_code.clone(),
+                amount_minor: l.total_minor(),
+            })
+            .collect();
+
+        let tenders = tx.tenders.iter()
+            .map(|t| ReceiptTender {
+                kind: t.kind,
+                amount_minor: t.amount_minor,
+                points: t.points,
+                tip_minor: t.card.as_ref().map(|c| c.tip_minor),
+                auth_reference: t.card.as_ref().map(|c| c.auth_reference.clone()),
+            })
+            .collect();
+
+        Self {
+            header: ReceiptHeader {
+                store: tx.store.clone(),
+                transaction_id: tx.id,
+                started_at: tx.started_at,
+                committed_at: tx.committed_at,
+                currency: tx.currency.code.clone(),
+                decimal_places: tx.currency.decimal_places,
+                order_reference: tx.order_reference.clone(),
+                customer_tier: tx.customer.as_ref().and_then(|c| c.tier.clone()),
+                text: layout.header_lines.clone(),
+            },
+            lines,
+            discounts,
+            taxes: tx.taxes.clone(),
+            subtotal_minor: tx.lines_total_minor(),
+            total_minor: tx.total_minor(),
+            tenders,
+            tendered_minor: tx.tendered_minor,
+            change_minor: tx.change_minor(),
+            loyalty: tx.loyalty.clone(),
+            footer: layout.footer_lines.clone(),
+            fiscal_qr: None,
+            tse_signature: tx.tse_signature.clone(),
+            tse_error: tx.tse_error.clone(),
+            fiscal_registration: tx.fiscal_registration.clone(),
+            fiscal_error: tx.fiscal_error.clone(),
+        }
+    }
+
+    fn build_line(tx: &Transaction, line: &Line) -> ReceiptLine {
+        ReceiptLine {
+            line_id: line.line_id,
+            sku: line.sku.to_string(),
+            description: line.product_name.clone(),
+            qty: line.qty,
+            unit_minor: line.unit_minor,
+            total_minor: line.total_minor(),
+            kind: line.kind,
+            weight: tx.weight_readings.get(&line.line_id).cloned(),
+            children: tx.lines.iter()
+                .filter(|c| c.parent_line_item_id == Some(line.line_id) && c.qty != 0)
+                .map(|c| Self::build_line(tx, c))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum DeliveryReason {
+    Commit,
+    Redelivery,
+}
+
+pub trait ReceiptDeliverer: Send + Sync {
+    // Identifies the deliverer in delivery events
+    fn name(&self) -> &str;
+
+    // Delivers the receipt; addressing (email, phone) is resolved by the deliverer
+    fn deliver(&self, receipt: &ReceiptDocument, reason: DeliveryReason) -> Result<(), String>;
+}
+
+// Registers an additional receipt deliverer
+pub fn add_receipt_deliverer(deliverer: Box<dyn ReceiptDeliverer>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.receipt_deliverers.push(Arc::from(deliverer));
+    Ok(())
+}
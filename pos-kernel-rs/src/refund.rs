@@ -0,0 +1,200 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Refund velocity controls
+//! SECURITY: Merchandise returns are checked against configurable limits: the amount refunded
+//! in one transaction, the number of refund transactions and amount refunded per operator per
+//! shift, and returns without an original receipt. A triggered limit blocks the return unless
+//! a supervisor holding the RefundOverride permission approves it; both outcomes are audited.
+//! Returns still open in an operator's other transactions count against their shift limits.
+//! The counters are kept in memory and rebuilt from the journal on recovery.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::{Journal, JournalOperation};
+use crate::pii::{self, PiiCategory};
+use crate::LineKind;
+
+pub const REFUND_LIMIT_EXCEEDED: &str = "Refund limit exceeded";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefundLimit {
+    TransactionAmount,
+    ShiftCount,
+    ShiftAmount,
+    NoReceipt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RefundLimits {
+    // 0 disables the amount and count checks
+    pub max_per_transaction_minor: i64,
+    pub max_count_per_shift: u32,
+    pub max_amount_per_shift_minor: i64,
+    pub require_receipt: bool,
+}
+
+// Committed refunds for one operator since their shift started
+#[derive(Debug, Clone, Serialize)]
+pub struct ShiftRefunds {
+    pub started_at: DateTime<Utc>,
+    pub refund_count: u32,
+    pub refunded_minor: i64,
+}
+
+impl ShiftRefunds {
+    fn new(started_at: DateTime<Utc>) -> Self {
+        Self { started_at, refund_count: 0, refunded_minor: 0 }
+    }
+}
+
+// A return about to be added, with the refund already pending in its transaction and in the
+// operator's other open transactions
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RefundRequest<'a> {
+    pub amount_minor: i64,
+    pub transaction_refund_minor: i64,
+    // The operator's other open transactions holding returns, and their refund total
+    pub other_pending_count: u32,
+    pub other_pending_minor: i64,
+    pub has_receipt: bool,
+    pub operator_id: Option<&'a str>,
+}
+
+// Operators are keyed as the journal records them, so counters rebuilt from it match the
+// live ones. An ID the PII policy redacts is kept as given and cannot be rebuilt.
+fn operator_key(operator_id: &str) -> String {
+    match pii::protect(PiiCategory::OperatorId, operator_id) {
+        protected if protected == pii::REDACTED => operator_id.to_string(),
+        protected => protected.into_owned(),
+    }
+}
+
+// An open transaction while the journal is folded: its operator and live return lines
+#[derive(Default)]
+struct OpenReturns {
+    operator_id: Option<String>,
+    returns_minor: HashMap<u32, i64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RefundTracker {
+    shifts: HashMap<String, ShiftRefunds>,
+}
+
+impl RefundTracker {
+    // RESILIENCE: Replays the journal's shift starts and committed returns. Operator IDs the
+    // PII policy redacted are skipped, as their refunds cannot be attributed.
+    pub fn from_journal(journal: &Journal) -> Result<Self, String> {
+        let mut tracker = Self::default();
+        let mut open: HashMap<u64, OpenReturns> = HashMap::new();
+        journal.scan_after(0, |entry| {
+            let handle = entry.transaction_handle;
+            match &entry.operation {
+                JournalOperation::ShiftStart { operator_id } if operator_id != pii::REDACTED => {
+                    tracker.shifts.insert(operator_id.clone(), ShiftRefunds::new(entry.timestamp));
+                },
+                JournalOperation::OperatorAssign { operator_id: Some(operator_id), .. } => {
+                    open.entry(handle).or_default().operator_id = Some(operator_id.clone());
+                },
+                JournalOperation::LineAdd { line_id, qty, unit_minor, kind: LineKind::Return, .. } => {
+                    let refund_minor = unit_minor.saturating_mul(i64::from(*qty));
+                    open.entry(handle).or_default().returns_minor.insert(*line_id, refund_minor);
+                },
+                JournalOperation::LineVoid { line_id, .. } => {
+                    if let Some(tx) = open.get_mut(&handle) {
+                        tx.returns_minor.remove(line_id);
+                    }
+                },
+                JournalOperation::LinesMove { line_ids, .. } => {
+                    if let Some(tx) = open.get_mut(&handle) {
+                        for line_id in line_ids {
+                            tx.returns_minor.remove(line_id);
+                        }
+                    }
+                },
+                JournalOperation::TransactionCommit { .. } => {
+                    let Some(tx) = open.remove(&handle) else { return true };
+                    let refunded_minor: i64 = tx.returns_minor.values().sum();
+                    match tx.operator_id {
+                        Some(operator_id) if refunded_minor > 0 && operator_id != pii::REDACTED => {
+                            tracker.record_key(operator_id, refunded_minor, entry.timestamp);
+                        },
+                        _ => {},
+                    }
+                },
+                JournalOperation::TransactionAbandon { .. } | JournalOperation::LayawayCancel { .. } => {
+                    open.remove(&handle);
+                },
+                _ => {},
+            }
+            true
+        })?;
+        Ok(tracker)
+    }
+
+    // Starting a shift resets the operator's counters
+    pub fn start_shift(&mut self, operator_id: &str, now: DateTime<Utc>) {
+        self.shifts.insert(operator_key(operator_id), ShiftRefunds::new(now));
+    }
+
+    pub fn shift(&self, operator_id: &str) -> Option<&ShiftRefunds> {
+        self.shifts.get(&operator_key(operator_id))
+    }
+
+    // Counts a committed refund transaction; operators without a started shift begin one here
+    pub fn record(&mut self, operator_id: &str, refunded_minor: i64, now: DateTime<Utc>) {
+        self.record_key(operator_key(operator_id), refunded_minor, now);
+    }
+
+    fn record_key(&mut self, key: String, refunded_minor: i64, now: DateTime<Utc>) {
+        let shift = self.shifts.entry(key).or_insert_with(|| ShiftRefunds::new(now));
+        shift.refund_count += 1;
+        shift.refunded_minor = shift.refunded_minor.saturating_add(refunded_minor);
+    }
+
+    // Returns every limit the request would exceed
+    pub fn check(&self, limits: RefundLimits, request: RefundRequest) -> Vec<RefundLimit> {
+        let mut triggered = Vec::new();
+        let transaction_minor = request.transaction_refund_minor.saturating_add(request.amount_minor);
+        if limits.max_per_transaction_minor > 0 && transaction_minor > limits.max_per_transaction_minor {
+            triggered.push(RefundLimit::TransactionAmount);
+        }
+
+        if let Some(operator_id) = request.operator_id {
+            let (count, refunded) = self.shift(operator_id)
+                .map_or((0, 0), |s| (s.refund_count, s.refunded_minor));
+            // The pending transaction counts once, with its first return
+            let pending_count = u32::from(request.transaction_refund_minor == 0) + request.other_pending_count;
+            if limits.max_count_per_shift > 0 && count + pending_count > limits.max_count_per_shift {
+                triggered.push(RefundLimit::ShiftCount);
+            }
+            let pending_minor = transaction_minor.saturating_add(request.other_pending_minor);
+            if limits.max_amount_per_shift_minor > 0
+                && refunded.saturating_add(pending_minor) > limits.max_amount_per_shift_minor {
+                triggered.push(RefundLimit::ShiftAmount);
+            }
+        }
+
+        if limits.require_receipt && !request.has_receipt {
+            triggered.push(RefundLimit::NoReceipt);
+        }
+        triggered
+    }
+}
@@ -0,0 +1,447 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Offline journal replay
+//! Support reproduces field issues from a copy of a lane's `journal.wal` without a running
+//! kernel. The replay folds the entries one at a time into the state they describe:
+//! transactions with their lines, taxes, tenders and state, drawer sessions and shifts. Each
+//! step reports the state it changed, field by field. The state is rebuilt from the journal
+//! alone: providers, plugins and configuration are not consulted, so taxes and prices are the
+//! ones journaled. Entries failing their checksum and gaps in the sequence are flagged rather
+//! than skipped, since they are often what is being looked for. Used by the `pk-replay` binary.
+//! The same fold over one transaction's entries gives its history, for reconstructing a
+//! disputed receipt entry by entry.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::cash::CashMovementKind;
+use crate::journal::{JournalEntry, JournalOperation};
+use crate::{LineKind, TransactionKind, PK_INVALID_HANDLE};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayedLine {
+    pub sku: String,
+    pub qty: i32,
+    pub unit_minor: i64,
+    pub kind: LineKind,
+    pub parent_line_id: Option<u32>,
+    pub reason_code: Option<String>,
+    pub approved_by: Option<String>,
+    // Set once the line is voided; voided lines stay for the record
+    pub void_reason: Option<String>,
+}
+
+impl ReplayedLine {
+    fn total_minor(&self) -> i64 {
+        if self.void_reason.is_some() {
+            return 0;
+        }
+        let gross = self.unit_minor * i64::from(self.qty);
+        match self.kind {
+            LineKind::Return | LineKind::DepositReturn => -gross,
+            LineKind::Sale | LineKind::Deposit | LineKind::Adjustment => gross,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayedTransaction {
+    pub store: Option<String>,
+    pub currency: Option<String>,
+    pub kind: TransactionKind,
+    // "building", "layaway", "committed" or "cancelled"
+    pub state: &'static str,
+    pub terminal_id: Option<String>,
+    pub operator_id: Option<String>,
+    pub customer: Option<String>,
+    pub lines: BTreeMap<u32, ReplayedLine>,
+    pub exclusive_tax_minor: i64,
+    pub total_minor: i64,
+    pub tendered_minor: i64,
+    pub attributes: BTreeMap<String, String>,
+    pub began_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub archived: bool,
+}
+
+impl ReplayedTransaction {
+    fn recompute(&mut self) {
+        self.total_minor = self.lines.values().map(ReplayedLine::total_minor).sum::<i64>() + self.exclusive_tax_minor;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayedDrawer {
+    pub operator_id: Option<String>,
+    pub currency: String,
+    pub float_minor: i64,
+    // Drops and pickups count negative, loans positive
+    pub movements_minor: i64,
+    pub opened_at: DateTime<Utc>,
+    // Once closed: the count and its variance against the expected amount
+    pub counted_minor: Option<i64>,
+    pub variance_minor: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayState {
+    pub last_sequence: u64,
+    pub transactions: BTreeMap<u64, ReplayedTransaction>,
+    // The latest session per terminal
+    pub drawer_sessions: BTreeMap<String, ReplayedDrawer>,
+    // Shift start per operator
+    pub shifts: BTreeMap<String, DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayEntry {
+    pub sequence_number: u64,
+    pub timestamp: DateTime<Utc>,
+    pub transaction_handle: u64,
+    // The journal operation name, such as `LineAdd`
+    pub operation: String,
+    pub checksum_valid: bool,
+    // The operation as journaled
+    pub detail: Value,
+}
+
+impl ReplayEntry {
+    fn new(entry: &JournalEntry) -> Self {
+        let detail = serde_json::to_value(&entry.operation).unwrap_or_default();
+        Self {
+            sequence_number: entry.sequence_number,
+            timestamp: entry.timestamp,
+            transaction_handle: entry.transaction_handle,
+            operation: detail.get("op").and_then(Value::as_str).unwrap_or_default().to_string(),
+            checksum_valid: entry.checksum_valid(),
+            detail,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    // Dotted, as in `transactions.7.lines.2.void_reason`
+    pub path: String,
+    // None where the field did not exist before, or no longer does
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayStep {
+    pub entry: ReplayEntry,
+    // The sequence number expected here, when entries are missing before this one
+    pub expected_sequence: Option<u64>,
+    pub changes: Vec<StateChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub entry: ReplayEntry,
+    // Working the transaction when the entry was journaled
+    pub terminal_id: Option<String>,
+    pub operator_id: Option<String>,
+    // Paths are relative to the transaction, as in `lines.2.void_reason`
+    pub changes: Vec<StateChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionHistory {
+    pub handle: u64,
+    pub entries: Vec<HistoryEntry>,
+    // The transaction as its entries leave it
+    pub transaction: Option<ReplayedTransaction>,
+}
+
+// The journal entries of one transaction, in order, each with what it changed
+// with the PII policy applied. Checksums are checked as journaled.
+pub(crate) fn transaction_history(handle: u64, entries: Vec<JournalEntry>) -> TransactionHistory {
+    let checksums_valid: Vec<bool> = entries.iter().map(JournalEntry::checksum_valid).collect();
+    let entries = entries.iter().map(JournalEntry::protected).collect();
+    let mut replay = JournalReplay { entries, next: 0, state: ReplayState::default(), unreadable_lines: 0 };
+    let prefix = format!("transactions.{}.", handle);
+    let mut history = Vec::with_capacity(replay.len());
+    while let Some(step) = replay.step() {
+        let tx = replay.state.transactions.get(&handle);
+        let checksum_valid = checksums_valid[history.len()];
+        history.push(HistoryEntry {
+            entry: ReplayEntry { checksum_valid, ..step.entry },
+            terminal_id: tx.and_then(|tx| tx.terminal_id.clone()),
+            operator_id: tx.and_then(|tx| tx.operator_id.clone()),
+            changes: step.changes.into_iter()
+                .map(|change| StateChange { path: change.path.strip_prefix(&prefix).unwrap_or(&change.path).to_string(), ..change })
+                .collect(),
+        });
+    }
+    TransactionHistory { handle, entries: history, transaction: replay.state.transactions.remove(&handle) }
+}
+
+// The transaction as its journal entries leave it, without the history
+pub(crate) fn replay_transaction(handle: u64, entries: Vec<JournalEntry>) -> Option<ReplayedTransaction> {
+    let mut replay = JournalReplay { entries: Vec::new(), next: 0, state: ReplayState::default(), unreadable_lines: 0 };
+    for entry in &entries {
+        replay.apply(entry);
+    }
+    replay.state.transactions.remove(&handle)
+}
+
+pub struct JournalReplay {
+    entries: Vec<JournalEntry>,
+    next: usize,
+    state: ReplayState,
+    unreadable_lines: usize,
+}
+
+impl JournalReplay {
+    // Reads a journal file; unreadable lines are counted and skipped
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+        let mut entries = Vec::new();
+        let mut unreadable_lines = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<JournalEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => unreadable_lines += 1,
+            }
+        }
+        Ok(Self { entries, next: 0, state: ReplayState::default(), unreadable_lines })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn unreadable_lines(&self) -> usize {
+        self.unreadable_lines
+    }
+
+    pub fn state(&self) -> &ReplayState {
+        &self.state
+    }
+
+    // The entry the next step applies
+    pub fn peek(&self) -> Option<ReplayEntry> {
+        self.entries.get(self.next).map(ReplayEntry::new)
+    }
+
+    pub fn step(&mut self) -> Option<ReplayStep> {
+        let entry = self.entries.get(self.next)?.clone();
+        self.next += 1;
+
+        let expected = self.state.last_sequence + 1;
+        let expected_sequence = (self.state.last_sequence > 0 && entry.sequence_number != expected).then_some(expected);
+        let before = self.touched(&entry);
+        self.apply(&entry);
+        let after = self.touched(&entry);
+        self.state.last_sequence = entry.sequence_number;
+
+        let mut changes = Vec::new();
+        diff("", Some(&before), Some(&after), &mut changes);
+        Some(ReplayStep { entry: ReplayEntry::new(&entry), expected_sequence, changes })
+    }
+
+    // The parts of the state an entry can change, so a step does not serialize all of it
+    fn touched(&self, entry: &JournalEntry) -> Value {
+        let mut touched = serde_json::Map::new();
+        if entry.transaction_handle != PK_INVALID_HANDLE {
+            let tx = serde_json::to_value(self.state.transactions.get(&entry.transaction_handle)).unwrap_or_default();
+            touched.insert("transactions".to_string(), serde_json::json!({ entry.transaction_handle.to_string(): tx }));
+        }
+        match &entry.operation {
+            JournalOperation::DrawerSessionOpen { terminal_id, .. }
+            | JournalOperation::CashMovement { terminal_id, .. }
+            | JournalOperation::DrawerSessionClose { terminal_id, .. } => {
+                let drawer = serde_json::to_value(self.state.drawer_sessions.get(terminal_id)).unwrap_or_default();
+                touched.insert("drawer_sessions".to_string(), serde_json::json!({ terminal_id.clone(): drawer }));
+            },
+            JournalOperation::ShiftStart { operator_id } => {
+                let shift = serde_json::to_value(self.state.shifts.get(operator_id)).unwrap_or_default();
+                touched.insert("shifts".to_string(), serde_json::json!({ operator_id.clone(): shift }));
+            },
+            _ => {},
+        }
+        Value::Object(touched)
+    }
+
+    fn apply(&mut self, entry: &JournalEntry) {
+        let at = entry.timestamp;
+        let state = &mut self.state;
+        match &entry.operation {
+            JournalOperation::ShiftStart { operator_id } => {
+                state.shifts.insert(operator_id.clone(), at);
+                return;
+            },
+            JournalOperation::DrawerSessionOpen { terminal_id, operator_id, currency, float_minor } => {
+                state.drawer_sessions.insert(terminal_id.clone(), ReplayedDrawer {
+                    operator_id: operator_id.clone(),
+                    currency: currency.clone(),
+                    float_minor: *float_minor,
+                    movements_minor: 0,
+                    opened_at: at,
+                    counted_minor: None,
+                    variance_minor: None,
+                });
+                return;
+            },
+            JournalOperation::CashMovement { terminal_id, kind, amount_minor, .. } => {
+                if let Some(drawer) = state.drawer_sessions.get_mut(terminal_id) {
+                    drawer.movements_minor += match kind {
+                        CashMovementKind::Loan => *amount_minor,
+                        CashMovementKind::Drop | CashMovementKind::Pickup => -amount_minor,
+                    };
+                }
+                return;
+            },
+            JournalOperation::DrawerSessionClose { terminal_id, counted_minor, variance_minor, .. } => {
+                if let Some(drawer) = state.drawer_sessions.get_mut(terminal_id) {
+                    drawer.counted_minor = Some(*counted_minor);
+                    drawer.variance_minor = Some(*variance_minor);
+                }
+                return;
+            },
+            _ => {},
+        }
+
+        if entry.transaction_handle == PK_INVALID_HANDLE {
+            return;
+        }
+        let tx = state.transactions.entry(entry.transaction_handle).or_insert_with(|| ReplayedTransaction {
+            state: "building",
+            ..ReplayedTransaction::default()
+        });
+        match &entry.operation {
+            JournalOperation::TransactionBegin { store, currency, kind, .. } => {
+                tx.store = Some(store.clone());
+                tx.currency = Some(currency.clone());
+                tx.kind = *kind;
+                tx.began_at = Some(at);
+            },
+            JournalOperation::LineAdd { line_id, sku, qty, unit_minor, parent_line_id, kind, reason_code, approved_by, .. } => {
+                tx.lines.insert(*line_id, ReplayedLine {
+                    sku: sku.to_string(),
+                    qty: *qty,
+                    unit_minor: *unit_minor,
+                    kind: *kind,
+                    parent_line_id: *parent_line_id,
+                    reason_code: reason_code.clone(),
+                    approved_by: approved_by.clone(),
+                    void_reason: None,
+                });
+            },
+            JournalOperation::LineVoid { line_id, reason } => {
+                if let Some(line) = tx.lines.get_mut(line_id) {
+                    line.void_reason = Some(reason.clone());
+                }
+            },
+            // The lines are journaled again as added to the transaction they moved to
+            JournalOperation::LinesMove { line_ids, .. } => {
+                for line_id in line_ids {
+                    tx.lines.remove(line_id);
+                }
+            },
+            JournalOperation::TaxAdd { entry } if !entry.inclusive => tx.exclusive_tax_minor += entry.tax_minor,
+            JournalOperation::TaxClear => tx.exclusive_tax_minor = 0,
+            JournalOperation::TenderAdd { amount_minor, .. } => tx.tendered_minor += amount_minor,
+            JournalOperation::LayawayCreate { deposit_minor } => {
+                tx.tendered_minor += deposit_minor;
+                tx.state = "layaway";
+            },
+            JournalOperation::LayawayPayment { amount_minor } => tx.tendered_minor += amount_minor,
+            JournalOperation::LayawayCancel { .. } | JournalOperation::TransactionAbandon { .. } => {
+                tx.state = "cancelled";
+                tx.closed_at = Some(at);
+            },
+            // The tip is settled with the card but is not part of the amount tendered
+            JournalOperation::TabCapture { captured_minor, tip_minor } => tx.tendered_minor += captured_minor - tip_minor,
+            JournalOperation::TransactionCommit { .. } => {
+                tx.state = "committed";
+                tx.closed_at = Some(at);
+            },
+            JournalOperation::TransactionArchive { .. } => tx.archived = true,
+            JournalOperation::AttributeSet { key, value } => {
+                tx.attributes.insert(key.clone(), value.clone());
+            },
+            JournalOperation::AttributeRemove { key } => {
+                tx.attributes.remove(key);
+            },
+            JournalOperation::CustomerAttach { token, .. } => tx.customer = Some(token.clone()),
+            JournalOperation::OperatorAssign { terminal_id, operator_id } => {
+                if terminal_id.is_some() {
+                    tx.terminal_id = terminal_id.clone();
+                }
+                if operator_id.is_some() {
+                    tx.operator_id = operator_id.clone();
+                }
+            },
+            _ => {},
+        }
+        tx.recompute();
+    }
+}
+
+// Leaf-level differences between two JSON values; objects are compared field by field, and
+// anything else as a whole
+fn diff(path: &str, before: Option<&Value>, after: Option<&Value>, changes: &mut Vec<StateChange>) {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff(&path, before.get(key), after.get(key), changes);
+            }
+        },
+        // A part that appears or goes as a whole is listed by its fields
+        (Some(Value::Null), Some(Value::Object(after))) => diff(path, None, Some(&Value::Object(after.clone())), changes),
+        (None, Some(Value::Object(after))) if !after.is_empty() => {
+            for (key, value) in after {
+                diff(&format!("{}.{}", path, key), None, Some(value), changes);
+            }
+        },
+        (before, after) => {
+            let (before, after) = (present(before), present(after));
+            if before != after {
+                changes.push(StateChange { path: path.to_string(), before: before.cloned(), after: after.cloned() });
+            }
+        },
+    }
+}
+
+// Null fields and empty collections count as absent
+fn present(value: Option<&Value>) -> Option<&Value> {
+    value.filter(|v| match v {
+        Value::Null => false,
+        Value::Object(fields) => !fields.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => true,
+    })
+}
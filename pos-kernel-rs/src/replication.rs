@@ -0,0 +1,455 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Primary/replica journal replication
+//! ARCHITECTURAL PRINCIPLE: A warm standby keeps a copy of the primary's journal, entry for
+//! entry, so a lane server failure loses no committed sale. The primary listens on a TCP
+//! address; each replica connects, names the last sequence number it holds with that entry's
+//! checksum and is streamed every later entry as a JSON line, with the primary's sequence
+//! numbers, timestamps and checksums. A replica whose last entry the primary does not hold
+//! with the same checksum has diverged (a different store, or a primary restored from an older
+//! backup) and is refused rather than streamed a mismatched tail. An idle primary sends an
+//! empty line as a heartbeat, so a replica notices a dead primary within `READ_TIMEOUT` and
+//! keeps reconnecting, resuming from its own journal.
+//! SECURITY: The journal carries customer tokens, so both sides prove they hold the shared
+//! replication secret before anything is streamed: the primary challenges the replica with a
+//! nonce, the replica answers with an HMAC-SHA256 over it and a nonce of its own, and the
+//! primary answers that in turn. The stream itself is not encrypted. A bare port listens on
+//! the loopback interface only; across hosts, carry the link over a TLS tunnel or VPN.
+//! A replica is read-only: its journal takes replicated entries only, and every operation that
+//! would journal (sales, shifts, drawer sessions and cash movements, no-sale drawer opens,
+//! fiscal days, rules scripts, anonymization, sync ingest) is refused with InvalidState.
+//! Promotion stops replication, makes the journal writable and moves the handle sequences past
+//! every handle replicated, so the promoted kernel can take over the store and serve replicas
+//! of its own. The failover procedure is described in docs/c-abi/README.md.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::JournalEntry;
+use crate::{read_store_for_api, signing, KernelError};
+
+pub(crate) const REPLICA_READ_ONLY: &str = "Kernel is a read-only replica";
+pub(crate) const NOT_A_REPLICA: &str = "Kernel is not a replica";
+pub(crate) const REPLICATION_STARTED: &str = "Replication already started";
+pub(crate) const NO_REPLICATION_SECRET: &str = "No replication secret configured";
+const REPLICA_DIVERGED: &str = "Replica journal diverges from the primary";
+const REPLICA_UNAUTHENTICATED: &str = "Replica failed authentication";
+const PRIMARY_UNAUTHENTICATED: &str = "Primary failed authentication";
+const MIN_SECRET_BYTES: usize = 16;
+
+// How often a primary looks for new entries while a replica is caught up
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Entries sent per read of the primary's journal
+const SEND_BATCH: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReplicationRole {
+    Standalone,
+    Primary,
+    Replica,
+    // A former replica that took over from its primary
+    Promoted,
+}
+
+// Sent by the primary when a replica connects
+#[derive(Debug, Serialize, Deserialize)]
+struct Challenge {
+    nonce: String,
+}
+
+// The replica's answer to the challenge
+#[derive(Debug, Serialize, Deserialize)]
+struct Handshake {
+    after_sequence: u64,
+    // The checksum of the replica's entry at `after_sequence`; None when its journal is empty
+    #[serde(default)]
+    checksum: Option<u32>,
+    // Challenges the primary in turn
+    nonce: String,
+    // HMAC over the primary's nonce and the fields above
+    mac: String,
+}
+
+impl Handshake {
+    fn signed_payload(&self, challenge: &str) -> String {
+        let checksum = self.checksum.map(|c| c.to_string()).unwrap_or_default();
+        format!("replica\n{}\n{}\n{}\n{}", challenge, self.nonce, self.after_sequence, checksum)
+    }
+}
+
+// Sent by the primary once the replica is authenticated and has not diverged
+#[derive(Debug, Serialize, Deserialize)]
+struct Accept {
+    // HMAC over both nonces
+    mac: String,
+}
+
+fn accept_payload(challenge: &str, replica_nonce: &str) -> String {
+    format!("primary\n{}\n{}", replica_nonce, challenge)
+}
+
+fn new_nonce() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn send_line(mut stream: &TcpStream, message: &impl Serialize) -> Result<(), String> {
+    let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    stream.write_all(format!("{}\n", line).as_bytes()).map_err(|e| e.to_string())
+}
+
+fn read_message<T: for<'de> Deserialize<'de>>(reader: &mut impl BufRead) -> Result<T, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("Connection closed".to_string()),
+        Ok(_) => serde_json::from_str(&line).map_err(|e| format!("Invalid replication handshake: {}", e)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaLink {
+    pub peer: String,
+    pub connected_at: DateTime<Utc>,
+    pub sent_through: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    pub role: ReplicationRole,
+    // The address listened on (primary) or replicated from (replica)
+    pub address: Option<String>,
+    // Primary: the replicas streaming from it
+    pub replicas: Vec<ReplicaLink>,
+    // Replica: connected to the primary
+    pub connected: bool,
+    // Replica: the last sequence number received
+    pub replicated_through: u64,
+    pub last_error: Option<String>,
+    pub promoted_at: Option<DateTime<Utc>>,
+}
+
+struct Shared {
+    status: Mutex<ReplicationStatus>,
+    // Set to end the current role's threads
+    stop: Mutex<Arc<AtomicBool>>,
+    // Shared by the primary and its replicas; never exported
+    secret: Mutex<Option<Vec<u8>>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Replication {
+    shared: Arc<Shared>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                status: Mutex::new(ReplicationStatus {
+                    role: ReplicationRole::Standalone,
+                    address: None,
+                    replicas: Vec::new(),
+                    connected: false,
+                    replicated_through: 0,
+                    last_error: None,
+                    promoted_at: None,
+                }),
+                stop: Mutex::new(Arc::new(AtomicBool::new(false))),
+                secret: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl Replication {
+    fn status_mut(&self) -> MutexGuard<'_, ReplicationStatus> {
+        self.shared.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shared.stop.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        self.status_mut().clone()
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.status_mut().role == ReplicationRole::Replica
+    }
+
+    // Refuses work that would journal on a replica
+    pub fn check_writable(&self) -> Result<(), KernelError> {
+        match self.is_replica() {
+            true => Err(KernelError::invalid_state(REPLICA_READ_ONLY)),
+            false => Ok(()),
+        }
+    }
+
+    // Connections made from now on authenticate with `secret`
+    pub fn set_secret(&self, secret: Vec<u8>) -> Result<(), KernelError> {
+        if secret.len() < MIN_SECRET_BYTES {
+            return Err(KernelError::Validation(format!("Replication secret must be at least {} bytes", MIN_SECRET_BYTES)));
+        }
+        *self.shared.secret.lock().unwrap_or_else(PoisonError::into_inner) = Some(secret);
+        Ok(())
+    }
+
+    fn secret(&self) -> Result<Vec<u8>, KernelError> {
+        self.shared.secret.lock().unwrap_or_else(PoisonError::into_inner).clone()
+            .ok_or_else(|| KernelError::invalid_state(NO_REPLICATION_SECRET))
+    }
+
+    fn record_error(&self, error: String) {
+        eprintln!("WARNING: Replication: {}", error);
+        self.status_mut().last_error = Some(error);
+    }
+
+    // Listens for replicas on `address`, or on the loopback interface when only a port is
+    // given; a standalone or promoted kernel becomes primary
+    pub fn start_primary(&self, address: &str) -> Result<(), KernelError> {
+        let mut status = self.status_mut();
+        if matches!(status.role, ReplicationRole::Primary | ReplicationRole::Replica) {
+            return Err(KernelError::invalid_state(REPLICATION_STARTED));
+        }
+        self.secret()?;
+
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{}", port),
+            Err(_) => address.to_string(),
+        };
+        let listener = TcpListener::bind(&address)
+            .map_err(|e| KernelError::Internal(format!("Failed to listen for replicas on {}: {}", address, e)))?;
+        status.role = ReplicationRole::Primary;
+        status.address = Some(address);
+        drop(status);
+
+        let (replication, stop) = (self.clone(), self.stop_flag());
+        thread::Builder::new()
+            .name("pk-repl-listen".to_string())
+            .spawn(move || replication.accept(listener, stop))
+            .map_err(|e| KernelError::Internal(format!("Failed to start replication listener: {}", e)))?;
+        Ok(())
+    }
+
+    fn accept(&self, listener: TcpListener, stop: Arc<AtomicBool>) {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    self.record_error(format!("Replica connection failed: {}", e));
+                    continue;
+                },
+            };
+            let (replication, stop) = (self.clone(), Arc::clone(&stop));
+            let spawned = thread::Builder::new()
+                .name("pk-repl-send".to_string())
+                .spawn(move || {
+                    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                    if let Err(e) = replication.serve(stream, &peer, &stop) {
+                        replication.record_error(format!("Replica {} disconnected: {}", peer, e));
+                    }
+                    replication.status_mut().replicas.retain(|r| r.peer != peer);
+                });
+            if let Err(e) = spawned {
+                self.record_error(format!("Failed to start replication sender: {}", e));
+            }
+        }
+    }
+
+    // Streams the journal to one replica until it disconnects or the primary stops
+    fn serve(&self, stream: TcpStream, peer: &str, stop: &AtomicBool) -> Result<(), String> {
+        stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|e| e.to_string())?;
+        let secret = self.secret().map_err(|e| e.to_string())?;
+        let challenge = new_nonce();
+        send_line(&stream, &Challenge { nonce: challenge.clone() })?;
+        let handshake: Handshake = read_message(&mut BufReader::new(&stream))?;
+        if !signing::verify_hex(&secret, handshake.signed_payload(&challenge).as_bytes(), &handshake.mac) {
+            return Err(REPLICA_UNAUTHENTICATED.to_string());
+        }
+        if handshake.after_sequence > 0 {
+            let held = entry_checksum(handshake.after_sequence)?;
+            if held.is_none() || held != handshake.checksum {
+                return Err(format!("{} at sequence {}", REPLICA_DIVERGED, handshake.after_sequence));
+            }
+        }
+        let mac = signing::sign_hex(&secret, accept_payload(&challenge, &handshake.nonce).as_bytes())?;
+        send_line(&stream, &Accept { mac })?;
+
+        let mut sent_through = handshake.after_sequence;
+        self.status_mut().replicas.push(ReplicaLink { peer: peer.to_string(), connected_at: Utc::now(), sent_through });
+        let mut writer = BufWriter::new(&stream);
+        let mut last_sent = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            let mut entries: Vec<JournalEntry> = Vec::new();
+            read_store_for_api().map_err(|e| e.to_string())?.journal.scan_after(sent_through, |entry| {
+                entries.push(entry.clone());
+                entries.len() < SEND_BATCH
+            })?;
+
+            if entries.is_empty() {
+                if last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                    writer.write_all(b"\n").and_then(|_| writer.flush()).map_err(|e| e.to_string())?;
+                    last_sent = Instant::now();
+                }
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+
+            for entry in &entries {
+                let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+                writeln!(writer, "{}", line).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+            last_sent = Instant::now();
+            sent_through = entries.last().map_or(sent_through, |e| e.sequence_number);
+            if let Some(link) = self.status_mut().replicas.iter_mut().find(|r| r.peer == peer) {
+                link.sent_through = sent_through;
+            }
+        }
+        Ok(())
+    }
+
+    // Replicates from the primary at `address`. The kernel becomes read-only until promoted.
+    pub fn start_replica(&self, address: &str) -> Result<(), KernelError> {
+        let mut status = self.status_mut();
+        if status.role != ReplicationRole::Standalone {
+            return Err(KernelError::invalid_state(REPLICATION_STARTED));
+        }
+        self.secret()?;
+
+        status.role = ReplicationRole::Replica;
+        status.address = Some(address.to_string());
+        drop(status);
+
+        let (replication, stop, address) = (self.clone(), self.stop_flag(), address.to_string());
+        thread::Builder::new()
+            .name("pk-repl-recv".to_string())
+            .spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Err(e) = replication.receive(&address, &stop) {
+                        replication.record_error(format!("Primary {}: {}", address, e));
+                    }
+                    replication.status_mut().connected = false;
+                    if !stop.load(Ordering::Relaxed) {
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            })
+            .map_err(|e| KernelError::Internal(format!("Failed to start replication receiver: {}", e)))?;
+        Ok(())
+    }
+
+    // One connection to the primary, resuming after the last entry held locally
+    fn receive(&self, address: &str, stop: &AtomicBool) -> Result<(), String> {
+        let target = address.to_socket_addrs().map_err(|e| e.to_string())?
+            .next()
+            .ok_or("Address did not resolve")?;
+        let stream = TcpStream::connect_timeout(&target, READ_TIMEOUT).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|e| e.to_string())?;
+        let secret = self.secret().map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(&stream);
+        let challenge: Challenge = read_message(&mut reader)?;
+
+        let after_sequence = read_store_for_api().map_err(|e| e.to_string())?.journal.last_sequence();
+        let checksum = match after_sequence {
+            0 => None,
+            _ => entry_checksum(after_sequence)?,
+        };
+        let mut handshake = Handshake { after_sequence, checksum, nonce: new_nonce(), mac: String::new() };
+        handshake.mac = signing::sign_hex(&secret, handshake.signed_payload(&challenge.nonce).as_bytes())?;
+        send_line(&stream, &handshake)?;
+        let accept: Accept = read_message(&mut reader)?;
+        if !signing::verify_hex(&secret, accept_payload(&challenge.nonce, &handshake.nonce).as_bytes(), &accept.mac) {
+            return Err(PRIMARY_UNAUTHENTICATED.to_string());
+        }
+        {
+            let mut status = self.status_mut();
+            status.connected = true;
+            status.replicated_through = after_sequence;
+        }
+
+        let mut line = String::new();
+        while !stop.load(Ordering::Relaxed) {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => return Err("Connection closed".to_string()),
+                Ok(_) => {},
+                Err(e) => return Err(e.to_string()),
+            }
+            // Heartbeat
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry: JournalEntry = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid replicated entry: {}", e))?;
+            let sequence_number = entry.sequence_number;
+            let store = read_store_for_api().map_err(|e| e.to_string())?;
+            // Promotion may have happened while the line was read
+            if stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            store.journal.append_replicated(entry)?;
+            drop(store);
+            let mut status = self.status_mut();
+            status.replicated_through = status.replicated_through.max(sequence_number);
+            status.last_error = None;
+        }
+        Ok(())
+    }
+
+    // Ends replication on a replica; the caller takes the kernel over from the primary
+    pub fn promote(&self) -> Result<u64, KernelError> {
+        let mut status = self.status_mut();
+        if status.role != ReplicationRole::Replica {
+            return Err(KernelError::invalid_state(NOT_A_REPLICA));
+        }
+
+        self.stop_flag().store(true, Ordering::Relaxed);
+        *self.shared.stop.lock().unwrap_or_else(PoisonError::into_inner) = Arc::new(AtomicBool::new(false));
+        status.role = ReplicationRole::Promoted;
+        status.connected = false;
+        status.promoted_at = Some(Utc::now());
+        Ok(status.replicated_through)
+    }
+}
+
+// The checksum of this kernel's journal entry at `sequence`, if it holds one
+fn entry_checksum(sequence: u64) -> Result<Option<u32>, String> {
+    let mut checksum = None;
+    read_store_for_api().map_err(|e| e.to_string())?.journal.scan_after(sequence - 1, |entry| {
+        if entry.sequence_number == sequence {
+            checksum = Some(entry.checksum);
+        }
+        false
+    })?;
+    Ok(checksum)
+}
@@ -0,0 +1,605 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reporting over committed transactions
+//! Reports aggregate committed transactions whose commit time falls in a half-open period
+//! [from, to). Amounts stay in minor units and are grouped per currency; reports are exported
+//! as JSON or CSV for filing and back-office preparation.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Deref;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::journal::{Journal, JournalOperation};
+use crate::pii::{self, PiiCategory};
+use crate::{LineKind, TenderKind, Transaction, TxState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ReportFormat {
+    Json = 1,
+    Csv = 2,
+}
+
+impl ReportFormat {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Json),
+            2 => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ReportPeriod {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl ReportPeriod {
+    pub fn new(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Self, String> {
+        if to <= from {
+            return Err("Report period end must be after its start".to_string());
+        }
+        Ok(Self { from, to })
+    }
+
+    pub(crate) fn includes(&self, tx: &Transaction) -> bool {
+        tx.state == TxState::Committed
+            && tx.committed_at.is_some_and(|t| t >= self.from && t < self.to)
+    }
+}
+
+// REGULATORY COMPLIANCE: Tax collected per jurisdiction and rate
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxSummaryRow {
+    pub currency: String,
+    pub jurisdiction: String,
+    pub rate_code: String,
+    pub rate_basis_points: i32,
+    pub taxable_minor: i64,
+    pub tax_minor: i64,
+    pub exempt_minor: i64,
+    pub transaction_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxSummaryReport {
+    pub period: ReportPeriod,
+    pub rows: Vec<TaxSummaryRow>,
+}
+
+pub(crate) fn tax_summary(transactions: impl Iterator<Item = impl Deref<Target = Transaction>>, period: ReportPeriod) -> TaxSummaryReport {
+    let mut rows: BTreeMap<(String, String, String, i32), TaxSummaryRow> = BTreeMap::new();
+    for tx in transactions.filter(|tx| period.includes(tx)) {
+        for tax in &tx.taxes {
+            let key = (tx.currency.code.clone(), tax.jurisdiction.clone(), tax.rate_code.clone(), tax.rate_basis_points);
+            let row = rows.entry(key).or_insert_with(|| TaxSummaryRow {
+                currency: tx.currency.code.clone(),
+                jurisdiction: tax.jurisdiction.clone(),
+                rate_code: tax.rate_code.clone(),
+                rate_basis_points: tax.rate_basis_points,
+                taxable_minor: 0,
+                tax_minor: 0,
+                exempt_minor: 0,
+                transaction_count: 0,
+            });
+            row.taxable_minor += tax.taxable_minor;
+            row.tax_minor += tax.tax_minor;
+            row.exempt_minor += tax.exempt_minor;
+            row.transaction_count += 1;
+        }
+    }
+
+    TaxSummaryReport {
+        period,
+        rows: rows.into_values().collect(),
+    }
+}
+
+impl TaxSummaryReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("currency,jurisdiction,rate_code,rate_basis_points,taxable_minor,tax_minor,exempt_minor,transaction_count\n");
+        for row in &self.rows {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&row.currency),
+                csv_field(&row.jurisdiction),
+                csv_field(&row.rate_code),
+                row.rate_basis_points,
+                row.taxable_minor,
+                row.tax_minor,
+                row.exempt_minor,
+                row.transaction_count
+            ));
+        }
+        csv
+    }
+}
+
+// Tenders taken and refunds paid out per currency and tender kind; change is given in cash
+#[derive(Debug, Clone, Serialize)]
+pub struct TenderSummaryRow {
+    pub currency: String,
+    pub tender: TenderKind,
+    pub tender_count: u64,
+    pub tendered_minor: i64,
+    pub refund_count: u64,
+    pub refunded_minor: i64,
+    pub change_minor: i64,
+    // Tendered less refunded and change
+    pub net_minor: i64,
+}
+
+// AUDIT COMPLIANCE: Over/short of the drawer sessions closed on a terminal
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawerVarianceRow {
+    pub terminal_id: String,
+    pub session_count: u64,
+    pub expected_minor: i64,
+    pub counted_minor: i64,
+    pub over_minor: i64,
+    pub short_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TenderSummaryReport {
+    pub period: ReportPeriod,
+    pub store: Option<String>,
+    pub tenders: Vec<TenderSummaryRow>,
+    pub drawers: Vec<DrawerVarianceRow>,
+    // Terminals that committed a transaction in the report's store during the period
+    #[serde(skip)]
+    terminals: BTreeSet<String>,
+}
+
+fn tender_name(kind: TenderKind) -> &'static str {
+    match kind {
+        TenderKind::Cash => "Cash",
+        TenderKind::Points => "Points",
+        TenderKind::Card => "Card",
+    }
+}
+
+fn tender_row<'a>(rows: &'a mut BTreeMap<(String, i32), TenderSummaryRow>, currency: &str, kind: TenderKind) -> &'a mut TenderSummaryRow {
+    rows.entry((currency.to_string(), kind as i32)).or_insert_with(|| TenderSummaryRow {
+        currency: currency.to_string(),
+        tender: kind,
+        tender_count: 0,
+        tendered_minor: 0,
+        refund_count: 0,
+        refunded_minor: 0,
+        change_minor: 0,
+        net_minor: 0,
+    })
+}
+
+pub(crate) fn tender_summary(transactions: impl Iterator<Item = impl Deref<Target = Transaction>>, period: ReportPeriod, store: Option<&str>) -> TenderSummaryReport {
+    let mut rows: BTreeMap<(String, i32), TenderSummaryRow> = BTreeMap::new();
+    let mut terminals = BTreeSet::new();
+    let in_store = |tx: &Transaction| store.is_none_or(|store| tx.store == store);
+    for tx in transactions.filter(|tx| period.includes(tx) && in_store(tx)) {
+        terminals.extend(tx.terminal_id.clone());
+        for tender in tx.tenders.iter().filter(|t| t.amount_minor != 0) {
+            let row = tender_row(&mut rows, &tx.currency.code, tender.kind);
+            if tender.amount_minor > 0 {
+                row.tender_count += 1;
+                row.tendered_minor += tender.amount_minor;
+            } else {
+                row.refund_count += 1;
+                row.refunded_minor -= tender.amount_minor;
+            }
+            row.net_minor += tender.amount_minor;
+        }
+        let change_minor = tx.change_minor();
+        if change_minor > 0 {
+            let row = tender_row(&mut rows, &tx.currency.code, TenderKind::Cash);
+            row.change_minor += change_minor;
+            row.net_minor -= change_minor;
+        }
+    }
+
+    TenderSummaryReport {
+        period,
+        store: store.map(str::to_string),
+        tenders: rows.into_values().collect(),
+        drawers: Vec::new(),
+        terminals,
+    }
+}
+
+impl TenderSummaryReport {
+    // A drawer session closed during the period; with a store, only the store's terminals count
+    pub(crate) fn add_drawer_close(&mut self, terminal_id: &str, expected_minor: i64, counted_minor: i64, variance_minor: i64) {
+        if self.store.is_some() && !self.terminals.contains(terminal_id) {
+            return;
+        }
+        let index = match self.drawers.binary_search_by(|row| row.terminal_id.as_str().cmp(terminal_id)) {
+            Ok(index) => index,
+            Err(index) => {
+                self.drawers.insert(index, DrawerVarianceRow {
+                    terminal_id: terminal_id.to_string(),
+                    session_count: 0,
+                    expected_minor: 0,
+                    counted_minor: 0,
+                    over_minor: 0,
+                    short_minor: 0,
+                });
+                index
+            },
+        };
+        let row = &mut self.drawers[index];
+        row.session_count += 1;
+        row.expected_minor += expected_minor;
+        row.counted_minor += counted_minor;
+        row.over_minor += variance_minor.max(0);
+        row.short_minor -= variance_minor.min(0);
+    }
+
+    // The tender table, a blank line, then the drawer table
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("currency,tender,tender_count,tendered_minor,refund_count,refunded_minor,change_minor,net_minor\n");
+        for row in &self.tenders {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&row.currency),
+                tender_name(row.tender),
+                row.tender_count,
+                row.tendered_minor,
+                row.refund_count,
+                row.refunded_minor,
+                row.change_minor,
+                row.net_minor
+            ));
+        }
+        csv.push_str("\nterminal_id,session_count,expected_minor,counted_minor,over_minor,short_minor\n");
+        for row in &self.drawers {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                csv_field(&row.terminal_id),
+                row.session_count,
+                row.expected_minor,
+                row.counted_minor,
+                row.over_minor,
+                row.short_minor
+            ));
+        }
+        csv
+    }
+}
+
+// Rows of a paged report returned when the caller does not ask for fewer
+const DEFAULT_PAGE_ROWS: usize = 100;
+const MAX_PAGE_ROWS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[repr(i32)]
+pub enum MovementGroup {
+    Sku = 0,
+    // By the catalog's department for each SKU
+    Department = 1,
+}
+
+impl MovementGroup {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Self::Sku),
+            1 => Some(Self::Department),
+            _ => None,
+        }
+    }
+}
+
+// MERCHANDISING: Units and revenue of sale and return lines; transaction-level adjustments are
+// not allocated to SKUs
+#[derive(Debug, Clone, Serialize)]
+pub struct MovementRow {
+    pub currency: String,
+    // None when grouped by department
+    pub sku: Option<String>,
+    // None for SKUs the catalog assigns no department
+    pub department: Option<String>,
+    pub units_sold: i64,
+    pub units_returned: i64,
+    pub net_units: i64,
+    pub revenue_minor: i64,
+    pub transaction_count: u64,
+}
+
+// Top sellers first: by net units, then revenue
+#[derive(Debug, Clone, Serialize)]
+pub struct MovementReport {
+    pub period: ReportPeriod,
+    pub store: Option<String>,
+    pub group: MovementGroup,
+    pub total_rows: usize,
+    pub offset: usize,
+    pub rows: Vec<MovementRow>,
+    // Offset of the next page, if there is one
+    pub next_offset: Option<usize>,
+}
+
+fn in_store(tx: &Transaction, store: Option<&str>) -> bool {
+    store.is_none_or(|store| tx.store == store)
+}
+
+// The (currency, SKU) pairs sold or returned in the period, for resolving their departments
+pub(crate) fn moved_skus(transactions: impl Iterator<Item = impl Deref<Target = Transaction>>, period: ReportPeriod, store: Option<&str>) -> BTreeSet<(String, String)> {
+    let mut skus = BTreeSet::new();
+    for tx in transactions.filter(|tx| period.includes(tx) && in_store(tx, store)) {
+        for line in tx.lines.iter().filter(|l| matches!(l.kind, LineKind::Sale | LineKind::Return) && l.qty != 0) {
+            skus.insert((tx.currency.code.clone(), line.sku.to_string()));
+        }
+    }
+    skus
+}
+
+// With `departments` (by currency and SKU), rows are departments; otherwise SKUs
+pub(crate) fn movement(
+    transactions: impl Iterator<Item = impl Deref<Target = Transaction>>,
+    period: ReportPeriod,
+    store: Option<&str>,
+    departments: Option<&HashMap<(String, String), String>>,
+    offset: usize,
+    limit: usize,
+) -> MovementReport {
+    let mut rows: HashMap<(String, Option<String>, Option<String>), MovementRow> = HashMap::new();
+    for tx in transactions.filter(|tx| period.includes(tx) && in_store(tx, store)) {
+        let mut seen = BTreeSet::new();
+        for line in tx.lines.iter().filter(|l| matches!(l.kind, LineKind::Sale | LineKind::Return) && l.qty != 0) {
+            let key = match departments {
+                Some(departments) => (tx.currency.code.clone(), None, departments.get(&(tx.currency.code.clone(), line.sku.to_string())).cloned()),
+                None => (tx.currency.code.clone(), Some(line.sku.to_string()), None),
+            };
+            let row = rows.entry(key.clone()).or_insert_with(|| MovementRow {
+                currency: key.0.clone(),
+                sku: key.1.clone(),
+                department: key.2.clone(),
+                units_sold: 0,
+                units_returned: 0,
+                net_units: 0,
+                revenue_minor: 0,
+                transaction_count: 0,
+            });
+            match line.kind {
+                LineKind::Return => row.units_returned += i64::from(line.qty),
+                _ => row.units_sold += i64::from(line.qty),
+            }
+            row.net_units = row.units_sold - row.units_returned;
+            row.revenue_minor += line.total_minor();
+            if seen.insert(key) {
+                row.transaction_count += 1;
+            }
+        }
+    }
+
+    let mut rows: Vec<MovementRow> = rows.into_values().collect();
+    rows.sort_by(|a, b| b.net_units.cmp(&a.net_units)
+        .then(b.revenue_minor.cmp(&a.revenue_minor))
+        .then_with(|| (&a.currency, &a.sku, &a.department).cmp(&(&b.currency, &b.sku, &b.department))));
+    let total_rows = rows.len();
+    let limit = match limit {
+        0 => DEFAULT_PAGE_ROWS,
+        n => n.min(MAX_PAGE_ROWS),
+    };
+    let rows: Vec<MovementRow> = rows.into_iter().skip(offset).take(limit).collect();
+    let next_offset = Some(offset + rows.len()).filter(|next| *next < total_rows);
+
+    MovementReport {
+        period,
+        store: store.map(str::to_string),
+        group: if departments.is_some() { MovementGroup::Department } else { MovementGroup::Sku },
+        total_rows,
+        offset,
+        rows,
+        next_offset,
+    }
+}
+
+impl MovementReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("currency,sku,department,units_sold,units_returned,net_units,revenue_minor,transaction_count\n");
+        for row in &self.rows {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&row.currency),
+                csv_field(row.sku.as_deref().unwrap_or("")),
+                csv_field(row.department.as_deref().unwrap_or("")),
+                row.units_sold,
+                row.units_returned,
+                row.net_units,
+                row.revenue_minor,
+                row.transaction_count
+            ));
+        }
+        csv
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AuditEventKind {
+    LineVoid,
+    // The whole transaction was voided before commit
+    TransactionAbandon,
+    PriceOverride,
+    Return,
+}
+
+impl AuditEventKind {
+    fn name(self) -> &'static str {
+        match self {
+            AuditEventKind::LineVoid => "LineVoid",
+            AuditEventKind::TransactionAbandon => "TransactionAbandon",
+            AuditEventKind::PriceOverride => "PriceOverride",
+            AuditEventKind::Return => "Return",
+        }
+    }
+}
+
+// SECURITY: One void, price override or return, with the transaction's operator at the time
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEventRow {
+    pub timestamp: DateTime<Utc>,
+    pub kind: AuditEventKind,
+    pub transaction_handle: u64,
+    pub store: Option<String>,
+    pub terminal_id: Option<String>,
+    pub operator_id: Option<String>,
+    // The supervisor who approved it; voids are not approved
+    pub approved_by: Option<String>,
+    pub reason_code: Option<String>,
+    pub line_id: Option<u32>,
+    pub sku: Option<String>,
+    // None when the line was added before the journal starts
+    pub amount_minor: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEventReport {
+    pub period: ReportPeriod,
+    pub operator_id: Option<String>,
+    pub events: Vec<AuditEventRow>,
+}
+
+// What the journal has said so far about a transaction that is still open
+#[derive(Default)]
+struct OpenTransaction {
+    store: Option<String>,
+    terminal_id: Option<String>,
+    operator_id: Option<String>,
+    // SKU and amount of each line not voided
+    lines: HashMap<u32, (String, i64)>,
+}
+
+// Reads the whole journal, since the lines an event refers to may have been added before the
+// period; state is kept only for transactions not yet committed or abandoned
+pub(crate) fn audit_events(journal: &Journal, period: ReportPeriod, operator_id: Option<&str>) -> Result<AuditEventReport, String> {
+    let mut open: HashMap<u64, OpenTransaction> = HashMap::new();
+    let mut events = Vec::new();
+    journal.scan_after(0, |entry| {
+        let handle = entry.transaction_handle;
+        let mut event = None;
+        match &entry.operation {
+            JournalOperation::TransactionBegin { store, .. } => {
+                open.insert(handle, OpenTransaction { store: Some(store.clone()), ..OpenTransaction::default() });
+            },
+            JournalOperation::OperatorAssign { terminal_id, operator_id } => {
+                let tx = open.entry(handle).or_default();
+                if terminal_id.is_some() {
+                    tx.terminal_id = terminal_id.clone();
+                }
+                if operator_id.is_some() {
+                    tx.operator_id = operator_id.clone();
+                }
+            },
+            JournalOperation::LineAdd { line_id, sku, qty, unit_minor, kind, reason_code, approved_by, .. } => {
+                let amount_minor = match kind {
+                    LineKind::Return | LineKind::DepositReturn => -(unit_minor * i64::from(*qty)),
+                    _ => unit_minor * i64::from(*qty),
+                };
+                open.entry(handle).or_default().lines.insert(*line_id, (sku.to_string(), amount_minor));
+                let kind = match kind {
+                    LineKind::Adjustment => Some(AuditEventKind::PriceOverride),
+                    LineKind::Return => Some(AuditEventKind::Return),
+                    _ => None,
+                };
+                event = kind.map(|kind| (kind, approved_by.clone(), reason_code.clone(), Some(*line_id), Some(sku.to_string()), Some(amount_minor)));
+            },
+            JournalOperation::LineVoid { line_id, reason } => {
+                let line = open.get_mut(&handle).and_then(|tx| tx.lines.remove(line_id));
+                event = Some((AuditEventKind::LineVoid, None, Some(reason.clone()), Some(*line_id), line.as_ref().map(|l| l.0.clone()), line.map(|l| l.1)));
+            },
+            JournalOperation::TransactionAbandon { reason } => {
+                let amount_minor = open.get(&handle).map(|tx| tx.lines.values().map(|l| l.1).sum());
+                event = Some((AuditEventKind::TransactionAbandon, None, Some(reason.clone()), None, None, amount_minor));
+            },
+            _ => {},
+        }
+
+        if let Some((kind, approved_by, reason_code, line_id, sku, amount_minor)) = event {
+            let tx = open.get(&handle);
+            let operator = tx.and_then(|tx| tx.operator_id.clone());
+            let included = entry.timestamp >= period.from && entry.timestamp < period.to
+                && operator_id.is_none_or(|operator_id| operator.as_deref() == Some(operator_id));
+            if included {
+                events.push(AuditEventRow {
+                    timestamp: entry.timestamp,
+                    kind,
+                    transaction_handle: handle,
+                    store: tx.and_then(|tx| tx.store.clone()),
+                    terminal_id: tx.and_then(|tx| tx.terminal_id.clone()),
+                    operator_id: operator.map(|o| pii::protect(PiiCategory::OperatorId, &o).into_owned()),
+                    approved_by: approved_by.map(|o| pii::protect(PiiCategory::OperatorId, &o).into_owned()),
+                    reason_code,
+                    line_id,
+                    sku,
+                    amount_minor,
+                });
+            }
+        }
+        if matches!(entry.operation, JournalOperation::TransactionCommit { .. } | JournalOperation::TransactionAbandon { .. }) {
+            open.remove(&handle);
+        }
+        entry.timestamp < period.to
+    })?;
+
+    let operator_id = operator_id.map(|o| pii::protect(PiiCategory::OperatorId, o).into_owned());
+    Ok(AuditEventReport { period, operator_id, events })
+}
+
+impl AuditEventReport {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("timestamp,kind,transaction_handle,store,terminal_id,operator_id,approved_by,reason_code,line_id,sku,amount_minor\n");
+        let optional = |value: &Option<String>| csv_field(value.as_deref().unwrap_or(""));
+        for row in &self.events {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{}\n",
+                row.timestamp.to_rfc3339(),
+                row.kind.name(),
+                row.transaction_handle,
+                optional(&row.store),
+                optional(&row.terminal_id),
+                optional(&row.operator_id),
+                optional(&row.approved_by),
+                optional(&row.reason_code),
+                row.line_id.map(|id| id.to_string()).unwrap_or_default(),
+                optional(&row.sku),
+                row.amount_minor.map(|a| a.to_string()).unwrap_or_default()
+            ));
+        }
+        csv
+    }
+}
+
+pub(crate) fn render<T: Serialize>(report: &T, csv: impl FnOnce(&T) -> String, format: ReportFormat) -> Result<String, String> {
+    match format {
+        ReportFormat::Json => serde_json::to_string(report)
+            .map_err(|e| format!("Failed to serialize report: {}", e)),
+        ReportFormat::Csv => Ok(csv(report)),
+    }
+}
+
+// RFC 4180 quoting for fields containing separators, quotes or line breaks
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
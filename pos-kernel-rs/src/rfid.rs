@@ -0,0 +1,128 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! RFID item entry
+//! Grab-and-go checkouts read every tag in the basket at once. Tags are SGTIN-96 EPCs, given
+//! as 24 hex digits or as pure-identity URIs (urn:epc:id:sgtin:CompanyPrefix.ItemRef.Serial);
+//! each decodes to the GTIN-14 of its product and a serial that makes the tag unique. Readers
+//! report a tag many times, and again on the next read of the same basket, so tags are
+//! deduplicated by EPC within a batch and against the tags the transaction already holds. Tags
+//! of other EPC schemes (a customer's own belongings, access badges) are ignored, not errors.
+
+use serde::{Deserialize, Serialize};
+
+use crate::scan;
+
+pub const MAX_BATCH_TAGS: usize = 1024;
+
+const SGTIN96_HEADER: u128 = 0x30;
+const SGTIN_URI_PREFIX: &str = "urn:epc:id:sgtin:";
+const SERIAL_BITS: u32 = 38;
+const MAX_SERIAL_LEN: usize = 20;
+// Company prefix and item reference share 44 bits and 13 digits; the partition value selects
+// the split as (company prefix bits, company prefix digits)
+const PARTITIONS: [(u32, usize); 7] = [(40, 12), (37, 11), (34, 10), (30, 9), (27, 8), (24, 7), (20, 6)];
+const GTIN_BITS: u32 = 44;
+const GTIN_DIGITS: usize = 13;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sgtin {
+    pub company_prefix: String,
+    // Indicator digit followed by the item reference
+    pub item_reference: String,
+    pub serial: String,
+}
+
+impl Sgtin {
+    pub fn gtin(&self) -> String {
+        let body = format!("{}{}{}", &self.item_reference[..1], self.company_prefix, &self.item_reference[1..]);
+        let check = scan::check_digit(&body);
+        format!("{}{}", body, check)
+    }
+
+    // The pure-identity URI, which tags are deduplicated by whichever form they were read in
+    pub fn uri(&self) -> String {
+        format!("{}{}.{}.{}", SGTIN_URI_PREFIX, self.company_prefix, self.item_reference, self.serial)
+    }
+}
+
+// The outcome of a batch: lines added (one per product), tags read more than once, tags that
+// are not SGTINs, and SGTINs the catalog does not know
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RfidBatch {
+    pub line_ids: Vec<u32>,
+    pub duplicates: usize,
+    pub ignored: Vec<String>,
+    pub unresolved: Vec<String>,
+}
+
+pub fn decode(tag: &str) -> Result<Sgtin, String> {
+    let tag = tag.trim();
+    match tag.strip_prefix(SGTIN_URI_PREFIX) {
+        Some(uri) => decode_uri(uri),
+        None => decode_hex(tag),
+    }
+}
+
+fn decode_hex(tag: &str) -> Result<Sgtin, String> {
+    if tag.len() != 24 || !tag.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("Not an SGTIN-96 tag".to_string());
+    }
+    let bits = u128::from_str_radix(tag, 16).map_err(|_| "Not an SGTIN-96 tag")?;
+    if bits >> 88 != SGTIN96_HEADER {
+        return Err("Not an SGTIN-96 tag".to_string());
+    }
+
+    let partition = ((bits >> 82) & 0b111) as usize;
+    let (prefix_bits, prefix_digits) = *PARTITIONS.get(partition).ok_or("Invalid SGTIN partition")?;
+    let item_bits = GTIN_BITS - prefix_bits;
+    let item_digits = GTIN_DIGITS - prefix_digits;
+    let company_prefix = (bits >> (SERIAL_BITS + item_bits)) & ((1 << prefix_bits) - 1);
+    let item_reference = (bits >> SERIAL_BITS) & ((1 << item_bits) - 1);
+    let serial = bits & ((1 << SERIAL_BITS) - 1);
+
+    let (company_prefix, item_reference) = (
+        format!("{:0>width$}", company_prefix, width = prefix_digits),
+        format!("{:0>width$}", item_reference, width = item_digits),
+    );
+    if company_prefix.len() != prefix_digits || item_reference.len() != item_digits {
+        return Err("Invalid SGTIN partition".to_string());
+    }
+    Ok(Sgtin { company_prefix, item_reference, serial: serial.to_string() })
+}
+
+fn decode_uri(uri: &str) -> Result<Sgtin, String> {
+    let mut parts = uri.splitn(3, '.');
+    let (company_prefix, item_reference, serial) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(c), Some(i), Some(s)) => (c, i, s),
+        _ => return Err("Invalid SGTIN URI".to_string()),
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(company_prefix) || !digits(item_reference)
+        || !(6..=12).contains(&company_prefix.len())
+        || company_prefix.len() + item_reference.len() != GTIN_DIGITS {
+        return Err("Invalid SGTIN URI".to_string());
+    }
+    if serial.is_empty() || serial.len() > MAX_SERIAL_LEN || !serial.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err("Invalid SGTIN URI".to_string());
+    }
+    Ok(Sgtin {
+        company_prefix: company_prefix.to_string(),
+        item_reference: item_reference.to_string(),
+        serial: serial.to_string(),
+    })
+}
@@ -0,0 +1,906 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Store rules scripts
+//! Integrators express local policies, such as a bag fee, in a small script instead of Rust.
+//! A script defines hooks the kernel runs at fixed points:
+//! - `after_add_line` after a lane adds a line, with `line` the line just added
+//! - `before_finalize` before a sale is committed by `pk_finalize_transaction`
+//!
+//! Both see `transaction`, the transaction's export. A hook reads the basket and asks for
+//! actions; the kernel applies them once the hook has finished, through the ordinary (and
+//! ordinarily journaled) operations:
+//! - `add_line(sku, qty, unit_minor)` adds a line; lines a hook adds do not run hooks
+//! - `set_attribute(key, value)` sets a transaction attribute
+//! - `reject(message)` (before_finalize only) refuses the commit with the message
+//!
+//! ```text
+//! // One bag per five items
+//! on after_add_line {
+//!     if line.sku != "BAG" {
+//!         let items = 0
+//!         for l in transaction.lines {
+//!             if l.sku != "BAG" { items = items + l.qty }
+//!         }
+//!         if items % 5 == 1 { add_line("BAG", 1, 10) }
+//!     }
+//! }
+//! ```
+//!
+//! Values are integers, strings, booleans, null, lists and records; a field of null is null.
+//! Statements are `let`, assignment, `if`/`else` and `for ... in`; expressions take
+//! `|| && == != < <= > >= + - * / % !`, parentheses and the functions `len`, `contains` and
+//! `starts_with`. SECURITY: Scripts are sandboxed: they reach nothing but their inputs and
+//! actions, every hook run has a step budget, and strings and actions are capped. The parser
+//! and the evaluator recurse, so a script is at most `MAX_SCRIPT_BYTES` and nests expressions
+//! and blocks at most `MAX_DEPTH` deep. A hook that fails at run time is skipped with a
+//! warning, so a broken script never stops a lane.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::journal::JournalOperation;
+use crate::{write_store_for_api, KernelError, PK_INVALID_HANDLE};
+
+// Evaluation steps one hook run may take
+const MAX_STEPS: usize = 10_000;
+const MAX_ACTIONS: usize = 16;
+const MAX_STRING_BYTES: usize = 4096;
+const MAX_SCRIPT_BYTES: usize = 64 * 1024;
+// Nesting of parentheses, unary operators, calls, operator chains, field paths and blocks
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    AfterAddLine,
+    BeforeFinalize,
+}
+
+impl Hook {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "after_add_line" => Some(Hook::AfterAddLine),
+            "before_finalize" => Some(Hook::BeforeFinalize),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Hook::AfterAddLine => "after_add_line",
+            Hook::BeforeFinalize => "before_finalize",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Action {
+    AddLine { sku: String, qty: i32, unit_minor: i64 },
+    SetAttribute { key: String, value: String },
+    Reject(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Str(String),
+    Sym(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||",
+    "{", "}", "(", ")", ",", ".", ";", "=", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut line = 1;
+    while let Some(&(start, c)) = chars.peek() {
+        let error = |message: String| format!("Rules script line {}: {}", line, message);
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' || source[start..].starts_with("//") {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            while let Some((i, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+                end = i + d.len_utf8();
+            }
+            let value = source[start..end].parse().map_err(|_| error("integer out of range".to_string()))?;
+            tokens.push((Token::Int(value), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some((i, d)) = chars.next_if(|(_, d)| d.is_alphanumeric() || *d == '_') {
+                end = i + d.len_utf8();
+            }
+            tokens.push((Token::Ident(source[start..end].to_string()), line));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => text.push('\n'),
+                        Some((_, 't')) => text.push('\t'),
+                        Some((_, e @ ('"' | '\\'))) => text.push(e),
+                        _ => return Err(error("invalid escape in string".to_string())),
+                    },
+                    Some((_, '\n')) | None => return Err(error("unterminated string".to_string())),
+                    Some((_, c)) => text.push(c),
+                }
+            }
+            tokens.push((Token::Str(text), line));
+        } else {
+            let symbol = SYMBOLS.iter().find(|s| source[start..].starts_with(**s))
+                .ok_or_else(|| error(format!("unexpected '{}'", c)))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((Token::Sym(symbol), line));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Var(String),
+    Field(Box<Expr>, String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum StmtKind {
+    Let(String, Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    For(String, Expr, Vec<Stmt>),
+    Action(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+const FUNCTIONS: &[&str] = &["len", "contains", "starts_with"];
+const ACTIONS: &[(&str, usize)] = &[("add_line", 3), ("set_attribute", 2), ("reject", 1)];
+const KEYWORDS: &[&str] = &["on", "let", "if", "else", "for", "in", "true", "false", "null"];
+
+// Binary operators by precedence, loosest first
+const PRECEDENCE: &[&[&str]] = &[&["||"], &["&&"], &["==", "!=", "<", "<=", ">", ">="], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    hook: Hook,
+    depth: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl fmt::Display) -> String {
+        format!("Rules script line {}: {}", self.line(), message)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn at_sym(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Sym(s)) if *s == symbol)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if s == keyword)
+    }
+
+    fn eat_sym(&mut self, symbol: &str) -> bool {
+        let at = self.at_sym(symbol);
+        self.pos += at as usize;
+        at
+    }
+
+    fn expect_sym(&mut self, symbol: &str) -> Result<(), String> {
+        match self.eat_sym(symbol) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected '{}'", symbol))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.at_keyword(keyword) {
+            true => {
+                self.pos += 1;
+                Ok(())
+            },
+            false => Err(self.error(format!("expected '{}'", keyword))),
+        }
+    }
+
+    // One level deeper; the caller restores `depth` when the level is parsed
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(self.error(format!("nested more than {} deep", MAX_DEPTH))),
+            false => Ok(()),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        self.expect_sym("{")?;
+        self.nest()?;
+        let mut stmts = Vec::new();
+        while !self.eat_sym("}") {
+            if self.peek().is_none() {
+                return Err(self.error("expected '}'"));
+            }
+            stmts.push(self.stmt()?);
+            self.eat_sym(";");
+        }
+        self.depth -= 1;
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        let kind = if self.at_keyword("let") {
+            self.pos += 1;
+            let name = self.name()?;
+            self.expect_sym("=")?;
+            StmtKind::Let(name, self.expr()?)
+        } else if self.at_keyword("if") {
+            return self.if_stmt();
+        } else if self.at_keyword("for") {
+            self.pos += 1;
+            let name = self.name()?;
+            self.expect_keyword("in")?;
+            let list = self.expr()?;
+            StmtKind::For(name, list, self.block()?)
+        } else {
+            let name = self.name()?;
+            if self.eat_sym("=") {
+                StmtKind::Assign(name, self.expr()?)
+            } else {
+                let arity = ACTIONS.iter().find(|(action, _)| *action == name).map(|(_, arity)| *arity)
+                    .ok_or_else(|| self.error(format!("'{}' is not an action", name)))?;
+                if name == "reject" && self.hook != Hook::BeforeFinalize {
+                    return Err(self.error("reject is only allowed in before_finalize"));
+                }
+                let args = self.args()?;
+                if args.len() != arity {
+                    return Err(self.error(format!("{} takes {} arguments", name, arity)));
+                }
+                StmtKind::Action(name, args)
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn if_stmt(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        self.expect_keyword("if")?;
+        let condition = self.expr()?;
+        let then = self.block()?;
+        let otherwise = match self.at_keyword("else") {
+            false => Vec::new(),
+            true => {
+                self.pos += 1;
+                match self.at_keyword("if") {
+                    true => {
+                        self.nest()?;
+                        let chained = self.if_stmt()?;
+                        self.depth -= 1;
+                        vec![chained]
+                    },
+                    false => self.block()?,
+                }
+            },
+        };
+        Ok(Stmt { line, kind: StmtKind::If(condition, then, otherwise) })
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, String> {
+        self.expect_sym("(")?;
+        self.nest()?;
+        let mut args = Vec::new();
+        if !self.eat_sym(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat_sym(")") {
+                    break;
+                }
+                self.expect_sym(",")?;
+            }
+        }
+        self.depth -= 1;
+        Ok(args)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let depth = self.depth;
+        let mut left = self.binary(level + 1)?;
+        // Each operator in a chain nests the tree one level
+        while let Some(op) = PRECEDENCE[level].iter().find(|op| self.at_sym(op)) {
+            self.pos += 1;
+            self.nest()?;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+            // Comparisons do not chain
+            if level == 2 {
+                break;
+            }
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let expr = if self.eat_sym("!") {
+            self.nest()?;
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.eat_sym("-") {
+            self.nest()?;
+            Expr::Negate(Box::new(self.unary()?))
+        } else {
+            let mut expr = self.primary()?;
+            while self.eat_sym(".") {
+                self.nest()?;
+                expr = Expr::Field(Box::new(expr), self.name()?);
+            }
+            expr
+        };
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("expected an expression"))?;
+        self.pos += 1;
+        match token {
+            Token::Int(value) => Ok(Expr::Literal(Value::from(value))),
+            Token::Str(text) => Ok(Expr::Literal(Value::String(text))),
+            Token::Sym("(") => {
+                self.nest()?;
+                let expr = self.expr()?;
+                self.expect_sym(")")?;
+                self.depth -= 1;
+                Ok(expr)
+            },
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if KEYWORDS.contains(&name.as_str()) => Err(self.error(format!("unexpected '{}'", name))),
+                _ if self.at_sym("(") => {
+                    if !FUNCTIONS.contains(&name.as_str()) {
+                        return Err(self.error(format!("'{}' is not a function", name)));
+                    }
+                    let args = self.args()?;
+                    if args.len() != if name == "len" { 1 } else { 2 } {
+                        return Err(self.error(format!("wrong number of arguments to {}", name)));
+                    }
+                    Ok(Expr::Call(name, args))
+                },
+                _ => Ok(Expr::Var(name)),
+            },
+            Token::Sym(symbol) => {
+                self.pos -= 1;
+                Err(self.error(format!("unexpected '{}'", symbol)))
+            },
+        }
+    }
+}
+
+// A parsed script
+#[derive(Debug)]
+pub(crate) struct Script {
+    hooks: HashMap<Hook, Vec<Stmt>>,
+    // SHA-256 of the source, hex encoded; journaled when the script is installed
+    pub digest: String,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_SCRIPT_BYTES {
+            return Err(format!("Rules script is {} bytes (limit {})", source.len(), MAX_SCRIPT_BYTES));
+        }
+        let mut parser = Parser { tokens: lex(source)?, pos: 0, hook: Hook::AfterAddLine, depth: 0 };
+        let mut hooks = HashMap::new();
+        while parser.peek().is_some() {
+            parser.expect_keyword("on")?;
+            let name = parser.name()?;
+            parser.hook = Hook::parse(&name).ok_or_else(|| parser.error(format!("unknown hook '{}'", name)))?;
+            let body = parser.block()?;
+            if hooks.insert(parser.hook, body).is_some() {
+                return Err(parser.error(format!("hook '{}' is defined twice", name)));
+            }
+        }
+        let digest = Sha256::digest(source.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(Self { hooks, digest })
+    }
+
+    pub fn handles(&self, hook: Hook) -> bool {
+        self.hooks.contains_key(&hook)
+    }
+
+    // Runs `hook` with `inputs` bound as variables; returns the actions it asked for
+    pub fn run(&self, hook: Hook, inputs: Vec<(&str, Value)>) -> Result<Vec<Action>, String> {
+        let body = match self.hooks.get(&hook) {
+            Some(body) => body,
+            None => return Ok(Vec::new()),
+        };
+        let mut run = Run {
+            scopes: vec![inputs.into_iter().map(|(name, value)| (name.to_string(), value)).collect()],
+            steps: 0,
+            actions: Vec::new(),
+        };
+        run.block(body)?;
+        Ok(run.actions)
+    }
+}
+
+struct Run {
+    scopes: Vec<HashMap<String, Value>>,
+    steps: usize,
+    actions: Vec<Action>,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "record",
+    }
+}
+
+fn int(value: &Value) -> Result<i64, String> {
+    value.as_i64().ok_or_else(|| format!("expected an integer, found {}", type_name(value)))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| format!("expected a boolean, found {}", type_name(value)))
+}
+
+fn text(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or_else(|| format!("expected a string, found {}", type_name(value)))
+}
+
+static NULL: Value = Value::Null;
+
+fn field_of<'a>(record: &'a Value, field: &str) -> Result<&'a Value, String> {
+    match record {
+        Value::Object(fields) => Ok(fields.get(field).unwrap_or(&NULL)),
+        Value::Null => Ok(&NULL),
+        other => Err(format!("{} has no field '{}'", type_name(other), field)),
+    }
+}
+
+impl Run {
+    // A variable or field path, borrowed from its scope so that reading one field of the
+    // transaction does not copy the whole record; None unless `expr` is such a path
+    fn resolve(&self, expr: &Expr) -> Option<Result<&Value, String>> {
+        match expr {
+            Expr::Var(name) => Some(self.scopes.iter().rev().find_map(|scope| scope.get(name))
+                .ok_or_else(|| format!("'{}' is not defined", name))),
+            Expr::Field(record, field) => Some(self.resolve(record)?.and_then(|record| field_of(record, field))),
+            _ => None,
+        }
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        match self.steps > MAX_STEPS {
+            true => Err(format!("exceeded {} steps", MAX_STEPS)),
+            false => Ok(()),
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        self.scopes.push(HashMap::new());
+        let result = stmts.iter().try_for_each(|stmt| {
+            self.stmt(stmt).map_err(|e| match e.starts_with("line ") {
+                true => e,
+                false => format!("line {}: {}", stmt.line, e),
+            })
+        });
+        self.scopes.pop();
+        result
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        self.step()?;
+        match &stmt.kind {
+            StmtKind::Let(name, expr) => {
+                let value = self.eval(expr)?;
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone(), value);
+                }
+            },
+            StmtKind::Assign(name, expr) => {
+                let value = self.eval(expr)?;
+                let slot = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+                    .ok_or_else(|| format!("'{}' is not defined", name))?;
+                *slot = value;
+            },
+            StmtKind::If(condition, then, otherwise) => {
+                match boolean(&self.eval(condition)?)? {
+                    true => self.block(then)?,
+                    false => self.block(otherwise)?,
+                }
+            },
+            StmtKind::For(name, list, body) => {
+                let items = match self.eval(list)? {
+                    Value::Array(items) => items,
+                    Value::Null => Vec::new(),
+                    other => return Err(format!("cannot loop over {}", type_name(&other))),
+                };
+                for item in items {
+                    self.scopes.push(HashMap::from([(name.clone(), item)]));
+                    let result = self.block(body);
+                    self.scopes.pop();
+                    result?;
+                }
+            },
+            StmtKind::Action(name, args) => {
+                let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+                if self.actions.len() >= MAX_ACTIONS {
+                    return Err(format!("exceeded {} actions", MAX_ACTIONS));
+                }
+                let action = match name.as_str() {
+                    "add_line" => Action::AddLine {
+                        sku: text(&args[0])?.to_string(),
+                        qty: i32::try_from(int(&args[1])?).map_err(|_| "quantity out of range".to_string())?,
+                        unit_minor: int(&args[2])?,
+                    },
+                    "set_attribute" => Action::SetAttribute { key: text(&args[0])?.to_string(), value: text(&args[1])?.to_string() },
+                    _ => Action::Reject(text(&args[0])?.to_string()),
+                };
+                self.actions.push(action);
+            },
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        self.step()?;
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Var(name) => self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned()
+                .ok_or_else(|| format!("'{}' is not defined", name)),
+            Expr::Field(record, field) => match self.resolve(expr) {
+                Some(value) => value.cloned(),
+                // A field of a computed value, such as `(a || b).c`
+                None => field_of(&self.eval(record)?, field).cloned(),
+            },
+            Expr::Not(operand) => Ok(Value::Bool(!boolean(&self.eval(operand)?)?)),
+            Expr::Negate(operand) => int(&self.eval(operand)?)?.checked_neg()
+                .map(Value::from)
+                .ok_or_else(|| "integer overflow".to_string()),
+            Expr::Binary(op, left, right) => self.binary(op, left, right),
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+                match (name.as_str(), &args[..]) {
+                    ("len", [Value::String(s)]) => Ok(Value::from(s.chars().count() as i64)),
+                    ("len", [Value::Array(items)]) => Ok(Value::from(items.len() as i64)),
+                    ("len", [Value::Object(fields)]) => Ok(Value::from(fields.len() as i64)),
+                    ("len", [Value::Null]) => Ok(Value::from(0)),
+                    ("contains", [Value::String(s), needle]) => Ok(Value::Bool(s.contains(text(needle)?))),
+                    ("contains", [Value::Array(items), needle]) => Ok(Value::Bool(items.contains(needle))),
+                    ("contains", [Value::Object(fields), key]) => Ok(Value::Bool(fields.contains_key(text(key)?))),
+                    ("starts_with", [Value::String(s), prefix]) => Ok(Value::Bool(s.starts_with(text(prefix)?))),
+                    _ => Err(format!("{} does not take {}", name, args.iter().map(type_name).collect::<Vec<_>>().join(", "))),
+                }
+            },
+        }
+    }
+
+    fn binary(&mut self, op: &str, left: &Expr, right: &Expr) -> Result<Value, String> {
+        let left = self.eval(left)?;
+        // Short-circuit
+        match (op, boolean(&left)) {
+            ("&&", Ok(false)) => return Ok(Value::Bool(false)),
+            ("||", Ok(true)) => return Ok(Value::Bool(true)),
+            ("&&" | "||", Err(e)) => return Err(e),
+            _ => {},
+        }
+        let right = self.eval(right)?;
+        let overflow = || "integer overflow".to_string();
+        let value = match op {
+            "&&" | "||" => Value::Bool(boolean(&right)?),
+            "==" => Value::Bool(left == right),
+            "!=" => Value::Bool(left != right),
+            "<" | "<=" | ">" | ">=" => {
+                let ordering = match (&left, &right) {
+                    (Value::String(a), Value::String(b)) => a.cmp(b),
+                    _ => int(&left)?.cmp(&int(&right)?),
+                };
+                Value::Bool(match op {
+                    "<" => ordering.is_lt(),
+                    "<=" => ordering.is_le(),
+                    ">" => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                })
+            },
+            "+" => match (&left, &right) {
+                (Value::String(a), Value::String(b)) => {
+                    if a.len() + b.len() > MAX_STRING_BYTES {
+                        return Err(format!("string longer than {} bytes", MAX_STRING_BYTES));
+                    }
+                    Value::String(format!("{}{}", a, b))
+                },
+                _ => Value::from(int(&left)?.checked_add(int(&right)?).ok_or_else(overflow)?),
+            },
+            "-" => Value::from(int(&left)?.checked_sub(int(&right)?).ok_or_else(overflow)?),
+            "*" => Value::from(int(&left)?.checked_mul(int(&right)?).ok_or_else(overflow)?),
+            _ => {
+                let (a, b) = (int(&left)?, int(&right)?);
+                if b == 0 {
+                    return Err("division by zero".to_string());
+                }
+                Value::from(match op {
+                    "/" => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                }.ok_or_else(overflow)?)
+            },
+        };
+        Ok(value)
+    }
+}
+
+// The line `line_id` of a transaction export, bound as `line` for after_add_line
+pub(crate) fn find_line(transaction: &Value, line_id: u32) -> Value {
+    transaction.get("lines")
+        .and_then(Value::as_array)
+        .and_then(|lines| lines.iter().find(|line| line.get("line_id").and_then(Value::as_u64) == Some(line_id as u64)))
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Map::new()))
+}
+
+// Installs (or replaces) the store's rules script; an empty source removes it
+pub fn set_rules_script(source: &str) -> Result<(), KernelError> {
+    let script = match source.trim().is_empty() {
+        true => None,
+        false => Some(Arc::new(Script::parse(source).map_err(KernelError::Validation)?)),
+    };
+    let mut store = write_store_for_api()?;
+    store.replication.check_writable()?;
+    let digest = script.as_ref().map(|script| script.digest.clone());
+    store.rules = script;
+    store.journal.record(PK_INVALID_HANDLE, JournalOperation::RulesScriptSet { digest });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{pk_add_cash_tender, pk_add_line, pk_begin_transaction, pk_finalize_transaction, pk_get_line_count, pk_set_transaction_attribute, ResultCode};
+
+    const BAG_SCRIPT: &str = r#"
+        // One bag per five items
+        on after_add_line {
+            if line.sku != "BAG" {
+                let items = 0
+                for l in transaction.lines {
+                    if l.sku != "BAG" { items = items + l.qty }
+                }
+                if items % 5 == 1 { add_line("BAG", 1, 10) }
+            }
+        }
+    "#;
+
+    fn basket(lines: &[(&str, i64)]) -> Value {
+        json!({ "lines": lines.iter().map(|(sku, qty)| json!({ "sku": sku, "qty": qty })).collect::<Vec<_>>() })
+    }
+
+    fn parse_error(source: &str) -> String {
+        Script::parse(source).expect_err("script should not parse")
+    }
+
+    #[test]
+    fn parses_hooks() {
+        let script = Script::parse(BAG_SCRIPT).expect("bag script parses");
+        assert!(script.handles(Hook::AfterAddLine));
+        assert!(!script.handles(Hook::BeforeFinalize));
+        assert_eq!(script.digest.len(), 64);
+        assert!(Script::parse("").expect("empty script parses").hooks.is_empty());
+    }
+
+    #[test]
+    fn reports_parse_errors_with_their_line() {
+        assert_eq!(parse_error("on after_scan {}"), "Rules script line 1: unknown hook 'after_scan'");
+        assert_eq!(
+            parse_error("on before_finalize {}\non before_finalize {}"),
+            "Rules script line 2: hook 'before_finalize' is defined twice"
+        );
+        assert_eq!(parse_error("on before_finalize {\n  let s = \"open\n}"), "Rules script line 2: unterminated string");
+        assert_eq!(parse_error("on before_finalize { let n = sum(1, 2) }"), "Rules script line 1: 'sum' is not a function");
+        assert_eq!(parse_error("on before_finalize { delete_line(1) }"), "Rules script line 1: 'delete_line' is not an action");
+        assert_eq!(parse_error("on before_finalize { add_line(\"BAG\", 1) }"), "Rules script line 1: add_line takes 3 arguments");
+        assert_eq!(parse_error("on before_finalize { let if = 1 }"), "Rules script line 1: expected a name");
+    }
+
+    #[test]
+    fn reject_is_only_allowed_in_before_finalize() {
+        assert_eq!(
+            parse_error("on after_add_line {\n  reject(\"no\")\n}"),
+            "Rules script line 2: reject is only allowed in before_finalize"
+        );
+
+        let script = Script::parse(r#"on before_finalize { if len(transaction.lines) > 2 { reject("Too many lines") } }"#)
+            .expect("reject parses in before_finalize");
+        let actions = script.run(Hook::BeforeFinalize, vec![("transaction", basket(&[("A", 1), ("B", 1), ("C", 1)]))]);
+        assert_eq!(actions, Ok(vec![Action::Reject("Too many lines".to_string())]));
+        let actions = script.run(Hook::BeforeFinalize, vec![("transaction", basket(&[("A", 1)]))]);
+        assert_eq!(actions, Ok(Vec::new()));
+    }
+
+    #[test]
+    fn runs_hooks_on_their_inputs() {
+        let script = Script::parse(BAG_SCRIPT).expect("bag script parses");
+        let run = |lines: &[(&str, i64)], sku: &str| {
+            script.run(Hook::AfterAddLine, vec![("line", json!({ "sku": sku })), ("transaction", basket(lines))])
+        };
+        let bag = Action::AddLine { sku: "BAG".to_string(), qty: 1, unit_minor: 10 };
+        assert_eq!(run(&[("A", 1)], "A"), Ok(vec![bag.clone()]));
+        assert_eq!(run(&[("A", 1), ("BAG", 1), ("B", 3)], "B"), Ok(Vec::new()));
+        assert_eq!(run(&[("A", 1), ("BAG", 1), ("B", 5)], "B"), Ok(vec![bag]));
+        assert_eq!(run(&[("A", 1), ("BAG", 1)], "BAG"), Ok(Vec::new()));
+        // A hook the script does not define asks for nothing
+        assert_eq!(script.run(Hook::BeforeFinalize, vec![("transaction", basket(&[]))]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn deep_nesting_is_refused() {
+        let nested = |open: &str, close: &str, depth: usize| {
+            format!("on before_finalize {{ let n = {}1{} }}", open.repeat(depth), close.repeat(depth))
+        };
+        let too_deep = format!("Rules script line 1: nested more than {} deep", MAX_DEPTH);
+        for (open, close) in [("(", ")"), ("!", ""), ("-", ""), ("len(", ")")] {
+            assert_eq!(parse_error(&nested(open, close, 10_000)), too_deep, "{}", open);
+        }
+        assert!(Script::parse(&nested("(", ")", MAX_DEPTH / 2)).is_ok());
+        assert!(Script::parse(&nested("-", "", MAX_DEPTH / 2)).is_ok());
+
+        let chain = format!("on before_finalize {{ let n = 1{} }}", " + 1".repeat(10_000));
+        assert_eq!(parse_error(&chain), too_deep);
+        let path = format!("on before_finalize {{ let n = transaction{} }}", ".a".repeat(10_000));
+        assert_eq!(parse_error(&path), too_deep);
+        let blocks = format!("on before_finalize {{ {}{} }}", "if true { ".repeat(1_000), "}".repeat(1_000));
+        assert_eq!(parse_error(&blocks), too_deep);
+
+        let long = nested("(", ")", 200_000);
+        assert_eq!(parse_error(&long), format!("Rules script is {} bytes (limit {})", long.len(), MAX_SCRIPT_BYTES));
+    }
+
+    #[test]
+    fn field_paths_read_nested_values() {
+        let script = Script::parse(r#"on before_finalize { if transaction.customer.tier == "gold" && transaction.missing.field == null { reject((transaction.customer).token) } }"#)
+            .expect("script parses");
+        let transaction = json!({ "customer": { "tier": "gold", "token": "C-1" } });
+        assert_eq!(script.run(Hook::BeforeFinalize, vec![("transaction", transaction)]), Ok(vec![Action::Reject("C-1".to_string())]));
+        let error = script.run(Hook::BeforeFinalize, vec![("transaction", json!({ "customer": 3 }))]).expect_err("no field on a number");
+        assert_eq!(error, "line 1: number has no field 'tier'");
+    }
+
+    #[test]
+    fn run_time_errors_carry_the_statement_line() {
+        let script = Script::parse("on before_finalize {\n  let n = 1 / transaction.count\n}").expect("script parses");
+        assert_eq!(
+            script.run(Hook::BeforeFinalize, vec![("transaction", json!({ "count": 0 }))]),
+            Err("line 2: division by zero".to_string())
+        );
+        assert_eq!(
+            script.run(Hook::BeforeFinalize, vec![("transaction", json!({ "count": "none" }))]),
+            Err("line 2: expected an integer, found string".to_string())
+        );
+    }
+
+    #[test]
+    fn hook_runs_are_bounded() {
+        let script = Script::parse(
+            "on before_finalize {\n  for a in transaction.lines {\n    for b in transaction.lines {\n      let n = 1\n    }\n  }\n}"
+        ).expect("script parses");
+        let small = basket(&[("A", 1); 10]);
+        assert_eq!(script.run(Hook::BeforeFinalize, vec![("transaction", small)]), Ok(Vec::new()));
+        let large = basket(&[("A", 1); 200]);
+        let error = script.run(Hook::BeforeFinalize, vec![("transaction", large)]).expect_err("budget exceeded");
+        assert!(error.ends_with(&format!("exceeded {} steps", MAX_STEPS)), "{}", error);
+
+        let script = Script::parse(r#"on before_finalize { for l in transaction.lines { set_attribute("k", "v") } }"#)
+            .expect("script parses");
+        let error = script.run(Hook::BeforeFinalize, vec![("transaction", basket(&[("A", 1); 20]))]).expect_err("too many actions");
+        assert!(error.ends_with(&format!("exceeded {} actions", MAX_ACTIONS)), "{}", error);
+
+        let script = Script::parse(r#"on before_finalize { let s = "x" for l in transaction.lines { s = s + s } }"#)
+            .expect("script parses");
+        let error = script.run(Hook::BeforeFinalize, vec![("transaction", basket(&[("A", 1); 16]))]).expect_err("string too long");
+        assert!(error.ends_with(&format!("string longer than {} bytes", MAX_STRING_BYTES)), "{}", error);
+    }
+
+    // The one test that installs a script: the kernel store is shared by every test here
+    #[test]
+    fn installed_script_drives_the_lane() {
+        set_rules_script(r#"
+            // Would recurse if the bag it adds ran the hook again
+            on after_add_line { add_line("BAG", 1, 10) }
+            on before_finalize {
+                if !contains(transaction.attributes, "approved") { reject("Needs approval") }
+            }
+        "#).expect("script installs");
+
+        let mut handle = 0;
+        unsafe {
+            assert_eq!(pk_begin_transaction(b"RULES".as_ptr(), 5, b"USD".as_ptr(), 3, 2, &mut handle).code, ResultCode::Ok as i32);
+            assert_eq!(pk_add_line(handle, b"A".as_ptr(), 1, 1, 100).code, ResultCode::Ok as i32);
+            let mut lines = 0;
+            assert_eq!(pk_get_line_count(handle, &mut lines).code, ResultCode::Ok as i32);
+            assert_eq!(lines, 2, "the hook's bag must not run the hook again");
+
+            assert_eq!(pk_add_cash_tender(handle, 110).code, ResultCode::Ok as i32);
+            assert_eq!(pk_finalize_transaction(handle).code, ResultCode::InvalidState as i32);
+            let (key, value) = (b"approved", b"yes");
+            assert_eq!(pk_set_transaction_attribute(handle, key.as_ptr(), key.len(), value.as_ptr(), value.len()).code, ResultCode::Ok as i32);
+            assert_eq!(pk_finalize_transaction(handle).code, ResultCode::Ok as i32);
+        }
+
+        set_rules_script("").expect("script removed");
+    }
+}
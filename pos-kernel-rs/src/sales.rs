@@ -0,0 +1,333 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Hourly sales aggregation
+//! ARCHITECTURAL PRINCIPLE: Dashboards ask for sales per hour far more often than anything
+//! changes, so every commit is added to an hourly bucket as it happens and queries read the
+//! buckets instead of scanning the archive. Buckets are kept per store and currency for the
+//! configured number of business dates. A business date starts at `business_day_start_hour`
+//! local time, `business_day_utc_offset_minutes` from UTC, so a sale at 01:30 on a late night
+//! belongs to the previous day when the day starts at 04:00. Changing either setting rebuilds
+//! the buckets from the archive.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{DateTime, Duration, DurationRound, NaiveDate, Timelike, Utc};
+use serde::Serialize;
+
+use crate::reports::{csv_field, ReportPeriod};
+use crate::{LineKind, Transaction, TxState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BusinessDay {
+    pub utc_offset_minutes: i32,
+    pub start_hour: u32,
+}
+
+impl BusinessDay {
+    fn date(&self, at: DateTime<Utc>) -> NaiveDate {
+        let local = at.naive_utc() + Duration::minutes(i64::from(self.utc_offset_minutes));
+        (local - Duration::hours(i64::from(self.start_hour))).date()
+    }
+}
+
+// Commits in one clock hour for a store and currency
+#[derive(Debug, Clone, Serialize)]
+pub struct SalesBucket {
+    pub business_date: NaiveDate,
+    pub hour_start: DateTime<Utc>,
+    // The local clock hour, 0 to 23
+    pub hour: u32,
+    pub store: String,
+    pub currency: String,
+    pub transaction_count: u64,
+    // Items sold less items returned
+    pub units: i64,
+    // Sale lines before adjustments, returns and exclusive tax
+    pub gross_minor: i64,
+    // Amount due less tax
+    pub net_minor: i64,
+    // Net per transaction
+    pub average_basket_minor: i64,
+    // From begin to commit, summed over the transactions
+    #[serde(skip)]
+    service_ms: i64,
+}
+
+impl SalesBucket {
+    fn add(&mut self, other: &SalesBucket) {
+        self.transaction_count += other.transaction_count;
+        self.units += other.units;
+        self.gross_minor += other.gross_minor;
+        self.net_minor += other.net_minor;
+        self.service_ms += other.service_ms;
+        self.average_basket_minor = self.net_minor / self.transaction_count.max(1) as i64;
+    }
+
+    fn empty(&self) -> Self {
+        Self { transaction_count: 0, units: 0, gross_minor: 0, net_minor: 0, average_basket_minor: 0, service_ms: 0, ..self.clone() }
+    }
+}
+
+// Per store, currency and business date, summed over its hours
+#[derive(Debug, Clone, Serialize)]
+pub struct DailySales {
+    pub business_date: NaiveDate,
+    pub store: String,
+    pub currency: String,
+    pub transaction_count: u64,
+    pub units: i64,
+    pub gross_minor: i64,
+    pub net_minor: i64,
+    pub average_basket_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HourlySalesReport {
+    pub period: ReportPeriod,
+    pub store: Option<String>,
+    pub hours: Vec<SalesBucket>,
+    pub days: Vec<DailySales>,
+}
+
+// Transactions committed in one hour of the business date, across currencies
+#[derive(Debug, Clone, Serialize)]
+pub struct HourTransactions {
+    pub hour_start: DateTime<Utc>,
+    pub hour: u32,
+    pub transaction_count: u64,
+}
+
+// A manager's dashboard, as of `as_of`
+#[derive(Debug, Clone, Serialize)]
+pub struct RealtimeStats {
+    pub as_of: DateTime<Utc>,
+    pub business_date: NaiveDate,
+    pub store: Option<String>,
+    pub open_transactions: usize,
+    // Sales so far in the business date, per store and currency
+    pub sales_today: Vec<DailySales>,
+    pub transactions_by_hour: Vec<HourTransactions>,
+    // From begin to commit, over the business date's commits
+    pub average_service_secs: f64,
+    // With an open transaction or a commit in the last `ACTIVE_TERMINAL_WINDOW`
+    pub active_terminals: Vec<String>,
+}
+
+const ACTIVE_TERMINAL_WINDOW: Duration = Duration::minutes(15);
+
+type BucketKey = (NaiveDate, DateTime<Utc>, String, String);
+
+#[derive(Debug)]
+pub(crate) struct SalesAggregates {
+    day: BusinessDay,
+    retention_days: u32,
+    buckets: BTreeMap<BucketKey, SalesBucket>,
+    // Last commit per store and terminal
+    terminal_commits: HashMap<(String, String), DateTime<Utc>>,
+}
+
+impl SalesAggregates {
+    pub fn new(day: BusinessDay, retention_days: u32) -> Self {
+        Self { day, retention_days, buckets: BTreeMap::new(), terminal_commits: HashMap::new() }
+    }
+
+    pub fn business_day(&self) -> BusinessDay {
+        self.day
+    }
+
+    // Adds a committed transaction to the bucket of its commit hour
+    pub fn record(&mut self, tx: &Transaction) {
+        let committed_at = match tx.committed_at {
+            Some(at) if tx.state == TxState::Committed => at,
+            _ => return,
+        };
+        let offset = Duration::minutes(i64::from(self.day.utc_offset_minutes));
+        let local_hour = (committed_at + offset).duration_trunc(Duration::hours(1)).unwrap_or(committed_at + offset);
+        let hour_start = local_hour - offset;
+        let business_date = self.day.date(committed_at);
+
+        let mut units = 0;
+        let mut gross_minor = 0;
+        for line in tx.lines.iter() {
+            match line.kind {
+                LineKind::Sale => {
+                    units += i64::from(line.qty);
+                    gross_minor += line.total_minor();
+                },
+                LineKind::Return => units -= i64::from(line.qty),
+                LineKind::Deposit | LineKind::DepositReturn | LineKind::Adjustment => {},
+            }
+        }
+        let tax_minor: i64 = tx.taxes.iter().map(|t| t.tax_minor).sum();
+
+        let key = (business_date, hour_start, tx.store.clone(), tx.currency.code.clone());
+        let bucket = self.buckets.entry(key).or_insert_with(|| SalesBucket {
+            business_date,
+            hour_start,
+            hour: (committed_at + offset).hour(),
+            store: tx.store.clone(),
+            currency: tx.currency.code.clone(),
+            transaction_count: 0,
+            units: 0,
+            gross_minor: 0,
+            net_minor: 0,
+            average_basket_minor: 0,
+            service_ms: 0,
+        });
+        bucket.transaction_count += 1;
+        bucket.units += units;
+        bucket.gross_minor += gross_minor;
+        bucket.net_minor += tx.total_minor() - tax_minor;
+        bucket.average_basket_minor = bucket.net_minor / bucket.transaction_count as i64;
+        bucket.service_ms += (committed_at - tx.started_at).num_milliseconds().max(0);
+        if let Some(terminal_id) = &tx.terminal_id {
+            let last = self.terminal_commits.entry((tx.store.clone(), terminal_id.clone())).or_insert(committed_at);
+            *last = committed_at.max(*last);
+        }
+        self.prune();
+    }
+
+    // Drops business dates older than the retention from the newest one
+    fn prune(&mut self) {
+        let newest = match self.buckets.last_key_value() {
+            Some(((date, ..), _)) => *date,
+            None => return,
+        };
+        let oldest_kept = newest - Duration::days(i64::from(self.retention_days.saturating_sub(1)));
+        while self.buckets.first_key_value().is_some_and(|((date, ..), _)| *date < oldest_kept) {
+            self.buckets.pop_first();
+        }
+    }
+
+    // Drops every bucket, for a rebuild under new settings
+    pub fn reset(&mut self, day: BusinessDay, retention_days: u32) {
+        *self = Self::new(day, retention_days);
+    }
+
+    pub fn set_retention(&mut self, retention_days: u32) {
+        self.retention_days = retention_days;
+        self.prune();
+    }
+
+    // Hours starting in the period, with each business date summed over the hours included
+    pub fn report(&self, period: ReportPeriod, store: Option<&str>) -> HourlySalesReport {
+        let hours: Vec<SalesBucket> = self.buckets.values()
+            .filter(|b| b.hour_start >= period.from && b.hour_start < period.to)
+            .filter(|b| store.is_none_or(|store| b.store == store))
+            .cloned()
+            .collect();
+
+        let days = daily(&hours);
+        HourlySalesReport { period, store: store.map(str::to_string), hours, days }
+    }
+
+    // `open_terminals` are the terminals of the transactions open in the store
+    pub fn realtime(&self, now: DateTime<Utc>, store: Option<&str>, open_transactions: usize, open_terminals: BTreeSet<String>) -> RealtimeStats {
+        let business_date = self.day.date(now);
+        let hours: Vec<&SalesBucket> = self.buckets.values()
+            .filter(|b| b.business_date == business_date && store.is_none_or(|store| b.store == store))
+            .collect();
+
+        let mut transactions_by_hour: Vec<HourTransactions> = Vec::new();
+        for bucket in &hours {
+            match transactions_by_hour.iter_mut().find(|h| h.hour_start == bucket.hour_start) {
+                Some(hour) => hour.transaction_count += bucket.transaction_count,
+                None => transactions_by_hour.push(HourTransactions { hour_start: bucket.hour_start, hour: bucket.hour, transaction_count: bucket.transaction_count }),
+            }
+        }
+        transactions_by_hour.sort_by_key(|h| h.hour_start);
+        let (count, service_ms) = hours.iter().fold((0, 0), |(count, ms), b| (count + b.transaction_count, ms + b.service_ms));
+
+        let mut active_terminals = open_terminals;
+        active_terminals.extend(self.terminal_commits.iter()
+            .filter(|((terminal_store, _), at)| store.is_none_or(|store| terminal_store == store) && now - **at < ACTIVE_TERMINAL_WINDOW)
+            .map(|((_, terminal_id), _)| terminal_id.clone()));
+
+        RealtimeStats {
+            as_of: now,
+            business_date,
+            store: store.map(str::to_string),
+            open_transactions,
+            sales_today: daily(&hours.into_iter().cloned().collect::<Vec<_>>()),
+            transactions_by_hour,
+            average_service_secs: match count {
+                0 => 0.0,
+                n => service_ms as f64 / n as f64 / 1000.0,
+            },
+            active_terminals: active_terminals.into_iter().collect(),
+        }
+    }
+}
+
+// Sums hourly buckets per business date, store and currency
+fn daily(hours: &[SalesBucket]) -> Vec<DailySales> {
+    let mut days: BTreeMap<(NaiveDate, &str, &str), SalesBucket> = BTreeMap::new();
+    for bucket in hours {
+        days.entry((bucket.business_date, &bucket.store, &bucket.currency))
+            .or_insert_with(|| bucket.empty())
+            .add(bucket);
+    }
+    days.into_values()
+        .map(|b| DailySales {
+            business_date: b.business_date,
+            store: b.store,
+            currency: b.currency,
+            transaction_count: b.transaction_count,
+            units: b.units,
+            gross_minor: b.gross_minor,
+            net_minor: b.net_minor,
+            average_basket_minor: b.average_basket_minor,
+        })
+        .collect()
+}
+
+impl HourlySalesReport {
+    // The hourly table, a blank line, then the daily table
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("business_date,hour_start,hour,store,currency,transaction_count,units,gross_minor,net_minor,average_basket_minor\n");
+        for row in &self.hours {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                row.business_date,
+                row.hour_start.to_rfc3339(),
+                row.hour,
+                csv_field(&row.store),
+                csv_field(&row.currency),
+                row.transaction_count,
+                row.units,
+                row.gross_minor,
+                row.net_minor,
+                row.average_basket_minor
+            ));
+        }
+        csv.push_str("\nbusiness_date,store,currency,transaction_count,units,gross_minor,net_minor,average_basket_minor\n");
+        for row in &self.days {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                row.business_date,
+                csv_field(&row.store),
+                csv_field(&row.currency),
+                row.transaction_count,
+                row.units,
+                row.gross_minor,
+                row.net_minor,
+                row.average_basket_minor
+            ));
+        }
+        csv
+    }
+}
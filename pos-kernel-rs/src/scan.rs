@@ -0,0 +1,224 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Barcode scans
+//! Raw scanner output is parsed before any product lookup. An optional AIM symbology
+//! identifier ("]E0", "]C1", ...) is stripped; EAN-13, EAN-8, UPC-A and UPC-E codes have their
+//! check digit verified and are normalized to a 14-digit GTIN; GS1 element strings (GS1-128,
+//! DataBar, GS1 DataMatrix and QR, FNC1 sent as GS) are split into application identifiers.
+//! Variable-measure items carry their net weight (AI 310n) or price (AI 392n) in the code.
+//! Other symbologies pass through as the scanned text. Unsupported AIs are rejected rather
+//! than guessed at, since a misread length would shift every field after it.
+
+use serde::{Deserialize, Serialize};
+
+const GS: char = '\u{1d}';
+const GTIN_LEN: usize = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Symbology {
+    Ean13,
+    Ean8,
+    UpcA,
+    UpcE,
+    Gs1,
+    Other,
+}
+
+// A decimal value encoded in the barcode with its number of decimal places
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodedDecimal {
+    pub value: i64,
+    pub decimal_places: u8,
+}
+
+impl EncodedDecimal {
+    // The value in units of 10^-decimal_places; None when that would lose digits or overflow
+    pub fn scaled(&self, decimal_places: u8) -> Option<i64> {
+        if decimal_places >= self.decimal_places {
+            let factor = 10i64.checked_pow(u32::from(decimal_places - self.decimal_places))?;
+            return self.value.checked_mul(factor);
+        }
+        let divisor = 10i64.pow(u32::from(self.decimal_places - decimal_places));
+        (self.value % divisor == 0).then(|| self.value / divisor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scan {
+    pub symbology: Symbology,
+    // The 14-digit GTIN of a GS1 code, otherwise the scanned text
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+    // Expiry date as encoded (YYMMDD)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    // Net weight in kilograms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_weight: Option<EncodedDecimal>,
+    // Price payable, in the transaction's currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<EncodedDecimal>,
+}
+
+impl Scan {
+    fn new(symbology: Symbology, code: String) -> Self {
+        Self { symbology, code, batch: None, expiry: None, serial: None, net_weight: None, price: None }
+    }
+}
+
+pub fn parse(raw: &str) -> Result<Scan, String> {
+    let raw = raw.trim_end_matches(['\r', '\n']);
+    if raw.is_empty() || raw.chars().any(|c| c.is_control() && c != GS) {
+        return Err("Unreadable barcode".to_string());
+    }
+
+    let (identifier, data) = match raw.strip_prefix(']') {
+        Some(rest) if rest.len() >= 2 && rest.is_char_boundary(2) => (Some(&rest[..2]), &rest[2..]),
+        Some(_) => return Err("Unreadable barcode".to_string()),
+        None => (None, raw),
+    };
+
+    // FNC1 in first position marks a GS1 element string
+    if matches!(identifier, Some("C1" | "e0" | "d2" | "Q3")) || data.starts_with(GS) {
+        return parse_element_string(data.trim_start_matches(GS));
+    }
+
+    let numeric = !data.is_empty() && data.bytes().all(|b| b.is_ascii_digit());
+    let symbology = match (numeric, data.len(), identifier) {
+        (true, 13, None | Some("E0")) => Symbology::Ean13,
+        (true, 12, None | Some("E0")) => Symbology::UpcA,
+        (true, 8, Some("E0")) => Symbology::UpcE,
+        (true, 8, None | Some("E4")) => Symbology::Ean8,
+        _ if matches!(identifier, Some(id) if id.starts_with('E')) => return Err("Unreadable barcode".to_string()),
+        _ => return Ok(Scan::new(Symbology::Other, data.to_string())),
+    };
+
+    let digits = match symbology {
+        Symbology::UpcE => expand_upc_e(data)?,
+        _ => data.to_string(),
+    };
+    Ok(Scan::new(symbology, gtin(&digits)?))
+}
+
+// Pads a GS1 code to a GTIN-14 after verifying its check digit
+fn gtin(digits: &str) -> Result<String, String> {
+    let (body, check) = digits.split_at(digits.len() - 1);
+    if check_digit(body) != check.as_bytes()[0] - b'0' {
+        return Err("Barcode check digit mismatch".to_string());
+    }
+    Ok(format!("{:0>width$}", digits, width = GTIN_LEN))
+}
+
+// GS1 mod-10: weights 3 and 1 alternate from the rightmost digit
+pub(crate) fn check_digit(body: &str) -> u8 {
+    let sum: u32 = body.bytes().rev().enumerate()
+        .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+// UPC-E (number system, six digits, check digit) to the UPC-A it abbreviates
+fn expand_upc_e(code: &str) -> Result<String, String> {
+    let (system, check) = (&code[..1], &code[7..]);
+    if system != "0" && system != "1" {
+        return Err("Unreadable barcode".to_string());
+    }
+    let d = |i: usize| char::from(code.as_bytes()[i + 1]);
+    let body: String = match d(5) {
+        '0'..='2' => [d(0), d(1), d(5), '0', '0', '0', '0', d(2), d(3), d(4)].iter().collect(),
+        '3' => [d(0), d(1), d(2), '0', '0', '0', '0', '0', d(3), d(4)].iter().collect(),
+        '4' => [d(0), d(1), d(2), d(3), '0', '0', '0', '0', '0', d(4)].iter().collect(),
+        _ => [d(0), d(1), d(2), d(3), d(4), '0', '0', '0', '0', d(5)].iter().collect(),
+    };
+    Ok(format!("{}{}{}", system, body, check))
+}
+
+// Supported application identifiers: (AI length, value length, fixed length)
+fn application_identifier(data: &str) -> Option<(usize, usize, bool)> {
+    Some(match data.get(..2)? {
+        "00" => (2, 18, true),
+        "01" | "02" => (2, 14, true),
+        "11" | "13" | "15" | "16" | "17" => (2, 6, true),
+        "10" | "21" => (2, 20, false),
+        "30" | "37" => (2, 8, false),
+        "31" if data.get(2..3)? == "0" => (4, 6, true),
+        "39" if data.get(2..3)? == "2" => (4, 15, false),
+        _ => return None,
+    })
+}
+
+fn parse_element_string(data: &str) -> Result<Scan, String> {
+    if !data.is_ascii() {
+        return Err("Unreadable barcode".to_string());
+    }
+
+    let mut scan = Scan::new(Symbology::Gs1, String::new());
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (ai_len, value_len, fixed) = application_identifier(rest)
+            .ok_or_else(|| format!("Unsupported GS1 application identifier in {}", &rest[..rest.len().min(4)]))?;
+        if !rest[..ai_len].bytes().all(|b| b.is_ascii_digit()) {
+            return Err("Unreadable barcode".to_string());
+        }
+        let (ai, after) = rest.split_at(ai_len);
+
+        // Fixed-length values may still be followed by a separator
+        let end = match fixed {
+            true if after.len() < value_len => return Err("Truncated GS1 element string".to_string()),
+            true => value_len,
+            false => after.find(GS).unwrap_or(after.len()),
+        };
+        let value = &after[..end];
+        if value.is_empty() || value.len() > value_len || value.contains(GS) {
+            return Err(format!("Invalid value for GS1 application identifier {}", ai));
+        }
+        rest = after[end..].strip_prefix(GS).unwrap_or(&after[end..]);
+
+        let numeric = || -> Result<i64, String> {
+            value.parse::<i64>().ok().filter(|_| value.bytes().all(|b| b.is_ascii_digit()))
+                .ok_or_else(|| format!("Invalid value for GS1 application identifier {}", ai))
+        };
+        let decimal = || -> Result<EncodedDecimal, String> {
+            Ok(EncodedDecimal { value: numeric()?, decimal_places: ai.as_bytes()[3] - b'0' })
+        };
+        match &ai[..2] {
+            "01" => {
+                numeric()?;
+                scan.code = gtin(value)?;
+            },
+            "10" => scan.batch = Some(value.to_string()),
+            "17" => {
+                numeric()?;
+                scan.expiry = Some(value.to_string());
+            },
+            "21" => scan.serial = Some(value.to_string()),
+            "31" => scan.net_weight = Some(decimal()?),
+            "39" => scan.price = Some(decimal()?),
+            // Recognized so that the fields after them parse; not used at the lane
+            _ => {},
+        }
+    }
+
+    if scan.code.is_empty() {
+        return Err("GS1 barcode has no GTIN".to_string());
+    }
+    Ok(scan)
+}
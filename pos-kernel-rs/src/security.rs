@@ -0,0 +1,91 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! Self-checkout security hooks
+//! SECURITY: A registered `LineSecurity` module screens every item line as it is added, while
+//! the transaction is still locked, and may hold it pending confirmation (a bagging-scale
+//! weight mismatch, an age-restricted item, an unscanned item seen by a camera). A held line
+//! stays on the transaction, but the transaction cannot be tendered or finalized until every
+//! hold is resolved: confirmed by the module once the condition clears, overridden by an
+//! attendant holding the SecurityOverride permission, or rejected, which voids the line.
+//! Holds and their resolutions are journaled and published as events.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{write_store_for_api, KernelError};
+
+// The line as the module sees it
+#[derive(Debug, Clone, Serialize)]
+pub struct LineScreening<'a> {
+    pub transaction_handle: u64,
+    pub terminal_id: Option<&'a str>,
+    pub line_id: u32,
+    pub parent_line_id: Option<u32>,
+    pub sku: &'a str,
+    pub qty: i32,
+    pub unit_minor: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityVerdict {
+    Accept,
+    Hold { reason: String },
+}
+
+pub trait LineSecurity: Send + Sync {
+    // Identifies the module in journal entries and events
+    fn name(&self) -> &str;
+
+    // Decides at once; a module waiting on a device holds the line and confirms it later
+    fn screen(&self, line: &LineScreening) -> SecurityVerdict;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineHold {
+    pub module: String,
+    pub reason: String,
+    pub held_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HoldResolution {
+    // The module saw the condition clear
+    Confirmed = 0,
+    // An attendant accepted the line as it stands
+    Overridden = 1,
+    // The line is voided
+    Rejected = 2,
+}
+
+impl HoldResolution {
+    pub(crate) fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(HoldResolution::Confirmed),
+            1 => Some(HoldResolution::Overridden),
+            2 => Some(HoldResolution::Rejected),
+            _ => None,
+        }
+    }
+}
+
+// Registers (or replaces) the line security module
+pub fn set_line_security(module: Box<dyn LineSecurity>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.line_security = Some(module);
+    Ok(())
+}
@@ -0,0 +1,278 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Sharded active-transaction table
+//! ARCHITECTURAL PRINCIPLE: Active transactions are partitioned by handle hash into shards,
+//! each behind its own lock. Operations holding the store exclusively reach transactions
+//! without locking; per-lane operations running under the shared store lock lock only the
+//! shard of their transaction, so lanes on different shards proceed in parallel.
+//! Each shard keeps its transactions in a slab: slots of settled transactions are reused by
+//! new ones, and guards address their transaction by slot without rehashing the handle.
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use slab::Slab;
+
+use crate::{KernelError, Transaction, TRANSACTION_NOT_FOUND};
+use crate::{lock, totals};
+
+#[derive(Default)]
+struct Shard {
+    slots: HashMap<u64, usize>,
+    transactions: Slab<Transaction>,
+}
+
+impl Shard {
+    fn slot(&self, handle: u64) -> Option<usize> {
+        self.slots.get(&handle).copied()
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        if let Some(slot) = self.slot(tx.id) {
+            self.transactions[slot] = tx;
+            return;
+        }
+        let handle = tx.id;
+        let slot = self.transactions.insert(tx);
+        self.slots.insert(handle, slot);
+    }
+
+    fn remove(&mut self, handle: u64) -> Option<Transaction> {
+        let slot = self.slots.remove(&handle)?;
+        Some(self.transactions.remove(slot))
+    }
+
+    fn get_mut(&mut self, handle: u64) -> Option<&mut Transaction> {
+        let slot = self.slot(handle)?;
+        self.transactions.get_mut(slot)
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+pub(crate) struct TransactionShards {
+    shards: Vec<RwLock<Shard>>,
+}
+
+// Shared access to one active transaction; holds its shard's read lock
+pub(crate) struct TxReadGuard<'a> {
+    guard: RwLockReadGuard<'a, Shard>,
+    slot: usize,
+}
+
+// Exclusive access to one active transaction; holds its shard's write lock
+pub(crate) struct TxWriteGuard<'a> {
+    guard: RwLockWriteGuard<'a, Shard>,
+    slot: usize,
+}
+
+// Exclusive access to one active transaction under the exclusive store lock
+// A shard write-locked ahead of inserting a new transaction, so a lane that waits out the lock
+// timeout fails before anything about the transaction is journaled
+pub(crate) struct ShardEntry<'a> {
+    guard: RwLockWriteGuard<'a, Shard>,
+}
+
+pub(crate) struct TxMut<'a> {
+    tx: &'a mut Transaction,
+}
+
+// A transaction looked up in the active table or in the archive
+pub(crate) enum TxRef<'a> {
+    Active(TxReadGuard<'a>),
+    Archived(Arc<Transaction>),
+}
+
+// Guards are only created for occupied slots of the locked shard, and the shard cannot
+// change while the guard is held
+impl Deref for TxReadGuard<'_> {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        &self.guard.transactions[self.slot]
+    }
+}
+
+impl Deref for TxWriteGuard<'_> {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        &self.guard.transactions[self.slot]
+    }
+}
+
+impl DerefMut for TxWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Transaction {
+        &mut self.guard.transactions[self.slot]
+    }
+}
+
+impl Deref for TxMut<'_> {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        self.tx
+    }
+}
+
+impl DerefMut for TxMut<'_> {
+    fn deref_mut(&mut self) -> &mut Transaction {
+        self.tx
+    }
+}
+
+// Releasing exclusive access publishes the transaction's totals for lock-free readers and
+// drops any pinned line buffer, which may no longer match the lines
+impl Drop for TxWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.release_pinned_lines();
+        self.publish_totals();
+    }
+}
+
+impl Drop for TxMut<'_> {
+    fn drop(&mut self) {
+        self.tx.release_pinned_lines();
+        self.tx.publish_totals();
+    }
+}
+
+impl Deref for TxRef<'_> {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        match self {
+            TxRef::Active(guard) => guard,
+            TxRef::Archived(tx) => tx,
+        }
+    }
+}
+
+impl ShardEntry<'_> {
+    pub fn insert(mut self, tx: Transaction) {
+        register(&tx);
+        self.guard.insert(tx);
+    }
+}
+
+fn register(tx: &Transaction) {
+    totals::register(tx.id, tx.cached_totals());
+    tx.publish_totals();
+}
+
+impl TransactionShards {
+    pub fn new(shard_count: usize) -> Self {
+        Self {
+            shards: (0..shard_count.max(1)).map(|_| RwLock::new(Shard::default())).collect(),
+        }
+    }
+
+    // Handles are sequential; mixing spreads consecutive handles across shards
+    fn shard_index(&self, handle: u64) -> usize {
+        (handle.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % self.shards.len()
+    }
+
+    // A panic while a shard was locked leaves per-transaction state for the operation's own
+    // checks; the shard itself stays usable
+    fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, Shard> {
+        self.shards[index].read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn shard_mut(&mut self, handle: u64) -> &mut Shard {
+        let index = self.shard_index(handle);
+        self.shards[index].get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn insert(&mut self, tx: Transaction) {
+        register(&tx);
+        self.shard_mut(tx.id).insert(tx);
+    }
+
+    // Lane-facing insert under the shared store lock
+    pub fn entry(&self, handle: u64) -> Result<ShardEntry<'_>, KernelError> {
+        let guard = lock::write(&self.shards[self.shard_index(handle)], "transaction shard")?
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(ShardEntry { guard })
+    }
+
+    pub fn remove(&mut self, handle: u64) -> Option<Transaction> {
+        let mut tx = self.shard_mut(handle).remove(handle)?;
+        totals::unregister(handle, &tx.cached_totals());
+        tx.release_pinned_lines();
+        Some(tx)
+    }
+
+    pub fn get_mut(&mut self, handle: u64) -> Option<TxMut<'_>> {
+        self.shard_mut(handle).get_mut(handle).map(|tx| TxMut { tx })
+    }
+
+    // Lane-facing lookups wait for the shard no longer than the lock timeout
+    pub fn get(&self, handle: u64) -> Result<Option<TxReadGuard<'_>>, KernelError> {
+        let guard = lock::read(&self.shards[self.shard_index(handle)], "transaction shard")?
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(guard.slot(handle).map(|slot| TxReadGuard { guard, slot }))
+    }
+
+    pub fn write(&self, handle: u64) -> Result<TxWriteGuard<'_>, KernelError> {
+        let guard = lock::write(&self.shards[self.shard_index(handle)], "transaction shard")?
+            .unwrap_or_else(PoisonError::into_inner);
+        // Built only for a present handle: dropping a guard publishes its transaction
+        let slot = guard.slot(handle).ok_or_else(|| KernelError::not_found(TRANSACTION_NOT_FOUND))?;
+        Ok(TxWriteGuard { guard, slot })
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Transaction> {
+        self.shards.iter_mut()
+            .flat_map(|s| s.get_mut().unwrap_or_else(PoisonError::into_inner).transactions.iter_mut().map(|(_, tx)| tx))
+    }
+
+    // Shard-aware enumeration: one shard is locked at a time, so enumerating never blocks
+    // lanes on other shards
+    pub fn shard_sizes(&self) -> Vec<usize> {
+        (0..self.shards.len()).map(|i| self.read_shard(i).len()).collect()
+    }
+
+    pub fn count_where(&self, predicate: impl Fn(&Transaction) -> bool) -> usize {
+        (0..self.shards.len())
+            .map(|i| self.read_shard(i).transactions.iter().filter(|(_, tx)| predicate(tx)).count())
+            .sum()
+    }
+
+    pub fn for_each(&self, mut visit: impl FnMut(&Transaction)) {
+        for i in 0..self.shards.len() {
+            self.read_shard(i).transactions.iter().for_each(|(_, tx)| visit(tx));
+        }
+    }
+
+    // Redistributes every transaction over a new number of shards
+    pub fn reshard(&mut self, shard_count: usize) {
+        let transactions: Vec<Transaction> = self.shards.iter_mut()
+            .flat_map(|s| s.get_mut().unwrap_or_else(PoisonError::into_inner).transactions.drain())
+            .collect();
+        *self = Self::new(shard_count);
+        for tx in transactions {
+            self.insert(tx);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Kernel record signing
+//! AUDIT COMPLIANCE: Records the kernel issues (anonymization records, ...) are signed with
+//! HMAC-SHA256 under a key supplied by user space, so they can be verified later.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub fn sign_hex(key: &[u8], payload: &[u8]) -> Result<String, String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|e| format!("Invalid signing key: {}", e))?;
+    mac.update(payload);
+    Ok(mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Checks a signature made by sign_hex; the comparison takes constant time
+pub fn verify_hex(key: &[u8], payload: &[u8], signature: &str) -> bool {
+    let signature: Option<Vec<u8>> = (0..signature.len()).step_by(2)
+        .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect();
+    let mut mac = match Hmac::<Sha256>::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(payload);
+    signature.is_some_and(|signature| mac.verify_slice(&signature).is_ok())
+}
@@ -0,0 +1,130 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Journal subscriptions
+//! ARCHITECTURAL PRINCIPLE: Downstream consumers follow the journal with resume tokens. Each
+//! entry is delivered with the token that resumes after it; a consumer stores the token in the
+//! same commit as its own effects, and resuming from it redelivers exactly what that commit did
+//! not cover. Processing is exactly once without the kernel tracking its subscribers.
+//! A token names a sequence number and the checksum of the entry there, so a token from
+//! another kernel's journal, or from a journal that was since replaced, is refused rather than
+//! silently skipping or replaying entries. Reads long-poll: a caught-up reader waits for the
+//! next entry, which is what a server-streaming endpoint needs to push entries as they are
+//! written.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::journal::{Journal, JournalEntry};
+use crate::{read_store_for_api, KernelError};
+
+pub(crate) const INVALID_RESUME_TOKEN: &str = "Invalid resume token";
+pub(crate) const RESUME_TOKEN_MISMATCH: &str = "Resume token does not match this journal";
+
+// How often a caught-up read looks for new entries
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_WAIT: Duration = Duration::from_secs(30);
+// Entries returned by a read that does not ask for fewer
+const MAX_READ_ENTRIES: usize = 1000;
+
+// Resumes after `sequence`; the start of the journal is sequence 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResumeToken {
+    sequence: u64,
+    checksum: u32,
+}
+
+impl ResumeToken {
+    pub const START: ResumeToken = ResumeToken { sequence: 0, checksum: 0 };
+
+    fn after(entry: &JournalEntry) -> Self {
+        Self { sequence: entry.sequence_number, checksum: entry.checksum }
+    }
+
+    // An empty token starts from the beginning of the journal
+    pub fn parse(token: &str) -> Result<Self, KernelError> {
+        if token.is_empty() {
+            return Ok(Self::START);
+        }
+        let (sequence, checksum) = token.split_once('-').ok_or_else(|| KernelError::validation(INVALID_RESUME_TOKEN))?;
+        match (sequence.parse(), u32::from_str_radix(checksum, 16)) {
+            (Ok(sequence), Ok(checksum)) => Ok(Self { sequence, checksum }),
+            _ => Err(KernelError::validation(INVALID_RESUME_TOKEN)),
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!("{}-{:08x}", self.sequence, self.checksum)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscribedEntry {
+    // Resumes after this entry
+    pub resume_token: String,
+    pub entry: JournalEntry,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JournalPage {
+    pub entries: Vec<SubscribedEntry>,
+    // Resumes after the last entry returned, or where the read started if there were none
+    pub resume_token: String,
+    // The journal's last sequence number when the page was read
+    pub last_sequence: u64,
+}
+
+// Reads the entries after `token`, checking that the token's entry is the one in this journal
+fn page_after(journal: &Journal, token: ResumeToken, limit: usize) -> Result<JournalPage, KernelError> {
+    let mut entries = Vec::new();
+    let mut verified = token.sequence == 0;
+    let mut mismatch = false;
+    journal.scan_after(token.sequence.saturating_sub(1), |entry| {
+        if !verified {
+            verified = entry.sequence_number == token.sequence && entry.checksum == token.checksum;
+            mismatch = !verified;
+            return verified;
+        }
+        entries.push(SubscribedEntry { resume_token: ResumeToken::after(entry).encode(), entry: entry.protected() });
+        entries.len() < limit
+    }).map_err(KernelError::Internal)?;
+    // A token past the end of the journal never verified either
+    if mismatch || !verified {
+        return Err(KernelError::invalid_state(RESUME_TOKEN_MISMATCH));
+    }
+
+    let resume_token = entries.last().map_or_else(|| token.encode(), |e| e.resume_token.clone());
+    Ok(JournalPage { entries, resume_token, last_sequence: journal.last_sequence() })
+}
+
+// Reads up to `max_entries` (0 for the default batch) after `token`, waiting up to `wait` for
+// an entry if none is there yet. The store lock is not held while waiting.
+pub(crate) fn read(token: ResumeToken, max_entries: usize, wait: Duration) -> Result<JournalPage, KernelError> {
+    let limit = match max_entries {
+        0 => MAX_READ_ENTRIES,
+        n => n.min(MAX_READ_ENTRIES),
+    };
+    let deadline = Instant::now() + wait.min(MAX_WAIT);
+    loop {
+        let page = page_after(&read_store_for_api()?.journal, token, limit)?;
+        if !page.entries.is_empty() || Instant::now() >= deadline {
+            return Ok(page);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
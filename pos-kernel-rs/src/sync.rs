@@ -0,0 +1,454 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Store-and-forward sync
+//! ARCHITECTURAL PRINCIPLE: A terminal runs its own embedded kernel and journals locally, so
+//! selling never waits on the central service. Committed transactions are forwarded upward in
+//! journal order through a registered `SyncUpstream`; while the upstream is unreachable the
+//! terminal keeps selling offline and the backlog is simply the commits past the forwarding
+//! cursor. The cursor is journaled each time the central service acknowledges a batch, so
+//! a restarted terminal resumes where it left off. What is forwarded is read from the journal,
+//! outside the store lock: each transaction as its entries from begin to commit describe it,
+//! so a backlog journaled before a restart is sent as it was sold. The receipt number is the
+//! terminal's transaction handle, which continues past the journal's handles on restart and is
+//! therefore unique per terminal.
+//!
+//! The central service hands each batch it receives to a `SyncLedger` (`pk_ingest_sync_batch`),
+//! which detects conflicts:
+//! - Sequence gap: the terminal believes more was forwarded than the central service has
+//!   received (a lost batch, a restored backup). The batch is refused and the terminal rewinds
+//!   to the sequence the central service reports, then forwards again from there.
+//! - Duplicate receipt number: the terminal forwarded different content under a receipt number
+//!   it already used (a terminal restored from an older backup). The transaction is not
+//!   accepted and the conflict is reported to both sides for resolution. Receiving the same
+//!   transaction again is an ordinary resend and is accepted without conflict.
+//!
+//! The ledger journals what it accepts, so a restarted central service rebuilds it from its
+//! own journal. It remembers the most recent `sync_ledger_receipts` receipts; a forgotten
+//! receipt number received again is accepted as new.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::journal::{Journal, JournalEntry, JournalOperation};
+use crate::replay;
+use crate::{write_store_for_api, KernelError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedTransaction {
+    // Journal sequence number of the commit on the terminal
+    pub sequence_number: u64,
+    pub store: String,
+    // The terminal's transaction handle
+    pub receipt_number: u64,
+    // SHA-256 of the transaction document, hex encoded
+    pub digest: String,
+    // The transaction as journaled, as a `replay::ReplayedTransaction`
+    pub transaction: Value,
+}
+
+impl SyncedTransaction {
+    pub(crate) fn new(sequence_number: u64, store: String, receipt_number: u64, transaction: Value) -> Self {
+        let digest = Sha256::digest(transaction.to_string().as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        Self { sequence_number, store, receipt_number, digest, transaction }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncBatch {
+    // The terminal's node ID, unique across the estate
+    pub origin: String,
+    // Everything the terminal journaled up to here was acknowledged by the central service
+    pub after_sequence: u64,
+    // The batch covers the terminal's journal up to here
+    pub through_sequence: u64,
+    pub transactions: Vec<SyncedTransaction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SyncConflict {
+    SequenceGap { origin: String, expected_after: u64, received_after: u64 },
+    // The terminal journal sequence numbers of the commit received before and of this one
+    DuplicateReceipt { origin: String, store: String, receipt_number: u64, existing_sequence: u64, sequence_number: u64 },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncResponse {
+    // The origin's journal is acknowledged up to here; on a gap, where to resume from
+    pub acknowledged_through: u64,
+    // Receipt numbers accepted from the batch
+    pub accepted: Vec<u64>,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+pub trait SyncUpstream: Send + Sync {
+    // Delivers a batch to the central service; an error means it could not be reached
+    fn submit(&self, batch: &SyncBatch) -> Result<SyncResponse, String>;
+}
+
+// Conflicts reported to the terminal that are kept for status reports
+const CONFLICT_HISTORY: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub node_id: String,
+    // The last attempt reached the central service
+    pub online: bool,
+    pub acknowledged_through: u64,
+    // Commits journaled since then, still to be forwarded
+    pub pending: usize,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    // Most recent first
+    pub conflicts: Vec<SyncConflict>,
+}
+
+struct ForwarderState {
+    acknowledged_through: u64,
+    syncing: bool,
+    online: bool,
+    last_attempt: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    conflicts: Vec<SyncConflict>,
+}
+
+// The terminal side: forwards commits past the acknowledged cursor
+pub(crate) struct Forwarder {
+    node_id: String,
+    upstream: Arc<dyn SyncUpstream>,
+    state: Mutex<ForwarderState>,
+}
+
+// A commit claimed for forwarding
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClaimedCommit {
+    pub sequence_number: u64,
+    pub handle: u64,
+    // None for commits journaled before begin sequences were recorded
+    pub begin_sequence: Option<u64>,
+}
+
+// A batch claimed under the store lock, read from the journal and sent without it
+pub(crate) struct PendingBatch {
+    pub forwarder: Arc<Forwarder>,
+    pub journal: Arc<Journal>,
+    pub batch: SyncBatch,
+    pub commits: Vec<ClaimedCommit>,
+}
+
+impl PendingBatch {
+    // An unreadable journal fails the attempt like an unreachable upstream, so the cursor stays
+    // put; a panicking upstream counts as unreachable, so the forwarder is not left claimed
+    pub fn submit(&mut self) -> Result<SyncResponse, String> {
+        self.batch.transactions = self.read_transactions()
+            .map_err(|e| format!("Sync backlog not read: {}", e))?;
+        panic::catch_unwind(AssertUnwindSafe(|| self.forwarder.upstream.submit(&self.batch)))
+            .unwrap_or_else(|_| Err("Sync upstream panicked".to_string()))
+    }
+
+    // Each claimed commit as its entries from the latest begin of its handle describe it.
+    // The scan starts at the earliest begin entry, or the start of the journal for commits
+    // without one.
+    fn read_transactions(&self) -> Result<Vec<SyncedTransaction>, String> {
+        let from = self.commits.iter().map(|c| c.begin_sequence.unwrap_or(1)).min().unwrap_or(1) - 1;
+        let handles: HashSet<u64> = self.commits.iter().map(|c| c.handle).collect();
+        let claimed: HashSet<u64> = self.commits.iter().map(|c| c.sequence_number).collect();
+        let through = self.batch.through_sequence;
+
+        let mut open: HashMap<u64, Vec<JournalEntry>> = HashMap::new();
+        let mut committed = HashMap::new();
+        self.journal.scan_after(from, |entry| {
+            if entry.sequence_number > through {
+                return false;
+            }
+            let handle = entry.transaction_handle;
+            if !handles.contains(&handle) {
+                return true;
+            }
+            if matches!(entry.operation, JournalOperation::TransactionBegin { .. }) {
+                open.remove(&handle);
+            }
+            open.entry(handle).or_default().push(entry.protected());
+            if claimed.contains(&entry.sequence_number) {
+                let entries = open.remove(&handle).unwrap_or_default();
+                committed.insert(entry.sequence_number, replay::replay_transaction(handle, entries));
+            }
+            true
+        })?;
+
+        self.commits.iter()
+            .map(|commit| {
+                let tx = committed.remove(&commit.sequence_number).flatten()
+                    .ok_or_else(|| format!("Commit {} of transaction {} not in the journal", commit.sequence_number, commit.handle))?;
+                let document = serde_json::to_value(&tx)
+                    .map_err(|e| format!("Failed to serialize transaction {}: {}", commit.handle, e))?;
+                Ok(SyncedTransaction::new(commit.sequence_number, tx.store.unwrap_or_default(), commit.handle, document))
+            })
+            .collect()
+    }
+}
+
+impl Forwarder {
+    pub fn new(node_id: String, upstream: Arc<dyn SyncUpstream>, acknowledged_through: u64) -> Self {
+        Self {
+            node_id,
+            upstream,
+            state: Mutex::new(ForwarderState {
+                acknowledged_through,
+                syncing: false,
+                online: false,
+                last_attempt: None,
+                last_error: None,
+                conflicts: Vec::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ForwarderState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Claims the next batch: up to `limit` commit entries past the cursor, returned for the
+    // batch to be read from the journal. None while a batch is in flight or nothing is
+    // pending, and, unless `force`, within `retry_interval_secs` of a failed attempt.
+    pub fn claim(&self, journal: &Journal, limit: usize, force: bool, retry_interval_secs: u64) -> Option<(SyncBatch, Vec<ClaimedCommit>)> {
+        let after_sequence = {
+            let mut state = self.state();
+            let backing_off = !state.online && state.last_attempt
+                .is_some_and(|at| u64::try_from((Utc::now() - at).num_seconds()).unwrap_or(0) < retry_interval_secs);
+            if state.syncing || (backing_off && !force) {
+                return None;
+            }
+            state.syncing = true;
+            state.acknowledged_through
+        };
+
+        let mut commits = Vec::new();
+        let scanned = journal.scan_after(after_sequence, |entry| {
+            if let JournalOperation::TransactionCommit { begin_sequence, .. } = entry.operation {
+                commits.push(ClaimedCommit {
+                    sequence_number: entry.sequence_number,
+                    handle: entry.transaction_handle,
+                    begin_sequence,
+                });
+            }
+            commits.len() < limit.max(1)
+        });
+        if let Err(e) = &scanned {
+            eprintln!("WARNING: Sync backlog not read: {}", e);
+        }
+        let through_sequence = match commits.last() {
+            Some(commit) if scanned.is_ok() => commit.sequence_number,
+            _ => {
+                self.state().syncing = false;
+                return None;
+            },
+        };
+        let batch = SyncBatch { origin: self.node_id.clone(), after_sequence, through_sequence, transactions: Vec::new() };
+        Some((batch, commits))
+    }
+
+    // Applies the central service's answer; returns the new cursor when it moved
+    pub fn finish(&self, result: &Result<SyncResponse, String>) -> Option<u64> {
+        let mut state = self.state();
+        state.syncing = false;
+        state.last_attempt = Some(Utc::now());
+        match result {
+            Ok(response) => {
+                state.online = true;
+                state.last_error = None;
+                for conflict in &response.conflicts {
+                    state.conflicts.insert(0, conflict.clone());
+                }
+                state.conflicts.truncate(CONFLICT_HISTORY);
+                let moved = response.acknowledged_through != state.acknowledged_through;
+                state.acknowledged_through = response.acknowledged_through;
+                moved.then_some(response.acknowledged_through)
+            },
+            Err(e) => {
+                state.online = false;
+                state.last_error = Some(e.clone());
+                None
+            },
+        }
+    }
+
+    pub fn status(&self, journal: &Journal) -> SyncStatus {
+        let mut status = {
+            let state = self.state();
+            SyncStatus {
+                node_id: self.node_id.clone(),
+                online: state.online,
+                acknowledged_through: state.acknowledged_through,
+                pending: 0,
+                last_attempt: state.last_attempt,
+                last_error: state.last_error.clone(),
+                conflicts: state.conflicts.clone(),
+            }
+        };
+        let scanned = journal.scan_after(status.acknowledged_through, |entry| {
+            status.pending += matches!(entry.operation, JournalOperation::TransactionCommit { .. }) as usize;
+            true
+        });
+        if let Err(e) = scanned {
+            eprintln!("WARNING: Sync backlog not read: {}", e);
+        }
+        status
+    }
+}
+
+// The cursor a restarted terminal resumes from: the last acknowledgement it journaled
+pub(crate) fn journaled_cursor(journal: &Journal) -> Result<u64, String> {
+    let mut cursor = 0;
+    journal.scan_after(0, |entry| {
+        if let JournalOperation::SyncAcknowledge { through_sequence, .. } = entry.operation {
+            cursor = through_sequence;
+        }
+        true
+    })?;
+    Ok(cursor)
+}
+
+// A receipt the central service accepted, as journaled by the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncReceipt {
+    pub receipt_number: u64,
+    // The terminal journal sequence number of the commit
+    pub sequence_number: u64,
+    pub store: String,
+    pub digest: String,
+}
+
+struct ReceivedReceipt {
+    sequence_number: u64,
+    digest: String,
+}
+
+// The central side: what each terminal has forwarded
+#[derive(Default)]
+pub(crate) struct SyncLedger {
+    // Acknowledged journal sequence, by origin
+    cursors: HashMap<String, u64>,
+    // By origin and receipt number
+    receipts: HashMap<(String, u64), ReceivedReceipt>,
+    // The same keys, oldest first, so the oldest are forgotten beyond the limit
+    received: VecDeque<(String, u64)>,
+}
+
+impl SyncLedger {
+    // The ledger a restarted central service resumes with, from the batches it journaled
+    pub fn from_journal(journal: &Journal, max_receipts: usize) -> Result<Self, String> {
+        let mut ledger = Self::default();
+        journal.scan_after(0, |entry| {
+            if let JournalOperation::SyncIngest { origin, through_sequence, receipts } = &entry.operation {
+                ledger.record(origin, *through_sequence, receipts, max_receipts);
+            }
+            true
+        })?;
+        Ok(ledger)
+    }
+
+    // A resend of batches already acknowledged does not move the cursor back
+    fn record(&mut self, origin: &str, through_sequence: u64, receipts: &[SyncReceipt], max_receipts: usize) {
+        let cursor = self.cursors.entry(origin.to_string()).or_insert(0);
+        *cursor = (*cursor).max(through_sequence);
+        for receipt in receipts {
+            let key = (origin.to_string(), receipt.receipt_number);
+            let received = ReceivedReceipt { sequence_number: receipt.sequence_number, digest: receipt.digest.clone() };
+            if self.receipts.insert(key.clone(), received).is_none() {
+                self.received.push_back(key);
+            }
+        }
+        while self.received.len() > max_receipts {
+            if let Some(key) = self.received.pop_front() {
+                self.receipts.remove(&key);
+            }
+        }
+    }
+
+    // Returns the answer for the terminal and, when the ledger changed, the entry to journal
+    pub fn ingest(&mut self, batch: &SyncBatch, max_receipts: usize) -> (SyncResponse, Option<JournalOperation>) {
+        let expected_after = self.cursors.get(&batch.origin).copied().unwrap_or(0);
+        if batch.after_sequence > expected_after {
+            let response = SyncResponse {
+                acknowledged_through: expected_after,
+                accepted: Vec::new(),
+                conflicts: vec![SyncConflict::SequenceGap {
+                    origin: batch.origin.clone(),
+                    expected_after,
+                    received_after: batch.after_sequence,
+                }],
+            };
+            return (response, None);
+        }
+
+        let mut response = SyncResponse::default();
+        let mut received = Vec::new();
+        for synced in &batch.transactions {
+            match self.receipts.get(&(batch.origin.clone(), synced.receipt_number)) {
+                Some(existing) if existing.digest == synced.digest => {
+                    response.accepted.push(synced.receipt_number);
+                },
+                Some(existing) => response.conflicts.push(SyncConflict::DuplicateReceipt {
+                    origin: batch.origin.clone(),
+                    store: synced.store.clone(),
+                    receipt_number: synced.receipt_number,
+                    existing_sequence: existing.sequence_number,
+                    sequence_number: synced.sequence_number,
+                }),
+                None => {
+                    received.push(SyncReceipt {
+                        receipt_number: synced.receipt_number,
+                        sequence_number: synced.sequence_number,
+                        store: synced.store.clone(),
+                        digest: synced.digest.clone(),
+                    });
+                    response.accepted.push(synced.receipt_number);
+                },
+            }
+        }
+
+        self.record(&batch.origin, batch.through_sequence, &received, max_receipts);
+        response.acknowledged_through = self.cursors.get(&batch.origin).copied().unwrap_or(0);
+        let changed = !received.is_empty() || response.acknowledged_through != expected_after;
+        let operation = changed.then(|| JournalOperation::SyncIngest {
+            origin: batch.origin.clone(),
+            through_sequence: response.acknowledged_through,
+            receipts: received,
+        });
+        (response, operation)
+    }
+}
+
+// Registers (or replaces) the upstream this terminal forwards its commits to. `node_id`
+// identifies the terminal to the central service and must be unique across the estate.
+pub fn set_sync_upstream(node_id: &str, upstream: Box<dyn SyncUpstream>) -> Result<(), KernelError> {
+    if node_id.is_empty() || node_id.chars().any(char::is_control) {
+        return Err(KernelError::validation("Sync node ID must be non-empty printable text"));
+    }
+
+    let mut store = write_store_for_api()?;
+    let cursor = journaled_cursor(&store.journal).map_err(KernelError::Internal)?;
+    store.sync_forwarder = Some(Arc::new(Forwarder::new(node_id.to_string(), Arc::from(upstream), cursor)));
+    Ok(())
+}
@@ -0,0 +1,223 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tax engine hook
+//! ARCHITECTURAL PRINCIPLE: Tax calculation stays in user space. A registered `TaxProvider`
+//! (typically a callout to an external tax calculation service) receives the basket and
+//! returns the per-line breakdown, which the kernel records in place of any earlier one.
+//! Results are cached by basket for `tax_cache_ttl_secs`, so recalculating an unchanged basket
+//! does not call the service again. When the service fails, the configured fallback rates are
+//! applied to each sale line instead and the fallback is journaled, so the lane can keep
+//! selling offline; with no fallback rates the calculation fails.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{write_store_for_api, KernelError, LineKind, TaxEntry, Transaction};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaxLine {
+    pub line_id: u32,
+    pub parent_line_id: Option<u32>,
+    pub sku: Arc<str>,
+    pub qty: i32,
+    pub unit_minor: i64,
+    // Signed: returns and deposit returns reduce the basket
+    pub total_minor: i64,
+    pub kind: LineKind,
+}
+
+// The basket as the tax engine sees it; voided lines are left out
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaxRequest {
+    pub store: String,
+    pub currency: String,
+    pub decimal_places: u8,
+    pub customer_tier: Option<String>,
+    pub lines: Vec<TaxLine>,
+}
+
+impl TaxRequest {
+    pub(crate) fn from_transaction(tx: &Transaction) -> Self {
+        TaxRequest {
+            store: tx.store.clone(),
+            currency: tx.currency.code.clone(),
+            decimal_places: tx.currency.decimal_places,
+            customer_tier: tx.customer.as_ref().and_then(|c| c.tier.clone()),
+            lines: tx.lines.iter()
+                .filter(|line| line.qty != 0)
+                .map(|line| TaxLine {
+                    line_id: line.line_id,
+                    parent_line_id: line.parent_line_item_id,
+                    sku: Arc::clone(&line.sku),
+                    qty: line.qty,
+                    unit_minor: line.unit_minor,
+                    total_minor: line.total_minor(),
+                    kind: line.kind,
+                })
+                .collect(),
+        }
+    }
+
+    // Identical baskets share a cache entry
+    fn cache_key(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+pub trait TaxProvider: Send + Sync {
+    // The tax breakdown for the basket; entries refer to basket lines by `line_id`
+    fn calculate(&self, request: &TaxRequest) -> Result<Vec<TaxEntry>, String>;
+}
+
+// A rate applied to every sale line while the tax provider is unreachable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackRate {
+    pub jurisdiction: String,
+    pub rate_code: String,
+    pub rate_basis_points: i32,
+    pub inclusive: bool,
+}
+
+impl FallbackRate {
+    // Rounded half up to the minor unit
+    fn entry(&self, line: &TaxLine) -> TaxEntry {
+        let (total, rate) = (line.total_minor as i128, self.rate_basis_points as i128);
+        let (taxable_minor, tax_minor) = if self.inclusive {
+            let tax = (total * rate * 2 + 10_000 + rate) / ((10_000 + rate) * 2);
+            (total - tax, tax)
+        } else {
+            (total, (total * rate * 2 + 10_000) / 20_000)
+        };
+        TaxEntry {
+            jurisdiction: self.jurisdiction.clone(),
+            rate_code: self.rate_code.clone(),
+            rate_basis_points: self.rate_basis_points,
+            taxable_minor: taxable_minor as i64,
+            tax_minor: tax_minor as i64,
+            exempt_minor: 0,
+            inclusive: self.inclusive,
+            line_id: Some(line.line_id),
+        }
+    }
+}
+
+pub(crate) const NO_TAX_PROVIDER: &str = "No tax provider registered";
+pub(crate) const TAX_UNAVAILABLE: &str = "Tax service unavailable";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum TaxSource {
+    Provider = 0,
+    Cache = 1,
+    Fallback = 2,
+}
+
+// Applies the fallback rates to the basket's live sale lines
+pub(crate) fn fallback_entries(request: &TaxRequest, rates: &[FallbackRate]) -> Vec<TaxEntry> {
+    request.lines.iter()
+        .filter(|line| line.kind == LineKind::Sale && line.total_minor > 0)
+        .flat_map(|line| rates.iter().map(move |rate| rate.entry(line)))
+        .collect()
+}
+
+struct CachedTaxes {
+    entries: Vec<TaxEntry>,
+    calculated_at: DateTime<Utc>,
+}
+
+impl CachedTaxes {
+    fn is_fresh(&self, now: DateTime<Utc>, ttl_secs: u64) -> bool {
+        u64::try_from((now - self.calculated_at).num_seconds()).unwrap_or(0) < ttl_secs
+    }
+}
+
+// Tax breakdowns returned by the provider, by basket
+#[derive(Default)]
+pub(crate) struct TaxCache {
+    baskets: Mutex<HashMap<String, CachedTaxes>>,
+}
+
+impl TaxCache {
+    fn baskets(&self) -> MutexGuard<'_, HashMap<String, CachedTaxes>> {
+        self.baskets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn len(&self) -> usize {
+        self.baskets().len()
+    }
+
+    pub fn clear(&self) {
+        self.baskets().clear();
+    }
+
+    // Calculates the basket's taxes, from the cache when an entry is younger than `ttl_secs`
+    // (0 disables the cache). The provider is called without the cache lock; a failure falls
+    // back to `rates` when there are any.
+    pub fn calculate(
+        &self,
+        provider: &dyn TaxProvider,
+        request: &TaxRequest,
+        ttl_secs: u64,
+        rates: &[FallbackRate],
+    ) -> Result<(Vec<TaxEntry>, TaxSource), KernelError> {
+        let key = request.cache_key();
+        if ttl_secs > 0 {
+            if let Some(cached) = self.baskets().get(&key).filter(|c| c.is_fresh(Utc::now(), ttl_secs)) {
+                return Ok((cached.entries.clone(), TaxSource::Cache));
+            }
+        }
+
+        match provider.calculate(request) {
+            Ok(entries) => {
+                if ttl_secs > 0 {
+                    let now = Utc::now();
+                    let mut baskets = self.baskets();
+                    baskets.retain(|_, c| c.is_fresh(now, ttl_secs));
+                    baskets.insert(key, CachedTaxes { entries: entries.clone(), calculated_at: now });
+                }
+                Ok((entries, TaxSource::Provider))
+            },
+            Err(e) if !rates.is_empty() => {
+                eprintln!("WARNING: Tax service failed, applying fallback rates: {}", e);
+                Ok((fallback_entries(request, rates), TaxSource::Fallback))
+            },
+            Err(e) => {
+                eprintln!("WARNING: Tax service failed: {}", e);
+                Err(KernelError::TimedOut(TAX_UNAVAILABLE.to_string()))
+            },
+        }
+    }
+}
+
+// Registers (or replaces) the store's tax provider; cached results of the previous one are
+// dropped
+pub fn set_tax_provider(provider: Box<dyn TaxProvider>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.tax_provider = Some(provider);
+    store.tax_cache.clear();
+    Ok(())
+}
+
+// Replaces the rates applied while the tax provider is unreachable; empty disables the fallback
+pub fn set_fallback_rates(rates: Vec<FallbackRate>) -> Result<(), KernelError> {
+    let mut store = write_store_for_api()?;
+    store.tax_fallback_rates = rates;
+    Ok(())
+}
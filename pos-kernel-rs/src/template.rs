@@ -0,0 +1,235 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Receipt template engine
+//! Stores customize receipt layout with text templates rendered against the `ReceiptDocument`.
+//!
+//! Syntax:
+//! - `{{header.store}}` inserts a value by dotted path; `{{.}}` is the current item
+//! - `{{total_minor|money}}` applies filters: `money[:sep]`, `upper`, `pad:N`, `rpad:N`, `default:text`
+//! - `{{#lines}}...{{/lines}}` repeats for each array item, or renders once for a non-empty value
+//! - `{{^discounts}}...{{/discounts}}` renders only when the value is missing or empty
+//! - `{{! comment }}` is ignored
+//!
+//! Besides the document fields, templates can iterate `items`: every printable line flattened in
+//! receipt order with `depth` and `indent` fields, for per-line formats without recursion.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::escpos::format_minor;
+use crate::receipt::{ReceiptDocument, ReceiptLine};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Value { path: String, filters: Vec<String> },
+    Section { path: String, inverted: bool, children: Vec<Node> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read template {}: {}", path.display(), e))?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut stack: Vec<(String, bool, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+            }
+            let offset = source.len() - rest.len() + start;
+            let line = source[..offset].matches('\n').count() + 1;
+            let end = rest[start..].find("}}")
+                .ok_or_else(|| format!("Unclosed tag on line {}", line))?;
+            let tag = rest[start + 2..start + end].trim();
+            rest = &rest[start + end + 2..];
+
+            if let Some(name) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+                let inverted = tag.starts_with('^');
+                stack.push((name.trim().to_string(), inverted, std::mem::take(&mut nodes)));
+            } else if let Some(name) = tag.strip_prefix('/') {
+                let (open, inverted, parent) = stack.pop()
+                    .ok_or_else(|| format!("Unexpected section close '{}' on line {}", name.trim(), line))?;
+                if open != name.trim() {
+                    return Err(format!("Section '{}' closed by '{}' on line {}", open, name.trim(), line));
+                }
+                let children = std::mem::replace(&mut nodes, parent);
+                nodes.push(Node::Section { path: open, inverted, children });
+            } else if !tag.starts_with('!') {
+                let mut parts = tag.split('|').map(|p| p.trim().to_string());
+                let path = parts.next().unwrap_or_default();
+                if path.is_empty() {
+                    return Err(format!("Empty tag on line {}", line));
+                }
+                nodes.push(Node::Value { path, filters: parts.collect() });
+            }
+        }
+
+        if let Some((open, _, _)) = stack.last() {
+            return Err(format!("Section '{}' is never closed", open));
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+        Ok(Self { nodes })
+    }
+
+    pub fn render(&self, receipt: &ReceiptDocument) -> Result<String, String> {
+        let mut root = serde_json::to_value(receipt)
+            .map_err(|e| format!("Failed to serialize receipt: {}", e))?;
+        let mut items = Vec::new();
+        for line in &receipt.lines {
+            flatten_line(line, 0, &mut items);
+        }
+        if let Value::Object(map) = &mut root {
+            map.insert("items".to_string(), Value::Array(items));
+        }
+
+        let decimal_places = receipt.header.decimal_places;
+        let mut out = String::new();
+        render_nodes(&self.nodes, &mut vec![&root], decimal_places, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn flatten_line(line: &ReceiptLine, depth: usize, items: &mut Vec<Value>) {
+    if let Ok(Value::Object(mut item)) = serde_json::to_value(line) {
+        item.remove("children");
+        item.insert("depth".to_string(), Value::from(depth));
+        item.insert("indent".to_string(), Value::from("  ".repeat(depth)));
+        items.push(Value::Object(item));
+    }
+    for child in &line.children {
+        flatten_line(child, depth + 1, items);
+    }
+}
+
+// Resolves a dotted path against the innermost context that has its first segment
+fn lookup<'a>(stack: &[&'a Value], path: &str) -> Option<&'a Value> {
+    if path == "." {
+        return stack.last().copied();
+    }
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    let mut value = stack.iter().rev().find_map(|context| context.get(first))?;
+    for segment in segments {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+    Some(value)
+}
+
+fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::Array(items)) => !items.is_empty(),
+        Some(Value::String(text)) => !text.is_empty(),
+        Some(Value::Object(map)) => !map.is_empty(),
+        Some(_) => true,
+    }
+}
+
+fn render_nodes(nodes: &[Node], stack: &mut Vec<&Value>, decimal_places: u8, out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value { path, filters } => {
+                let value = lookup(stack, path);
+                out.push_str(&apply_filters(value, filters, decimal_places)?);
+            },
+            Node::Section { path, inverted: true, children } => {
+                if !is_truthy(lookup(stack, path)) {
+                    render_nodes(children, stack, decimal_places, out)?;
+                }
+            },
+            Node::Section { path, inverted: false, children } => {
+                match lookup(stack, path) {
+                    Some(Value::Array(items)) => {
+                        for item in items {
+                            stack.push(item);
+                            render_nodes(children, stack, decimal_places, out)?;
+                            stack.pop();
+                        }
+                    },
+                    Some(item @ Value::Object(_)) => {
+                        stack.push(item);
+                        render_nodes(children, stack, decimal_places, out)?;
+                        stack.pop();
+                    },
+                    other if is_truthy(other) => render_nodes(children, stack, decimal_places, out)?,
+                    _ => {}
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+fn value_text(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn apply_filters(value: Option<&Value>, filters: &[String], decimal_places: u8) -> Result<String, String> {
+    let mut text = value_text(value);
+    for filter in filters {
+        let (name, arg) = match filter.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg)),
+            None => (filter.as_str(), None),
+        };
+        text = match name {
+            "money" => {
+                let minor = text.parse::<i64>()
+                    .map_err(|_| format!("'money' filter needs an integer amount, got '{}'", text))?;
+                let separator = arg.and_then(|a| a.chars().next()).unwrap_or('.');
+                format_minor(minor, decimal_places, separator)?
+            },
+            "upper" => text.to_uppercase(),
+            "default" => if text.is_empty() { arg.unwrap_or("").to_string() } else { text },
+            "pad" | "rpad" => {
+                let width = arg.and_then(|a| a.trim().parse::<usize>().ok())
+                    .ok_or_else(|| format!("'{}' filter needs a width", name))?;
+                let len = text.chars().count();
+                if len >= width {
+                    text.chars().take(width).collect()
+                } else if name == "pad" {
+                    format!("{}{}", text, " ".repeat(width - len))
+                } else {
+                    format!("{}{}", " ".repeat(width - len), text)
+                }
+            },
+            _ => return Err(format!("Unknown template filter '{}'", name)),
+        };
+    }
+    Ok(text)
+}
@@ -0,0 +1,122 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cached transaction totals
+//! ARCHITECTURAL PRINCIPLE: Customer displays poll totals constantly. Each transaction
+//! publishes its totals into a cell of atomics whenever it is modified, and readers load the
+//! cell without touching the store or shard locks. A sequence counter lets readers detect a
+//! concurrent publish and retry, so a snapshot never mixes values from two updates.
+//! A cell is registered under its handle while the transaction is in the active table and
+//! unregistered when it leaves; totals of settled transactions are then read from the store.
+//! Each registration has its own generation, so a cell unregistered concurrently with a
+//! lookup, or replaced by a later transaction under the same handle, is never served.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub(crate) struct TotalsSnapshot {
+    pub total_minor: i64,
+    pub tendered_minor: i64,
+    pub change_minor: i64,
+    pub line_count: u32,
+    pub state: u32,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CachedTotals {
+    // The registration the cell is published under; 0 while unregistered
+    generation: AtomicU64,
+    // Odd while a publish is in progress
+    sequence: AtomicU64,
+    total_minor: AtomicI64,
+    tendered_minor: AtomicI64,
+    change_minor: AtomicI64,
+    line_count: AtomicU32,
+    state: AtomicU32,
+}
+
+impl CachedTotals {
+    // Publishers are serialized by the lock guarding the transaction itself
+    pub fn publish(&self, totals: TotalsSnapshot) {
+        if self.load() == totals {
+            return;
+        }
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+        self.total_minor.store(totals.total_minor, Ordering::Release);
+        self.tendered_minor.store(totals.tendered_minor, Ordering::Release);
+        self.change_minor.store(totals.change_minor, Ordering::Release);
+        self.line_count.store(totals.line_count, Ordering::Release);
+        self.state.store(totals.state, Ordering::Release);
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn load(&self) -> TotalsSnapshot {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let totals = TotalsSnapshot {
+                total_minor: self.total_minor.load(Ordering::Acquire),
+                tendered_minor: self.tendered_minor.load(Ordering::Acquire),
+                change_minor: self.change_minor.load(Ordering::Acquire),
+                line_count: self.line_count.load(Ordering::Acquire),
+                state: self.state.load(Ordering::Acquire),
+            };
+            if self.sequence.load(Ordering::Acquire) == before {
+                return totals;
+            }
+        }
+    }
+}
+
+// Handle -> registration generation and cell, separate from the store lock. It is
+// write-locked only briefly while a transaction enters or leaves the active table, so polling
+// readers never wait on lane activity.
+type Registry = RwLock<HashMap<u64, (u64, Arc<CachedTotals>)>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn register(handle: u64, totals: Arc<CachedTotals>) {
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    totals.generation.store(generation, Ordering::Release);
+    registry().write().unwrap_or_else(PoisonError::into_inner).insert(handle, (generation, totals));
+}
+
+// Leaves a later registration of the same handle in place
+pub(crate) fn unregister(handle: u64, totals: &CachedTotals) {
+    let generation = totals.generation.swap(0, Ordering::AcqRel);
+    let mut registry = registry().write().unwrap_or_else(PoisonError::into_inner);
+    if registry.get(&handle).is_some_and(|(registered, _)| *registered == generation) {
+        registry.remove(&handle);
+    }
+}
+
+pub(crate) fn lookup(handle: u64) -> Option<TotalsSnapshot> {
+    let (generation, cell) = registry().read().unwrap_or_else(PoisonError::into_inner).get(&handle).cloned()?;
+    let totals = cell.load();
+    (cell.generation.load(Ordering::Acquire) == generation).then_some(totals)
+}
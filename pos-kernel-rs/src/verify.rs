@@ -0,0 +1,368 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Journal-derived totals verification
+//! Recomputes a transaction's totals from its journal entries alone (lines added, voided or
+//! moved out, taxes, tenders) and compares them with the figures the kernel holds: the
+//! transaction itself and the cached totals cell customer displays read. A difference means a
+//! mutation reached one of them without the other, and the report lists where.
+//!
+//! The store-wide check applies that comparison to every active transaction and adds what no
+//! single transaction shows: journal entries out of sequence or no longer matching their
+//! checksums, handles the kernel never issued, parent references to missing lines, live child
+//! lines under voided parents, and breaks in the archive hash chain.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::archive::ArchiveSnapshot;
+use crate::journal::{JournalEntry, JournalOperation};
+use crate::totals::{self, TotalsSnapshot};
+use crate::{LineKind, Transaction, PK_QUOTE_HANDLE_FLAG};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct LineFigures {
+    pub kind: LineKind,
+    pub qty: i32,
+    pub unit_minor: i64,
+}
+
+impl LineFigures {
+    // Recomputed here rather than through Line, so the check does not share the code it checks
+    fn total_minor(&self) -> i64 {
+        let gross = self.unit_minor * self.qty as i64;
+        match self.kind {
+            LineKind::DepositReturn | LineKind::Return => -gross,
+            LineKind::Sale | LineKind::Deposit | LineKind::Adjustment => gross,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct Totals {
+    pub lines_minor: i64,
+    pub exclusive_tax_minor: i64,
+    pub total_minor: i64,
+    pub tendered_minor: i64,
+    pub line_count: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Difference {
+    // "transaction" or "cached"
+    pub source: &'static str,
+    pub field: &'static str,
+    pub journal: i64,
+    pub actual: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LineDifference {
+    pub line_id: u32,
+    // None when the line is missing on that side
+    pub journal: Option<LineFigures>,
+    pub transaction: Option<LineFigures>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Verification {
+    pub handle: u64,
+    pub consistent: bool,
+    pub entries: usize,
+    pub journal: Totals,
+    pub transaction: Totals,
+    pub cached: Option<TotalsSnapshot>,
+    pub differences: Vec<Difference>,
+    pub lines: Vec<LineDifference>,
+}
+
+// The transaction as its journal entries describe it
+#[derive(Default)]
+struct Replay {
+    lines: BTreeMap<u32, LineFigures>,
+    exclusive_tax_minor: i64,
+    tendered_minor: i64,
+}
+
+impl Replay {
+    fn apply(&mut self, operation: &JournalOperation) {
+        match operation {
+            JournalOperation::LineAdd { line_id, qty, unit_minor, kind, .. } => {
+                self.lines.insert(*line_id, LineFigures { kind: *kind, qty: *qty, unit_minor: *unit_minor });
+            },
+            JournalOperation::LineVoid { line_id, .. } => {
+                if let Some(line) = self.lines.get_mut(line_id) {
+                    line.qty = 0;
+                }
+            },
+            JournalOperation::LinesMove { line_ids, .. } => {
+                for line_id in line_ids {
+                    self.lines.remove(line_id);
+                }
+            },
+            JournalOperation::TaxAdd { entry } if !entry.inclusive => self.exclusive_tax_minor += entry.tax_minor,
+            JournalOperation::TaxClear => self.exclusive_tax_minor = 0,
+            JournalOperation::TenderAdd { amount_minor, .. } => self.tendered_minor += amount_minor,
+            JournalOperation::LayawayCreate { deposit_minor } => self.tendered_minor += deposit_minor,
+            JournalOperation::LayawayPayment { amount_minor } => self.tendered_minor += amount_minor,
+            // The tip is settled with the card but is not part of the amount tendered
+            JournalOperation::TabCapture { captured_minor, tip_minor } => self.tendered_minor += captured_minor - tip_minor,
+            _ => {}
+        }
+    }
+
+    fn totals(&self) -> Totals {
+        let lines_minor = self.lines.values().map(LineFigures::total_minor).sum();
+        Totals {
+            lines_minor,
+            exclusive_tax_minor: self.exclusive_tax_minor,
+            total_minor: lines_minor + self.exclusive_tax_minor,
+            tendered_minor: self.tendered_minor,
+            line_count: self.lines.len() as u32,
+        }
+    }
+}
+
+pub(crate) fn verify<'a>(
+    tx: &Transaction,
+    entries: impl IntoIterator<Item = &'a JournalEntry>,
+    cached: Option<TotalsSnapshot>,
+) -> Verification {
+    let mut replay = Replay::default();
+    let mut entry_count = 0;
+    for entry in entries {
+        replay.apply(&entry.operation);
+        entry_count += 1;
+    }
+    let journal = replay.totals();
+    let transaction = Totals {
+        lines_minor: tx.lines_total_minor(),
+        exclusive_tax_minor: tx.exclusive_tax_minor(),
+        total_minor: tx.total_minor(),
+        tendered_minor: tx.tendered_minor,
+        line_count: tx.line_count(),
+    };
+
+    let mut differences = Vec::new();
+    let mut compare = |source, field, journal: i64, actual: i64| {
+        if journal != actual {
+            differences.push(Difference { source, field, journal, actual });
+        }
+    };
+    compare("transaction", "lines_minor", journal.lines_minor, transaction.lines_minor);
+    compare("transaction", "exclusive_tax_minor", journal.exclusive_tax_minor, transaction.exclusive_tax_minor);
+    compare("transaction", "total_minor", journal.total_minor, transaction.total_minor);
+    compare("transaction", "tendered_minor", journal.tendered_minor, transaction.tendered_minor);
+    compare("transaction", "line_count", journal.line_count.into(), transaction.line_count.into());
+    // The cell holds only what displays poll
+    if let Some(cached) = cached {
+        compare("cached", "total_minor", journal.total_minor, cached.total_minor);
+        compare("cached", "tendered_minor", journal.tendered_minor, cached.tendered_minor);
+        compare("cached", "line_count", journal.line_count.into(), cached.line_count.into());
+    }
+
+    let held: BTreeMap<u32, LineFigures> = tx.lines.iter()
+        .map(|l| (l.line_id, LineFigures { kind: l.kind, qty: l.qty, unit_minor: l.unit_minor }))
+        .collect();
+    let mut lines = Vec::new();
+    for line_id in replay.lines.keys().chain(held.keys().filter(|id| !replay.lines.contains_key(id))) {
+        let (journal, transaction) = (replay.lines.get(line_id).copied(), held.get(line_id).copied());
+        if journal != transaction {
+            lines.push(LineDifference { line_id: *line_id, journal, transaction });
+        }
+    }
+
+    Verification {
+        handle: tx.id,
+        consistent: differences.is_empty() && lines.is_empty(),
+        entries: entry_count,
+        journal,
+        transaction,
+        cached,
+        differences,
+        lines,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Finding {
+    // "sequence", "journal_chain", "dangling_parent", "orphaned_child", "totals" or "archive_chain"
+    pub check: &'static str,
+    pub handle: Option<u64>,
+    pub line_id: Option<u32>,
+    pub sequence_number: Option<u64>,
+    pub detail: String,
+}
+
+impl Finding {
+    fn new(check: &'static str, detail: String) -> Self {
+        Self { check, handle: None, line_id: None, sequence_number: None, detail }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StoreVerification {
+    pub consistent: bool,
+    pub journal_entries: usize,
+    pub active_transactions: usize,
+    pub archived_transactions: u64,
+    pub archive_head_hash: String,
+    pub findings: Vec<Finding>,
+    // Next transaction and quote numbers; handles at or beyond them were never issued
+    #[serde(skip)]
+    next_handles: (u64, u64),
+}
+
+// Checks active transactions against the journal while the store is held exclusively
+pub(crate) struct StoreCheck<'a> {
+    journal: HashMap<u64, Vec<&'a JournalEntry>>,
+    report: StoreVerification,
+}
+
+impl<'a> StoreCheck<'a> {
+    pub fn new(entries: &'a [JournalEntry], next_handles: (u64, u64)) -> Self {
+        let mut findings = Vec::new();
+        for pair in entries.windows(2) {
+            if pair[1].sequence_number != pair[0].sequence_number + 1 {
+                findings.push(Finding {
+                    handle: Some(pair[1].transaction_handle),
+                    sequence_number: Some(pair[1].sequence_number),
+                    ..Finding::new("sequence", format!(
+                        "Journal entry {} follows entry {}", pair[1].sequence_number, pair[0].sequence_number
+                    ))
+                });
+            }
+        }
+        let mut journal: HashMap<u64, Vec<&JournalEntry>> = HashMap::new();
+        for entry in entries {
+            if !entry.checksum_valid() {
+                findings.push(Finding {
+                    handle: Some(entry.transaction_handle),
+                    sequence_number: Some(entry.sequence_number),
+                    ..Finding::new("journal_chain", format!("Journal entry {} does not match its checksum", entry.sequence_number))
+                });
+            }
+            journal.entry(entry.transaction_handle).or_default().push(entry);
+        }
+
+        Self {
+            journal,
+            report: StoreVerification {
+                consistent: false,
+                journal_entries: entries.len(),
+                active_transactions: 0,
+                archived_transactions: 0,
+                archive_head_hash: String::new(),
+                findings,
+                next_handles,
+            },
+        }
+    }
+
+    pub fn check_active(&mut self, tx: &Transaction) {
+        self.report.active_transactions += 1;
+        self.report.check_handle(tx.id);
+        check_lines(tx, &mut self.report.findings);
+
+        let entries = self.journal.get(&tx.id).map(Vec::as_slice).unwrap_or_default();
+        if !matches!(entries.first().map(|e| &e.operation), Some(JournalOperation::TransactionBegin { .. })) {
+            self.report.findings.push(Finding {
+                handle: Some(tx.id),
+                ..Finding::new("journal_chain", format!("Transaction {} has no journaled begin", tx.id))
+            });
+        }
+        let verification = verify(tx, entries.iter().copied(), totals::lookup(tx.id));
+        if !verification.consistent {
+            self.report.findings.push(Finding {
+                handle: Some(tx.id),
+                ..Finding::new("totals", format!(
+                    "{} figures and {} lines of transaction {} disagree with its journal",
+                    verification.differences.len(), verification.lines.len(), tx.id
+                ))
+            });
+        }
+    }
+
+    pub fn finish(self) -> StoreVerification {
+        self.report
+    }
+}
+
+impl StoreVerification {
+    // Runs on a snapshot after the store lock is released; evicted transactions are reloaded
+    pub fn check_archive(mut self, snapshot: &ArchiveSnapshot) -> Self {
+        let archive = snapshot.verify();
+        self.archived_transactions = archive.records;
+        self.archive_head_hash = archive.head_hash;
+        if let Some(sequence_number) = archive.first_invalid {
+            self.findings.push(Finding {
+                sequence_number: Some(sequence_number),
+                ..Finding::new("archive_chain", format!("Archive hash chain breaks at record {}", sequence_number))
+            });
+        }
+
+        let scanned = snapshot.scan(|transactions| {
+            for tx in transactions {
+                self.check_handle(tx.id);
+                check_lines(&tx, &mut self.findings);
+            }
+        });
+        if let Err(e) = scanned {
+            self.findings.push(Finding::new("archive_chain", e));
+        }
+
+        self.consistent = self.findings.is_empty();
+        self
+    }
+
+    fn check_handle(&mut self, handle: u64) {
+        let (number, next) = match handle & PK_QUOTE_HANDLE_FLAG {
+            0 => (handle, self.next_handles.0),
+            _ => (handle & !PK_QUOTE_HANDLE_FLAG, self.next_handles.1),
+        };
+        if number == 0 || number >= next {
+            self.findings.push(Finding {
+                handle: Some(handle),
+                ..Finding::new("sequence", format!("Transaction handle {} was never issued", handle))
+            });
+        }
+    }
+}
+
+// Lines the kernel adds reference an existing earlier parent, and voiding a parent voids its
+// children with it
+fn check_lines(tx: &Transaction, findings: &mut Vec<Finding>) {
+    for line in tx.lines.iter() {
+        let parent_id = match line.parent_line_item_id {
+            Some(parent_id) => parent_id,
+            None => continue,
+        };
+        let problem = match tx.find_line(parent_id) {
+            None => Some(("dangling_parent", format!("Line {} references missing parent line {}", line.line_id, parent_id))),
+            Some(_) if tx.line_depth(line.line_id).is_none() => {
+                Some(("dangling_parent", format!("Line {} has a cyclic parent chain", line.line_id)))
+            },
+            Some(parent) if parent.qty == 0 && line.qty != 0 => {
+                Some(("orphaned_child", format!("Line {} is live under voided parent line {}", line.line_id, parent_id)))
+            },
+            Some(_) => None,
+        };
+        if let Some((check, detail)) = problem {
+            findings.push(Finding { handle: Some(tx.id), line_id: Some(line.line_id), ..Finding::new(check, detail) });
+        }
+    }
+}
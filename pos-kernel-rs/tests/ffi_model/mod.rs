@@ -0,0 +1,512 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Model of the FFI surface for randomized call sequences
+//! Shared by the property tests (tests/ffi_properties.rs) and the fuzz target
+//! (fuzz/fuzz_targets/ffi_sequence.rs). A harness applies each call to the kernel and, when the
+//! kernel accepts it, to its own model of the transaction; after every call the kernel's cached
+//! totals must match the model for every transaction the harness opened. Pointers passed to
+//! the kernel are always valid for the length passed with them: junk input means arbitrary
+//! bytes, zero lengths, null pointers with zero length and undersized output buffers.
+//!
+//! The kernel store is process-wide and other harnesses may run on other threads, so a harness
+//! only makes claims about the handles it was issued, and leaves configuration at its defaults.
+
+#![allow(dead_code)] // Each consumer uses part of the model
+
+use std::collections::HashSet;
+
+use pos_kernel::*;
+
+const OK: i32 = 0;
+const NOT_FOUND: i32 = 1;
+const VALIDATION_FAILED: i32 = 3;
+const INSUFFICIENT_BUFFER: i32 = 4;
+const PARENT_LINE_VOIDED: i32 = 8;
+const CURRENCY_MISMATCH: i32 = 9;
+const KNOWN_CODES: &[i32] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 255];
+
+// Default configuration limits the model checks against
+const MAX_CURRENCY_DECIMAL_PLACES: u8 = 4;
+const MAX_LINE_QTY: i32 = 99_999;
+const MAX_UNIT_MINOR: u64 = 10_000_000_000;
+
+// Never issued: transaction numbers count up from 1 and quote handles carry the high bit
+const FOREIGN_BASE: u64 = 1 << 62;
+
+// Largest output buffer an operation asks the kernel to fill
+pub const MAX_BUFFER: usize = 4096;
+
+// Optional byte string; `None` is passed as a null pointer with zero length
+pub type Blob = Option<Vec<u8>>;
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Begin { store: Blob, currency: Blob, decimal_places: u8 },
+    AddLine { tx: usize, sku: Blob, qty: i32, unit_minor: i64 },
+    AddChildLine { tx: usize, sku: Blob, qty: i32, unit_minor: i64, parent: u32 },
+    VoidLine { tx: usize, line: u32, reason: Blob },
+    Tender { tx: usize, amount_minor: i64 },
+    // Tokens come from a small set so that retries happen
+    TenderWithToken { tx: usize, amount_minor: i64, token: u8 },
+    TenderInCurrency { tx: usize, amount_minor: i64, currency: Blob },
+    Finalize { tx: usize },
+    SetAttribute { tx: usize, key: Blob, value: Blob },
+    Export { tx: usize, buffer_size: usize },
+    Verify { tx: usize },
+    LastError { buffer_size: usize },
+    // Calls made with a handle this harness was never issued
+    Foreign { offset: u64, quote: bool },
+}
+
+impl Op {
+    // Decodes a call sequence from unstructured fuzzer input; running out of bytes ends it
+    pub fn decode_all(data: &[u8]) -> Vec<Op> {
+        let mut input = Input { data };
+        let mut ops = Vec::new();
+        while !input.data.is_empty() {
+            ops.push(input.op());
+        }
+        ops
+    }
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl Input<'_> {
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let mut out = [0u8; N];
+        let n = N.min(self.data.len());
+        out[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        out
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes::<1>()[0]
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes())
+    }
+
+    fn i64(&mut self) -> i64 {
+        i64::from_le_bytes(self.bytes())
+    }
+
+    // Small amounts are far more interesting than uniformly random 64-bit ones
+    fn amount(&mut self) -> i64 {
+        match self.u8() % 4 {
+            0 => self.i64(),
+            _ => i64::from(self.u32() % 100_000) - 1_000,
+        }
+    }
+
+    fn blob(&mut self) -> Blob {
+        let len = self.u8();
+        if len == 0xff {
+            return None;
+        }
+        let len = usize::from(len % 24).min(self.data.len());
+        let (blob, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(blob.to_vec())
+    }
+
+    fn op(&mut self) -> Op {
+        let tx = usize::from(self.u8());
+        match self.u8() % 13 {
+            0 => Op::Begin { store: self.blob(), currency: self.blob(), decimal_places: self.u8() % 8 },
+            1 => Op::AddLine { tx, sku: self.blob(), qty: self.u32() as i32 % 200_000, unit_minor: self.amount() },
+            2 => Op::AddChildLine { tx, sku: self.blob(), qty: self.u32() as i32 % 200_000, unit_minor: self.amount(), parent: self.u32() },
+            3 => Op::VoidLine { tx, line: self.u32(), reason: self.blob() },
+            4 => Op::Tender { tx, amount_minor: self.amount() },
+            5 => Op::TenderWithToken { tx, amount_minor: self.amount(), token: self.u8() },
+            6 => Op::TenderInCurrency { tx, amount_minor: self.amount(), currency: self.blob() },
+            7 => Op::Finalize { tx },
+            8 => Op::SetAttribute { tx, key: self.blob(), value: self.blob() },
+            9 => Op::Export { tx, buffer_size: self.u32() as usize % MAX_BUFFER },
+            10 => Op::Verify { tx },
+            11 => Op::LastError { buffer_size: self.u32() as usize % MAX_BUFFER },
+            _ => Op::Foreign { offset: u64::from(self.u32()), quote: self.u8() % 2 == 1 },
+        }
+    }
+}
+
+fn raw(blob: &Blob) -> (*const u8, usize) {
+    match blob {
+        Some(bytes) => (bytes.as_ptr(), bytes.len()),
+        None => (std::ptr::null(), 0),
+    }
+}
+
+fn text(blob: &Blob) -> String {
+    blob.as_deref().map(String::from_utf8_lossy).unwrap_or_default().into_owned()
+}
+
+fn is_currency_code(blob: &Blob) -> bool {
+    blob.as_deref().is_some_and(|b| b.len() == 3 && b.iter().all(u8::is_ascii_alphabetic))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    parent: Option<u32>,
+    qty: i64,
+    unit_minor: i64,
+}
+
+// What the harness expects of one transaction; line IDs are positions counted from 1
+#[derive(Debug)]
+struct Expected {
+    handle: u64,
+    currency: String,
+    lines: Vec<Line>,
+    tendered_minor: i64,
+    tokens: HashSet<String>,
+    committed: bool,
+}
+
+impl Expected {
+    fn total_minor(&self) -> i64 {
+        self.lines.iter().map(|l| l.qty * l.unit_minor).sum()
+    }
+
+    fn has_live_lines(&self) -> bool {
+        self.lines.iter().any(|l| l.qty != 0)
+    }
+
+    fn fully_tendered(&self) -> bool {
+        let total = self.total_minor();
+        (total >= 0 && self.tendered_minor >= total) || (total < 0 && self.tendered_minor <= total)
+    }
+
+    fn line(&self, line_id: u32) -> Option<&Line> {
+        (line_id as usize).checked_sub(1).and_then(|i| self.lines.get(i))
+    }
+
+    // Children always follow their parent, so one pass in ID order reaches every descendant
+    fn void(&mut self, line_id: u32) {
+        let mut voided = HashSet::from([line_id]);
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            let id = i as u32 + 1;
+            if id == line_id || line.parent.is_some_and(|p| voided.contains(&p)) {
+                line.qty = 0;
+                voided.insert(id);
+            }
+        }
+    }
+
+    // Picks a line ID that is sometimes 0 or past the last line
+    fn pick_line(&self, raw: u32) -> u32 {
+        raw % (self.lines.len() as u32 + 2)
+    }
+}
+
+#[derive(Default)]
+pub struct Harness {
+    transactions: Vec<Expected>,
+    issued: HashSet<u64>,
+}
+
+impl Harness {
+    pub fn run(ops: &[Op]) {
+        let mut harness = Harness::default();
+        for op in ops {
+            harness.apply(op);
+            harness.check_totals();
+        }
+        for tx in 0..harness.transactions.len() {
+            harness.verify(tx);
+        }
+    }
+
+    fn target(&self, tx: usize) -> Option<usize> {
+        (!self.transactions.is_empty()).then(|| tx % self.transactions.len())
+    }
+
+    // A selector with no transaction to pick addresses a handle the kernel never issued
+    fn handle(&self, tx: Option<usize>) -> u64 {
+        tx.map_or(FOREIGN_BASE, |tx| self.transactions[tx].handle)
+    }
+
+    fn known(code: i32, op: &Op) -> i32 {
+        assert!(KNOWN_CODES.contains(&code), "unknown result code {} from {:?}", code, op);
+        code
+    }
+
+    pub fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Begin { store, currency, decimal_places } => self.begin(op, store, currency, *decimal_places),
+            Op::AddLine { tx, sku, qty, unit_minor } => self.add_line(op, *tx, sku, *qty, *unit_minor, None),
+            Op::AddChildLine { tx, sku, qty, unit_minor, parent } => {
+                let parent = self.target(*tx).map_or(*parent, |t| self.transactions[t].pick_line(*parent));
+                self.add_line(op, *tx, sku, *qty, *unit_minor, Some(parent))
+            },
+            Op::VoidLine { tx, line, reason } => self.void_line(op, *tx, *line, reason),
+            Op::Tender { tx, amount_minor } => self.tender(op, *tx, *amount_minor, None, None),
+            Op::TenderWithToken { tx, amount_minor, token } => {
+                let token = Some(format!("token-{}", token % 4).into_bytes());
+                self.tender(op, *tx, *amount_minor, Some(&token), None)
+            },
+            Op::TenderInCurrency { tx, amount_minor, currency } => self.tender(op, *tx, *amount_minor, None, Some(currency)),
+            Op::Finalize { tx } => self.finalize(op, *tx),
+            Op::SetAttribute { tx, key, value } => self.set_attribute(op, *tx, key, value),
+            Op::Export { tx, buffer_size } => self.export(op, *tx, *buffer_size),
+            Op::Verify { tx } => {
+                if let Some(tx) = self.target(*tx) {
+                    self.verify(tx);
+                }
+            },
+            Op::LastError { buffer_size } => {
+                let mut buffer = vec![0u8; *buffer_size];
+                let mut required = 0;
+                let code = Self::known(unsafe { pk_get_last_error(buffer.as_mut_ptr(), buffer.len(), &mut required) }.code, op);
+                Self::check_buffer(code, *buffer_size, required, op);
+            },
+            Op::Foreign { offset, quote } => self.foreign(op, *offset, *quote),
+        }
+    }
+
+    fn begin(&mut self, op: &Op, store: &Blob, currency: &Blob, decimal_places: u8) {
+        let ((store_ptr, store_len), (currency_ptr, currency_len)) = (raw(store), raw(currency));
+        let mut handle = 0;
+        let code = Self::known(unsafe {
+            pk_begin_transaction(store_ptr, store_len, currency_ptr, currency_len, decimal_places, &mut handle)
+        }.code, op);
+
+        if !is_currency_code(currency) || decimal_places > MAX_CURRENCY_DECIMAL_PLACES {
+            assert_eq!(code, VALIDATION_FAILED, "{:?}", op);
+        }
+        if code != OK {
+            return;
+        }
+
+        assert!(handle != PK_INVALID_HANDLE && handle < FOREIGN_BASE, "begin issued handle {:#x}", handle);
+        assert!(self.issued.insert(handle), "begin issued handle {} twice", handle);
+        self.transactions.push(Expected {
+            handle,
+            currency: text(currency).to_uppercase(),
+            lines: Vec::new(),
+            tendered_minor: 0,
+            tokens: HashSet::new(),
+            committed: false,
+        });
+    }
+
+    fn add_line(&mut self, op: &Op, tx: usize, sku: &Blob, qty: i32, unit_minor: i64, parent: Option<u32>) {
+        let target = self.target(tx);
+        let handle = self.handle(target);
+        let (sku_ptr, sku_len) = raw(sku);
+        let code = Self::known(match parent {
+            Some(parent) => unsafe { pk_add_child_line(handle, sku_ptr, sku_len, qty, unit_minor, parent) },
+            None => unsafe { pk_add_line(handle, sku_ptr, sku_len, qty, unit_minor) },
+        }.code, op);
+
+        if qty <= 0 || sku_len == 0 {
+            assert_eq!(code, VALIDATION_FAILED, "{:?}", op);
+        }
+        let expected = match target {
+            Some(tx) => &mut self.transactions[tx],
+            None => return assert_ne!(code, OK, "{:?} succeeded on a foreign handle", op),
+        };
+        if code == PARENT_LINE_VOIDED {
+            assert!(parent.and_then(|p| expected.line(p)).is_some_and(|l| l.qty == 0), "{:?} reported a live parent as voided", op);
+        }
+        if code != OK {
+            return;
+        }
+
+        assert!(!expected.committed, "{:?} changed a committed transaction", op);
+        assert!(qty <= MAX_LINE_QTY && unit_minor.unsigned_abs() <= MAX_UNIT_MINOR, "{:?} accepted out of bounds", op);
+        if let Some(parent) = parent {
+            assert!(expected.line(parent).is_some_and(|l| l.qty != 0), "{:?} accepted a missing or voided parent", op);
+        }
+        expected.lines.push(Line { parent, qty: i64::from(qty), unit_minor });
+    }
+
+    fn void_line(&mut self, op: &Op, tx: usize, line: u32, reason: &Blob) {
+        let target = self.target(tx);
+        let handle = self.handle(target);
+        let line_id = target.map_or(line, |t| self.transactions[t].pick_line(line));
+        let (reason_ptr, reason_len) = raw(reason);
+        let code = Self::known(unsafe { pk_void_line_item_with_cascade(handle, line_id, reason_ptr, reason_len) }.code, op);
+
+        let expected = match target {
+            Some(tx) => &mut self.transactions[tx],
+            None => return assert_ne!(code, OK, "{:?} succeeded on a foreign handle", op),
+        };
+        if code != OK {
+            return;
+        }
+
+        assert!(!expected.committed, "{:?} changed a committed transaction", op);
+        assert!(expected.line(line_id).is_some(), "{:?} voided line {} that does not exist", op, line_id);
+        expected.void(line_id);
+    }
+
+    fn tender(&mut self, op: &Op, tx: usize, amount_minor: i64, token: Option<&Blob>, currency: Option<&Blob>) {
+        let target = self.target(tx);
+        let handle = self.handle(target);
+        let code = Self::known(match (token, currency) {
+            (Some(token), _) => {
+                let (token_ptr, token_len) = raw(token);
+                unsafe { pk_add_cash_tender_with_token(handle, amount_minor, token_ptr, token_len) }
+            },
+            (_, Some(currency)) => {
+                let (currency_ptr, currency_len) = raw(currency);
+                unsafe { pk_add_cash_tender_in_currency(handle, amount_minor, currency_ptr, currency_len) }
+            },
+            (None, None) => pk_add_cash_tender(handle, amount_minor),
+        }.code, op);
+
+        if amount_minor <= 0 {
+            assert_eq!(code, VALIDATION_FAILED, "{:?}", op);
+        }
+        let expected = match target {
+            Some(tx) => &mut self.transactions[tx],
+            None => return assert_ne!(code, OK, "{:?} succeeded on a foreign handle", op),
+        };
+        if let Some(currency) = currency.filter(|c| c.as_ref().is_some_and(|b| !b.is_empty()) && amount_minor > 0) {
+            if !text(currency).eq_ignore_ascii_case(&expected.currency) {
+                assert_eq!(code, CURRENCY_MISMATCH, "{:?} against {}", op, expected.currency);
+            }
+        }
+
+        // A token already tendered is acknowledged again without tendering anything
+        let token = token.map(text);
+        if let Some(token) = token.as_ref().filter(|t| expected.tokens.contains(*t)) {
+            if amount_minor > 0 {
+                assert_eq!(code, OK, "retry of token {} was not acknowledged", token);
+            }
+            return;
+        }
+        if code != OK {
+            return;
+        }
+
+        assert!(!expected.committed, "{:?} changed a committed transaction", op);
+        assert!(expected.has_live_lines(), "{:?} tendered a transaction with no lines", op);
+        expected.tendered_minor += amount_minor;
+        if let Some(token) = token {
+            expected.tokens.insert(token);
+        }
+    }
+
+    fn finalize(&mut self, op: &Op, tx: usize) {
+        let target = self.target(tx);
+        let code = Self::known(pk_finalize_transaction(self.handle(target)).code, op);
+
+        let expected = match target {
+            Some(tx) => &mut self.transactions[tx],
+            None => return assert_ne!(code, OK, "{:?} succeeded on a foreign handle", op),
+        };
+        let ready = !expected.committed && expected.has_live_lines() && expected.fully_tendered();
+        assert_eq!(code == OK, ready, "{:?} returned {} for {:?}", op, code, expected);
+        if code == OK {
+            expected.committed = true;
+        }
+    }
+
+    fn set_attribute(&mut self, op: &Op, tx: usize, key: &Blob, value: &Blob) {
+        let target = self.target(tx);
+        let ((key_ptr, key_len), (value_ptr, value_len)) = (raw(key), raw(value));
+        let code = Self::known(unsafe {
+            pk_set_transaction_attribute(self.handle(target), key_ptr, key_len, value_ptr, value_len)
+        }.code, op);
+
+        if target.is_none() {
+            assert_ne!(code, OK, "{:?} succeeded on a foreign handle", op);
+        }
+    }
+
+    fn export(&mut self, op: &Op, tx: usize, buffer_size: usize) {
+        let target = self.target(tx);
+        let mut buffer = vec![0u8; buffer_size];
+        let mut required = 0;
+        let code = Self::known(unsafe {
+            pk_export_transaction_json(self.handle(target), buffer.as_mut_ptr(), buffer.len(), &mut required)
+        }.code, op);
+
+        if target.is_none() {
+            return assert_eq!(code, NOT_FOUND, "{:?}", op);
+        }
+        Self::check_buffer(code, buffer_size, required, op);
+        if code == OK {
+            let json: serde_json::Value = serde_json::from_slice(&buffer[..required])
+                .unwrap_or_else(|e| panic!("{:?} wrote invalid JSON: {}", op, e));
+            assert!(json.is_object(), "{:?} wrote {}", op, json);
+        }
+    }
+
+    // Win32-style buffers: a short buffer reports the size needed and writes nothing usable
+    fn check_buffer(code: i32, buffer_size: usize, required: usize, op: &Op) {
+        match code {
+            OK => assert!(required <= buffer_size, "{:?} wrote {} bytes into {}", op, required, buffer_size),
+            INSUFFICIENT_BUFFER => assert!(required > buffer_size, "{:?} asked for {} bytes with {}", op, required, buffer_size),
+            _ => panic!("{:?} returned {}", op, code),
+        }
+    }
+
+    fn foreign(&mut self, op: &Op, offset: u64, quote: bool) {
+        let handle = match quote {
+            true => PK_QUOTE_HANDLE_FLAG | FOREIGN_BASE | offset,
+            false => FOREIGN_BASE | offset,
+        };
+        let (mut total, mut tendered, mut change, mut state) = (0, 0, 0, 0);
+        let code = Self::known(unsafe { pk_get_totals(handle, &mut total, &mut tendered, &mut change, &mut state) }.code, op);
+        assert_eq!(code, NOT_FOUND, "{:?}", op);
+        let code = Self::known(unsafe { pk_add_line(handle, b"SKU".as_ptr(), 3, 1, 100) }.code, op);
+        assert_eq!(code, NOT_FOUND, "{:?}", op);
+        let code = Self::known(pk_add_cash_tender(handle, 100).code, op);
+        assert_eq!(code, NOT_FOUND, "{:?}", op);
+        let code = Self::known(pk_finalize_transaction(handle).code, op);
+        assert_eq!(code, NOT_FOUND, "{:?}", op);
+    }
+
+    // The cached totals of every transaction this harness opened match its model, so a call
+    // on one handle never shows up on another
+    pub fn check_totals(&self) {
+        for expected in &self.transactions {
+            let (mut total, mut tendered, mut change, mut state) = (0, 0, 0, 0);
+            let code = unsafe { pk_get_totals(expected.handle, &mut total, &mut tendered, &mut change, &mut state) }.code;
+            assert_eq!(code, OK, "totals of {} not readable", expected.handle);
+
+            let total_minor = expected.total_minor();
+            assert_eq!(total, total_minor, "total of {:?}", expected);
+            assert_eq!(tendered, expected.tendered_minor, "tendered of {:?}", expected);
+            assert_eq!(change, (expected.tendered_minor - total_minor).max(0), "change of {:?}", expected);
+            assert_eq!(state, i32::from(expected.committed), "state of {:?}", expected);
+
+            let mut count = 0;
+            assert_eq!(unsafe { pk_get_line_count(expected.handle, &mut count) }.code, OK);
+            assert_eq!(count as usize, expected.lines.len(), "line count of {:?}", expected);
+        }
+    }
+
+    // Figures replayed from the journal agree with the transaction and its cached totals
+    fn verify(&self, tx: usize) {
+        let handle = self.transactions[tx].handle;
+        let mut required = 0;
+        let code = unsafe { pk_verify_transaction(handle, std::ptr::null_mut(), 0, &mut required) }.code;
+        assert_eq!(code, INSUFFICIENT_BUFFER, "verify of {} returned {}", handle, code);
+
+        let mut buffer = vec![0u8; required];
+        let code = unsafe { pk_verify_transaction(handle, buffer.as_mut_ptr(), buffer.len(), &mut required) }.code;
+        assert_eq!(code, OK, "transaction {} inconsistent: {}", handle, String::from_utf8_lossy(&buffer[..required.min(buffer.len())]));
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Property tests for the FFI surface
+//! Random sequences of FFI calls, with junk strings and buffer sizes mixed into plausible ones,
+//! must keep the kernel invariants checked by the model in tests/ffi_model: totals consistent
+//! with the accepted calls and with the journal, no handle confusion, no panics. A panic inside
+//! an FFI entry point aborts the test binary rather than failing one case.
+//!
+//! Run more cases with PROPTEST_CASES=10000 cargo test --test ffi_properties
+
+mod ffi_model;
+
+use ffi_model::{Blob, Harness, Op, MAX_BUFFER};
+use proptest::prelude::*;
+
+fn blob() -> impl Strategy<Value = Blob> {
+    prop_oneof![
+        4 => "[A-Za-z0-9 -]{1,12}".prop_map(|s| Some(s.into_bytes())),
+        2 => prop::collection::vec(any::<u8>(), 0..24).prop_map(Some),
+        1 => Just(Some(b"4111111111111111".to_vec())),
+        1 => Just(None),
+    ]
+}
+
+fn currency() -> impl Strategy<Value = Blob> {
+    prop_oneof![
+        4 => prop::sample::select(vec!["USD", "usd", "EUR", "JPY"]).prop_map(|c| Some(c.as_bytes().to_vec())),
+        1 => blob(),
+    ]
+}
+
+fn amount() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        6 => -1_000i64..100_000,
+        1 => Just(0i64),
+        1 => any::<i64>(),
+        1 => prop::sample::select(vec![i64::MIN, i64::MAX, i64::MAX / 2 + 1, 10_000_000_001]),
+    ]
+}
+
+fn qty() -> impl Strategy<Value = i32> {
+    prop_oneof![
+        8 => 1i32..5,
+        1 => any::<i32>(),
+        1 => prop::sample::select(vec![0, -1, 99_999, 100_000]),
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let tx = any::<usize>();
+    prop_oneof![
+        2 => (blob(), currency(), 0u8..8)
+            .prop_map(|(store, currency, decimal_places)| Op::Begin { store, currency, decimal_places }),
+        5 => (tx, blob(), qty(), amount())
+            .prop_map(|(tx, sku, qty, unit_minor)| Op::AddLine { tx, sku, qty, unit_minor }),
+        3 => (tx, blob(), qty(), amount(), any::<u32>())
+            .prop_map(|(tx, sku, qty, unit_minor, parent)| Op::AddChildLine { tx, sku, qty, unit_minor, parent }),
+        2 => (tx, any::<u32>(), blob())
+            .prop_map(|(tx, line, reason)| Op::VoidLine { tx, line, reason }),
+        3 => (tx, amount()).prop_map(|(tx, amount_minor)| Op::Tender { tx, amount_minor }),
+        2 => (tx, amount(), any::<u8>())
+            .prop_map(|(tx, amount_minor, token)| Op::TenderWithToken { tx, amount_minor, token }),
+        2 => (tx, amount(), currency())
+            .prop_map(|(tx, amount_minor, currency)| Op::TenderInCurrency { tx, amount_minor, currency }),
+        2 => tx.prop_map(|tx| Op::Finalize { tx }),
+        1 => (tx, blob(), blob()).prop_map(|(tx, key, value)| Op::SetAttribute { tx, key, value }),
+        1 => (tx, 0..MAX_BUFFER).prop_map(|(tx, buffer_size)| Op::Export { tx, buffer_size }),
+        1 => tx.prop_map(|tx| Op::Verify { tx }),
+        1 => (0..MAX_BUFFER).prop_map(|buffer_size| Op::LastError { buffer_size }),
+        1 => (any::<u32>(), any::<bool>()).prop_map(|(offset, quote)| Op::Foreign { offset: u64::from(offset), quote }),
+    ]
+}
+
+// Sequences start with a sale already open so that most calls have a transaction to act on
+fn sequence() -> impl Strategy<Value = Vec<Op>> {
+    prop::collection::vec(op(), 1..64).prop_map(|ops| {
+        let begin = Op::Begin { store: Some(b"PROP".to_vec()), currency: Some(b"USD".to_vec()), decimal_places: 2 };
+        std::iter::once(begin).chain(ops).collect()
+    })
+}
+
+proptest! {
+    #[test]
+    fn ffi_call_sequences_keep_kernel_invariants(ops in sequence()) {
+        Harness::run(&ops);
+    }
+
+    #[test]
+    fn decoded_fuzz_input_keeps_kernel_invariants(data in prop::collection::vec(any::<u8>(), 0..512)) {
+        Harness::run(&Op::decode_all(&data));
+    }
+}
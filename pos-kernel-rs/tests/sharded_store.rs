@@ -0,0 +1,147 @@
+/*
+ * Copyright 2025 Paul Moore Parks and contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Concurrency tests for the sharded active-transaction table
+//! Lanes on many threads begin, fill, tender and finalize sales at once, spread over the
+//! shards. Every handle must stay unique and resolve to its own transaction: while open, once
+//! committed and archived, after its shard slot is reused and after the table is resharded.
+//! The kernel store is process-wide, so each test only makes claims about its own handles.
+
+use std::collections::HashSet;
+use std::thread;
+
+use pos_kernel::*;
+
+const OK: i32 = 0;
+const NOT_FOUND: i32 = 1;
+const BUILDING: i32 = 0;
+const COMMITTED: i32 = 1;
+
+// Never issued: transaction numbers count up from 1
+const UNKNOWN_HANDLE: u64 = 1 << 62;
+
+const LANES: usize = 16;
+const SALES_PER_LANE: usize = 25;
+
+fn begin() -> u64 {
+    let mut handle = 0;
+    let result = unsafe { pk_begin_transaction(b"SHARDS".as_ptr(), 6, b"USD".as_ptr(), 3, 2, &mut handle) };
+    assert_eq!(result.code, OK);
+    handle
+}
+
+fn add_line(handle: u64, sku: &str, qty: i32, unit_minor: i64) {
+    let result = unsafe { pk_add_line(handle, sku.as_ptr(), sku.len(), qty, unit_minor) };
+    assert_eq!(result.code, OK, "add_line on {}", handle);
+}
+
+// (total, tendered, state), or the result code
+fn totals(handle: u64) -> Result<(i64, i64, i32), i32> {
+    let (mut total, mut tendered, mut change, mut state) = (0, 0, 0, 0);
+    let result = unsafe { pk_get_totals(handle, &mut total, &mut tendered, &mut change, &mut state) };
+    match result.code {
+        OK => Ok((total, tendered, state)),
+        code => Err(code),
+    }
+}
+
+fn store_stats() -> serde_json::Value {
+    let mut buffer = vec![0u8; 8192];
+    let mut required = 0;
+    let result = unsafe { pk_get_store_stats_json(buffer.as_mut_ptr(), buffer.len(), &mut required) };
+    assert_eq!(result.code, OK);
+    serde_json::from_slice(&buffer[..required]).expect("store stats are JSON")
+}
+
+// Each lane prices its lines uniquely, so a lookup that reached another lane's transaction
+// would report the wrong total
+fn sell(lane: usize, sale: usize) -> (u64, i64) {
+    let handle = begin();
+    let unit_minor = (lane * 1000 + sale) as i64 + 1;
+    add_line(handle, &format!("LANE{}", lane), 2, unit_minor);
+    add_line(handle, "BAG", 1, 5);
+    let total = unit_minor * 2 + 5;
+    assert_eq!(totals(handle), Ok((total, 0, BUILDING)));
+
+    assert_eq!(pk_add_cash_tender(handle, total).code, OK);
+    assert_eq!(pk_finalize_transaction(handle).code, OK);
+    assert_eq!(totals(handle), Ok((total, total, COMMITTED)));
+    (handle, total)
+}
+
+#[test]
+fn concurrent_lanes_commit_their_own_sales() {
+    let lanes: Vec<_> = (0..LANES)
+        .map(|lane| thread::spawn(move || (0..SALES_PER_LANE).map(|sale| sell(lane, sale)).collect::<Vec<_>>()))
+        .collect();
+    let sales: Vec<(u64, i64)> = lanes.into_iter().flat_map(|lane| lane.join().expect("lane panicked")).collect();
+
+    let handles: HashSet<u64> = sales.iter().map(|(handle, _)| *handle).collect();
+    assert_eq!(handles.len(), LANES * SALES_PER_LANE, "handles were issued twice");
+
+    // Committed sales are archived by the persistence workers and must still resolve there,
+    // though their shard slots have been reused by later sales
+    assert_eq!(pk_wait_for_persistence(10_000).code, OK);
+    for (handle, total) in sales {
+        assert_eq!(totals(handle), Ok((total, total, COMMITTED)), "transaction {}", handle);
+    }
+}
+
+#[test]
+fn open_transactions_resolve_across_shards() {
+    let lanes: Vec<_> = (0..LANES)
+        .map(|lane| thread::spawn(move || {
+            let handle = begin();
+            add_line(handle, &format!("OPEN{}", lane), 1, lane as i64 + 1);
+            handle
+        }))
+        .collect();
+    let open: Vec<(u64, i64)> = lanes.into_iter()
+        .enumerate()
+        .map(|(lane, handle)| (handle.join().expect("lane panicked"), lane as i64 + 1))
+        .collect();
+
+    let stats = store_stats();
+    let per_shard: Vec<u64> = stats["transactions_per_shard"].as_array().expect("per-shard counts")
+        .iter()
+        .map(|n| n.as_u64().expect("count"))
+        .collect();
+    assert_eq!(per_shard.len() as u64, stats["transaction_shards"].as_u64().expect("shard count"));
+    assert_eq!(per_shard.iter().sum::<u64>(), stats["active_transactions"].as_u64().expect("active count"));
+    assert!(per_shard.iter().filter(|n| **n > 0).count() > 1, "transactions were not spread over the shards");
+
+    for (handle, total) in &open {
+        assert_eq!(totals(*handle), Ok((*total, 0, BUILDING)));
+    }
+
+    // Resharding moves every open transaction; each must still be found under its handle
+    let shards = b"7";
+    let key = b"transaction_shards";
+    assert_eq!(unsafe { pk_set_config(key.as_ptr(), key.len(), shards.as_ptr(), shards.len()) }.code, OK);
+    for (handle, total) in &open {
+        assert_eq!(totals(*handle), Ok((*total, 0, BUILDING)));
+        add_line(*handle, "BAG", 1, 5);
+        assert_eq!(totals(*handle), Ok((*total + 5, 0, BUILDING)));
+    }
+}
+
+#[test]
+fn unknown_handles_are_not_found() {
+    assert_eq!(totals(UNKNOWN_HANDLE), Err(NOT_FOUND));
+    let result = unsafe { pk_add_line(UNKNOWN_HANDLE, b"SKU".as_ptr(), 3, 1, 100) };
+    assert_eq!(result.code, NOT_FOUND);
+    assert_eq!(pk_finalize_transaction(UNKNOWN_HANDLE).code, NOT_FOUND);
+}